@@ -0,0 +1,81 @@
+//! Frame encoding and parsing throughput
+//!
+//! Run with `cargo bench --bench frame`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gs_usb::gateway::{FdToClassic, Translation};
+use gs_usb::{slcan, GsUsbFrame};
+
+const FORMATS: [(&str, bool, bool); 4] = [
+    ("classic", false, false),
+    ("classic_ts", true, false),
+    ("fd", false, true),
+    ("fd_ts", true, true),
+];
+
+fn sample_frame(fd: bool) -> GsUsbFrame {
+    if fd {
+        GsUsbFrame::with_fd_data(0x18DA_F110 | gs_usb::CAN_EFF_FLAG, &[0x5A; 64], true)
+    } else {
+        GsUsbFrame::with_data(0x123, &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88])
+    }
+}
+
+fn bench_pack(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pack");
+    group.throughput(Throughput::Elements(1));
+    for (name, hw_timestamp, fd) in FORMATS {
+        let frame = sample_frame(fd);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| black_box(&frame).pack(hw_timestamp, fd))
+        });
+    }
+    group.finish();
+}
+
+fn bench_unpack(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unpack");
+    group.throughput(Throughput::Elements(1));
+    for (name, hw_timestamp, fd) in FORMATS {
+        let bytes = sample_frame(fd).pack(hw_timestamp, fd);
+        group.bench_function(BenchmarkId::new("from_bytes", name), |b| {
+            b.iter(|| GsUsbFrame::from_bytes(black_box(&bytes), hw_timestamp, fd))
+        });
+        group.bench_function(BenchmarkId::new("from_received", name), |b| {
+            b.iter(|| GsUsbFrame::from_received(black_box(&bytes), hw_timestamp))
+        });
+    }
+    group.finish();
+}
+
+fn bench_slcan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slcan");
+    group.throughput(Throughput::Elements(1));
+    let frame = sample_frame(false);
+    let line = slcan::encode(&frame).unwrap();
+    group.bench_function("encode", |b| b.iter(|| slcan::encode(black_box(&frame))));
+    group.bench_function("decode", |b| b.iter(|| slcan::decode(black_box(&line))));
+    group.finish();
+}
+
+fn bench_translate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gateway_translate");
+    group.throughput(Throughput::Elements(1));
+    let frame = sample_frame(true);
+    for policy in [FdToClassic::Truncate, FdToClassic::Fragment] {
+        let translation = Translation::to_classic(policy);
+        group.bench_function(BenchmarkId::from_parameter(format!("{:?}", policy)), |b| {
+            b.iter(|| translation.translate(black_box(&frame)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_pack,
+    bench_unpack,
+    bench_slcan,
+    bench_translate
+);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! End-to-end frames/sec through `GsUsb` on the mock backend
+//!
+//! Measures the full host-side TX and RX paths (packing, transport dispatch,
+//! parsing) without USB latency. Run with
+//! `cargo bench --features test-util --bench loopback`.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gs_usb::{GsUsb, GsUsbFrame, MockGsUsb, GS_CAN_MODE_FD, GS_CAN_MODE_HW_TIMESTAMP};
+
+fn started(flags: u32) -> GsUsb {
+    let mut dev = MockGsUsb::new().open();
+    dev.set_bitrate(500_000).unwrap();
+    if flags & GS_CAN_MODE_FD != 0 {
+        dev.set_data_bitrate(2_000_000).unwrap();
+    }
+    dev.start(flags).unwrap();
+    dev
+}
+
+fn bench_loopback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("loopback");
+    group.throughput(Throughput::Elements(1));
+
+    let cases = [
+        (
+            "classic",
+            GS_CAN_MODE_HW_TIMESTAMP,
+            GsUsbFrame::with_data(0x123, &[0xAA; 8]),
+        ),
+        (
+            "fd",
+            GS_CAN_MODE_HW_TIMESTAMP | GS_CAN_MODE_FD,
+            GsUsbFrame::with_fd_data(0x123, &[0xAA; 64], true),
+        ),
+    ];
+
+    for (name, flags, frame) in cases {
+        let mut dev = started(flags);
+        group.bench_function(BenchmarkId::new("send_and_echo", name), |b| {
+            b.iter(|| {
+                dev.send(&frame).unwrap();
+                dev.read(Duration::from_millis(10)).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_loopback);
+criterion_main!(benches);
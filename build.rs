@@ -0,0 +1,14 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/gs_usb.proto");
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("vendored protoc not available"),
+        );
+        tonic_build::compile_protos("proto/gs_usb.proto").expect("failed to compile protos");
+    }
+
+    #[cfg(feature = "uniffi")]
+    uniffi::generate_scaffolding("src/gs_usb.udl").expect("failed to generate uniffi scaffolding");
+}
@@ -8,14 +8,7 @@
 //! 3. BT_CONST (4) - Get bit timing constraints
 //! 4. BT_CONST_EXT (11) - Get CAN FD constraints (if supported)
 
-use gs_usb::{
-    DeviceCapability, DeviceInfo, GsUsb, GsUsbError, GS_CAN_FEATURE_BERR_REPORTING,
-    GS_CAN_FEATURE_BT_CONST_EXT, GS_CAN_FEATURE_FD, GS_CAN_FEATURE_GET_STATE,
-    GS_CAN_FEATURE_HW_TIMESTAMP, GS_CAN_FEATURE_IDENTIFY, GS_CAN_FEATURE_LISTEN_ONLY,
-    GS_CAN_FEATURE_LOOP_BACK, GS_CAN_FEATURE_ONE_SHOT, GS_CAN_FEATURE_PAD_PKTS_TO_MAX_PKT_SIZE,
-    GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX, GS_CAN_FEATURE_TERMINATION,
-    GS_CAN_FEATURE_TRIPLE_SAMPLE, GS_CAN_FEATURE_USER_ID,
-};
+use gs_usb::prelude::*;
 
 fn main() {
     env_logger::init();
@@ -13,10 +13,7 @@
 
 use std::time::{Duration, Instant};
 
-use gs_usb::{
-    GsUsb, GsUsbError, GsUsbFrame, GS_CAN_MODE_FD, GS_CAN_MODE_LOOP_BACK, GS_CAN_MODE_NORMAL,
-    GS_CAN_MODE_ONE_SHOT,
-};
+use gs_usb::prelude::*;
 
 fn main() {
     env_logger::init();
@@ -14,15 +14,12 @@
 
 use std::time::{Duration, Instant};
 
-use gs_usb::{
-    GsUsb, GsUsbError, GsUsbFrame, GS_CAN_MODE_FD, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_LOOP_BACK,
-    GS_CAN_MODE_NORMAL,
-};
+use gs_usb::prelude::*;
 
 // Test configuration
 const TEST_CAN_ID: u32 = 0x123;
 const TEST_DATA_CLASSIC: [u8; 8] = [0xDE, 0xAD, 0xBE, 0xEF, 0xCA, 0xFE, 0xBA, 0xBE];
-const READ_TIMEOUT_MS: u64 = 1000;
+const READ_DEADLINE_MS: u64 = 2000;
 
 // Classic CAN bitrates to test
 const CLASSIC_CAN_BITRATES: [u32; 8] = [
@@ -87,15 +84,11 @@ fn run_single_test(
     }
 
     // Read frames (expecting 2: echo + loopback RX)
-    let mut frames_received = Vec::new();
-    let start_time = Instant::now();
-    while frames_received.len() < 2 && start_time.elapsed() < Duration::from_secs(2) {
-        match dev.read(Duration::from_millis(READ_TIMEOUT_MS)) {
-            Ok(frame) => frames_received.push(frame),
-            Err(GsUsbError::ReadTimeout) => continue,
-            Err(_) => break,
-        }
-    }
+    let deadline = Instant::now() + Duration::from_millis(READ_DEADLINE_MS);
+    let frames_received = dev
+        .read_n(2, deadline)
+        .map(|batch| batch.frames)
+        .unwrap_or_default();
 
     // Analyze received frames
     for frame in &frames_received {
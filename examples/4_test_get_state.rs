@@ -13,11 +13,7 @@
 use std::thread;
 use std::time::{Duration, Instant};
 
-use gs_usb::{
-    GsUsb, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_LOOP_BACK, GS_CAN_MODE_NORMAL,
-    GS_CAN_STATE_BUS_OFF, GS_CAN_STATE_ERROR_ACTIVE, GS_CAN_STATE_ERROR_PASSIVE,
-    GS_CAN_STATE_ERROR_WARNING,
-};
+use gs_usb::prelude::*;
 
 fn main() {
     env_logger::init();
@@ -0,0 +1,27 @@
+//! GS-USB gRPC Remote Bus Server
+//!
+//! Serves all locally attached GS-USB devices over gRPC so that remote
+//! clients can open, configure, send and stream-receive frames.
+//!
+//! Usage: cargo run --example grpc_server --features grpc [listen-address]
+
+use gs_usb::remote::RemoteBusService;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "0.0.0.0:50051".to_string())
+        .parse()?;
+
+    println!("GS-USB remote bus server listening on {}", addr);
+
+    tonic::transport::Server::builder()
+        .add_service(RemoteBusService::new().into_server())
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
@@ -8,10 +8,7 @@
 
 use std::time::{Duration, Instant};
 
-use gs_usb::{
-    GsUsb, GsUsbError, GsUsbFrame, CAN_EFF_FLAG, CAN_ERR_FLAG, CAN_RTR_FLAG,
-    GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_NORMAL,
-};
+use gs_usb::prelude::*;
 
 fn main() {
     // Initialize logging
@@ -24,23 +21,20 @@ fn main() {
 }
 
 fn run() -> gs_usb::Result<()> {
-    // Find our device
-    let devices = GsUsb::scan()?;
-    if devices.is_empty() {
-        println!("Can not find gs_usb device");
-        return Ok(());
-    }
-
-    let mut dev = devices.into_iter().next().unwrap();
-    println!("Found device: {}", dev);
-
-    // Configuration
-    dev.set_bitrate(250000)?;
-    println!("Bitrate set to 250 kbps");
-
-    // Start device
-    dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)?;
-    println!("Device started");
+    // Find the first device, set 250 kbps and start it with hardware timestamps
+    let mut dev = match GsUsb::builder()
+        .bitrate(250000)
+        .hw_timestamp()
+        .open_and_start()
+    {
+        Ok(dev) => dev,
+        Err(GsUsbError::DeviceNotFound) => {
+            println!("Can not find gs_usb device");
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    println!("Started device: {}", dev);
 
     // Prepare frames
     let data: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
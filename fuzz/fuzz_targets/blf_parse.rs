@@ -0,0 +1,13 @@
+//! Vector BLF log reader
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(records) = gs_usb::logfile::parse_blf(data) {
+        for record in records {
+            let _ = record.frame.data();
+        }
+    }
+});
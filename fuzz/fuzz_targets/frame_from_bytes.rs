@@ -0,0 +1,14 @@
+//! Raw frame unpacking with every (hw_timestamp, fd_mode) combination
+
+#![no_main]
+
+use gs_usb::GsUsbFrame;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for (hw_timestamp, fd_mode) in [(false, false), (true, false), (false, true), (true, true)] {
+        let frame = GsUsbFrame::from_bytes(data, hw_timestamp, fd_mode);
+        let _ = frame.data();
+        let _ = frame.to_string();
+    }
+});
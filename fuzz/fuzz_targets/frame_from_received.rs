@@ -0,0 +1,17 @@
+//! Bulk IN parsing as done by `GsUsb::read()`
+
+#![no_main]
+
+use gs_usb::GsUsbFrame;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for hw_timestamp in [false, true] {
+        if let Ok(frame) = GsUsbFrame::from_received(data, hw_timestamp) {
+            // A validated frame must survive a round trip through pack()
+            let packed = frame.pack(hw_timestamp, frame.is_fd());
+            let again = GsUsbFrame::from_received(&packed, hw_timestamp).unwrap();
+            assert_eq!(again.data(), frame.data());
+        }
+    }
+});
@@ -0,0 +1,15 @@
+//! USB traffic recording parser
+
+#![no_main]
+
+use gs_usb::Recording;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(recording) = Recording::parse(text) {
+            // Whatever parses must serialize back to the same recording
+            assert_eq!(Recording::parse(&recording.to_string()).unwrap(), recording);
+        }
+    }
+});
@@ -0,0 +1,17 @@
+//! SLCAN command parsing, line by line and as a byte stream
+
+#![no_main]
+
+use gs_usb::{slcan, SlcanDecoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(frame) = slcan::decode(text) {
+            let _ = slcan::encode(&frame);
+        }
+    }
+
+    let mut decoder = SlcanDecoder::new();
+    let _ = decoder.feed(data);
+});
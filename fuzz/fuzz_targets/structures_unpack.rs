@@ -0,0 +1,13 @@
+//! Control response parsing for all device-to-host structures
+
+#![no_main]
+
+use gs_usb::{DeviceCapability, DeviceInfo, DeviceState};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DeviceInfo::unpack(data);
+    let _ = DeviceCapability::unpack(data);
+    let _ = DeviceCapability::unpack_extended(data);
+    let _ = DeviceState::unpack(data);
+});
@@ -0,0 +1,393 @@
+//! Multi-device frame aggregation
+//!
+//! This module provides `Aggregator`, which reads from several `GsUsb` devices
+//! concurrently (one reader thread per device) and yields a single merged stream
+//! of frames tagged with a per-device label, e.g. for logging several buses of
+//! a vehicle into one file.
+//!
+//! Frames are ordered by the host time at which they were on the bus
+//! (`GsUsbFrame::host_instant()`, derived from the device timestamps), or by
+//! host arrival time for frames without one. A short reorder window holds
+//! frames back so that frames read slightly later by another reader thread
+//! can still be sorted in front of them.
+
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+
+/// Read timeout used by reader threads between checks of the stop flag
+const READER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Default reorder window
+pub const DEFAULT_REORDER_WINDOW: Duration = Duration::from_millis(20);
+
+/// A frame received by one of the aggregated devices
+#[derive(Debug, Clone)]
+pub struct TaggedFrame {
+    /// Label of the device that received the frame
+    pub label: Arc<str>,
+    /// Host time at which the frame was read from the device
+    pub host_time: Instant,
+    /// The received frame
+    pub frame: GsUsbFrame,
+}
+
+impl TaggedFrame {
+    /// Time by which frames are merged
+    ///
+    /// The frame's `host_instant()` if it has one, `host_time` otherwise.
+    pub fn time(&self) -> Instant {
+        self.frame.host_instant().unwrap_or(self.host_time)
+    }
+}
+
+enum ReaderEvent {
+    Frame(TaggedFrame),
+    Error { label: Arc<str>, error: GsUsbError },
+}
+
+/// Heap entry ordered by frame time (earliest first)
+struct Pending {
+    sequence: u64,
+    frame: TaggedFrame,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        // Reversed so that BinaryHeap (a max-heap) pops the earliest frame
+        (other.frame.time(), other.sequence).cmp(&(self.frame.time(), self.sequence))
+    }
+}
+
+struct Reader {
+    label: Arc<str>,
+    handle: JoinHandle<GsUsb>,
+}
+
+/// Reads several devices concurrently and merges their frames
+///
+/// Devices must already be configured and started.
+///
+/// # Example
+///
+/// ```no_run
+/// use gs_usb::{Aggregator, GsUsb, GsUsbError, GS_CAN_MODE_NORMAL};
+/// use std::time::Duration;
+///
+/// let mut aggregator = Aggregator::new();
+/// for (i, mut dev) in GsUsb::scan()?.into_iter().enumerate() {
+///     dev.set_bitrate(500_000)?;
+///     dev.start(GS_CAN_MODE_NORMAL)?;
+///     aggregator.add(format!("can{}", i), dev);
+/// }
+///
+/// loop {
+///     match aggregator.recv(Duration::from_millis(100)) {
+///         Ok(tagged) => println!("{:>6}  {}", tagged.label, tagged.frame),
+///         Err(GsUsbError::ReadTimeout) => continue,
+///         Err(e) => return Err(e),
+///     }
+/// }
+/// # Ok::<(), gs_usb::GsUsbError>(())
+/// ```
+pub struct Aggregator {
+    tx: Sender<ReaderEvent>,
+    rx: Receiver<ReaderEvent>,
+    readers: Vec<Reader>,
+    stop: Arc<AtomicBool>,
+    reorder_window: Duration,
+    pending: BinaryHeap<Pending>,
+    sequence: u64,
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Aggregator {
+    /// Create an aggregator with the default reorder window
+    pub fn new() -> Self {
+        Self::with_reorder_window(DEFAULT_REORDER_WINDOW)
+    }
+
+    /// Create an aggregator with a custom reorder window
+    ///
+    /// Frames are held back for this long before being released, so that
+    /// frames read slightly later by another reader thread can still be
+    /// sorted in front of them. A zero window releases frames immediately.
+    pub fn with_reorder_window(reorder_window: Duration) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            tx,
+            rx,
+            readers: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            reorder_window,
+            pending: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Add a started device and begin reading from it
+    pub fn add(&mut self, label: impl Into<String>, mut device: GsUsb) {
+        let label: Arc<str> = Arc::from(label.into());
+        let tx = self.tx.clone();
+        let stop = self.stop.clone();
+        let thread_label = label.clone();
+
+        let handle = std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                match device.read(READER_POLL_INTERVAL) {
+                    Ok(frame) => {
+                        let event = ReaderEvent::Frame(TaggedFrame {
+                            label: thread_label.clone(),
+                            host_time: Instant::now(),
+                            frame,
+                        });
+                        if tx.send(event).is_err() {
+                            break;
+                        }
+                    }
+                    Err(GsUsbError::ReadTimeout) => continue,
+                    Err(e) => {
+                        log::warn!("aggregator: reading {} failed: {}", thread_label, e);
+                        let _ = tx.send(ReaderEvent::Error {
+                            label: thread_label.clone(),
+                            error: e,
+                        });
+                        break;
+                    }
+                }
+            }
+            device
+        });
+
+        self.readers.push(Reader { label, handle });
+    }
+
+    /// Labels of all aggregated devices
+    pub fn labels(&self) -> Vec<Arc<str>> {
+        self.readers.iter().map(|r| r.label.clone()).collect()
+    }
+
+    /// Receive the next frame in time order (see `TaggedFrame::time()`)
+    ///
+    /// Returns `GsUsbError::ReadTimeout` if no frame became available within
+    /// `timeout`. If a device fails, `GsUsbError::DeviceFailed` with its label
+    /// is returned once and its reader stops; the other devices keep being
+    /// read. Once all readers have stopped and every frame has been returned,
+    /// fails with `GsUsbError::DeviceNotOpen`.
+    pub fn recv(&mut self, timeout: Duration) -> Result<TaggedFrame> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            // Checked before draining, so the events of stopped readers are
+            // all in the channel by then
+            let stopped = self.readers.iter().all(|r| r.handle.is_finished());
+
+            // Move everything already received into the reorder buffer
+            while let Ok(event) = self.rx.try_recv() {
+                self.push(event)?;
+            }
+
+            let now = Instant::now();
+            let wait_until = match self.pending.peek() {
+                Some(oldest) => {
+                    if stopped {
+                        return Ok(self.pending.pop().unwrap().frame);
+                    }
+                    let release = oldest.frame.time() + self.reorder_window;
+                    if release <= now {
+                        return Ok(self.pending.pop().unwrap().frame);
+                    }
+                    release.min(deadline)
+                }
+                None if stopped => return Err(GsUsbError::DeviceNotOpen),
+                None => deadline,
+            };
+
+            if now >= deadline {
+                return Err(GsUsbError::ReadTimeout);
+            }
+
+            // The channel never disconnects while `self.tx` is held
+            if let Ok(event) = self.rx.recv_timeout(wait_until - now) {
+                self.push(event)?;
+            }
+        }
+    }
+
+    fn push(&mut self, event: ReaderEvent) -> Result<()> {
+        match event {
+            ReaderEvent::Frame(frame) => {
+                self.sequence += 1;
+                self.pending.push(Pending {
+                    sequence: self.sequence,
+                    frame,
+                });
+                Ok(())
+            }
+            ReaderEvent::Error { label, error } => Err(GsUsbError::DeviceFailed {
+                label: label.to_string(),
+                source: Box::new(error),
+            }),
+        }
+    }
+
+    /// Stop all reader threads and return the devices with their labels
+    ///
+    /// Frames still held in the reorder buffer are discarded.
+    pub fn stop(mut self) -> Vec<(Arc<str>, GsUsb)> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.readers
+            .drain(..)
+            .filter_map(|r| r.handle.join().ok().map(|dev| (r.label, dev)))
+            .collect()
+    }
+}
+
+impl Drop for Aggregator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for reader in self.readers.drain(..) {
+            let _ = reader.handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::timestamp::TimestampSource;
+    use crate::virtual_bus::VirtualBus;
+
+    fn started(bus: &VirtualBus) -> GsUsb {
+        let mut dev = bus.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        dev
+    }
+
+    #[test]
+    fn test_merges_devices_in_arrival_order() {
+        let bus_a = VirtualBus::new();
+        let bus_b = VirtualBus::new();
+        let mut tx_a = started(&bus_a);
+        let mut tx_b = started(&bus_b);
+
+        let mut aggregator = Aggregator::new();
+        aggregator.add("a", started(&bus_a));
+        aggregator.add("b", started(&bus_b));
+
+        tx_a.send(&GsUsbFrame::with_data(0x100, &[])).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        tx_b.send(&GsUsbFrame::with_data(0x200, &[])).unwrap();
+
+        let first = aggregator.recv(Duration::from_secs(1)).unwrap();
+        let second = aggregator.recv(Duration::from_secs(1)).unwrap();
+        assert_eq!(&*first.label, "a");
+        assert_eq!(first.frame.arbitration_id(), 0x100);
+        assert_eq!(&*second.label, "b");
+        assert_eq!(second.frame.arbitration_id(), 0x200);
+        assert!(first.host_time <= second.host_time);
+
+        assert!(matches!(
+            aggregator.recv(Duration::from_millis(10)),
+            Err(GsUsbError::ReadTimeout)
+        ));
+
+        let devices = aggregator.stop();
+        assert_eq!(devices.len(), 2);
+    }
+
+    #[test]
+    fn test_orders_by_frame_time() {
+        let mut aggregator = Aggregator::with_reorder_window(Duration::ZERO);
+        let arrival = Instant::now();
+        let on_bus = arrival - Duration::from_millis(5);
+
+        // Read first, but was on the bus after the second frame
+        let mut late = GsUsbFrame::with_data(0x100, &[]);
+        late.set_timestamp(TimestampSource::SyncedHost, Some(on_bus));
+        let mut early = GsUsbFrame::with_data(0x200, &[]);
+        early.set_timestamp(
+            TimestampSource::SyncedHost,
+            Some(on_bus - Duration::from_millis(1)),
+        );
+        // No device timestamp: merged by arrival time
+        let local = GsUsbFrame::with_data(0x300, &[]);
+
+        for (label, frame) in [("a", late), ("b", early), ("c", local)] {
+            aggregator
+                .push(ReaderEvent::Frame(TaggedFrame {
+                    label: Arc::from(label),
+                    host_time: arrival,
+                    frame,
+                }))
+                .unwrap();
+        }
+
+        let ids: Vec<u32> = (0..3)
+            .map(|_| {
+                aggregator
+                    .recv(Duration::ZERO)
+                    .unwrap()
+                    .frame
+                    .arbitration_id()
+            })
+            .collect();
+        assert_eq!(ids, [0x200, 0x100, 0x300]);
+        assert!(matches!(
+            aggregator.recv(Duration::ZERO),
+            Err(GsUsbError::DeviceNotOpen)
+        ));
+    }
+
+    #[test]
+    fn test_reports_failed_device() {
+        let bus = VirtualBus::new();
+        let mut dev = started(&bus);
+        dev.cancel_token().cancel();
+
+        let mut aggregator = Aggregator::new();
+        aggregator.add("a", dev);
+
+        match aggregator.recv(Duration::from_secs(1)) {
+            Err(GsUsbError::DeviceFailed { label, source }) => {
+                assert_eq!(label, "a");
+                assert!(matches!(*source, GsUsbError::Cancelled));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // The only reader has stopped
+        assert!(matches!(
+            aggregator.recv(Duration::from_secs(1)),
+            Err(GsUsbError::DeviceNotOpen)
+        ));
+    }
+}
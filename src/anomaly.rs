@@ -0,0 +1,433 @@
+//! Bus anomaly heuristics
+//!
+//! `AnomalyDetector` is fed every frame read from a device and applies a few
+//! simple heuristics to the traffic, reporting what looks wrong as typed
+//! `Anomaly` events for alerting:
+//!
+//! - error-frame bursts: many error frames within one window
+//! - babbling nodes: one identifier taking most of a busy bus
+//! - bus-off flapping: repeated bus-off within a longer period
+//! - load spikes: a window's load far above the recent average
+//!
+//! Traffic is measured in fixed windows of hardware timestamp time, so logs
+//! can be checked like live traffic. Thresholds are set in `AnomalyConfig`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{AnomalyDetector, GsUsb, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_LISTEN_ONLY};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_LISTEN_ONLY | GS_CAN_MODE_HW_TIMESTAMP)?;
+//!
+//! let mut detector = AnomalyDetector::new(500_000.into());
+//! loop {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     for anomaly in detector.observe(&frame) {
+//!         eprintln!("anomaly: {anomaly}");
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use crate::bitrate::Bitrate;
+use crate::error_frame::CanErrorFrame;
+use crate::frame::GsUsbFrame;
+use crate::generator::frame_duration;
+use crate::id::CanId;
+
+/// Thresholds of the heuristics
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyConfig {
+    /// Length of the measurement windows
+    pub window: Duration,
+    /// Error frames in one window that make a burst
+    pub error_burst: u32,
+    /// Bus load in percent above which a babbling node is looked for
+    pub babble_min_load: f64,
+    /// Share of the bus time in percent one identifier must take to count
+    /// as babbling
+    pub babble_share: f64,
+    /// Bus-off events within `flap_period` that count as flapping
+    pub flap_count: u32,
+    /// Period in which bus-off events are counted
+    pub flap_period: Duration,
+    /// Factor by which a window's load must exceed the average of the
+    /// previous windows to be a spike
+    pub spike_factor: f64,
+    /// Percentage points by which a window's load must exceed the average,
+    /// so small loads don't spike on single frames
+    pub spike_min_delta: f64,
+    /// Windows averaged before load spikes are reported
+    pub spike_warmup: u32,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            error_burst: 10,
+            babble_min_load: 30.0,
+            babble_share: 50.0,
+            flap_count: 3,
+            flap_period: Duration::from_secs(10),
+            spike_factor: 2.0,
+            spike_min_delta: 20.0,
+            spike_warmup: 5,
+        }
+    }
+}
+
+/// Something unusual on the bus
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anomaly {
+    /// Many error frames within one window
+    ErrorBurst {
+        /// Hardware timestamp at the start of the window
+        start_us: u32,
+        /// Error frames in the window
+        error_frames: u32,
+    },
+    /// One identifier took most of the bus time of a busy window
+    BabblingNode {
+        /// Hardware timestamp at the start of the window
+        start_us: u32,
+        /// The identifier
+        id: CanId,
+        /// Its share of the bus time in percent
+        share_percent: f64,
+        /// Bus load of the window in percent
+        load_percent: f64,
+    },
+    /// The controller went bus-off repeatedly
+    BusOffFlapping {
+        /// Hardware timestamp of the last bus-off
+        timestamp_us: u32,
+        /// Bus-off events within the flap period
+        bus_offs: u32,
+    },
+    /// A window's load far exceeded the recent average
+    LoadSpike {
+        /// Hardware timestamp at the start of the window
+        start_us: u32,
+        /// Bus load of the window in percent
+        load_percent: f64,
+        /// Average load of the previous windows in percent
+        average_percent: f64,
+    },
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::ErrorBurst { error_frames, .. } => {
+                write!(f, "error burst: {error_frames} error frames")
+            }
+            Self::BabblingNode {
+                id,
+                share_percent,
+                load_percent,
+                ..
+            } => write!(
+                f,
+                "babbling node: {id} takes {share_percent:.0}% of a {load_percent:.0}% bus load"
+            ),
+            Self::BusOffFlapping { bus_offs, .. } => {
+                write!(f, "bus-off flapping: {bus_offs} bus-offs")
+            }
+            Self::LoadSpike {
+                load_percent,
+                average_percent,
+                ..
+            } => write!(
+                f,
+                "load spike: {load_percent:.0}% against an average of {average_percent:.0}%"
+            ),
+        }
+    }
+}
+
+/// Applies the anomaly heuristics to a stream of frames, see the module
+/// documentation
+#[derive(Debug, Clone)]
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    bitrate: Bitrate,
+    window_start: Option<u32>,
+    error_frames: u32,
+    busy: Duration,
+    busy_per_id: BTreeMap<CanId, Duration>,
+    bus_offs: VecDeque<u32>,
+    average_load: f64,
+    windows: u32,
+}
+
+impl AnomalyDetector {
+    /// Detector with the default thresholds for a bus at `bitrate`
+    pub fn new(bitrate: Bitrate) -> Self {
+        Self::with_config(bitrate, AnomalyConfig::default())
+    }
+
+    /// Detector with the given thresholds
+    pub fn with_config(bitrate: Bitrate, config: AnomalyConfig) -> Self {
+        Self {
+            config,
+            bitrate,
+            window_start: None,
+            error_frames: 0,
+            busy: Duration::ZERO,
+            busy_per_id: BTreeMap::new(),
+            bus_offs: VecDeque::new(),
+            average_load: 0.0,
+            windows: 0,
+        }
+    }
+
+    /// Thresholds in use
+    pub fn config(&self) -> &AnomalyConfig {
+        &self.config
+    }
+
+    /// Take a frame, reporting the anomalies of a window it completes and
+    /// bus-off flapping it shows
+    pub fn observe(&mut self, frame: &GsUsbFrame) -> Vec<Anomaly> {
+        let timestamp_us = frame.timestamp_us;
+        let mut anomalies = self.close_windows(timestamp_us);
+        if let Some(error) = CanErrorFrame::from_frame(frame) {
+            self.error_frames += 1;
+            if error.is_bus_off() {
+                anomalies.extend(self.record_bus_off(timestamp_us));
+            }
+        } else {
+            let duration = frame_duration(frame, &self.bitrate);
+            self.busy += duration;
+            *self
+                .busy_per_id
+                .entry(CanId::from_can_id(frame.can_id))
+                .or_default() += duration;
+        }
+        anomalies
+    }
+
+    /// Forget the traffic seen, including the load average
+    pub fn reset(&mut self) {
+        *self = Self::with_config(self.bitrate, self.config);
+    }
+
+    fn window_us(&self) -> u32 {
+        (self.config.window.as_micros() as u32).max(1)
+    }
+
+    /// Evaluate and close the windows `timestamp_us` lies past
+    fn close_windows(&mut self, timestamp_us: u32) -> Vec<Anomaly> {
+        let window_us = self.window_us();
+        let start_us = *self.window_start.get_or_insert(timestamp_us);
+        let elapsed = timestamp_us.wrapping_sub(start_us);
+        // Frames stamped slightly before the window count in it
+        if elapsed >= 1 << 31 || elapsed < window_us {
+            return Vec::new();
+        }
+        let anomalies = self.evaluate(start_us);
+        // Empty windows in between lower the average; after a hundred of
+        // them it is zero for all purposes
+        let empty = (elapsed / window_us - 1).min(100);
+        for _ in 0..empty {
+            self.update_average(0.0);
+        }
+        self.window_start = Some(start_us.wrapping_add(elapsed / window_us * window_us));
+        self.error_frames = 0;
+        self.busy = Duration::ZERO;
+        self.busy_per_id.clear();
+        anomalies
+    }
+
+    fn evaluate(&mut self, start_us: u32) -> Vec<Anomaly> {
+        let config = self.config;
+        let mut anomalies = Vec::new();
+        if self.error_frames >= config.error_burst {
+            anomalies.push(Anomaly::ErrorBurst {
+                start_us,
+                error_frames: self.error_frames,
+            });
+        }
+
+        let load_percent = 100.0 * self.busy.as_secs_f64() / config.window.as_secs_f64();
+        if load_percent >= config.babble_min_load {
+            let top = self.busy_per_id.iter().max_by_key(|&(_, busy)| *busy);
+            if let Some((&id, busy)) = top {
+                let share_percent = 100.0 * busy.as_secs_f64() / self.busy.as_secs_f64();
+                if share_percent >= config.babble_share {
+                    anomalies.push(Anomaly::BabblingNode {
+                        start_us,
+                        id,
+                        share_percent,
+                        load_percent,
+                    });
+                }
+            }
+        }
+
+        let average_percent = self.average_load;
+        if self.windows >= config.spike_warmup
+            && load_percent >= average_percent * config.spike_factor
+            && load_percent - average_percent >= config.spike_min_delta
+        {
+            anomalies.push(Anomaly::LoadSpike {
+                start_us,
+                load_percent,
+                average_percent,
+            });
+        }
+        self.update_average(load_percent);
+        anomalies
+    }
+
+    /// Average over the last windows, weighing older ones exponentially less
+    fn update_average(&mut self, load_percent: f64) {
+        const WEIGHT: f64 = 0.1;
+        self.average_load = if self.windows == 0 {
+            load_percent
+        } else {
+            self.average_load + WEIGHT * (load_percent - self.average_load)
+        };
+        self.windows = self.windows.saturating_add(1);
+    }
+
+    fn record_bus_off(&mut self, timestamp_us: u32) -> Option<Anomaly> {
+        let period_us = self
+            .config
+            .flap_period
+            .as_micros()
+            .min(u128::from(u32::MAX / 2)) as u32;
+        self.bus_offs.push_back(timestamp_us);
+        while self
+            .bus_offs
+            .front()
+            .is_some_and(|&at| timestamp_us.wrapping_sub(at) > period_us)
+        {
+            self.bus_offs.pop_front();
+        }
+        let bus_offs = self.bus_offs.len() as u32;
+        (bus_offs >= self.config.flap_count).then_some(Anomaly::BusOffFlapping {
+            timestamp_us,
+            bus_offs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{CAN_ERR_BUSOFF, CAN_ERR_FLAG, CAN_ERR_PROT};
+
+    fn frame(can_id: u32, timestamp_us: u32) -> GsUsbFrame {
+        let mut frame = GsUsbFrame::with_data(can_id, &[0; 8]);
+        frame.timestamp_us = timestamp_us;
+        frame
+    }
+
+    /// 111 bit frames at 111 kbit/s, 1 ms each
+    fn detector() -> AnomalyDetector {
+        let config = AnomalyConfig {
+            window: Duration::from_millis(100),
+            ..AnomalyConfig::default()
+        };
+        AnomalyDetector::with_config(Bitrate::new(111_000), config)
+    }
+
+    /// Feed `count` frames of `id` spread over the window starting at `start`
+    fn fill(detector: &mut AnomalyDetector, id: u32, start: u32, count: u32) -> Vec<Anomaly> {
+        let step = 100_000 / count;
+        (0..count)
+            .flat_map(|i| detector.observe(&frame(id, start + i * step)))
+            .collect()
+    }
+
+    #[test]
+    fn test_error_burst() {
+        let mut detector = detector();
+        for i in 0..10 {
+            assert!(detector
+                .observe(&frame(CAN_ERR_FLAG | CAN_ERR_PROT, i * 100))
+                .is_empty());
+        }
+        let anomalies = detector.observe(&frame(0x100, 100_000));
+        assert_eq!(
+            anomalies,
+            [Anomaly::ErrorBurst {
+                start_us: 0,
+                error_frames: 10
+            }]
+        );
+        assert_eq!(anomalies[0].to_string(), "error burst: 10 error frames");
+    }
+
+    #[test]
+    fn test_babbling_node() {
+        let mut detector = detector();
+        fill(&mut detector, 0x000, 0, 30);
+        fill(&mut detector, 0x100, 0, 10);
+        let anomalies = detector.observe(&frame(0x100, 100_000));
+        match anomalies[..] {
+            [Anomaly::BabblingNode {
+                id,
+                share_percent,
+                load_percent,
+                ..
+            }] => {
+                assert_eq!(id, CanId::Standard(0));
+                assert!((share_percent - 75.0).abs() < 0.1);
+                assert!((load_percent - 40.0).abs() < 0.1);
+            }
+            _ => panic!("unexpected {anomalies:?}"),
+        }
+    }
+
+    #[test]
+    fn test_bus_off_flapping() {
+        let mut detector = detector();
+        let bus_off = |at| frame(CAN_ERR_FLAG | CAN_ERR_BUSOFF, at);
+        assert!(detector.observe(&bus_off(0)).is_empty());
+        assert!(detector.observe(&bus_off(5_000_000)).is_empty());
+        // The first one is out of the period by now
+        assert!(detector.observe(&bus_off(10_500_000)).is_empty());
+        let anomalies = detector.observe(&bus_off(11_000_000));
+        assert_eq!(
+            anomalies.last(),
+            Some(&Anomaly::BusOffFlapping {
+                timestamp_us: 11_000_000,
+                bus_offs: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_spike() {
+        let mut detector = detector();
+        for window in 0..6 {
+            assert!(fill(&mut detector, 0x100 + window, window * 100_000, 10).is_empty());
+        }
+        // 10% average, then 25%: not enough above the average
+        fill(&mut detector, 0x200, 600_000, 25);
+        assert!(fill(&mut detector, 0x200, 700_000, 10).is_empty());
+
+        // 40% spread over four identifiers, so no node babbles
+        for id in 0x300..0x304 {
+            assert!(fill(&mut detector, id, 800_000, 10).is_empty());
+        }
+        let anomalies = detector.observe(&frame(0x100, 900_000));
+        assert!(matches!(
+            anomalies[..],
+            [Anomaly::LoadSpike { start_us: 800_000, load_percent, .. }]
+                if (load_percent - 40.0).abs() < 0.1
+        ));
+
+        detector.reset();
+        assert!(fill(&mut detector, 0x100, 0, 1).is_empty());
+    }
+}
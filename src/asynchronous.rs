@@ -0,0 +1,371 @@
+//! Async device API and embedded-can style traits
+//!
+//! `AsyncGsUsb` wraps a `GsUsb` for use from tokio tasks: a background thread
+//! reads the device and hands frames to `receive()`, while `send()` and other
+//! device requests run on tokio's blocking thread pool.
+//!
+//! `subscribe()` hands out streams of the received frames matching some
+//! filters, each with its own bounded buffer, so concurrent tasks can each
+//! await the traffic they care about.
+//!
+//! `AsyncCan` is the async counterpart of `embedded_can::blocking::Can`,
+//! with the same method names and semantics and frames implementing
+//! `embedded_can::Frame`, so drivers written generically against it run on
+//! gs_usb hardware as well as on other CAN controllers implementing it.
+//!
+//! Enabled with the `async` cargo feature, which includes `embedded-can`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::asynchronous::{AsyncCan, AsyncGsUsb, Frame};
+//! use gs_usb::{CanId, GsUsb, GsUsbFrame, GS_CAN_MODE_NORMAL};
+//!
+//! // A driver that only knows the traits
+//! async fn ping<C: AsyncCan>(can: &mut C) -> Result<C::Frame, C::Error> {
+//!     let request = C::Frame::new(CanId::Standard(0x7DF), &[0x02, 0x01, 0x00]).unwrap();
+//!     can.transmit(&request).await?;
+//!     can.receive().await
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() -> gs_usb::Result<()> {
+//!     let mut dev = GsUsb::scan()?.remove(0);
+//!     dev.set_bitrate(500_000)?;
+//!     dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//!     let mut dev = AsyncGsUsb::new(dev);
+//!     let reply: GsUsbFrame = ping(&mut dev).await?;
+//!     println!("{reply}");
+//!     let state = dev.with_device(|dev| dev.get_state(0)).await?;
+//!     println!("{}", state.state_name());
+//!     Ok(())
+//! }
+//! ```
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread::JoinHandle;
+
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+
+use crate::config::IdFilter;
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::shared::spawn_reader;
+use crate::sync::lock;
+
+/// Received frames buffered for `receive()`
+const RECEIVE_QUEUE_DEPTH: usize = 1024;
+
+/// Frames buffered per subscription by `subscribe()`
+pub const SUBSCRIPTION_DEPTH: usize = 256;
+
+pub use embedded_can::{Frame, Id};
+
+/// An async CAN interface, as `embedded_can::blocking::Can` with async methods
+#[allow(async_fn_in_trait)]
+pub trait AsyncCan {
+    /// Frame type
+    type Frame: Frame;
+    /// Error type
+    type Error: embedded_can::Error;
+
+    /// Send a frame, waiting until the controller has taken it
+    async fn transmit(&mut self, frame: &Self::Frame) -> std::result::Result<(), Self::Error>;
+
+    /// Wait for the next received frame
+    async fn receive(&mut self) -> std::result::Result<Self::Frame, Self::Error>;
+}
+
+/// Sending end of a subscription
+struct Subscriber {
+    filters: Vec<IdFilter>,
+    sender: mpsc::Sender<GsUsbFrame>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscriber {
+    fn wants(&self, frame: &GsUsbFrame) -> bool {
+        frame.is_rx_frame()
+            && !frame.is_error_frame()
+            && (self.filters.is_empty() || self.filters.iter().any(|f| f.matches(frame)))
+    }
+
+    /// Hand a frame over, returning false once the subscription is gone
+    fn offer(&self, frame: &GsUsbFrame) -> bool {
+        match self.sender.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+type Subscribers = Arc<Mutex<Vec<Subscriber>>>;
+
+/// Thread reading the device into the receive queue and the subscriptions
+struct Reader {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Reader {
+    fn spawn(
+        device: Arc<Mutex<GsUsb>>,
+        queue: mpsc::Sender<Result<GsUsbFrame>>,
+        subscribers: Subscribers,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = spawn_reader(device, Arc::clone(&stop), move |result| {
+            let subscribed = {
+                let mut subscribers = lock(&subscribers);
+                if let Ok(frame) = &result {
+                    subscribers.retain(|s| !s.wants(frame) || s.offer(frame));
+                }
+                !subscribers.is_empty()
+            };
+            if subscribed {
+                // Don't hold up the subscriptions for `receive()`
+                !matches!(
+                    queue.try_send(result),
+                    Err(mpsc::error::TrySendError::Closed(_))
+                )
+            } else {
+                queue.blocking_send(result).is_ok()
+            }
+        });
+        Self { stop, handle }
+    }
+}
+
+/// Received frames matching a subscription's filters, see
+/// `AsyncGsUsb::subscribe()`
+///
+/// A `Stream` of frames, ending when the device is dropped or fails.
+pub struct Subscription {
+    frames: mpsc::Receiver<GsUsbFrame>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// Wait for the next matching frame, `None` once reading has ended
+    pub async fn recv(&mut self) -> Option<GsUsbFrame> {
+        self.frames.recv().await
+    }
+
+    /// Matching frames dropped because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for Subscription {
+    type Item = GsUsbFrame;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<GsUsbFrame>> {
+        self.frames.poll_recv(cx)
+    }
+}
+
+/// A started device driven from async code
+///
+/// Received frames, echoes and errors are queued by a reader thread until
+/// `receive()` takes them. Transient errors (bus errors reported with
+/// `set_error_frames_as_errors()`, RX overflows) are passed on and reading
+/// continues; after any other error, `receive()` returns it and then
+/// `GsUsbError::DeviceNotOpen`.
+pub struct AsyncGsUsb {
+    device: Arc<Mutex<GsUsb>>,
+    frames: mpsc::Receiver<Result<GsUsbFrame>>,
+    subscribers: Subscribers,
+    reader: Option<Reader>,
+}
+
+impl AsyncGsUsb {
+    /// Start reading a started device
+    pub fn new(dev: GsUsb) -> Self {
+        let device = Arc::new(Mutex::new(dev));
+        let (sender, frames) = mpsc::channel(RECEIVE_QUEUE_DEPTH);
+        let subscribers = Subscribers::default();
+        let reader = Reader::spawn(Arc::clone(&device), sender, Arc::clone(&subscribers));
+        Self {
+            device,
+            frames,
+            subscribers,
+            reader: Some(reader),
+        }
+    }
+
+    /// Stream of the received frames passing any of `filters`, all received
+    /// frames if there are none
+    ///
+    /// Each subscription buffers up to `SUBSCRIPTION_DEPTH` frames; when its
+    /// task falls behind, further frames are dropped for it alone and
+    /// counted by `Subscription::dropped()`. Echoes and error frames only go
+    /// to `receive()`. Frames still go to `receive()` as well, but while
+    /// subscriptions exist its queue drops frames when full instead of
+    /// holding up reading.
+    ///
+    /// ```no_run
+    /// # async fn run(dev: gs_usb::asynchronous::AsyncGsUsb) {
+    /// use gs_usb::IdFilter;
+    /// use tokio_stream::StreamExt;
+    ///
+    /// let mut responses = dev.subscribe(&[IdFilter::new(0x7E8, 0x7F8)]);
+    /// while let Some(frame) = responses.next().await {
+    ///     println!("{frame}");
+    /// }
+    /// # }
+    /// ```
+    pub fn subscribe(&self, filters: &[IdFilter]) -> Subscription {
+        self.subscribe_with_depth(filters, SUBSCRIPTION_DEPTH)
+    }
+
+    /// `subscribe()` with a buffer of `depth` frames
+    pub fn subscribe_with_depth(&self, filters: &[IdFilter], depth: usize) -> Subscription {
+        let (sender, frames) = mpsc::channel(depth.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        lock(&self.subscribers).push(Subscriber {
+            filters: filters.to_vec(),
+            sender,
+            dropped: Arc::clone(&dropped),
+        });
+        Subscription { frames, dropped }
+    }
+
+    /// Send a frame
+    pub async fn send(&self, frame: &GsUsbFrame) -> Result<()> {
+        let frame = frame.clone();
+        self.with_device(move |dev| dev.send(&frame)).await
+    }
+
+    /// Wait for the next received frame
+    pub async fn receive(&mut self) -> Result<GsUsbFrame> {
+        self.frames
+            .recv()
+            .await
+            .unwrap_or(Err(GsUsbError::DeviceNotOpen))
+    }
+
+    /// The next received frame if one is queued, without waiting
+    pub fn try_receive(&mut self) -> Option<Result<GsUsbFrame>> {
+        self.frames.try_recv().ok()
+    }
+
+    /// Run blocking requests on the device, e.g. `get_state()`
+    ///
+    /// `f` runs on tokio's blocking thread pool, between reads of the
+    /// reader thread.
+    pub async fn with_device<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut GsUsb) -> Result<T> + Send + 'static,
+    {
+        let device = Arc::clone(&self.device);
+        match tokio::task::spawn_blocking(move || f(&mut lock(&device))).await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(std::io::Error::other(e).into()),
+        }
+    }
+}
+
+impl AsyncCan for AsyncGsUsb {
+    type Frame = GsUsbFrame;
+    type Error = GsUsbError;
+
+    async fn transmit(&mut self, frame: &GsUsbFrame) -> Result<()> {
+        self.send(frame).await
+    }
+
+    async fn receive(&mut self) -> Result<GsUsbFrame> {
+        AsyncGsUsb::receive(self).await
+    }
+}
+
+impl Drop for AsyncGsUsb {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            reader.stop.store(true, Ordering::Relaxed);
+            // Unblock a reader waiting for queue space
+            self.frames.close();
+            if reader.handle.join().is_err() {
+                log::warn!("Async device reader panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::id::CanId;
+    use crate::virtual_bus::VirtualBus;
+
+    fn started(bus: &VirtualBus) -> AsyncGsUsb {
+        let mut dev = bus.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        AsyncGsUsb::new(dev)
+    }
+
+    #[tokio::test]
+    async fn test_transmit_and_receive() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus);
+        let mut b = started(&bus);
+
+        // Through the traits only
+        async fn request<C: AsyncCan>(can: &mut C, id: Id) -> std::result::Result<(), C::Error> {
+            can.transmit(&C::Frame::new(id, &[0xAA]).unwrap()).await
+        }
+        request(&mut a, CanId::Standard(0x123).into())
+            .await
+            .unwrap();
+        let frame = AsyncCan::receive(&mut b).await.unwrap();
+        assert_eq!(CanId::from(frame.id()), CanId::Standard(0x123));
+        assert_eq!(Frame::data(&frame), [0xAA]);
+
+        // The sender sees its echo
+        assert!(a.receive().await.unwrap().is_echo_frame());
+        assert!(b.try_receive().is_none());
+
+        let state = b.with_device(|dev| dev.get_state(0)).await.unwrap();
+        assert_eq!(state.state_name(), "ERROR_ACTIVE");
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        use tokio_stream::StreamExt;
+
+        let bus = VirtualBus::new();
+        let a = started(&bus);
+        let b = started(&bus);
+        let mut low = b.subscribe(&[IdFilter::new(0x100, 0x700)]);
+        let mut all = b.subscribe(&[]);
+        let mut small = b.subscribe_with_depth(&[IdFilter::exact(0x200, false)], 1);
+
+        for id in [0x100, 0x200, 0x1FF, 0x200] {
+            a.send(&GsUsbFrame::with_data(id, &[])).await.unwrap();
+        }
+        let ids = |frames: Vec<GsUsbFrame>| frames.iter().map(|f| f.can_id).collect::<Vec<_>>();
+        let frames = (&mut all).take(4).collect().await;
+        assert_eq!(ids(frames), [0x100, 0x200, 0x1FF, 0x200]);
+        let frames = (&mut low).take(2).collect().await;
+        assert_eq!(ids(frames), [0x100, 0x1FF]);
+        assert_eq!(small.recv().await.unwrap().can_id, 0x200);
+        assert_eq!(small.dropped(), 1);
+
+        drop(b);
+        assert!(all.next().await.is_none());
+    }
+}
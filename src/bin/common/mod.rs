@@ -0,0 +1,111 @@
+//! Command line handling shared by the `gsusb-*` tools
+
+// Each tool compiles its own copy and uses only part of it
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use gs_usb::{CanId, Config, DeviceProfile, GsUsb, GsUsbBuilder, GsUsbError, IdFilter};
+
+/// Options selecting an adapter
+#[derive(Debug, Clone, clap::Args)]
+pub struct DeviceArgs {
+    /// Use the adapter with this serial number
+    #[arg(long)]
+    pub serial: Option<String>,
+    /// Use the adapter with this user ID (decimal or 0x hex)
+    #[arg(long, value_parser = parse_u32)]
+    pub user_id: Option<u32>,
+}
+
+impl DeviceArgs {
+    /// Builder selecting the adapter
+    pub fn builder(&self) -> GsUsbBuilder {
+        select(self.serial.as_deref(), self.user_id)
+    }
+
+    /// Whether an adapter was selected rather than left to the first found
+    pub fn is_selected(&self) -> bool {
+        self.serial.is_some() || self.user_id.is_some()
+    }
+}
+
+/// Builder selecting an adapter by serial number and user ID, if given
+pub fn select(serial: Option<&str>, user_id: Option<u32>) -> GsUsbBuilder {
+    let mut builder = GsUsbBuilder::new();
+    if let Some(serial) = serial {
+        builder = builder.serial(serial);
+    }
+    if let Some(user_id) = user_id {
+        builder = builder.user_id(user_id);
+    }
+    builder
+}
+
+/// Start every channel up to `channel` with `config`
+pub fn start_channels(dev: &mut GsUsb, config: &Config, channel: u8) -> gs_usb::Result<()> {
+    let channels = vec![config.clone(); usize::from(channel) + 1];
+    dev.apply_profile(&DeviceProfile::new(channels))
+}
+
+/// Set up logging from `RUST_LOG`, warnings by default
+pub fn init_logging() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+}
+
+/// Parse a number given in decimal or with a `0x` prefix in hex
+pub fn parse_u32(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|e| format!("invalid number \"{text}\": {e}"))
+}
+
+/// Parse a non-negative number of seconds, e.g. `2.5`
+pub fn parse_seconds(text: &str) -> Result<Duration, String> {
+    let seconds: f64 = text
+        .parse()
+        .map_err(|e| format!("invalid number of seconds \"{text}\": {e}"))?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| format!("expected a finite, non-negative number of seconds, got \"{text}\""))
+}
+
+/// Parse a CAN identifier, always in hex as in candump
+pub fn parse_id(text: &str) -> Result<u32, String> {
+    let hex = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u32::from_str_radix(hex, 16).map_err(|e| format!("invalid identifier \"{text}\": {e}"))
+}
+
+/// Parse an identifier into `GsUsbFrame::can_id` form
+///
+/// As in candump, 8 hex digits or a value above 7FF make it extended, as
+/// does a trailing `x`.
+pub fn parse_can_id(text: &str) -> Result<u32, String> {
+    text.parse::<CanId>()
+        .map(|id| id.to_can_id())
+        .map_err(|e| e.to_string())
+}
+
+/// Parse a candump style filter, `ID:MASK` or just `ID` for an exact match
+pub fn parse_filter(text: &str) -> Result<IdFilter, String> {
+    text.parse().map_err(|e: GsUsbError| e.to_string())
+}
+
+/// Parse hex bytes like `DEADBEEF` or `de.ad.be.ef`
+pub fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = text.chars().filter(|c| *c != '.' && *c != ' ').collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in \"{text}\""));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16).map_err(|_| format!("invalid hex byte \"{byte}\""))
+        })
+        .collect()
+}
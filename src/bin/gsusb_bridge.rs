@@ -0,0 +1,173 @@
+//! Forward frames from one GS-USB adapter to another
+//!
+//! Usage: gsusb-bridge --from-serial A --to-serial B [--filter 100:700]
+//!        [--remap 123=456] [--fd-to-classic fragment]
+//!
+//! Runs a `Gateway` between two adapters, e.g. to put a node behind a
+//! filtering or rewriting man in the middle, and prints its counters.
+
+mod common;
+
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use common::{parse_can_id, parse_filter, parse_seconds, parse_u32, select, start_channels};
+use gs_usb::gateway::GatewayStats;
+use gs_usb::{Bitrate, Config, FdToClassic, Gateway, GsUsb, GsUsbError, IdFilter, Translation};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "gsusb-bridge",
+    version,
+    about = "Forward frames between two adapters"
+)]
+struct Args {
+    /// Receive from the adapter with this serial number
+    #[arg(long)]
+    from_serial: Option<String>,
+    /// Receive from the adapter with this user ID
+    #[arg(long, value_parser = parse_u32)]
+    from_user_id: Option<u32>,
+    /// Receive on this channel of the source adapter
+    #[arg(long, default_value_t = 0)]
+    from_channel: u8,
+    /// Send to the adapter with this serial number
+    #[arg(long)]
+    to_serial: Option<String>,
+    /// Send to the adapter with this user ID
+    #[arg(long, value_parser = parse_u32)]
+    to_user_id: Option<u32>,
+    /// Send on this channel of the destination adapter
+    #[arg(long, default_value_t = 0)]
+    to_channel: u8,
+    /// Bitrate of the source bus, e.g. 500k or 500k/2M
+    #[arg(short, long, default_value = "500k")]
+    bitrate: Bitrate,
+    /// Bitrate of the destination bus (default: the source bitrate)
+    #[arg(long)]
+    to_bitrate: Option<Bitrate>,
+    /// Forward only frames matching ID:MASK (hex); may be repeated
+    #[arg(short, long = "filter", value_parser = parse_filter)]
+    filters: Vec<IdFilter>,
+    /// Forward identifier FROM as TO (hex), e.g. 123=456; may be repeated
+    #[arg(short, long = "remap", value_parser = parse_remap)]
+    remaps: Vec<(u32, u32)>,
+    /// FD frames over 8 bytes towards a classic bus: drop, truncate or fragment
+    #[arg(long, value_parser = parse_fd_to_classic, default_value = "drop")]
+    fd_to_classic: FdToClassic,
+    /// Print the counters every this many seconds; 0 only at the end
+    #[arg(long, value_parser = parse_seconds, default_value = "1")]
+    stats: Duration,
+    /// Stop after this many seconds
+    #[arg(short, long, value_parser = parse_seconds)]
+    duration: Option<Duration>,
+}
+
+fn parse_remap(text: &str) -> Result<(u32, u32), String> {
+    let (from, to) = text
+        .split_once('=')
+        .ok_or_else(|| format!("expected FROM=TO, got \"{text}\""))?;
+    Ok((parse_can_id(from)?, parse_can_id(to)?))
+}
+
+fn parse_fd_to_classic(text: &str) -> Result<FdToClassic, String> {
+    match text {
+        "drop" => Ok(FdToClassic::Drop),
+        "truncate" => Ok(FdToClassic::Truncate),
+        "fragment" => Ok(FdToClassic::Fragment),
+        _ => Err(format!(
+            "expected drop, truncate or fragment, got \"{text}\""
+        )),
+    }
+}
+
+fn main() -> ExitCode {
+    common::init_logging();
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gsusb-bridge: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> gs_usb::Result<()> {
+    let (mut source, mut destination) = open_devices(args)?;
+    let to_bitrate = args.to_bitrate.unwrap_or(args.bitrate);
+    start_channels(&mut source, &config(args.bitrate), args.from_channel)?;
+    start_channels(&mut destination, &config(to_bitrate), args.to_channel)?;
+    eprintln!(
+        "Forwarding {source} channel {} to {destination} channel {}",
+        args.from_channel, args.to_channel
+    );
+
+    let translation = if to_bitrate.is_fd() {
+        Translation::to_fd()
+    } else {
+        Translation::to_classic(args.fd_to_classic)
+    };
+    let mut gateway = Gateway::new(source, destination)
+        .with_translation(translation)
+        .with_filters(args.filters.clone())
+        .with_channels(args.from_channel, args.to_channel);
+    for &(from, to) in &args.remaps {
+        gateway = gateway.remap(from, to);
+    }
+
+    let start = Instant::now();
+    let mut last_report = start;
+    while args
+        .duration
+        .is_none_or(|duration| start.elapsed() < duration)
+    {
+        gateway.poll(Duration::from_millis(100))?;
+        if !args.stats.is_zero() && last_report.elapsed() >= args.stats {
+            print_stats(&gateway.stats(), start.elapsed());
+            last_report = Instant::now();
+        }
+    }
+    print_stats(&gateway.stats(), start.elapsed());
+
+    let (source, destination) = gateway.into_devices();
+    source.close()?;
+    destination.close()
+}
+
+/// Source and destination adapter; the first two found if none is selected
+fn open_devices(args: &Args) -> gs_usb::Result<(GsUsb, GsUsb)> {
+    let from_selected = args.from_serial.is_some() || args.from_user_id.is_some();
+    let to_selected = args.to_serial.is_some() || args.to_user_id.is_some();
+    if !from_selected && !to_selected {
+        let mut devices = GsUsb::scan()?.into_iter();
+        return match (devices.next(), devices.next()) {
+            (Some(source), Some(destination)) => Ok((source, destination)),
+            _ => Err(GsUsbError::InvalidConfig(
+                "found fewer than two adapters; select them with --from-serial and --to-serial"
+                    .into(),
+            )),
+        };
+    }
+    let source = select(args.from_serial.as_deref(), args.from_user_id).open()?;
+    let destination = select(args.to_serial.as_deref(), args.to_user_id).open()?;
+    Ok((source, destination))
+}
+
+fn config(bitrate: Bitrate) -> Config {
+    let mut config = Config::new(bitrate.nominal);
+    config.set_bitrates(bitrate);
+    config
+}
+
+fn print_stats(stats: &GatewayStats, elapsed: Duration) {
+    println!(
+        "{:.1} s: {} received, {} forwarded, {} filtered, {} dropped",
+        elapsed.as_secs_f64(),
+        stats.received,
+        stats.forwarded,
+        stats.filtered,
+        stats.dropped
+    );
+}
@@ -0,0 +1,162 @@
+//! Generate CAN traffic through a GS-USB adapter
+//!
+//! Usage: gsusb-gen [--bitrate 500k] [--ids 100-1FF] [--load 30] [--count N]
+//!
+//! Sends random or patterned frames, paced to a frame rate or bus load, for
+//! stress-testing devices and the software on the other end of the bus.
+
+mod common;
+
+use std::ops::RangeInclusive;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+use common::{parse_hex_bytes, parse_id, parse_seconds, DeviceArgs};
+use gs_usb::generator::Pacing;
+use gs_usb::{Bitrate, Generator, PayloadPattern};
+
+#[derive(Debug, Parser)]
+#[command(name = "gsusb-gen", version, about = "Generate CAN traffic")]
+struct Args {
+    #[command(flatten)]
+    device: DeviceArgs,
+    /// Bitrate, e.g. 500k, or 500k/2M for CAN FD
+    #[arg(short, long, default_value = "500k")]
+    bitrate: Bitrate,
+    /// Identifier range in hex, e.g. 100-1FF, or a single identifier
+    #[arg(long, value_parser = parse_id_range, default_value = "0-7FF")]
+    ids: RangeInclusive<u32>,
+    /// Use extended (29 bit) identifiers
+    #[arg(short = 'x', long)]
+    extended: bool,
+    /// Walk through the identifiers in order instead of at random
+    #[arg(long)]
+    sequential: bool,
+    /// Payload length range in bytes, e.g. 0-8 or 64
+    #[arg(short, long, value_parser = parse_length_range, default_value = "0-8")]
+    length: RangeInclusive<usize>,
+    /// Payload: random, counter, or hex bytes repeated to the length
+    #[arg(short, long, value_parser = parse_payload, default_value = "random")]
+    payload: PayloadPattern,
+    /// Frames per second
+    #[arg(short, long, value_parser = parse_positive, conflicts_with = "load")]
+    rate: Option<f64>,
+    /// Bus load in percent
+    #[arg(long, value_parser = parse_positive)]
+    load: Option<f64>,
+    /// Share of CAN FD frames, 0 to 1 (needs a data bitrate)
+    #[arg(long, default_value_t = 0.0)]
+    fd_ratio: f64,
+    /// Send CAN FD frames with bit rate switch
+    #[arg(long)]
+    brs: bool,
+    /// Stop after this many frames
+    #[arg(short = 'n', long)]
+    count: Option<u64>,
+    /// Stop after this many seconds
+    #[arg(short, long, value_parser = parse_seconds)]
+    duration: Option<Duration>,
+    /// Seed of the random generator
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    /// Loop frames back inside the adapter instead of sending them on the bus
+    #[arg(long)]
+    loopback: bool,
+}
+
+fn parse_positive(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        Ok(_) => Err(format!("expected a finite number above 0, got \"{text}\"")),
+        Err(e) => Err(format!("invalid number \"{text}\": {e}")),
+    }
+}
+
+fn parse_id_range(text: &str) -> Result<RangeInclusive<u32>, String> {
+    match text.split_once('-') {
+        Some((first, last)) => Ok(parse_id(first)?..=parse_id(last)?),
+        None => parse_id(text).map(|id| id..=id),
+    }
+}
+
+fn parse_length_range(text: &str) -> Result<RangeInclusive<usize>, String> {
+    let parse = |text: &str| {
+        text.parse::<usize>()
+            .map_err(|e| format!("invalid length \"{text}\": {e}"))
+    };
+    match text.split_once('-') {
+        Some((first, last)) => Ok(parse(first)?..=parse(last)?),
+        None => parse(text).map(|length| length..=length),
+    }
+}
+
+fn parse_payload(text: &str) -> Result<PayloadPattern, String> {
+    match text {
+        "random" => Ok(PayloadPattern::Random),
+        "counter" => Ok(PayloadPattern::Counter),
+        bytes => parse_hex_bytes(bytes).map(PayloadPattern::Fixed),
+    }
+}
+
+fn main() -> ExitCode {
+    common::init_logging();
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gsusb-gen: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> gs_usb::Result<()> {
+    if args.fd_ratio > 0.0 && !args.bitrate.is_fd() {
+        return Err(gs_usb::GsUsbError::InvalidConfig(
+            "--fd-ratio needs a data bitrate, e.g. --bitrate 500k/2M".into(),
+        ));
+    }
+    let mut builder = args.device.builder().bitrates(args.bitrate);
+    if args.loopback {
+        builder = builder.loopback();
+    }
+    let mut dev = builder.open_and_start()?;
+
+    let mut generator = Generator::new(args.seed)
+        .lengths(args.length.clone())
+        .payload(args.payload.clone())
+        .fd_mix(args.fd_ratio, args.brs);
+    generator = if args.extended {
+        generator.extended_ids(args.ids.clone())
+    } else {
+        generator.ids(args.ids.clone())
+    };
+    if args.sequential {
+        generator = generator.sequential();
+    }
+    if let Some(rate) = args.rate {
+        generator = generator.frame_rate(rate);
+    }
+    if let Some(load) = args.load {
+        generator = generator.bus_load(load, args.bitrate);
+    }
+
+    match generator.pacing() {
+        Pacing::Unlimited => eprintln!("Sending on {dev} as fast as possible"),
+        Pacing::FrameRate(rate) => eprintln!("Sending on {dev} at {rate} frames/s"),
+        Pacing::BusLoad { percent, bitrate } => {
+            eprintln!("Sending on {dev} at {percent}% load of {bitrate}")
+        }
+    }
+    let stats = generator.run(&mut dev, args.count, args.duration)?;
+    println!(
+        "{} frames ({} FD), {} bytes in {:.3} s, {:.1} frames/s",
+        stats.frames,
+        stats.fd_frames,
+        stats.bytes,
+        stats.elapsed.as_secs_f64(),
+        stats.frame_rate()
+    );
+    dev.close()
+}
@@ -0,0 +1,156 @@
+//! Show what GS-USB adapters are connected and what they can do
+//!
+//! Usage: gsusb-info [--serial S | --user-id N]
+//!
+//! Prints identity, firmware and hardware versions, supported features, bit
+//! timing constraints, and per channel the bus state, error counters and
+//! termination, of the selected adapter or of every adapter found.
+
+mod common;
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use common::DeviceArgs;
+use gs_usb::{DeviceCapability, GsUsb, GsUsbError};
+
+#[derive(Debug, Parser)]
+#[command(name = "gsusb-info", version, about = "Show GS-USB adapter details")]
+struct Args {
+    #[command(flatten)]
+    device: DeviceArgs,
+}
+
+fn main() -> ExitCode {
+    common::init_logging();
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gsusb-info: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> gs_usb::Result<()> {
+    let devices = if args.device.is_selected() {
+        vec![args.device.builder().open()?]
+    } else {
+        GsUsb::scan()?
+    };
+    if devices.is_empty() {
+        return Err(GsUsbError::DeviceNotFound);
+    }
+    for (i, mut dev) in devices.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_device(&mut dev)?;
+    }
+    Ok(())
+}
+
+fn print_device(dev: &mut GsUsb) -> gs_usb::Result<()> {
+    println!("{dev}");
+    let serial = dev.serial_number()?;
+    println!(
+        "  Serial number:    {}",
+        if serial.is_empty() { "none" } else { &serial }
+    );
+    println!(
+        "  User ID:          {}",
+        optional(dev.user_id().map(|id| format!("0x{id:08X}")))?
+    );
+
+    let info = dev.device_info()?;
+    println!("  Firmware version: {:.1}", info.firmware_version());
+    println!("  Hardware version: {:.1}", info.hardware_version());
+    println!("  Channels:         {}", info.channel_count());
+
+    let capability = dev.device_capability()?;
+    println!(
+        "  Features:         {} (0x{:08X})",
+        capability.feature_names().join(" "),
+        capability.feature
+    );
+    let quirks = dev.quirks()?;
+    if !quirks.is_none() {
+        println!("  Quirks:           {quirks}");
+    }
+    print_timing_constraints(&capability);
+    if let Some(extended) = dev.device_capability_extended()? {
+        print_data_timing_constraints(&extended);
+    }
+
+    for channel in 0..u16::from(info.channel_count()) {
+        let state = dev.get_state(channel).map(|state| {
+            format!(
+                "{} (TEC {}, REC {})",
+                state.state_name(),
+                state.txerr,
+                state.rxerr
+            )
+        });
+        let termination = dev
+            .termination(channel)
+            .map(|on| if on { "on" } else { "off" });
+        println!("  Channel {channel}:");
+        println!("    State:       {}", optional(state)?);
+        println!("    Termination: {}", optional(termination)?);
+    }
+    Ok(())
+}
+
+fn print_timing_constraints(capability: &DeviceCapability) {
+    println!("  Clock:            {:.1} MHz", capability.clock_mhz());
+    println!(
+        "  Bit timing:       TSEG1 {}-{}, TSEG2 {}-{}, SJW max {}, BRP {}-{} (step {})",
+        capability.tseg1_min,
+        capability.tseg1_max,
+        capability.tseg2_min,
+        capability.tseg2_max,
+        capability.sjw_max,
+        capability.brp_min,
+        capability.brp_max,
+        capability.brp_inc
+    );
+}
+
+fn print_data_timing_constraints(extended: &DeviceCapability) {
+    if let (
+        Some(dtseg1_min),
+        Some(dtseg1_max),
+        Some(dtseg2_min),
+        Some(dtseg2_max),
+        Some(dsjw_max),
+        Some(dbrp_min),
+        Some(dbrp_max),
+        Some(dbrp_inc),
+    ) = (
+        extended.dtseg1_min,
+        extended.dtseg1_max,
+        extended.dtseg2_min,
+        extended.dtseg2_max,
+        extended.dsjw_max,
+        extended.dbrp_min,
+        extended.dbrp_max,
+        extended.dbrp_inc,
+    ) {
+        println!(
+            "  Data timing:      TSEG1 {dtseg1_min}-{dtseg1_max}, TSEG2 {dtseg2_min}-{dtseg2_max}, \
+             SJW max {dsjw_max}, BRP {dbrp_min}-{dbrp_max} (step {dbrp_inc})"
+        );
+    }
+}
+
+/// The value, or a note if the device doesn't support the request
+fn optional<T: std::fmt::Display>(result: gs_usb::Result<T>) -> gs_usb::Result<String> {
+    match result {
+        Ok(value) => Ok(value.to_string()),
+        Err(GsUsbError::FeatureNotSupported(_) | GsUsbError::GetStateNotSupported) => {
+            Ok("not supported".to_string())
+        }
+        Err(e) => Err(e),
+    }
+}
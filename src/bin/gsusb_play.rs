@@ -0,0 +1,114 @@
+//! Replay CAN logs through a GS-USB adapter
+//!
+//! Usage: gsusb-play [--bitrate 500k] [--speed 2] [--map 1:0] FILE
+//!
+//! Reads candump, ASC or BLF logs and sends the frames with their original
+//! spacing, so captured scenarios can be reproduced on a bench.
+
+mod common;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use common::DeviceArgs;
+use gs_usb::{Bitrate, LogFormat, Replay};
+
+#[derive(Debug, Parser)]
+#[command(name = "gsusb-play", version, about = "Replay CAN logs")]
+struct Args {
+    #[command(flatten)]
+    device: DeviceArgs,
+    /// Bitrate, e.g. 500k, or 500k/2M for logs with CAN FD frames
+    #[arg(short, long, default_value = "500k")]
+    bitrate: Bitrate,
+    /// Log format: candump, asc or blf (default: from the file)
+    #[arg(short, long)]
+    format: Option<LogFormat>,
+    /// Speed factor; 0 sends as fast as possible
+    #[arg(short, long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+    /// Send log channel LOG on adapter channel DEV, as LOG:DEV; may be
+    /// repeated, unmapped channels are skipped (default: all to channel 0)
+    #[arg(short, long = "map", value_parser = parse_mapping)]
+    mappings: Vec<(u8, u8)>,
+    /// Play the log this many times
+    #[arg(long = "loop", default_value_t = 1)]
+    repeat: u32,
+    /// Loop frames back inside the adapter instead of sending them on the bus
+    #[arg(long)]
+    loopback: bool,
+    /// Log file
+    file: PathBuf,
+}
+
+fn parse_speed(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(value) if value == 0.0 || (value.is_finite() && value >= Replay::MIN_SPEED) => Ok(value),
+        Ok(_) => Err(format!(
+            "expected 0 or a finite factor of at least {}, got \"{text}\"",
+            Replay::MIN_SPEED
+        )),
+        Err(e) => Err(format!("invalid number \"{text}\": {e}")),
+    }
+}
+
+fn parse_mapping(text: &str) -> Result<(u8, u8), String> {
+    let (log, device) = text
+        .split_once(':')
+        .ok_or_else(|| format!("expected LOG:DEV, got \"{text}\""))?;
+    let channel = |text: &str| {
+        text.parse::<u8>()
+            .map_err(|e| format!("invalid channel \"{text}\": {e}"))
+    };
+    Ok((channel(log)?, channel(device)?))
+}
+
+fn main() -> ExitCode {
+    common::init_logging();
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gsusb-play: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> gs_usb::Result<()> {
+    let records = match args.format {
+        Some(format) => format.parse(&std::fs::read(&args.file)?)?,
+        None => gs_usb::logfile::read_log(&args.file)?,
+    };
+    let mut replay = Replay::new(records).speed(args.speed);
+    for &(log, device) in &args.mappings {
+        replay = replay.map_channel(log, device);
+    }
+    if replay.records().iter().any(|record| record.frame.is_fd()) && !args.bitrate.is_fd() {
+        log::warn!("The log has CAN FD frames, but no data bitrate is set");
+    }
+
+    let mut builder = args.device.builder().bitrates(args.bitrate);
+    if args.loopback {
+        builder = builder.loopback();
+    }
+    let mut dev = builder.open_and_start()?;
+    eprintln!(
+        "Replaying {} frames ({:.3} s) from {} on {dev}",
+        replay.records().len(),
+        replay.duration().as_secs_f64(),
+        args.file.display()
+    );
+    for _ in 0..args.repeat {
+        let stats = replay.run(&mut dev)?;
+        println!(
+            "{} frames sent, {} skipped in {:.3} s, up to {:.1} ms late",
+            stats.sent,
+            stats.skipped,
+            stats.elapsed.as_secs_f64(),
+            stats.max_lag.as_secs_f64() * 1000.0
+        );
+    }
+    dev.close()
+}
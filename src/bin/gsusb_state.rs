@@ -0,0 +1,166 @@
+//! Monitor the bus state of a GS-USB adapter as newline-delimited JSON
+//!
+//! Usage: gsusb-state [--bitrate 500k] [--interval 1] [--count N]
+//!
+//! Polls GET_STATE at an interval and counts the bus error frames received
+//! in between, one JSON object per line:
+//!
+//! ```text
+//! {"time":1760600000.125,"elapsed":1.000,"channel":0,"state":"ERROR_ACTIVE","tec":0,"rec":0,"bus_errors":0,"bus_off":false,"woke":false,"frames":118}
+//! ```
+//!
+//! Adapters without GET_STATE report `"state":null` and the error counters
+//! of the last error frame. `"woke"` tells that the channel left the
+//! SLEEPING state since the last record, by GET_STATE or a received frame.
+
+mod common;
+
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use common::{start_channels, DeviceArgs};
+use gs_usb::constants::{CAN_ERR_BUSERROR, CAN_ERR_PROT};
+use gs_usb::{
+    Bitrate, CanErrorFrame, Config, GsUsb, GsUsbError, StateWatcher, GS_CAN_FEATURE_BERR_REPORTING,
+};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "gsusb-state",
+    version,
+    about = "Monitor bus state as JSON lines"
+)]
+struct Args {
+    #[command(flatten)]
+    device: DeviceArgs,
+    /// Bitrate, e.g. 500k or 500k/2M
+    #[arg(short, long, default_value = "500k")]
+    bitrate: Bitrate,
+    /// Channel of the adapter
+    #[arg(short, long, default_value_t = 0)]
+    channel: u8,
+    /// Seconds between records
+    #[arg(short, long, default_value_t = 1.0)]
+    interval: f64,
+    /// Stop after this many records
+    #[arg(short = 'n', long)]
+    count: Option<u64>,
+    /// Only listen, never acknowledge frames
+    #[arg(long)]
+    listen_only: bool,
+    /// Don't ask the adapter to report every bus error
+    #[arg(long)]
+    no_berr: bool,
+}
+
+/// What happened on the channel since the last record
+#[derive(Debug, Default)]
+struct Interval {
+    frames: u64,
+    bus_errors: u64,
+    bus_off: bool,
+    woke: bool,
+}
+
+fn main() -> ExitCode {
+    common::init_logging();
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gsusb-state: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> gs_usb::Result<()> {
+    let mut dev = args.device.builder().open()?;
+    let mut config = Config::new(args.bitrate.nominal);
+    config.set_bitrates(args.bitrate);
+    config.mode.listen_only = args.listen_only;
+    config.mode.berr_reporting =
+        !args.no_berr && dev.device_capability()?.feature & GS_CAN_FEATURE_BERR_REPORTING != 0;
+    start_channels(&mut dev, &config, args.channel)?;
+    let has_state = dev.supports_get_state()?;
+
+    let start = Instant::now();
+    let period = Duration::from_secs_f64(args.interval.max(0.01));
+    let mut counters = (0, 0);
+    let mut watcher = StateWatcher::new(u16::from(args.channel));
+    let mut records = 0;
+    while args.count.is_none_or(|count| records < count) {
+        let due = start + period * (records as u32 + 1);
+        let mut interval = collect(&mut dev, &mut watcher, due, &mut counters)?;
+        let state = if has_state {
+            let state = dev.get_state(u16::from(args.channel))?;
+            counters = (state.txerr, state.rxerr);
+            interval.woke |= watcher
+                .observe_state(&state)
+                .is_some_and(|event| event.is_wake());
+            Some(state.state_name())
+        } else {
+            None
+        };
+        print_record(start.elapsed(), args.channel, state, counters, &interval);
+        records += 1;
+    }
+    dev.close()
+}
+
+/// Read frames until `due`, keeping the error counters of error frames
+fn collect(
+    dev: &mut GsUsb,
+    watcher: &mut StateWatcher,
+    due: Instant,
+    counters: &mut (u32, u32),
+) -> gs_usb::Result<Interval> {
+    let mut interval = Interval::default();
+    loop {
+        let now = Instant::now();
+        if now >= due {
+            return Ok(interval);
+        }
+        let frame = match dev.read(due - now) {
+            Ok(frame) if u16::from(frame.channel) == watcher.channel() => frame,
+            Ok(_) | Err(GsUsbError::ReadTimeout) => continue,
+            Err(e) => return Err(e),
+        };
+        interval.woke |= watcher.observe_frame(&frame).is_some();
+        let Some(error) = CanErrorFrame::from_frame(&frame) else {
+            interval.frames += 1;
+            continue;
+        };
+        if error.has_class(CAN_ERR_PROT | CAN_ERR_BUSERROR) {
+            interval.bus_errors += 1;
+        }
+        interval.bus_off |= error.is_bus_off();
+        if let (Some(tec), Some(rec)) = (error.tx_error_count(), error.rx_error_count()) {
+            *counters = (u32::from(tec), u32::from(rec));
+        }
+    }
+}
+
+fn print_record(
+    elapsed: Duration,
+    channel: u8,
+    state: Option<&str>,
+    (tec, rec): (u32, u32),
+    interval: &Interval,
+) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let state = state.map_or("null".to_string(), |state| format!("\"{state}\""));
+    println!(
+        "{{\"time\":{time:.3},\"elapsed\":{:.3},\"channel\":{channel},\"state\":{state},\
+         \"tec\":{tec},\"rec\":{rec},\"bus_errors\":{},\"bus_off\":{},\"woke\":{},\"frames\":{}}}",
+        elapsed.as_secs_f64(),
+        interval.bus_errors,
+        interval.bus_off,
+        interval.woke,
+        interval.frames
+    );
+}
@@ -0,0 +1,107 @@
+//! Hardware housekeeping for GS-USB adapters
+//!
+//! Usage: gsusb-util [--serial S | --user-id N] <COMMAND>
+//!
+//! Switches the termination resistor, blinks the identify LED, and reads or
+//! stores the user ID that `--user-id` selects adapters by.
+
+mod common;
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use common::{parse_u32, DeviceArgs};
+
+#[derive(Debug, Parser)]
+#[command(name = "gsusb-util", version, about = "Adapter housekeeping")]
+struct Args {
+    #[command(flatten)]
+    device: DeviceArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Show or switch the termination resistor
+    Termination {
+        /// on or off; shows the current setting if left out
+        #[arg(value_parser = parse_on_off)]
+        state: Option<bool>,
+        /// Channel of the adapter
+        #[arg(short, long, default_value_t = 0)]
+        channel: u16,
+    },
+    /// Blink the LED of a channel to find the adapter
+    Identify {
+        /// Seconds to blink
+        #[arg(short, long, default_value_t = 5.0)]
+        duration: f64,
+        /// Channel of the adapter
+        #[arg(short, long, default_value_t = 0)]
+        channel: u16,
+    },
+    /// Show or store the user ID
+    UserId {
+        /// New user ID, decimal or 0x hex; shows the current one if left out
+        #[arg(value_parser = parse_u32)]
+        user_id: Option<u32>,
+    },
+}
+
+fn parse_on_off(text: &str) -> Result<bool, String> {
+    match text {
+        "on" | "1" => Ok(true),
+        "off" | "0" => Ok(false),
+        _ => Err(format!("expected on or off, got \"{text}\"")),
+    }
+}
+
+fn main() -> ExitCode {
+    common::init_logging();
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gsusb-util: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> gs_usb::Result<()> {
+    let mut dev = args.device.builder().open()?;
+    match args.command {
+        Command::Termination {
+            state: Some(on),
+            channel,
+        } => {
+            dev.set_termination(channel, on)?;
+            println!("Termination {}", if on { "on" } else { "off" });
+        }
+        Command::Termination {
+            state: None,
+            channel,
+        } => {
+            let on = dev.termination(channel)?;
+            println!("Termination {}", if on { "on" } else { "off" });
+        }
+        Command::Identify { duration, channel } => {
+            eprintln!("Blinking {dev} channel {channel}");
+            dev.identify(channel, true)?;
+            std::thread::sleep(Duration::from_secs_f64(duration.max(0.0)));
+            dev.identify(channel, false)?;
+        }
+        Command::UserId {
+            user_id: Some(user_id),
+        } => {
+            dev.set_user_id(user_id)?;
+            println!("User ID set to 0x{user_id:08X}");
+        }
+        Command::UserId { user_id: None } => {
+            println!("0x{:08X}", dev.user_id()?);
+        }
+    }
+    dev.close()
+}
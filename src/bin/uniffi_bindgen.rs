@@ -0,0 +1,8 @@
+//! uniffi binding generator, see `src/gs_usb.udl`
+//!
+//! Usage: uniffi-bindgen generate --library target/release/libgs_usb.so
+//!        --language kotlin --out-dir out
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
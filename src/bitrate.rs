@@ -0,0 +1,342 @@
+//! Bitrates in human notation
+//!
+//! `Bitrate` parses and prints the notation used on command lines and in
+//! configuration files: a rate with an optional `k` or `M` suffix, optionally
+//! followed by `@` and a sample point in percent, and for CAN FD a second
+//! rate for the data phase after a `/`:
+//!
+//! | Text | Meaning |
+//! |------|---------|
+//! | `500k` | 500 kbit/s, default sample point |
+//! | `1M@75%` | 1 Mbit/s, sample point 75% |
+//! | `500k/2M@80%` | 500 kbit/s nominal, 2 Mbit/s data phase with 80% sample point |
+//! | `500k@87.5%/2M@80%` | both sample points given |
+//!
+//! Sample points may also be written as fractions (`@0.875`).
+//!
+//! Where the API takes a sample point it takes a `SamplePoint`, so 875,
+//! 87.5 and 0.875 can't be mixed up: it is built with
+//! `SamplePoint::from_permille(875)`, `from_percent(87.5)`,
+//! `from_fraction(0.875)` or parsed from `"87.5%"`.
+//!
+//! ```
+//! use gs_usb::Bitrate;
+//!
+//! let bitrate: Bitrate = "500k/2M@80%".parse()?;
+//! assert_eq!(bitrate.nominal, 500_000);
+//! assert_eq!(bitrate.data, Some(2_000_000));
+//! assert_eq!(bitrate.data_sample_point, Some(800));
+//! assert_eq!(bitrate.to_string(), "500k/2M@80%");
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{GsUsbError, Result};
+
+/// Nominal and optional data phase bitrate, with optional sample points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bitrate {
+    /// Nominal (arbitration) bitrate in bits per second
+    pub nominal: u32,
+    /// Nominal sample point in permille
+    pub sample_point: Option<u32>,
+    /// CAN FD data phase bitrate in bits per second
+    pub data: Option<u32>,
+    /// Data phase sample point in permille
+    pub data_sample_point: Option<u32>,
+}
+
+impl Bitrate {
+    /// Classic CAN bitrate with the default sample point
+    pub fn new(nominal: u32) -> Self {
+        Self {
+            nominal,
+            sample_point: None,
+            data: None,
+            data_sample_point: None,
+        }
+    }
+
+    /// Add a CAN FD data phase bitrate
+    pub fn with_data(mut self, data: u32) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Check if a data phase bitrate is set
+    pub fn is_fd(&self) -> bool {
+        self.data.is_some()
+    }
+}
+
+/// Sample point of a bit, from 0.1% to 99.9% in permille steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SamplePoint(u16);
+
+impl SamplePoint {
+    /// Sample point in permille (875 = 87.5%), `None` outside 1..=999
+    pub const fn from_permille(permille: u32) -> Option<Self> {
+        if permille >= 1 && permille < 1000 {
+            Some(Self(permille as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Sample point in percent (87.5), rounded to permille
+    pub fn from_percent(percent: f64) -> Option<Self> {
+        Self::from_fraction(percent / 100.0)
+    }
+
+    /// Sample point as a fraction of the bit (0.875), rounded to permille
+    pub fn from_fraction(fraction: f64) -> Option<Self> {
+        let permille = (fraction * 1000.0).round();
+        if permille.is_finite() && (1.0..1000.0).contains(&permille) {
+            Self::from_permille(permille as u32)
+        } else {
+            None
+        }
+    }
+
+    /// The sample point in permille
+    pub const fn permille(&self) -> u32 {
+        self.0 as u32
+    }
+
+    /// The sample point in percent
+    pub fn percent(&self) -> f64 {
+        f64::from(self.0) / 10.0
+    }
+}
+
+impl From<SamplePoint> for u32 {
+    /// The sample point in permille
+    fn from(sample_point: SamplePoint) -> u32 {
+        sample_point.permille()
+    }
+}
+
+impl TryFrom<u32> for SamplePoint {
+    type Error = GsUsbError;
+
+    /// A sample point in permille
+    fn try_from(permille: u32) -> Result<Self> {
+        Self::from_permille(permille).ok_or_else(|| {
+            GsUsbError::InvalidConfig(format!(
+                "sample point {permille} out of range (1 to 999 permille)"
+            ))
+        })
+    }
+}
+
+impl FromStr for SamplePoint {
+    type Err = GsUsbError;
+
+    /// Parse `87.5%` or `0.875`
+    fn from_str(text: &str) -> Result<Self> {
+        parse_sample_point(text.trim())
+            .map(|permille| Self(permille as u16))
+            .map_err(|reason| GsUsbError::InvalidBitrate(format!("\"{text}\": {reason}")))
+    }
+}
+
+impl fmt::Display for SamplePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let permille = self.permille();
+        if permille.is_multiple_of(10) {
+            write!(f, "{}%", permille / 10)
+        } else {
+            write!(f, "{}.{}%", permille / 10, permille % 10)
+        }
+    }
+}
+
+impl From<u32> for Bitrate {
+    fn from(nominal: u32) -> Self {
+        Self::new(nominal)
+    }
+}
+
+impl FromStr for Bitrate {
+    type Err = GsUsbError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = |reason: &str| GsUsbError::InvalidBitrate(format!("\"{text}\": {reason}"));
+
+        let (nominal, data) = match text.trim().split_once('/') {
+            Some((nominal, data)) => (nominal, Some(data)),
+            None => (text.trim(), None),
+        };
+        let (nominal, sample_point) = parse_phase(nominal).map_err(invalid)?;
+        let (data, data_sample_point) = match data {
+            Some(data) => {
+                let (rate, point) = parse_phase(data).map_err(invalid)?;
+                (Some(rate), point)
+            }
+            None => (None, None),
+        };
+        Ok(Self {
+            nominal,
+            sample_point,
+            data,
+            data_sample_point,
+        })
+    }
+}
+
+/// Parse `<rate>[@<sample point>]`
+fn parse_phase(text: &str) -> std::result::Result<(u32, Option<u32>), &'static str> {
+    let (rate, point) = match text.split_once('@') {
+        Some((rate, point)) => (rate, Some(point)),
+        None => (text, None),
+    };
+    let rate = parse_rate(rate.trim())?;
+    let point = point
+        .map(|point| parse_sample_point(point.trim()))
+        .transpose()?;
+    Ok((rate, point))
+}
+
+/// Parse a rate like `500000`, `500k`, `83.333k` or `1M`
+fn parse_rate(text: &str) -> std::result::Result<u32, &'static str> {
+    let (number, scale) = match text.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&text[..i], 1e3),
+        Some((i, 'M' | 'm')) => (&text[..i], 1e6),
+        _ => (text, 1.0),
+    };
+    let value: f64 = number.parse().map_err(|_| "rate is not a number")?;
+    let rate = (value * scale).round();
+    if !(1.0..=f64::from(u32::MAX)).contains(&rate) {
+        return Err("rate out of range");
+    }
+    Ok(rate as u32)
+}
+
+/// Parse a sample point like `87.5%` or `0.875` into permille
+fn parse_sample_point(text: &str) -> std::result::Result<u32, &'static str> {
+    let permille = match text.strip_suffix('%') {
+        Some(percent) => {
+            percent
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| "sample point is not a number")?
+                * 10.0
+        }
+        None => {
+            let fraction: f64 = text.parse().map_err(|_| "sample point is not a number")?;
+            if fraction >= 1.0 {
+                return Err("sample point needs a % sign or must be a fraction below 1");
+            }
+            fraction * 1000.0
+        }
+    };
+    let permille = permille.round();
+    if !(1.0..1000.0).contains(&permille) {
+        return Err("sample point must be between 0% and 100%");
+    }
+    Ok(permille as u32)
+}
+
+fn fmt_phase(f: &mut fmt::Formatter<'_>, rate: u32, sample_point: Option<u32>) -> fmt::Result {
+    if rate.is_multiple_of(1_000_000) {
+        write!(f, "{}M", rate / 1_000_000)?;
+    } else if rate.is_multiple_of(1_000) {
+        write!(f, "{}k", rate / 1_000)?;
+    } else {
+        write!(f, "{rate}")?;
+    }
+    match sample_point {
+        Some(point) if point.is_multiple_of(10) => write!(f, "@{}%", point / 10),
+        Some(point) => write!(f, "@{}.{}%", point / 10, point % 10),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Bitrate {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Bitrate {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let text = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Bitrate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_phase(f, self.nominal, self.sample_point)?;
+        if let Some(data) = self.data {
+            write!(f, "/")?;
+            fmt_phase(f, data, self.data_sample_point)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |text: &str| text.parse::<Bitrate>().unwrap();
+        assert_eq!(parse("500k"), Bitrate::new(500_000));
+        assert_eq!(parse("1M"), Bitrate::new(1_000_000));
+        assert_eq!(parse("125000"), Bitrate::new(125_000));
+        assert_eq!(parse("83.333k").nominal, 83_333);
+        assert_eq!(parse("1M@0.75").sample_point, Some(750));
+
+        let fd = parse("500k@87.5%/2M@80%");
+        assert_eq!(fd.nominal, 500_000);
+        assert_eq!(fd.sample_point, Some(875));
+        assert_eq!(fd.data, Some(2_000_000));
+        assert_eq!(fd.data_sample_point, Some(800));
+        assert_eq!(parse("500k/2M@80%").sample_point, None);
+
+        for bad in ["", "fast", "0", "500k@80", "500k@120%", "500k/", "5000M0"] {
+            assert!(bad.parse::<Bitrate>().is_err(), "{bad:?} accepted");
+        }
+    }
+
+    #[test]
+    fn test_sample_point() {
+        let point = SamplePoint::from_permille(875).unwrap();
+        assert_eq!(SamplePoint::from_percent(87.5), Some(point));
+        assert_eq!(SamplePoint::from_fraction(0.875), Some(point));
+        assert_eq!("87.5%".parse::<SamplePoint>().unwrap(), point);
+        assert_eq!("0.875".parse::<SamplePoint>().unwrap(), point);
+        assert_eq!(SamplePoint::try_from(875).unwrap(), point);
+        assert_eq!((point.permille(), point.percent()), (875, 87.5));
+        assert_eq!(point.to_string(), "87.5%");
+        assert_eq!(SamplePoint::from_permille(800).unwrap().to_string(), "80%");
+
+        // The usual mix-ups are rejected rather than taken for something else
+        assert_eq!(SamplePoint::from_permille(1000), None);
+        assert_eq!(SamplePoint::from_percent(875.0), None);
+        assert_eq!(SamplePoint::from_fraction(87.5), None);
+        assert_eq!(SamplePoint::from_fraction(f64::NAN), None);
+        assert!(SamplePoint::try_from(0).is_err());
+        for bad in ["875", "87.5", "100%", "0%", "-1%"] {
+            assert!(bad.parse::<SamplePoint>().is_err(), "{bad:?} accepted");
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for text in ["500k", "1M@75%", "500k@87.5%/2M@80%", "83333/5M"] {
+            assert_eq!(text.parse::<Bitrate>().unwrap().to_string(), text);
+        }
+    }
+}
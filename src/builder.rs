@@ -0,0 +1,376 @@
+//! Fluent device setup
+//!
+//! `GsUsb::builder()` collects which adapter to use and how to configure it,
+//! then finds the device, checks the requested features against its
+//! capability, sets the bit timing and starts it in one call:
+//!
+//! ```no_run
+//! use gs_usb::GsUsb;
+//!
+//! let mut dev = GsUsb::builder()
+//!     .serial("2087358E5853")
+//!     .bitrate(500_000)
+//!     .fd(2_000_000)
+//!     .listen_only()
+//!     .open_and_start()?;
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use crate::bitrate::{Bitrate, SamplePoint};
+use crate::config::{Config, IdFilter};
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::mode::Loopback;
+
+/// Builder for an opened and started `GsUsb`, see `GsUsb::builder()`
+#[derive(Debug, Clone)]
+pub struct GsUsbBuilder {
+    serial: Option<String>,
+    user_id: Option<u32>,
+    location: Option<(u8, u8)>,
+    config: Config,
+}
+
+impl GsUsbBuilder {
+    /// Builder for the first device found, without a bitrate
+    pub fn new() -> Self {
+        Self {
+            serial: None,
+            user_id: None,
+            location: None,
+            config: Config::new(0),
+        }
+    }
+
+    /// Start from a configuration profile
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Only use the device with this serial number
+    pub fn serial(mut self, serial: impl Into<String>) -> Self {
+        self.serial = Some(serial.into());
+        self
+    }
+
+    /// Only use the device with this user ID, see `GsUsb::set_user_id()`
+    pub fn user_id(mut self, user_id: u32) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    /// Only use the device at this USB bus number and address
+    pub fn location(mut self, bus: u8, address: u8) -> Self {
+        self.location = Some((bus, address));
+        self
+    }
+
+    /// Nominal (arbitration) bitrate in bits per second
+    pub fn bitrate(mut self, bitrate: u32) -> Self {
+        self.config.bitrate = bitrate;
+        self
+    }
+
+    /// Bitrates and sample points in one, e.g. parsed from `"500k/2M@80%"`
+    ///
+    /// Sample points and a data bitrate left open in `bitrate` keep what was
+    /// set before.
+    pub fn bitrates(mut self, bitrate: Bitrate) -> Self {
+        self.config.bitrate = bitrate.nominal;
+        self.config.sample_point = bitrate.sample_point.or(self.config.sample_point);
+        self.config.data_bitrate = bitrate.data.or(self.config.data_bitrate);
+        self.config.data_sample_point = bitrate.data_sample_point.or(self.config.data_sample_point);
+        self
+    }
+
+    /// Nominal sample point
+    pub fn sample_point(mut self, sample_point: SamplePoint) -> Self {
+        self.config.sample_point = Some(sample_point.permille());
+        self
+    }
+
+    /// Enable CAN FD with the given data phase bitrate
+    pub fn fd(mut self, data_bitrate: u32) -> Self {
+        self.config.data_bitrate = Some(data_bitrate);
+        self
+    }
+
+    /// Data phase sample point
+    pub fn data_sample_point(mut self, sample_point: SamplePoint) -> Self {
+        self.config.data_sample_point = Some(sample_point.permille());
+        self
+    }
+
+    /// Only listen, never acknowledge or transmit
+    pub fn listen_only(mut self) -> Self {
+        self.config.mode.listen_only = true;
+        self
+    }
+
+    /// Receive own frames without an ACK, see `self_test()`
+    pub fn loopback(mut self) -> Self {
+        self.config.mode.loopback = true;
+        self
+    }
+
+    /// Loop frames back for a self test, inside the controller or also on
+    /// the bus
+    ///
+    /// Internal loopback needs listen-only mode; starting fails with
+    /// `FeatureNotSupported` if the device lacks it.
+    pub fn self_test(mut self, loopback: Loopback) -> Self {
+        self.config.mode.loopback = true;
+        self.config.mode.listen_only = loopback == Loopback::Internal;
+        self
+    }
+
+    /// Don't retransmit frames that failed
+    pub fn one_shot(mut self) -> Self {
+        self.config.mode.one_shot = true;
+        self
+    }
+
+    /// Timestamp frames in hardware
+    pub fn hw_timestamp(mut self) -> Self {
+        self.config.mode.hw_timestamp = true;
+        self
+    }
+
+    /// Report every bus error as an error frame
+    pub fn berr_reporting(mut self) -> Self {
+        self.config.mode.berr_reporting = true;
+        self
+    }
+
+    /// Sample each bit three times, which helps on noisy low-bitrate buses
+    ///
+    /// Starting fails with `FeatureNotSupported` if the device lacks
+    /// `GS_CAN_FEATURE_TRIPLE_SAMPLE`.
+    pub fn triple_sample(mut self) -> Self {
+        self.config.mode.triple_sample = true;
+        self
+    }
+
+    /// Add an acceptance filter for received frames
+    pub fn filter(mut self, filter: IdFilter) -> Self {
+        self.config.filters.push(filter);
+        self
+    }
+
+    /// Return error frames from `read()` as `GsUsbError::BusError`
+    pub fn error_frames_as_errors(mut self) -> Self {
+        self.config.error_frames_as_errors = true;
+        self
+    }
+
+    /// Find the device without configuring it
+    ///
+    /// Fails with `GsUsbError::DeviceNotFound` if no connected device matches
+    /// the serial number, user ID and location.
+    pub fn open(&self) -> Result<GsUsb> {
+        self.select(GsUsb::scan()?)
+    }
+
+    /// Find the device, configure and start it
+    pub fn open_and_start(self) -> Result<GsUsb> {
+        let dev = self.open()?;
+        self.start(dev)
+    }
+
+    /// Configure and start an already opened device
+    ///
+    /// Every requested feature is checked against the device capability
+    /// before anything is sent (see `GsUsb::configure()`), so a missing
+    /// feature is an error rather than being left out of the mode silently.
+    pub fn start(&self, mut dev: GsUsb) -> Result<GsUsb> {
+        if self.config.bitrate == 0 {
+            return Err(GsUsbError::InvalidConfig("no bitrate set".into()));
+        }
+        dev.apply(&self.config)?;
+        Ok(dev)
+    }
+
+    /// Pick the first device matching the serial number, user ID and location
+    fn select(&self, devices: Vec<GsUsb>) -> Result<GsUsb> {
+        for mut dev in devices {
+            if self
+                .location
+                .is_some_and(|location| location != (dev.bus(), dev.address()))
+            {
+                continue;
+            }
+            if let Some(serial) = &self.serial {
+                match dev.serial_number() {
+                    Ok(found) if found == *serial => {}
+                    Ok(_) => continue,
+                    Err(e) => {
+                        log::debug!("Skipping {dev}: cannot read serial number ({e})");
+                        continue;
+                    }
+                }
+            }
+            if let Some(user_id) = self.user_id {
+                match dev.user_id() {
+                    Ok(found) if found == user_id => {}
+                    Ok(_) => continue,
+                    Err(e) => {
+                        log::debug!("Skipping {dev}: cannot read user ID ({e})");
+                        continue;
+                    }
+                }
+            }
+            return Ok(dev);
+        }
+        Err(GsUsbError::DeviceNotFound)
+    }
+}
+
+impl Default for GsUsbBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{GS_CAN_FEATURE_FD, GS_CAN_FEATURE_ONE_SHOT, GS_CAN_FEATURE_USER_ID};
+    use crate::mock::MockGsUsb;
+    use crate::virtual_bus::VirtualBus;
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_start() {
+        use crate::constants::{GS_CAN_MODE_FD, GS_CAN_MODE_LISTEN_ONLY};
+
+        let mock = MockGsUsb::new();
+        let dev = GsUsb::builder()
+            .bitrate(500_000)
+            .fd(2_000_000)
+            .listen_only()
+            .start(mock.open())
+            .unwrap();
+        assert_eq!(mock.mode_flags(), GS_CAN_MODE_LISTEN_ONLY | GS_CAN_MODE_FD);
+        assert_eq!(dev.last_data_timing().map(|t| t.total_tq()), Some(20));
+
+        let dev = GsUsb::builder()
+            .bitrates("1M@80%/5M".parse().unwrap())
+            .start(mock.open())
+            .unwrap();
+        assert_eq!(dev.last_timing().map(|t| t.sample_point()), Some(800));
+        drop(dev);
+
+        assert!(matches!(
+            GsUsb::builder().start(mock.open()),
+            Err(GsUsbError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_self_test() {
+        use std::time::Duration;
+
+        use crate::frame::GsUsbFrame;
+
+        let bus = VirtualBus::new();
+        let mut other = bus.open();
+        other.set_bitrate(500_000).unwrap();
+        other.start(0).unwrap();
+        let timeout = Duration::from_millis(100);
+
+        for (loopback, on_bus) in [(Loopback::Internal, false), (Loopback::External, true)] {
+            let mut dev = GsUsb::builder()
+                .bitrate(500_000)
+                .self_test(loopback)
+                .start(bus.open())
+                .unwrap();
+            assert_eq!(dev.active_mode().unwrap().loopback(), Some(loopback));
+            dev.send(&GsUsbFrame::with_data(0x123, &[1])).unwrap();
+            assert!(dev.read(timeout).unwrap().is_rx_frame());
+            assert!(dev.read(timeout).unwrap().is_echo_frame());
+            assert_eq!(other.read(timeout).is_ok(), on_bus, "{loopback}");
+        }
+    }
+
+    #[test]
+    fn test_triple_sample() {
+        use crate::constants::{GS_CAN_FEATURE_TRIPLE_SAMPLE, GS_CAN_MODE_TRIPLE_SAMPLE};
+
+        let mut capability = VirtualBus::default_capability();
+        capability.feature |= GS_CAN_FEATURE_TRIPLE_SAMPLE;
+        let mock = MockGsUsb::with_capability(capability);
+        let mut dev = GsUsb::builder()
+            .bitrate(50_000)
+            .triple_sample()
+            .start(mock.open())
+            .unwrap();
+        assert_eq!(mock.mode_flags(), GS_CAN_MODE_TRIPLE_SAMPLE);
+        assert!(dev.active_mode().unwrap().is_complete());
+        assert!(dev.current_config().unwrap().mode.triple_sample);
+    }
+
+    #[test]
+    fn test_missing_features_rejected() {
+        let mut capability = VirtualBus::default_capability();
+        capability.feature &= !(GS_CAN_FEATURE_FD | GS_CAN_FEATURE_ONE_SHOT);
+        let mock = MockGsUsb::with_capability(capability);
+
+        let builder = GsUsb::builder().bitrate(500_000);
+        assert!(matches!(
+            builder.clone().fd(2_000_000).start(mock.open()),
+            Err(GsUsbError::FdNotSupported)
+        ));
+        assert!(matches!(
+            builder.clone().one_shot().start(mock.open()),
+            Err(GsUsbError::FeatureNotSupported("one-shot"))
+        ));
+        assert!(matches!(
+            builder.clone().berr_reporting().start(mock.open()),
+            Err(GsUsbError::FeatureNotSupported("bus error reporting"))
+        ));
+        assert!(matches!(
+            builder.clone().triple_sample().start(mock.open()),
+            Err(GsUsbError::FeatureNotSupported("triple sampling"))
+        ));
+        assert!(!mock.is_started());
+        let _dev = builder.start(mock.open()).unwrap();
+        assert!(mock.is_started());
+    }
+
+    #[test]
+    fn test_select_by_serial() {
+        let bus = VirtualBus::new();
+        let devices = vec![bus.open(), bus.open()];
+        let mut dev = GsUsb::builder()
+            .serial("VIRTUAL0001")
+            .select(devices)
+            .unwrap();
+        assert_eq!(dev.serial_number().unwrap(), "VIRTUAL0001");
+
+        assert!(matches!(
+            GsUsb::builder().serial("NONE").select(vec![bus.open()]),
+            Err(GsUsbError::DeviceNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_select_by_user_id() {
+        let bus = VirtualBus::new();
+        let (mut a, mut b) = (bus.open(), bus.open());
+        a.set_user_id(0xC0FFEE).unwrap();
+        b.set_user_id(0xBEEF).unwrap();
+        assert_eq!(b.user_id().unwrap(), 0xBEEF);
+
+        let mut dev = GsUsb::builder().user_id(0xBEEF).select(vec![a, b]).unwrap();
+        assert_eq!(dev.serial_number().unwrap(), "VIRTUAL0001");
+
+        let mut capability = VirtualBus::default_capability();
+        capability.feature &= !GS_CAN_FEATURE_USER_ID;
+        let mut dev = MockGsUsb::with_capability(capability).open();
+        assert!(matches!(
+            dev.user_id(),
+            Err(GsUsbError::FeatureNotSupported(_))
+        ));
+    }
+}
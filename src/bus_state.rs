@@ -0,0 +1,266 @@
+//! Bus state transitions and sleep
+//!
+//! `StateWatcher` is fed the results of GET_STATE polls and the received
+//! frames of a channel, and reports changes of the bus state. Its main use
+//! is coordinating with bus sleep: firmware whose controller supports it
+//! reports `GS_CAN_STATE_SLEEPING` while the bus is asleep, and the watcher
+//! tells when the channel fell asleep and when it woke up, either because
+//! GET_STATE says so or because a frame arrived.
+//!
+//! The gs_usb protocol has no request to put a channel to sleep; firmware
+//! enters and leaves the SLEEPING state on its own.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, StateWatcher, GS_CAN_MODE_LISTEN_ONLY};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_LISTEN_ONLY)?;
+//!
+//! let mut watcher = StateWatcher::new(0);
+//! loop {
+//!     let event = watcher.poll(&mut dev)?;
+//!     if event.is_some_and(|event| event.is_sleep()) {
+//!         println!("bus asleep, flushing logs");
+//!         if let Some(wake) = watcher.wait_for_wake(&mut dev, Duration::from_secs(3600))? {
+//!             println!("bus awake: {wake}");
+//!         }
+//!     }
+//!     std::thread::sleep(Duration::from_secs(1));
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::constants::{can_state_name, GS_CAN_STATE_SLEEPING};
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::structures::DeviceState;
+
+/// How often `wait_for_wake()` polls GET_STATE between reads
+const WAKE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A change of a channel's bus state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateEvent {
+    /// GET_STATE reported a different state (`GS_CAN_STATE_*`)
+    Changed {
+        /// Previously reported state
+        from: u32,
+        /// Newly reported state
+        to: u32,
+    },
+    /// A frame was received while the channel was reported sleeping
+    Activity,
+}
+
+impl StateEvent {
+    /// Whether the channel fell asleep
+    pub fn is_sleep(&self) -> bool {
+        matches!(self, Self::Changed { to, .. } if *to == GS_CAN_STATE_SLEEPING)
+    }
+
+    /// Whether the channel woke up from sleep
+    pub fn is_wake(&self) -> bool {
+        match *self {
+            Self::Changed { from, .. } => from == GS_CAN_STATE_SLEEPING,
+            Self::Activity => true,
+        }
+    }
+}
+
+impl std::fmt::Display for StateEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Changed { from, to } => {
+                write!(f, "{} -> {}", can_state_name(from), can_state_name(to))
+            }
+            Self::Activity => write!(f, "activity while SLEEPING"),
+        }
+    }
+}
+
+/// Follows the bus state of one channel, see the module documentation
+#[derive(Debug, Clone)]
+pub struct StateWatcher {
+    channel: u16,
+    state: Option<u32>,
+    sleeping: bool,
+}
+
+impl StateWatcher {
+    /// Watcher for `channel`, without a known state
+    pub fn new(channel: u16) -> Self {
+        Self {
+            channel,
+            state: None,
+            sleeping: false,
+        }
+    }
+
+    /// Channel the watcher follows
+    pub fn channel(&self) -> u16 {
+        self.channel
+    }
+
+    /// Last reported state (`GS_CAN_STATE_*`), `None` before the first
+    pub fn state(&self) -> Option<u32> {
+        self.state
+    }
+
+    /// Whether the channel is asleep as far as known
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    /// Take a GET_STATE result, reporting a change from the last one
+    ///
+    /// The first state is not a change; `is_sleeping()` tells whether the
+    /// channel starts out asleep.
+    pub fn observe_state(&mut self, state: &DeviceState) -> Option<StateEvent> {
+        self.sleeping = state.is_sleeping();
+        match self.state.replace(state.state) {
+            Some(from) if from != state.state => Some(StateEvent::Changed {
+                from,
+                to: state.state,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Take a received frame, reporting activity on a sleeping channel
+    ///
+    /// The channel then counts as awake, and the next GET_STATE result is
+    /// taken like the first.
+    pub fn observe_frame(&mut self, frame: &GsUsbFrame) -> Option<StateEvent> {
+        if !self.sleeping || u16::from(frame.channel) != self.channel || !frame.is_rx_frame() {
+            return None;
+        }
+        self.sleeping = false;
+        self.state = None;
+        Some(StateEvent::Activity)
+    }
+
+    /// Poll GET_STATE of the channel and report a change
+    pub fn poll(&mut self, dev: &mut GsUsb) -> Result<Option<StateEvent>> {
+        let state = dev.get_state(self.channel)?;
+        Ok(self.observe_state(&state))
+    }
+
+    /// Wait until a sleeping channel wakes up
+    ///
+    /// Reads frames, polling GET_STATE in between, until a frame is received
+    /// on the channel or the state leaves SLEEPING. Frames read meanwhile are
+    /// dropped. Returns `None` at once if the channel isn't known to be
+    /// asleep, and fails with `GsUsbError::ReadTimeout` after `timeout`.
+    pub fn wait_for_wake(
+        &mut self,
+        dev: &mut GsUsb,
+        timeout: Duration,
+    ) -> Result<Option<StateEvent>> {
+        if !self.sleeping {
+            return Ok(None);
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(GsUsbError::ReadTimeout);
+            }
+            match dev.read(remaining.min(WAKE_POLL_INTERVAL)) {
+                Ok(frame) => {
+                    if let Some(event) = self.observe_frame(&frame) {
+                        return Ok(Some(event));
+                    }
+                }
+                Err(GsUsbError::ReadTimeout) => {
+                    if let Some(event) = self.poll(dev)? {
+                        return Ok(Some(event));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{GS_CAN_MODE_NORMAL, GS_CAN_STATE_ERROR_ACTIVE};
+    use crate::mock::MockGsUsb;
+
+    fn state(state: u32) -> DeviceState {
+        DeviceState {
+            state,
+            rxerr: 0,
+            txerr: 0,
+        }
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut watcher = StateWatcher::new(0);
+        assert_eq!(
+            watcher.observe_state(&state(GS_CAN_STATE_ERROR_ACTIVE)),
+            None
+        );
+        let sleep = watcher
+            .observe_state(&state(GS_CAN_STATE_SLEEPING))
+            .unwrap();
+        assert!(sleep.is_sleep() && !sleep.is_wake());
+        assert_eq!(sleep.to_string(), "ERROR_ACTIVE -> SLEEPING");
+        assert!(watcher.is_sleeping());
+
+        // Echoes and other channels aren't bus activity
+        let mut frame = GsUsbFrame::with_data(0x123, &[]);
+        assert_eq!(watcher.observe_frame(&frame), None);
+        frame.echo_id = crate::constants::GS_USB_RX_ECHO_ID;
+        frame.channel = 1;
+        assert_eq!(watcher.observe_frame(&frame), None);
+        frame.channel = 0;
+        assert_eq!(watcher.observe_frame(&frame), Some(StateEvent::Activity));
+        assert!(!watcher.is_sleeping());
+        // The state polled after the wake isn't reported again
+        assert_eq!(
+            watcher.observe_state(&state(GS_CAN_STATE_ERROR_ACTIVE)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_wait_for_wake() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let mut watcher = StateWatcher::new(0);
+        let timeout = Duration::from_millis(300);
+        assert_eq!(watcher.wait_for_wake(&mut dev, timeout).unwrap(), None);
+
+        mock.set_device_state(state(GS_CAN_STATE_SLEEPING));
+        assert_eq!(watcher.poll(&mut dev).unwrap(), None);
+        assert!(matches!(
+            watcher.wait_for_wake(&mut dev, timeout),
+            Err(GsUsbError::ReadTimeout)
+        ));
+
+        // Woken by a frame
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[1]));
+        assert_eq!(
+            watcher.wait_for_wake(&mut dev, timeout).unwrap(),
+            Some(StateEvent::Activity)
+        );
+
+        // Woken according to GET_STATE
+        watcher.poll(&mut dev).unwrap();
+        mock.set_device_state(state(GS_CAN_STATE_ERROR_ACTIVE));
+        let wake = watcher.wait_for_wake(&mut dev, timeout).unwrap().unwrap();
+        assert!(wake.is_wake());
+    }
+}
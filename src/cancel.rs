@@ -0,0 +1,74 @@
+//! Cancellation of blocking reads
+//!
+//! A thread blocked in `GsUsb::read()` with a long timeout can be woken for
+//! shutdown through a `CancelToken` taken from the device beforehand: once
+//! `cancel()` is called from another thread, the read returns
+//! `GsUsbError::Cancelled` within `CANCEL_POLL_INTERVAL`, and so do all reads
+//! after it until the token is reset.
+//!
+//! USB bulk transfers can't be interrupted from the outside, so while a
+//! token exists, reads wait in slices of `CANCEL_POLL_INTERVAL` and check it
+//! in between. Devices without a token read as before.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, GsUsbError, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let token = dev.cancel_token();
+//! let reader = std::thread::spawn(move || loop {
+//!     match dev.read(Duration::from_secs(3600)) {
+//!         Ok(frame) => println!("{frame}"),
+//!         Err(GsUsbError::Cancelled) => return dev,
+//!         Err(e) => panic!("{e}"),
+//!     }
+//! });
+//! std::thread::sleep(Duration::from_secs(10));
+//! token.cancel();
+//! let dev = reader.join().unwrap();
+//! dev.close()?;
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Longest time a cancelled read keeps waiting
+pub const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Cancels the reads of a device, see the module documentation
+///
+/// Clones share the same state and can be sent to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Create a token that isn't cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the current and all further reads fail with
+    /// `GsUsbError::Cancelled`
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Check if `cancel()` was called since the last reset
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Let reads through again
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Release);
+    }
+}
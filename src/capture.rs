@@ -0,0 +1,717 @@
+//! Capture with loss accounting
+//!
+//! `Capture` takes over a started device and reads it continuously on a
+//! dedicated thread into a bounded buffer, so reading keeps up with the bus
+//! however irregularly the consumer writes its log. Every frame lost on the
+//! way shows up in the captured stream as a `Loss` record at the position
+//! of the loss:
+//!
+//! - frames the device dropped, as it reports with `GS_CAN_FLAG_OVERFLOW`
+//! - USB transfers too short to decode
+//! - with `Backpressure::Drop`, records dropped because the buffer was full
+//!
+//! Records are numbered without gaps, and `CaptureSummary` totals the
+//! frames and losses, so a log can be shown to be complete, or to be
+//! complete except at the marked positions. With the default
+//! `Backpressure::Block` the capture itself never drops anything: a slow
+//! consumer holds up reading until the device overflows, which is then
+//! reported.
+//!
+//! The buffer is a pool of buffers of records, which the reader fills and
+//! hands over a buffer at a time: when it is full, or when the bus pauses.
+//! `CaptureConfig` sets their number and size, and whether they are all
+//! allocated up front and reused (`BufferReuse::Recycle`, the default), so
+//! a long capture allocates nothing after starting, or allocated for each
+//! batch and freed once taken.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{Capture, CaptureConfig, CaptureEvent, GsUsb, GS_CAN_MODE_NORMAL};
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let mut capture = Capture::start(dev, CaptureConfig::default())?;
+//! for record in capture.by_ref().take(100_000) {
+//!     match record.event {
+//!         CaptureEvent::Frame(frame) => println!("{:>8} {frame}", record.seq),
+//!         CaptureEvent::Lost(loss) => println!("{:>8} {loss}", record.seq),
+//!     }
+//! }
+//! let (_dev, summary) = capture.finish()?;
+//! println!("gap free: {}", summary.is_gap_free());
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::error_frame::{CanErrorFrame, RxOverflow};
+use crate::frame::GsUsbFrame;
+
+/// Buffers in the pool by default
+pub const DEFAULT_CAPTURE_BUFFERS: usize = 256;
+
+/// Records a buffer holds by default, for 65 536 records in all
+pub const DEFAULT_CAPTURE_BUFFER_RECORDS: usize = 256;
+
+/// Longest a read waits, bounding how long stopping takes
+const READ_POLL: Duration = Duration::from_millis(100);
+
+/// Pause in the traffic after which a partly filled buffer is handed over
+const BATCH_GAP: Duration = Duration::from_millis(1);
+
+/// A buffer of the pool
+type Buffer = VecDeque<CaptureRecord>;
+
+/// What the reader does when the buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Wait for the consumer; losses can then only happen on the device
+    #[default]
+    Block,
+    /// Keep reading and drop records, reporting how many
+    Drop,
+}
+
+/// How the buffers of the pool are reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BufferReuse {
+    /// Allocate every buffer when starting and refill buffers once their
+    /// records were taken
+    #[default]
+    Recycle,
+    /// Allocate a buffer for each batch and free it once its records were
+    /// taken, holding memory only while records wait
+    Allocate,
+}
+
+/// Settings of a `Capture`
+///
+/// The buffer holds up to `buffers` times `buffer_records` records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureConfig {
+    /// Buffers in the pool
+    pub buffers: usize,
+    /// Records a buffer holds
+    pub buffer_records: usize,
+    /// Whether buffers are allocated up front and reused
+    pub reuse: BufferReuse,
+    /// What to do when all buffers are full
+    pub backpressure: Backpressure,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            buffers: DEFAULT_CAPTURE_BUFFERS,
+            buffer_records: DEFAULT_CAPTURE_BUFFER_RECORDS,
+            reuse: BufferReuse::Recycle,
+            backpressure: Backpressure::Block,
+        }
+    }
+}
+
+/// Frames lost at one position of the captured stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loss {
+    /// The device dropped frames before the next one
+    DeviceOverflow(RxOverflow),
+    /// A USB transfer too short to decode, one frame lost
+    CorruptTransfer {
+        /// Bytes a frame takes
+        expected: usize,
+        /// Bytes received
+        actual: usize,
+    },
+    /// Records dropped because the buffer was full
+    BufferFull {
+        /// Records dropped, frames and loss records alike
+        records: u64,
+    },
+}
+
+impl std::fmt::Display for Loss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeviceOverflow(overflow) => overflow.fmt(f),
+            Self::CorruptTransfer { expected, actual } => {
+                write!(
+                    f,
+                    "corrupt transfer of {actual} bytes, expected {expected}: 1 frame lost"
+                )
+            }
+            Self::BufferFull { records } => {
+                write!(f, "capture buffer full: {records} record(s) dropped")
+            }
+        }
+    }
+}
+
+/// What a `CaptureRecord` holds
+#[derive(Debug, Clone)]
+pub enum CaptureEvent {
+    /// A frame as `GsUsb::read()` returned it
+    Frame(GsUsbFrame),
+    /// Frames lost at this position
+    Lost(Loss),
+}
+
+/// One entry of the captured stream
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    /// Position in the stream, counting from 0 without gaps
+    pub seq: u64,
+    /// The frame or loss
+    pub event: CaptureEvent,
+}
+
+/// Totals of a capture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureSummary {
+    /// Records put in the buffer
+    pub records: u64,
+    /// Frames among them
+    pub frames: u64,
+    /// Overflows the device reported
+    pub device_overflows: u64,
+    /// Lower bound on the frames the device dropped
+    pub device_lost_estimate: u64,
+    /// Transfers too short to decode
+    pub corrupt_transfers: u64,
+    /// Records dropped because the buffer was full
+    pub dropped_records: u64,
+    /// Frames among them
+    pub dropped_frames: u64,
+}
+
+impl CaptureSummary {
+    /// Lower bound on the frames lost, wherever they were lost
+    pub fn lost_estimate(&self) -> u64 {
+        self.device_lost_estimate + self.corrupt_transfers + self.dropped_frames
+    }
+
+    /// Whether every frame received was captured
+    pub fn is_gap_free(&self) -> bool {
+        self.device_overflows == 0 && self.corrupt_transfers == 0 && self.dropped_records == 0
+    }
+}
+
+/// What the reader thread hands back
+type Outcome = (GsUsb, CaptureSummary, Result<()>);
+
+/// A device read on a dedicated thread, see the module documentation
+///
+/// Iterating waits for the next record and ends once the capture stopped
+/// and all records were taken.
+pub struct Capture {
+    pool: Option<Pool>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<Outcome>>,
+}
+
+impl Capture {
+    /// Start capturing from a started device
+    ///
+    /// Fails with `InvalidConfig` for a pool without buffers or buffers of
+    /// no records, or if the device is in python-can compatibility mode,
+    /// which doesn't report overflows.
+    pub fn start(dev: GsUsb, config: CaptureConfig) -> Result<Self> {
+        if config.buffers == 0 || config.buffer_records == 0 {
+            return Err(GsUsbError::InvalidConfig(format!(
+                "capture pool of {} buffers of {} records",
+                config.buffers, config.buffer_records
+            )));
+        }
+        if dev.python_can_compat() {
+            return Err(GsUsbError::InvalidConfig(
+                "overflows are not reported in python-can compatibility mode".to_string(),
+            ));
+        }
+        let (full_sender, full) = mpsc::channel();
+        let (empty_sender, empty) = mpsc::channel();
+        for _ in 0..config.buffers {
+            let buffer = match config.reuse {
+                BufferReuse::Recycle => Buffer::with_capacity(config.buffer_records),
+                BufferReuse::Allocate => Buffer::new(),
+            };
+            // The receiver is right here
+            let _ = empty_sender.send(buffer);
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = Writer {
+            full: full_sender,
+            empty,
+            buffer: None,
+            buffer_records: config.buffer_records,
+            backpressure: config.backpressure,
+            summary: CaptureSummary::default(),
+            pending_records: 0,
+            pending_frames: 0,
+        };
+        let reader = {
+            let stop = Arc::clone(&stop);
+            std::thread::Builder::new()
+                .name("gs_usb capture".to_string())
+                .spawn(move || read_loop(dev, writer, &stop))?
+        };
+        Ok(Self {
+            pool: Some(Pool {
+                full,
+                empty: empty_sender,
+                current: None,
+                reuse: config.reuse,
+            }),
+            stop,
+            reader: Some(reader),
+        })
+    }
+
+    /// Stop reading after the current read
+    ///
+    /// Records already buffered can still be taken.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// The next record, waiting up to `timeout`
+    ///
+    /// `None` if there was none in time or the capture has ended.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<CaptureRecord> {
+        let pool = self.pool.as_mut()?;
+        pool.next(|full| {
+            full.recv_timeout(timeout)
+                .map_err(|_| RecvTimeoutError::Timeout)
+        })
+    }
+
+    /// Stop and return the device and the totals
+    ///
+    /// Records not taken yet are discarded, so to keep them call `stop()`
+    /// and take them first. Fails with the error that ended reading, if
+    /// any; the device is closed then.
+    pub fn finish(mut self) -> Result<(GsUsb, CaptureSummary)> {
+        let (dev, summary, result) = self.join().expect("capture is only finished once");
+        result.map(|()| (dev, summary))
+    }
+
+    fn join(&mut self) -> Option<Outcome> {
+        self.stop();
+        // Unblock a reader waiting for an empty buffer
+        self.pool = None;
+        let reader = self.reader.take()?;
+        match reader.join() {
+            Ok(outcome) => Some(outcome),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Iterator for Capture {
+    type Item = CaptureRecord;
+
+    fn next(&mut self) -> Option<CaptureRecord> {
+        let pool = self.pool.as_mut()?;
+        pool.next(|full| full.recv().map_err(|_| RecvTimeoutError::Disconnected))
+    }
+}
+
+impl std::fmt::Debug for Capture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Capture")
+            .field("stopped", &self.stop.load(Ordering::Relaxed))
+            .field("finished", &self.reader.is_none())
+            .finish()
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            self.join();
+        }
+    }
+}
+
+/// The consumer's end of the buffer pool
+struct Pool {
+    /// Buffers filled by the reader
+    full: Receiver<Buffer>,
+    /// Buffers handed back to the reader
+    empty: Sender<Buffer>,
+    /// Buffer records are taken from
+    current: Option<Buffer>,
+    reuse: BufferReuse,
+}
+
+impl Pool {
+    /// The next record, getting the next buffer with `recv` when needed
+    /// and handing buffers back as soon as they are empty
+    fn next(
+        &mut self,
+        recv: impl FnOnce(&Receiver<Buffer>) -> std::result::Result<Buffer, RecvTimeoutError>,
+    ) -> Option<CaptureRecord> {
+        let mut buffer = match self.current.take() {
+            Some(buffer) => buffer,
+            None => recv(&self.full).ok()?,
+        };
+        // Buffers are handed over with records only
+        let record = buffer.pop_front();
+        if buffer.is_empty() {
+            let returned = match self.reuse {
+                BufferReuse::Recycle => buffer,
+                // Only the permission to allocate a buffer goes back
+                BufferReuse::Allocate => Buffer::new(),
+            };
+            // The reader may have ended
+            let _ = self.empty.send(returned);
+        } else {
+            self.current = Some(buffer);
+        }
+        record
+    }
+}
+
+/// Why a record couldn't be put in a buffer
+enum SendError {
+    /// All buffers are full
+    Full,
+    /// The consumer is gone
+    Disconnected,
+}
+
+/// The reader's end of the buffer pool
+struct Writer {
+    /// Buffers handed to the consumer
+    full: Sender<Buffer>,
+    /// Buffers to fill
+    empty: Receiver<Buffer>,
+    /// Buffer being filled
+    buffer: Option<Buffer>,
+    buffer_records: usize,
+    backpressure: Backpressure,
+    summary: CaptureSummary,
+    /// Records dropped since the last one put in the buffer
+    pending_records: u64,
+    pending_frames: u64,
+}
+
+impl Writer {
+    /// Put `event` in the buffer, false once the consumer is gone
+    fn push(&mut self, event: CaptureEvent) -> bool {
+        if !self.flush() {
+            return false;
+        }
+        let is_frame = matches!(event, CaptureEvent::Frame(_));
+        match &event {
+            CaptureEvent::Frame(_) => {}
+            CaptureEvent::Lost(Loss::DeviceOverflow(overflow)) => {
+                self.summary.device_overflows += 1;
+                self.summary.device_lost_estimate += u64::from(overflow.lost_estimate);
+            }
+            CaptureEvent::Lost(Loss::CorruptTransfer { .. }) => {
+                self.summary.corrupt_transfers += 1;
+            }
+            CaptureEvent::Lost(Loss::BufferFull { .. }) => {}
+        }
+        if self.pending_records > 0 {
+            self.drop_record(is_frame);
+            return true;
+        }
+        match self.send(event) {
+            Ok(()) => {
+                if is_frame {
+                    self.summary.frames += 1;
+                }
+                true
+            }
+            Err(SendError::Full) => {
+                self.drop_record(is_frame);
+                true
+            }
+            Err(SendError::Disconnected) => false,
+        }
+    }
+
+    /// Hand the buffer being filled to the consumer, false once the
+    /// consumer is gone
+    fn hand_over(&mut self) -> bool {
+        match self.buffer.take() {
+            Some(buffer) if !buffer.is_empty() => self.full.send(buffer).is_ok(),
+            buffer => {
+                self.buffer = buffer;
+                true
+            }
+        }
+    }
+
+    /// Whether the buffer being filled holds records
+    fn has_records(&self) -> bool {
+        self.buffer
+            .as_ref()
+            .is_some_and(|buffer| !buffer.is_empty())
+    }
+
+    /// Report the records dropped if there is space, false once the
+    /// consumer is gone
+    fn flush(&mut self) -> bool {
+        if self.pending_records == 0 {
+            return true;
+        }
+        let loss = Loss::BufferFull {
+            records: self.pending_records,
+        };
+        match self.send(CaptureEvent::Lost(loss)) {
+            Ok(()) => {
+                self.summary.dropped_records += self.pending_records;
+                self.summary.dropped_frames += self.pending_frames;
+                self.pending_records = 0;
+                self.pending_frames = 0;
+                true
+            }
+            Err(SendError::Full) => true,
+            Err(SendError::Disconnected) => false,
+        }
+    }
+
+    fn drop_record(&mut self, is_frame: bool) {
+        self.pending_records += 1;
+        self.pending_frames += u64::from(is_frame);
+    }
+
+    fn send(&mut self, event: CaptureEvent) -> std::result::Result<(), SendError> {
+        let mut buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => {
+                let empty = match self.backpressure {
+                    Backpressure::Block => self.empty.recv().map_err(|_| SendError::Disconnected),
+                    Backpressure::Drop => self.empty.try_recv().map_err(|e| match e {
+                        mpsc::TryRecvError::Empty => SendError::Full,
+                        mpsc::TryRecvError::Disconnected => SendError::Disconnected,
+                    }),
+                };
+                let mut buffer = empty?;
+                // Returned without memory with `BufferReuse::Allocate`
+                buffer.reserve_exact(self.buffer_records);
+                buffer
+            }
+        };
+        buffer.push_back(CaptureRecord {
+            seq: self.summary.records,
+            event,
+        });
+        self.summary.records += 1;
+        if buffer.len() < self.buffer_records {
+            self.buffer = Some(buffer);
+        } else if self.full.send(buffer).is_err() {
+            return Err(SendError::Disconnected);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> CaptureSummary {
+        // Drops not reported in the stream still count
+        self.summary.dropped_records += self.pending_records;
+        self.summary.dropped_frames += self.pending_frames;
+        self.summary
+    }
+}
+
+fn read_loop(mut dev: GsUsb, mut writer: Writer, stop: &AtomicBool) -> Outcome {
+    let mut result = Ok(());
+    while !stop.load(Ordering::Relaxed) {
+        // Wait briefly while a buffer is partly filled, to hand it over
+        // once the burst ends
+        let timeout = if writer.has_records() {
+            BATCH_GAP
+        } else {
+            READ_POLL
+        };
+        let event = match dev.read(timeout) {
+            Ok(frame) => match CanErrorFrame::from_frame(&frame).and_then(|e| e.rx_overflow()) {
+                Some(overflow) => CaptureEvent::Lost(Loss::DeviceOverflow(overflow)),
+                None => CaptureEvent::Frame(frame),
+            },
+            Err(GsUsbError::RxOverflow(overflow)) => {
+                CaptureEvent::Lost(Loss::DeviceOverflow(overflow))
+            }
+            Err(GsUsbError::BusError(error)) => CaptureEvent::Frame(error.frame().clone()),
+            Err(GsUsbError::InvalidResponse { expected, actual }) => {
+                CaptureEvent::Lost(Loss::CorruptTransfer { expected, actual })
+            }
+            Err(GsUsbError::ReadTimeout) => {
+                if !writer.hand_over() || !writer.flush() {
+                    break;
+                }
+                continue;
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        if !writer.push(event) {
+            break;
+        }
+    }
+    // Records of a partly filled buffer can still be taken
+    writer.hand_over();
+    (dev, writer.finish(), result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{GS_CAN_FLAG_OVERFLOW, GS_CAN_MODE_NORMAL};
+    use crate::mock::MockGsUsb;
+    use crate::scenario::{Scenario, ScenarioEvent};
+
+    fn started(mock: &MockGsUsb) -> GsUsb {
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        dev
+    }
+
+    fn ids(records: &[CaptureRecord]) -> Vec<Option<u32>> {
+        records
+            .iter()
+            .map(|record| match &record.event {
+                CaptureEvent::Frame(frame) => Some(frame.can_id),
+                CaptureEvent::Lost(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_losses_in_place() {
+        let mock = MockGsUsb::new();
+        let dev = started(&mock);
+        let mut flagged = GsUsbFrame::with_data(0x101, &[1]);
+        flagged.flags |= GS_CAN_FLAG_OVERFLOW;
+        let ms = Duration::from_millis;
+        mock.play(
+            Scenario::new()
+                .frame(ms(0), GsUsbFrame::with_data(0x100, &[0]))
+                .frame(ms(1), flagged)
+                .at(ms(2), ScenarioEvent::RawRx(vec![0; 5]))
+                .frame(ms(3), GsUsbFrame::with_data(0x102, &[2])),
+        );
+
+        let mut capture = Capture::start(dev, CaptureConfig::default()).unwrap();
+        let records: Vec<_> = capture.by_ref().take(5).collect();
+        assert_eq!(
+            ids(&records),
+            [Some(0x100), None, Some(0x101), None, Some(0x102)]
+        );
+        assert!(records.iter().map(|r| r.seq).eq(0..5));
+        assert!(matches!(
+            records[1].event,
+            CaptureEvent::Lost(Loss::DeviceOverflow(_))
+        ));
+        assert!(matches!(
+            records[3].event,
+            CaptureEvent::Lost(Loss::CorruptTransfer { actual: 5, .. })
+        ));
+
+        let (_dev, summary) = capture.finish().unwrap();
+        assert_eq!(
+            summary,
+            CaptureSummary {
+                records: 5,
+                frames: 3,
+                device_overflows: 1,
+                device_lost_estimate: 1,
+                corrupt_transfers: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(summary.lost_estimate(), 2);
+        assert!(!summary.is_gap_free());
+    }
+
+    #[test]
+    fn test_buffer_full() {
+        let mock = MockGsUsb::new();
+        let dev = started(&mock);
+        for id in 0..5 {
+            mock.push_rx(&GsUsbFrame::with_data(id, &[]));
+        }
+        let config = CaptureConfig {
+            buffers: 2,
+            buffer_records: 1,
+            backpressure: Backpressure::Drop,
+            ..CaptureConfig::default()
+        };
+        let mut capture = Capture::start(dev, config).unwrap();
+        // Let the reader overrun the buffer
+        std::thread::sleep(Duration::from_millis(50));
+        let records: Vec<_> = capture.by_ref().take(3).collect();
+        assert_eq!(ids(&records), [Some(0), Some(1), None]);
+        assert!(matches!(
+            records[2].event,
+            CaptureEvent::Lost(Loss::BufferFull { records: 3 })
+        ));
+
+        capture.stop();
+        assert!(capture.next().is_none());
+        let (_dev, summary) = capture.finish().unwrap();
+        assert_eq!(summary.records, 3);
+        assert_eq!(summary.dropped_frames, 3);
+        assert_eq!(summary.lost_estimate(), 3);
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        for reuse in [BufferReuse::Recycle, BufferReuse::Allocate] {
+            let mock = MockGsUsb::new();
+            let dev = started(&mock);
+            for id in 0..10 {
+                mock.push_rx(&GsUsbFrame::with_data(id, &[]));
+            }
+            // A single buffer passes back and forth without losses
+            let config = CaptureConfig {
+                buffers: 1,
+                buffer_records: 3,
+                reuse,
+                ..CaptureConfig::default()
+            };
+            let mut capture = Capture::start(dev, config).unwrap();
+            let records: Vec<_> = capture.by_ref().take(10).collect();
+            assert_eq!(ids(&records), (0..10).map(Some).collect::<Vec<_>>());
+            assert!(capture.finish().unwrap().1.is_gap_free());
+        }
+
+        let mock = MockGsUsb::new();
+        for (buffers, buffer_records) in [(0, 1), (1, 0)] {
+            let config = CaptureConfig {
+                buffers,
+                buffer_records,
+                ..CaptureConfig::default()
+            };
+            assert!(Capture::start(started(&mock), config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_read_error() {
+        let mock = MockGsUsb::new();
+        let dev = started(&mock);
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[]));
+        mock.push_rx_error(rusb::Error::NoDevice);
+        let mut capture = Capture::start(dev, CaptureConfig::default()).unwrap();
+        assert_eq!(ids(&capture.by_ref().collect::<Vec<_>>()), [Some(0x100)]);
+        assert!(capture.finish().is_err());
+
+        let mut dev = started(&mock);
+        dev.set_python_can_compat(true);
+        assert!(Capture::start(dev, CaptureConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Payload change detection
+//!
+//! `ChangeDetector` remembers the last payload of every identifier and
+//! reports when it changes. Restricted to some identifiers, byte ranges or
+//! bits with masks, it answers the classic reverse-engineering question of
+//! which byte moves when a button is pressed, without the noise of counters
+//! and checksums.
+//!
+//! `PayloadDiff` tells which bytes and bits differ between two payloads and
+//! renders a payload with the changed bytes highlighted, for monitors that
+//! show traffic live.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{CanId, ChangeDetector, GsUsb, GS_CAN_MODE_LISTEN_ONLY};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_LISTEN_ONLY)?;
+//!
+//! // Bytes 0-5 of 0x3C0, ignoring the rolling counter in the low nibble of byte 6
+//! let mut changes = ChangeDetector::new();
+//! changes.watch(CanId::Standard(0x3C0), &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xF0]);
+//! loop {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     if let Some(change) = changes.observe(&frame) {
+//!         println!("{change}");
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::frame::GsUsbFrame;
+use crate::id::CanId;
+
+/// The payload of an identifier changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadChange {
+    /// Identifier of the frames
+    pub id: CanId,
+    /// Payload before the change
+    pub previous: Vec<u8>,
+    /// Payload after the change
+    pub current: Vec<u8>,
+}
+
+impl PayloadChange {
+    /// Bits that changed
+    pub fn diff(&self) -> PayloadDiff {
+        PayloadDiff::new(&self.previous, &self.current)
+    }
+}
+
+impl std::fmt::Display for PayloadChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: [{}] -> [{}]",
+            self.id,
+            hex(&self.previous),
+            hex(&self.current)
+        )
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Bits that differ between two payloads
+///
+/// The mask is as long as the longer payload; bytes present in only one of
+/// them count as fully changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PayloadDiff {
+    mask: Vec<u8>,
+}
+
+impl PayloadDiff {
+    /// Compare two payloads, usually of consecutive frames of an identifier
+    pub fn new(previous: &[u8], current: &[u8]) -> Self {
+        let len = previous.len().max(current.len());
+        let mask = (0..len)
+            .map(|i| match (previous.get(i), current.get(i)) {
+                (Some(a), Some(b)) => a ^ b,
+                _ => 0xFF,
+            })
+            .collect();
+        Self { mask }
+    }
+
+    /// Changed bits per byte
+    pub fn mask(&self) -> &[u8] {
+        &self.mask
+    }
+
+    /// Check if the payloads are equal
+    pub fn is_empty(&self) -> bool {
+        self.mask.iter().all(|&bits| bits == 0)
+    }
+
+    /// Check if byte `index` changed
+    pub fn is_changed(&self, index: usize) -> bool {
+        self.mask.get(index).is_some_and(|&bits| bits != 0)
+    }
+
+    /// Indices of the changed bytes
+    pub fn changed_bytes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.mask.len()).filter(|&i| self.is_changed(i))
+    }
+
+    /// Changed bits as (byte index, bit number), bit 0 being the least
+    /// significant
+    pub fn changed_bits(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.changed_bytes().flat_map(move |i| {
+            (0..8)
+                .filter(move |bit| self.mask[i] & (1 << bit) != 0)
+                .map(move |bit| (i, bit))
+        })
+    }
+
+    /// Render `data` as hex bytes, with the changed ones between `open` and
+    /// `close`
+    ///
+    /// `open` and `close` can be brackets or terminal escape sequences, e.g.
+    /// `"\x1b[1m"` and `"\x1b[0m"` for bold.
+    pub fn highlight(&self, data: &[u8], open: &str, close: &str) -> String {
+        data.iter()
+            .enumerate()
+            .map(|(i, b)| {
+                if self.is_changed(i) {
+                    format!("{open}{b:02X}{close}")
+                } else {
+                    format!("{b:02X}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Reports payload changes per identifier, see the module documentation
+///
+/// Without `watch()`, every identifier is followed and every bit counts.
+/// Once identifiers are watched, only those are followed, and only the bits
+/// set in their masks count. The first frame of an identifier sets its
+/// baseline and is not a change. Remote and error frames are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeDetector {
+    /// Masks of the watched identifiers, all identifiers if empty
+    masks: BTreeMap<CanId, Vec<u8>>,
+    /// Last payload per identifier
+    last: BTreeMap<CanId, Vec<u8>>,
+}
+
+impl ChangeDetector {
+    /// Create a detector following every identifier
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow `id`, counting only the bits set in `mask`
+    ///
+    /// Bytes past the end of the mask don't count.
+    pub fn watch(&mut self, id: CanId, mask: &[u8]) {
+        self.masks.insert(id, mask.to_vec());
+    }
+
+    /// Follow `id`, counting only the bytes in `bytes`
+    pub fn watch_bytes(&mut self, id: CanId, bytes: Range<usize>) {
+        let mut mask = vec![0; bytes.end];
+        mask[bytes].fill(0xFF);
+        self.watch(id, &mask);
+    }
+
+    /// Follow `id` with every bit counting
+    pub fn watch_all(&mut self, id: CanId) {
+        self.watch(id, &[0xFF; 64]);
+    }
+
+    /// Take a frame, reporting a change from the previous payload of its
+    /// identifier
+    pub fn observe(&mut self, frame: &GsUsbFrame) -> Option<PayloadChange> {
+        if frame.is_error_frame() || frame.is_remote_frame() {
+            return None;
+        }
+        let id = CanId::from_can_id(frame.can_id);
+        let mask = if self.masks.is_empty() {
+            None
+        } else {
+            Some(self.masks.get(&id)?)
+        };
+        let current = frame.data();
+        let previous = self.last.insert(id, current.to_vec())?;
+        let changed = match mask {
+            None => previous != current,
+            Some(mask) => differs(mask, &previous, current),
+        };
+        changed.then(|| PayloadChange {
+            id,
+            previous,
+            current: current.to_vec(),
+        })
+    }
+
+    /// Last payload seen for `id`
+    pub fn last(&self, id: CanId) -> Option<&[u8]> {
+        self.last.get(&id).map(Vec::as_slice)
+    }
+
+    /// Forget the payloads seen, so the next frames set new baselines
+    pub fn reset(&mut self) {
+        self.last.clear();
+    }
+}
+
+/// Whether the masked bits of two payloads differ; a masked byte present in
+/// only one of them is a difference
+fn differs(mask: &[u8], a: &[u8], b: &[u8]) -> bool {
+    mask.iter().enumerate().any(|(i, &bits)| {
+        bits != 0
+            && match (a.get(i), b.get(i)) {
+                (Some(x), Some(y)) => (x ^ y) & bits != 0,
+                (None, None) => false,
+                _ => true,
+            }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(id: u32, data: &[u8]) -> GsUsbFrame {
+        GsUsbFrame::with_data(id, data)
+    }
+
+    #[test]
+    fn test_all_identifiers() {
+        let mut changes = ChangeDetector::new();
+        assert_eq!(changes.observe(&frame(0x100, &[1, 2])), None);
+        assert_eq!(changes.observe(&frame(0x100, &[1, 2])), None);
+        assert_eq!(changes.observe(&frame(0x200, &[9])), None);
+
+        let change = changes.observe(&frame(0x100, &[1, 3])).unwrap();
+        assert_eq!(change.id, CanId::Standard(0x100));
+        assert_eq!(change.to_string(), "100: [01 02] -> [01 03]");
+        // A shorter payload is a change too
+        assert!(changes.observe(&frame(0x100, &[1])).is_some());
+        assert_eq!(changes.last(CanId::Standard(0x100)), Some(&[1][..]));
+
+        changes.reset();
+        assert_eq!(changes.observe(&frame(0x100, &[7])), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let diff = PayloadDiff::new(&[0x00, 0x12, 0xFF], &[0x00, 0x13, 0x7F, 0x01]);
+        assert_eq!(diff.mask(), [0x00, 0x01, 0x80, 0xFF]);
+        assert!(!diff.is_empty() && !diff.is_changed(0) && diff.is_changed(3));
+        assert_eq!(diff.changed_bytes().collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(
+            diff.changed_bits().take(3).collect::<Vec<_>>(),
+            [(1, 0), (2, 7), (3, 0)]
+        );
+        assert_eq!(
+            diff.highlight(&[0x00, 0x13, 0x7F, 0x01], "[", "]"),
+            "00 [13] [7F] [01]"
+        );
+        assert!(PayloadDiff::new(&[1, 2], &[1, 2]).is_empty());
+
+        let mut changes = ChangeDetector::new();
+        changes.observe(&frame(0x100, &[1, 2]));
+        let change = changes.observe(&frame(0x100, &[1, 3])).unwrap();
+        assert_eq!(change.diff().changed_bits().collect::<Vec<_>>(), [(1, 0)]);
+    }
+
+    #[test]
+    fn test_masks() {
+        let mut changes = ChangeDetector::new();
+        changes.watch(CanId::Standard(0x100), &[0x00, 0xF0]);
+        changes.watch_bytes(CanId::Standard(0x200), 2..4);
+
+        changes.observe(&frame(0x100, &[0, 0x00]));
+        // Unmasked bits and bytes past the mask
+        assert_eq!(changes.observe(&frame(0x100, &[5, 0x0F, 1])), None);
+        assert!(changes.observe(&frame(0x100, &[5, 0x1F, 1])).is_some());
+
+        changes.observe(&frame(0x200, &[0, 0, 0, 0]));
+        assert_eq!(changes.observe(&frame(0x200, &[1, 1, 0, 0, 1])), None);
+        assert!(changes.observe(&frame(0x200, &[1, 1, 0])).is_some());
+
+        // Identifiers not watched are ignored
+        changes.observe(&frame(0x300, &[0]));
+        assert_eq!(changes.observe(&frame(0x300, &[1])), None);
+        assert_eq!(changes.last(CanId::Standard(0x300)), None);
+    }
+}
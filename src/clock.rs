@@ -0,0 +1,93 @@
+//! Time source abstraction
+//!
+//! Time-dependent logic (timestamp extension, cyclic transmission, watchdogs)
+//! reads the time through the `Clock` trait instead of calling
+//! `Instant::now()` and `thread::sleep()` directly. `SystemClock` is the real
+//! monotonic clock; `TestClock` (`test-util` feature) only moves when told to,
+//! so hours of device time can be simulated in a unit test without sleeping.
+
+use std::time::{Duration, Instant};
+
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::{Arc, Mutex};
+
+/// Monotonic time source
+pub trait Clock: Send + Sync {
+    /// Current time
+    fn now(&self) -> Instant;
+
+    /// Block the calling thread for `duration` of this clock's time
+    fn sleep(&self, duration: Duration);
+}
+
+/// The host's monotonic clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Manually advanced clock for deterministic tests
+///
+/// Clones share the same time. `sleep()` advances the clock by the requested
+/// duration and returns immediately.
+///
+/// Available with the `test-util` feature.
+///
+/// # Example
+///
+/// ```
+/// use gs_usb::{Clock, TestClock};
+/// use std::time::Duration;
+///
+/// let clock = TestClock::new();
+/// let start = clock.now();
+/// clock.advance(Duration::from_secs(3600));
+/// clock.sleep(Duration::from_millis(5));
+/// assert_eq!(clock.now() - start, Duration::from_millis(3_600_005));
+/// ```
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone)]
+pub struct TestClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl TestClock {
+    /// Create a clock stopped at the current host time
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
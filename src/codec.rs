@@ -0,0 +1,260 @@
+//! Byte stream codec for `GsUsbFrame`
+//!
+//! `FrameCodec` writes frames in the gs_usb host frame format the adapters use
+//! on their bulk endpoints (12-byte header, 8 or 64 data bytes, optional
+//! 32-bit hardware timestamp) and splits a byte stream back into frames, so
+//! frames can be carried over sockets, pipes or files unchanged.
+//!
+//! Each frame is self-describing: FD frames (`GS_CAN_FLAG_FD`) take 76 bytes,
+//! classic frames 20, plus 4 for the timestamp if enabled. Both ends of a
+//! stream must agree on whether timestamps are included.
+//!
+//! With the `codec` feature, `FrameCodec` also implements the tokio-util
+//! `Encoder` and `Decoder` traits, for use with `FramedRead`/`FramedWrite`.
+//!
+//! # Example
+//!
+//! ```
+//! use gs_usb::{FrameCodec, GsUsbFrame};
+//!
+//! let codec = FrameCodec::new().hw_timestamp(true);
+//! let mut stream = Vec::new();
+//! codec.encode(&GsUsbFrame::with_data(0x123, &[1, 2, 3]), &mut stream);
+//! codec.encode(&GsUsbFrame::with_fd_data(0x456, &[0; 12], true), &mut stream);
+//! assert_eq!(stream.len(), 24 + 80);
+//!
+//! let (frame, used) = codec.decode(&stream).unwrap();
+//! assert_eq!(frame.arbitration_id(), 0x123);
+//! let (frame, _) = codec.decode(&stream[used..]).unwrap();
+//! assert!(frame.is_fd());
+//! ```
+
+use std::io::{ErrorKind, Read, Write};
+
+use crate::constants::{
+    GS_CAN_FLAG_FD, GS_USB_FRAME_HEADER_SIZE, GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP,
+};
+use crate::error::Result;
+use crate::frame::GsUsbFrame;
+
+/// Offset of the flags byte in the frame header
+const FLAGS_OFFSET: usize = 10;
+
+/// Encoder and decoder of gs_usb host frames on a byte stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameCodec {
+    hw_timestamp: bool,
+}
+
+impl FrameCodec {
+    /// Codec for frames without hardware timestamps
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Include the 32-bit hardware timestamp after the data
+    pub fn hw_timestamp(mut self, enabled: bool) -> Self {
+        self.hw_timestamp = enabled;
+        self
+    }
+
+    /// Whether frames include the hardware timestamp
+    pub fn has_hw_timestamp(&self) -> bool {
+        self.hw_timestamp
+    }
+
+    /// Number of bytes `frame` takes on the stream
+    pub fn encoded_len(&self, frame: &GsUsbFrame) -> usize {
+        GsUsbFrame::frame_size(self.hw_timestamp, frame.is_fd())
+    }
+
+    /// Append `frame` to `dst`
+    pub fn encode(&self, frame: &GsUsbFrame, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&frame.pack(self.hw_timestamp, frame.is_fd()));
+    }
+
+    /// Decode the frame at the start of `src`
+    ///
+    /// Returns the frame and the number of bytes it took, or `None` if `src`
+    /// doesn't hold a complete frame yet.
+    pub fn decode(&self, src: &[u8]) -> Option<(GsUsbFrame, usize)> {
+        let len = self.frame_len(src)?;
+        if src.len() < len {
+            return None;
+        }
+        Some((
+            GsUsbFrame::from_bytes(&src[..len], self.hw_timestamp, self.is_fd(src)),
+            len,
+        ))
+    }
+
+    /// Read one frame from a blocking stream
+    ///
+    /// Returns `None` at end of stream; a stream ending inside a frame is an
+    /// error.
+    pub fn read_frame<R: Read>(&self, reader: &mut R) -> Result<Option<GsUsbFrame>> {
+        let mut buf = [0u8; GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP];
+        let mut read = 0;
+        while read < GS_USB_FRAME_HEADER_SIZE {
+            match reader.read(&mut buf[read..GS_USB_FRAME_HEADER_SIZE]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let len = GsUsbFrame::frame_size(self.hw_timestamp, self.is_fd(&buf));
+        reader.read_exact(&mut buf[GS_USB_FRAME_HEADER_SIZE..len])?;
+        Ok(self.decode(&buf[..len]).map(|(frame, _)| frame))
+    }
+
+    /// Write one frame to a blocking stream
+    pub fn write_frame<W: Write>(&self, writer: &mut W, frame: &GsUsbFrame) -> Result<()> {
+        writer.write_all(&frame.pack(self.hw_timestamp, frame.is_fd()))?;
+        Ok(())
+    }
+
+    /// Length of the frame starting at `src`, once its header is there
+    fn frame_len(&self, src: &[u8]) -> Option<usize> {
+        (src.len() >= GS_USB_FRAME_HEADER_SIZE)
+            .then(|| GsUsbFrame::frame_size(self.hw_timestamp, self.is_fd(src)))
+    }
+
+    fn is_fd(&self, header: &[u8]) -> bool {
+        header[FLAGS_OFFSET] & GS_CAN_FLAG_FD != 0
+    }
+}
+
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Encoder<GsUsbFrame> for FrameCodec {
+    type Error = crate::error::GsUsbError;
+
+    fn encode(&mut self, frame: GsUsbFrame, dst: &mut bytes::BytesMut) -> Result<()> {
+        tokio_util::codec::Encoder::<&GsUsbFrame>::encode(self, &frame, dst)
+    }
+}
+
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Encoder<&GsUsbFrame> for FrameCodec {
+    type Error = crate::error::GsUsbError;
+
+    fn encode(&mut self, frame: &GsUsbFrame, dst: &mut bytes::BytesMut) -> Result<()> {
+        dst.extend_from_slice(&frame.pack(self.hw_timestamp, frame.is_fd()));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Decoder for FrameCodec {
+    type Item = GsUsbFrame;
+    type Error = crate::error::GsUsbError;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<GsUsbFrame>> {
+        use bytes::Buf;
+
+        match FrameCodec::decode(self, src) {
+            Some((frame, len)) => {
+                src.advance(len);
+                Ok(Some(frame))
+            }
+            None => {
+                if let Some(len) = self.frame_len(src) {
+                    src.reserve(len - src.len());
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CAN_EFF_FLAG;
+    use crate::error::GsUsbError;
+
+    fn frames() -> Vec<GsUsbFrame> {
+        let mut classic = GsUsbFrame::with_data(0x1234567 | CAN_EFF_FLAG, &[1, 2, 3]);
+        classic.channel = 1;
+        classic.timestamp_us = 0xDEADBEEF;
+        let mut fd = GsUsbFrame::with_fd_data(0x7FF, &[0x55; 48], true);
+        fd.timestamp_us = 42;
+        vec![classic, fd, GsUsbFrame::with_data(0x1, &[])]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for hw_timestamp in [false, true] {
+            let codec = FrameCodec::new().hw_timestamp(hw_timestamp);
+            let mut stream = Vec::new();
+            for frame in frames() {
+                codec.encode(&frame, &mut stream);
+            }
+            let expected: usize = frames().iter().map(|f| codec.encoded_len(f)).sum();
+            assert_eq!(stream.len(), expected);
+
+            let mut rest = &stream[..];
+            for frame in frames() {
+                // Nothing comes out of a partial frame
+                assert!(codec
+                    .decode(&rest[..codec.encoded_len(&frame) - 1])
+                    .is_none());
+                let (decoded, len) = codec.decode(rest).unwrap();
+                assert_eq!(decoded.can_id, frame.can_id);
+                assert_eq!(decoded.channel, frame.channel);
+                assert_eq!(decoded.data(), frame.data());
+                let timestamp = if hw_timestamp { frame.timestamp_us } else { 0 };
+                assert_eq!(decoded.timestamp_us, timestamp);
+                rest = &rest[len..];
+            }
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_write() {
+        let codec = FrameCodec::new().hw_timestamp(true);
+        let mut stream = Vec::new();
+        for frame in frames() {
+            codec.write_frame(&mut stream, &frame).unwrap();
+        }
+
+        let mut reader = &stream[..];
+        let ids: Vec<u32> = std::iter::from_fn(|| codec.read_frame(&mut reader).unwrap())
+            .map(|f| f.arbitration_id())
+            .collect();
+        assert_eq!(ids, [0x1234567, 0x7FF, 0x1]);
+
+        let mut truncated = &stream[..30];
+        codec.read_frame(&mut truncated).unwrap();
+        assert!(matches!(
+            codec.read_frame(&mut truncated),
+            Err(GsUsbError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[cfg(feature = "codec")]
+    #[test]
+    fn test_tokio_codec() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        for frame in frames() {
+            Encoder::encode(&mut codec, frame, &mut buf).unwrap();
+        }
+        let mut tail = buf.split_off(30);
+
+        let first = Decoder::decode(&mut codec, &mut buf).unwrap().unwrap();
+        assert_eq!(first.arbitration_id(), 0x1234567);
+        assert!(Decoder::decode(&mut codec, &mut buf).unwrap().is_none());
+        buf.unsplit(tail.split());
+        let ids: Vec<u32> = std::iter::from_fn(|| Decoder::decode(&mut codec, &mut buf).unwrap())
+            .map(|f| f.arbitration_id())
+            .collect();
+        assert_eq!(ids, [0x7FF, 0x1]);
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,1060 @@
+//! Device configuration profiles
+//!
+//! A `Config` describes how a device is set up: nominal and data bitrates
+//! with their sample points, mode flags, acceptance filters and how error
+//! frames are reported. `GsUsb::apply()` sets a device up from a `Config` and
+//! `GsUsb::current_config()` captures the setup in effect, so a working
+//! configuration can be stored and restored later. A `DeviceProfile` holds
+//! one `Config` per channel of a multi-channel device.
+//!
+//! With the `serde` feature, configurations can be read from and written to
+//! TOML or JSON:
+//!
+//! ```toml
+//! bitrate = 500000
+//! sample_point = 875
+//! data_bitrate = 2000000
+//!
+//! [mode]
+//! hw_timestamp = true
+//!
+//! [[filters]]
+//! id = 0x100
+//! mask = 0x700
+//! ```
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{Config, GsUsb, IdFilter};
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! let mut config = Config::new(250_000);
+//! config.mode.hw_timestamp = true;
+//! config.filters.push(IdFilter::new(0x100, 0x700));
+//! dev.apply(&config)?;
+//! assert_eq!(dev.current_config()?.bitrate, 250_000);
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use crate::bitrate::{Bitrate, SamplePoint};
+use crate::constants::{
+    CAN_EFF_MASK, GS_CAN_FEATURE_FD, GS_CAN_MODE_BERR_REPORTING, GS_CAN_MODE_FD,
+    GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_LISTEN_ONLY, GS_CAN_MODE_LOOP_BACK, GS_CAN_MODE_ONE_SHOT,
+    GS_CAN_MODE_TRIPLE_SAMPLE,
+};
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::id::CanId;
+use crate::structures::{DeviceBitTiming, DeviceCapability};
+use crate::timing::{
+    data_timing_at, default_sample_point, nominal_timing_at, DEFAULT_DATA_SAMPLE_POINT,
+};
+
+/// Complete configuration of a device
+///
+/// In configuration files `bitrate` may also be given in `Bitrate` notation,
+/// e.g. `bitrate = "500k/2M@80%"`, which sets the data bitrate and sample
+/// points as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "ConfigFile"))]
+pub struct Config {
+    /// Nominal (arbitration) bitrate in bits per second
+    pub bitrate: u32,
+    /// Nominal sample point in permille, `timing::default_sample_point()` if
+    /// not set
+    pub sample_point: Option<u32>,
+    /// CAN FD data phase bitrate in bits per second; setting it enables FD
+    pub data_bitrate: Option<u32>,
+    /// Data phase sample point in permille, 75% if not set
+    pub data_sample_point: Option<u32>,
+    /// Whether `read()` returns error frames as `GsUsbError::BusError`
+    pub error_frames_as_errors: bool,
+    /// Operating mode
+    pub mode: ModeConfig,
+    /// Acceptance filters for received frames; all frames pass if empty
+    pub filters: Vec<IdFilter>,
+}
+
+impl Config {
+    /// Configuration for a classic CAN bus at `bitrate`, with default sample
+    /// point, normal mode and no filters
+    pub fn new(bitrate: u32) -> Self {
+        Self {
+            bitrate,
+            sample_point: None,
+            data_bitrate: None,
+            data_sample_point: None,
+            error_frames_as_errors: false,
+            mode: ModeConfig::default(),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Bitrates and sample points in `Bitrate` form
+    pub fn bitrates(&self) -> Bitrate {
+        Bitrate {
+            nominal: self.bitrate,
+            sample_point: self.sample_point,
+            data: self.data_bitrate,
+            data_sample_point: self.data_sample_point,
+        }
+    }
+
+    /// Set bitrates and sample points; those `bitrate` leaves open are reset
+    pub fn set_bitrates(&mut self, bitrate: Bitrate) {
+        self.bitrate = bitrate.nominal;
+        self.sample_point = bitrate.sample_point;
+        self.data_bitrate = bitrate.data;
+        self.data_sample_point = bitrate.data_sample_point;
+    }
+
+    /// Mode flags for `GsUsb::start()`, including `GS_CAN_MODE_FD` with a
+    /// data bitrate
+    ///
+    /// Fails if the device lacks a requested feature.
+    pub fn start_flags(&self, capability: &DeviceCapability) -> Result<u32> {
+        if let Some(e) = self.feature_violations(capability).into_iter().next() {
+            return Err(e);
+        }
+        let fd = if self.data_bitrate.is_some() {
+            GS_CAN_MODE_FD
+        } else {
+            0
+        };
+        Ok(self.mode.flags() | fd)
+    }
+
+    /// Nominal and data phase bit timing for a device
+    ///
+    /// Fails if a bitrate can't be reached with the device clock and limits,
+    /// or only more than 1% away from the configured sample point.
+    pub fn bit_timings(
+        &self,
+        capability: &DeviceCapability,
+    ) -> Result<(DeviceBitTiming, Option<DeviceBitTiming>)> {
+        let timing = self.nominal_timing(capability)?;
+        let data_timing = self.data_timing(capability).transpose()?;
+        Ok((timing, data_timing))
+    }
+
+    fn nominal_timing(&self, capability: &DeviceCapability) -> Result<DeviceBitTiming> {
+        let sample_point = match self.sample_point {
+            Some(permille) => SamplePoint::try_from(permille)?,
+            None => default_sample_point(self.bitrate),
+        };
+        let timing = nominal_timing_at(capability, self.bitrate, sample_point).ok_or(
+            GsUsbError::UnsupportedBitrate {
+                bitrate: self.bitrate,
+                clock_hz: capability.fclk_can,
+            },
+        )?;
+        check_sample_point("sample_point", self.sample_point, &timing)?;
+        Ok(timing)
+    }
+
+    fn data_timing(&self, capability: &DeviceCapability) -> Option<Result<DeviceBitTiming>> {
+        let bitrate = self.data_bitrate?;
+        let sample_point = match self.data_sample_point.map(SamplePoint::try_from) {
+            Some(Ok(sample_point)) => sample_point,
+            Some(Err(e)) => return Some(Err(e)),
+            None => DEFAULT_DATA_SAMPLE_POINT,
+        };
+        let timing = data_timing_at(capability, bitrate, sample_point).ok_or(
+            GsUsbError::UnsupportedDataBitrate {
+                bitrate,
+                clock_hz: capability.fclk_can,
+            },
+        );
+        Some(timing.and_then(|timing| {
+            check_sample_point("data_sample_point", self.data_sample_point, &timing)?;
+            Ok(timing)
+        }))
+    }
+
+    /// Check the configuration against a device capability, without
+    /// touching the hardware
+    ///
+    /// A single problem is returned as is, several together as
+    /// `GsUsbError::InvalidConfigs`; see `violations()`.
+    pub fn validate(&self, capability: &DeviceCapability) -> Result<()> {
+        check(self.violations(capability))
+    }
+
+    /// All problems of the configuration for a device
+    ///
+    /// Reports values out of range, features the device lacks, and bitrates
+    /// and sample points it can't reach. Bitrates are only checked once all
+    /// values are in range.
+    pub fn violations(&self, capability: &DeviceCapability) -> Vec<GsUsbError> {
+        let mut violations = self.value_violations();
+        let values_valid = violations.is_empty();
+        violations.extend(self.feature_violations(capability));
+        if values_valid {
+            violations.extend(self.nominal_timing(capability).err());
+            violations.extend(self.data_timing(capability).and_then(Result::err));
+        }
+        violations
+    }
+
+    /// Values no device can use
+    fn value_violations(&self) -> Vec<GsUsbError> {
+        let mut violations = Vec::new();
+        let mut invalid = |reason: String| violations.push(GsUsbError::InvalidConfig(reason));
+        if self.bitrate == 0 {
+            invalid("bitrate must not be zero".into());
+        }
+        if self.data_bitrate == Some(0) {
+            invalid("data_bitrate must not be zero".into());
+        }
+        if self.data_sample_point.is_some() && self.data_bitrate.is_none() {
+            invalid("data_sample_point requires data_bitrate".into());
+        }
+        for (name, point) in [
+            ("sample_point", self.sample_point),
+            ("data_sample_point", self.data_sample_point),
+        ] {
+            if let Some(point) = point.filter(|point| !(1..1000).contains(point)) {
+                invalid(format!("{name} {point} is not between 1 and 999 permille"));
+            }
+        }
+        violations
+    }
+
+    /// Requested features the device lacks
+    fn feature_violations(&self, capability: &DeviceCapability) -> Vec<GsUsbError> {
+        let mut violations = Vec::new();
+        if self.data_bitrate.is_some() && capability.feature & GS_CAN_FEATURE_FD == 0 {
+            violations.push(GsUsbError::FdNotSupported);
+        }
+        for (requested, flag, name) in [
+            (
+                self.mode.listen_only,
+                GS_CAN_MODE_LISTEN_ONLY,
+                "listen-only",
+            ),
+            (self.mode.loopback, GS_CAN_MODE_LOOP_BACK, "loopback"),
+            (self.mode.one_shot, GS_CAN_MODE_ONE_SHOT, "one-shot"),
+            (
+                self.mode.hw_timestamp,
+                GS_CAN_MODE_HW_TIMESTAMP,
+                "hardware timestamps",
+            ),
+            (
+                self.mode.berr_reporting,
+                GS_CAN_MODE_BERR_REPORTING,
+                "bus error reporting",
+            ),
+            (
+                self.mode.triple_sample,
+                GS_CAN_MODE_TRIPLE_SAMPLE,
+                "triple sampling",
+            ),
+        ] {
+            // Mode flags share their bit with the feature flag
+            if requested && capability.feature & flag == 0 {
+                violations.push(GsUsbError::FeatureNotSupported(name));
+            }
+        }
+        violations
+    }
+}
+
+/// Sample point deviation `Config` accepts, in permille
+const MAX_SAMPLE_POINT_ERROR: u32 = 10;
+
+/// Check that `timing` is close enough to a requested sample point
+/// A single violation as is, several as `GsUsbError::InvalidConfigs`
+fn check(mut violations: Vec<GsUsbError>) -> Result<()> {
+    match violations.len() {
+        0 => Ok(()),
+        1 => Err(violations.remove(0)),
+        _ => Err(GsUsbError::InvalidConfigs(violations)),
+    }
+}
+
+fn check_sample_point(name: &str, requested: Option<u32>, timing: &DeviceBitTiming) -> Result<()> {
+    match requested {
+        Some(requested) if timing.sample_point().abs_diff(requested) > MAX_SAMPLE_POINT_ERROR => {
+            Err(GsUsbError::InvalidConfig(format!(
+                "{name} {requested} can't be reached, closest is {} permille",
+                timing.sample_point()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Config {
+    /// Parse a configuration from TOML
+    pub fn from_toml(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(|e| GsUsbError::InvalidConfig(e.to_string()))
+    }
+
+    /// Serialize the configuration as TOML
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| GsUsbError::InvalidConfig(e.to_string()))
+    }
+
+    /// Parse a configuration from JSON
+    pub fn from_json(text: &str) -> Result<Self> {
+        serde_json::from_str(text).map_err(|e| GsUsbError::InvalidConfig(e.to_string()))
+    }
+
+    /// Serialize the configuration as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| GsUsbError::InvalidConfig(e.to_string()))
+    }
+
+    /// Load a configuration file, JSON if the extension is `.json`, TOML
+    /// otherwise
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        if is_json(path) {
+            Self::from_json(&text)
+        } else {
+            Self::from_toml(&text)
+        }
+    }
+
+    /// Save the configuration, as JSON if the extension is `.json`, TOML
+    /// otherwise
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            self.to_json()?
+        } else {
+            self.to_toml()?
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// On-disk form of `Config`, with `bitrate` as number or `Bitrate` text
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    bitrate: BitrateField,
+    #[serde(default)]
+    sample_point: Option<u32>,
+    #[serde(default)]
+    data_bitrate: Option<u32>,
+    #[serde(default)]
+    data_sample_point: Option<u32>,
+    #[serde(default)]
+    error_frames_as_errors: bool,
+    #[serde(default)]
+    mode: ModeConfig,
+    #[serde(default)]
+    filters: Vec<IdFilter>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum BitrateField {
+    Rate(u32),
+    Text(Bitrate),
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ConfigFile> for Config {
+    type Error = String;
+
+    fn try_from(file: ConfigFile) -> std::result::Result<Self, String> {
+        let bitrate = match file.bitrate {
+            BitrateField::Rate(rate) => Bitrate::new(rate),
+            BitrateField::Text(bitrate) => bitrate,
+        };
+        fn merge(
+            name: &str,
+            a: Option<u32>,
+            b: Option<u32>,
+        ) -> std::result::Result<Option<u32>, String> {
+            match (a, b) {
+                (Some(_), Some(_)) => Err(format!("{name} is given twice")),
+                (a, b) => Ok(a.or(b)),
+            }
+        }
+        Ok(Self {
+            bitrate: bitrate.nominal,
+            sample_point: merge("sample_point", bitrate.sample_point, file.sample_point)?,
+            data_bitrate: merge("data_bitrate", bitrate.data, file.data_bitrate)?,
+            data_sample_point: merge(
+                "data_sample_point",
+                bitrate.data_sample_point,
+                file.data_sample_point,
+            )?,
+            error_frames_as_errors: file.error_frames_as_errors,
+            mode: file.mode,
+            filters: file.filters,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+fn is_json(path: &std::path::Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Independent configurations for the channels of a multi-channel device
+///
+/// `GsUsb::apply_profile()` sets up channel `n` from `channels[n]`, each with
+/// its own bitrates, mode and filters. Hardware timestamps and CAN FD change
+/// the layout of USB frames for the whole device, so they, like
+/// `error_frames_as_errors`, must be the same on all channels.
+///
+/// Profile files list the channels in order:
+///
+/// ```toml
+/// [[channels]]
+/// bitrate = "500k/2M"
+///
+/// [[channels]]
+/// bitrate = "250k/1M"
+/// mode = { listen_only = true }
+/// ```
+///
+/// A plain `Config` file loads as a profile for channel 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct DeviceProfile {
+    /// Configuration of each channel, by channel number
+    pub channels: Vec<Config>,
+}
+
+impl DeviceProfile {
+    /// Profile with the given channel configurations
+    pub fn new(channels: Vec<Config>) -> Self {
+        Self { channels }
+    }
+
+    /// Check the profile against a device with `channel_count` channels,
+    /// without touching the hardware
+    ///
+    /// Like `Config::validate()`; problems of a channel are prefixed with its
+    /// number when there are several channels.
+    pub fn validate(&self, capability: &DeviceCapability, channel_count: u8) -> Result<()> {
+        check(self.violations(capability, channel_count))
+    }
+
+    /// All problems of the profile for a device
+    pub fn violations(&self, capability: &DeviceCapability, channel_count: u8) -> Vec<GsUsbError> {
+        let Some(first) = self.channels.first() else {
+            return vec![GsUsbError::InvalidConfig("no channels configured".into())];
+        };
+        let mut violations = Vec::new();
+        if self.channels.len() > usize::from(channel_count) {
+            violations.push(GsUsbError::InvalidConfig(format!(
+                "{} channels configured, the device has {channel_count}",
+                self.channels.len()
+            )));
+        }
+        if self.channels.len() == 1 {
+            violations.extend(first.violations(capability));
+            return violations;
+        }
+        for (channel, config) in self.channels.iter().enumerate() {
+            violations.extend(
+                config
+                    .violations(capability)
+                    .into_iter()
+                    .map(|e| GsUsbError::InvalidConfig(format!("channel {channel}: {e}"))),
+            );
+        }
+        let differs = |setting: fn(&Config) -> bool| {
+            self.channels
+                .iter()
+                .any(|config| setting(config) != setting(first))
+        };
+        if differs(|config| config.mode.hw_timestamp) {
+            violations.push(GsUsbError::InvalidConfig(
+                "hw_timestamp differs between channels".into(),
+            ));
+        }
+        if differs(|config| config.data_bitrate.is_some()) {
+            violations.push(GsUsbError::InvalidConfig(
+                "data_bitrate must be set on all channels or none".into(),
+            ));
+        }
+        if differs(|config| config.error_frames_as_errors) {
+            violations.push(GsUsbError::InvalidConfig(
+                "error_frames_as_errors differs between channels".into(),
+            ));
+        }
+        violations
+    }
+}
+
+impl From<Config> for DeviceProfile {
+    fn from(config: Config) -> Self {
+        Self::new(vec![config])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl DeviceProfile {
+    /// Parse a profile, or a single `Config` for channel 0, from TOML
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let invalid = |e: toml::de::Error| GsUsbError::InvalidConfig(e.to_string());
+        let table: toml::Table = toml::from_str(text).map_err(invalid)?;
+        if table.contains_key("channels") {
+            table.try_into().map_err(invalid)
+        } else {
+            table.try_into().map(Config::into).map_err(invalid)
+        }
+    }
+
+    /// Serialize the profile as TOML
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| GsUsbError::InvalidConfig(e.to_string()))
+    }
+
+    /// Parse a profile, or a single `Config` for channel 0, from JSON
+    pub fn from_json(text: &str) -> Result<Self> {
+        let invalid = |e: serde_json::Error| GsUsbError::InvalidConfig(e.to_string());
+        let value: serde_json::Value = serde_json::from_str(text).map_err(invalid)?;
+        if value.get("channels").is_some() {
+            serde_json::from_value(value).map_err(invalid)
+        } else {
+            serde_json::from_value(value)
+                .map(Config::into)
+                .map_err(invalid)
+        }
+    }
+
+    /// Serialize the profile as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| GsUsbError::InvalidConfig(e.to_string()))
+    }
+
+    /// Load a profile file, JSON if the extension is `.json`, TOML otherwise
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        if is_json(path) {
+            Self::from_json(&text)
+        } else {
+            Self::from_toml(&text)
+        }
+    }
+
+    /// Save the profile, as JSON if the extension is `.json`, TOML otherwise
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            self.to_json()?
+        } else {
+            self.to_toml()?
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Mode flags of a `Config`
+///
+/// CAN FD is not listed here; it is enabled by setting `Config::data_bitrate`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ModeConfig {
+    /// Only listen, never acknowledge or transmit (`GS_CAN_MODE_LISTEN_ONLY`)
+    pub listen_only: bool,
+    /// Receive own frames without an ACK (`GS_CAN_MODE_LOOP_BACK`), kept off
+    /// the bus with `listen_only`, see `Loopback`
+    pub loopback: bool,
+    /// Don't retransmit frames that failed (`GS_CAN_MODE_ONE_SHOT`)
+    pub one_shot: bool,
+    /// Timestamp frames in hardware (`GS_CAN_MODE_HW_TIMESTAMP`)
+    pub hw_timestamp: bool,
+    /// Report every bus error as an error frame (`GS_CAN_MODE_BERR_REPORTING`)
+    pub berr_reporting: bool,
+    /// Sample each bit three times, for noisy low-bitrate buses
+    /// (`GS_CAN_MODE_TRIPLE_SAMPLE`)
+    pub triple_sample: bool,
+}
+
+impl ModeConfig {
+    /// Combination of `GS_CAN_MODE_*` flags for `GsUsb::start()`
+    pub fn flags(&self) -> u32 {
+        [
+            (self.listen_only, GS_CAN_MODE_LISTEN_ONLY),
+            (self.loopback, GS_CAN_MODE_LOOP_BACK),
+            (self.one_shot, GS_CAN_MODE_ONE_SHOT),
+            (self.hw_timestamp, GS_CAN_MODE_HW_TIMESTAMP),
+            (self.berr_reporting, GS_CAN_MODE_BERR_REPORTING),
+            (self.triple_sample, GS_CAN_MODE_TRIPLE_SAMPLE),
+        ]
+        .into_iter()
+        .filter(|&(set, _)| set)
+        .fold(0, |flags, (_, flag)| flags | flag)
+    }
+
+    /// Mode from `GS_CAN_MODE_*` flags, ignoring flags not listed here
+    pub fn from_flags(flags: u32) -> Self {
+        Self {
+            listen_only: flags & GS_CAN_MODE_LISTEN_ONLY != 0,
+            loopback: flags & GS_CAN_MODE_LOOP_BACK != 0,
+            one_shot: flags & GS_CAN_MODE_ONE_SHOT != 0,
+            hw_timestamp: flags & GS_CAN_MODE_HW_TIMESTAMP != 0,
+            berr_reporting: flags & GS_CAN_MODE_BERR_REPORTING != 0,
+            triple_sample: flags & GS_CAN_MODE_TRIPLE_SAMPLE != 0,
+        }
+    }
+}
+
+/// Acceptance filter for received frames
+///
+/// A frame matches if it has the filter's ID format and its identifier agrees
+/// with `id` in all bits set in `mask`. The device has no hardware filters,
+/// so `GsUsb::read()` applies them to received data frames; echoes and error
+/// frames always pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct IdFilter {
+    /// Identifier to compare with
+    pub id: u32,
+    /// Identifier bits that have to match
+    pub mask: u32,
+    /// Whether the filter is for extended (29 bit) identifiers
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extended: bool,
+}
+
+impl IdFilter {
+    /// Filter for standard identifiers
+    pub fn new(id: u32, mask: u32) -> Self {
+        Self {
+            id,
+            mask,
+            extended: false,
+        }
+    }
+
+    /// Filter for extended identifiers
+    pub fn extended(id: u32, mask: u32) -> Self {
+        Self {
+            id,
+            mask,
+            extended: true,
+        }
+    }
+
+    /// Filter passing exactly one identifier
+    pub fn exact(id: u32, extended: bool) -> Self {
+        Self {
+            id,
+            mask: CAN_EFF_MASK,
+            extended,
+        }
+    }
+
+    /// Check if a frame matches the filter
+    pub fn matches(&self, frame: &GsUsbFrame) -> bool {
+        frame.is_extended_id() == self.extended
+            && (frame.arbitration_id() ^ self.id) & self.mask == 0
+    }
+}
+
+impl std::str::FromStr for IdFilter {
+    type Err = GsUsbError;
+
+    /// Parse a candump style filter, `ID:MASK`, or just `ID` for one identifier
+    ///
+    /// The ID is read as a `CanId`, which decides if the filter is extended;
+    /// the mask is hex.
+    fn from_str(text: &str) -> Result<Self> {
+        let (id, mask) = match text.trim().split_once(':') {
+            Some((id, mask)) => (id, Some(mask)),
+            None => (text.trim(), None),
+        };
+        let id: CanId = id.parse()?;
+        let Some(mask) = mask else {
+            return Ok(Self::exact(id.as_raw(), id.is_extended()));
+        };
+        let digits = mask
+            .strip_prefix("0x")
+            .or_else(|| mask.strip_prefix("0X"))
+            .unwrap_or(mask);
+        let mask = u32::from_str_radix(digits, 16)
+            .map_err(|_| GsUsbError::InvalidConfig(format!("invalid filter mask \"{mask}\"")))?;
+        Ok(Self {
+            id: id.as_raw(),
+            mask,
+            extended: id.is_extended(),
+        })
+    }
+}
+
+impl std::fmt::Display for IdFilter {
+    /// `ID:MASK` as candump takes it, or just `ID` for an exact filter
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = if self.extended { 8 } else { 3 };
+        write!(f, "{:0width$X}", self.id)?;
+        if self.mask != CAN_EFF_MASK {
+            write!(f, ":{:0width$X}", self.mask)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check if `read()` hands out a frame under the given filters
+pub(crate) fn passes_filters(filters: &[IdFilter], frame: &GsUsbFrame) -> bool {
+    filters.is_empty()
+        || !frame.is_rx_frame()
+        || frame.is_error_frame()
+        || filters.iter().any(|filter| filter.matches(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_FEATURE_ONE_SHOT;
+    use crate::constants::{
+        CAN_EFF_FLAG, GS_USB_BREQ_BITTIMING, GS_USB_BREQ_DATA_BITTIMING, GS_USB_BREQ_DEVICE_CONFIG,
+        GS_USB_BREQ_HOST_FORMAT, GS_USB_BREQ_MODE,
+    };
+    use crate::device::GsUsb;
+    use crate::mock::MockGsUsb;
+    use crate::structures::DeviceInfo;
+    use crate::virtual_bus::VirtualBus;
+    use std::time::Duration;
+
+    #[test]
+    fn test_filter_text() {
+        let filter: IdFilter = "0x100:700".parse().unwrap();
+        assert_eq!(filter, IdFilter::new(0x100, 0x700));
+        assert_eq!(
+            "18FF50E5:1FFFF00".parse::<IdFilter>().unwrap(),
+            IdFilter::extended(0x18FF50E5, 0x1FFFF00)
+        );
+        assert_eq!(
+            "123x".parse::<IdFilter>().unwrap(),
+            IdFilter::exact(0x123, true)
+        );
+        assert!("123:zz".parse::<IdFilter>().is_err());
+        assert!(":7FF".parse::<IdFilter>().is_err());
+
+        for filter in [
+            IdFilter::new(0x100, 0x700),
+            IdFilter::extended(0x100, 0x1FFFFF00),
+            IdFilter::exact(0x7FF, false),
+            IdFilter::exact(0x12, true),
+        ] {
+            assert_eq!(filter.to_string().parse::<IdFilter>().unwrap(), filter);
+        }
+        assert_eq!(IdFilter::new(0x100, 0x700).to_string(), "100:700");
+        assert_eq!(IdFilter::exact(0x12, true).to_string(), "00000012");
+    }
+
+    #[test]
+    fn test_filters() {
+        let filter = IdFilter::new(0x100, 0x700);
+        assert!(filter.matches(&GsUsbFrame::with_data(0x1AB, &[])));
+        assert!(!filter.matches(&GsUsbFrame::with_data(0x200, &[])));
+        assert!(!filter.matches(&GsUsbFrame::with_data(0x100 | CAN_EFF_FLAG, &[])));
+        assert!(
+            IdFilter::exact(0x100, true).matches(&GsUsbFrame::with_data(0x100 | CAN_EFF_FLAG, &[]))
+        );
+        assert!(passes_filters(&[], &GsUsbFrame::with_data(0x200, &[])));
+    }
+
+    #[test]
+    fn test_validate() {
+        let capability = VirtualBus::default_capability();
+        assert!(Config::new(500_000).validate(&capability).is_ok());
+        assert!(Config::new(0).validate(&capability).is_err());
+
+        let mut config = Config::new(500_000);
+        config.sample_point = Some(1000);
+        assert!(config.validate(&capability).is_err());
+
+        let mut config = Config::new(500_000);
+        config.data_sample_point = Some(800);
+        assert!(config.validate(&capability).is_err());
+        config.data_bitrate = Some(2_000_000);
+        assert!(config.validate(&capability).is_ok());
+
+        // Everything wrong is reported at once
+        let mut classic = capability;
+        classic.feature &= !(GS_CAN_FEATURE_FD | GS_CAN_FEATURE_ONE_SHOT);
+        config.bitrate = 3;
+        config.mode.one_shot = true;
+        let violations = config.violations(&classic);
+        assert!(matches!(
+            violations[..],
+            [
+                GsUsbError::FdNotSupported,
+                GsUsbError::FeatureNotSupported("one-shot"),
+                GsUsbError::UnsupportedBitrate { bitrate: 3, .. },
+            ]
+        ));
+        assert!(matches!(
+            config.validate(&classic),
+            Err(GsUsbError::InvalidConfigs(v)) if v.len() == 3
+        ));
+
+        // 1 Mbit/s on 40 MHz allows 40 time quanta, so 81.2% is 80% at best
+        let mut config = Config::new(1_000_000);
+        config.sample_point = Some(812);
+        assert!(config.validate(&capability).is_err());
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_apply_and_current_config() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+
+        let mut config = Config::new(250_000);
+        config.sample_point = Some(800);
+        config.data_bitrate = Some(2_000_000);
+        config.data_sample_point = Some(800);
+        config.mode.hw_timestamp = true;
+        config.filters.push(IdFilter::new(0x100, 0x7FF));
+        dev.apply(&config).unwrap();
+        assert!(mock.is_started());
+        assert_eq!(mock.mode_flags(), GS_CAN_MODE_HW_TIMESTAMP | GS_CAN_MODE_FD);
+        assert_eq!(dev.current_config().unwrap(), config);
+
+        mock.push_rx(&GsUsbFrame::with_data(0x200, &[1]));
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[2]));
+        assert_eq!(
+            dev.read(Duration::from_millis(10))
+                .unwrap()
+                .arbitration_id(),
+            0x100
+        );
+        mock.push_rx(&GsUsbFrame::with_data(0x200, &[3]));
+        assert!(dev
+            .read(Duration::from_millis(10))
+            .unwrap_err()
+            .is_timeout());
+
+        // Applying again restarts the device with the new setup
+        dev.apply(&Config::new(500_000)).unwrap();
+        assert_eq!(mock.mode_flags(), 0);
+        assert_eq!(dev.current_config().unwrap().sample_point, Some(875));
+        assert!(dev.filters().is_empty());
+    }
+
+    #[test]
+    fn test_configure_is_all_or_nothing() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+
+        // Rejected before any request is sent
+        let mut config = Config::new(500_000);
+        config.data_bitrate = Some(3);
+        assert!(matches!(
+            dev.configure(&config),
+            Err(GsUsbError::UnsupportedDataBitrate { bitrate: 3, .. })
+        ));
+        assert!(mock.control_writes().is_empty());
+
+        // Failing midway leaves the channel stopped
+        config.data_bitrate = Some(2_000_000);
+        mock.set_error(GS_USB_BREQ_DATA_BITTIMING, rusb::Error::Pipe);
+        assert!(dev.configure(&config).is_err());
+        assert!(!mock.is_started());
+        assert!(!mock.is_claimed());
+
+        mock.clear_response(GS_USB_BREQ_DATA_BITTIMING);
+        dev.configure(&config).unwrap();
+        let requests: Vec<u8> = mock.control_writes().iter().map(|w| w.request).collect();
+        assert_eq!(
+            requests[requests.len() - 4..],
+            [
+                GS_USB_BREQ_HOST_FORMAT,
+                GS_USB_BREQ_BITTIMING,
+                GS_USB_BREQ_DATA_BITTIMING,
+                GS_USB_BREQ_MODE
+            ]
+        );
+        assert!(matches!(
+            dev.configure(&config),
+            Err(GsUsbError::AlreadyStarted)
+        ));
+    }
+
+    #[test]
+    fn test_reconfigure_in_place() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.reconfigure(&Config::new(500_000)).unwrap();
+        assert!(mock.is_started());
+
+        let before = mock.control_writes().len();
+        let mut config = Config::new(250_000);
+        config.mode.listen_only = true;
+        dev.reconfigure(&config).unwrap();
+        let requests: Vec<u8> = mock.control_writes()[before..]
+            .iter()
+            .map(|w| w.request)
+            .collect();
+        assert_eq!(
+            requests,
+            [GS_USB_BREQ_MODE, GS_USB_BREQ_BITTIMING, GS_USB_BREQ_MODE]
+        );
+        assert!(mock.is_claimed());
+        assert_eq!(mock.mode_flags(), GS_CAN_MODE_LISTEN_ONLY);
+        assert_eq!(dev.current_config().unwrap().bitrate, 250_000);
+
+        mock.set_error(GS_USB_BREQ_BITTIMING, rusb::Error::Pipe);
+        assert!(dev.reconfigure(&Config::new(125_000)).is_err());
+        assert!(!mock.is_started());
+        assert!(!mock.is_claimed());
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let mock = MockGsUsb::new();
+        let info = DeviceInfo {
+            reserved1: 0,
+            reserved2: 0,
+            reserved3: 0,
+            icount: 1,
+            fw_version: 20,
+            hw_version: 10,
+        };
+        mock.set_response(GS_USB_BREQ_DEVICE_CONFIG, info.pack());
+        let mut dev = mock.open();
+
+        let mut second = Config::new(250_000);
+        second.mode.listen_only = true;
+        second.filters.push(IdFilter::exact(0x200, false));
+        let mut profile = DeviceProfile::new(vec![Config::new(500_000), second]);
+        dev.apply_profile(&profile).unwrap();
+
+        let writes: Vec<(u8, u16)> = mock
+            .control_writes()
+            .iter()
+            .map(|w| (w.request, w.value))
+            .collect();
+        assert_eq!(
+            writes[writes.len() - 4..],
+            [
+                (GS_USB_BREQ_BITTIMING, 0),
+                (GS_USB_BREQ_MODE, 0),
+                (GS_USB_BREQ_BITTIMING, 1),
+                (GS_USB_BREQ_MODE, 1)
+            ]
+        );
+        assert_eq!(mock.mode_flags(), GS_CAN_MODE_LISTEN_ONLY);
+        assert_eq!(dev.active_mode().unwrap().flags, 0);
+        assert_eq!(dev.current_config().unwrap().bitrate, 500_000);
+
+        // Filters only apply to their channel
+        for (channel, id) in [(1, 0x100), (0, 0x100), (1, 0x200)] {
+            let mut frame = GsUsbFrame::with_data(id, &[]);
+            frame.channel = channel;
+            mock.push_rx(&frame);
+        }
+        let read = |dev: &mut GsUsb| dev.read(Duration::from_millis(10)).unwrap();
+        assert_eq!(read(&mut dev).channel, 0);
+        assert_eq!(read(&mut dev).arbitration_id(), 0x200);
+
+        // Changing channel 0 alone would drop the other channels' setup
+        let before = mock.control_writes().len();
+        assert!(matches!(
+            dev.reconfigure(&Config::new(125_000)),
+            Err(GsUsbError::InvalidConfig(_))
+        ));
+        assert_eq!(mock.control_writes().len(), before);
+        assert!(mock.is_started());
+        assert_eq!(dev.channel_filters(1), [IdFilter::exact(0x200, false)]);
+
+        let stops_before = mock.control_writes().len();
+        dev.stop().unwrap();
+        assert_eq!(mock.control_writes()[stops_before..].len(), 2);
+
+        // Checked as a whole before anything is sent
+        profile.channels[1].mode.hw_timestamp = true;
+        profile.channels.push(Config::new(3));
+        let before = mock.control_writes().len();
+        match dev.apply_profile(&profile) {
+            Err(GsUsbError::InvalidConfigs(violations)) => assert_eq!(violations.len(), 3),
+            other => panic!("expected violations, got {other:?}"),
+        }
+        assert_eq!(mock.control_writes().len(), before);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_toml_and_json() {
+        let config = Config::from_toml(
+            "bitrate = 500000\n\
+             data_bitrate = 2000000\n\
+             [mode]\n\
+             hw_timestamp = true\n\
+             [[filters]]\n\
+             id = 0x100\n\
+             mask = 0x700\n",
+        )
+        .unwrap();
+        assert_eq!(config.data_bitrate, Some(2_000_000));
+        assert!(config.mode.hw_timestamp);
+        assert_eq!(config.filters, [IdFilter::new(0x100, 0x700)]);
+
+        assert_eq!(
+            Config::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+        assert_eq!(
+            Config::from_json(&config.to_json().unwrap()).unwrap(),
+            config
+        );
+        assert!(Config::from_toml("bitrate = 500000\nbitrat = 1").is_err());
+
+        let config = Config::from_toml("bitrate = \"500k/2M@80%\"").unwrap();
+        assert_eq!(config.bitrates(), "500k/2M@80%".parse().unwrap());
+        assert!(Config::from_toml("bitrate = \"500k@80%\"\nsample_point = 875").is_err());
+
+        let path = std::env::temp_dir().join(format!("gs_usb_config_{}.json", std::process::id()));
+        config.save(&path).unwrap();
+        let loaded = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), config);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_profile_files() {
+        let profile = DeviceProfile::from_toml(
+            "[[channels]]\n\
+             bitrate = \"500k/2M\"\n\
+             [[channels]]\n\
+             bitrate = \"250k/1M\"\n\
+             mode = { listen_only = true }\n",
+        )
+        .unwrap();
+        assert_eq!(profile.channels.len(), 2);
+        assert_eq!(profile.channels[1].data_bitrate, Some(1_000_000));
+        assert!(profile.channels[1].mode.listen_only);
+        assert_eq!(
+            DeviceProfile::from_toml(&profile.to_toml().unwrap()).unwrap(),
+            profile
+        );
+        assert_eq!(
+            DeviceProfile::from_json(&profile.to_json().unwrap()).unwrap(),
+            profile
+        );
+
+        let single = DeviceProfile::from_toml("bitrate = 125000").unwrap();
+        assert_eq!(single, DeviceProfile::from(Config::new(125_000)));
+        assert_eq!(
+            DeviceProfile::from_json("{\"bitrate\": 125000}").unwrap(),
+            single
+        );
+        assert!(DeviceProfile::from_toml("[[channels]]\nbitrat = 1").is_err());
+    }
+}
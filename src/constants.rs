@@ -11,7 +11,11 @@
 pub const GS_CAN_MODE_NORMAL: u32 = 0;
 /// Listen-only mode (no ACKs sent)
 pub const GS_CAN_MODE_LISTEN_ONLY: u32 = 1 << 0;
-/// Loopback mode (for testing)
+/// Loopback mode: sent frames are received back and need no ACK
+///
+/// Alone, the frames are also driven onto the bus by most controllers
+/// (external loopback); with `GS_CAN_MODE_LISTEN_ONLY` they stay inside the
+/// controller (internal loopback). See `mode::Loopback`.
 pub const GS_CAN_MODE_LOOP_BACK: u32 = 1 << 1;
 /// Triple sample mode
 pub const GS_CAN_MODE_TRIPLE_SAMPLE: u32 = 1 << 2;
@@ -90,6 +94,34 @@ pub const CAN_SFF_ID_BITS: u8 = 11;
 /// Number of bits in extended frame ID
 pub const CAN_EFF_ID_BITS: u8 = 29;
 
+// ============================================================================
+// CAN Error Frame Classes (in error frame identifier, SocketCAN compatible)
+// ============================================================================
+
+/// TX timeout (by netdevice driver)
+pub const CAN_ERR_TX_TIMEOUT: u32 = 0x0000_0001;
+/// Lost arbitration
+pub const CAN_ERR_LOSTARB: u32 = 0x0000_0002;
+/// Controller problems
+pub const CAN_ERR_CRTL: u32 = 0x0000_0004;
+/// Protocol violations
+pub const CAN_ERR_PROT: u32 = 0x0000_0008;
+/// Transceiver status
+pub const CAN_ERR_TRX: u32 = 0x0000_0010;
+/// Received no ACK on transmission
+pub const CAN_ERR_ACK: u32 = 0x0000_0020;
+/// Bus off
+pub const CAN_ERR_BUSOFF: u32 = 0x0000_0040;
+/// Bus error (may flood!)
+pub const CAN_ERR_BUSERROR: u32 = 0x0000_0080;
+/// Controller restarted
+pub const CAN_ERR_RESTARTED: u32 = 0x0000_0100;
+/// TX error counter in data[6] / RX error counter in data[7]
+pub const CAN_ERR_CNT: u32 = 0x0000_0200;
+
+/// Controller problem detail (data[1] of a `CAN_ERR_CRTL` frame): RX buffer overflow
+pub const CAN_ERR_CRTL_RX_OVERFLOW: u8 = 0x01;
+
 // ============================================================================
 // CAN Payload Definitions
 // ============================================================================
@@ -104,6 +136,13 @@ pub const CANFD_MAX_DLC: u8 = 15;
 /// Maximum data length for CAN FD
 pub const CANFD_MAX_DLEN: usize = 64;
 
+/// Data bytes a `GsUsbFrame` holds: 64, or 8 without the `fd` feature
+pub const GS_USB_FRAME_DATA_LEN: usize = if cfg!(feature = "fd") {
+    CANFD_MAX_DLEN
+} else {
+    CAN_MAX_DLEN
+};
+
 // ============================================================================
 // GS-USB Frame Flags (in gs_host_frame.flags field)
 // ============================================================================
@@ -136,10 +175,19 @@ pub const GS_USB_ECHO_ID: u32 = 0;
 /// Echo ID value for received frames (from CAN bus)
 pub const GS_USB_RX_ECHO_ID: u32 = 0xFFFF_FFFF;
 
+/// Nominal rate of the hardware timestamp counter (1 MHz, microseconds)
+pub const GS_USB_TIMESTAMP_TICK_HZ: u32 = 1_000_000;
+
+/// Mode flags python-can's gs_usb interface starts devices with
+pub const PYTHON_CAN_MODE_FLAGS: u32 = GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP;
+
 // ============================================================================
 // Frame Sizes
 // ============================================================================
 
+/// Frame header size: echo_id, can_id, can_dlc, channel, flags, reserved
+pub const GS_USB_FRAME_HEADER_SIZE: usize = 12;
+
 /// Classic CAN frame size (without timestamp)
 pub const GS_USB_FRAME_SIZE: usize = 20;
 /// Classic CAN frame size (with hardware timestamp)
@@ -204,6 +252,26 @@ pub const GS_USB_ABE_CANDEBUGGER_FD_VENDOR_ID: u16 = 0x16D0;
 /// ABE CANdebugger FD product ID
 pub const GS_USB_ABE_CANDEBUGGER_FD_PRODUCT_ID: u16 = 0x10B8;
 
+/// Vendor ID, product ID and name of every adapter `GsUsb::scan()` finds
+pub const GS_USB_KNOWN_DEVICES: &[(u16, u16, &str)] = &[
+    (GS_USB_ID_VENDOR, GS_USB_ID_PRODUCT, "GS-USB"),
+    (
+        GS_USB_CANDLELIGHT_VENDOR_ID,
+        GS_USB_CANDLELIGHT_PRODUCT_ID,
+        "candleLight",
+    ),
+    (
+        GS_USB_CES_CANEXT_FD_VENDOR_ID,
+        GS_USB_CES_CANEXT_FD_PRODUCT_ID,
+        "CES CANext FD",
+    ),
+    (
+        GS_USB_ABE_CANDEBUGGER_FD_VENDOR_ID,
+        GS_USB_ABE_CANDEBUGGER_FD_PRODUCT_ID,
+        "ABE CANdebugger FD",
+    ),
+];
+
 // ============================================================================
 // GS-USB Control Request Codes
 // ============================================================================
@@ -239,6 +307,66 @@ pub const GS_USB_BREQ_GET_TERMINATION: u8 = 13;
 /// Get CAN state
 pub const GS_USB_BREQ_GET_STATE: u8 = 14;
 
+/// Get human-readable name for a control request code
+pub fn request_name(request: u8) -> &'static str {
+    match request {
+        GS_USB_BREQ_HOST_FORMAT => "HOST_FORMAT",
+        GS_USB_BREQ_BITTIMING => "BITTIMING",
+        GS_USB_BREQ_MODE => "MODE",
+        GS_USB_BREQ_BERR => "BERR",
+        GS_USB_BREQ_BT_CONST => "BT_CONST",
+        GS_USB_BREQ_DEVICE_CONFIG => "DEVICE_CONFIG",
+        GS_USB_BREQ_TIMESTAMP => "TIMESTAMP",
+        GS_USB_BREQ_IDENTIFY => "IDENTIFY",
+        GS_USB_BREQ_GET_USER_ID => "GET_USER_ID",
+        GS_USB_BREQ_SET_USER_ID => "SET_USER_ID",
+        GS_USB_BREQ_DATA_BITTIMING => "DATA_BITTIMING",
+        GS_USB_BREQ_BT_CONST_EXT => "BT_CONST_EXT",
+        GS_USB_BREQ_SET_TERMINATION => "SET_TERMINATION",
+        GS_USB_BREQ_GET_TERMINATION => "GET_TERMINATION",
+        GS_USB_BREQ_GET_STATE => "GET_STATE",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Get human-readable name for a single `GS_CAN_MODE_*` flag
+pub fn mode_flag_name(flag: u32) -> &'static str {
+    match flag {
+        GS_CAN_MODE_LISTEN_ONLY => "LISTEN_ONLY",
+        GS_CAN_MODE_LOOP_BACK => "LOOP_BACK",
+        GS_CAN_MODE_TRIPLE_SAMPLE => "TRIPLE_SAMPLE",
+        GS_CAN_MODE_ONE_SHOT => "ONE_SHOT",
+        GS_CAN_MODE_HW_TIMESTAMP => "HW_TIMESTAMP",
+        GS_CAN_MODE_IDENTIFY => "IDENTIFY",
+        GS_CAN_MODE_USER_ID => "USER_ID",
+        GS_CAN_MODE_PAD_PKTS_TO_MAX_PKT_SIZE => "PAD_PKTS_TO_MAX_PKT_SIZE",
+        GS_CAN_MODE_FD => "FD",
+        GS_CAN_MODE_BERR_REPORTING => "BERR_REPORTING",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Get human-readable name for a single `GS_CAN_FEATURE_*` flag
+pub fn feature_flag_name(flag: u32) -> &'static str {
+    match flag {
+        GS_CAN_FEATURE_LISTEN_ONLY => "LISTEN_ONLY",
+        GS_CAN_FEATURE_LOOP_BACK => "LOOP_BACK",
+        GS_CAN_FEATURE_TRIPLE_SAMPLE => "TRIPLE_SAMPLE",
+        GS_CAN_FEATURE_ONE_SHOT => "ONE_SHOT",
+        GS_CAN_FEATURE_HW_TIMESTAMP => "HW_TIMESTAMP",
+        GS_CAN_FEATURE_IDENTIFY => "IDENTIFY",
+        GS_CAN_FEATURE_USER_ID => "USER_ID",
+        GS_CAN_FEATURE_PAD_PKTS_TO_MAX_PKT_SIZE => "PAD_PKTS_TO_MAX_PKT_SIZE",
+        GS_CAN_FEATURE_FD => "FD",
+        GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX => "REQ_USB_QUIRK_LPC546XX",
+        GS_CAN_FEATURE_BT_CONST_EXT => "BT_CONST_EXT",
+        GS_CAN_FEATURE_TERMINATION => "TERMINATION",
+        GS_CAN_FEATURE_BERR_REPORTING => "BERR_REPORTING",
+        GS_CAN_FEATURE_GET_STATE => "GET_STATE",
+        _ => "UNKNOWN",
+    }
+}
+
 // ============================================================================
 // GS-USB Mode Values
 // ============================================================================
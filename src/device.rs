@@ -3,14 +3,96 @@
 //! This module provides the `GsUsb` struct for interfacing with GS-USB compatible
 //! CAN adapters, including candleLight, CANable, and similar devices.
 
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 use rusb::{DeviceHandle, GlobalContext};
 
+use crate::bitrate::SamplePoint;
+use crate::builder::GsUsbBuilder;
+use crate::cancel::{CancelToken, CANCEL_POLL_INTERVAL};
+use crate::clock::SystemClock;
+use crate::config::{passes_filters, Config, DeviceProfile, IdFilter, ModeConfig};
 use crate::constants::*;
 use crate::error::{GsUsbError, Result};
+use crate::error_frame::{CanErrorFrame, RxOverflow};
 use crate::frame::GsUsbFrame;
+use crate::latency::{LatencyStats, LatencyTracker};
+use crate::mode::{ActiveMode, ModeRequest, DRIVER_MODE_FLAGS};
+use crate::monitor::Monitor;
+use crate::quirks::{self, Quirks};
+use crate::retry::RetryPolicy;
+#[cfg(feature = "instrument")]
+use crate::stats::HotPathStats;
+use crate::stats::TransferStats;
 use crate::structures::{DeviceBitTiming, DeviceCapability, DeviceInfo, DeviceMode, DeviceState};
+use crate::tap::{TapDirection, TapId, TapOptions, Taps};
+use crate::time_sync::{TimeSync, DEFAULT_SAMPLES};
+use crate::timestamp::{snap_tick_hz, TickScaler, TimestampSource};
+use crate::timing::{default_sample_point, DEFAULT_DATA_SAMPLE_POINT};
+use crate::transport::{Detached, Transport, UsbParts, UsbTransport};
+use crate::typed_frame::ClassicFrame;
+#[cfg(feature = "fd")]
+use crate::typed_frame::FdFrame;
+
+/// Bulk packet size assumed when the transport doesn't know the endpoint's
+const FULL_SPEED_BULK_PACKET_SIZE: usize = 64;
+
+/// Most frames `drain()` reads from the device, more than any adapter
+/// buffers, so a drain on a busy bus ends
+const MAX_DRAIN_FRAMES: usize = 1024;
+
+/// Longest control IN response, the extended bit timing constants
+const MAX_CONTROL_IN_LEN: usize = DeviceCapability::EXTENDED_SIZE;
+
+/// How long `read_many()` waits for further frames of a batch
+const BATCH_GAP: Duration = Duration::from_millis(1);
+
+/// Shortest bulk read timeout: libusb counts whole milliseconds and waits
+/// forever for 0, so shorter waits are rounded up to this
+const MIN_TRANSFER_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Pause between claims of a device held elsewhere, see `set_claim_wait()`
+const CLAIM_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Sample point python-can's gs_usb interface uses at every bitrate
+const PYTHON_CAN_SAMPLE_POINT: SamplePoint = SamplePoint::from_permille(875).unwrap();
+
+/// What `read_until()` does with frames that don't match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmatched {
+    /// Drop them
+    Discard,
+    /// Keep them for the next reads, in order
+    Keep,
+}
+
+/// Frames gathered by `read_n()`
+#[derive(Debug, Clone, Default)]
+pub struct ReadBatch {
+    /// Frames in the order read
+    pub frames: Vec<GsUsbFrame>,
+    /// Whether the deadline passed before all frames were read
+    pub timed_out: bool,
+}
+
+/// A received frame kept on the host until a read takes it
+struct Queued {
+    frame: GsUsbFrame,
+    /// When it was kept, for `HotPathStats::queue_wait()`
+    #[cfg(feature = "instrument")]
+    at: Instant,
+}
+
+impl Queued {
+    fn new(frame: GsUsbFrame) -> Self {
+        Self {
+            frame,
+            #[cfg(feature = "instrument")]
+            at: Instant::now(),
+        }
+    }
+}
 
 /// GS-USB device handle
 ///
@@ -51,8 +133,8 @@ use crate::structures::{DeviceBitTiming, DeviceCapability, DeviceInfo, DeviceMod
 /// # Ok::<(), gs_usb::GsUsbError>(())
 /// ```
 pub struct GsUsb {
-    /// USB device handle
-    handle: DeviceHandle<GlobalContext>,
+    /// Transport used to talk to the device (USB or virtual)
+    transport: Box<dyn Transport>,
     /// Cached device capability
     capability: Option<DeviceCapability>,
     /// Current device flags
@@ -61,6 +143,10 @@ pub struct GsUsb {
     fd_mode: bool,
     /// Whether the device has been started
     started: bool,
+    /// Number of channels `stop()` resets, more than one after `apply_profile()`
+    started_channels: u16,
+    /// Requested and effective flags of the last start
+    active_mode: Option<ActiveMode>,
     /// USB bus number
     bus: u8,
     /// USB device address
@@ -71,23 +157,771 @@ pub struct GsUsb {
     last_timing: Option<DeviceBitTiming>,
     /// Last data phase (CAN FD) bit timing that was set
     last_data_timing: Option<DeviceBitTiming>,
+    /// Retry policy for control requests
+    retry: RetryPolicy,
+    /// Whether `stop()` reattaches a kernel driver detached by `start()`
+    reattach_kernel_driver: bool,
+    /// How long starting waits for another process to release the device
+    claim_wait: Duration,
+    /// Whether `read()` returns error frames as `GsUsbError::BusError`
+    error_frames_as_errors: bool,
+    /// Frame held back by `read()` while its overflow is being reported
+    after_overflow: Option<Queued>,
+    /// Frames read but not consumed yet, returned by `read()` first
+    rx_queue: VecDeque<Queued>,
+    /// Buffer of the RX bulk transfers, reused from read to read
+    rx_buf: Vec<u8>,
+    /// Token that cancels reads, once one was handed out
+    cancel: Option<CancelToken>,
+    /// Relation of hardware timestamps to host time, see `sync_time()`
+    time_sync: Option<TimeSync>,
+    /// Time base `read()` attaches to frames
+    timestamp_source: TimestampSource,
+    /// Rescales hardware timestamps of counters not running at 1 MHz
+    ticks: TickScaler,
+    /// How often `read()` adds a sync point to `time_sync`
+    resync_interval: Option<Duration>,
+    /// Echo ID allocation and statistics while TX latency is measured
+    latency: Option<LatencyTracker>,
+    /// Sizes and decoding results of the received bulk transfers
+    transfer_stats: TransferStats,
+    /// Acceptance filters `read()` applies to received frames, by channel
+    filters: Vec<Vec<IdFilter>>,
+    /// Whether to behave like python-can's gs_usb interface
+    python_can_compat: bool,
+    /// Packet size TX transfers are padded to for the LPC546xx USB quirk
+    quirk_packet_size: Option<usize>,
+    /// Firmware workarounds, looked up with the capability
+    quirks: Option<Quirks>,
+    /// Passive observers of the frames received and sent
+    taps: Taps,
+    /// Time spent reading frames, per stage
+    #[cfg(feature = "instrument")]
+    hot_path: HotPathStats,
 }
 
 impl GsUsb {
-    /// Create a new GsUsb from a USB device handle
-    fn new(handle: DeviceHandle<GlobalContext>, bus: u8, address: u8) -> Self {
+    /// Create a new GsUsb from a transport
+    fn new(transport: Box<dyn Transport>, bus: u8, address: u8) -> Self {
         Self {
-            handle,
+            transport,
             capability: None,
             device_flags: 0,
             fd_mode: false,
             started: false,
+            started_channels: 1,
+            active_mode: None,
             bus,
             address,
             serial_number: None,
             last_timing: None,
             last_data_timing: None,
+            retry: RetryPolicy::NONE,
+            reattach_kernel_driver: true,
+            claim_wait: Duration::ZERO,
+            error_frames_as_errors: false,
+            after_overflow: None,
+            rx_queue: VecDeque::new(),
+            rx_buf: Vec::new(),
+            cancel: None,
+            time_sync: None,
+            timestamp_source: TimestampSource::default(),
+            ticks: TickScaler::new(GS_USB_TIMESTAMP_TICK_HZ),
+            resync_interval: None,
+            latency: None,
+            transfer_stats: TransferStats::default(),
+            filters: Vec::new(),
+            python_can_compat: false,
+            quirk_packet_size: None,
+            quirks: None,
+            taps: Taps::default(),
+            #[cfg(feature = "instrument")]
+            hot_path: HotPathStats::default(),
+        }
+    }
+
+    /// Start describing a device to open and configure, see `GsUsbBuilder`
+    pub fn builder() -> GsUsbBuilder {
+        GsUsbBuilder::new()
+    }
+
+    /// Start the device listen-only at `bitrate` for passive monitoring
+    ///
+    /// The device neither sends nor acknowledges frames, and the returned
+    /// `Monitor` can only receive. Hardware timestamps are enabled if the
+    /// device has them. Fails with `FeatureNotSupported` before touching the
+    /// device if it lacks listen-only mode.
+    pub fn monitor(mut self, bitrate: u32) -> Result<Monitor> {
+        let capability = self.device_capability()?;
+        let mut config = Config::new(bitrate);
+        config.mode.listen_only = true;
+        config.mode.hw_timestamp = capability.feature & GS_CAN_FEATURE_HW_TIMESTAMP != 0;
+        self.apply(&config)?;
+        Ok(Monitor::new(self))
+    }
+
+    /// Create a GsUsb that talks to the device through a custom transport
+    ///
+    /// This allows the full device API to be used with non-USB backends such
+    /// as the in-memory `VirtualBus`. Bus and address are reported as given.
+    pub fn from_transport<T: Transport + 'static>(transport: T, bus: u8, address: u8) -> Self {
+        Self::new(Box::new(transport), bus, address)
+    }
+
+    /// Take a device opened from USB apart into its handle and interface state
+    ///
+    /// For vendor requests of custom firmware and other USB operations this
+    /// crate doesn't cover; `from_parts()` makes a `GsUsb` of the handle
+    /// again. The device is left as it is, not stopped. Fails with
+    /// `GsUsbError::FeatureNotSupported` for other transports, including
+    /// wrapped ones (see `wrap_transport()`).
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use gs_usb::GsUsb;
+    /// use std::time::Duration;
+    ///
+    /// let parts = GsUsb::scan()?.remove(0).into_parts()?;
+    /// // Vendor request 0x40 of a custom firmware
+    /// parts
+    ///     .handle
+    ///     .write_control(0x41, 0x40, 1, 0, &[], Duration::from_millis(100))?;
+    /// let dev = GsUsb::from_parts(parts);
+    /// # Ok::<(), gs_usb::GsUsbError>(())
+    /// ```
+    pub fn into_parts(mut self) -> Result<UsbParts> {
+        if self.raw_handle().is_none() {
+            return Err(GsUsbError::FeatureNotSupported("raw USB handle"));
+        }
+        // `Drop` finds nothing left to stop
+        let transport = std::mem::replace(&mut self.transport, Box::new(Detached));
+        transport
+            .into_usb_parts()
+            .ok_or(GsUsbError::FeatureNotSupported("raw USB handle"))
+    }
+
+    /// Make a `GsUsb` of a handle taken apart with `into_parts()`
+    ///
+    /// Cached device state is gone: the device counts as stopped until
+    /// `start()`, which resets it.
+    pub fn from_parts(parts: UsbParts) -> Self {
+        let device = parts.handle.device();
+        let (bus, address) = (device.bus_number(), device.address());
+        Self::new(Box::new(UsbTransport::from_parts(parts)), bus, address)
+    }
+
+    /// Open an adapter from the file descriptor of an Android
+    /// `UsbDeviceConnection`
+    ///
+    /// Android apps can't scan the USB bus: they get permission for the
+    /// device through the platform USB host API, open it with
+    /// `UsbManager.openDevice()` and pass `getFileDescriptor()` of the
+    /// connection here. On Android, libusb's device discovery is turned off
+    /// before its first use, as the app may not enumerate devices itself;
+    /// `scan()` and `find()` then find nothing. On Linux, any usbfs file
+    /// descriptor of an adapter works.
+    ///
+    /// The device is not checked against the known adapters, so adapters
+    /// with custom IDs work as well.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor of the device, and stay open
+    /// while the returned `GsUsb` exists. It is not closed when the device
+    /// is dropped; the connection it came from still owns it.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub unsafe fn from_android_fd(fd: std::os::unix::io::RawFd) -> Result<GsUsb> {
+        use rusb::UsbContext;
+
+        #[cfg(target_os = "android")]
+        {
+            static NO_DISCOVERY: std::sync::Once = std::sync::Once::new();
+            NO_DISCOVERY.call_once(|| {
+                if let Err(e) = rusb::disable_device_discovery() {
+                    log::warn!("Failed to disable USB device discovery: {e}");
+                }
+            });
+        }
+
+        // SAFETY: the caller keeps `fd` open for the life of the handle
+        let handle = unsafe { GlobalContext::default().open_device_with_fd(fd) }?;
+        let device = handle.device();
+        let (bus, address) = (device.bus_number(), device.address());
+        Ok(Self::new(Box::new(UsbTransport::new(handle)), bus, address))
+    }
+
+    /// The USB device handle, for vendor requests this crate doesn't cover
+    ///
+    /// `None` unless the device was opened from USB and its transport isn't
+    /// wrapped. The gs_usb interface is claimed while the device is started.
+    pub fn raw_handle(&self) -> Option<&DeviceHandle<GlobalContext>> {
+        self.transport.usb_handle()
+    }
+
+    /// Replace the transport with a wrapper around it
+    ///
+    /// Used to layer recording or fault injection on top of a device that was
+    /// opened with `scan()` or `find()`. Device state (capability cache, mode
+    /// flags) is kept.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use gs_usb::GsUsb;
+    /// use gs_usb::RecordingTransport;
+    ///
+    /// let dev = GsUsb::scan()?.remove(0);
+    /// let dev = dev.wrap_transport(|transport| {
+    ///     RecordingTransport::create(transport, "session.gsrec").unwrap()
+    /// });
+    /// # Ok::<(), gs_usb::GsUsbError>(())
+    /// ```
+    pub fn wrap_transport<W, F>(mut self, wrap: F) -> Self
+    where
+        W: Transport + 'static,
+        F: FnOnce(Box<dyn Transport>) -> W,
+    {
+        let transport = std::mem::replace(&mut self.transport, Box::new(Detached));
+        self.transport = Box::new(wrap(transport));
+        self
+    }
+
+    /// Set the retry policy for control requests
+    ///
+    /// By default a failed control request is not repeated.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use gs_usb::{GsUsb, RetryPolicy};
+    /// # use std::time::Duration;
+    /// # let mut dev: GsUsb = todo!();
+    /// dev.set_retry_policy(RetryPolicy::new(3, Duration::from_millis(50)));
+    /// ```
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Get the retry policy for control requests
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Set whether `stop()` hands the device back to the kernel driver
+    ///
+    /// If `start()` had to detach a kernel driver (e.g. the Linux `gs_usb`
+    /// module), `stop()` reattaches it by default so the SocketCAN interface
+    /// comes back. Disable this to keep the device away from the kernel
+    /// between sessions.
+    pub fn set_reattach_kernel_driver(&mut self, reattach: bool) {
+        self.reattach_kernel_driver = reattach;
+    }
+
+    /// Whether `stop()` reattaches a detached kernel driver
+    pub fn reattach_kernel_driver(&self) -> bool {
+        self.reattach_kernel_driver
+    }
+
+    /// Set how long starting keeps trying to claim a device another process
+    /// or driver holds
+    ///
+    /// By default `start()` and the other starts fail at once with an error
+    /// for which `GsUsbError::is_claimed_elsewhere()` is true. With a wait,
+    /// they retry until the device is released or the wait is over, e.g.
+    /// while another tool on macOS still has it open for exclusive access.
+    pub fn set_claim_wait(&mut self, wait: Duration) {
+        self.claim_wait = wait;
+    }
+
+    /// How long starting waits for the device to be released
+    pub fn claim_wait(&self) -> Duration {
+        self.claim_wait
+    }
+
+    /// Set whether `read()` returns CAN error frames as errors
+    ///
+    /// When enabled, a received frame with `CAN_ERR_FLAG` set is returned as
+    /// `GsUsbError::BusError` instead of `Ok(frame)`, so the read loop doesn't
+    /// have to check every frame for the error bit. Disabled by default.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use gs_usb::{GsUsb, GsUsbError};
+    /// # use std::time::Duration;
+    /// # let mut dev: GsUsb = todo!();
+    /// dev.set_error_frames_as_errors(true);
+    /// match dev.read(Duration::from_millis(100)) {
+    ///     Ok(frame) => println!("RX  {frame}"),
+    ///     Err(GsUsbError::BusError(err)) if !err.is_bus_off() => eprintln!("{err}"),
+    ///     Err(e) => return Err(e),
+    /// }
+    /// # Ok::<(), gs_usb::GsUsbError>(())
+    /// ```
+    pub fn set_error_frames_as_errors(&mut self, enabled: bool) {
+        self.error_frames_as_errors = enabled;
+    }
+
+    /// Whether `read()` returns CAN error frames as errors
+    pub fn error_frames_as_errors(&self) -> bool {
+        self.error_frames_as_errors
+    }
+
+    /// Behave like python-can's `gs_usb` interface
+    ///
+    /// For test rigs mixing this crate and python-can, so that both see the
+    /// same frames with the same timestamps:
+    /// - `set_bitrate()` uses an 87.5% sample point at all bitrates
+    /// - `read()` returns frames with `GS_CAN_FLAG_OVERFLOW` as they are,
+    ///   without reporting an `RxOverflow` first
+    /// - `timestamp_us` is the raw hardware counter, not rescaled by
+    ///   `set_timestamp_tick_hz()`
+    /// - `send()` sends echo IDs as they are, also while TX latency is
+    ///   measured, so `tx_latency()` gets no samples
+    ///
+    /// Echo frames are returned by `read()` either way, as python-can does.
+    /// Start the device with `PYTHON_CAN_MODE_FLAGS` for python-can's
+    /// default mode.
+    pub fn set_python_can_compat(&mut self, enabled: bool) {
+        self.python_can_compat = enabled;
+    }
+
+    /// Whether the device behaves like python-can's `gs_usb` interface
+    pub fn python_can_compat(&self) -> bool {
+        self.python_can_compat
+    }
+
+    /// Synchronize hardware timestamps with host time
+    ///
+    /// Adds a sync point to the device's `TimeSync`. Afterwards `read()` sets
+    /// `GsUsbFrame::host_instant()` from the hardware timestamp instead of the
+    /// USB reception time. Call it after `start()` with
+    /// `GS_CAN_MODE_HW_TIMESTAMP`, and again from time to time to track clock
+    /// drift. `start()` discards the synchronization.
+    pub fn sync_time(&mut self) -> Result<()> {
+        let mut sync = self.time_sync.take().unwrap_or_default();
+        let result = sync.sample(self, DEFAULT_SAMPLES);
+        if sync.points() > 0 {
+            self.time_sync = Some(sync);
+        }
+        result
+    }
+
+    /// Current host-device time synchronization, if any
+    pub fn time_sync(&self) -> Option<&TimeSync> {
+        self.time_sync.as_ref()
+    }
+
+    /// Select the timestamp attached to received frames
+    ///
+    /// Defaults to `TimestampSource::SyncedHost`. `Hardware` and `None` skip
+    /// the host time conversion, so `GsUsbFrame::host_instant()` is `None`
+    /// and TX latency can't be measured.
+    pub fn set_timestamp_source(&mut self, source: TimestampSource) {
+        self.timestamp_source = source;
+    }
+
+    /// Timestamp source attached to received frames
+    pub fn timestamp_source(&self) -> TimestampSource {
+        self.timestamp_source
+    }
+
+    /// Set the rate of the device's hardware timestamp counter
+    ///
+    /// Timestamps of frames and `device_timestamp()` are scaled to
+    /// microseconds from this rate. The default is 1 MHz, which the gs_usb
+    /// protocol specifies but not all firmware follows; see
+    /// `detect_timestamp_tick_hz()`.
+    pub fn set_timestamp_tick_hz(&mut self, tick_hz: u32) {
+        self.ticks = TickScaler::new(tick_hz);
+    }
+
+    /// Rate of the hardware timestamp counter that timestamps are scaled from
+    pub fn timestamp_tick_hz(&self) -> u32 {
+        self.ticks.tick_hz()
+    }
+
+    /// Measure the rate of the hardware timestamp counter and use it
+    ///
+    /// Reads the counter twice, `window` apart, and compares against host
+    /// time. Rates within 1% of a common rate (1, 2, 4, 8, 10 or 16 MHz) are
+    /// snapped to it, others are rounded to the nearest kHz. A window of a
+    /// few hundred milliseconds is enough to tell the common rates apart.
+    pub fn detect_timestamp_tick_hz(&mut self, window: Duration) -> Result<u32> {
+        let (first_host, first) = self.raw_timestamp_sample()?;
+        std::thread::sleep(window);
+        let (second_host, second) = self.raw_timestamp_sample()?;
+
+        let elapsed = second_host.duration_since(first_host).as_secs_f64();
+        let ticks = f64::from(second.wrapping_sub(first));
+        if elapsed <= 0.0 || ticks <= 0.0 {
+            return Err(GsUsbError::FeatureNotSupported(
+                "running hardware timestamps",
+            ));
+        }
+        let tick_hz = snap_tick_hz(ticks / elapsed);
+        log::debug!("Detected timestamp counter rate: {tick_hz} Hz");
+        self.set_timestamp_tick_hz(tick_hz);
+        Ok(tick_hz)
+    }
+
+    /// Read the unscaled counter, with the host time halfway through the request
+    fn raw_timestamp_sample(&mut self) -> Result<(Instant, u32)> {
+        let before = Instant::now();
+        let raw = self.raw_device_timestamp()?;
+        Ok((before + before.elapsed() / 2, raw))
+    }
+
+    /// Keep hardware timestamps synchronized during long captures
+    ///
+    /// With an interval set, `read()` calls `sync_time()` whenever the latest
+    /// sync point is older than `interval`, so the drift model follows the
+    /// device crystal and `GsUsbFrame::host_instant()` stays aligned with host
+    /// time over hours. Each sync costs a few control requests. Only active
+    /// while hardware timestamps are enabled; `None` (the default) disables it.
+    pub fn set_resync_interval(&mut self, interval: Option<Duration>) {
+        self.resync_interval = interval;
+    }
+
+    /// Interval of the continuous re-synchronization, if enabled
+    pub fn resync_interval(&self) -> Option<Duration> {
+        self.resync_interval
+    }
+
+    /// Add a sync point if the re-synchronization interval has passed
+    fn resync_if_due(&mut self) {
+        let Some(interval) = self.resync_interval else {
+            return;
+        };
+        if !self.started || (self.device_flags & GS_CAN_MODE_HW_TIMESTAMP) == 0 {
+            return;
+        }
+        let due = self
+            .time_sync
+            .as_ref()
+            .and_then(TimeSync::last_sync)
+            .is_none_or(|last| last.elapsed() >= interval);
+        if due {
+            if let Err(e) = self.sync_time() {
+                log::warn!("Timestamp re-synchronization failed: {e}");
+            }
+        }
+    }
+
+    /// Measure the latency from `send()` to transmission on the bus
+    ///
+    /// From now on `send()` overwrites the echo ID of each frame with a
+    /// unique one, and `read()` matches the echoes to keep statistics over
+    /// the latest `window` frames. Calling it again resets the statistics.
+    /// Synchronize with `sync_time()` first to measure up to the hardware
+    /// timestamp of the echo rather than its arrival over USB.
+    pub fn enable_tx_latency(&mut self, window: usize) {
+        self.latency = Some(LatencyTracker::new(window));
+    }
+
+    /// Stop measuring TX latency
+    pub fn disable_tx_latency(&mut self) {
+        self.latency = None;
+    }
+
+    /// TX latency statistics, if enabled with `enable_tx_latency()`
+    pub fn tx_latency(&self) -> Option<&LatencyStats> {
+        self.latency.as_ref().map(LatencyTracker::stats)
+    }
+
+    /// Statistics of the USB bulk transfers received since opening or the
+    /// last `reset_transfer_stats()`
+    pub fn transfer_stats(&self) -> &TransferStats {
+        &self.transfer_stats
+    }
+
+    /// Start the transfer statistics over
+    pub fn reset_transfer_stats(&mut self) {
+        self.transfer_stats.reset();
+    }
+
+    /// Time spent in the stages of reading frames
+    #[cfg(feature = "instrument")]
+    pub fn hot_path_stats(&self) -> &HotPathStats {
+        &self.hot_path
+    }
+
+    /// Start the stage times over
+    #[cfg(feature = "instrument")]
+    pub fn reset_hot_path_stats(&mut self) {
+        self.hot_path.reset();
+    }
+
+    /// Call `callback` with every received frame, echoes excepted, see
+    /// the `tap` module
+    ///
+    /// The frames are still returned by the reads as before.
+    pub fn tap(&mut self, mut callback: impl FnMut(&GsUsbFrame) + Send + 'static) -> TapId {
+        self.taps
+            .add(TapOptions::default(), move |_, frame| callback(frame))
+    }
+
+    /// Call `callback` with every received frame and, as `options` say,
+    /// echoes and sent frames
+    pub fn tap_with(
+        &mut self,
+        options: TapOptions,
+        callback: impl FnMut(TapDirection, &GsUsbFrame) + Send + 'static,
+    ) -> TapId {
+        self.taps.add(options, callback)
+    }
+
+    /// Remove a tap, false if it was already removed
+    pub fn untap(&mut self, id: TapId) -> bool {
+        self.taps.remove(id)
+    }
+
+    /// Set the acceptance filters for frames received on channel 0
+    ///
+    /// `read()` drops received data frames that match none of the filters.
+    /// Echoes and error frames are always returned. An empty list, the
+    /// default, passes every frame.
+    pub fn set_filters(&mut self, filters: Vec<IdFilter>) {
+        self.set_channel_filters(0, filters);
+    }
+
+    /// Acceptance filters for frames received on channel 0
+    pub fn filters(&self) -> &[IdFilter] {
+        self.channel_filters(0)
+    }
+
+    /// Set the acceptance filters for frames received on a channel
+    pub fn set_channel_filters(&mut self, channel: u8, filters: Vec<IdFilter>) {
+        let channel = usize::from(channel);
+        if self.filters.len() <= channel {
+            self.filters.resize(channel + 1, Vec::new());
+        }
+        self.filters[channel] = filters;
+    }
+
+    /// Acceptance filters for frames received on a channel
+    pub fn channel_filters(&self, channel: u8) -> &[IdFilter] {
+        self.filters
+            .get(usize::from(channel))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Best mode flags for this device, see `ModeRequest::negotiate()`
+    pub fn negotiate_mode(&mut self, request: &ModeRequest) -> Result<u32> {
+        let capability = self.device_capability()?;
+        request.negotiate(&capability)
+    }
+
+    /// Set the device up from a configuration and start it
+    ///
+    /// A running device is stopped first, then set up with `configure()`.
+    pub fn apply(&mut self, config: &Config) -> Result<()> {
+        if self.started {
+            self.stop()?;
+        }
+        self.configure(config)
+    }
+
+    /// Configure a stopped device and start it, all or nothing
+    ///
+    /// The configuration is checked against the device capability first:
+    /// requested features, bit timings for the bitrates and sample points.
+    /// Only then are HOST_FORMAT, BITTIMING, DATA_BITTIMING (with a data
+    /// bitrate) and MODE sent. If one of them fails, the channel is reset and
+    /// the interface released, as by `stop()`, and the error returned.
+    pub fn configure(&mut self, config: &Config) -> Result<()> {
+        if self.started {
+            return Err(GsUsbError::AlreadyStarted);
+        }
+        let capability = self.device_capability()?;
+        config.validate(&capability)?;
+        let flags = config.start_flags(&capability)?;
+        let (timing, data_timing) = config.bit_timings(&capability)?;
+
+        let result = self.send_configuration(timing, data_timing, flags);
+        self.finish_configuration(config, result)
+    }
+
+    /// Change the configuration of a running device in place
+    ///
+    /// Where `apply()` resets the USB device and claims the interface again,
+    /// this only puts the channel in reset mode, sends the new bit timings
+    /// and starts it with the new mode, which is much faster when sweeping
+    /// bitrates. The configuration is checked like in `configure()`, and a
+    /// failure stops the device the same way. A stopped device is simply
+    /// configured.
+    ///
+    /// Only channel 0 is changed, so after `apply_profile()` started several
+    /// channels this fails with `GsUsbError::InvalidConfig`, leaving them
+    /// running; use `apply_profile()` again instead.
+    pub fn reconfigure(&mut self, config: &Config) -> Result<()> {
+        if !self.started {
+            return self.configure(config);
+        }
+        if self.started_channels > 1 {
+            return Err(GsUsbError::InvalidConfig(format!(
+                "reconfigure() changes channel 0 only, but {} channels are started",
+                self.started_channels
+            )));
+        }
+        let capability = self.device_capability()?;
+        config.validate(&capability)?;
+        let flags = config.start_flags(&capability)?;
+        let (timing, data_timing) = config.bit_timings(&capability)?;
+
+        let result = self.send_reconfiguration(timing, data_timing, flags);
+        self.finish_configuration(config, result)
+    }
+
+    /// Stop on a failed configuration, take over the host side settings otherwise
+    fn finish_configuration(&mut self, config: &Config, result: Result<()>) -> Result<()> {
+        if let Err(e) = result {
+            log::debug!("Configuration failed ({e}), stopping");
+            let _ = self.stop();
+            return Err(e);
+        }
+        self.filters = vec![config.filters.clone()];
+        self.error_frames_as_errors = config.error_frames_as_errors;
+        Ok(())
+    }
+
+    /// Set all channels up from a profile and start them
+    ///
+    /// `apply()` for several channels in one call: a running device is
+    /// stopped, the whole profile is checked against the device before
+    /// anything is sent, and if a request fails all channels are stopped
+    /// again. `active_mode()`, `current_config()` and `filters()` refer to
+    /// channel 0. The bit timing limits of channel 0 are used for all
+    /// channels.
+    pub fn apply_profile(&mut self, profile: &DeviceProfile) -> Result<()> {
+        if self.started {
+            self.stop()?;
+        }
+        let capability = self.device_capability()?;
+        let channel_count = self.device_info()?.channel_count();
+        profile.validate(&capability, channel_count)?;
+        let mut setups = Vec::with_capacity(profile.channels.len());
+        for config in &profile.channels {
+            let (timing, data_timing) = config.bit_timings(&capability)?;
+            setups.push((timing, data_timing, config.start_flags(&capability)?));
+        }
+
+        self.started_channels = setups.len() as u16;
+        if let Err(e) = self.send_profile(&setups) {
+            log::debug!("Configuration failed ({e}), stopping");
+            let _ = self.stop();
+            return Err(e);
+        }
+        self.filters = profile
+            .channels
+            .iter()
+            .map(|config| config.filters.clone())
+            .collect();
+        self.error_frames_as_errors = profile.channels[0].error_frames_as_errors;
+        Ok(())
+    }
+
+    /// Control request sequence of `apply_profile()`
+    fn send_profile(
+        &mut self,
+        setups: &[(DeviceBitTiming, Option<DeviceBitTiming>, u32)],
+    ) -> Result<()> {
+        self.transport.reset()?;
+        self.claim()?;
+        self.send_host_format()?;
+        let mut first_mode = None;
+        for (channel, &(timing, data_timing, flags)) in (0u16..).zip(setups) {
+            let active_mode = self.send_channel(channel, timing, data_timing, flags)?;
+            first_mode.get_or_insert(active_mode);
+        }
+        if let Some(active_mode) = first_mode {
+            self.set_active_mode(active_mode);
+        }
+        Ok(())
+    }
+
+    /// Control request sequence of `configure()`
+    fn send_configuration(
+        &mut self,
+        timing: DeviceBitTiming,
+        data_timing: Option<DeviceBitTiming>,
+        flags: u32,
+    ) -> Result<()> {
+        self.transport.reset()?;
+        self.claim()?;
+        self.send_host_format()?;
+        self.send_timings_and_start(timing, data_timing, flags)
+    }
+
+    /// Control request sequence of `reconfigure()`
+    fn send_reconfiguration(
+        &mut self,
+        timing: DeviceBitTiming,
+        data_timing: Option<DeviceBitTiming>,
+        flags: u32,
+    ) -> Result<()> {
+        let mode = DeviceMode::new(GS_CAN_MODE_RESET, 0);
+        self.control_out(GS_USB_BREQ_MODE, 0, &mode.pack())?;
+        self.started = false;
+        self.active_mode = None;
+        self.after_overflow = None;
+        self.rx_queue.clear();
+        self.send_timings_and_start(timing, data_timing, flags)
+    }
+
+    /// Send the bit timings and start channel 0
+    fn send_timings_and_start(
+        &mut self,
+        timing: DeviceBitTiming,
+        data_timing: Option<DeviceBitTiming>,
+        flags: u32,
+    ) -> Result<()> {
+        let active_mode = self.send_channel(0, timing, data_timing, flags)?;
+        self.set_active_mode(active_mode);
+        Ok(())
+    }
+
+    /// Send the bit timings of a channel and start it
+    fn send_channel(
+        &mut self,
+        channel: u16,
+        timing: DeviceBitTiming,
+        data_timing: Option<DeviceBitTiming>,
+        flags: u32,
+    ) -> Result<ActiveMode> {
+        self.control_out(GS_USB_BREQ_BITTIMING, channel, &timing.pack())?;
+        if channel == 0 {
+            self.last_timing = Some(timing);
+        }
+        if let Some(data_timing) = data_timing {
+            let request = self.data_bittiming_request();
+            self.control_out(request, channel, &data_timing.pack())?;
+            if channel == 0 {
+                self.last_data_timing = Some(data_timing);
+            }
         }
+        self.start_channel(channel, flags)
+    }
+
+    /// Capture the configuration in effect
+    ///
+    /// Bitrates and sample points are calculated from the bit timings last
+    /// set, the mode from the flags the device was last started with. Fails
+    /// with `GsUsbError::InvalidConfig` if no bitrate has been set yet.
+    pub fn current_config(&mut self) -> Result<Config> {
+        let timing = self
+            .last_timing
+            .ok_or_else(|| GsUsbError::InvalidConfig("no bitrate has been set".into()))?;
+        let clock_hz = self.device_capability()?.fclk_can;
+        let data_timing = self
+            .last_data_timing
+            .filter(|_| self.fd_mode || !self.started);
+        Ok(Config {
+            bitrate: timing.bitrate(clock_hz),
+            sample_point: Some(timing.sample_point()),
+            data_bitrate: data_timing.map(|timing| timing.bitrate(clock_hz)),
+            data_sample_point: data_timing.map(|timing| timing.sample_point()),
+            error_frames_as_errors: self.error_frames_as_errors,
+            mode: ModeConfig::from_flags(self.device_flags),
+            filters: self.filters().to_vec(),
+        })
     }
 
     /// Start the GS-USB device
@@ -105,63 +939,122 @@ impl GsUsb {
     /// ```
     pub fn start(&mut self, flags: u32) -> Result<()> {
         // Reset to support restart multiple times
-        self.handle.reset()?;
+        self.transport.reset()?;
 
-        // Detach kernel driver on Linux/Unix
-        #[cfg(any(target_os = "linux", target_os = "macos"))]
-        {
-            if self.handle.kernel_driver_active(0).unwrap_or(false) {
-                self.handle
-                    .detach_kernel_driver(0)
-                    .map_err(GsUsbError::DetachKernelDriver)?;
+        // Detach kernel driver (if any) and claim the interface
+        self.claim()?;
+
+        let active_mode = self.start_channel(0, flags)?;
+        self.set_active_mode(active_mode);
+        Ok(())
+    }
+
+    /// Claim the interface, retrying while it is held elsewhere for up to
+    /// the claim wait
+    fn claim(&mut self) -> Result<()> {
+        let deadline = Instant::now() + self.claim_wait;
+        loop {
+            match self.transport.claim_interface() {
+                Err(e) if e.is_claimed_elsewhere() && Instant::now() < deadline => {
+                    log::debug!("Device held elsewhere ({e}), waiting for its release");
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    std::thread::sleep(CLAIM_POLL_INTERVAL.min(left));
+                }
+                result => return result,
             }
         }
+    }
 
-        // Claim the interface
-        self.handle
-            .claim_interface(0)
-            .map_err(GsUsbError::ClaimInterface)?;
-
+    /// Send the MODE start request for a channel with the flags the device supports
+    fn start_channel(&mut self, channel: u16, requested: u32) -> Result<ActiveMode> {
         // Get capability to check supported features
         let capability = self.device_capability()?;
 
         // Only allow features that the device supports
-        let mut flags = flags & capability.feature;
+        let mut flags = requested & capability.feature;
 
         // Only allow features that this driver supports
-        flags &= GS_CAN_MODE_LISTEN_ONLY
-            | GS_CAN_MODE_LOOP_BACK
-            | GS_CAN_MODE_ONE_SHOT
-            | GS_CAN_MODE_HW_TIMESTAMP
-            | GS_CAN_MODE_FD;
+        flags &= DRIVER_MODE_FLAGS;
 
-        self.device_flags = flags;
-        self.fd_mode = (flags & GS_CAN_MODE_FD) == GS_CAN_MODE_FD;
+        let active_mode = ActiveMode { requested, flags };
+        if !active_mode.is_complete() {
+            log::warn!("Starting channel {channel} in mode {active_mode}");
+        }
 
         let mode = DeviceMode::new(GS_CAN_MODE_START, flags);
-        self.control_out(GS_USB_BREQ_MODE, 0, &mode.pack())?;
+        self.control_out(GS_USB_BREQ_MODE, channel, &mode.pack())?;
+
+        // The USB controller of the LPC546xx loses transfers that end in a
+        // short packet, so they are padded to full packets and closed with a
+        // zero-length packet instead
+        self.quirk_packet_size = (capability.feature & GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX != 0)
+            .then(|| {
+                self.transport
+                    .tx_max_packet_size()
+                    .unwrap_or(FULL_SPEED_BULK_PACKET_SIZE)
+            });
+        Ok(active_mode)
+    }
 
+    /// Take over the mode channel 0 was started with
+    fn set_active_mode(&mut self, active_mode: ActiveMode) {
+        self.device_flags = active_mode.flags;
+        self.fd_mode = (active_mode.flags & GS_CAN_MODE_FD) == GS_CAN_MODE_FD;
+        self.time_sync = None;
+        self.ticks.reset();
         self.started = true;
-        Ok(())
+        self.active_mode = Some(active_mode);
+    }
+
+    /// Mode the device was started with, `None` while stopped
+    ///
+    /// `start()` leaves out requested flags the device or this driver doesn't
+    /// support; `ActiveMode::stripped()` tells which.
+    pub fn active_mode(&self) -> Option<ActiveMode> {
+        self.active_mode
     }
 
     /// Stop the GS-USB device
+    ///
+    /// Resets the channels, releases the USB interface and, unless disabled
+    /// with `set_reattach_kernel_driver(false)`, reattaches the kernel driver
+    /// that `start()` detached. The device can be started again afterwards.
     pub fn stop(&mut self) -> Result<()> {
         let mode = DeviceMode::new(GS_CAN_MODE_RESET, 0);
-        // Ignore errors when stopping (device might already be stopped)
-        let _ = self.control_out(GS_USB_BREQ_MODE, 0, &mode.pack());
+        for channel in 0..self.started_channels.max(1) {
+            // Ignore errors when stopping (device might already be stopped)
+            let _ = self.control_out(GS_USB_BREQ_MODE, channel, &mode.pack());
+        }
+        self.started_channels = 1;
         self.started = false;
-        Ok(())
+        self.active_mode = None;
+        self.after_overflow = None;
+        self.rx_queue.clear();
+        self.transport
+            .release_interface(self.reattach_kernel_driver)
+    }
+
+    /// Stop the device and close it
+    ///
+    /// Unlike dropping the device, this reports errors from releasing the
+    /// interface or reattaching the kernel driver.
+    pub fn close(mut self) -> Result<()> {
+        let result = self.stop();
+        // Nothing left for `Drop` to do
+        self.transport = Box::new(Detached);
+        result
     }
 
     /// Set the CAN bitrate
     ///
-    /// Configures the nominal (arbitration) bitrate with a sample point of 87.5%.
+    /// Configures the nominal (arbitration) bitrate with a sample point of 87.5%
+    /// up to 500 kbps, 80% up to 800 kbps and 75% above. The register values
+    /// are calculated by `timing::nominal_timing()`.
     ///
     /// # Arguments
     /// * `bitrate` - Bitrate in bits per second (e.g., 250000 for 250 kbps)
     ///
-    /// # Supported bitrates
+    /// # Common bitrates
     /// - 10000 (10 kbps)
     /// - 20000 (20 kbps)
     /// - 50000 (50 kbps)
@@ -172,47 +1065,34 @@ impl GsUsb {
     /// - 800000 (800 kbps)
     /// - 1000000 (1 Mbps)
     pub fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
-        let capability = self.device_capability()?;
-        let clock = capability.fclk_can;
-
-        // Get timing parameters based on clock
-        // Returns (prop_seg, phase_seg1, phase_seg2, sjw, brp)
-        // Sample points: 87.5% for 10-500 Kbps, 75% for 1 Mbps
-        let timing = match clock {
-            // 80 MHz clock (PEAK Systems)
-            80_000_000 => match bitrate {
-                10_000 => Some((87, 87, 25, 12, 40)),   // 87.5% sample point
-                20_000 => Some((87, 87, 25, 12, 20)),   // 87.5% sample point
-                50_000 => Some((87, 87, 25, 12, 8)),    // 87.5% sample point
-                100_000 => Some((87, 87, 25, 12, 4)),   // 87.5% sample point
-                125_000 => Some((69, 70, 20, 10, 4)),   // 87.5% sample point
-                250_000 => Some((69, 70, 20, 10, 2)),   // 87.5% sample point
-                500_000 => Some((69, 70, 20, 10, 1)),   // 87.5% sample point
-                1_000_000 => Some((29, 30, 20, 10, 1)), // 75% sample point
-                _ => None,
-            },
-            // 40 MHz clock (CF3 / candleLight)
-            40_000_000 => match bitrate {
-                10_000 => Some((87, 87, 25, 12, 20)),  // 87.5% sample point
-                20_000 => Some((87, 87, 25, 12, 10)),  // 87.5% sample point
-                50_000 => Some((87, 87, 25, 12, 4)),   // 87.5% sample point
-                100_000 => Some((87, 87, 25, 12, 2)),  // 87.5% sample point
-                125_000 => Some((69, 70, 20, 10, 2)),  // 87.5% sample point
-                250_000 => Some((69, 70, 20, 10, 1)),  // 87.5% sample point
-                500_000 => Some((34, 35, 10, 5, 1)),   // 87.5% sample point
-                1_000_000 => Some((14, 15, 10, 5, 1)), // 75% sample point
-                _ => None,
-            },
-            _ => None,
+        let sample_point = if self.python_can_compat {
+            PYTHON_CAN_SAMPLE_POINT
+        } else {
+            default_sample_point(bitrate)
         };
+        self.set_bitrate_with_sample_point(bitrate, sample_point)
+    }
 
-        match timing {
-            Some((prop_seg, phase_seg1, phase_seg2, sjw, brp)) => {
-                self.set_timing(prop_seg, phase_seg1, phase_seg2, sjw, brp)
-            }
+    /// Set the CAN bitrate with a given sample point
+    ///
+    /// The register values are calculated by `timing::nominal_timing_at()`.
+    pub fn set_bitrate_with_sample_point(
+        &mut self,
+        bitrate: u32,
+        sample_point: SamplePoint,
+    ) -> Result<()> {
+        let capability = self.device_capability()?;
+        match crate::timing::nominal_timing_at(&capability, bitrate, sample_point) {
+            Some(timing) => self.set_timing(
+                timing.prop_seg,
+                timing.phase_seg1,
+                timing.phase_seg2,
+                timing.sjw,
+                timing.brp,
+            ),
             None => Err(GsUsbError::UnsupportedBitrate {
                 bitrate,
-                clock_hz: clock,
+                clock_hz: capability.fclk_can,
             }),
         }
     }
@@ -248,12 +1128,28 @@ impl GsUsb {
         sjw: u32,
         brp: u32,
     ) -> Result<()> {
+        if !cfg!(feature = "fd") {
+            return Err(GsUsbError::FdNotSupported);
+        }
         let timing = DeviceBitTiming::new(prop_seg, phase_seg1, phase_seg2, sjw, brp);
-        self.control_out(GS_USB_BREQ_DATA_BITTIMING, 0, &timing.pack())?;
+        let request = self.data_bittiming_request();
+        self.control_out(request, 0, &timing.pack())?;
         self.last_data_timing = Some(timing);
         Ok(())
     }
 
+    /// Request number of DATA_BITTIMING, which the CANtact Pro moved
+    fn data_bittiming_request(&self) -> u8 {
+        if self
+            .quirks
+            .is_some_and(|quirks| quirks.cantact_pro_data_bittiming)
+        {
+            GS_USB_BREQ_GET_USER_ID
+        } else {
+            GS_USB_BREQ_DATA_BITTIMING
+        }
+    }
+
     /// Get the last nominal (arbitration) phase timing that was set via `set_timing`/`set_bitrate*`
     pub fn last_timing(&self) -> Option<DeviceBitTiming> {
         self.last_timing
@@ -266,52 +1162,42 @@ impl GsUsb {
 
     /// Set CAN FD data phase bitrate
     ///
-    /// Common data bitrates: 1 Mbps, 2 Mbps, 5 Mbps, 8 Mbps, 10 Mbps
+    /// Common data bitrates: 1 Mbps, 2 Mbps, 5 Mbps, 8 Mbps, 10 Mbps. The
+    /// register values are calculated by `timing::data_timing()` for a 75%
+    /// sample point.
     ///
     /// # Arguments
     /// * `bitrate` - Data phase bitrate in bits per second
     pub fn set_data_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        self.set_data_bitrate_with_sample_point(bitrate, DEFAULT_DATA_SAMPLE_POINT)
+    }
+
+    /// Set CAN FD data phase bitrate with a given sample point
+    ///
+    /// The register values are calculated by `timing::data_timing_at()`.
+    pub fn set_data_bitrate_with_sample_point(
+        &mut self,
+        bitrate: u32,
+        sample_point: SamplePoint,
+    ) -> Result<()> {
         let capability = self.device_capability()?;
 
-        // Check if device supports CAN FD
-        if (capability.feature & GS_CAN_FEATURE_FD) == 0 {
+        // Check if device and driver support CAN FD
+        if (capability.feature & GS_CAN_FEATURE_FD) == 0 || !cfg!(feature = "fd") {
             return Err(GsUsbError::FdNotSupported);
         }
 
-        let clock = capability.fclk_can;
-
-        // Get timing parameters based on clock
-        // Returns (prop_seg, phase_seg1, phase_seg2, sjw, brp)
-        // Sample points: 75% for most bitrates, 70% for 8 Mbps @ 80 MHz, 60% for 8 Mbps @ 40 MHz
-        let timing = match clock {
-            // 80 MHz clock (PEAK Systems)
-            80_000_000 => match bitrate {
-                1_000_000 => Some((14, 15, 10, 5, 2)), // 75% sample point
-                2_000_000 => Some((14, 15, 10, 5, 1)), // 75% sample point
-                5_000_000 => Some((5, 6, 4, 2, 1)),    // 75% sample point
-                8_000_000 => Some((3, 3, 3, 1, 1)),    // 70% sample point
-                10_000_000 => Some((2, 3, 2, 1, 1)),   // 75% sample point
-                _ => None,
-            },
-            // 40 MHz clock (CF3 / candleLight)
-            40_000_000 => match bitrate {
-                1_000_000 => Some((14, 15, 10, 5, 1)), // 75% sample point
-                2_000_000 => Some((7, 7, 5, 2, 1)),    // 75% sample point
-                5_000_000 => Some((2, 3, 2, 1, 1)),    // 75% sample point
-                8_000_000 => Some((1, 1, 2, 1, 1)),    // 60% sample point
-                10_000_000 => Some((1, 1, 1, 1, 1)),   // 75% sample point
-                _ => None,
-            },
-            _ => None,
-        };
-
-        match timing {
-            Some((prop_seg, phase_seg1, phase_seg2, sjw, brp)) => {
-                self.set_data_timing(prop_seg, phase_seg1, phase_seg2, sjw, brp)
-            }
+        match crate::timing::data_timing_at(&capability, bitrate, sample_point) {
+            Some(timing) => self.set_data_timing(
+                timing.prop_seg,
+                timing.phase_seg1,
+                timing.phase_seg2,
+                timing.sjw,
+                timing.brp,
+            ),
             None => Err(GsUsbError::UnsupportedDataBitrate {
                 bitrate,
-                clock_hz: clock,
+                clock_hz: capability.fclk_can,
             }),
         }
     }
@@ -321,14 +1207,80 @@ impl GsUsb {
     /// # Arguments
     /// * `frame` - The CAN frame to send
     pub fn send(&mut self, frame: &GsUsbFrame) -> Result<()> {
+        if frame.is_fd() && !cfg!(feature = "fd") {
+            return Err(GsUsbError::FdNotSupported);
+        }
         let hw_timestamps = (self.device_flags & GS_CAN_MODE_HW_TIMESTAMP) != 0;
-        let data = frame.pack(hw_timestamps, self.fd_mode);
+        let echo_id = self
+            .latency
+            .as_mut()
+            .filter(|_| !self.python_can_compat)
+            .map(|tracker| tracker.start(Instant::now()));
+        let packed = match echo_id {
+            Some(echo_id) => {
+                let mut frame = frame.clone();
+                frame.echo_id = echo_id;
+                frame.pack(hw_timestamps, self.fd_mode)
+            }
+            None => frame.pack(hw_timestamps, self.fd_mode),
+        };
+        // Padding to whole packets can exceed the largest frame
+        let padded = self.quirk_packet_size.map(|packet_size| {
+            let mut data = packed.to_vec();
+            data.resize(data.len().next_multiple_of(packet_size), 0);
+            data
+        });
+        let data = padded.as_deref().unwrap_or(&packed);
+
+        let timeout = Duration::from_millis(1000);
+        let result = self
+            .transport
+            .write_bulk(data, timeout)
+            .and_then(|len| match self.quirk_packet_size {
+                Some(_) => self.transport.write_bulk(&[], timeout).map(|_| len),
+                None => Ok(len),
+            })
+            .map_err(|source| GsUsbError::BulkTransfer {
+                endpoint: self.transport.interface().endpoint_out,
+                channel: Some(frame.channel),
+                length: data.len(),
+                source,
+            });
+        if let (Err(_), Some(tracker), Some(echo_id)) = (&result, &mut self.latency, echo_id) {
+            tracker.cancel(echo_id);
+        }
+        if result.is_ok() {
+            self.taps.tx(frame);
+        }
+        result.map(|_| ())
+    }
 
-        self.handle
-            .write_bulk(GS_USB_ENDPOINT_OUT, &data, Duration::from_millis(1000))
-            .map_err(GsUsbError::BulkTransfer)?;
+    /// Send a classic CAN frame
+    ///
+    /// A `ClassicFrame` holds at most 8 bytes, so this never sends an FD
+    /// payload.
+    pub fn send_classic(&mut self, frame: &ClassicFrame) -> Result<()> {
+        self.send(&frame.into())
+    }
 
-        Ok(())
+    /// Send a CAN FD frame
+    ///
+    /// Fails with `GsUsbError::FdNotSupported` unless the device was
+    /// started in FD mode.
+    #[cfg(feature = "fd")]
+    pub fn send_fd(&mut self, frame: &FdFrame) -> Result<()> {
+        if !self.fd_mode {
+            return Err(GsUsbError::FdNotSupported);
+        }
+        self.send(&frame.into())
+    }
+
+    /// Token to cancel reads from another thread, see `CancelToken`
+    ///
+    /// The device keeps the token, so every call returns a clone of the
+    /// same one.
+    pub fn cancel_token(&mut self) -> CancelToken {
+        self.cancel.get_or_insert_with(CancelToken::new).clone()
     }
 
     /// Read a CAN frame
@@ -337,30 +1289,357 @@ impl GsUsb {
     /// * `timeout` - Read timeout duration
     ///
     /// # Returns
-    /// The received CAN frame, or an error if timeout or other failure
+    /// The received CAN frame, or an error if timeout or other failure. Error
+    /// frames are returned as `GsUsbError::BusError` if enabled with
+    /// `set_error_frames_as_errors()`.
+    ///
+    /// If the device lost frames before the received one (`GS_CAN_FLAG_OVERFLOW`),
+    /// an `RxOverflow` is reported first, as an error frame or as
+    /// `GsUsbError::RxOverflow`, and the received frame is returned by the
+    /// next call.
+    ///
+    /// Received frames rejected by the filters set with `set_filters()` are
+    /// skipped; the timeout covers the whole call. Frames kept by `peek()`
+    /// and `read_until()` are returned first.
+    ///
+    /// Fails with `GsUsbError::Cancelled` once the token of `cancel_token()`
+    /// is cancelled.
     pub fn read(&mut self, timeout: Duration) -> Result<GsUsbFrame> {
+        self.check_cancelled()?;
+        if let Some(queued) = self.rx_queue.pop_front() {
+            return Ok(self.unqueue(queued));
+        }
+        self.read_filtered(timeout)
+    }
+
+    /// The frame the next `read()` returns, without taking it
+    ///
+    /// Reads a frame within `timeout` if none is buffered, and keeps it
+    /// for the next read, so layered protocols can look ahead before
+    /// deciding how to handle it.
+    pub fn peek(&mut self, timeout: Duration) -> Result<&GsUsbFrame> {
+        self.check_cancelled()?;
+        if self.rx_queue.is_empty() {
+            let frame = self.read_filtered(timeout)?;
+            self.rx_queue.push_back(Queued::new(frame));
+        }
+        Ok(&self.rx_queue.front().expect("frame is queued").frame)
+    }
+
+    /// Read until a frame matches `predicate`, or fail with
+    /// `GsUsbError::ReadTimeout` at `deadline`
+    ///
+    /// Frames that don't match are dropped or kept for the next reads, as
+    /// `unmatched` says; kept frames are searched first. Kept frames are
+    /// not limited in number, so waiting long on busy traffic with
+    /// `Unmatched::Keep` costs memory.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use gs_usb::{GsUsb, Unmatched};
+    /// # use std::time::{Duration, Instant};
+    /// # let mut dev: GsUsb = todo!();
+    /// // Wait for the response to a request, leaving other traffic to be read later
+    /// let deadline = Instant::now() + Duration::from_millis(500);
+    /// let response = dev.read_until(|frame| frame.can_id == 0x7E8, deadline, Unmatched::Keep)?;
+    /// # Ok::<(), gs_usb::GsUsbError>(())
+    /// ```
+    pub fn read_until<F>(
+        &mut self,
+        mut predicate: F,
+        deadline: Instant,
+        unmatched: Unmatched,
+    ) -> Result<GsUsbFrame>
+    where
+        F: FnMut(&GsUsbFrame) -> bool,
+    {
+        if let Some(index) = self.rx_queue.iter().position(|q| predicate(&q.frame)) {
+            let queued = if unmatched == Unmatched::Discard {
+                self.rx_queue.drain(..index);
+                self.rx_queue.pop_front()
+            } else {
+                self.rx_queue.remove(index)
+            }
+            .expect("matching frame is queued");
+            return Ok(self.unqueue(queued));
+        }
+        if unmatched == Unmatched::Discard {
+            self.rx_queue.clear();
+        }
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(GsUsbError::ReadTimeout);
+            }
+            let frame = self.read_filtered(remaining)?;
+            if predicate(&frame) {
+                return Ok(frame);
+            }
+            if unmatched == Unmatched::Keep {
+                self.rx_queue.push_back(Queued::new(frame));
+            }
+        }
+    }
+
+    /// Read up to `count` frames, until `deadline`
+    ///
+    /// The deadline covers the whole call rather than each read, so a slow
+    /// trickle of frames can't stretch it. Running into the deadline isn't an
+    /// error: the frames read so far are returned with `timed_out` set. Other
+    /// errors are returned as such, dropping the frames read.
+    pub fn read_n(&mut self, count: usize, deadline: Instant) -> Result<ReadBatch> {
+        let mut batch = ReadBatch::default();
+        while batch.frames.len() < count {
+            if let Some(queued) = self.rx_queue.pop_front() {
+                let frame = self.unqueue(queued);
+                batch.frames.push(frame);
+                continue;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                batch.timed_out = true;
+                break;
+            }
+            match self.read_filtered(remaining) {
+                Ok(frame) => batch.frames.push(frame),
+                Err(GsUsbError::ReadTimeout) => {
+                    batch.timed_out = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Read the frames available within `timeout`, up to `max_frames`, in
+    /// one call
+    ///
+    /// Waits up to `timeout` for the first frame, then takes the frames
+    /// following it without a pause, so consumers processing frames in
+    /// batches make one call per burst. Returns an empty batch if no frame
+    /// arrives.
+    pub fn read_many(&mut self, max_frames: usize, timeout: Duration) -> Result<Vec<GsUsbFrame>> {
+        let mut frames = Vec::new();
+        self.read_many_into(&mut frames, max_frames, timeout)?;
+        Ok(frames)
+    }
+
+    /// `read_many()` into a batch the caller keeps, returning the number of
+    /// frames read
+    ///
+    /// `frames` is cleared first and keeps its capacity, so a batch
+    /// allocated once with room for `max_frames` serves every call. On an
+    /// error, it holds the frames read before it.
+    pub fn read_many_into(
+        &mut self,
+        frames: &mut Vec<GsUsbFrame>,
+        max_frames: usize,
+        timeout: Duration,
+    ) -> Result<usize> {
+        frames.clear();
+        self.check_cancelled()?;
+        let deadline = Instant::now() + timeout;
+        while frames.len() < max_frames {
+            let Some(queued) = self.rx_queue.pop_front() else {
+                break;
+            };
+            let frame = self.unqueue(queued);
+            frames.push(frame);
+        }
+        while frames.len() < max_frames {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let wait = if frames.is_empty() {
+                remaining
+            } else {
+                remaining.min(BATCH_GAP)
+            };
+            match self.read_filtered(wait) {
+                Ok(frame) => frames.push(frame),
+                Err(GsUsbError::ReadTimeout) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(frames.len())
+    }
+
+    /// Take the frames buffered on the host, and with `flush` those waiting
+    /// on the device
+    ///
+    /// Returns the frames kept by `read_until()` and a frame held back
+    /// behind an overflow report. With `flush`, reads from the device until
+    /// no frame arrives within it, so stale frames from earlier activity
+    /// don't end up in a measurement; on a busy bus, that ends after 1024
+    /// frames. Frames are returned in order and pass the
+    /// filters like `read()`'s.
+    pub fn drain(&mut self, flush: Option<Duration>) -> Result<Vec<GsUsbFrame>> {
+        let queued: Vec<Queued> = self
+            .rx_queue
+            .drain(..)
+            .chain(self.after_overflow.take())
+            .collect();
+        let mut frames: Vec<GsUsbFrame> = queued.into_iter().map(|q| self.unqueue(q)).collect();
+        if let Some(quiet) = flush {
+            for _ in 0..MAX_DRAIN_FRAMES {
+                match self.read_filtered(quiet) {
+                    Ok(frame) => frames.push(frame),
+                    Err(GsUsbError::ReadTimeout) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Take a frame out of `rx_queue` or `after_overflow`
+    fn unqueue(&mut self, queued: Queued) -> GsUsbFrame {
+        #[cfg(feature = "instrument")]
+        self.hot_path.unqueued(queued.at);
+        queued.frame
+    }
+
+    /// Read the next frame passing the filters, without the kept frames
+    fn read_filtered(&mut self, timeout: Duration) -> Result<GsUsbFrame> {
+        let deadline = Instant::now() + timeout;
+        let mut remaining = timeout;
+        loop {
+            let frame = self.read_frame(remaining)?;
+            if passes_filters(self.channel_filters(frame.channel), &frame) {
+                #[cfg(feature = "instrument")]
+                self.hot_path.dispatched();
+                return Ok(frame);
+            }
+            remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(GsUsbError::ReadTimeout);
+            }
+        }
+    }
+
+    /// Read the next frame, before filtering
+    fn read_frame(&mut self, timeout: Duration) -> Result<GsUsbFrame> {
+        if let Some(held) = self.after_overflow.take() {
+            #[cfg(feature = "instrument")]
+            self.hot_path.resumed(held.at);
+            return self.error_checked(held.frame);
+        }
+        self.resync_if_due();
+
         let hw_timestamps = (self.device_flags & GS_CAN_MODE_HW_TIMESTAMP) != 0;
         let max_size = GsUsbFrame::frame_size(hw_timestamps, self.fd_mode);
 
-        let mut buf = vec![0u8; max_size];
-        let len = match self.handle.read_bulk(GS_USB_ENDPOINT_IN, &mut buf, timeout) {
-            Ok(len) => len,
-            Err(rusb::Error::Timeout) => return Err(GsUsbError::ReadTimeout),
-            Err(e) => return Err(GsUsbError::BulkTransfer(e)),
+        let mut buf = std::mem::take(&mut self.rx_buf);
+        buf.resize(max_size, 0);
+        #[cfg(feature = "instrument")]
+        let waiting = Instant::now();
+        let transfer = self.read_transfer(&mut buf, timeout);
+        let received = Instant::now();
+        let decoded =
+            transfer.map(|len| (len, GsUsbFrame::from_received(&buf[..len], hw_timestamps)));
+        self.rx_buf = buf;
+        let (len, decoded) = decoded?;
+        #[cfg(feature = "instrument")]
+        self.hot_path.transferred(received - waiting);
+
+        self.transfer_stats.record(len, max_size, &decoded);
+        let mut frame = decoded?;
+        if hw_timestamps && !self.python_can_compat {
+            frame.timestamp_us = self.ticks.scale(frame.timestamp_us);
+        }
+        let synced = match &self.time_sync {
+            Some(sync) if hw_timestamps => sync.to_host(frame.timestamp_us),
+            _ => None,
         };
-
-        // Determine if this is an FD frame by checking the flags byte (offset 10)
-        let is_fd_frame = if len >= 11 {
-            (buf[10] & GS_CAN_FLAG_FD) != 0
-        } else {
-            false
+        let (source, host_time) = match (self.timestamp_source, synced) {
+            (TimestampSource::Hardware, _) if hw_timestamps => (TimestampSource::Hardware, None),
+            (TimestampSource::Hardware | TimestampSource::None, _) => (TimestampSource::None, None),
+            (TimestampSource::SyncedHost, Some(synced)) => {
+                (TimestampSource::SyncedHost, Some(synced))
+            }
+            (TimestampSource::SyncedHost | TimestampSource::HostArrival, _) => {
+                (TimestampSource::HostArrival, Some(received))
+            }
         };
+        frame.set_timestamp(source, host_time);
+        if let (Some(tracker), Some(host_time)) = (&mut self.latency, host_time) {
+            if frame.is_echo_frame() {
+                tracker.finish(frame.echo_id, host_time);
+            }
+        }
+        #[cfg(feature = "instrument")]
+        self.hot_path.parsed(received);
+        self.taps.rx(&frame);
+        if frame.is_overflow() && frame.is_rx_frame() && !self.python_can_compat {
+            let overflow = RxOverflow {
+                channel: frame.channel,
+                timestamp_us: frame.timestamp_us,
+                lost_estimate: 1,
+            };
+            log::debug!("{overflow}");
+            self.after_overflow = Some(Queued::new(frame));
+            return if self.error_frames_as_errors {
+                Err(GsUsbError::RxOverflow(overflow))
+            } else {
+                let mut error_frame = overflow.to_error_frame();
+                error_frame.set_timestamp(source, host_time);
+                Ok(error_frame)
+            };
+        }
+        self.error_checked(frame)
+    }
 
-        Ok(GsUsbFrame::from_bytes(
-            &buf[..len],
-            hw_timestamps,
-            is_fd_frame,
-        ))
+    /// `frame`, or the `GsUsbError::BusError` it reports if error frames
+    /// are returned as errors
+    fn error_checked(&self, frame: GsUsbFrame) -> Result<GsUsbFrame> {
+        if self.error_frames_as_errors {
+            if let Some(err) = CanErrorFrame::from_frame(&frame) {
+                return Err(GsUsbError::BusError(err));
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Fail with `GsUsbError::Cancelled` if reads are cancelled
+    fn check_cancelled(&self) -> Result<()> {
+        match &self.cancel {
+            Some(token) if token.is_cancelled() => Err(GsUsbError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Bulk IN transfer of one frame, in slices while reads can be cancelled
+    fn read_transfer(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.check_cancelled()?;
+            let (slice, last) = match self.cancel {
+                Some(_) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let slice = remaining
+                        .min(CANCEL_POLL_INTERVAL)
+                        .max(MIN_TRANSFER_TIMEOUT);
+                    (slice, slice >= remaining)
+                }
+                None => (timeout.max(MIN_TRANSFER_TIMEOUT), true),
+            };
+            match self.transport.read_bulk(buf, slice) {
+                Ok(len) => return Ok(len),
+                Err(rusb::Error::Timeout) if !last => {}
+                Err(rusb::Error::Timeout) => return Err(GsUsbError::ReadTimeout),
+                Err(source) => {
+                    return Err(GsUsbError::BulkTransfer {
+                        endpoint: self.transport.interface().endpoint_in,
+                        channel: None,
+                        length: buf.len(),
+                        source,
+                    })
+                }
+            }
+        }
     }
 
     /// Get the USB bus number
@@ -379,24 +1658,19 @@ impl GsUsb {
             return Ok(sn.clone());
         }
 
-        let device = self.handle.device();
-        let desc = device.device_descriptor()?;
-
-        if desc.serial_number_string_index().is_some() {
-            let sn = self
-                .handle
-                .read_string_descriptor_ascii(desc.serial_number_string_index().unwrap())?;
-            self.serial_number = Some(sn.clone());
-            Ok(sn)
-        } else {
-            Ok(String::new())
+        match self.transport.serial_number()? {
+            Some(sn) => {
+                self.serial_number = Some(sn.clone());
+                Ok(sn)
+            }
+            None => Ok(String::new()),
         }
     }
 
     /// Get device information (channel count, firmware/hardware version)
     pub fn device_info(&mut self) -> Result<DeviceInfo> {
-        let data = self.control_in(GS_USB_BREQ_DEVICE_CONFIG, 0, 12)?;
-        Ok(DeviceInfo::unpack(&data))
+        let data = self.control_in::<{ DeviceInfo::SIZE }>(GS_USB_BREQ_DEVICE_CONFIG, 0)?;
+        DeviceInfo::unpack(&data)
     }
 
     /// Get device capability (bit timing constraints, feature flags)
@@ -405,12 +1679,47 @@ impl GsUsb {
             return Ok(*cap);
         }
 
-        let data = self.control_in(GS_USB_BREQ_BT_CONST, 0, 40)?;
-        let cap = DeviceCapability::unpack(&data);
+        let data = self.control_in::<{ DeviceCapability::SIZE }>(GS_USB_BREQ_BT_CONST, 0)?;
+        let mut cap = DeviceCapability::unpack(&data)?;
+        self.quirks()?.apply(&mut cap);
         self.capability = Some(cap);
         Ok(cap)
     }
 
+    /// Workarounds for known firmware bugs of the device, see `quirks`
+    ///
+    /// Looked up in `quirks::KNOWN_QUIRKS` by USB IDs, product string and
+    /// the firmware version of DEVICE_CONFIG, unless set with `set_quirks()`.
+    /// The capability reports features as corrected by them.
+    pub fn quirks(&mut self) -> Result<Quirks> {
+        if let Some(quirks) = self.quirks {
+            return Ok(quirks);
+        }
+        let usb_id = self.transport.vendor_product();
+        let product = self.transport.product().unwrap_or_else(|e| {
+            log::debug!("Reading the product string failed: {e}");
+            None
+        });
+        let fw_version = self.device_info()?.fw_version;
+        let quirks = quirks::lookup(usb_id, product.as_deref(), fw_version);
+        if !quirks.is_none() {
+            log::info!("Working around firmware quirks of {self}: {quirks}");
+        }
+        self.set_quirks(quirks);
+        Ok(quirks)
+    }
+
+    /// Use these workarounds instead of the looked up ones
+    ///
+    /// Takes effect on the next start; the capability is read again.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = Some(quirks);
+        self.capability = None;
+        if let Some(tick_hz) = quirks.timestamp_tick_hz {
+            self.ticks = TickScaler::new(tick_hz);
+        }
+    }
+
     /// Get extended device capability (includes CAN FD timing constraints)
     ///
     /// Returns `None` if device doesn't support BT_CONST_EXT
@@ -430,16 +1739,18 @@ impl GsUsb {
         }
 
         // Fetch extended capability and replace the basic one
-        let data = self.control_in(GS_USB_BREQ_BT_CONST_EXT, 0, 72)?;
-        let cap = DeviceCapability::unpack_extended(&data);
+        let data =
+            self.control_in::<{ DeviceCapability::EXTENDED_SIZE }>(GS_USB_BREQ_BT_CONST_EXT, 0)?;
+        let mut cap = DeviceCapability::unpack_extended(&data)?;
+        self.quirks()?.apply(&mut cap);
         self.capability = Some(cap);
         Ok(Some(cap))
     }
 
-    /// Check if device supports CAN FD
+    /// Check if device supports CAN FD, always false without the `fd` feature
     pub fn supports_fd(&mut self) -> Result<bool> {
         let cap = self.device_capability()?;
-        Ok((cap.feature & GS_CAN_FEATURE_FD) != 0)
+        Ok((cap.feature & GS_CAN_FEATURE_FD) != 0 && cfg!(feature = "fd"))
     }
 
     /// Check if device supports GET_STATE request
@@ -457,8 +1768,82 @@ impl GsUsb {
             return Err(GsUsbError::GetStateNotSupported);
         }
 
-        let data = self.control_in(GS_USB_BREQ_GET_STATE, channel, 12)?;
-        Ok(DeviceState::unpack(&data))
+        let data = self.control_in::<{ DeviceState::SIZE }>(GS_USB_BREQ_GET_STATE, channel)?;
+        DeviceState::unpack(&data)
+    }
+
+    /// Check whether the termination resistor of `channel` is switched on
+    ///
+    /// Needs `GS_CAN_FEATURE_TERMINATION`; most adapters have a fixed
+    /// resistor or a jumper instead.
+    pub fn termination(&mut self, channel: u16) -> Result<bool> {
+        self.require_feature(GS_CAN_FEATURE_TERMINATION, "termination control")?;
+        let data = self.control_in(GS_USB_BREQ_GET_TERMINATION, channel)?;
+        Ok(u32::from_le_bytes(data) != 0)
+    }
+
+    /// Switch the termination resistor of `channel` on or off
+    pub fn set_termination(&mut self, channel: u16, on: bool) -> Result<()> {
+        self.require_feature(GS_CAN_FEATURE_TERMINATION, "termination control")?;
+        self.control_out(
+            GS_USB_BREQ_SET_TERMINATION,
+            channel,
+            &u32::from(on).to_le_bytes(),
+        )
+    }
+
+    /// Start or stop blinking the LED of `channel`
+    ///
+    /// Tells otherwise identical adapters apart on the desk. The firmware
+    /// keeps blinking until told to stop.
+    pub fn identify(&mut self, channel: u16, on: bool) -> Result<()> {
+        self.require_feature(GS_CAN_FEATURE_IDENTIFY, "identify")?;
+        self.control_out(GS_USB_BREQ_IDENTIFY, channel, &u32::from(on).to_le_bytes())
+    }
+
+    /// Read the user ID stored in the device
+    ///
+    /// Devices with `GS_CAN_FEATURE_USER_ID` keep a 32 bit number chosen by
+    /// the user across power cycles, which gives adapters without unique
+    /// serial numbers a stable identity (see `open_by_user_id()`).
+    pub fn user_id(&mut self) -> Result<u32> {
+        self.require_feature(GS_CAN_FEATURE_USER_ID, "user ID")?;
+        let data = self.control_in(GS_USB_BREQ_GET_USER_ID, 0)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
+    /// Store a user ID in the device
+    pub fn set_user_id(&mut self, user_id: u32) -> Result<()> {
+        self.require_feature(GS_CAN_FEATURE_USER_ID, "user ID")?;
+        self.control_out(GS_USB_BREQ_SET_USER_ID, 0, &user_id.to_le_bytes())
+    }
+
+    /// Fail with `FeatureNotSupported(name)` unless the device has `feature`
+    fn require_feature(&mut self, feature: u32, name: &'static str) -> Result<()> {
+        if self.device_capability()?.feature & feature == 0 {
+            return Err(GsUsbError::FeatureNotSupported(name));
+        }
+        Ok(())
+    }
+
+    /// Read the current value of the device's hardware timestamp counter
+    ///
+    /// Microseconds in the same time base as `GsUsbFrame::timestamp_us`. See
+    /// `TimeSync` for relating it to host time.
+    pub fn device_timestamp(&mut self) -> Result<u32> {
+        let raw = self.raw_device_timestamp()?;
+        Ok(self.ticks.scale(raw))
+    }
+
+    /// Read the hardware timestamp counter without scaling it
+    fn raw_device_timestamp(&mut self) -> Result<u32> {
+        let cap = self.device_capability()?;
+        if (cap.feature & GS_CAN_FEATURE_HW_TIMESTAMP) == 0 {
+            return Err(GsUsbError::FeatureNotSupported("hardware timestamps"));
+        }
+
+        let data = self.control_in(GS_USB_BREQ_TIMESTAMP, 0)?;
+        Ok(u32::from_le_bytes(data))
     }
 
     /// Send HOST_FORMAT request (legacy requirement)
@@ -473,65 +1858,60 @@ impl GsUsb {
     }
 
     /// Perform a control OUT transfer
-    fn control_out(&self, request: u8, value: u16, data: &[u8]) -> Result<()> {
-        self.handle
-            .write_control(
-                0x41, // bmRequestType: vendor, host-to-device
-                request,
-                value,
-                0, // wIndex
-                data,
-                Duration::from_millis(1000),
-            )
-            .map_err(GsUsbError::ControlTransfer)?;
-        Ok(())
+    fn control_out(&mut self, request: u8, value: u16, data: &[u8]) -> Result<()> {
+        let transport = &mut self.transport;
+        self.retry.run(&SystemClock, || {
+            transport
+                .write_control(request, value, data, Duration::from_millis(1000))
+                .map_err(|source| GsUsbError::ControlTransfer {
+                    request,
+                    value,
+                    length: data.len(),
+                    source,
+                })?;
+            Ok(())
+        })
     }
 
-    /// Perform a control IN transfer
-    fn control_in(&self, request: u8, value: u16, length: usize) -> Result<Vec<u8>> {
-        let mut buf = vec![0u8; length];
-        let len = self
-            .handle
-            .read_control(
-                0xC1, // bmRequestType: vendor, device-to-host
-                request,
-                value,
-                0, // wIndex
-                &mut buf,
-                Duration::from_millis(1000),
-            )
-            .map_err(GsUsbError::ControlTransfer)?;
-
-        if len < length {
-            return Err(GsUsbError::InvalidResponse {
-                expected: length,
-                actual: len,
-            });
-        }
+    /// Perform a control IN transfer of `N` bytes, into a buffer on the
+    /// stack
+    fn control_in<const N: usize>(&mut self, request: u8, value: u16) -> Result<[u8; N]> {
+        const { assert!(N <= MAX_CONTROL_IN_LEN) };
+        let transport = &mut self.transport;
+        self.retry.run(&SystemClock, || {
+            let mut buf = [0u8; N];
+            let len = transport
+                .read_control(request, value, &mut buf, Duration::from_millis(1000))
+                .map_err(|source| GsUsbError::ControlTransfer {
+                    request,
+                    value,
+                    length: N,
+                    source,
+                })?;
+
+            if len < N {
+                return Err(GsUsbError::InvalidResponse {
+                    expected: N,
+                    actual: len,
+                });
+            }
 
-        Ok(buf)
+            Ok(buf)
+        })
     }
 
     /// Check if a USB device is a GS-USB device
     fn is_gs_usb_device(vendor_id: u16, product_id: u16) -> bool {
-        matches!(
-            (vendor_id, product_id),
-            (GS_USB_ID_VENDOR, GS_USB_ID_PRODUCT)
-                | (GS_USB_CANDLELIGHT_VENDOR_ID, GS_USB_CANDLELIGHT_PRODUCT_ID)
-                | (
-                    GS_USB_CES_CANEXT_FD_VENDOR_ID,
-                    GS_USB_CES_CANEXT_FD_PRODUCT_ID
-                )
-                | (
-                    GS_USB_ABE_CANDEBUGGER_FD_VENDOR_ID,
-                    GS_USB_ABE_CANDEBUGGER_FD_PRODUCT_ID
-                )
-        )
+        GS_USB_KNOWN_DEVICES
+            .iter()
+            .any(|&(vendor, product, _)| (vendor, product) == (vendor_id, product_id))
     }
 
     /// Scan for GS-USB devices
     ///
-    /// Returns a list of all connected GS-USB compatible devices.
+    /// Returns a list of all connected GS-USB compatible devices. Devices that
+    /// can't be opened are skipped; if the cause is a host setup problem
+    /// (see `platform::diagnose()`) it is logged as a warning.
     pub fn scan() -> Result<Vec<GsUsb>> {
         let mut devices = Vec::new();
 
@@ -544,16 +1924,40 @@ impl GsUsb {
             if Self::is_gs_usb_device(desc.vendor_id(), desc.product_id()) {
                 let handle = match device.open() {
                     Ok(handle) => handle,
-                    Err(_) => continue,
+                    Err(e) => {
+                        if let Some(issue) =
+                            crate::platform::diagnose(e, device.bus_number(), device.address())
+                        {
+                            log::warn!(
+                                "Skipping GS-USB device (bus {}, addr {}): {}",
+                                device.bus_number(),
+                                device.address(),
+                                issue
+                            );
+                        }
+                        continue;
+                    }
                 };
 
-                devices.push(GsUsb::new(handle, device.bus_number(), device.address()));
+                devices.push(GsUsb::new(
+                    Box::new(UsbTransport::new(handle)),
+                    device.bus_number(),
+                    device.address(),
+                ));
             }
         }
 
         Ok(devices)
     }
 
+    /// Open the first device that has the given user ID
+    ///
+    /// Fails with `GsUsbError::DeviceNotFound` if no connected device
+    /// reports it. See `set_user_id()`.
+    pub fn open_by_user_id(user_id: u32) -> Result<GsUsb> {
+        Self::builder().user_id(user_id).open()
+    }
+
     /// Find a specific GS-USB device by bus and address
     pub fn find(bus: u8, address: u8) -> Result<Option<GsUsb>> {
         for device in rusb::devices()?.iter() {
@@ -567,8 +1971,14 @@ impl GsUsb {
             };
 
             if Self::is_gs_usb_device(desc.vendor_id(), desc.product_id()) {
-                let handle = device.open()?;
-                return Ok(Some(GsUsb::new(handle, bus, address)));
+                let handle = device
+                    .open()
+                    .map_err(|e| crate::platform::access_error(e, bus, address, GsUsbError::Usb))?;
+                return Ok(Some(GsUsb::new(
+                    Box::new(UsbTransport::new(handle)),
+                    bus,
+                    address,
+                )));
             }
         }
 
@@ -578,15 +1988,11 @@ impl GsUsb {
 
 impl std::fmt::Display for GsUsb {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let device = self.handle.device();
-        if let Ok(desc) = device.device_descriptor() {
+        if let Some((vendor_id, product_id)) = self.transport.vendor_product() {
             write!(
                 f,
                 "GS-USB {:04x}:{:04x} (bus {}, addr {})",
-                desc.vendor_id(),
-                desc.product_id(),
-                self.bus,
-                self.address
+                vendor_id, product_id, self.bus, self.address
             )
         } else {
             write!(f, "GS-USB (bus {}, addr {})", self.bus, self.address)
@@ -602,6 +2008,7 @@ impl std::fmt::Debug for GsUsb {
             .field("started", &self.started)
             .field("fd_mode", &self.fd_mode)
             .field("device_flags", &format_args!("0x{:08x}", self.device_flags))
+            .field("taps", &self.taps.len())
             .finish()
     }
 }
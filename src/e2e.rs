@@ -0,0 +1,686 @@
+//! AUTOSAR E2E protection
+//!
+//! Production ECUs protect safety-relevant messages with a CRC and an alive
+//! counter, and drop or flag messages whose CRC doesn't match or whose
+//! counter doesn't move on. `E2eProtection` stamps both into payloads before
+//! they are sent and verifies them in received ones, for one message laid
+//! out as described by an `E2eConfig`. `E2eSet` keeps one per identifier, so
+//! a test bench can protect and check all its messages in one place.
+//!
+//! Configurations for the common profiles are built in:
+//!
+//! | Profile | CRC                                | Counter                | Data ID                     |
+//! |---------|------------------------------------|------------------------|-----------------------------|
+//! | 1       | CRC-8 SAE J1850, start and XOR 0   | 4 bits, 0-14           | 16 bits before the payload  |
+//! | 2       | CRC-8H2F in byte 0                 | low nibble of byte 1   | one byte per counter value  |
+//! | 5       | CRC-16 CCITT-FALSE, little endian  | byte after the CRC     | 16 bits after the payload   |
+//!
+//! Profile 1 is supported in the "both" data ID mode only. Other layouts and
+//! CRCs can be described by filling in an `E2eConfig`.
+//!
+//! Messages outside E2E often still carry a plain alive counter and a
+//! checksum over some bytes. `CyclicFields` describes those, for the
+//! periodic messages of a `PassThruChannel` to update on every send.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{CanId, E2eConfig, E2eSet, GsUsb, GsUsbFrame, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let mut e2e = E2eSet::new();
+//! e2e.add(CanId::Standard(0x100), E2eConfig::profile1(0x0123));
+//! e2e.add(CanId::Standard(0x200), E2eConfig::profile5(0x0456));
+//!
+//! let mut frame = GsUsbFrame::with_data(0x100, &[0, 0, 0x12, 0x34, 0, 0, 0, 0]);
+//! e2e.protect(&mut frame)?;
+//! dev.send(&frame)?;
+//!
+//! let frame = dev.read(Duration::from_secs(1))?;
+//! if let Some(status) = e2e.check(&frame) {
+//!     println!("{}: {status:?}", CanId::from_can_id(frame.can_id));
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::id::CanId;
+
+/// Parameters of a CRC of 8 or 16 bits, without bit reflection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc {
+    /// Width in bits, 8 or 16
+    pub width: u8,
+    /// Generator polynomial, without the highest bit
+    pub poly: u16,
+    /// Start value
+    pub init: u16,
+    /// Value the result is XORed with
+    pub xor_out: u16,
+}
+
+impl Crc {
+    /// CRC-8 SAE J1850, AUTOSAR's `Crc_CalculateCRC8`
+    pub const CRC8_SAE_J1850: Crc = Crc {
+        width: 8,
+        poly: 0x1D,
+        init: 0xFF,
+        xor_out: 0xFF,
+    };
+
+    /// CRC-8 SAE J1850 with start and XOR value 0, as profile 1 uses it
+    pub const CRC8_SAE_J1850_ZERO: Crc = Crc {
+        width: 8,
+        poly: 0x1D,
+        init: 0x00,
+        xor_out: 0x00,
+    };
+
+    /// CRC-8H2F, AUTOSAR's `Crc_CalculateCRC8H2F`
+    pub const CRC8_H2F: Crc = Crc {
+        width: 8,
+        poly: 0x2F,
+        init: 0xFF,
+        xor_out: 0xFF,
+    };
+
+    /// CRC-16 CCITT-FALSE, AUTOSAR's `Crc_CalculateCRC16`
+    pub const CRC16_CCITT_FALSE: Crc = Crc {
+        width: 16,
+        poly: 0x1021,
+        init: 0xFFFF,
+        xor_out: 0x0000,
+    };
+
+    /// Bytes the CRC takes in a payload
+    pub fn bytes(&self) -> usize {
+        usize::from(self.width / 8)
+    }
+
+    /// CRC of `data`
+    pub fn checksum(&self, data: &[u8]) -> u16 {
+        self.checksum_parts(&[data])
+    }
+
+    /// CRC of the concatenated `parts`
+    fn checksum_parts(&self, parts: &[&[u8]]) -> u16 {
+        let top = 1u16 << (self.width - 1);
+        let mask = if self.width == 16 { 0xFFFF } else { 0xFF };
+        let mut crc = self.init & mask;
+        for &byte in parts.iter().flat_map(|part| part.iter()) {
+            crc ^= u16::from(byte) << (self.width - 8);
+            for _ in 0..8 {
+                crc = if crc & top != 0 {
+                    (crc << 1) ^ self.poly
+                } else {
+                    crc << 1
+                } & mask;
+            }
+        }
+        (crc ^ self.xor_out) & mask
+    }
+}
+
+/// How the data ID of a message goes into its CRC
+///
+/// The data ID is never sent, so a receiver only accepts a message if it
+/// was computed with the ID it expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataId {
+    /// No data ID
+    None,
+    /// Low and high byte, before the payload
+    Before(u16),
+    /// Low and high byte, after the payload
+    After(u16),
+    /// One byte after the payload, picked from the list by the counter
+    PerCounter([u8; 16]),
+}
+
+/// Layout and parameters of an E2E protected message
+///
+/// The CRC is computed over the payload without the CRC bytes, with the
+/// data ID added as configured; a 16 bit CRC is stored little endian. The
+/// counter counts from 0 to `counter_max` and wraps to 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct E2eConfig {
+    /// The CRC
+    pub crc: Crc,
+    /// Offset of the CRC in the payload in bytes
+    pub crc_offset: usize,
+    /// Offset of the counter in the payload in bits, bit 0 being the least
+    /// significant bit of byte 0
+    pub counter_offset: usize,
+    /// Width of the counter in bits, within one byte
+    pub counter_bits: u8,
+    /// Largest counter value
+    pub counter_max: u8,
+    /// Data ID added to the CRC
+    pub data_id: DataId,
+    /// Largest counter step accepted as new data, with the frames in between
+    /// lost
+    pub max_delta_counter: u8,
+}
+
+impl E2eConfig {
+    /// Profile 1: CRC in byte 0, counter in the low nibble of byte 1
+    pub fn profile1(data_id: u16) -> Self {
+        Self {
+            crc: Crc::CRC8_SAE_J1850_ZERO,
+            crc_offset: 0,
+            counter_offset: 8,
+            counter_bits: 4,
+            counter_max: 14,
+            data_id: DataId::Before(data_id),
+            max_delta_counter: 1,
+        }
+    }
+
+    /// Profile 2: CRC in byte 0, counter in the low nibble of byte 1, the
+    /// data ID picked from `data_ids` by the counter
+    pub fn profile2(data_ids: [u8; 16]) -> Self {
+        Self {
+            crc: Crc::CRC8_H2F,
+            crc_offset: 0,
+            counter_offset: 8,
+            counter_bits: 4,
+            counter_max: 15,
+            data_id: DataId::PerCounter(data_ids),
+            max_delta_counter: 1,
+        }
+    }
+
+    /// Profile 5: CRC in bytes 0 and 1, counter in byte 2
+    ///
+    /// Set `crc_offset` and `counter_offset` to move them.
+    pub fn profile5(data_id: u16) -> Self {
+        Self {
+            crc: Crc::CRC16_CCITT_FALSE,
+            crc_offset: 0,
+            counter_offset: 16,
+            counter_bits: 8,
+            counter_max: 255,
+            data_id: DataId::After(data_id),
+            max_delta_counter: 1,
+        }
+    }
+
+    /// Shortest payload that holds the CRC and the counter
+    pub fn min_len(&self) -> usize {
+        (self.crc_offset + self.crc.bytes()).max(self.counter_offset / 8 + 1)
+    }
+
+    fn read_counter(&self, data: &[u8]) -> u8 {
+        read_bits(data, self.counter_offset, self.counter_bits)
+    }
+
+    fn write_counter(&self, data: &mut [u8], counter: u8) {
+        write_bits(data, self.counter_offset, self.counter_bits, counter);
+    }
+
+    fn read_crc(&self, data: &[u8]) -> u16 {
+        let bytes = &data[self.crc_offset..self.crc_offset + self.crc.bytes()];
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |crc, &byte| (crc << 8) | u16::from(byte))
+    }
+
+    fn write_crc(&self, data: &mut [u8], crc: u16) {
+        write_le(data, self.crc_offset, self.crc.bytes(), crc);
+    }
+
+    /// CRC of a payload whose counter is already set
+    fn compute_crc(&self, data: &[u8]) -> u16 {
+        let before = &data[..self.crc_offset];
+        let after = &data[self.crc_offset + self.crc.bytes()..];
+        match self.data_id {
+            DataId::None => self.crc.checksum_parts(&[before, after]),
+            DataId::Before(id) => self.crc.checksum_parts(&[&id.to_le_bytes(), before, after]),
+            DataId::After(id) => self.crc.checksum_parts(&[before, after, &id.to_le_bytes()]),
+            DataId::PerCounter(ids) => {
+                let id = ids[usize::from(self.read_counter(data) & 0x0F)];
+                self.crc.checksum_parts(&[before, after, &[id]])
+            }
+        }
+    }
+}
+
+fn counter_mask(bits: u8) -> u8 {
+    (0xFFu16 >> 8u8.saturating_sub(bits)) as u8
+}
+
+/// Read `bits` bits at bit `offset`, within one byte
+fn read_bits(data: &[u8], offset: usize, bits: u8) -> u8 {
+    (data[offset / 8] >> (offset % 8)) & counter_mask(bits)
+}
+
+/// Write `bits` bits of `value` at bit `offset`, within one byte
+fn write_bits(data: &mut [u8], offset: usize, bits: u8, value: u8) {
+    let shift = offset % 8;
+    let mask = counter_mask(bits) << shift;
+    let byte = &mut data[offset / 8];
+    *byte = (*byte & !mask) | ((value << shift) & mask);
+}
+
+/// Write the low `len` bytes of `value` little endian at byte `offset`
+fn write_le(data: &mut [u8], offset: usize, len: usize, value: u16) {
+    data[offset..offset + len].copy_from_slice(&value.to_le_bytes()[..len]);
+}
+
+/// Result of checking a received payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E2eStatus {
+    /// CRC correct, counter one past the previous one, or the first payload
+    Ok,
+    /// CRC correct, counter moved on by more than one but no more than
+    /// `max_delta_counter`
+    OkSomeLost {
+        /// Payloads missed in between
+        lost: u8,
+    },
+    /// CRC correct, counter unchanged
+    Repeated,
+    /// CRC correct, counter moved on by more than `max_delta_counter`
+    WrongSequence,
+    /// CRC mismatch; the counter is ignored
+    WrongCrc,
+    /// Payload too short for the layout
+    TooShort,
+}
+
+impl E2eStatus {
+    /// Check if the payload is new and valid
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Self::Ok | Self::OkSomeLost { .. })
+    }
+}
+
+/// Protection state of one message: the counter to send next and the
+/// counter last received
+#[derive(Debug, Clone)]
+pub struct E2eProtection {
+    config: E2eConfig,
+    next_counter: u8,
+    last_counter: Option<u8>,
+}
+
+impl E2eProtection {
+    /// Start with counter 0 and nothing received
+    pub fn new(config: E2eConfig) -> Self {
+        Self {
+            config,
+            next_counter: 0,
+            last_counter: None,
+        }
+    }
+
+    /// The layout
+    pub fn config(&self) -> &E2eConfig {
+        &self.config
+    }
+
+    /// Stamp the counter and the CRC into `data`, then advance the counter
+    ///
+    /// Fails with `InvalidConfig` if `data` is too short for the layout.
+    pub fn protect(&mut self, data: &mut [u8]) -> Result<()> {
+        let config = &self.config;
+        if data.len() < config.min_len() {
+            return Err(GsUsbError::InvalidConfig(format!(
+                "E2E payload of {} bytes, at least {} needed",
+                data.len(),
+                config.min_len()
+            )));
+        }
+        config.write_counter(data, self.next_counter);
+        let crc = config.compute_crc(data);
+        config.write_crc(data, crc);
+        self.next_counter = if self.next_counter >= config.counter_max {
+            0
+        } else {
+            self.next_counter + 1
+        };
+        Ok(())
+    }
+
+    /// Verify the CRC and the counter of a received payload
+    pub fn check(&mut self, data: &[u8]) -> E2eStatus {
+        let config = &self.config;
+        if data.len() < config.min_len() {
+            return E2eStatus::TooShort;
+        }
+        if config.read_crc(data) != config.compute_crc(data) {
+            return E2eStatus::WrongCrc;
+        }
+        let counter = config.read_counter(data);
+        let Some(last) = self.last_counter.replace(counter) else {
+            return E2eStatus::Ok;
+        };
+        let range = u16::from(config.counter_max) + 1;
+        let delta = (u16::from(counter) + range - u16::from(last)) % range;
+        match delta {
+            0 => E2eStatus::Repeated,
+            1 => E2eStatus::Ok,
+            delta if delta <= u16::from(config.max_delta_counter) => E2eStatus::OkSomeLost {
+                lost: (delta - 1) as u8,
+            },
+            _ => E2eStatus::WrongSequence,
+        }
+    }
+
+    /// Start over with counter 0 and nothing received
+    pub fn reset(&mut self) {
+        self.next_counter = 0;
+        self.last_counter = None;
+    }
+}
+
+/// E2E protection of several messages, by identifier
+#[derive(Debug, Clone, Default)]
+pub struct E2eSet {
+    messages: BTreeMap<CanId, E2eProtection>,
+}
+
+impl E2eSet {
+    /// Create a set without messages
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Protect the frames of `id` as configured, replacing an earlier
+    /// configuration and its counters
+    pub fn add(&mut self, id: CanId, config: E2eConfig) {
+        self.messages.insert(id, E2eProtection::new(config));
+    }
+
+    /// Stop protecting the frames of `id`
+    pub fn remove(&mut self, id: CanId) {
+        self.messages.remove(&id);
+    }
+
+    /// Protection state of `id`
+    pub fn get(&self, id: CanId) -> Option<&E2eProtection> {
+        self.messages.get(&id)
+    }
+
+    /// Stamp the counter and CRC into a frame to send
+    ///
+    /// Returns whether the frame's identifier is protected; frames of other
+    /// identifiers are left alone.
+    pub fn protect(&mut self, frame: &mut GsUsbFrame) -> Result<bool> {
+        let Some(protection) = self.messages.get_mut(&CanId::from_can_id(frame.can_id)) else {
+            return Ok(false);
+        };
+        let len = frame.data_length();
+        protection.protect(&mut frame.data[..len])?;
+        Ok(true)
+    }
+
+    /// Verify a received frame, `None` if its identifier isn't protected
+    ///
+    /// Error and remote frames are not checked.
+    pub fn check(&mut self, frame: &GsUsbFrame) -> Option<E2eStatus> {
+        if frame.is_error_frame() || frame.is_remote_frame() {
+            return None;
+        }
+        let protection = self.messages.get_mut(&CanId::from_can_id(frame.can_id))?;
+        Some(protection.check(frame.data()))
+    }
+
+    /// Start all counters over
+    pub fn reset(&mut self) {
+        self.messages.values_mut().for_each(E2eProtection::reset);
+    }
+}
+
+/// Checksum algorithm of a `ChecksumField`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// A CRC of 8 or 16 bits
+    Crc(Crc),
+    /// Sum of the bytes, modulo 256
+    Sum8,
+    /// XOR of the bytes
+    Xor8,
+}
+
+impl Checksum {
+    /// Bytes the checksum takes in a payload
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::Crc(crc) => crc.bytes(),
+            Self::Sum8 | Self::Xor8 => 1,
+        }
+    }
+
+    fn compute(&self, parts: &[&[u8]]) -> u16 {
+        let bytes = parts.iter().flat_map(|part| part.iter());
+        match self {
+            Self::Crc(crc) => crc.checksum_parts(parts),
+            Self::Sum8 => u16::from(bytes.fold(0u8, |sum, &b| sum.wrapping_add(b))),
+            Self::Xor8 => u16::from(bytes.fold(0u8, |sum, &b| sum ^ b)),
+        }
+    }
+}
+
+/// Alive counter in a payload, counting from 0 to `max` and wrapping to 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CounterField {
+    /// Offset in bits, bit 0 being the least significant bit of byte 0
+    pub offset: usize,
+    /// Width in bits, within one byte
+    pub bits: u8,
+    /// Largest value
+    pub max: u8,
+}
+
+/// Checksum in a payload
+///
+/// The checksum covers the bytes in `range`, skipping its own bytes if they
+/// lie within; a 16 bit CRC is stored little endian.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumField {
+    /// The algorithm
+    pub checksum: Checksum,
+    /// Offset of the checksum in bytes
+    pub offset: usize,
+    /// Bytes covered
+    pub range: Range<usize>,
+}
+
+/// Alive counter and checksum of a cyclically sent message, see
+/// `PassThruChannel::start_periodic_msg_with_fields()`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CyclicFields {
+    /// Counter advanced with every transmission
+    pub counter: Option<CounterField>,
+    /// Checksum computed after the counter is set
+    pub checksum: Option<ChecksumField>,
+}
+
+impl CyclicFields {
+    /// Shortest payload that holds the fields
+    pub fn min_len(&self) -> usize {
+        let counter = self.counter.map_or(0, |counter| counter.offset / 8 + 1);
+        let checksum = self.checksum.as_ref().map_or(0, |field| {
+            (field.offset + field.checksum.bytes()).max(field.range.end)
+        });
+        counter.max(checksum)
+    }
+
+    /// Write `counter` and then the checksum into `data`
+    ///
+    /// Fails with `InvalidConfig` if `data` is too short for the fields.
+    pub fn stamp(&self, data: &mut [u8], counter: u8) -> Result<()> {
+        if data.len() < self.min_len() {
+            return Err(GsUsbError::InvalidConfig(format!(
+                "payload of {} bytes, at least {} needed for the counter and checksum",
+                data.len(),
+                self.min_len()
+            )));
+        }
+        if let Some(field) = self.counter {
+            write_bits(data, field.offset, field.bits, counter);
+        }
+        if let Some(field) = &self.checksum {
+            let (start, end) = (field.range.start, field.range.end);
+            let own = field.offset..field.offset + field.checksum.bytes();
+            let before = &data[start..end.min(own.start).max(start)];
+            let after = &data[own.end.max(start).min(end)..end];
+            let value = field.checksum.compute(&[before, after]);
+            write_le(data, field.offset, field.checksum.bytes(), value);
+        }
+        Ok(())
+    }
+
+    /// Counter value following `counter`
+    pub fn next_counter(&self, counter: u8) -> u8 {
+        match self.counter {
+            Some(field) if counter < field.max.min(counter_mask(field.bits)) => counter + 1,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_check_values() {
+        let check = b"123456789";
+        assert_eq!(Crc::CRC8_SAE_J1850.checksum(check), 0x4B);
+        assert_eq!(Crc::CRC8_H2F.checksum(check), 0xDF);
+        assert_eq!(Crc::CRC16_CCITT_FALSE.checksum(check), 0x29B1);
+        assert_eq!(Crc::CRC8_SAE_J1850_ZERO.checksum(check), 0x37);
+    }
+
+    #[test]
+    fn test_profile1() {
+        let config = E2eConfig::profile1(0x0123);
+        let mut tx = E2eProtection::new(config);
+        let mut rx = E2eProtection::new(config);
+        let mut data = [0, 0, 0x12, 0x34, 0, 0, 0, 0];
+        tx.protect(&mut data).unwrap();
+        assert_eq!(data[1] & 0x0F, 0);
+        let crc = Crc::CRC8_SAE_J1850_ZERO.checksum(&[0x23, 0x01, 0x00, 0x12, 0x34, 0, 0, 0, 0]);
+        assert_eq!(u16::from(data[0]), crc);
+        assert_eq!(rx.check(&data), E2eStatus::Ok);
+        assert_eq!(rx.check(&data), E2eStatus::Repeated);
+
+        // Another data ID gives another CRC
+        let mut other = E2eProtection::new(E2eConfig::profile1(0x0124));
+        assert_eq!(other.check(&data), E2eStatus::WrongCrc);
+
+        // The counter wraps from 14 to 0
+        for _ in 0..15 {
+            tx.protect(&mut data).unwrap();
+            assert_eq!(rx.check(&data), E2eStatus::Ok);
+        }
+        assert_eq!(data[1] & 0x0F, 0);
+        assert_eq!(rx.check(&data[..1]), E2eStatus::TooShort);
+    }
+
+    #[test]
+    fn test_profile2_and_5() {
+        let data_ids: [u8; 16] = std::array::from_fn(|i| i as u8 * 3);
+        let mut tx = E2eProtection::new(E2eConfig::profile2(data_ids));
+        let mut data = [0, 0xA0, 1, 2, 3, 4, 5, 6];
+        tx.protect(&mut data).unwrap();
+        tx.protect(&mut data).unwrap();
+        assert_eq!(data[1], 0xA1);
+        let crc = Crc::CRC8_H2F.checksum(&[0xA1, 1, 2, 3, 4, 5, 6, 3]);
+        assert_eq!(u16::from(data[0]), crc);
+
+        let mut tx = E2eProtection::new(E2eConfig::profile5(0x1234));
+        let mut data = [0; 8];
+        data[3] = 0x55;
+        tx.protect(&mut data).unwrap();
+        let crc = Crc::CRC16_CCITT_FALSE.checksum(&[0, 0x55, 0, 0, 0, 0, 0x34, 0x12]);
+        assert_eq!(data[..3], [crc as u8, (crc >> 8) as u8, 0]);
+    }
+
+    #[test]
+    fn test_sequence() {
+        let config = E2eConfig {
+            max_delta_counter: 3,
+            ..E2eConfig::profile5(0x1234)
+        };
+        let mut tx = E2eProtection::new(config);
+        let mut rx = E2eProtection::new(config);
+        let mut send = || {
+            let mut data = [0; 4];
+            tx.protect(&mut data).unwrap();
+            data
+        };
+        assert_eq!(rx.check(&send()), E2eStatus::Ok);
+        send();
+        send();
+        assert_eq!(rx.check(&send()), E2eStatus::OkSomeLost { lost: 2 });
+        for _ in 0..3 {
+            send();
+        }
+        assert_eq!(rx.check(&send()), E2eStatus::WrongSequence);
+        assert_eq!(rx.check(&send()), E2eStatus::Ok);
+
+        let mut data = send();
+        data[3] ^= 1;
+        assert_eq!(rx.check(&data), E2eStatus::WrongCrc);
+        assert!(!E2eStatus::WrongCrc.is_ok());
+    }
+
+    #[test]
+    fn test_cyclic_fields() {
+        let mut fields = CyclicFields {
+            counter: Some(CounterField {
+                offset: 56,
+                bits: 8,
+                max: 255,
+            }),
+            checksum: Some(ChecksumField {
+                checksum: Checksum::Crc(Crc::CRC16_CCITT_FALSE),
+                offset: 0,
+                range: 0..8,
+            }),
+        };
+        let mut data = [0, 0, 1, 2, 3, 4, 5, 0];
+        fields.stamp(&mut data, 7).unwrap();
+        let crc = Crc::CRC16_CCITT_FALSE.checksum(&[1, 2, 3, 4, 5, 7]);
+        assert_eq!(data, [crc as u8, (crc >> 8) as u8, 1, 2, 3, 4, 5, 7]);
+        assert_eq!(fields.next_counter(255), 0);
+        assert!(fields.stamp(&mut data[..7], 0).is_err());
+
+        fields.checksum = Some(ChecksumField {
+            checksum: Checksum::Xor8,
+            offset: 0,
+            range: 2..4,
+        });
+        fields.stamp(&mut data, 0).unwrap();
+        assert_eq!(data[0], 1 ^ 2);
+        assert_eq!(CyclicFields::default().next_counter(3), 0);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut set = E2eSet::new();
+        set.add(CanId::Standard(0x100), E2eConfig::profile1(0x0123));
+        let mut frame = GsUsbFrame::with_data(0x100, &[0; 8]);
+        assert!(set.protect(&mut frame).unwrap());
+        assert_eq!(set.check(&frame), Some(E2eStatus::Ok));
+
+        let mut other = GsUsbFrame::with_data(0x200, &[0; 8]);
+        assert!(!set.protect(&mut other).unwrap());
+        assert_eq!(set.check(&other), None);
+
+        let mut short = GsUsbFrame::with_data(0x100, &[0]);
+        assert!(set.protect(&mut short).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! embedded-can trait implementations
+//!
+//! `GsUsbFrame` implements `embedded_can::Frame` and `GsUsb` the blocking
+//! `embedded_can::blocking::Can`, so drivers written generically against
+//! these traits run on gs_usb hardware as well as on microcontroller CAN
+//! peripherals. `CanId` converts to and from `embedded_can::Id`.
+//!
+//! Enabled with the `embedded-can` cargo feature.
+//!
+//! ```no_run
+//! use embedded_can::blocking::Can;
+//! use embedded_can::{Frame, StandardId};
+//! use gs_usb::{GsUsb, GS_CAN_MODE_NORMAL};
+//!
+//! // A driver that only knows the traits
+//! fn ping<C: Can>(can: &mut C) -> Result<C::Frame, C::Error> {
+//!     let id = StandardId::new(0x7DF).unwrap();
+//!     can.transmit(&C::Frame::new(id, &[0x02, 0x01, 0x00]).unwrap())?;
+//!     can.receive()
+//! }
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//! println!("{}", ping(&mut dev)?);
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::time::Duration;
+
+use embedded_can::{ExtendedId, StandardId};
+
+use crate::constants::{
+    CAN_EFF_MASK, CAN_ERR_ACK, CAN_MAX_DLC, CAN_MAX_DLEN, CAN_RTR_FLAG, CAN_SFF_MASK,
+};
+use crate::device::GsUsb;
+use crate::error::GsUsbError;
+use crate::frame::GsUsbFrame;
+use crate::id::CanId;
+
+/// Read timeout of `Can::receive()`, which waits until a frame arrives
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+impl From<CanId> for embedded_can::Id {
+    /// The identifier, with bits above its 11 or 29 bits masked off
+    fn from(id: CanId) -> Self {
+        match id {
+            CanId::Standard(raw) => {
+                Self::Standard(StandardId::new(raw & CAN_SFF_MASK as u16).unwrap())
+            }
+            CanId::Extended(raw) => Self::Extended(ExtendedId::new(raw & CAN_EFF_MASK).unwrap()),
+        }
+    }
+}
+
+impl From<embedded_can::Id> for CanId {
+    fn from(id: embedded_can::Id) -> Self {
+        match id {
+            embedded_can::Id::Standard(id) => Self::Standard(id.as_raw()),
+            embedded_can::Id::Extended(id) => Self::Extended(id.as_raw()),
+        }
+    }
+}
+
+impl embedded_can::Frame for GsUsbFrame {
+    fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+        (data.len() <= CAN_MAX_DLEN)
+            .then(|| GsUsbFrame::with_data(CanId::from(id.into()).to_can_id(), data))
+    }
+
+    fn new_remote(id: impl Into<embedded_can::Id>, dlc: usize) -> Option<Self> {
+        (dlc <= CAN_MAX_DLEN).then(|| {
+            let mut frame = GsUsbFrame::new();
+            frame.can_id = CanId::from(id.into()).to_can_id() | CAN_RTR_FLAG;
+            frame.can_dlc = dlc as u8;
+            frame
+        })
+    }
+
+    fn is_extended(&self) -> bool {
+        self.is_extended_id()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        GsUsbFrame::is_remote_frame(self)
+    }
+
+    fn id(&self) -> embedded_can::Id {
+        CanId::from_can_id(self.can_id).into()
+    }
+
+    fn dlc(&self) -> usize {
+        if GsUsbFrame::is_remote_frame(self) {
+            usize::from(self.can_dlc.min(CAN_MAX_DLC))
+        } else {
+            self.data_length()
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        if GsUsbFrame::is_remote_frame(self) {
+            &[]
+        } else {
+            GsUsbFrame::data(self)
+        }
+    }
+}
+
+impl embedded_can::Error for GsUsbError {
+    fn kind(&self) -> embedded_can::ErrorKind {
+        match self {
+            GsUsbError::RxOverflow(_) => embedded_can::ErrorKind::Overrun,
+            GsUsbError::BusError(e) if e.has_class(CAN_ERR_ACK) => {
+                embedded_can::ErrorKind::Acknowledge
+            }
+            GsUsbError::DeviceFailed { source, .. } => embedded_can::Error::kind(&**source),
+            _ => embedded_can::ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_can::blocking::Can for GsUsb {
+    type Frame = GsUsbFrame;
+    type Error = GsUsbError;
+
+    fn transmit(&mut self, frame: &GsUsbFrame) -> Result<(), GsUsbError> {
+        self.send(frame)
+    }
+
+    /// Wait for the next frame from `read()`, echoes included
+    fn receive(&mut self) -> Result<GsUsbFrame, GsUsbError> {
+        loop {
+            match self.read(RECEIVE_POLL_INTERVAL) {
+                Err(GsUsbError::ReadTimeout) => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_can::blocking::Can;
+    use embedded_can::{Error, ErrorKind, Frame, Id};
+
+    use super::*;
+    use crate::constants::{CAN_ERR_FLAG, GS_CAN_MODE_NORMAL};
+    use crate::error_frame::CanErrorFrame;
+    use crate::virtual_bus::VirtualBus;
+
+    #[test]
+    fn test_id() {
+        let id = Id::from(CanId::Extended(0x1234567));
+        assert_eq!(id, Id::Extended(ExtendedId::new(0x1234567).unwrap()));
+        assert_eq!(CanId::from(id), CanId::Extended(0x1234567));
+        assert_eq!(
+            CanId::from(Id::from(CanId::Standard(0x7FF))),
+            CanId::Standard(0x7FF)
+        );
+        assert!(CanId::standard(0x800).is_none());
+        assert!(CanId::extended(0x2000_0000).is_none());
+    }
+
+    #[test]
+    fn test_frame() {
+        let frame = <GsUsbFrame as Frame>::new(CanId::Extended(0x1234567), &[1, 2]).unwrap();
+        assert!(frame.is_extended() && frame.is_data_frame());
+        assert_eq!(CanId::from(Frame::id(&frame)), CanId::Extended(0x1234567));
+        assert_eq!(Frame::data(&frame), [1, 2]);
+
+        let remote = GsUsbFrame::new_remote(StandardId::MAX, 4).unwrap();
+        assert!(Frame::is_remote_frame(&remote) && remote.is_standard());
+        assert_eq!(Frame::id(&remote), Id::Standard(StandardId::MAX));
+        assert_eq!(Frame::dlc(&remote), 4);
+        assert!(Frame::data(&remote).is_empty());
+
+        assert!(<GsUsbFrame as Frame>::new(StandardId::ZERO, &[0; 9]).is_none());
+        assert!(GsUsbFrame::new_remote(StandardId::ZERO, 9).is_none());
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let mut frame = GsUsbFrame::new();
+        frame.can_id = CAN_ERR_FLAG | CAN_ERR_ACK;
+        let error = GsUsbError::BusError(CanErrorFrame::from_frame(&frame).unwrap());
+        assert_eq!(Error::kind(&error), ErrorKind::Acknowledge);
+        assert_eq!(Error::kind(&GsUsbError::NotStarted), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_transmit_and_receive() {
+        let bus = VirtualBus::new();
+        let [mut a, mut b] = [bus.open(), bus.open()];
+        for dev in [&mut a, &mut b] {
+            dev.set_bitrate(500_000).unwrap();
+            dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        }
+
+        // Through the trait only
+        fn request<C: Can>(can: &mut C, id: impl Into<Id>) -> Result<(), C::Error> {
+            can.transmit(&C::Frame::new(id, &[0xAA]).unwrap())
+        }
+        request(&mut a, StandardId::new(0x123).unwrap()).unwrap();
+        let frame = Can::receive(&mut b).unwrap();
+        assert_eq!(Frame::id(&frame), Id::from(CanId::Standard(0x123)));
+        assert_eq!(Frame::data(&frame), [0xAA]);
+    }
+}
@@ -5,9 +5,48 @@
 
 use thiserror::Error;
 
+use crate::constants::request_name;
+use crate::error_frame::{CanErrorFrame, RxOverflow};
+use crate::platform::PlatformIssue;
+
 /// Result type alias for GS-USB operations
 pub type Result<T> = std::result::Result<T, GsUsbError>;
 
+/// Broad category of a `GsUsbError`, for deciding how to react to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Temporary failure (timeout, busy endpoint, stall, garbled transfer);
+    /// repeating the operation may succeed
+    Transient,
+    /// Invalid argument or configuration (bitrate, channel, malformed input)
+    Configuration,
+    /// The device does not support the requested feature
+    Unsupported,
+    /// The operation is not valid in the current device state
+    State,
+    /// The device is gone and has to be opened again
+    Disconnected,
+    /// Any other failure (permissions, host I/O, USB stack errors)
+    Fatal,
+}
+
+impl ErrorKind {
+    fn of_usb(err: rusb::Error) -> Self {
+        match err {
+            rusb::Error::Timeout
+            | rusb::Error::Busy
+            | rusb::Error::Interrupted
+            | rusb::Error::Overflow
+            | rusb::Error::Pipe
+            | rusb::Error::Io => ErrorKind::Transient,
+            rusb::Error::NoDevice => ErrorKind::Disconnected,
+            rusb::Error::InvalidParam => ErrorKind::Configuration,
+            rusb::Error::NotSupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Fatal,
+        }
+    }
+}
+
 /// Error types for GS-USB operations
 #[derive(Error, Debug)]
 pub enum GsUsbError {
@@ -27,6 +66,21 @@ pub enum GsUsbError {
     #[error("Failed to claim USB interface: {0}")]
     ClaimInterface(rusb::Error),
 
+    /// Failed to release interface
+    #[error("Failed to release USB interface: {0}")]
+    ReleaseInterface(rusb::Error),
+
+    /// Failed to reattach kernel driver
+    #[error("Failed to reattach kernel driver: {0}")]
+    AttachKernelDriver(rusb::Error),
+
+    /// Opening or claiming the device failed for a host-side reason
+    #[error("Cannot access device: {issue}")]
+    Platform {
+        issue: PlatformIssue,
+        source: rusb::Error,
+    },
+
     /// Failed to detach kernel driver
     #[error("Failed to detach kernel driver: {0}")]
     DetachKernelDriver(rusb::Error),
@@ -55,17 +109,61 @@ pub enum GsUsbError {
     #[error("Write timeout")]
     WriteTimeout,
 
+    /// Read cancelled through a `CancelToken`
+    #[error("Read cancelled")]
+    Cancelled,
+
+    /// A device read by `Aggregator` failed
+    #[error("Device {label} failed: {source}")]
+    DeviceFailed {
+        label: String,
+        source: Box<GsUsbError>,
+    },
+
     /// Invalid response from device
     #[error("Invalid response from device: expected {expected} bytes, got {actual}")]
     InvalidResponse { expected: usize, actual: usize },
 
     /// Control transfer failed
-    #[error("Control transfer failed: {0}")]
-    ControlTransfer(rusb::Error),
+    #[error(
+        "Control transfer {} (request {request}, value {value}, {length} bytes) failed: {source}",
+        request_name(*.request)
+    )]
+    ControlTransfer {
+        /// Request code (`GS_USB_BREQ_*`)
+        request: u8,
+        /// wValue, the channel for per-channel requests
+        value: u16,
+        /// Number of bytes sent or requested
+        length: usize,
+        /// Error reported by the transport
+        source: rusb::Error,
+    },
 
     /// Bulk transfer failed
-    #[error("Bulk transfer failed: {0}")]
-    BulkTransfer(rusb::Error),
+    #[error(
+        "Bulk transfer on endpoint 0x{endpoint:02x} ({length} bytes{}) failed: {source}",
+        .channel.map(|c| format!(", channel {c}")).unwrap_or_default()
+    )]
+    BulkTransfer {
+        /// Endpoint address, see `Transport::interface()`
+        endpoint: u8,
+        /// CAN channel of the frame, if known
+        channel: Option<u8>,
+        /// Number of bytes sent or the receive buffer size
+        length: usize,
+        /// Error reported by the transport
+        source: rusb::Error,
+    },
+
+    /// Error frame received while error frames are reported as errors
+    #[error("{0}")]
+    BusError(CanErrorFrame),
+
+    /// Received frames were lost, reported while error frames are reported
+    /// as errors
+    #[error("{0}")]
+    RxOverflow(RxOverflow),
 
     /// Device is already started
     #[error("Device is already started")]
@@ -82,9 +180,107 @@ pub enum GsUsbError {
     /// GET_STATE feature not supported
     #[error("Device does not support GET_STATE feature")]
     GetStateNotSupported,
+
+    /// Malformed SLCAN command
+    #[error("Invalid SLCAN command: {0}")]
+    InvalidSlcan(&'static str),
+
+    /// Malformed USB traffic recording
+    #[error("Invalid recording at line {line}: {reason}")]
+    InvalidRecording { line: usize, reason: &'static str },
+
+    /// Malformed CAN log file
+    #[error("Invalid log file at {location}: {reason}")]
+    InvalidLog {
+        location: String,
+        reason: &'static str,
+    },
+
+    /// Bitrate text that can't be parsed
+    #[error("Invalid bitrate {0}")]
+    InvalidBitrate(String),
+
+    /// CAN identifier text that can't be parsed
+    #[error("Invalid CAN identifier {0}")]
+    InvalidCanId(String),
+
+    /// Signal that doesn't fit its payload, or value out of its range
+    #[error("Invalid signal: {0}")]
+    InvalidSignal(String),
+
+    /// Invalid or incomplete device configuration
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// Several problems with a configuration, see `Config::validate()`
+    #[error("Invalid configuration: {}", join(.0))]
+    InvalidConfigs(Vec<GsUsbError>),
+
+    /// File I/O error
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Messages of several errors, separated by semicolons
+fn join(errors: &[GsUsbError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 impl GsUsbError {
+    /// Get the category of this error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            GsUsbError::Usb(e)
+            | GsUsbError::ClaimInterface(e)
+            | GsUsbError::DetachKernelDriver(e)
+            | GsUsbError::ReleaseInterface(e)
+            | GsUsbError::AttachKernelDriver(e) => ErrorKind::of_usb(*e),
+            GsUsbError::ControlTransfer { source, .. }
+            | GsUsbError::BulkTransfer { source, .. }
+            | GsUsbError::Platform { source, .. } => ErrorKind::of_usb(*source),
+            GsUsbError::ReadTimeout
+            | GsUsbError::WriteTimeout
+            | GsUsbError::InvalidResponse { .. } => ErrorKind::Transient,
+            GsUsbError::UnsupportedBitrate { .. }
+            | GsUsbError::UnsupportedDataBitrate { .. }
+            | GsUsbError::InvalidChannel { .. }
+            | GsUsbError::InvalidSlcan(_)
+            | GsUsbError::InvalidRecording { .. }
+            | GsUsbError::InvalidLog { .. }
+            | GsUsbError::InvalidBitrate(_)
+            | GsUsbError::InvalidCanId(_)
+            | GsUsbError::InvalidSignal(_)
+            | GsUsbError::InvalidConfig(_)
+            | GsUsbError::InvalidConfigs(_) => ErrorKind::Configuration,
+            GsUsbError::FdNotSupported
+            | GsUsbError::FeatureNotSupported(_)
+            | GsUsbError::GetStateNotSupported => ErrorKind::Unsupported,
+            GsUsbError::DeviceNotOpen
+            | GsUsbError::AlreadyStarted
+            | GsUsbError::NotStarted
+            | GsUsbError::Cancelled => ErrorKind::State,
+            GsUsbError::BusError(e) if e.is_bus_off() => ErrorKind::State,
+            GsUsbError::BusError(_) | GsUsbError::RxOverflow(_) => ErrorKind::Transient,
+            GsUsbError::DeviceNotFound => ErrorKind::Disconnected,
+            GsUsbError::DeviceFailed { source, .. } => source.kind(),
+            GsUsbError::Io(_) => ErrorKind::Fatal,
+        }
+    }
+
+    /// Check if repeating the failed operation may succeed
+    pub fn is_recoverable(&self) -> bool {
+        self.kind() == ErrorKind::Transient
+    }
+
+    /// Check if the device is gone and has to be opened again
+    pub fn is_disconnected(&self) -> bool {
+        self.kind() == ErrorKind::Disconnected
+    }
+
     /// Check if this error is a timeout error
     pub fn is_timeout(&self) -> bool {
         matches!(
@@ -95,6 +291,22 @@ impl GsUsbError {
         )
     }
 
+    /// Check if the device couldn't be claimed because another process or
+    /// driver holds it
+    ///
+    /// Covers the ways the platforms report this: a busy interface on Linux
+    /// and Windows, exclusive access on macOS.
+    pub fn is_claimed_elsewhere(&self) -> bool {
+        matches!(
+            self,
+            GsUsbError::ClaimInterface(rusb::Error::Busy)
+                | GsUsbError::Platform {
+                    issue: PlatformIssue::InterfaceBusy | PlatformIssue::ExclusiveAccess,
+                    ..
+                }
+        )
+    }
+
     /// Check if this error is a USB error
     pub fn is_usb_error(&self) -> bool {
         matches!(
@@ -102,8 +314,59 @@ impl GsUsbError {
             GsUsbError::Usb(_)
                 | GsUsbError::ClaimInterface(_)
                 | GsUsbError::DetachKernelDriver(_)
-                | GsUsbError::ControlTransfer(_)
-                | GsUsbError::BulkTransfer(_)
+                | GsUsbError::ReleaseInterface(_)
+                | GsUsbError::AttachKernelDriver(_)
+                | GsUsbError::Platform { .. }
+                | GsUsbError::ControlTransfer { .. }
+                | GsUsbError::BulkTransfer { .. }
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{GS_USB_BREQ_BT_CONST, GS_USB_ENDPOINT_OUT};
+
+    #[test]
+    fn test_error_kind() {
+        assert!(GsUsbError::ReadTimeout.is_recoverable());
+        let stall = GsUsbError::ControlTransfer {
+            request: GS_USB_BREQ_BT_CONST,
+            value: 0,
+            length: 40,
+            source: rusb::Error::Pipe,
+        };
+        assert!(stall.is_recoverable());
+        assert_eq!(
+            stall.to_string(),
+            "Control transfer BT_CONST (request 4, value 0, 40 bytes) failed: Pipe error"
+        );
+        let unplugged = GsUsbError::BulkTransfer {
+            endpoint: GS_USB_ENDPOINT_OUT,
+            channel: Some(1),
+            length: 20,
+            source: rusb::Error::NoDevice,
+        };
+        assert!(unplugged.is_disconnected());
+        assert!(!unplugged.is_recoverable());
+        assert_eq!(
+            unplugged.to_string(),
+            "Bulk transfer on endpoint 0x02 (20 bytes, channel 1) failed: No such device (it may have been disconnected)"
+        );
+        assert_eq!(
+            GsUsbError::UnsupportedBitrate {
+                bitrate: 1,
+                clock_hz: 40_000_000
+            }
+            .kind(),
+            ErrorKind::Configuration
+        );
+        assert_eq!(GsUsbError::FdNotSupported.kind(), ErrorKind::Unsupported);
+        assert_eq!(GsUsbError::NotStarted.kind(), ErrorKind::State);
+        assert_eq!(
+            GsUsbError::Usb(rusb::Error::Access).kind(),
+            ErrorKind::Fatal
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Decoded CAN error frames
+//!
+//! Devices report bus errors and state changes as frames with `CAN_ERR_FLAG`
+//! set in the identifier, laid out like SocketCAN error frames: the error
+//! classes are in the identifier, details and error counters in the payload.
+//! `CanErrorFrame` gives typed access to them. With
+//! `GsUsb::set_error_frames_as_errors(true)`, `read()` returns error frames as
+//! `GsUsbError::BusError` instead of handing them out as regular frames.
+//!
+//! Lost frames are reported the same way. When the device flags a received
+//! frame with `GS_CAN_FLAG_OVERFLOW`, `read()` first returns an `RxOverflow`
+//! notification, as a SocketCAN style controller error frame or as
+//! `GsUsbError::RxOverflow`, and then the flagged frame itself, so loggers can
+//! mark the gap at the right position.
+
+use crate::constants::{
+    CAN_ERR_ACK, CAN_ERR_BUSERROR, CAN_ERR_BUSOFF, CAN_ERR_CNT, CAN_ERR_CRTL,
+    CAN_ERR_CRTL_RX_OVERFLOW, CAN_ERR_FLAG, CAN_ERR_LOSTARB, CAN_ERR_MASK, CAN_ERR_PROT,
+    CAN_ERR_RESTARTED, CAN_ERR_TRX, CAN_ERR_TX_TIMEOUT, CAN_MAX_DLC, GS_USB_RX_ECHO_ID,
+};
+use crate::frame::GsUsbFrame;
+use std::time::Duration;
+
+/// Error classes with their names, in bit order
+pub(crate) const CLASS_NAMES: [(u32, &str); 9] = [
+    (CAN_ERR_TX_TIMEOUT, "tx-timeout"),
+    (CAN_ERR_LOSTARB, "lost-arbitration"),
+    (CAN_ERR_CRTL, "controller"),
+    (CAN_ERR_PROT, "protocol"),
+    (CAN_ERR_TRX, "transceiver"),
+    (CAN_ERR_ACK, "no-ack"),
+    (CAN_ERR_BUSOFF, "bus-off"),
+    (CAN_ERR_BUSERROR, "bus-error"),
+    (CAN_ERR_RESTARTED, "restarted"),
+];
+
+/// A CAN error frame received from the device
+#[derive(Debug, Clone)]
+pub struct CanErrorFrame {
+    frame: GsUsbFrame,
+}
+
+impl CanErrorFrame {
+    /// Decode an error frame, or return `None` if `frame` isn't one
+    pub fn from_frame(frame: &GsUsbFrame) -> Option<Self> {
+        frame.is_error_frame().then(|| Self {
+            frame: frame.clone(),
+        })
+    }
+
+    /// The raw frame
+    pub fn frame(&self) -> &GsUsbFrame {
+        &self.frame
+    }
+
+    /// CAN channel the error was reported on
+    pub fn channel(&self) -> u8 {
+        self.frame.channel
+    }
+
+    /// Hardware timestamp in microseconds
+    pub fn timestamp_us(&self) -> u32 {
+        self.frame.timestamp_us
+    }
+
+    /// Error classes (combination of `CAN_ERR_*` class constants)
+    pub fn class(&self) -> u32 {
+        self.frame.can_id & CAN_ERR_MASK
+    }
+
+    /// Check if any of the given error classes is set
+    pub fn has_class(&self, class: u32) -> bool {
+        self.class() & class != 0
+    }
+
+    /// Check if the controller went bus-off
+    pub fn is_bus_off(&self) -> bool {
+        self.has_class(CAN_ERR_BUSOFF)
+    }
+
+    /// TX error counter, if the device reported it
+    pub fn tx_error_count(&self) -> Option<u8> {
+        self.has_class(CAN_ERR_CNT).then_some(self.frame.data[6])
+    }
+
+    /// RX error counter, if the device reported it
+    pub fn rx_error_count(&self) -> Option<u8> {
+        self.has_class(CAN_ERR_CNT).then_some(self.frame.data[7])
+    }
+
+    /// The receive overflow this frame reports, if any
+    pub fn rx_overflow(&self) -> Option<RxOverflow> {
+        (self.has_class(CAN_ERR_CRTL) && self.frame.data[1] & CAN_ERR_CRTL_RX_OVERFLOW != 0).then(
+            || RxOverflow {
+                channel: self.channel(),
+                timestamp_us: self.timestamp_us(),
+                lost_estimate: 1,
+            },
+        )
+    }
+}
+
+/// Received frames were lost because a receive buffer overflowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RxOverflow {
+    /// CAN channel that lost frames
+    pub channel: u8,
+    /// Hardware timestamp of the first frame after the gap, in microseconds
+    pub timestamp_us: u32,
+    /// Lower bound on the number of lost frames
+    ///
+    /// The device only flags that frames were lost, not how many, so an
+    /// overflow seen through `GS_CAN_FLAG_OVERFLOW` counts as one.
+    pub lost_estimate: u32,
+}
+
+impl RxOverflow {
+    /// Hardware timestamp of the first frame after the gap
+    pub fn timestamp(&self) -> Duration {
+        Duration::from_micros(u64::from(self.timestamp_us))
+    }
+
+    /// SocketCAN style controller error frame reporting this overflow
+    pub fn to_error_frame(&self) -> GsUsbFrame {
+        let mut frame = GsUsbFrame::new();
+        frame.echo_id = GS_USB_RX_ECHO_ID;
+        frame.can_id = CAN_ERR_FLAG | CAN_ERR_CRTL;
+        frame.can_dlc = CAN_MAX_DLC;
+        frame.channel = self.channel;
+        frame.data[1] = CAN_ERR_CRTL_RX_OVERFLOW;
+        frame.timestamp_us = self.timestamp_us;
+        frame
+    }
+}
+
+impl std::fmt::Display for RxOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "RX overflow on channel {}: at least {} frame(s) lost",
+            self.channel, self.lost_estimate
+        )
+    }
+}
+
+impl std::fmt::Display for CanErrorFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CAN error on channel {}:", self.channel())?;
+        let mut any = false;
+        for (class, name) in CLASS_NAMES {
+            if self.has_class(class) {
+                write!(f, " {name}")?;
+                any = true;
+            }
+        }
+        if !any {
+            write!(f, " unknown")?;
+        }
+        if self.rx_overflow().is_some() {
+            write!(f, " rx-overflow")?;
+        }
+        if let (Some(tx), Some(rx)) = (self.tx_error_count(), self.rx_error_count()) {
+            write!(f, " (tx {tx}, rx {rx})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{GS_CAN_FLAG_OVERFLOW, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_NORMAL};
+    use crate::error::GsUsbError;
+    use crate::mock::MockGsUsb;
+
+    #[test]
+    fn test_decode() {
+        assert!(CanErrorFrame::from_frame(&GsUsbFrame::with_data(0x100, &[1])).is_none());
+
+        let mut frame = GsUsbFrame::with_data(0, &[0, 0, 0, 0, 0, 0, 255, 3]);
+        frame.can_id = CAN_ERR_FLAG | CAN_ERR_CNT | CAN_ERR_BUSOFF | CAN_ERR_ACK;
+        frame.channel = 1;
+        let err = CanErrorFrame::from_frame(&frame).unwrap();
+        assert!(err.is_bus_off());
+        assert_eq!(err.tx_error_count(), Some(255));
+        assert_eq!(err.rx_error_count(), Some(3));
+        assert_eq!(
+            err.to_string(),
+            "CAN error on channel 1: no-ack bus-off (tx 255, rx 3)"
+        );
+    }
+
+    #[test]
+    fn test_read_as_error() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+
+        let mut frame = GsUsbFrame::new();
+        frame.can_id = CAN_ERR_FLAG | CAN_ERR_ACK;
+        mock.push_rx(&frame);
+        assert!(dev
+            .read(Duration::from_millis(10))
+            .unwrap()
+            .is_error_frame());
+
+        dev.set_error_frames_as_errors(true);
+        mock.push_rx(&frame);
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[1]));
+        match dev.read(Duration::from_millis(10)) {
+            Err(e @ GsUsbError::BusError(_)) => assert!(e.is_recoverable()),
+            other => panic!("expected bus error, got {other:?}"),
+        }
+        assert_eq!(
+            dev.read(Duration::from_millis(10))
+                .unwrap()
+                .arbitration_id(),
+            0x100
+        );
+    }
+
+    #[test]
+    fn test_rx_overflow() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+
+        let mut frame = GsUsbFrame::with_data(0x200, &[7]);
+        frame.flags |= GS_CAN_FLAG_OVERFLOW;
+        frame.timestamp_us = 1234;
+        mock.push_rx(&frame);
+        let err = dev.read(Duration::from_millis(10)).unwrap();
+        let overflow = CanErrorFrame::from_frame(&err)
+            .and_then(|e| e.rx_overflow())
+            .unwrap();
+        assert_eq!(overflow.timestamp(), Duration::from_micros(1234));
+        assert_eq!(overflow.lost_estimate, 1);
+        assert_eq!(
+            dev.read(Duration::from_millis(10))
+                .unwrap()
+                .arbitration_id(),
+            0x200
+        );
+
+        dev.set_error_frames_as_errors(true);
+        mock.push_rx(&frame);
+        assert!(matches!(
+            dev.read(Duration::from_millis(10)),
+            Err(GsUsbError::RxOverflow(RxOverflow { channel: 0, .. }))
+        ));
+        assert!(dev.read(Duration::from_millis(10)).unwrap().is_overflow());
+
+        // A held back error frame is returned as an error as well
+        let mut error = GsUsbFrame::with_data(CAN_ERR_FLAG | CAN_ERR_BUSOFF, &[0; 8]);
+        error.flags |= GS_CAN_FLAG_OVERFLOW;
+        mock.push_rx(&error);
+        assert!(matches!(
+            dev.read(Duration::from_millis(10)),
+            Err(GsUsbError::RxOverflow(_))
+        ));
+        assert!(matches!(
+            dev.read(Duration::from_millis(10)),
+            Err(GsUsbError::BusError(err)) if err.is_bus_off()
+        ));
+    }
+}
@@ -0,0 +1,459 @@
+//! Fault injection for robustness testing
+//!
+//! This module provides `FaultyTransport`, a wrapper around any `Transport` that
+//! randomly drops, duplicates, delays or truncates bulk transfers and fails
+//! control transfers. Wrapping a mock, a virtual bus device or a real adapter
+//! shows how an application copes with a misbehaving link. Faults are drawn
+//! from a seeded generator, so failing runs can be reproduced.
+//!
+//! Available with the `test-util` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use gs_usb::{FaultConfig, FaultyTransport, GsUsb, MockGsUsb, GS_CAN_MODE_NORMAL};
+//!
+//! let faulty = FaultyTransport::new(MockGsUsb::new(), FaultConfig::default(), 42);
+//! let faults = faulty.handle();
+//! let mut dev = GsUsb::from_transport(faulty, 0, 1);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! // Only start misbehaving once the device is configured
+//! faults.set_config(FaultConfig {
+//!     drop_rx: 0.1,
+//!     duplicate_rx: 0.05,
+//!     ..FaultConfig::default()
+//! });
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::rng::XorShift64;
+use crate::transport::{GsUsbInterface, Transport};
+
+/// Shortest wait passed to the wrapped transport's bulk reads
+const MIN_READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Fault probabilities (0.0 - 1.0) and parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultConfig {
+    /// Probability that a received frame is discarded
+    pub drop_rx: f64,
+    /// Probability that a sent frame is discarded while reporting success
+    pub drop_tx: f64,
+    /// Probability that a received frame is delivered twice
+    pub duplicate_rx: f64,
+    /// Probability that a received frame is held back for `delay`
+    ///
+    /// Frames received in the meantime overtake it.
+    pub delay_rx: f64,
+    /// Hold-back time for delayed frames
+    pub delay: Duration,
+    /// Probability that a bulk read returns fewer bytes than received
+    pub truncate_rx: f64,
+    /// Probability that a control transfer fails
+    pub control_failure: f64,
+    /// Error returned by failed control transfers
+    pub control_error: rusb::Error,
+}
+
+impl Default for FaultConfig {
+    /// No faults
+    fn default() -> Self {
+        Self {
+            drop_rx: 0.0,
+            drop_tx: 0.0,
+            duplicate_rx: 0.0,
+            delay_rx: 0.0,
+            delay: Duration::from_millis(10),
+            truncate_rx: 0.0,
+            control_failure: 0.0,
+            control_error: rusb::Error::Pipe,
+        }
+    }
+}
+
+/// Number of injected faults by kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    /// Received frames discarded
+    pub dropped_rx: u64,
+    /// Sent frames discarded
+    pub dropped_tx: u64,
+    /// Received frames delivered twice
+    pub duplicated_rx: u64,
+    /// Received frames held back
+    pub delayed_rx: u64,
+    /// Bulk reads cut short
+    pub truncated_rx: u64,
+    /// Control transfers failed
+    pub failed_control: u64,
+}
+
+struct FaultState {
+    config: FaultConfig,
+    stats: FaultStats,
+}
+
+/// Handle for changing the faults of a `FaultyTransport` after it has been
+/// moved into a `GsUsb`
+#[derive(Clone)]
+pub struct FaultHandle {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultHandle {
+    /// Get the current fault configuration
+    pub fn config(&self) -> FaultConfig {
+        self.state.lock().unwrap().config
+    }
+
+    /// Replace the fault configuration
+    pub fn set_config(&self, config: FaultConfig) {
+        self.state.lock().unwrap().config = config;
+    }
+
+    /// Get the number of faults injected so far
+    pub fn stats(&self) -> FaultStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+/// Transport wrapper injecting random faults
+pub struct FaultyTransport<T> {
+    inner: T,
+    state: Arc<Mutex<FaultState>>,
+    rng: XorShift64,
+    duplicates: VecDeque<Vec<u8>>,
+    delayed: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+    /// Wrap a transport
+    pub fn new(inner: T, config: FaultConfig, seed: u64) -> Self {
+        Self {
+            inner,
+            state: Arc::new(Mutex::new(FaultState {
+                config,
+                stats: FaultStats::default(),
+            })),
+            rng: XorShift64::new(seed),
+            duplicates: VecDeque::new(),
+            delayed: VecDeque::new(),
+        }
+    }
+
+    /// Get a handle for changing the configuration and reading statistics
+    pub fn handle(&self) -> FaultHandle {
+        FaultHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Get the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Unwrap the transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn control_fault(&mut self) -> rusb::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if self.rng.chance(state.config.control_failure) {
+            state.stats.failed_control += 1;
+            return Err(state.config.control_error);
+        }
+        Ok(())
+    }
+}
+
+fn deliver(data: &[u8], buf: &mut [u8]) -> usize {
+    let len = data.len().min(buf.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+    fn reset(&mut self) -> rusb::Result<()> {
+        self.duplicates.clear();
+        self.delayed.clear();
+        self.inner.reset()
+    }
+
+    fn claim_interface(&mut self) -> Result<()> {
+        self.inner.claim_interface()
+    }
+
+    fn release_interface(&mut self, reattach_kernel_driver: bool) -> Result<()> {
+        self.inner.release_interface(reattach_kernel_driver)
+    }
+
+    fn write_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.control_fault()?;
+        self.inner.write_control(request, value, data, timeout)
+    }
+
+    fn read_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.control_fault()?;
+        self.inner.read_control(request, value, buf, timeout)
+    }
+
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if self.rng.chance(state.config.drop_tx) {
+                state.stats.dropped_tx += 1;
+                return Ok(data.len());
+            }
+        }
+        self.inner.write_bulk(data, timeout)
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(data) = self.duplicates.pop_front() {
+                return Ok(deliver(&data, buf));
+            }
+            let now = Instant::now();
+            if self.delayed.front().is_some_and(|(due, _)| *due <= now) {
+                let (_, data) = self.delayed.pop_front().unwrap();
+                return Ok(deliver(&data, buf));
+            }
+
+            // Don't wait past the release of the next delayed frame
+            let mut wake = deadline;
+            if let Some((due, _)) = self.delayed.front() {
+                wake = wake.min(*due);
+            }
+            let wait = wake.saturating_duration_since(now);
+            if wait.is_zero() {
+                return Err(rusb::Error::Timeout);
+            }
+
+            let mut data = vec![0u8; buf.len()];
+            // libusb counts whole milliseconds and waits forever for 0
+            let len = match self.inner.read_bulk(&mut data, wait.max(MIN_READ_TIMEOUT)) {
+                Ok(len) => len,
+                Err(rusb::Error::Timeout) if Instant::now() < deadline => continue,
+                Err(e) => return Err(e),
+            };
+            data.truncate(len);
+
+            let mut state = self.state.lock().unwrap();
+            let config = state.config;
+            if self.rng.chance(config.drop_rx) {
+                state.stats.dropped_rx += 1;
+                continue;
+            }
+            if self.rng.chance(config.delay_rx) {
+                state.stats.delayed_rx += 1;
+                self.delayed
+                    .push_back((Instant::now() + config.delay, data));
+                continue;
+            }
+            if self.rng.chance(config.duplicate_rx) {
+                state.stats.duplicated_rx += 1;
+                self.duplicates.push_back(data.clone());
+            }
+            if len > 0 && self.rng.chance(config.truncate_rx) {
+                state.stats.truncated_rx += 1;
+                data.truncate(self.rng.next_u64() as usize % len);
+            }
+            return Ok(deliver(&data, buf));
+        }
+    }
+
+    fn tx_max_packet_size(&self) -> Option<usize> {
+        self.inner.tx_max_packet_size()
+    }
+
+    fn interface(&self) -> GsUsbInterface {
+        self.inner.interface()
+    }
+
+    fn vendor_product(&self) -> Option<(u16, u16)> {
+        self.inner.vendor_product()
+    }
+
+    fn serial_number(&mut self) -> rusb::Result<Option<String>> {
+        self.inner.serial_number()
+    }
+
+    fn product(&mut self) -> rusb::Result<Option<String>> {
+        self.inner.product()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::device::GsUsb;
+    use crate::error::GsUsbError;
+    use crate::frame::GsUsbFrame;
+    use crate::mock::MockGsUsb;
+
+    fn setup() -> (MockGsUsb, FaultHandle, GsUsb) {
+        let mock = MockGsUsb::new();
+        mock.set_echo(false);
+        let faulty = FaultyTransport::new(mock.clone(), FaultConfig::default(), 1);
+        let faults = faulty.handle();
+        let mut dev = GsUsb::from_transport(faulty, 0, 1);
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        (mock, faults, dev)
+    }
+
+    fn push(mock: &MockGsUsb, id: u32) {
+        mock.push_rx(&GsUsbFrame::with_data(id, &[1, 2, 3, 4, 5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn test_drop_and_duplicate() {
+        let (mock, faults, mut dev) = setup();
+
+        faults.set_config(FaultConfig {
+            drop_rx: 1.0,
+            ..FaultConfig::default()
+        });
+        push(&mock, 0x1);
+        assert!(matches!(
+            dev.read(Duration::from_millis(5)),
+            Err(GsUsbError::ReadTimeout)
+        ));
+
+        faults.set_config(FaultConfig {
+            duplicate_rx: 1.0,
+            ..FaultConfig::default()
+        });
+        push(&mock, 0x2);
+        assert_eq!(
+            dev.read(Duration::from_millis(5)).unwrap().arbitration_id(),
+            0x2
+        );
+        assert_eq!(
+            dev.read(Duration::from_millis(5)).unwrap().arbitration_id(),
+            0x2
+        );
+
+        faults.set_config(FaultConfig {
+            drop_tx: 1.0,
+            ..FaultConfig::default()
+        });
+        dev.send(&GsUsbFrame::with_data(0x3, &[])).unwrap();
+        assert!(mock.sent_frames().is_empty());
+
+        let stats = faults.stats();
+        assert_eq!(stats.dropped_rx, 1);
+        assert_eq!(stats.duplicated_rx, 1);
+        assert_eq!(stats.dropped_tx, 1);
+    }
+
+    #[test]
+    fn test_delay_reorders() {
+        let (mock, faults, mut dev) = setup();
+        faults.set_config(FaultConfig {
+            delay_rx: 1.0,
+            delay: Duration::from_millis(30),
+            ..FaultConfig::default()
+        });
+        push(&mock, 0x1);
+        assert!(matches!(
+            dev.read(Duration::from_millis(5)),
+            Err(GsUsbError::ReadTimeout)
+        ));
+
+        faults.set_config(FaultConfig::default());
+        push(&mock, 0x2);
+        assert_eq!(
+            dev.read(Duration::from_millis(5)).unwrap().arbitration_id(),
+            0x2
+        );
+        assert_eq!(
+            dev.read(Duration::from_millis(500))
+                .unwrap()
+                .arbitration_id(),
+            0x1
+        );
+    }
+
+    #[test]
+    fn test_truncated_reads_and_control_failures() {
+        let (mock, faults, mut dev) = setup();
+        faults.set_config(FaultConfig {
+            truncate_rx: 1.0,
+            control_failure: 1.0,
+            control_error: rusb::Error::Io,
+            ..FaultConfig::default()
+        });
+
+        for id in 0..20 {
+            push(&mock, id);
+            assert!(matches!(
+                dev.read(Duration::from_millis(5)),
+                Err(GsUsbError::InvalidResponse { .. })
+            ));
+        }
+        assert!(matches!(
+            dev.get_state(0),
+            Err(GsUsbError::ControlTransfer {
+                source: rusb::Error::Io,
+                ..
+            })
+        ));
+
+        let stats = faults.stats();
+        assert_eq!(stats.truncated_rx, 20);
+        assert_eq!(stats.failed_control, 1);
+    }
+
+    #[test]
+    fn test_short_read_timeouts() {
+        let mock = MockGsUsb::new();
+        let mut faulty = FaultyTransport::new(mock.clone(), FaultConfig::default(), 1);
+        let mut buf = [0u8; 64];
+        assert_eq!(
+            faulty.read_bulk(&mut buf, Duration::ZERO),
+            Err(rusb::Error::Timeout)
+        );
+        assert!(mock.bulk_read_timeouts().is_empty());
+
+        // Sub-millisecond waits reach the wrapped transport as 1 ms
+        assert_eq!(
+            faulty.read_bulk(&mut buf, Duration::from_micros(300)),
+            Err(rusb::Error::Timeout)
+        );
+        assert_eq!(mock.bulk_read_timeouts(), [MIN_READ_TIMEOUT]);
+    }
+
+    #[test]
+    fn test_forwards_packet_size() {
+        let mock = MockGsUsb::new();
+        let faulty = FaultyTransport::new(mock.clone(), FaultConfig::default(), 1);
+        assert_eq!(faulty.tx_max_packet_size(), None);
+        mock.set_tx_max_packet_size(512);
+        assert_eq!(faulty.tx_max_packet_size(), Some(512));
+    }
+}
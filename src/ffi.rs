@@ -0,0 +1,512 @@
+//! uniffi bindings for Kotlin and Swift
+//!
+//! Implements the interface in `src/gs_usb.udl`: discovering adapters,
+//! configuring and starting them, sending and receiving frames, and
+//! receiving through a listener called from a background thread. The types
+//! here are simplified views of the crate's own, shaped for foreign
+//! languages; Rust code should use `GsUsb` directly.
+//!
+//! Enabled with the `uniffi` cargo feature.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::config::{Config, ModeConfig};
+use crate::constants::{
+    CAN_EFF_FLAG, CAN_EFF_MASK, CAN_ERR_MASK, CAN_MAX_DLEN, CAN_RTR_FLAG, CAN_SFF_MASK,
+    GS_USB_FRAME_DATA_LEN,
+};
+use crate::device::GsUsb;
+use crate::error::{ErrorKind, GsUsbError};
+use crate::frame::GsUsbFrame;
+use crate::shared::spawn_reader;
+use crate::sync::lock;
+
+/// Identity and capabilities of an adapter
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdapterInfo {
+    /// USB bus number
+    pub bus: u8,
+    /// USB device address
+    pub address: u8,
+    /// Serial number, empty if the adapter has none
+    pub serial_number: String,
+    /// Number of CAN channels
+    pub channel_count: u8,
+    /// Firmware version
+    pub firmware_version: f32,
+    /// Hardware version
+    pub hardware_version: f32,
+    /// Names of the supported features, see `DeviceCapability::feature_names()`
+    pub features: Vec<String>,
+}
+
+/// A CAN or CAN FD frame, with the flags of `GsUsbFrame` spelled out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Identifier without flags, or the `CAN_ERR_*` classes of an error frame
+    pub id: u32,
+    /// 29 bit identifier
+    pub extended: bool,
+    /// Remote transmission request
+    pub remote: bool,
+    /// CAN FD frame
+    pub fd: bool,
+    /// CAN FD bit rate switch
+    pub brs: bool,
+    /// Payload
+    pub data: Vec<u8>,
+    /// CAN channel
+    pub channel: u8,
+    /// Confirmation of a frame sent by this host
+    pub echo: bool,
+    /// Error frame
+    pub error: bool,
+    /// Hardware timestamp in microseconds
+    pub timestamp_us: u32,
+}
+
+/// Bitrate and mode of a channel, a subset of `Config`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterConfig {
+    /// Nominal bitrate in bits per second
+    pub bitrate: u32,
+    /// Nominal sample point in permille
+    pub sample_point: Option<u32>,
+    /// CAN FD data phase bitrate; setting it enables FD
+    pub data_bitrate: Option<u32>,
+    /// Data phase sample point in permille
+    pub data_sample_point: Option<u32>,
+    /// Only listen, never acknowledge or transmit
+    pub listen_only: bool,
+    /// Receive own frames without a bus
+    pub loopback: bool,
+    /// Don't retransmit frames that failed
+    pub one_shot: bool,
+    /// Timestamp frames in hardware
+    pub hw_timestamp: bool,
+}
+
+/// Bus state and error counters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusState {
+    /// Name of the state, see `DeviceState::state_name()`
+    pub state: String,
+    /// Transmit error counter
+    pub tec: u32,
+    /// Receive error counter
+    pub rec: u32,
+}
+
+/// Errors of the bindings: one per `ErrorKind`, plus no adapter found and
+/// timeouts, which callers usually handle on their own
+#[derive(Debug, thiserror::Error)]
+pub enum AdapterError {
+    /// `GsUsbError::DeviceNotFound`
+    #[error("{message}")]
+    NotFound { message: String },
+    /// `GsUsbError::ReadTimeout` or `WriteTimeout`
+    #[error("{message}")]
+    Timeout { message: String },
+    /// `ErrorKind::Transient`
+    #[error("{message}")]
+    Transient { message: String },
+    /// `ErrorKind::Configuration`
+    #[error("{message}")]
+    Configuration { message: String },
+    /// `ErrorKind::Unsupported`
+    #[error("{message}")]
+    Unsupported { message: String },
+    /// `ErrorKind::State`
+    #[error("{message}")]
+    State { message: String },
+    /// `ErrorKind::Disconnected`
+    #[error("{message}")]
+    Disconnected { message: String },
+    /// `ErrorKind::Fatal`
+    #[error("{message}")]
+    Fatal { message: String },
+}
+
+/// Receives frames on the listener thread of an `Adapter`
+pub trait AdapterListener: Send + Sync {
+    /// A frame was received
+    fn on_frame(&self, frame: Frame);
+    /// Receiving failed; no more calls follow
+    fn on_stopped(&self, reason: String);
+}
+
+impl From<GsUsbError> for AdapterError {
+    fn from(err: GsUsbError) -> Self {
+        let message = err.to_string();
+        match err {
+            GsUsbError::DeviceNotFound => Self::NotFound { message },
+            GsUsbError::ReadTimeout | GsUsbError::WriteTimeout => Self::Timeout { message },
+            _ => match err.kind() {
+                ErrorKind::Transient => Self::Transient { message },
+                ErrorKind::Configuration => Self::Configuration { message },
+                ErrorKind::Unsupported => Self::Unsupported { message },
+                ErrorKind::State => Self::State { message },
+                ErrorKind::Disconnected => Self::Disconnected { message },
+                ErrorKind::Fatal => Self::Fatal { message },
+            },
+        }
+    }
+}
+
+impl From<&GsUsbFrame> for Frame {
+    fn from(frame: &GsUsbFrame) -> Self {
+        let error = frame.is_error_frame();
+        Self {
+            id: if error {
+                frame.can_id & CAN_ERR_MASK
+            } else {
+                frame.arbitration_id()
+            },
+            extended: frame.is_extended_id(),
+            remote: frame.is_remote_frame(),
+            fd: frame.is_fd(),
+            brs: frame.is_brs(),
+            data: frame.data().to_vec(),
+            channel: frame.channel,
+            echo: frame.is_echo_frame(),
+            error,
+            timestamp_us: frame.timestamp_us,
+        }
+    }
+}
+
+impl TryFrom<&Frame> for GsUsbFrame {
+    type Error = AdapterError;
+
+    fn try_from(frame: &Frame) -> Result<Self, AdapterError> {
+        let invalid = |message: String| AdapterError::Configuration { message };
+        if frame.error {
+            return Err(invalid("error frames can't be sent".into()));
+        }
+        let max_id = if frame.extended {
+            CAN_EFF_MASK
+        } else {
+            CAN_SFF_MASK
+        };
+        if frame.id > max_id {
+            return Err(invalid(format!("identifier {:X} out of range", frame.id)));
+        }
+        let max_len = if frame.fd {
+            GS_USB_FRAME_DATA_LEN
+        } else {
+            CAN_MAX_DLEN
+        };
+        if frame.data.len() > max_len {
+            return Err(invalid(format!(
+                "frame data too long: {} bytes (max {max_len})",
+                frame.data.len()
+            )));
+        }
+
+        let mut can_id = frame.id;
+        if frame.extended {
+            can_id |= CAN_EFF_FLAG;
+        }
+        let mut out = if frame.fd {
+            GsUsbFrame::with_fd_data(can_id, &frame.data, frame.brs)
+        } else {
+            GsUsbFrame::with_data(can_id, &frame.data)
+        };
+        if frame.remote && !frame.fd {
+            out.can_id |= CAN_RTR_FLAG;
+        }
+        out.channel = frame.channel;
+        Ok(out)
+    }
+}
+
+impl From<&AdapterConfig> for Config {
+    fn from(config: &AdapterConfig) -> Self {
+        Self {
+            sample_point: config.sample_point,
+            data_bitrate: config.data_bitrate,
+            data_sample_point: config.data_sample_point,
+            mode: ModeConfig {
+                listen_only: config.listen_only,
+                loopback: config.loopback,
+                one_shot: config.one_shot,
+                hw_timestamp: config.hw_timestamp,
+                ..ModeConfig::default()
+            },
+            ..Config::new(config.bitrate)
+        }
+    }
+}
+
+/// Every connected adapter
+pub fn list_adapters() -> Result<Vec<AdapterInfo>, AdapterError> {
+    GsUsb::scan()?
+        .iter_mut()
+        .map(|dev| adapter_info(dev).map_err(AdapterError::from))
+        .collect()
+}
+
+fn adapter_info(dev: &mut GsUsb) -> crate::error::Result<AdapterInfo> {
+    let info = dev.device_info()?;
+    let capability = dev.device_capability()?;
+    Ok(AdapterInfo {
+        bus: dev.bus(),
+        address: dev.address(),
+        serial_number: dev.serial_number()?,
+        channel_count: info.channel_count(),
+        firmware_version: info.firmware_version(),
+        hardware_version: info.hardware_version(),
+        features: capability
+            .feature_names()
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    })
+}
+
+/// Thread handing received frames to an `AdapterListener`
+struct ListenerThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl ListenerThread {
+    fn spawn(device: Arc<Mutex<GsUsb>>, listener: Box<dyn AdapterListener>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = spawn_reader(device, Arc::clone(&stop), move |result| match result {
+            Ok(frame) => {
+                listener.on_frame(Frame::from(&frame));
+                true
+            }
+            Err(e) => {
+                listener.on_stopped(e.to_string());
+                false
+            }
+        });
+        Self { stop, handle }
+    }
+
+    fn join(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.handle.join().is_err() {
+            log::warn!("Adapter listener panicked");
+        }
+    }
+}
+
+/// An opened adapter, safe to share between threads
+pub struct Adapter {
+    device: Arc<Mutex<GsUsb>>,
+    listener: Mutex<Option<ListenerThread>>,
+}
+
+impl Adapter {
+    /// The adapter with this serial number, or the first one found
+    pub fn new(serial_number: Option<String>) -> Result<Self, AdapterError> {
+        let mut builder = GsUsb::builder();
+        if let Some(serial_number) = serial_number {
+            builder = builder.serial(serial_number);
+        }
+        Ok(Self::from_device(builder.open()?))
+    }
+
+    /// The adapter at this USB bus number and address
+    pub fn at_location(bus: u8, address: u8) -> Result<Self, AdapterError> {
+        Ok(Self::from_device(
+            GsUsb::builder().location(bus, address).open()?,
+        ))
+    }
+
+    /// The adapter with this user ID
+    pub fn with_user_id(user_id: u32) -> Result<Self, AdapterError> {
+        Ok(Self::from_device(GsUsb::open_by_user_id(user_id)?))
+    }
+
+    /// The adapter of an Android `UsbDeviceConnection`, from its file
+    /// descriptor
+    ///
+    /// Only available on Android and Linux; fails with `Unsupported`
+    /// elsewhere. Exported to the bindings only: `fd` has to stay open while
+    /// the adapter exists, which Rust can't check, so Rust callers use the
+    /// unsafe `GsUsb::from_android_fd()` and `from_device()`.
+    pub(crate) fn from_android_fd(fd: i32) -> Result<Self, AdapterError> {
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            // SAFETY: the app keeps the connection, and with it `fd`, open
+            // while it uses the adapter, as the interface documents
+            let device = unsafe { GsUsb::from_android_fd(fd) }?;
+            Ok(Self::from_device(device))
+        }
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        {
+            let _ = fd;
+            Err(GsUsbError::FeatureNotSupported("USB file descriptors").into())
+        }
+    }
+
+    /// Wrap an opened device
+    pub fn from_device(device: GsUsb) -> Self {
+        Self {
+            device: Arc::new(Mutex::new(device)),
+            listener: Mutex::new(None),
+        }
+    }
+
+    pub fn info(&self) -> Result<AdapterInfo, AdapterError> {
+        Ok(adapter_info(&mut self.device())?)
+    }
+
+    pub fn start(&self, config: AdapterConfig) -> Result<(), AdapterError> {
+        Ok(self.device().apply(&Config::from(&config))?)
+    }
+
+    pub fn stop(&self) -> Result<(), AdapterError> {
+        self.set_listener(None);
+        Ok(self.device().stop()?)
+    }
+
+    pub fn send(&self, frame: Frame) -> Result<(), AdapterError> {
+        let frame = GsUsbFrame::try_from(&frame)?;
+        Ok(self.device().send(&frame)?)
+    }
+
+    pub fn receive(&self, timeout_ms: u32) -> Result<Option<Frame>, AdapterError> {
+        if lock(&self.listener).is_some() {
+            return Err(AdapterError::State {
+                message: "frames go to the listener".into(),
+            });
+        }
+        match self
+            .device()
+            .read(Duration::from_millis(u64::from(timeout_ms)))
+        {
+            Ok(frame) => Ok(Some(Frame::from(&frame))),
+            Err(GsUsbError::ReadTimeout) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn bus_state(&self, channel: u8) -> Result<BusState, AdapterError> {
+        let state = self.device().get_state(u16::from(channel))?;
+        Ok(BusState {
+            state: state.state_name().to_string(),
+            tec: state.txerr,
+            rec: state.rxerr,
+        })
+    }
+
+    pub fn set_listener(&self, listener: Option<Box<dyn AdapterListener>>) {
+        let mut current = lock(&self.listener);
+        if let Some(thread) = current.take() {
+            thread.join();
+        }
+        *current =
+            listener.map(|listener| ListenerThread::spawn(Arc::clone(&self.device), listener));
+    }
+
+    fn device(&self) -> MutexGuard<'_, GsUsb> {
+        lock(&self.device)
+    }
+}
+
+impl Drop for Adapter {
+    fn drop(&mut self) {
+        self.set_listener(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_bus::VirtualBus;
+    use std::sync::mpsc;
+
+    struct Forward(Mutex<mpsc::Sender<Frame>>);
+
+    impl AdapterListener for Forward {
+        fn on_frame(&self, frame: Frame) {
+            let _ = lock(&self.0).send(frame);
+        }
+
+        fn on_stopped(&self, _reason: String) {}
+    }
+
+    fn frame(id: u32, data: &[u8]) -> Frame {
+        Frame {
+            id,
+            extended: false,
+            remote: false,
+            fd: false,
+            brs: false,
+            data: data.to_vec(),
+            channel: 0,
+            echo: false,
+            error: false,
+            timestamp_us: 0,
+        }
+    }
+
+    fn started_pair(data_bitrate: Option<u32>) -> (Adapter, Adapter) {
+        let bus = VirtualBus::new();
+        let config = AdapterConfig {
+            bitrate: 500_000,
+            sample_point: None,
+            data_bitrate,
+            data_sample_point: None,
+            listen_only: false,
+            loopback: false,
+            one_shot: false,
+            hw_timestamp: false,
+        };
+        let pair = (
+            Adapter::from_device(bus.open()),
+            Adapter::from_device(bus.open()),
+        );
+        pair.0.start(config.clone()).unwrap();
+        pair.1.start(config).unwrap();
+        pair
+    }
+
+    #[test]
+    fn test_send_and_receive() {
+        let (a, b) = started_pair(Some(2_000_000));
+        let fd = Frame {
+            fd: true,
+            brs: true,
+            extended: true,
+            ..frame(0x1234, &[7; 12])
+        };
+        a.send(fd.clone()).unwrap();
+        let received = b.receive(100).unwrap().unwrap();
+        assert_eq!((received.id, received.extended), (0x1234, true));
+        assert_eq!((received.fd, received.brs), (true, true));
+        assert_eq!(received.data, fd.data);
+        assert!(a.receive(100).unwrap().unwrap().echo);
+        assert_eq!(b.receive(10).unwrap(), None);
+
+        assert!(matches!(
+            a.send(frame(0x800, &[])),
+            Err(AdapterError::Configuration { .. })
+        ));
+        assert_eq!(b.bus_state(0).unwrap().state, "ERROR_ACTIVE");
+    }
+
+    #[test]
+    fn test_listener() {
+        let (a, b) = started_pair(None);
+        let (tx, rx) = mpsc::channel();
+        b.set_listener(Some(Box::new(Forward(Mutex::new(tx)))));
+        assert!(matches!(b.receive(0), Err(AdapterError::State { .. })));
+
+        a.send(frame(0x100, &[1, 2])).unwrap();
+        let received = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((received.id, received.data), (0x100, vec![1, 2]));
+
+        b.set_listener(None);
+        a.send(frame(0x101, &[])).unwrap();
+        assert_eq!(b.receive(100).unwrap().unwrap().id, 0x101);
+    }
+}
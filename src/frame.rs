@@ -5,10 +5,13 @@
 
 use crate::constants::{
     CANFD_DLC_TO_LEN, CANFD_MAX_DLEN, CAN_EFF_FLAG, CAN_EFF_MASK, CAN_ERR_FLAG, CAN_MAX_DLEN,
-    CAN_RTR_FLAG, GS_CAN_FLAG_BRS, GS_CAN_FLAG_FD, GS_USB_ECHO_ID, GS_USB_FRAME_SIZE,
-    GS_USB_FRAME_SIZE_FD, GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP, GS_USB_FRAME_SIZE_HW_TIMESTAMP,
-    GS_USB_RX_ECHO_ID,
+    CAN_RTR_FLAG, GS_CAN_FLAG_BRS, GS_CAN_FLAG_FD, GS_CAN_FLAG_OVERFLOW, GS_USB_ECHO_ID,
+    GS_USB_FRAME_DATA_LEN, GS_USB_FRAME_HEADER_SIZE, GS_USB_FRAME_SIZE, GS_USB_FRAME_SIZE_FD,
+    GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP, GS_USB_FRAME_SIZE_HW_TIMESTAMP, GS_USB_RX_ECHO_ID,
 };
+use crate::error::{GsUsbError, Result};
+use crate::timestamp::TimestampSource;
+use std::time::{Duration, Instant, SystemTime};
 
 /// Convert DLC to data length
 pub fn dlc_to_len(dlc: u8, fd: bool) -> usize {
@@ -37,6 +40,38 @@ pub fn len_to_dlc(length: usize, fd: bool) -> u8 {
     }
 }
 
+/// Wire format context for parsing frames with `TryFrom`
+///
+/// The bytes of a frame don't tell whether a hardware timestamp follows the
+/// data, so this comes from the mode the channel was started in. Classic and
+/// FD frames are told apart by their flags.
+///
+/// ```
+/// use gs_usb::{FrameFormat, GsUsbFrame};
+///
+/// let bytes = GsUsbFrame::with_data(0x123, &[1, 2]).pack(true, false);
+/// let frame = GsUsbFrame::try_from((&bytes[..], FrameFormat::with_hw_timestamp()))?;
+/// assert_eq!(frame.data(), [1, 2]);
+/// # Ok::<(), gs_usb::GsUsbError>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameFormat {
+    /// Frames end with a 32-bit hardware timestamp
+    pub hw_timestamp: bool,
+}
+
+impl FrameFormat {
+    /// Frames without hardware timestamps
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames with hardware timestamps (`GS_CAN_MODE_HW_TIMESTAMP`)
+    pub fn with_hw_timestamp() -> Self {
+        Self { hw_timestamp: true }
+    }
+}
+
 /// GS-USB CAN frame
 ///
 /// Represents a CAN frame in the GS-USB protocol format.
@@ -55,10 +90,14 @@ pub struct GsUsbFrame {
     pub flags: u8,
     /// Reserved byte
     pub reserved: u8,
-    /// Frame data (up to 64 bytes for CAN FD)
-    pub data: [u8; CANFD_MAX_DLEN],
+    /// Frame data (up to 64 bytes for CAN FD, 8 without the `fd` feature)
+    pub data: [u8; GS_USB_FRAME_DATA_LEN],
     /// Hardware timestamp in microseconds
     pub timestamp_us: u32,
+    /// Host time of the frame, set by `GsUsb::read()`
+    host_time: Option<Instant>,
+    /// Where `host_time` and `timestamp_us` come from
+    timestamp_source: TimestampSource,
 }
 
 impl Default for GsUsbFrame {
@@ -77,8 +116,10 @@ impl GsUsbFrame {
             channel: 0,
             flags: 0,
             reserved: 0,
-            data: [0u8; CANFD_MAX_DLEN],
+            data: [0u8; GS_USB_FRAME_DATA_LEN],
             timestamp_us: 0,
+            host_time: None,
+            timestamp_source: TimestampSource::None,
         }
     }
 
@@ -98,7 +139,8 @@ impl GsUsbFrame {
     ///
     /// # Arguments
     /// * `can_id` - CAN identifier (with flags like CAN_EFF_FLAG if needed)
-    /// * `data` - Frame data (up to 64 bytes)
+    /// * `data` - Frame data (up to 64 bytes; 8 without the `fd` feature,
+    ///   where longer data is cut off and the device refuses to send the frame)
     /// * `brs` - Enable bit rate switch (transmit data at higher rate)
     pub fn with_fd_data(can_id: u32, data: &[u8], brs: bool) -> Self {
         let mut frame = Self::new();
@@ -114,12 +156,12 @@ impl GsUsbFrame {
     /// Set frame data
     fn set_data(&mut self, data: &[u8], fd: bool) {
         let max_len = if fd { CANFD_MAX_DLEN } else { CAN_MAX_DLEN };
-        let data_len = data.len().min(max_len);
+        let data_len = data.len().min(max_len).min(GS_USB_FRAME_DATA_LEN);
 
         // Clear data array and copy new data
-        self.data = [0u8; CANFD_MAX_DLEN];
+        self.data = [0u8; GS_USB_FRAME_DATA_LEN];
         self.data[..data_len].copy_from_slice(&data[..data_len]);
-        self.can_dlc = len_to_dlc(data.len(), fd);
+        self.can_dlc = len_to_dlc(data.len().min(GS_USB_FRAME_DATA_LEN), fd);
     }
 
     /// Get the arbitration ID (without flags)
@@ -152,6 +194,11 @@ impl GsUsbFrame {
         (self.flags & GS_CAN_FLAG_BRS) != 0
     }
 
+    /// Check if the device lost received frames before this one
+    pub fn is_overflow(&self) -> bool {
+        (self.flags & GS_CAN_FLAG_OVERFLOW) != 0
+    }
+
     /// Check if this is an echo frame (TX confirmation from device)
     pub fn is_echo_frame(&self) -> bool {
         self.echo_id != GS_USB_RX_ECHO_ID
@@ -163,13 +210,57 @@ impl GsUsbFrame {
     }
 
     /// Get timestamp in seconds
+    ///
+    /// Prefer `timestamp_duration()` for arithmetic on timestamps; an `f64`
+    /// can't represent every microsecond exactly.
     pub fn timestamp(&self) -> f64 {
         self.timestamp_us as f64 / 1_000_000.0
     }
 
+    /// Get the hardware timestamp as a duration since the counter's zero
+    pub fn timestamp_duration(&self) -> Duration {
+        Duration::from_micros(u64::from(self.timestamp_us))
+    }
+
+    /// Host time at which the frame was on the bus
+    ///
+    /// Set by `GsUsb::read()` for the `SyncedHost` and `HostArrival`
+    /// timestamp sources (see `timestamp_source()`). `None` for other sources
+    /// and for frames built locally.
+    pub fn host_instant(&self) -> Option<Instant> {
+        self.host_time
+    }
+
+    /// Source of this frame's timestamp
+    ///
+    /// `TimestampSource::None` for frames built locally.
+    pub fn timestamp_source(&self) -> TimestampSource {
+        self.timestamp_source
+    }
+
+    /// Wall-clock time at which the frame was on the bus
+    ///
+    /// Derived from `host_instant()` and the current system time, so it
+    /// follows wall-clock adjustments made since the frame was received.
+    pub fn system_time(&self) -> Option<SystemTime> {
+        let instant = self.host_time?;
+        let (now, wall) = (Instant::now(), SystemTime::now());
+        if instant <= now {
+            wall.checked_sub(now - instant)
+        } else {
+            wall.checked_add(instant - now)
+        }
+    }
+
+    /// Set the timestamp source and the host time returned by `host_instant()`
+    pub(crate) fn set_timestamp(&mut self, source: TimestampSource, instant: Option<Instant>) {
+        self.timestamp_source = source;
+        self.host_time = instant;
+    }
+
     /// Get actual data length based on DLC and frame type
     pub fn data_length(&self) -> usize {
-        dlc_to_len(self.can_dlc, self.is_fd())
+        dlc_to_len(self.can_dlc, self.is_fd()).min(GS_USB_FRAME_DATA_LEN)
     }
 
     /// Get frame data as a slice
@@ -189,62 +280,88 @@ impl GsUsbFrame {
 
     /// Pack frame into bytes for transmission
     ///
+    /// The bytes are returned on the stack, see `PackedFrame`.
+    ///
     /// # Arguments
     /// * `hw_timestamp` - Include timestamp field
     /// * `fd_mode` - Use CAN FD frame format (64-byte data)
-    pub fn pack(&self, hw_timestamp: bool, fd_mode: bool) -> Vec<u8> {
+    pub fn pack(&self, hw_timestamp: bool, fd_mode: bool) -> PackedFrame {
+        let mut packed = PackedFrame {
+            buf: [0; GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP],
+            len: 0,
+        };
+        packed.len = self.pack_into(&mut packed.buf, hw_timestamp, fd_mode);
+        packed
+    }
+
+    /// Pack frame into the start of `buf`, returning the number of bytes
+    /// written
+    ///
+    /// # Panics
+    /// If `buf` is shorter than `frame_size(hw_timestamp, fd_mode)`.
+    pub fn pack_into(&self, buf: &mut [u8], hw_timestamp: bool, fd_mode: bool) -> usize {
         let data_len = if fd_mode { 64 } else { 8 };
         let size = Self::frame_size(hw_timestamp, fd_mode);
-        let mut buf = Vec::with_capacity(size);
+        let buf = &mut buf[..size];
 
         // Header: echo_id (4) + can_id (4) + can_dlc (1) + channel (1) + flags (1) + reserved (1)
-        buf.extend_from_slice(&self.echo_id.to_le_bytes());
-        buf.extend_from_slice(&self.can_id.to_le_bytes());
-        buf.push(self.can_dlc);
-        buf.push(self.channel);
-        buf.push(self.flags);
-        buf.push(self.reserved);
-
-        // Data
-        buf.extend_from_slice(&self.data[..data_len]);
+        buf[0..4].copy_from_slice(&self.echo_id.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.can_id.to_le_bytes());
+        buf[8] = self.can_dlc;
+        buf[9] = self.channel;
+        buf[10] = self.flags;
+        buf[11] = self.reserved;
+
+        // Data, zero padded if FD frames don't fit in `data`
+        let data = &mut buf[GS_USB_FRAME_HEADER_SIZE..GS_USB_FRAME_HEADER_SIZE + data_len];
+        let kept = data_len.min(GS_USB_FRAME_DATA_LEN);
+        data[..kept].copy_from_slice(&self.data[..kept]);
+        data[kept..].fill(0);
 
         // Optional timestamp
         if hw_timestamp {
-            buf.extend_from_slice(&self.timestamp_us.to_le_bytes());
+            buf[size - 4..].copy_from_slice(&self.timestamp_us.to_le_bytes());
         }
 
-        buf
+        size
     }
 
     /// Unpack received bytes into this frame
     ///
+    /// Missing bytes of a short transfer are treated as zero; use
+    /// `from_received()` to reject truncated frames instead.
+    ///
     /// # Arguments
     /// * `data` - Raw bytes received from device
     /// * `hw_timestamp` - Data includes timestamp field
     /// * `fd_mode` - CAN FD frame format (64-byte data)
     pub fn unpack_from(&mut self, data: &[u8], hw_timestamp: bool, fd_mode: bool) {
+        let mut buf = [0u8; GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP];
+        let len = data.len().min(Self::frame_size(hw_timestamp, fd_mode));
+        buf[..len].copy_from_slice(&data[..len]);
+
         // Header
-        self.echo_id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-        self.can_id = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-        self.can_dlc = data[8];
-        self.channel = data[9];
-        self.flags = data[10];
-        self.reserved = data[11];
+        self.echo_id = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        self.can_id = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        self.can_dlc = buf[8];
+        self.channel = buf[9];
+        self.flags = buf[10];
+        self.reserved = buf[11];
 
         // Data
         let data_len = if fd_mode { 64 } else { 8 };
-        self.data = [0u8; CANFD_MAX_DLEN];
-        let copy_len = data_len.min(data.len() - 12);
-        self.data[..copy_len].copy_from_slice(&data[12..12 + copy_len]);
+        let kept = data_len.min(GS_USB_FRAME_DATA_LEN);
+        self.data = [0u8; GS_USB_FRAME_DATA_LEN];
+        self.data[..kept].copy_from_slice(&buf[12..12 + kept]);
 
         // Timestamp
         if hw_timestamp && data.len() >= 12 + data_len + 4 {
             let ts_offset = 12 + data_len;
             self.timestamp_us = u32::from_le_bytes([
-                data[ts_offset],
-                data[ts_offset + 1],
-                data[ts_offset + 2],
-                data[ts_offset + 3],
+                buf[ts_offset],
+                buf[ts_offset + 1],
+                buf[ts_offset + 2],
+                buf[ts_offset + 3],
             ]);
         } else {
             self.timestamp_us = 0;
@@ -262,6 +379,43 @@ impl GsUsbFrame {
         frame.unpack_from(data, hw_timestamp, fd_mode);
         frame
     }
+
+    /// Parse a frame received on the bulk IN endpoint
+    ///
+    /// Unlike `from_bytes()`, the frame format (classic or FD) is taken from
+    /// the frame's own flags and the transfer is validated: it must contain
+    /// the header and the payload announced by the DLC. Without the `fd`
+    /// feature, FD frames fail with `GsUsbError::FdNotSupported`.
+    ///
+    /// # Arguments
+    /// * `data` - Bytes of one bulk IN transfer
+    /// * `hw_timestamp` - Hardware timestamps are enabled on the device
+    pub fn from_received(data: &[u8], hw_timestamp: bool) -> Result<Self> {
+        if data.len() < GS_USB_FRAME_HEADER_SIZE {
+            return Err(GsUsbError::InvalidResponse {
+                expected: GS_USB_FRAME_HEADER_SIZE,
+                actual: data.len(),
+            });
+        }
+
+        // Determine if this is an FD frame by checking the flags byte (offset 10)
+        let fd_frame = (data[10] & GS_CAN_FLAG_FD) != 0;
+        if fd_frame && !cfg!(feature = "fd") {
+            return Err(GsUsbError::FdNotSupported);
+        }
+        let frame = Self::from_bytes(data, hw_timestamp, fd_frame);
+
+        // Reject transfers cut short inside the payload
+        let expected = GS_USB_FRAME_HEADER_SIZE + frame.data_length();
+        if data.len() < expected {
+            return Err(GsUsbError::InvalidResponse {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        Ok(frame)
+    }
 }
 
 impl std::fmt::Display for GsUsbFrame {
@@ -303,14 +457,153 @@ impl std::fmt::Debug for GsUsbFrame {
             .field("is_fd", &self.is_fd())
             .field("is_echo", &self.is_echo_frame())
             .field("timestamp_us", &self.timestamp_us)
+            .field("host_instant", &self.host_time)
+            .field("timestamp_source", &self.timestamp_source)
             .finish()
     }
 }
 
+impl TryFrom<&[u8]> for GsUsbFrame {
+    type Error = GsUsbError;
+
+    /// Parse a frame without hardware timestamp, as `from_received()`
+    fn try_from(data: &[u8]) -> Result<Self> {
+        Self::from_received(data, false)
+    }
+}
+
+impl TryFrom<(&[u8], FrameFormat)> for GsUsbFrame {
+    type Error = GsUsbError;
+
+    /// Parse a frame in the given format, as `from_received()`
+    fn try_from((data, format): (&[u8], FrameFormat)) -> Result<Self> {
+        Self::from_received(data, format.hw_timestamp)
+    }
+}
+
+/// The bytes of a packed frame, as returned by `GsUsbFrame::pack()`
+///
+/// Held in a buffer of the largest frame size, so packing frames for
+/// transmission doesn't allocate. Dereferences to the bytes of the frame.
+#[derive(Clone, Copy)]
+pub struct PackedFrame {
+    buf: [u8; GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP],
+    len: usize,
+}
+
+impl PackedFrame {
+    /// The packed bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl std::ops::Deref for PackedFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for PackedFrame {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl From<PackedFrame> for Vec<u8> {
+    fn from(packed: PackedFrame) -> Self {
+        packed.as_bytes().to_vec()
+    }
+}
+
+impl std::fmt::Debug for PackedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_bytes().fmt(f)
+    }
+}
+
+impl PartialEq for PackedFrame {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for PackedFrame {}
+
+impl PartialEq<[u8]> for PackedFrame {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+impl PartialEq<&[u8]> for PackedFrame {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.as_bytes() == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for PackedFrame {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self.as_bytes() == other.as_slice()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_from_received() {
+        let mut frame = GsUsbFrame::with_fd_data(0x123, &[0xAA; 12], true);
+        frame.timestamp_us = u32::MAX;
+        let packed = frame.pack(true, true);
+        let parsed = GsUsbFrame::from_received(&packed, true).unwrap();
+        assert!(parsed.is_fd());
+        assert_eq!(parsed.data(), frame.data());
+        assert_eq!(
+            parsed.timestamp_duration(),
+            Duration::from_micros(4_294_967_295)
+        );
+
+        assert!(GsUsbFrame::from_received(&packed[..5], true).is_err());
+        assert!(matches!(
+            GsUsbFrame::from_received(&packed[..20], true),
+            Err(GsUsbError::InvalidResponse {
+                expected: 24,
+                actual: 20
+            })
+        ));
+    }
+
+    #[test]
+    fn test_try_from() {
+        let frame = GsUsbFrame::with_data(0x123 | CAN_EFF_FLAG, &[1, 2, 3]);
+        let packed = frame.pack(false, false);
+        let parsed = GsUsbFrame::try_from(&packed[..]).unwrap();
+        assert_eq!(
+            (parsed.can_id, parsed.data()),
+            (frame.can_id, &[1, 2, 3][..])
+        );
+        assert!(GsUsbFrame::try_from(&packed[..8]).is_err());
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_try_from_fd() {
+        let mut frame = GsUsbFrame::with_fd_data(0x456, &[0x55; 20], false);
+        frame.timestamp_us = 1234;
+        let packed = frame.pack(true, true);
+        let format = FrameFormat::with_hw_timestamp();
+        let parsed = GsUsbFrame::try_from((&packed[..], format)).unwrap();
+        assert!(parsed.is_fd());
+        assert_eq!(parsed.timestamp_us, 1234);
+
+        assert!(GsUsbFrame::try_from(&packed[..8]).is_err());
+    }
+
     #[test]
     fn test_dlc_to_len_classic() {
         assert_eq!(dlc_to_len(0, false), 0);
@@ -353,6 +646,7 @@ mod tests {
         assert_eq!(frame.data(), &data);
     }
 
+    #[cfg(feature = "fd")]
     #[test]
     fn test_fd_frame_creation() {
         let data: Vec<u8> = (0..64).collect();
@@ -378,6 +672,21 @@ mod tests {
         assert_eq!(unpacked.data(), frame.data());
     }
 
+    #[test]
+    fn test_pack_into() {
+        let mut frame = GsUsbFrame::with_data(0x123, &[1, 2, 3]);
+        frame.timestamp_us = 0x0403_0201;
+        let mut buf = [0xFF; 32];
+        let len = frame.pack_into(&mut buf, true, false);
+        assert_eq!(len, GS_USB_FRAME_SIZE_HW_TIMESTAMP);
+        assert_eq!(frame.pack(true, false), buf[..len]);
+        assert_eq!(
+            &buf[20..],
+            [1, 2, 3, 4, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(Vec::from(frame.pack(false, false)).len(), GS_USB_FRAME_SIZE);
+    }
+
     #[test]
     fn test_pack_unpack_fd() {
         let data: Vec<u8> = (0..64).collect();
@@ -391,4 +700,86 @@ mod tests {
         assert_eq!(unpacked.flags, frame.flags);
         assert_eq!(unpacked.data(), frame.data());
     }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn fd_dlc_round_trips(dlc in 0u8..=15) {
+                prop_assert_eq!(len_to_dlc(dlc_to_len(dlc, true), true), dlc);
+            }
+
+            #[test]
+            fn classic_dlc_round_trips(dlc in 0u8..=8) {
+                prop_assert_eq!(len_to_dlc(dlc_to_len(dlc, false), false), dlc);
+            }
+
+            #[test]
+            fn len_to_dlc_picks_smallest_fit(len in 0usize..=CANFD_MAX_DLEN) {
+                let dlc = len_to_dlc(len, true);
+                prop_assert!(dlc_to_len(dlc, true) >= len);
+                prop_assert!(dlc == 0 || dlc_to_len(dlc - 1, true) < len);
+            }
+
+            #[test]
+            fn dlc_to_len_is_bounded(dlc: u8, fd: bool) {
+                let max = if fd { CANFD_MAX_DLEN } else { CAN_MAX_DLEN };
+                prop_assert!(dlc_to_len(dlc, fd) <= max);
+            }
+
+            #[cfg(feature = "fd")]
+            #[test]
+            fn bytes_round_trip(
+                hw_timestamp: bool,
+                fd_mode: bool,
+                bytes in prop::collection::vec(any::<u8>(), GS_USB_FRAME_SIZE_FD_HW_TIMESTAMP),
+            ) {
+                let bytes = &bytes[..GsUsbFrame::frame_size(hw_timestamp, fd_mode)];
+                let frame = GsUsbFrame::from_bytes(bytes, hw_timestamp, fd_mode);
+                prop_assert_eq!(frame.pack(hw_timestamp, fd_mode), bytes);
+            }
+
+            #[cfg(feature = "fd")]
+            #[test]
+            fn frame_round_trip(
+                hw_timestamp: bool,
+                fd_mode: bool,
+                can_id: u32,
+                channel: u8,
+                timestamp_us: u32,
+                payload in prop::collection::vec(any::<u8>(), 0..=CANFD_MAX_DLEN),
+            ) {
+                let mut frame = if fd_mode {
+                    GsUsbFrame::with_fd_data(can_id, &payload, true)
+                } else {
+                    GsUsbFrame::with_data(can_id, &payload[..payload.len().min(CAN_MAX_DLEN)])
+                };
+                frame.channel = channel;
+                frame.timestamp_us = timestamp_us;
+
+                let packed = frame.pack(hw_timestamp, fd_mode);
+                prop_assert_eq!(packed.len(), GsUsbFrame::frame_size(hw_timestamp, fd_mode));
+                let parsed = GsUsbFrame::from_received(&packed, hw_timestamp).unwrap();
+                prop_assert_eq!(parsed.can_id, frame.can_id);
+                prop_assert_eq!(parsed.channel, channel);
+                prop_assert_eq!(parsed.flags, frame.flags);
+                prop_assert_eq!(parsed.data(), frame.data());
+                prop_assert_eq!(parsed.timestamp_us, if hw_timestamp { timestamp_us } else { 0 });
+            }
+
+            #[test]
+            fn unpack_is_total(
+                hw_timestamp: bool,
+                fd_mode: bool,
+                bytes in prop::collection::vec(any::<u8>(), 0..128),
+            ) {
+                let frame = GsUsbFrame::from_bytes(&bytes, hw_timestamp, fd_mode);
+                let _ = frame.data();
+                let _ = frame.to_string();
+                let _ = GsUsbFrame::from_received(&bytes, hw_timestamp);
+            }
+        }
+    }
 }
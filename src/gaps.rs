@@ -0,0 +1,216 @@
+//! Inter-frame gap and period statistics
+//!
+//! `GapTracker` is fed received frames in order and keeps, from their
+//! hardware timestamps, statistics of the gaps between consecutive frames on
+//! the bus and of the period of every CAN identifier. Schedule verification
+//! and jitter analysis build on these.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GapTracker, GsUsb, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)?;
+//!
+//! let mut gaps = GapTracker::new();
+//! for _ in 0..1000 {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     if frame.is_rx_frame() {
+//!         gaps.observe(&frame);
+//!     }
+//! }
+//! for (id, period) in gaps.periods() {
+//!     println!("{id:>8X}  mean {:?}  jitter {:?}", period.mean().unwrap(), period.jitter());
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::constants::{CAN_EFF_FLAG, CAN_EFF_MASK};
+use crate::frame::GsUsbFrame;
+
+/// Minimum, maximum and mean of a series of intervals
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GapStats {
+    count: u64,
+    total_us: u64,
+    min_us: u32,
+    max_us: u32,
+    last_us: u32,
+}
+
+impl GapStats {
+    pub(crate) fn record(&mut self, gap_us: u32) {
+        if self.count == 0 {
+            self.min_us = gap_us;
+            self.max_us = gap_us;
+        } else {
+            self.min_us = self.min_us.min(gap_us);
+            self.max_us = self.max_us.max(gap_us);
+        }
+        self.count += 1;
+        self.total_us += u64::from(gap_us);
+        self.last_us = gap_us;
+    }
+
+    /// Number of intervals
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Shortest interval
+    pub fn min(&self) -> Option<Duration> {
+        (self.count > 0).then(|| micros(self.min_us))
+    }
+
+    /// Longest interval
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then(|| micros(self.max_us))
+    }
+
+    /// Mean interval
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.total_us / self.count))
+    }
+
+    /// Most recent interval
+    pub fn last(&self) -> Option<Duration> {
+        (self.count > 0).then(|| micros(self.last_us))
+    }
+
+    /// Difference between the longest and the shortest interval
+    pub fn jitter(&self) -> Duration {
+        micros(self.max_us - self.min_us)
+    }
+}
+
+fn micros(us: u32) -> Duration {
+    Duration::from_micros(u64::from(us))
+}
+
+/// Tracks inter-frame gaps and per-identifier periods
+///
+/// Identifiers are keyed with `CAN_EFF_FLAG` for extended frames, so a
+/// standard and an extended frame with the same numeric ID are kept apart.
+/// Timestamps are compared with wrap-around, so intervals must be shorter
+/// than the 71.6 minute counter period.
+#[derive(Debug, Clone, Default)]
+pub struct GapTracker {
+    last_timestamp: Option<u32>,
+    gaps: GapStats,
+    /// Last timestamp and period statistics per identifier
+    ids: BTreeMap<u32, (u32, GapStats)>,
+}
+
+impl GapTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a frame, using its hardware timestamp
+    pub fn observe(&mut self, frame: &GsUsbFrame) {
+        self.record(
+            frame.can_id & (CAN_EFF_FLAG | CAN_EFF_MASK),
+            frame.timestamp_us,
+        );
+    }
+
+    /// Add an occurrence of `can_id` at `timestamp_us`
+    pub fn record(&mut self, can_id: u32, timestamp_us: u32) {
+        if let Some(last) = self.last_timestamp {
+            self.gaps.record(timestamp_us.wrapping_sub(last));
+        }
+        self.last_timestamp = Some(timestamp_us);
+
+        match self.ids.get_mut(&can_id) {
+            Some((last, period)) => {
+                period.record(timestamp_us.wrapping_sub(*last));
+                *last = timestamp_us;
+            }
+            None => {
+                self.ids.insert(can_id, (timestamp_us, GapStats::default()));
+            }
+        }
+    }
+
+    /// Gaps between consecutive frames, regardless of identifier
+    pub fn gaps(&self) -> &GapStats {
+        &self.gaps
+    }
+
+    /// Period of an identifier, if it has been seen
+    pub fn period(&self, can_id: u32) -> Option<&GapStats> {
+        self.ids.get(&can_id).map(|(_, period)| period)
+    }
+
+    /// Periods of all identifiers seen, in ascending identifier order
+    pub fn periods(&self) -> impl Iterator<Item = (u32, &GapStats)> {
+        self.ids.iter().map(|(&id, (_, period))| (id, period))
+    }
+
+    /// Forget all frames seen so far
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_periods_and_gaps() {
+        let mut tracker = GapTracker::new();
+        // 0x100 every 10 ms with 1 ms jitter, 0x200 every 20 ms, across a wrap
+        let start = u32::MAX - 15_000;
+        for (id, at) in [
+            (0x100, 0),
+            (0x200, 5_000),
+            (0x100, 10_000),
+            (0x100, 21_000),
+            (0x200, 25_000),
+            (0x100, 30_000),
+        ] {
+            tracker.record(id, start.wrapping_add(at));
+        }
+
+        let period = tracker.period(0x100).unwrap();
+        assert_eq!(period.count(), 3);
+        assert_eq!(period.min(), Some(Duration::from_millis(9)));
+        assert_eq!(period.max(), Some(Duration::from_millis(11)));
+        assert_eq!(period.mean(), Some(Duration::from_millis(10)));
+        assert_eq!(period.jitter(), Duration::from_millis(2));
+        assert_eq!(
+            tracker.period(0x200).unwrap().last(),
+            Some(Duration::from_millis(20))
+        );
+        assert!(tracker.period(0x300).is_none());
+
+        assert_eq!(tracker.gaps().count(), 5);
+        assert_eq!(tracker.gaps().min(), Some(Duration::from_millis(4)));
+        assert_eq!(tracker.gaps().max(), Some(Duration::from_millis(11)));
+
+        let ids: Vec<u32> = tracker.periods().map(|(id, _)| id).collect();
+        assert_eq!(ids, [0x100, 0x200]);
+    }
+
+    #[test]
+    fn test_extended_ids_kept_apart() {
+        let mut tracker = GapTracker::new();
+        let mut frame = GsUsbFrame::with_data(0x100, &[]);
+        tracker.observe(&frame);
+        frame.can_id |= CAN_EFF_FLAG;
+        frame.timestamp_us = 1_000;
+        tracker.observe(&frame);
+
+        assert_eq!(tracker.period(0x100).unwrap().count(), 0);
+        assert!(tracker.period(0x100 | CAN_EFF_FLAG).is_some());
+        assert_eq!(tracker.gaps().last(), Some(Duration::from_millis(1)));
+    }
+}
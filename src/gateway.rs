@@ -0,0 +1,442 @@
+//! Frame forwarding between devices
+//!
+//! This module provides `Gateway`, which forwards frames received on one device
+//! to another, and `Translation`, which describes how frames are converted when
+//! the two buses differ in CAN FD support:
+//!
+//! - FD frames with more than 8 bytes can be dropped, truncated, or fragmented
+//!   into several classic frames (see `FdToClassic` and `fragment()`)
+//! - Classic frames can be upgraded to FD frames on the way to an FD bus
+//! - The BRS and ESI flags can be stripped
+//!
+//! A gateway can also pass only the frames matching acceptance filters,
+//! rewrite identifiers, and connect particular channels of the two devices.
+//!
+//! # Fragmentation scheme
+//!
+//! Fragmented payloads use the scheme of the `segmentation` module: classic
+//! frames with the same identifier, each starting with a header byte. A
+//! `Desegmenter` on the classic bus reassembles them.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::IdFilter;
+use crate::constants::{
+    CAN_EFF_FLAG, CAN_EFF_MASK, CAN_MAX_DLEN, GS_CAN_FLAG_BRS, GS_CAN_FLAG_ESI, GS_CAN_FLAG_FD,
+};
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::segmentation::segment;
+
+/// What to do with FD frames longer than 8 bytes on their way to a classic bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FdToClassic {
+    /// Drop the frame
+    #[default]
+    Drop,
+    /// Forward only the first 8 bytes
+    Truncate,
+    /// Split the payload into several classic frames (see module docs)
+    Fragment,
+}
+
+/// Frame conversion applied by a `Gateway`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Translation {
+    /// Whether the destination bus runs in CAN FD mode
+    pub destination_fd: bool,
+    /// Policy for FD frames that don't fit a classic frame
+    pub fd_to_classic: FdToClassic,
+    /// Send classic frames as FD frames when the destination is an FD bus
+    pub upgrade_to_fd: bool,
+    /// Set BRS on frames upgraded to FD
+    pub upgrade_brs: bool,
+    /// Clear the bit rate switch flag on forwarded FD frames
+    pub strip_brs: bool,
+    /// Clear the error state indicator flag on forwarded FD frames
+    pub strip_esi: bool,
+}
+
+impl Translation {
+    /// Translation towards a classic CAN bus with the given policy
+    pub fn to_classic(policy: FdToClassic) -> Self {
+        Self {
+            destination_fd: false,
+            fd_to_classic: policy,
+            ..Self::default()
+        }
+    }
+
+    /// Translation towards a CAN FD bus
+    pub fn to_fd() -> Self {
+        Self {
+            destination_fd: true,
+            ..Self::default()
+        }
+    }
+
+    /// Convert a frame for the destination bus
+    ///
+    /// Returns the frames to send, which may be empty (dropped) or contain
+    /// several fragments.
+    pub fn translate(&self, frame: &GsUsbFrame) -> Vec<GsUsbFrame> {
+        let mut out = frame.clone();
+        out.echo_id = GsUsbFrame::new().echo_id;
+        out.timestamp_us = 0;
+
+        if self.destination_fd {
+            if out.is_fd() {
+                if self.strip_brs {
+                    out.flags &= !GS_CAN_FLAG_BRS;
+                }
+                if self.strip_esi {
+                    out.flags &= !GS_CAN_FLAG_ESI;
+                }
+            } else if self.upgrade_to_fd && !out.is_remote_frame() {
+                out.flags |= GS_CAN_FLAG_FD;
+                if self.upgrade_brs {
+                    out.flags |= GS_CAN_FLAG_BRS;
+                }
+            }
+            return vec![out];
+        }
+
+        if !out.is_fd() {
+            return vec![out];
+        }
+
+        out.flags &= !(GS_CAN_FLAG_FD | GS_CAN_FLAG_BRS | GS_CAN_FLAG_ESI);
+        let data = frame.data();
+        if data.len() <= CAN_MAX_DLEN {
+            return vec![out];
+        }
+
+        let mut frames = match self.fd_to_classic {
+            FdToClassic::Drop => Vec::new(),
+            FdToClassic::Truncate => vec![GsUsbFrame::with_data(out.can_id, &data[..CAN_MAX_DLEN])],
+            // FD payloads are at most 64 bytes and always fit
+            FdToClassic::Fragment => fragment(out.can_id, data).unwrap_or_default(),
+        };
+        for f in &mut frames {
+            f.channel = out.channel;
+        }
+        frames
+    }
+}
+
+/// Split a payload into classic frames using the gateway fragmentation scheme
+///
+/// The same as `segment()`: fails with `InvalidConfig` for payloads longer
+/// than `MAX_SEGMENTED_LEN`.
+pub fn fragment(can_id: u32, payload: &[u8]) -> Result<Vec<GsUsbFrame>> {
+    segment(can_id, payload)
+}
+
+/// Forwarding counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GatewayStats {
+    /// Frames received from the source device (echoes excluded)
+    pub received: u64,
+    /// Frames sent to the destination device (fragments counted individually)
+    pub forwarded: u64,
+    /// Frames dropped by the translation policy
+    pub dropped: u64,
+    /// Frames not forwarded because of the filters or their channel
+    pub filtered: u64,
+}
+
+/// Identifier bits of `can_id`, with the extended frame flag
+const ID_BITS: u32 = CAN_EFF_FLAG | CAN_EFF_MASK;
+
+/// One-way frame forwarder between two started devices
+///
+/// Echo frames (TX confirmations) and error frames on the source are not
+/// forwarded.
+pub struct Gateway {
+    source: GsUsb,
+    destination: GsUsb,
+    translation: Translation,
+    filters: Vec<IdFilter>,
+    remaps: BTreeMap<u32, u32>,
+    channels: Option<(u8, u8)>,
+    stats: GatewayStats,
+}
+
+impl Gateway {
+    /// Create a gateway forwarding from `source` to `destination`
+    pub fn new(source: GsUsb, destination: GsUsb) -> Self {
+        Self {
+            source,
+            destination,
+            translation: Translation::default(),
+            filters: Vec::new(),
+            remaps: BTreeMap::new(),
+            channels: None,
+            stats: GatewayStats::default(),
+        }
+    }
+
+    /// Forward only frames matching one of `filters`; all frames if empty
+    pub fn with_filters(mut self, filters: Vec<IdFilter>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Forward frames with identifier `from` as `to`
+    ///
+    /// Identifiers are given as in `GsUsbFrame::can_id`, with `CAN_EFF_FLAG`
+    /// for extended ones, so a remap can also change the identifier format.
+    /// Filters see the original identifier.
+    pub fn remap(mut self, from: u32, to: u32) -> Self {
+        self.remaps.insert(from & ID_BITS, to & ID_BITS);
+        self
+    }
+
+    /// Forward only frames received on channel `source`, and send them on
+    /// channel `destination`
+    ///
+    /// Without this, frames keep the channel they were received on.
+    pub fn with_channels(mut self, source: u8, destination: u8) -> Self {
+        self.channels = Some((source, destination));
+        self
+    }
+
+    /// Set the frame translation
+    pub fn with_translation(mut self, translation: Translation) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    /// Get the frame translation
+    pub fn translation(&self) -> &Translation {
+        &self.translation
+    }
+
+    /// Forward at most one received frame
+    ///
+    /// Returns the number of frames sent to the destination (0 if the frame
+    /// was dropped, filtered, or nothing arrived within `timeout`).
+    pub fn poll(&mut self, timeout: Duration) -> Result<usize> {
+        let frame = match self.source.read(timeout) {
+            Ok(frame) => frame,
+            Err(GsUsbError::ReadTimeout) => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        if frame.is_echo_frame() || frame.is_error_frame() {
+            return Ok(0);
+        }
+
+        self.stats.received += 1;
+        if self
+            .channels
+            .is_some_and(|(source, _)| frame.channel != source)
+            || !(self.filters.is_empty() || self.filters.iter().any(|f| f.matches(&frame)))
+        {
+            self.stats.filtered += 1;
+            return Ok(0);
+        }
+
+        let mut frame = frame;
+        if let Some(&to) = self.remaps.get(&(frame.can_id & ID_BITS)) {
+            frame.can_id = (frame.can_id & !ID_BITS) | to;
+        }
+        let mut out = self.translation.translate(&frame);
+        if out.is_empty() {
+            self.stats.dropped += 1;
+        }
+        for f in &mut out {
+            if let Some((_, destination)) = self.channels {
+                f.channel = destination;
+            }
+            self.destination.send(f)?;
+            self.stats.forwarded += 1;
+        }
+        Ok(out.len())
+    }
+
+    /// Get forwarding counters
+    pub fn stats(&self) -> GatewayStats {
+        self.stats
+    }
+
+    /// Consume the gateway and return the (source, destination) devices
+    pub fn into_devices(self) -> (GsUsb, GsUsb) {
+        (self.source, self.destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    #[cfg(feature = "fd")]
+    use crate::constants::GS_USB_RX_ECHO_ID;
+    #[cfg(feature = "fd")]
+    use crate::reassembly::Reassembly;
+    #[cfg(feature = "fd")]
+    use crate::segmentation::{Desegmenter, Segmented, MAX_SEGMENTED_LEN};
+    use crate::virtual_bus::VirtualBus;
+
+    fn fd_frame(len: u8) -> GsUsbFrame {
+        let data: Vec<u8> = (0..len).collect();
+        GsUsbFrame::with_fd_data(0x123, &data, true)
+    }
+
+    #[test]
+    fn test_short_fd_frame_becomes_classic() {
+        let out = Translation::to_classic(FdToClassic::Drop).translate(&fd_frame(6));
+        assert_eq!(out.len(), 1);
+        assert!(!out[0].is_fd());
+        assert!(!out[0].is_brs());
+        assert_eq!(out[0].data(), &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_drop_and_truncate() {
+        assert!(Translation::to_classic(FdToClassic::Drop)
+            .translate(&fd_frame(12))
+            .is_empty());
+
+        let mut frame = fd_frame(12);
+        frame.channel = 1;
+        let out = Translation::to_classic(FdToClassic::Truncate).translate(&frame);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].data(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(out[0].channel, 1);
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_fragment() {
+        let out = Translation::to_classic(FdToClassic::Fragment).translate(&fd_frame(16));
+        assert_eq!(out.len(), 3);
+        assert_eq!(out[0].data(), &[0x80 | 16, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(out[1].data(), &[1, 7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(out[2].data(), &[2, 14, 15]);
+        assert!(out
+            .iter()
+            .all(|f| f.arbitration_id() == 0x123 && !f.is_fd()));
+
+        // Reassembled by a Desegmenter on the classic bus
+        let mut desegmenter = Desegmenter::new(Segmented);
+        let events: Vec<_> = out
+            .iter()
+            .flat_map(|fragment| {
+                let mut received = fragment.clone();
+                received.echo_id = GS_USB_RX_ECHO_ID;
+                desegmenter.observe(&received)
+            })
+            .collect();
+        assert!(matches!(
+            &events[..],
+            [Reassembly::Complete(message)] if message.data == fd_frame(16).data()
+        ));
+        assert!(fragment(0x123, &[0; MAX_SEGMENTED_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn test_flag_translation() {
+        let mut frame = fd_frame(12);
+        frame.flags |= GS_CAN_FLAG_ESI;
+        let translation = Translation {
+            strip_brs: true,
+            strip_esi: true,
+            ..Translation::to_fd()
+        };
+        let out = translation.translate(&frame);
+        assert!(out[0].is_fd());
+        assert_eq!(out[0].flags & (GS_CAN_FLAG_BRS | GS_CAN_FLAG_ESI), 0);
+
+        let upgrade = Translation {
+            upgrade_to_fd: true,
+            upgrade_brs: true,
+            ..Translation::to_fd()
+        };
+        let out = upgrade.translate(&GsUsbFrame::with_data(0x10, &[1, 2]));
+        assert!(out[0].is_fd() && out[0].is_brs());
+        assert_eq!(out[0].data(), &[1, 2]);
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_gateway_forwards_fd_to_classic() {
+        use crate::constants::GS_CAN_MODE_FD;
+
+        let fd_bus = VirtualBus::new();
+        let classic_bus = VirtualBus::new();
+
+        let mut sender = fd_bus.open();
+        let mut source = fd_bus.open();
+        let mut destination = classic_bus.open();
+        let mut listener = classic_bus.open();
+        for dev in [&mut sender, &mut source] {
+            dev.set_bitrate(500_000).unwrap();
+            dev.start(GS_CAN_MODE_FD).unwrap();
+        }
+        for dev in [&mut destination, &mut listener] {
+            dev.set_bitrate(500_000).unwrap();
+            dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        }
+
+        let mut gateway = Gateway::new(source, destination)
+            .with_translation(Translation::to_classic(FdToClassic::Fragment));
+
+        // Fragments keep the channel the frame was received on
+        let mut frame = fd_frame(12);
+        frame.channel = 1;
+        sender.send(&frame).unwrap();
+        assert_eq!(gateway.poll(Duration::from_millis(100)).unwrap(), 2);
+
+        let first = listener.read(Duration::from_millis(100)).unwrap();
+        assert_eq!(first.data()[0], 0x80 | 12);
+        let second = listener.read(Duration::from_millis(100)).unwrap();
+        assert_eq!(second.data(), &[1, 7, 8, 9, 10, 11]);
+        assert_eq!((first.channel, second.channel), (1, 1));
+
+        assert_eq!(
+            gateway.stats(),
+            GatewayStats {
+                received: 1,
+                forwarded: 2,
+                dropped: 0,
+                filtered: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_filters_and_remaps() {
+        let source_bus = VirtualBus::new();
+        let destination_bus = VirtualBus::new();
+
+        let mut sender = source_bus.open();
+        let mut source = source_bus.open();
+        let mut destination = destination_bus.open();
+        let mut listener = destination_bus.open();
+        for dev in [&mut sender, &mut source, &mut destination, &mut listener] {
+            dev.set_bitrate(500_000).unwrap();
+            dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        }
+
+        let mut gateway = Gateway::new(source, destination)
+            .with_filters(vec![IdFilter::new(0x100, 0x700)])
+            .remap(0x123, 0x1234 | CAN_EFF_FLAG)
+            .with_channels(0, 0);
+        for id in [0x123, 0x200, 0x1FF] {
+            sender.send(&GsUsbFrame::with_data(id, &[1])).unwrap();
+        }
+        // Pending frames win arbitration by identifier: 0x123, 0x1FF, 0x200
+        let timeout = Duration::from_millis(100);
+        let forwarded: Vec<usize> = (0..3).map(|_| gateway.poll(timeout).unwrap()).collect();
+        assert_eq!(forwarded, [1, 1, 0]);
+
+        let remapped = listener.read(timeout).unwrap();
+        assert!(remapped.is_extended_id());
+        assert_eq!(remapped.arbitration_id(), 0x1234);
+        assert_eq!(listener.read(timeout).unwrap().arbitration_id(), 0x1FF);
+        assert_eq!(gateway.stats().filtered, 1);
+    }
+}
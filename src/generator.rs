@@ -0,0 +1,426 @@
+//! Synthetic CAN traffic
+//!
+//! `Generator` produces frames for stress-testing devices and the software
+//! reading from them: identifiers from a range, in order or at random,
+//! payloads with random, counting or fixed content, and optionally a share
+//! of CAN FD frames. `run()` sends them paced to a frame rate or to a share
+//! of the bus bandwidth. Generators are seeded, so a run can be repeated
+//! frame for frame.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{Generator, GsUsb, PayloadPattern, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let mut generator = Generator::new(1)
+//!     .ids(0x100..=0x1FF)
+//!     .payload(PayloadPattern::Counter)
+//!     .bus_load(30.0, 500_000.into());
+//! let stats = generator.run(&mut dev, None, Some(Duration::from_secs(10)))?;
+//! println!("{} frames, {:.0} frames/s", stats.frames, stats.frame_rate());
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use crate::bitrate::Bitrate;
+use crate::constants::{CANFD_MAX_DLEN, CAN_EFF_FLAG, CAN_EFF_MASK, CAN_MAX_DLEN, CAN_SFF_MASK};
+use crate::device::GsUsb;
+use crate::error::Result;
+use crate::frame::{dlc_to_len, len_to_dlc, GsUsbFrame};
+use crate::rng::XorShift64;
+
+/// Content of generated payloads
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum PayloadPattern {
+    /// Random bytes
+    #[default]
+    Random,
+    /// Frame sequence number, little-endian in the first (up to) 8 bytes,
+    /// so a receiver can tell lost and reordered frames
+    Counter,
+    /// These bytes, repeated to the frame length
+    Fixed(Vec<u8>),
+}
+
+/// How frames are spaced in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// As fast as the device accepts them
+    Unlimited,
+    /// Frames per second
+    FrameRate(f64),
+    /// Percentage of the bus bandwidth at the given bitrates
+    BusLoad {
+        /// Share of the bus time the frames occupy, 0 to 100
+        percent: f64,
+        /// Nominal and data bitrate of the bus
+        bitrate: Bitrate,
+    },
+}
+
+/// Counters of a `Generator::run()`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GeneratorStats {
+    /// Frames sent
+    pub frames: u64,
+    /// CAN FD frames among them
+    pub fd_frames: u64,
+    /// Payload bytes sent
+    pub bytes: u64,
+    /// Time the run took
+    pub elapsed: Duration,
+}
+
+impl GeneratorStats {
+    /// Average frames per second
+    pub fn frame_rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.frames as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Seeded source of synthetic frames
+///
+/// Defaults to random standard identifiers, 0 to 8 random bytes, classic
+/// frames only, sent as fast as possible.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    ids: RangeInclusive<u32>,
+    extended: bool,
+    sequential: bool,
+    lengths: RangeInclusive<usize>,
+    payload: PayloadPattern,
+    fd_ratio: f64,
+    brs: bool,
+    pacing: Pacing,
+    rng: XorShift64,
+    next_id: u32,
+    sequence: u64,
+}
+
+impl Generator {
+    /// Generator with the default settings and the given seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            ids: 0..=CAN_SFF_MASK,
+            extended: false,
+            sequential: false,
+            lengths: 0..=CAN_MAX_DLEN,
+            payload: PayloadPattern::Random,
+            fd_ratio: 0.0,
+            brs: false,
+            pacing: Pacing::Unlimited,
+            rng: XorShift64::new(seed),
+            next_id: 0,
+            sequence: 0,
+        }
+    }
+
+    /// Use standard identifiers from `ids`, clamped to 11 bits
+    pub fn ids(mut self, ids: RangeInclusive<u32>) -> Self {
+        self.ids = clamp_range(ids, CAN_SFF_MASK);
+        self.extended = false;
+        self.next_id = *self.ids.start();
+        self
+    }
+
+    /// Use extended identifiers from `ids`, clamped to 29 bits
+    pub fn extended_ids(mut self, ids: RangeInclusive<u32>) -> Self {
+        self.ids = clamp_range(ids, CAN_EFF_MASK);
+        self.extended = true;
+        self.next_id = *self.ids.start();
+        self
+    }
+
+    /// Walk through the identifier range in order instead of at random
+    pub fn sequential(mut self) -> Self {
+        self.sequential = true;
+        self
+    }
+
+    /// Payload lengths in bytes
+    ///
+    /// Classic frames are cut to 8 bytes, FD frames rounded up to the next
+    /// valid FD length.
+    pub fn lengths(mut self, lengths: RangeInclusive<usize>) -> Self {
+        self.lengths = clamp_range(lengths, CANFD_MAX_DLEN);
+        self
+    }
+
+    /// Content of the payloads
+    pub fn payload(mut self, payload: PayloadPattern) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Send `ratio` (0 to 1) of the frames as CAN FD frames, with or without
+    /// bit rate switch
+    pub fn fd_mix(mut self, ratio: f64, brs: bool) -> Self {
+        self.fd_ratio = ratio.clamp(0.0, 1.0);
+        self.brs = brs;
+        self
+    }
+
+    /// Send `frames_per_second` frames per second
+    pub fn frame_rate(mut self, frames_per_second: f64) -> Self {
+        self.pacing = Pacing::FrameRate(frames_per_second);
+        self
+    }
+
+    /// Space frames so they occupy `percent` of the bus time at `bitrate`
+    pub fn bus_load(mut self, percent: f64, bitrate: Bitrate) -> Self {
+        self.pacing = Pacing::BusLoad { percent, bitrate };
+        self
+    }
+
+    /// Pacing used by `run()`
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    /// Generate the next frame
+    pub fn next_frame(&mut self) -> GsUsbFrame {
+        let id = self.next_id();
+        let fd = self.rng.chance(self.fd_ratio);
+        let length = self.next_length(fd);
+        let data = self.next_payload(length);
+        self.sequence += 1;
+
+        let can_id = if self.extended { id | CAN_EFF_FLAG } else { id };
+        if fd {
+            GsUsbFrame::with_fd_data(can_id, &data, self.brs)
+        } else {
+            GsUsbFrame::with_data(can_id, &data)
+        }
+    }
+
+    /// Time to wait after `frame` before sending the next one
+    ///
+    /// `Duration::MAX` for rates and loads too small to pace by.
+    pub fn interval(&self, frame: &GsUsbFrame) -> Duration {
+        let seconds = match self.pacing {
+            Pacing::Unlimited => return Duration::ZERO,
+            Pacing::FrameRate(rate) if rate > 0.0 => 1.0 / rate,
+            Pacing::BusLoad { percent, bitrate } if percent > 0.0 => {
+                (100.0 / percent) * frame_duration(frame, &bitrate).as_secs_f64()
+            }
+            Pacing::FrameRate(_) | Pacing::BusLoad { .. } => return Duration::MAX,
+        };
+        Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX)
+    }
+
+    /// Send generated frames until `max_frames` are sent or `max_duration`
+    /// has passed, whichever comes first
+    ///
+    /// With neither limit set, runs until sending fails. Pacing keeps to a
+    /// schedule, so a send that took long is made up for by the next ones.
+    pub fn run(
+        &mut self,
+        dev: &mut GsUsb,
+        max_frames: Option<u64>,
+        max_duration: Option<Duration>,
+    ) -> Result<GeneratorStats> {
+        let start = Instant::now();
+        let mut stats = GeneratorStats::default();
+        let mut next_at = start;
+        loop {
+            if max_frames.is_some_and(|max| stats.frames >= max)
+                || max_duration.is_some_and(|max| next_at - start >= max)
+            {
+                break;
+            }
+            let now = Instant::now();
+            if next_at > now {
+                std::thread::sleep(next_at - now);
+            }
+
+            let frame = self.next_frame();
+            dev.send(&frame)?;
+            stats.frames += 1;
+            stats.bytes += frame.data_length() as u64;
+            if frame.is_fd() {
+                stats.fd_frames += 1;
+            }
+
+            match next_at.checked_add(self.interval(&frame)) {
+                Some(at) => next_at = at,
+                None => break,
+            }
+        }
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+
+    fn next_id(&mut self) -> u32 {
+        let (first, last) = (*self.ids.start(), *self.ids.end());
+        if self.sequential {
+            let id = self.next_id;
+            self.next_id = if id >= last { first } else { id + 1 };
+            id
+        } else {
+            let span = u64::from(last - first) + 1;
+            first + (self.rng.next_u64() % span) as u32
+        }
+    }
+
+    fn next_length(&mut self, fd: bool) -> usize {
+        let (first, last) = (*self.lengths.start(), *self.lengths.end());
+        let length = first + (self.rng.next_u64() % (last - first + 1) as u64) as usize;
+        if fd {
+            dlc_to_len(len_to_dlc(length, true), true)
+        } else {
+            length.min(CAN_MAX_DLEN)
+        }
+    }
+
+    fn next_payload(&mut self, length: usize) -> Vec<u8> {
+        match &self.payload {
+            PayloadPattern::Random => (0..length).map(|_| self.rng.next_u64() as u8).collect(),
+            PayloadPattern::Counter => {
+                let mut data = vec![0; length];
+                let counter = self.sequence.to_le_bytes();
+                let n = length.min(counter.len());
+                data[..n].copy_from_slice(&counter[..n]);
+                data
+            }
+            PayloadPattern::Fixed(bytes) if bytes.is_empty() => vec![0; length],
+            PayloadPattern::Fixed(bytes) => bytes.iter().copied().cycle().take(length).collect(),
+        }
+    }
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+fn clamp_range<T: Ord + Copy>(range: RangeInclusive<T>, max: T) -> RangeInclusive<T> {
+    let end = (*range.end()).min(max);
+    (*range.start()).min(end)..=end
+}
+
+/// Estimated time `frame` occupies the bus, without stuff bits
+///
+/// The bit rate switched part of an FD frame runs at the data bitrate, or
+/// at the nominal bitrate if `bitrate` has none.
+pub fn frame_duration(frame: &GsUsbFrame, bitrate: &Bitrate) -> Duration {
+    let extended = frame.is_extended_id();
+    let data_bits = 8 * frame.data_length() as u64;
+    let (nominal_bits, fast_bits) = if frame.is_fd() {
+        // SOF, arbitration and control field up to BRS, then CRC delimiter,
+        // ACK, EOF and intermission
+        let nominal = if extended { 36 } else { 17 } + 13;
+        // ESI, DLC, stuff count and CRC
+        let crc = if frame.data_length() > 16 { 21 } else { 17 };
+        (nominal, 1 + 4 + 4 + crc + data_bits)
+    } else {
+        (if extended { 67 } else { 47 } + data_bits, 0)
+    };
+    let fast_rate = if frame.is_brs() {
+        bitrate.data.unwrap_or(bitrate.nominal)
+    } else {
+        bitrate.nominal
+    };
+    Duration::from_secs_f64(
+        nominal_bits as f64 / f64::from(bitrate.nominal) + fast_bits as f64 / f64::from(fast_rate),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::mock::MockGsUsb;
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_patterns() {
+        let mut generator = Generator::new(7)
+            .ids(0x7FE..=0x900)
+            .sequential()
+            .lengths(2..=2)
+            .payload(PayloadPattern::Counter);
+        let ids: Vec<u32> = (0..3)
+            .map(|_| generator.next_frame().arbitration_id())
+            .collect();
+        assert_eq!(ids, [0x7FE, 0x7FF, 0x7FE]);
+        assert_eq!(generator.next_frame().data(), &[3, 0]);
+
+        let mut fixed = Generator::new(7)
+            .extended_ids(0x1234..=0x1234)
+            .lengths(5..=5)
+            .payload(PayloadPattern::Fixed(vec![0xAA, 0x55]));
+        let frame = fixed.next_frame();
+        assert!(frame.is_extended_id());
+        assert_eq!(frame.data(), &[0xAA, 0x55, 0xAA, 0x55, 0xAA]);
+
+        // Same seed, same frames
+        let frames = |seed| {
+            let mut generator = Generator::new(seed).fd_mix(0.5, true).lengths(0..=64);
+            (0..20)
+                .map(|_| generator.next_frame().pack(false, true))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(frames(1), frames(1));
+        assert_ne!(frames(1), frames(2));
+
+        let mut fd = Generator::new(3).fd_mix(1.0, false).lengths(9..=9);
+        let frame = fd.next_frame();
+        assert!(frame.is_fd());
+        assert_eq!(frame.data_length(), 12);
+    }
+
+    #[test]
+    fn test_pacing() {
+        let frame = GsUsbFrame::with_data(0x100, &[0; 8]);
+        // 111 bits at 500 kbit/s
+        assert_eq!(
+            frame_duration(&frame, &Bitrate::new(500_000)),
+            Duration::from_micros(222)
+        );
+        let generator = Generator::new(0).bus_load(50.0, Bitrate::new(500_000));
+        assert_eq!(generator.interval(&frame), Duration::from_micros(444));
+        let generator = Generator::new(0).frame_rate(1000.0);
+        assert_eq!(generator.interval(&frame), Duration::from_millis(1));
+
+        // Rates and loads too small to pace by
+        for generator in [
+            Generator::new(0).frame_rate(1e-320),
+            Generator::new(0).frame_rate(f64::NAN),
+            Generator::new(0).bus_load(1e-320, Bitrate::new(500_000)),
+        ] {
+            assert_eq!(generator.interval(&frame), Duration::MAX);
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+
+        let mut generator = Generator::new(5).lengths(8..=8);
+        let stats = generator.run(&mut dev, Some(25), None).unwrap();
+        assert_eq!(stats.frames, 25);
+        assert_eq!(stats.bytes, 200);
+        assert_eq!(mock.sent_frames().len(), 25);
+
+        let mut generator = Generator::new(5).frame_rate(1000.0);
+        let stats = generator
+            .run(&mut dev, None, Some(Duration::from_millis(20)))
+            .unwrap();
+        assert_eq!(stats.frames, 20);
+    }
+}
@@ -0,0 +1,327 @@
+//! Heartbeat exchange
+//!
+//! `Heartbeat` sends a heartbeat frame at a fixed interval and at the same
+//! time supervises the heartbeats of peers, reporting every beat a peer
+//! misses and its return, as device bring-up and redundancy tests need.
+//! A counter and checksum in the beat can be kept up to date with
+//! `CyclicFields`.
+//!
+//! A peer's beat counts as missed half a period after it was due, so
+//! ordinary jitter doesn't raise events.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{CanId, GsUsb, GsUsbError, GsUsbFrame, Heartbeat, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let beat = GsUsbFrame::with_data(0x700, &[0x05]);
+//! let mut heartbeat = Heartbeat::new(beat, Duration::from_millis(100));
+//! heartbeat.watch(CanId::Standard(0x701), Duration::from_millis(100));
+//! loop {
+//!     for event in heartbeat.poll(&mut dev)? {
+//!         eprintln!("{event}");
+//!     }
+//!     match dev.read(Duration::from_millis(10)) {
+//!         Ok(frame) => {
+//!             if let Some(event) = heartbeat.observe(&frame) {
+//!                 eprintln!("{event}");
+//!             }
+//!         }
+//!         Err(GsUsbError::ReadTimeout) => {}
+//!         Err(e) => return Err(e),
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::device::GsUsb;
+use crate::e2e::CyclicFields;
+use crate::error::Result;
+use crate::frame::GsUsbFrame;
+use crate::id::CanId;
+
+/// A peer missed beats or came back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatEvent {
+    /// Another beat of the peer is missing
+    Missed {
+        /// Identifier of the peer's heartbeat
+        id: CanId,
+        /// Beats missed in a row so far
+        missed: u32,
+    },
+    /// A beat arrived after beats were missed
+    Resumed {
+        /// Identifier of the peer's heartbeat
+        id: CanId,
+        /// Beats missed in a row before
+        missed: u32,
+    },
+}
+
+impl std::fmt::Display for HeartbeatEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missed { id, missed } => write!(f, "{id} missed {missed} heartbeat(s)"),
+            Self::Resumed { id, missed } => {
+                write!(f, "{id} resumed after {missed} missed heartbeat(s)")
+            }
+        }
+    }
+}
+
+/// Supervision state of one peer
+#[derive(Debug, Clone, Copy)]
+struct Peer {
+    period: Duration,
+    last_seen: Instant,
+    missed: u32,
+}
+
+impl Peer {
+    /// Beats missed at `now`
+    fn missed_at(&self, now: Instant) -> u32 {
+        let silence = now.saturating_duration_since(self.last_seen);
+        let late = silence.saturating_sub(self.period / 2);
+        (late.as_nanos() / self.period.as_nanos().max(1)) as u32
+    }
+}
+
+/// Sends a heartbeat and supervises those of peers, see the module
+/// documentation
+///
+/// Times are read from the clock; frames count by their arrival on the host
+/// when passed to `observe()`.
+#[derive(Debug, Clone)]
+pub struct Heartbeat<C: Clock = SystemClock> {
+    clock: C,
+    frame: GsUsbFrame,
+    interval: Duration,
+    fields: CyclicFields,
+    counter: u8,
+    next_send: Instant,
+    sent: u64,
+    peers: BTreeMap<CanId, Peer>,
+}
+
+impl Heartbeat {
+    /// Send `frame` every `interval`, starting now, using the system clock
+    pub fn new(frame: GsUsbFrame, interval: Duration) -> Self {
+        Self::with_clock(frame, interval, SystemClock)
+    }
+}
+
+impl<C: Clock> Heartbeat<C> {
+    /// Send `frame` every `interval`, starting now, using the given clock
+    pub fn with_clock(frame: GsUsbFrame, interval: Duration, clock: C) -> Self {
+        Self {
+            next_send: clock.now(),
+            clock,
+            frame,
+            interval,
+            fields: CyclicFields::default(),
+            counter: 0,
+            sent: 0,
+            peers: BTreeMap::new(),
+        }
+    }
+
+    /// Update a counter and checksum in every beat sent
+    pub fn with_fields(mut self, fields: CyclicFields) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Supervise the heartbeat of a peer sending `id` every `period`
+    ///
+    /// The first beat is due `period` from now. Watching a peer again
+    /// changes its period and restarts it.
+    pub fn watch(&mut self, id: CanId, period: Duration) {
+        let peer = Peer {
+            period,
+            last_seen: self.clock.now(),
+            missed: 0,
+        };
+        self.peers.insert(id, peer);
+    }
+
+    /// Stop supervising the peer sending `id`
+    pub fn forget(&mut self, id: CanId) {
+        self.peers.remove(&id);
+    }
+
+    /// The next beat to send if it is due, scheduling the one after it
+    ///
+    /// Fails with `InvalidConfig` if the frame is too short for the fields.
+    pub fn next_beat(&mut self) -> Result<Option<GsUsbFrame>> {
+        let now = self.clock.now();
+        if now < self.next_send {
+            return Ok(None);
+        }
+        let len = self.frame.data_length();
+        self.fields
+            .stamp(&mut self.frame.data[..len], self.counter)?;
+        self.counter = self.fields.next_counter(self.counter);
+        self.next_send += self.interval;
+        if self.next_send < now {
+            // Fell behind, don't send a burst to catch up
+            self.next_send = now + self.interval;
+        }
+        self.sent += 1;
+        Ok(Some(self.frame.clone()))
+    }
+
+    /// Send the beat if it is due, and report the peers' missed beats
+    pub fn poll(&mut self, dev: &mut GsUsb) -> Result<Vec<HeartbeatEvent>> {
+        if let Some(frame) = self.next_beat()? {
+            dev.send(&frame)?;
+        }
+        Ok(self.check())
+    }
+
+    /// Take a received frame, reporting a peer's return
+    pub fn observe(&mut self, frame: &GsUsbFrame) -> Option<HeartbeatEvent> {
+        if frame.is_error_frame() || frame.is_echo_frame() {
+            return None;
+        }
+        let id = CanId::from_can_id(frame.can_id);
+        let peer = self.peers.get_mut(&id)?;
+        peer.last_seen = self.clock.now();
+        let missed = std::mem::take(&mut peer.missed);
+        (missed > 0).then_some(HeartbeatEvent::Resumed { id, missed })
+    }
+
+    /// Report the beats peers missed since the last check, in identifier
+    /// order
+    ///
+    /// Each missed beat is reported once, with the number missed in a row.
+    pub fn check(&mut self) -> Vec<HeartbeatEvent> {
+        let now = self.clock.now();
+        self.peers
+            .iter_mut()
+            .filter_map(|(&id, peer)| {
+                let missed = peer.missed_at(now);
+                if missed <= peer.missed {
+                    return None;
+                }
+                peer.missed = missed;
+                Some(HeartbeatEvent::Missed { id, missed })
+            })
+            .collect()
+    }
+
+    /// When the next beat is due to be sent
+    pub fn next_send(&self) -> Instant {
+        self.next_send
+    }
+
+    /// Beats sent
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Beats the peer sending `id` missed in a row, as last checked
+    pub fn missed(&self, id: CanId) -> Option<u32> {
+        self.peers.get(&id).map(|peer| peer.missed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::constants::{GS_CAN_MODE_NORMAL, GS_USB_RX_ECHO_ID};
+    use crate::e2e::CounterField;
+    use crate::mock::MockGsUsb;
+
+    #[test]
+    fn test_send() {
+        let clock = TestClock::new();
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let fields = CyclicFields {
+            counter: Some(CounterField {
+                offset: 8,
+                bits: 8,
+                max: 255,
+            }),
+            checksum: None,
+        };
+        let beat = GsUsbFrame::with_data(0x700, &[0x05, 0]);
+        let mut heartbeat = Heartbeat::with_clock(beat, Duration::from_millis(100), clock.clone())
+            .with_fields(fields);
+
+        heartbeat.poll(&mut dev).unwrap();
+        heartbeat.poll(&mut dev).unwrap();
+        clock.advance(Duration::from_millis(100));
+        heartbeat.poll(&mut dev).unwrap();
+        // Long stalls don't cause bursts
+        clock.advance(Duration::from_secs(1));
+        heartbeat.poll(&mut dev).unwrap();
+        heartbeat.poll(&mut dev).unwrap();
+
+        let sent: Vec<_> = mock.sent_frames().iter().map(|f| f.data()[1]).collect();
+        assert_eq!(sent, [0, 1, 2]);
+        assert_eq!(heartbeat.sent(), 3);
+        assert_eq!(
+            heartbeat.next_send(),
+            clock.now() + Duration::from_millis(100)
+        );
+    }
+
+    #[test]
+    fn test_missed_and_resumed() {
+        let clock = TestClock::new();
+        let beat = GsUsbFrame::with_data(0x700, &[]);
+        let mut heartbeat = Heartbeat::with_clock(beat, Duration::from_millis(100), clock.clone());
+        let peer = CanId::Standard(0x701);
+        heartbeat.watch(peer, Duration::from_millis(100));
+        let mut peer_beat = GsUsbFrame::with_data(0x701, &[]);
+        peer_beat.echo_id = GS_USB_RX_ECHO_ID;
+
+        // Within half a period of jitter
+        clock.advance(Duration::from_millis(140));
+        assert!(heartbeat.check().is_empty());
+        assert_eq!(heartbeat.observe(&peer_beat), None);
+
+        clock.advance(Duration::from_millis(160));
+        assert_eq!(
+            heartbeat.check(),
+            [HeartbeatEvent::Missed {
+                id: peer,
+                missed: 1
+            }]
+        );
+        assert!(heartbeat.check().is_empty());
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(
+            heartbeat.check(),
+            [HeartbeatEvent::Missed {
+                id: peer,
+                missed: 2
+            }]
+        );
+        assert_eq!(heartbeat.missed(peer), Some(2));
+
+        let event = heartbeat.observe(&peer_beat).unwrap();
+        assert_eq!(
+            event,
+            HeartbeatEvent::Resumed {
+                id: peer,
+                missed: 2
+            }
+        );
+        assert_eq!(event.to_string(), "701 resumed after 2 missed heartbeat(s)");
+        assert_eq!(heartbeat.missed(peer), Some(0));
+    }
+}
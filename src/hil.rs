@@ -0,0 +1,386 @@
+//! Hardware-in-the-loop test helpers
+//!
+//! Assertions for writing device and firmware tests as plain `#[test]`s against
+//! a real adapter (or a `VirtualBus` device). They panic with a description of
+//! what was expected and what was received instead, like `assert!`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::hil::{self, FrameMatcher};
+//! use gs_usb::{GsUsbFrame, GS_CAN_MODE_LOOP_BACK};
+//! use std::time::Duration;
+//!
+//! #[test]
+//! #[ignore = "needs hardware"]
+//! fn loopback_500k() {
+//!     let Some(mut dev) = hil::device() else { return };
+//!     dev.set_bitrate(500_000).unwrap();
+//!     dev.start(GS_CAN_MODE_LOOP_BACK).unwrap();
+//!
+//!     hil::loopback_round_trip(&mut dev, &GsUsbFrame::with_data(0x123, &[1, 2]), Duration::from_secs(1));
+//!     hil::expect_silence(&mut dev, Duration::from_millis(100));
+//! }
+//! ```
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::device::GsUsb;
+use crate::error::GsUsbError;
+use crate::frame::GsUsbFrame;
+
+/// Environment variable selecting the adapter used by `device()`, as
+/// `<bus>:<address>` (e.g. `1:7`)
+pub const HIL_DEVICE_ENV: &str = "GS_USB_HIL_DEVICE";
+
+/// Open the adapter to test against
+///
+/// Uses the device named by `GS_USB_HIL_DEVICE` if set, otherwise the first
+/// GS-USB device found. Returns `None` if no device is available, so tests can
+/// skip themselves on machines without hardware.
+///
+/// # Panics
+/// Panics if `GS_USB_HIL_DEVICE` is set but malformed or names no device.
+pub fn device() -> Option<GsUsb> {
+    match std::env::var(HIL_DEVICE_ENV) {
+        Ok(spec) => {
+            let (bus, address) = spec
+                .split_once(':')
+                .and_then(|(bus, address)| Some((bus.parse().ok()?, address.parse().ok()?)))
+                .unwrap_or_else(|| {
+                    panic!("{} must be <bus>:<address>, got {:?}", HIL_DEVICE_ENV, spec)
+                });
+            match GsUsb::find(bus, address) {
+                Ok(Some(dev)) => Some(dev),
+                Ok(None) => panic!("no GS-USB device at bus {} address {}", bus, address),
+                Err(e) => panic!("opening bus {} address {} failed: {}", bus, address, e),
+            }
+        }
+        Err(_) => GsUsb::scan().ok()?.into_iter().next(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Rx,
+    Echo,
+    Error,
+}
+
+/// Predicate on received frames
+///
+/// An empty matcher matches every frame; each builder method adds a condition.
+///
+/// ```
+/// use gs_usb::hil::FrameMatcher;
+/// use gs_usb::GsUsbFrame;
+///
+/// let matcher = FrameMatcher::new().id(0x123).data_prefix(&[0x02]);
+/// assert!(matcher.matches(&GsUsbFrame::with_data(0x123, &[0x02, 0x10])));
+/// assert!(!matcher.clone().rx().matches(&GsUsbFrame::with_data(0x123, &[0x02])));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameMatcher {
+    id: Option<u32>,
+    data: Option<Vec<u8>>,
+    data_prefix: Option<Vec<u8>>,
+    fd: Option<bool>,
+    kind: Option<FrameKind>,
+}
+
+impl FrameMatcher {
+    /// Matcher accepting every frame
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require the given arbitration ID
+    pub fn id(mut self, id: u32) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Require exactly this payload
+    pub fn data(mut self, data: &[u8]) -> Self {
+        self.data = Some(data.to_vec());
+        self
+    }
+
+    /// Require the payload to start with these bytes
+    pub fn data_prefix(mut self, prefix: &[u8]) -> Self {
+        self.data_prefix = Some(prefix.to_vec());
+        self
+    }
+
+    /// Require a CAN FD (`true`) or classic (`false`) frame
+    pub fn fd(mut self, fd: bool) -> Self {
+        self.fd = Some(fd);
+        self
+    }
+
+    /// Require a frame received from the bus
+    pub fn rx(mut self) -> Self {
+        self.kind = Some(FrameKind::Rx);
+        self
+    }
+
+    /// Require a TX echo frame
+    pub fn echo(mut self) -> Self {
+        self.kind = Some(FrameKind::Echo);
+        self
+    }
+
+    /// Require an error frame
+    pub fn error_frame(mut self) -> Self {
+        self.kind = Some(FrameKind::Error);
+        self
+    }
+
+    /// Check a frame against all conditions
+    pub fn matches(&self, frame: &GsUsbFrame) -> bool {
+        let kind = if frame.is_error_frame() {
+            FrameKind::Error
+        } else if frame.is_rx_frame() {
+            FrameKind::Rx
+        } else {
+            FrameKind::Echo
+        };
+
+        self.kind.is_none_or(|k| k == kind)
+            && self.id.is_none_or(|id| id == frame.arbitration_id())
+            && self.fd.is_none_or(|fd| fd == frame.is_fd())
+            && self.data.as_deref().is_none_or(|data| data == frame.data())
+            && self
+                .data_prefix
+                .as_deref()
+                .is_none_or(|prefix| frame.data().starts_with(prefix))
+    }
+}
+
+impl fmt::Display for FrameMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut conditions = Vec::new();
+        if let Some(kind) = self.kind {
+            conditions.push(format!("{:?}", kind).to_lowercase());
+        }
+        if let Some(id) = self.id {
+            conditions.push(format!("id 0x{:X}", id));
+        }
+        if let Some(fd) = self.fd {
+            conditions.push(if fd { "FD" } else { "classic" }.to_string());
+        }
+        if let Some(data) = &self.data {
+            conditions.push(format!("data {:02X?}", data));
+        }
+        if let Some(prefix) = &self.data_prefix {
+            conditions.push(format!("data starting with {:02X?}", prefix));
+        }
+        if conditions.is_empty() {
+            write!(f, "any frame")
+        } else {
+            write!(f, "frame with {}", conditions.join(", "))
+        }
+    }
+}
+
+/// Read until a frame matching `matcher` arrives and return it
+///
+/// Non-matching frames are skipped.
+///
+/// # Panics
+/// Panics if no matching frame arrives within `timeout` (listing the frames
+/// that did arrive) or if reading fails.
+pub fn expect_frame(dev: &mut GsUsb, matcher: &FrameMatcher, timeout: Duration) -> GsUsbFrame {
+    let deadline = Instant::now() + timeout;
+    let mut skipped = Vec::new();
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            panic!(
+                "expected {} within {:?}, received {} other frame(s){}",
+                matcher,
+                timeout,
+                skipped.len(),
+                skipped
+                    .iter()
+                    .map(|frame| format!("\n  {}", frame))
+                    .collect::<String>()
+            );
+        }
+        match dev.read(remaining) {
+            Ok(frame) if matcher.matches(&frame) => return frame,
+            Ok(frame) => skipped.push(frame),
+            Err(GsUsbError::ReadTimeout) => {}
+            Err(e) => panic!("reading while expecting {} failed: {}", matcher, e),
+        }
+    }
+}
+
+/// Assert that no frame (including echoes and error frames) arrives for
+/// `duration`
+///
+/// # Panics
+/// Panics on the first received frame or if reading fails.
+pub fn expect_silence(dev: &mut GsUsb, duration: Duration) {
+    let deadline = Instant::now() + duration;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        match dev.read(remaining) {
+            Ok(frame) => panic!("expected silence for {:?}, received {}", duration, frame),
+            Err(GsUsbError::ReadTimeout) => {}
+            Err(e) => panic!("reading while expecting silence failed: {}", e),
+        }
+    }
+}
+
+/// Frames observed by a loopback round trip
+#[derive(Debug, Clone)]
+pub struct RoundTrip {
+    /// TX echo of the sent frame
+    pub echo: GsUsbFrame,
+    /// Looped-back RX copy of the sent frame
+    pub rx: GsUsbFrame,
+    /// Time from sending until both frames were received
+    pub elapsed: Duration,
+}
+
+fn same_content(frame: &GsUsbFrame) -> FrameMatcher {
+    FrameMatcher::new()
+        .id(frame.arbitration_id())
+        .fd(frame.is_fd())
+        .data(frame.data())
+}
+
+/// Send a frame on a device started in loopback mode and expect both its echo
+/// and the looped-back RX frame with identical content
+///
+/// # Panics
+/// Panics if sending fails or either frame is missing or differs.
+pub fn loopback_round_trip(dev: &mut GsUsb, frame: &GsUsbFrame, timeout: Duration) -> RoundTrip {
+    let start = Instant::now();
+    if let Err(e) = dev.send(frame) {
+        panic!("sending {} failed: {}", frame, e);
+    }
+
+    let expected = same_content(frame);
+    let mut echo = None;
+    let mut rx = None;
+    while echo.is_none() || rx.is_none() {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        let matcher = match (&echo, &rx) {
+            (None, None) => expected.clone(),
+            (None, Some(_)) => expected.clone().echo(),
+            _ => expected.clone().rx(),
+        };
+        let received = expect_frame(dev, &matcher, remaining);
+        if received.is_rx_frame() {
+            rx = Some(received);
+        } else {
+            echo = Some(received);
+        }
+    }
+
+    RoundTrip {
+        echo: echo.unwrap(),
+        rx: rx.unwrap(),
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Send a frame on `tx` and expect it to be received with identical content
+/// on `rx` (e.g. two adapters wired to the same bus)
+///
+/// # Panics
+/// Panics if sending fails or the frame doesn't arrive on `rx`.
+pub fn round_trip(
+    tx: &mut GsUsb,
+    rx: &mut GsUsb,
+    frame: &GsUsbFrame,
+    timeout: Duration,
+) -> GsUsbFrame {
+    if let Err(e) = tx.send(frame) {
+        panic!("sending {} failed: {}", frame, e);
+    }
+    expect_frame(rx, &same_content(frame).rx(), timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{GS_CAN_MODE_LOOP_BACK, GS_CAN_MODE_NORMAL};
+    use crate::virtual_bus::VirtualBus;
+
+    fn started(bus: &VirtualBus, flags: u32) -> GsUsb {
+        let mut dev = bus.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(flags).unwrap();
+        dev
+    }
+
+    #[test]
+    fn test_matcher() {
+        let mut frame = GsUsbFrame::with_data(0x123, &[1, 2, 3]);
+        frame.echo_id = crate::constants::GS_USB_RX_ECHO_ID;
+
+        assert!(FrameMatcher::new().matches(&frame));
+        assert!(FrameMatcher::new()
+            .id(0x123)
+            .rx()
+            .data_prefix(&[1, 2])
+            .matches(&frame));
+        assert!(!FrameMatcher::new().echo().matches(&frame));
+        assert!(!FrameMatcher::new().data(&[1, 2]).matches(&frame));
+        assert!(!FrameMatcher::new().fd(true).matches(&frame));
+        assert_eq!(
+            FrameMatcher::new().rx().id(0x7FF).to_string(),
+            "frame with rx, id 0x7FF"
+        );
+    }
+
+    #[test]
+    fn test_loopback_and_silence() {
+        let bus = VirtualBus::new();
+        let mut dev = started(&bus, GS_CAN_MODE_LOOP_BACK);
+        let trip = loopback_round_trip(
+            &mut dev,
+            &GsUsbFrame::with_data(0x42, &[9, 9]),
+            Duration::from_secs(1),
+        );
+        assert!(trip.echo.is_echo_frame());
+        assert_eq!(trip.rx.data(), &[9, 9]);
+        expect_silence(&mut dev, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_round_trip_between_devices() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_NORMAL);
+        let mut b = started(&bus, GS_CAN_MODE_NORMAL);
+        let frame = round_trip(
+            &mut a,
+            &mut b,
+            &GsUsbFrame::with_data(0x10, &[1]),
+            Duration::from_secs(1),
+        );
+        assert_eq!(frame.arbitration_id(), 0x10);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected frame with id 0x99")]
+    fn test_expect_frame_times_out() {
+        let bus = VirtualBus::new();
+        let mut dev = started(&bus, GS_CAN_MODE_LOOP_BACK);
+        dev.send(&GsUsbFrame::with_data(0x10, &[])).unwrap();
+        expect_frame(
+            &mut dev,
+            &FrameMatcher::new().id(0x99),
+            Duration::from_millis(20),
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Typed CAN identifiers
+//!
+//! `CanId` tells standard from extended identifiers without flag bits, and
+//! reads and writes them in the usual hex notation:
+//!
+//! | Text | Identifier |
+//! |------|------------|
+//! | `123`, `0x123` | standard 0x123 |
+//! | `18FF50E5`, `0x18FF50E5` | extended, above 0x7FF |
+//! | `00000123` | extended, 8 digits as in candump |
+//! | `123x`, `0x123X` | extended, trailing `x` as in Vector ASC logs |
+//!
+//! `Display` writes standard identifiers with 3 hex digits and extended ones
+//! with 8, as candump does, so every identifier parses back to itself.
+//!
+//! ```
+//! use gs_usb::CanId;
+//!
+//! let id: CanId = "18FF50E5".parse()?;
+//! assert_eq!(id, CanId::Extended(0x18FF50E5));
+//! assert_eq!("123x".parse::<CanId>()?.to_string(), "00000123");
+//! assert_eq!(CanId::Standard(0x7DF).to_string(), "7DF");
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::constants::{CAN_EFF_FLAG, CAN_EFF_MASK, CAN_SFF_MASK};
+use crate::error::{GsUsbError, Result};
+
+/// Number of hex digits that make an identifier extended
+const EXTENDED_DIGITS: usize = 8;
+
+/// Standard (11-bit) or extended (29-bit) CAN identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CanId {
+    /// 11-bit identifier
+    Standard(u16),
+    /// 29-bit identifier
+    Extended(u32),
+}
+
+impl CanId {
+    /// A standard identifier, `None` above 0x7FF
+    pub fn standard(raw: u16) -> Option<Self> {
+        (u32::from(raw) <= CAN_SFF_MASK).then_some(Self::Standard(raw))
+    }
+
+    /// An extended identifier, `None` above 0x1FFFFFFF
+    pub fn extended(raw: u32) -> Option<Self> {
+        (raw <= CAN_EFF_MASK).then_some(Self::Extended(raw))
+    }
+
+    /// The identifier of a gs_usb `can_id`, extended if `CAN_EFF_FLAG` is set
+    pub fn from_can_id(can_id: u32) -> Self {
+        if can_id & CAN_EFF_FLAG != 0 {
+            Self::Extended(can_id & CAN_EFF_MASK)
+        } else {
+            Self::Standard((can_id & CAN_SFF_MASK) as u16)
+        }
+    }
+
+    /// Whether the identifier is extended
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Self::Extended(_))
+    }
+
+    /// The identifier without flags
+    pub fn as_raw(&self) -> u32 {
+        match *self {
+            Self::Standard(raw) => u32::from(raw),
+            Self::Extended(raw) => raw,
+        }
+    }
+
+    /// The identifier as a gs_usb `can_id`, with `CAN_EFF_FLAG` if extended
+    pub fn to_can_id(&self) -> u32 {
+        match *self {
+            Self::Standard(raw) => u32::from(raw),
+            Self::Extended(raw) => raw | CAN_EFF_FLAG,
+        }
+    }
+}
+
+impl From<CanId> for u32 {
+    /// The `can_id` for `GsUsbFrame::with_data()` and friends
+    fn from(id: CanId) -> u32 {
+        id.to_can_id()
+    }
+}
+
+impl fmt::Display for CanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Standard(raw) => write!(f, "{raw:03X}"),
+            Self::Extended(raw) => write!(f, "{raw:08X}"),
+        }
+    }
+}
+
+impl FromStr for CanId {
+    type Err = GsUsbError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = |reason: &str| GsUsbError::InvalidCanId(format!("\"{text}\": {reason}"));
+
+        let trimmed = text.trim();
+        let digits = trimmed
+            .strip_prefix("0x")
+            .or_else(|| trimmed.strip_prefix("0X"))
+            .unwrap_or(trimmed);
+        let (digits, marked) = match digits.strip_suffix(['x', 'X']) {
+            Some(digits) => (digits, true),
+            None => (digits, false),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid("expected hex digits"));
+        }
+        let raw = u32::from_str_radix(digits, 16).map_err(|_| invalid("too long"))?;
+        if marked || digits.len() == EXTENDED_DIGITS || raw > CAN_SFF_MASK {
+            Self::extended(raw).ok_or_else(|| invalid("above 1FFFFFFF"))
+        } else {
+            Ok(Self::Standard(raw as u16))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |text: &str| text.parse::<CanId>().unwrap();
+        assert_eq!(parse("123"), CanId::Standard(0x123));
+        assert_eq!(parse("0x7ff"), CanId::Standard(0x7FF));
+        assert_eq!(parse("800"), CanId::Extended(0x800));
+        assert_eq!(parse("18FF50E5"), CanId::Extended(0x18FF50E5));
+        assert_eq!(parse("00000123"), CanId::Extended(0x123));
+        assert_eq!(parse("123x"), CanId::Extended(0x123));
+        assert_eq!(parse("0X18ff50e5X"), CanId::Extended(0x18FF50E5));
+
+        for bad in ["", "0x", "x", "12G", "-1", "20000000", "123456789", "1 2"] {
+            assert!(
+                matches!(bad.parse::<CanId>(), Err(GsUsbError::InvalidCanId(_))),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let ids = [
+            CanId::Standard(0),
+            CanId::Standard(0x7FF),
+            CanId::Extended(0),
+            CanId::Extended(0x7FF),
+            CanId::Extended(0x1FFFFFFF),
+        ];
+        for id in ids {
+            assert_eq!(id.to_string().parse::<CanId>().unwrap(), id);
+            assert_eq!(CanId::from_can_id(id.to_can_id()), id);
+        }
+        assert_eq!(CanId::Standard(0x12).to_string(), "012");
+        assert_eq!(CanId::Extended(0x12).to_string(), "00000012");
+    }
+}
@@ -0,0 +1,191 @@
+//! TX latency measurement
+//!
+//! The device echoes every transmitted frame back with the echo ID it was sent
+//! with, once the frame is on the bus. With `GsUsb::enable_tx_latency()`,
+//! `send()` gives each frame its own echo ID and `read()` matches the echoes,
+//! recording the time from the `send()` call to the transmission.
+//!
+//! The transmission time is the echo's hardware timestamp if the device has
+//! been synchronized with `GsUsb::sync_time()`. Otherwise it is the time the
+//! echo was read from USB, which adds the USB and read loop delay.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::constants::GS_USB_RX_ECHO_ID;
+
+/// Frames awaiting their echo; older ones are assumed lost
+const MAX_IN_FLIGHT: usize = 1024;
+
+/// Rolling statistics of the latest TX latencies
+#[derive(Debug, Clone)]
+pub struct LatencyStats {
+    window: usize,
+    samples: VecDeque<Duration>,
+    count: u64,
+}
+
+impl LatencyStats {
+    /// Keep statistics over the latest `window` samples
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: VecDeque::new(),
+            count: 0,
+        }
+    }
+
+    /// Add a sample
+    pub fn record(&mut self, latency: Duration) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+        self.count += 1;
+    }
+
+    /// Number of samples recorded in total
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Most recent sample
+    pub fn last(&self) -> Option<Duration> {
+        self.samples.back().copied()
+    }
+
+    /// Smallest sample in the window
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().copied()
+    }
+
+    /// Largest sample in the window
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+
+    /// Mean of the samples in the window
+    pub fn mean(&self) -> Option<Duration> {
+        let sum: Duration = self.samples.iter().sum();
+        (!self.samples.is_empty()).then(|| sum / self.samples.len() as u32)
+    }
+
+    /// Sample below which `percent` of the window lies (nearest rank)
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort();
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    /// Forget all samples
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.count = 0;
+    }
+}
+
+/// Echo ID bookkeeping for `GsUsb`
+#[derive(Debug, Clone)]
+pub(crate) struct LatencyTracker {
+    next_echo_id: u32,
+    in_flight: HashMap<u32, Instant>,
+    order: VecDeque<u32>,
+    stats: LatencyStats,
+}
+
+impl LatencyTracker {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            next_echo_id: 0,
+            in_flight: HashMap::new(),
+            order: VecDeque::new(),
+            stats: LatencyStats::new(window),
+        }
+    }
+
+    /// Allocate an echo ID for a frame sent at `sent`
+    pub(crate) fn start(&mut self, sent: Instant) -> u32 {
+        let echo_id = self.next_echo_id;
+        self.next_echo_id = (self.next_echo_id + 1) % GS_USB_RX_ECHO_ID;
+        if self.order.len() == MAX_IN_FLIGHT {
+            if let Some(lost) = self.order.pop_front() {
+                self.in_flight.remove(&lost);
+            }
+        }
+        self.in_flight.insert(echo_id, sent);
+        self.order.push_back(echo_id);
+        echo_id
+    }
+
+    /// Forget an echo ID whose frame could not be sent
+    pub(crate) fn cancel(&mut self, echo_id: u32) {
+        self.in_flight.remove(&echo_id);
+        self.order.retain(|&id| id != echo_id);
+    }
+
+    /// Record the echo of a frame transmitted at `transmitted`
+    pub(crate) fn finish(&mut self, echo_id: u32, transmitted: Instant) {
+        if let Some(sent) = self.in_flight.remove(&echo_id) {
+            self.order.retain(|&id| id != echo_id);
+            self.stats
+                .record(transmitted.saturating_duration_since(sent));
+        }
+    }
+
+    pub(crate) fn stats(&self) -> &LatencyStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_NORMAL};
+    use crate::frame::GsUsbFrame;
+    use crate::virtual_bus::VirtualBus;
+
+    #[test]
+    fn test_stats() {
+        let mut stats = LatencyStats::new(4);
+        assert_eq!(stats.mean(), None);
+        for ms in [9, 1, 2, 3, 4] {
+            stats.record(Duration::from_millis(ms));
+        }
+        assert_eq!(stats.count(), 5);
+        assert_eq!(stats.last(), Some(Duration::from_millis(4)));
+        assert_eq!(stats.min(), Some(Duration::from_millis(1)));
+        assert_eq!(stats.max(), Some(Duration::from_millis(4)));
+        assert_eq!(stats.mean(), Some(Duration::from_micros(2500)));
+        assert_eq!(stats.percentile(50.0), Some(Duration::from_millis(2)));
+        assert_eq!(stats.percentile(100.0), Some(Duration::from_millis(4)));
+    }
+
+    #[test]
+    fn test_echo_latency() {
+        let bus = VirtualBus::new();
+        let mut a = bus.open();
+        let mut b = bus.open();
+        a.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+        b.start(GS_CAN_MODE_NORMAL).unwrap();
+        a.sync_time().unwrap();
+        a.enable_tx_latency(16);
+
+        for i in 0..3u8 {
+            a.send(&GsUsbFrame::with_data(0x100, &[i])).unwrap();
+            let echo = a.read(Duration::from_millis(100)).unwrap();
+            assert!(echo.is_echo_frame());
+            assert_eq!(echo.echo_id, u32::from(i));
+        }
+        let stats = a.tx_latency().unwrap();
+        assert_eq!(stats.count(), 3);
+        assert!(stats.max().unwrap() < Duration::from_millis(5));
+
+        a.disable_tx_latency();
+        assert!(a.tx_latency().is_none());
+    }
+}
@@ -7,9 +7,63 @@
 //!
 //! - Support for classic CAN (up to 1 Mbps)
 //! - Support for CAN FD (up to 10 Mbps data rate)
-//! - Hardware timestamps
-//! - Multiple operating modes (normal, listen-only, loopback, one-shot)
-//! - Device state and error counter monitoring
+//! - Frame types sized for classic CAN or CAN FD, checked when sending
+//! - Bit timing calculation for any device clock and sample point
+//! - Bitrates in human notation (`"500k/2M@80%"`)
+//! - Hardware timestamps, extended to 64 bits across counter wraps and
+//!   converted to host time
+//! - Multiple operating modes (normal, listen-only, loopback, one-shot),
+//!   negotiated from required and preferred flags
+//! - Configuration profiles with acceptance filters, loadable from and
+//!   savable to TOML or JSON (`serde` feature)
+//! - TX latency measurement from echo frames
+//! - Inter-frame gap and per-ID period statistics
+//! - Supervision of periodic messages with lost/recovered events
+//! - Heartbeat transmission with detection of peers' missed heartbeats
+//! - Payload change detection per identifier, with byte and bit masks,
+//!   and changed-byte highlighting
+//! - Per-ID bus statistics with period and length histograms, and new-ID
+//!   discovery after a learning phase
+//! - Bus load history and error counters, exported as JSON or CSV snapshots
+//! - USB transfer statistics: sizes, frames per transfer, short and
+//!   truncated transfers
+//! - Anomaly heuristics: error bursts, babbling nodes, bus-off flapping and
+//!   load spikes
+//! - Device state and error counter monitoring, with optional reporting of
+//!   CAN error frames as errors
+//! - AUTOSAR E2E protection (profiles 1, 2 and 5, or custom CRC and
+//!   counter layouts) for sending and checking frames, and alive counters
+//!   and checksums updated in periodic messages
+//! - Bit-level signal extraction and insertion with Intel or Motorola byte
+//!   order, sign and scaling, without a DBC file, and multiplexed message
+//!   layouts
+//! - Multi-frame reassembly for transport protocols, driven by a
+//!   user-supplied framing description, and a simple segmentation scheme
+//!   for payloads of up to 127 bytes over classic CAN
+//! - SLCAN (LAWICEL) ASCII frame encoding and decoding
+//! - `embedded_can::Frame` and blocking `Can` implementations
+//!   (`embedded-can` feature)
+//! - gRPC remote bus service and client (`grpc` feature)
+//! - In-process virtual bus for development and CI without hardware
+//! - Multi-device aggregation into one merged, labelled frame stream
+//! - Frame forwarding between devices with FD/classic translation policies
+//! - Seeded traffic generation paced to a frame rate or bus load
+//! - Time-slotted transmission within a fixed cycle, with cycle multiplexing
+//! - Passive taps seeing every frame received and sent, alongside the
+//!   application's own reads
+//! - Stage timing of the receive path: transfer wait, parsing and dispatch
+//!   (`instrument` feature)
+//! - Capture on a dedicated thread into a configurable pool of buffers,
+//!   accounting for every lost frame at its position in the stream
+//! - USB traffic recording, with replay through the mock device
+//! - Reading candump, ASC and BLF (`blf` feature) logs and replaying them
+//!   through a device
+//! - Hardware-in-the-loop test assertions
+//! - udev rule generation for non-root access on Linux
+//! - Android USB host support through the file descriptor of a
+//!   `UsbDeviceConnection`
+//! - Scriptable mock device, behavior scenarios, fault injection and a
+//!   manually advanced clock for tests (`test-util` feature)
 //!
 //! # Example
 //!
@@ -56,11 +110,77 @@
 //! - CES CANext FD (VID: 0x1CD2, PID: 0x606F)
 //! - ABE CANdebugger FD (VID: 0x16D0, PID: 0x10B8)
 
+// The generated uniffi scaffolding leaves a blank line after a doc comment
+#![cfg_attr(feature = "uniffi", allow(clippy::empty_line_after_doc_comments))]
+
+pub mod aggregator;
+pub mod anomaly;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod bitrate;
+pub mod builder;
+pub mod bus_state;
+pub mod cancel;
+pub mod capture;
+pub mod changes;
+pub mod clock;
+pub mod codec;
+pub mod config;
 pub mod constants;
 pub mod device;
+pub mod e2e;
+#[cfg(feature = "embedded-can")]
+pub mod embedded;
 pub mod error;
+pub mod error_frame;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fault;
+#[cfg(feature = "uniffi")]
+pub mod ffi;
 pub mod frame;
+pub mod gaps;
+pub mod gateway;
+pub mod generator;
+pub mod heartbeat;
+pub mod hil;
+pub mod id;
+pub mod latency;
+pub mod logfile;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod mode;
+pub mod monitor;
+pub mod passthru;
+pub mod platform;
+pub mod prelude;
+pub mod quirks;
+pub mod reassembly;
+pub mod recording;
+#[cfg(feature = "grpc")]
+pub mod remote;
+pub mod replay;
+pub mod retry;
+mod rng;
+#[cfg(any(test, feature = "test-util"))]
+pub mod scenario;
+pub mod segmentation;
+pub mod shared;
+pub mod signal;
+pub mod slcan;
+pub mod slots;
+pub mod snapshot;
+pub mod stats;
 pub mod structures;
+mod sync;
+pub mod tap;
+pub mod time_sync;
+pub mod timestamp;
+pub mod timing;
+pub mod transport;
+pub mod typed_frame;
+pub mod udev;
+pub mod virtual_bus;
+pub mod watchdog;
 
 // Re-export main types at crate root
 pub use constants::{
@@ -113,7 +233,82 @@ pub use constants::{
     GS_CAN_STATE_STOPPED,
 };
 
-pub use device::GsUsb;
-pub use error::{GsUsbError, Result};
-pub use frame::GsUsbFrame;
+pub use aggregator::{Aggregator, TaggedFrame};
+pub use anomaly::{Anomaly, AnomalyConfig, AnomalyDetector};
+pub use bitrate::{Bitrate, SamplePoint};
+pub use builder::GsUsbBuilder;
+pub use bus_state::{StateEvent, StateWatcher};
+pub use cancel::CancelToken;
+pub use capture::{
+    Backpressure, BufferReuse, Capture, CaptureConfig, CaptureEvent, CaptureRecord, CaptureSummary,
+    Loss, DEFAULT_CAPTURE_BUFFERS, DEFAULT_CAPTURE_BUFFER_RECORDS,
+};
+pub use changes::{ChangeDetector, PayloadChange, PayloadDiff};
+#[cfg(any(test, feature = "test-util"))]
+pub use clock::TestClock;
+pub use clock::{Clock, SystemClock};
+pub use codec::FrameCodec;
+pub use config::{Config, DeviceProfile, IdFilter, ModeConfig};
+pub use device::{GsUsb, ReadBatch, Unmatched};
+pub use e2e::{
+    Checksum, ChecksumField, CounterField, Crc, CyclicFields, DataId, E2eConfig, E2eProtection,
+    E2eSet, E2eStatus,
+};
+pub use error::{ErrorKind, GsUsbError, Result};
+pub use error_frame::{CanErrorFrame, RxOverflow};
+#[cfg(any(test, feature = "test-util"))]
+pub use fault::{FaultConfig, FaultHandle, FaultStats, FaultyTransport};
+pub use frame::{FrameFormat, GsUsbFrame, PackedFrame};
+pub use gaps::{GapStats, GapTracker};
+pub use gateway::{FdToClassic, Gateway, Translation};
+pub use generator::{Generator, PayloadPattern};
+pub use heartbeat::{Heartbeat, HeartbeatEvent};
+pub use id::CanId;
+pub use latency::LatencyStats;
+pub use logfile::{LogFormat, LogRecord};
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::MockGsUsb;
+pub use mode::{ActiveMode, Loopback, ModeRequest};
+pub use monitor::Monitor;
+pub use platform::PlatformIssue;
+pub use quirks::Quirks;
+pub use reassembly::{AbortReason, Framing, Message, Reassembler, Reassembly, Segment};
+pub use recording::{Recording, RecordingTransport};
+pub use replay::Replay;
+pub use retry::RetryPolicy;
+#[cfg(any(test, feature = "test-util"))]
+pub use scenario::{Scenario, ScenarioEvent};
+pub use segmentation::{
+    segment, Desegmenter, Segmented, MAX_SEGMENTED_LEN, SEGMENT_FIRST, SEGMENT_PAYLOAD_LEN,
+};
+pub use shared::SharedGsUsb;
+pub use signal::{ByteOrder, MessageLayout, Signal};
+pub use slcan::SlcanDecoder;
+pub use slots::SlotScheduler;
+pub use snapshot::{ExportFormat, IdRow, StatsExporter, StatsSnapshot};
+pub use stats::{
+    Bucket, BusStats, ErrorCounts, Histogram, IdStats, LoadSample, NewId, TransferStats,
+};
+#[cfg(feature = "instrument")]
+pub use stats::{HotPathStats, StageTime};
 pub use structures::{DeviceBitTiming, DeviceCapability, DeviceInfo, DeviceMode, DeviceState};
+pub use tap::{TapDirection, TapId, TapOptions};
+pub use time_sync::TimeSync;
+pub use timestamp::{TimestampExtender, TimestampSource};
+pub use transport::{GsUsbInterface, Transport, UsbParts, UsbTransport};
+pub use typed_frame::ClassicFrame;
+#[cfg(feature = "fd")]
+pub use typed_frame::FdFrame;
+pub use udev::{UdevDevice, UdevRules, UDEV_RULES_PATH};
+pub use virtual_bus::{VirtualBus, VirtualGsUsb};
+pub use watchdog::{MessageWatchdog, WatchEvent};
+
+// The uniffi scaffolding has to live in the crate root and refers to the
+// exported items by name
+#[cfg(feature = "uniffi")]
+use ffi::{
+    list_adapters, Adapter, AdapterConfig, AdapterError, AdapterInfo, AdapterListener, BusState,
+    Frame,
+};
+#[cfg(feature = "uniffi")]
+uniffi::include_scaffolding!("gs_usb");
@@ -0,0 +1,872 @@
+//! CAN log files
+//!
+//! Reads the log formats common CAN tools write, for replay (see
+//! `crate::replay`) and offline analysis:
+//!
+//! | Format | Written by | Extension |
+//! |--------|------------|-----------|
+//! | `LogFormat::Candump` | `candump -L` of SocketCAN's can-utils | `.log` |
+//! | `LogFormat::Asc` | Vector CANalyzer/CANoe, python-can | `.asc` |
+//! | `LogFormat::Blf` | Vector CANalyzer/CANoe, python-can (`blf` feature) | `.blf` |
+//!
+//! Channels are numbered from 0 in every format: `can1` in a candump log and
+//! channel 2 in an ASC or BLF file both become channel 1.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::logfile;
+//!
+//! for record in logfile::read_log("trace.asc")? {
+//!     println!("{:>12.6} {}", record.timestamp.as_secs_f64(), record.frame);
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::constants::{
+    CAN_EFF_FLAG, CAN_EFF_MASK, CAN_ERR_FLAG, CAN_RTR_FLAG, GS_CAN_FLAG_ESI, GS_USB_FRAME_DATA_LEN,
+};
+use crate::error::{GsUsbError, Result};
+use crate::frame::{dlc_to_len, GsUsbFrame};
+
+/// Format of a CAN log file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `candump -L` log: `(1436509052.249713) can0 123#DEADBEEF`
+    Candump,
+    /// Vector ASCII log
+    Asc,
+    /// Vector binary logging format
+    Blf,
+}
+
+impl LogFormat {
+    /// Format by file extension (`.log`, `.asc`, `.blf`)
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "log" | "candump" => Some(Self::Candump),
+            "asc" => Some(Self::Asc),
+            "blf" => Some(Self::Blf),
+            _ => None,
+        }
+    }
+
+    /// Format by the content of a file
+    pub fn detect(content: &[u8]) -> Option<Self> {
+        if content.starts_with(b"LOGG") {
+            return Some(Self::Blf);
+        }
+        let text = std::str::from_utf8(&content[..content.len().min(4096)]).ok()?;
+        let first = text.lines().map(str::trim).find(|line| !line.is_empty())?;
+        if first.starts_with('(') {
+            Some(Self::Candump)
+        } else if first.starts_with("date") || first.starts_with("base") {
+            Some(Self::Asc)
+        } else {
+            None
+        }
+    }
+
+    /// Parse a log in this format
+    pub fn parse(self, content: &[u8]) -> Result<Vec<LogRecord>> {
+        match self {
+            Self::Candump => parse_candump(text(content)?),
+            Self::Asc => parse_asc(text(content)?),
+            #[cfg(feature = "blf")]
+            Self::Blf => parse_blf(content),
+            #[cfg(not(feature = "blf"))]
+            Self::Blf => Err(GsUsbError::FeatureNotSupported(
+                "BLF logs (build with the `blf` feature)",
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Candump => "candump",
+            Self::Asc => "ASC",
+            Self::Blf => "BLF",
+        })
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = GsUsbError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        match text.to_ascii_lowercase().as_str() {
+            "candump" | "log" => Ok(Self::Candump),
+            "asc" => Ok(Self::Asc),
+            "blf" => Ok(Self::Blf),
+            _ => Err(GsUsbError::InvalidConfig(format!(
+                "unknown log format \"{text}\" (candump, asc or blf)"
+            ))),
+        }
+    }
+}
+
+/// A frame from a log file
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    /// Time of the frame as logged; the origin depends on the file
+    pub timestamp: Duration,
+    /// The frame, with `channel` set to the logged channel
+    pub frame: GsUsbFrame,
+    /// Whether the logging node sent the frame itself
+    pub tx: bool,
+}
+
+/// Read a log file, in the format given by its extension or else its content
+pub fn read_log(path: impl AsRef<Path>) -> Result<Vec<LogRecord>> {
+    let path = path.as_ref();
+    let content = std::fs::read(path)?;
+    let format = LogFormat::from_path(path)
+        .or_else(|| LogFormat::detect(&content))
+        .ok_or_else(|| GsUsbError::InvalidLog {
+            location: path.display().to_string(),
+            reason: "unknown log format",
+        })?;
+    format.parse(&content)
+}
+
+fn text(content: &[u8]) -> Result<&str> {
+    std::str::from_utf8(content).map_err(|e| GsUsbError::InvalidLog {
+        location: format!("byte {}", e.valid_up_to()),
+        reason: "not UTF-8 text",
+    })
+}
+
+fn invalid_line(line: usize, reason: &'static str) -> GsUsbError {
+    GsUsbError::InvalidLog {
+        location: format!("line {line}"),
+        reason,
+    }
+}
+
+/// Parse a `candump -L` log
+///
+/// Lines look like `(1436509052.249713) can0 123#DEADBEEF`, with `#R` for
+/// remote frames, `##<flags>` for CAN FD frames and an optional trailing
+/// `T` or `R` for the direction. The channel is the number the interface
+/// name ends with, or 0.
+pub fn parse_candump(text: &str) -> Result<Vec<LogRecord>> {
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason| invalid_line(i + 1, reason);
+        let mut fields = line.split_whitespace();
+        let (Some(time), Some(interface), Some(frame)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid("expected (timestamp) interface frame"));
+        };
+        let timestamp = time
+            .strip_prefix('(')
+            .and_then(|time| time.strip_suffix(')'))
+            .and_then(parse_seconds)
+            .ok_or_else(|| invalid("invalid timestamp"))?;
+        let mut frame = parse_candump_frame(frame).map_err(invalid)?;
+        frame.channel = trailing_number(interface);
+        records.push(LogRecord {
+            timestamp,
+            frame,
+            tx: fields.next() == Some("T"),
+        });
+    }
+    Ok(records)
+}
+
+/// Parse seconds with up to nanosecond decimals, exactly
+fn parse_seconds(text: &str) -> Option<Duration> {
+    let (secs, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let digits = &fraction[..fraction.len().min(9)];
+    let nanos = if digits.is_empty() {
+        0
+    } else {
+        digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32)
+    };
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+/// Number at the end of an interface name like `can1`, or 0
+fn trailing_number(name: &str) -> u8 {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    name[name.len() - digits..].parse().unwrap_or(0)
+}
+
+/// Parse the `<id>#<data>` part of a candump line
+fn parse_candump_frame(text: &str) -> std::result::Result<GsUsbFrame, &'static str> {
+    let (id, rest) = text.split_once('#').ok_or("missing '#'")?;
+    let raw_id = u32::from_str_radix(id, 16).map_err(|_| "invalid identifier")?;
+    let can_id = match id.len() {
+        3 => raw_id,
+        8 if raw_id & CAN_ERR_FLAG != 0 => raw_id,
+        8 => (raw_id & CAN_EFF_MASK) | CAN_EFF_FLAG,
+        _ => return Err("identifier must have 3 or 8 hex digits"),
+    };
+
+    if let Some(fd) = rest.strip_prefix('#') {
+        let mut chars = fd.chars();
+        let flags = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or("missing CAN FD flags")?;
+        let data = hex_bytes(chars.as_str()).ok_or("invalid data")?;
+        if data.len() > 64 {
+            return Err("more than 64 data bytes");
+        }
+        if data.len() > GS_USB_FRAME_DATA_LEN {
+            return Err("CAN FD data needs the fd feature");
+        }
+        let mut frame = GsUsbFrame::with_fd_data(can_id, &data, flags & 1 != 0);
+        if flags & 2 != 0 {
+            frame.flags |= GS_CAN_FLAG_ESI;
+        }
+        return Ok(frame);
+    }
+    if let Some(dlc) = rest.strip_prefix(['R', 'r']) {
+        let mut frame = GsUsbFrame::new();
+        frame.can_id = can_id | CAN_RTR_FLAG;
+        frame.can_dlc = match dlc {
+            "" => 0,
+            dlc => dlc
+                .parse::<u8>()
+                .map_err(|_| "invalid remote frame DLC")?
+                .min(8),
+        };
+        return Ok(frame);
+    }
+    // Drop a raw DLC suffix (`_D`) of 8 byte frames
+    let data = rest.split_once('_').map_or(rest, |(data, _)| data);
+    let data = hex_bytes(data).ok_or("invalid data")?;
+    if data.len() > 8 {
+        return Err("more than 8 data bytes");
+    }
+    Ok(GsUsbFrame::with_data(can_id, &data))
+}
+
+/// Hex bytes, optionally separated by dots
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text.bytes().filter(|b| *b != b'.').collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Parse a Vector ASC log
+///
+/// Classic and CAN FD frame lines are read; error frames, statistics and
+/// other events are skipped. `base dec` and `timestamps relative` headers
+/// are honored.
+pub fn parse_asc(text: &str) -> Result<Vec<LogRecord>> {
+    let mut radix = 16;
+    let mut relative = false;
+    let mut last = Duration::ZERO;
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["base", base, "timestamps", mode, ..] => {
+                radix = if *base == "dec" { 10 } else { 16 };
+                relative = *mode == "relative";
+                continue;
+            }
+            [time, ..] if time.parse::<f64>().is_ok() => {}
+            _ => continue,
+        }
+        let invalid = |reason| invalid_line(i + 1, reason);
+        let Some((channel, tx, mut frame)) =
+            parse_asc_frame(&fields[1..], radix).map_err(invalid)?
+        else {
+            continue;
+        };
+        let time = parse_seconds(fields[0]).ok_or_else(|| invalid("invalid timestamp"))?;
+        let timestamp = if relative { last + time } else { time };
+        last = timestamp;
+        frame.channel = channel;
+        records.push(LogRecord {
+            timestamp,
+            frame,
+            tx,
+        });
+    }
+    Ok(records)
+}
+
+/// Parse the fields of an ASC event after the timestamp
+///
+/// Returns `None` for events other than frames.
+fn parse_asc_frame(
+    fields: &[&str],
+    radix: u32,
+) -> std::result::Result<Option<(u8, bool, GsUsbFrame)>, &'static str> {
+    let byte = |text: &str| u8::from_str_radix(text, radix).map_err(|_| "invalid data byte");
+    let channel = |text: &str| {
+        text.parse::<u8>()
+            .map(|channel| channel.saturating_sub(1))
+            .map_err(|_| "invalid channel")
+    };
+    let direction = |text: &str| match text {
+        "Rx" => Ok(false),
+        "Tx" => Ok(true),
+        _ => Err("expected Rx or Tx"),
+    };
+    let id = |text: &str| {
+        let (digits, extended) = match text.strip_suffix(['x', 'X']) {
+            Some(digits) => (digits, true),
+            None => (text, false),
+        };
+        let id = u32::from_str_radix(digits, radix).map_err(|_| "invalid identifier")?;
+        Ok::<_, &'static str>(if extended {
+            (id & CAN_EFF_MASK) | CAN_EFF_FLAG
+        } else {
+            id
+        })
+    };
+
+    match fields {
+        ["CANFD", ch, dir, can_id, rest @ ..] => {
+            if can_id.eq_ignore_ascii_case("ErrorFrame") {
+                return Ok(None);
+            }
+            // An optional symbolic message name precedes BRS and ESI
+            let rest = match rest {
+                [name, rest @ ..] if !matches!(*name, "0" | "1") => rest,
+                rest => rest,
+            };
+            let [brs, esi, dlc, length, data @ ..] = rest else {
+                return Err("incomplete CAN FD frame");
+            };
+            let dlc = u8::from_str_radix(dlc, 16).map_err(|_| "invalid DLC")?;
+            let length: usize = length.parse().map_err(|_| "invalid data length")?;
+            if length != dlc_to_len(dlc, true) || data.len() < length {
+                return Err("data length doesn't match the DLC");
+            }
+            let data = data[..length]
+                .iter()
+                .map(|b| byte(b))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            if data.len() > GS_USB_FRAME_DATA_LEN {
+                return Err("CAN FD data needs the fd feature");
+            }
+            let mut frame = GsUsbFrame::with_fd_data(id(can_id)?, &data, *brs == "1");
+            if *esi == "1" {
+                frame.flags |= GS_CAN_FLAG_ESI;
+            }
+            Ok(Some((channel(ch)?, direction(dir)?, frame)))
+        }
+        [ch, can_id, dir, kind, rest @ ..] if ch.parse::<u8>().is_ok() => {
+            if can_id.eq_ignore_ascii_case("ErrorFrame") {
+                return Ok(None);
+            }
+            match (*kind, rest) {
+                ("d", [dlc, data @ ..]) => {
+                    let dlc = u8::from_str_radix(dlc, 16).map_err(|_| "invalid DLC")?;
+                    let length = dlc_to_len(dlc, false);
+                    if data.len() < length {
+                        return Err("fewer data bytes than the DLC");
+                    }
+                    let data = data[..length]
+                        .iter()
+                        .map(|b| byte(b))
+                        .collect::<std::result::Result<Vec<_>, _>>()?;
+                    let frame = GsUsbFrame::with_data(id(can_id)?, &data);
+                    Ok(Some((channel(ch)?, direction(dir)?, frame)))
+                }
+                ("r", rest) => {
+                    let mut frame = GsUsbFrame::new();
+                    frame.can_id = id(can_id)? | CAN_RTR_FLAG;
+                    frame.can_dlc = match rest.first() {
+                        Some(dlc) => u8::from_str_radix(dlc, 16).unwrap_or(0).min(8),
+                        None => 0,
+                    };
+                    Ok(Some((channel(ch)?, direction(dir)?, frame)))
+                }
+                _ => Ok(None),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(feature = "blf")]
+mod blf {
+    //! Vector BLF reader
+    //!
+    //! A BLF file is a header followed by objects, each starting with `LOBJ`.
+    //! Frames are stored in zlib compressed log containers; an object may
+    //! continue from one container into the next.
+
+    use std::io::Read;
+    use std::time::Duration;
+
+    use super::LogRecord;
+    use crate::constants::{
+        CAN_EFF_FLAG, CAN_EFF_MASK, CAN_RTR_FLAG, GS_CAN_FLAG_ESI, GS_USB_FRAME_DATA_LEN,
+    };
+    use crate::error::{GsUsbError, Result};
+    use crate::frame::{dlc_to_len, GsUsbFrame};
+
+    const OBJECT_SIGNATURE: &[u8] = b"LOBJ";
+    const BASE_HEADER_SIZE: usize = 16;
+
+    const CAN_MESSAGE: u32 = 1;
+    const LOG_CONTAINER: u32 = 10;
+    const CAN_MESSAGE2: u32 = 86;
+    const CAN_FD_MESSAGE: u32 = 100;
+    const CAN_FD_MESSAGE_64: u32 = 101;
+
+    const TIME_TEN_MICS: u32 = 1;
+
+    /// Most memory reserved up front for a container's uncompressed data,
+    /// whose size comes from the file (loggers write 128 KiB containers)
+    const MAX_CONTAINER_RESERVE: usize = 1 << 20;
+
+    fn invalid(offset: usize, reason: &'static str) -> GsUsbError {
+        GsUsbError::InvalidLog {
+            location: format!("offset {offset:#x}"),
+            reason,
+        }
+    }
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([data[at], data[at + 1]])
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+    }
+
+    /// Parse a BLF file
+    pub fn parse(content: &[u8]) -> Result<Vec<LogRecord>> {
+        if content.len() < 8 || !content.starts_with(b"LOGG") {
+            return Err(invalid(0, "not a BLF file"));
+        }
+        let header_size = u32_at(content, 4) as usize;
+        let mut records = Vec::new();
+        // Unparsed bytes of an object continued in the next container
+        let mut pending = Vec::new();
+        let mut pos = header_size;
+        while let Some(start) = find_object(content, pos) {
+            if content.len() < start + BASE_HEADER_SIZE {
+                break;
+            }
+            let object_size = u32_at(content, start + 8) as usize;
+            let object_type = u32_at(content, start + 12);
+            let end = start + object_size;
+            if object_size < BASE_HEADER_SIZE || end > content.len() {
+                return Err(invalid(start, "truncated object"));
+            }
+            if object_type == LOG_CONTAINER {
+                pending.extend(container_data(&content[start..end], start)?);
+                let used = parse_objects(&pending, &mut records)?;
+                pending.drain(..used);
+            } else {
+                parse_object(&content[start..end], start, &mut records)?;
+            }
+            pos = end;
+        }
+        Ok(records)
+    }
+
+    /// Start of the next object at or shortly after `pos`, skipping padding
+    fn find_object(data: &[u8], pos: usize) -> Option<usize> {
+        let window = data.get(pos..(pos + 8).min(data.len()))?;
+        window
+            .windows(OBJECT_SIGNATURE.len())
+            .position(|w| w == OBJECT_SIGNATURE)
+            .map(|i| pos + i)
+    }
+
+    /// Uncompressed content of a log container object
+    fn container_data(object: &[u8], offset: usize) -> Result<Vec<u8>> {
+        let header_size = usize::from(u16_at(object, 4));
+        if object.len() < header_size + 16 {
+            return Err(invalid(offset, "truncated log container"));
+        }
+        let method = u16_at(object, header_size);
+        let size = u32_at(object, header_size + 8) as usize;
+        let data = &object[header_size + 16..];
+        match method {
+            0 => Ok(data.to_vec()),
+            2 => {
+                let mut out = Vec::with_capacity(size.min(MAX_CONTAINER_RESERVE));
+                flate2::read::ZlibDecoder::new(data)
+                    .read_to_end(&mut out)
+                    .map_err(|_| invalid(offset, "corrupt compressed data"))?;
+                Ok(out)
+            }
+            _ => Err(invalid(offset, "unknown compression method")),
+        }
+    }
+
+    /// Parse the complete objects in container data, returning the number of
+    /// bytes used
+    fn parse_objects(data: &[u8], records: &mut Vec<LogRecord>) -> Result<usize> {
+        let mut pos = 0;
+        while let Some(start) = find_object(data, pos) {
+            if data.len() < start + BASE_HEADER_SIZE {
+                return Ok(start);
+            }
+            let object_size = u32_at(data, start + 8) as usize;
+            if object_size < BASE_HEADER_SIZE {
+                return Err(invalid(start, "truncated object"));
+            }
+            let end = start + object_size;
+            if end > data.len() {
+                return Ok(start);
+            }
+            parse_object(&data[start..end], start, records)?;
+            pos = end;
+        }
+        Ok(if data.len() - pos < 8 {
+            pos
+        } else {
+            data.len()
+        })
+    }
+
+    /// Parse one object, adding it to `records` if it is a CAN frame
+    fn parse_object(object: &[u8], offset: usize, records: &mut Vec<LogRecord>) -> Result<()> {
+        if object.len() < BASE_HEADER_SIZE {
+            return Err(invalid(offset, "truncated object"));
+        }
+        let header_size = usize::from(u16_at(object, 4));
+        if header_size > object.len() || header_size < BASE_HEADER_SIZE + 16 {
+            return Err(invalid(offset, "invalid object header"));
+        }
+        let header_version = u16_at(object, 6);
+        let object_type = u32_at(object, 12);
+        let flags = u32_at(object, 16);
+        let raw_time = match header_version {
+            1 | 2 => u64_at(object, 24),
+            _ => return Ok(()),
+        };
+        let timestamp = if flags == TIME_TEN_MICS {
+            Duration::from_micros(raw_time.saturating_mul(10))
+        } else {
+            Duration::from_nanos(raw_time)
+        };
+        let body = &object[header_size..];
+        let frame = match object_type {
+            CAN_MESSAGE | CAN_MESSAGE2 if body.len() >= 16 => {
+                let (channel, flags, dlc) = (u16_at(body, 0), body[2], body[3]);
+                let id = can_id(u32_at(body, 4));
+                let mut frame = if flags & 0x80 != 0 {
+                    let mut frame = GsUsbFrame::new();
+                    frame.can_id = id | CAN_RTR_FLAG;
+                    frame.can_dlc = dlc.min(8);
+                    frame
+                } else {
+                    GsUsbFrame::with_data(id, &body[8..8 + dlc_to_len(dlc, false)])
+                };
+                frame.channel = channel.saturating_sub(1) as u8;
+                Some((frame, flags & 1 != 0))
+            }
+            CAN_FD_MESSAGE if body.len() >= 84 => {
+                let (channel, flags, dlc) = (u16_at(body, 0), body[2], body[3]);
+                let id = can_id(u32_at(body, 4));
+                let fd_flags = body[13];
+                let length = usize::from(body[14]).min(64);
+                let mut frame = if fd_flags & 1 != 0 {
+                    fd_frame(
+                        id,
+                        &body[20..20 + length],
+                        fd_flags & 2 != 0,
+                        fd_flags & 4 != 0,
+                    )
+                    .ok_or_else(|| invalid(offset, "CAN FD data needs the fd feature"))?
+                } else {
+                    GsUsbFrame::with_data(id, &body[20..20 + dlc_to_len(dlc, false)])
+                };
+                frame.channel = channel.saturating_sub(1) as u8;
+                Some((frame, flags & 1 != 0))
+            }
+            CAN_FD_MESSAGE_64 if body.len() >= 40 => {
+                let (channel, dlc, length) = (body[0], body[1], usize::from(body[2]));
+                let id = can_id(u32_at(body, 4));
+                let fd_flags = u32_at(body, 12);
+                let direction = body[34];
+                let data = body.get(40..40 + length.min(64)).unwrap_or_default();
+                let mut frame = if fd_flags & 0x1000 != 0 {
+                    fd_frame(id, data, fd_flags & 0x2000 != 0, fd_flags & 0x4000 != 0)
+                        .ok_or_else(|| invalid(offset, "CAN FD data needs the fd feature"))?
+                } else if fd_flags & 0x0010 != 0 {
+                    let mut frame = GsUsbFrame::new();
+                    frame.can_id = id | CAN_RTR_FLAG;
+                    frame.can_dlc = dlc.min(8);
+                    frame
+                } else {
+                    GsUsbFrame::with_data(id, &data[..data.len().min(dlc_to_len(dlc, false))])
+                };
+                frame.channel = channel.saturating_sub(1);
+                Some((frame, direction == 1))
+            }
+            _ => None,
+        };
+        if let Some((frame, tx)) = frame {
+            records.push(LogRecord {
+                timestamp,
+                frame,
+                tx,
+            });
+        }
+        Ok(())
+    }
+
+    /// Identifier with the extended bit of BLF moved to `CAN_EFF_FLAG`
+    fn can_id(raw: u32) -> u32 {
+        if raw & 0x8000_0000 != 0 {
+            (raw & CAN_EFF_MASK) | CAN_EFF_FLAG
+        } else {
+            raw
+        }
+    }
+
+    fn fd_frame(can_id: u32, data: &[u8], brs: bool, esi: bool) -> Option<GsUsbFrame> {
+        if data.len() > GS_USB_FRAME_DATA_LEN {
+            return None;
+        }
+        let mut frame = GsUsbFrame::with_fd_data(can_id, data, brs);
+        if esi {
+            frame.flags |= GS_CAN_FLAG_ESI;
+        }
+        Some(frame)
+    }
+
+    #[cfg(test)]
+    pub(super) mod tests {
+        use std::io::Write;
+
+        /// BLF file with one zlib container holding `objects`
+        pub fn file(objects: &[Vec<u8>]) -> Vec<u8> {
+            let mut data = Vec::new();
+            for object in objects {
+                data.extend(object);
+                data.resize(data.len().next_multiple_of(4), 0);
+            }
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+
+            let mut file = b"LOGG".to_vec();
+            file.extend(144u32.to_le_bytes());
+            file.resize(144, 0);
+            file.extend(b"LOBJ");
+            file.extend(16u16.to_le_bytes());
+            file.extend(1u16.to_le_bytes());
+            file.extend(((32 + compressed.len()) as u32).to_le_bytes());
+            file.extend(super::LOG_CONTAINER.to_le_bytes());
+            file.extend(2u16.to_le_bytes());
+            file.extend([0; 6]);
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend([0; 4]);
+            file.extend(compressed);
+            file
+        }
+
+        /// CAN_MESSAGE object with a timestamp in nanoseconds
+        pub fn can_message(time_ns: u64, channel: u16, id: u32, data: &[u8], tx: bool) -> Vec<u8> {
+            let mut object = b"LOBJ".to_vec();
+            object.extend(32u16.to_le_bytes());
+            object.extend(1u16.to_le_bytes());
+            object.extend(48u32.to_le_bytes());
+            object.extend(super::CAN_MESSAGE.to_le_bytes());
+            object.extend(2u32.to_le_bytes());
+            object.extend([0; 4]);
+            object.extend(time_ns.to_le_bytes());
+            object.extend(channel.to_le_bytes());
+            object.push(u8::from(tx));
+            object.push(data.len() as u8);
+            object.extend(id.to_le_bytes());
+            let mut payload = [0; 8];
+            payload[..data.len()].copy_from_slice(data);
+            object.extend(payload);
+            object
+        }
+
+        /// CAN_FD_MESSAGE_64 object of a CAN FD frame, received on channel 1
+        pub fn can_fd_message_64(time_ns: u64, id: u32, data: &[u8]) -> Vec<u8> {
+            let mut object = b"LOBJ".to_vec();
+            object.extend(32u16.to_le_bytes());
+            object.extend(1u16.to_le_bytes());
+            object.extend(((72 + data.len()) as u32).to_le_bytes());
+            object.extend(super::CAN_FD_MESSAGE_64.to_le_bytes());
+            object.extend(2u32.to_le_bytes());
+            object.extend([0; 4]);
+            object.extend(time_ns.to_le_bytes());
+            object.extend([
+                1,
+                crate::frame::len_to_dlc(data.len(), true),
+                data.len() as u8,
+                0,
+            ]);
+            object.extend(id.to_le_bytes());
+            object.extend([0; 4]);
+            object.extend(0x1000u32.to_le_bytes());
+            object.resize(32 + 40, 0);
+            object.extend(data);
+            object
+        }
+    }
+}
+
+/// Parse a Vector BLF log (`blf` feature)
+///
+/// Classic and CAN FD frames are read, other objects skipped.
+#[cfg(feature = "blf")]
+pub fn parse_blf(content: &[u8]) -> Result<Vec<LogRecord>> {
+    blf::parse(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candump() {
+        let records = parse_candump(
+            "(1436509052.249713) can0 123#DEADBEEF\n\
+             (1436509052.250000) vcan1 12345678#R2 T\n\
+             (1436509052.260000) can0 7FF##3AABB\n\
+             (1436509052.270000) can0 20000080#0000000000000000\n",
+        )
+        .unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].frame.data(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(
+            records[0].timestamp,
+            Duration::from_micros(1_436_509_052_249_713)
+        );
+        assert!(!records[0].tx);
+
+        let remote = &records[1].frame;
+        assert_eq!(remote.channel, 1);
+        assert!(remote.is_extended_id() && remote.is_remote_frame());
+        assert_eq!(remote.arbitration_id(), 0x12345678);
+        assert!(records[1].tx);
+
+        let fd = &records[2].frame;
+        assert!(fd.is_fd() && fd.is_brs());
+        assert_eq!(fd.flags & GS_CAN_FLAG_ESI, GS_CAN_FLAG_ESI);
+        assert!(records[3].frame.is_error_frame());
+
+        assert!(matches!(
+            parse_candump("(1.0) can0 123#DEADBEEF\n(2.0) can0 1234#00"),
+            Err(GsUsbError::InvalidLog { ref location, .. }) if location == "line 2"
+        ));
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_asc() {
+        let records = parse_asc(
+            "date Wed Jan 1 12:00:00.000 am 2020\n\
+             base hex  timestamps absolute\n\
+             internal events logged\n\
+             Begin Triggerblock Wed Jan 1 12:00:00.000 am 2020\n\
+             \x20  0.000000 Start of measurement\n\
+             \x20  0.010000 1  123             Rx   d 3 01 02 03  Length = 0 BitCount = 0\n\
+             \x20  0.020000 2  1ABCDEFx        Tx   d 1 AA\n\
+             \x20  0.025000 1  ErrorFrame\n\
+             \x20  0.030000 1  200             Rx   r\n\
+             \x20  0.040000 CANFD   1 Rx        7ff  Msg1  1 0 9 12 00 01 02 03 04 05 06 07 08 09 0a 0b 0 0\n\
+             End TriggerBlock\n",
+        )
+        .unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].frame.data(), &[1, 2, 3]);
+        assert_eq!(records[0].timestamp, Duration::from_millis(10));
+        assert_eq!(records[1].frame.channel, 1);
+        assert_eq!(records[1].frame.arbitration_id(), 0x1ABCDEF);
+        assert!(records[1].tx);
+        assert!(records[2].frame.is_remote_frame());
+        let fd = &records[3].frame;
+        assert!(fd.is_fd() && fd.is_brs());
+        assert_eq!(fd.data_length(), 12);
+
+        let relative = parse_asc(
+            "base dec  timestamps relative\n\
+             \x20  0.5 1  256 Rx d 1 255\n\
+             \x20  0.5 1  256 Rx d 1 16\n",
+        )
+        .unwrap();
+        assert_eq!(relative[1].timestamp, Duration::from_secs(1));
+        assert_eq!(relative[0].frame.arbitration_id(), 0x100);
+        assert_eq!(relative[1].frame.data(), &[0x10]);
+    }
+
+    #[cfg(feature = "blf")]
+    #[test]
+    fn test_blf() {
+        let content = blf::tests::file(&[
+            blf::tests::can_message(1_000_000, 1, 0x123, &[1, 2, 3], false),
+            blf::tests::can_message(2_000_000, 2, 0x8000_0456, &[], true),
+        ]);
+        assert_eq!(LogFormat::detect(&content), Some(LogFormat::Blf));
+        let records = parse_blf(&content).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, Duration::from_millis(1));
+        assert_eq!(records[0].frame.data(), &[1, 2, 3]);
+        assert_eq!(records[1].frame.channel, 1);
+        assert_eq!(records[1].frame.arbitration_id(), 0x456);
+        assert!(records[1].frame.is_extended_id() && records[1].tx);
+
+        // Objects in a container with a size too small for their header
+        let mut empty = b"LOBJ".to_vec();
+        empty.resize(16, 0);
+        assert!(parse_blf(&blf::tests::file(&[empty])).is_err());
+        let mut short_header = blf::tests::can_message(0, 1, 0x1, &[], false);
+        short_header[8..12].copy_from_slice(&20u32.to_le_bytes());
+        assert!(parse_blf(&blf::tests::file(&[short_header])).is_err());
+    }
+
+    #[cfg(feature = "blf")]
+    #[test]
+    fn test_blf_fd() {
+        let content = blf::tests::file(&[blf::tests::can_fd_message_64(0, 0x123, &[0xAA; 12])]);
+        let result = parse_blf(&content);
+        #[cfg(feature = "fd")]
+        assert_eq!(result.unwrap()[0].frame.data(), &[0xAA; 12]);
+        #[cfg(not(feature = "fd"))]
+        assert!(matches!(
+            result,
+            Err(GsUsbError::InvalidLog {
+                reason: "CAN FD data needs the fd feature",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(LogFormat::from_path("a/b.ASC"), Some(LogFormat::Asc));
+        assert_eq!(
+            LogFormat::detect(b"\n(1.0) can0 123#"),
+            Some(LogFormat::Candump)
+        );
+        assert_eq!(LogFormat::detect(b"date Wed"), Some(LogFormat::Asc));
+        assert_eq!(LogFormat::detect(b"hello"), None);
+    }
+}
@@ -0,0 +1,1468 @@
+//! Mock device for hardware-free tests
+//!
+//! This module provides `MockGsUsb`, a scriptable transport for unit testing code
+//! built on `GsUsb`. Unlike the virtual bus, it doesn't simulate a CAN bus: the
+//! test decides what every control request returns, which frames the device
+//! receives, and inspects what the code under test sent. Timed sequences of
+//! events can be scripted with a `Scenario` (see the `scenario` module).
+//!
+//! Available with the `test-util` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use gs_usb::{GsUsbFrame, MockGsUsb, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mock = MockGsUsb::new();
+//! let mut dev = mock.open();
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! mock.push_rx(&GsUsbFrame::with_data(0x100, &[1, 2]));
+//! assert_eq!(dev.read(Duration::from_millis(10))?.arbitration_id(), 0x100);
+//!
+//! dev.send(&GsUsbFrame::with_data(0x200, &[3]))?;
+//! assert_eq!(mock.sent_frames()[0].arbitration_id(), 0x200);
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::constants::*;
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::recording::{Recording, TransferKind};
+use crate::scenario::{Scenario, ScenarioEvent};
+use crate::structures::{DeviceCapability, DeviceInfo, DeviceMode, DeviceState};
+use crate::transport::{GsUsbInterface, Transport};
+use crate::virtual_bus::VirtualBus;
+
+/// A control OUT transfer recorded by `MockGsUsb`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlWrite {
+    /// bRequest
+    pub request: u8,
+    /// wValue
+    pub value: u16,
+    /// Transferred data
+    pub data: Vec<u8>,
+}
+
+/// Queued bulk IN result
+enum RxItem {
+    Frame(GsUsbFrame),
+    Raw(Vec<u8>),
+    Error(rusb::Error),
+}
+
+/// Scenario being played back
+struct Playback {
+    steps: VecDeque<(Duration, ScenarioEvent)>,
+    realtime: bool,
+    /// Wall clock time of the first start
+    started_at: Option<Instant>,
+    /// Scenario clock (fast-forward mode)
+    now: Duration,
+}
+
+struct MockState {
+    responses: HashMap<u8, rusb::Result<Vec<u8>>>,
+    queued: HashMap<u8, VecDeque<rusb::Result<Vec<u8>>>>,
+    control_writes: Vec<ControlWrite>,
+    rx: VecDeque<RxItem>,
+    sent: Vec<GsUsbFrame>,
+    bulk_write_sizes: Vec<usize>,
+    bulk_read_timeouts: Vec<Duration>,
+    echo: bool,
+    started: bool,
+    flags: u32,
+    claimed: bool,
+    /// Claims still to fail as held elsewhere, see `hold_elsewhere()`
+    held_claims: u32,
+    connected: bool,
+    disconnect_reported: bool,
+    playback: Option<Playback>,
+    /// USB vendor and product ID and product string, see `set_usb_product()`
+    usb_product: Option<((u16, u16), String)>,
+    /// See `set_tx_max_packet_size()`
+    tx_max_packet_size: Option<usize>,
+    /// See `set_interface()`
+    interface: GsUsbInterface,
+}
+
+impl MockState {
+    /// Apply all scenario events that are due
+    fn run_scenario(&mut self) {
+        let Some(playback) = self.playback.as_mut() else {
+            return;
+        };
+        let Some(started_at) = playback.started_at else {
+            return;
+        };
+        let now = if playback.realtime {
+            started_at.elapsed()
+        } else {
+            playback.now
+        };
+
+        let mut due = Vec::new();
+        while playback.steps.front().is_some_and(|(at, _)| *at <= now) {
+            due.push(playback.steps.pop_front().unwrap());
+        }
+        for (at, event) in due {
+            self.apply(at, event);
+        }
+    }
+
+    /// In fast-forward mode, jump the scenario clock to the next event
+    ///
+    /// Returns true if the clock moved.
+    fn skip_to_next_event(&mut self) -> bool {
+        let Some(playback) = self.playback.as_mut() else {
+            return false;
+        };
+        if playback.realtime || playback.started_at.is_none() {
+            return false;
+        }
+        match playback.steps.front() {
+            Some((at, _)) => {
+                playback.now = playback.now.max(*at);
+                self.run_scenario();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wall clock time at which the next realtime event is due
+    fn next_event_due(&self) -> Option<Instant> {
+        let playback = self.playback.as_ref().filter(|p| p.realtime)?;
+        let started_at = playback.started_at?;
+        playback.steps.front().map(|(at, _)| started_at + *at)
+    }
+
+    fn apply(&mut self, at: Duration, event: ScenarioEvent) {
+        let timestamp_us = at.as_micros() as u32;
+        match event {
+            ScenarioEvent::Frame(mut frame) => {
+                if self.started {
+                    if frame.echo_id == GS_USB_ECHO_ID {
+                        frame.echo_id = GS_USB_RX_ECHO_ID;
+                    }
+                    frame.timestamp_us = timestamp_us;
+                    self.rx.push_back(RxItem::Frame(frame));
+                }
+            }
+            ScenarioEvent::RawRx(data) => {
+                if self.started {
+                    self.rx.push_back(RxItem::Raw(data));
+                }
+            }
+            ScenarioEvent::RxError(e) => {
+                self.rx.push_back(RxItem::Error(e));
+            }
+            ScenarioEvent::State(state) => {
+                self.responses
+                    .insert(GS_USB_BREQ_GET_STATE, Ok(state.pack().to_vec()));
+            }
+            ScenarioEvent::BusOff => {
+                self.set_bus_state(GS_CAN_STATE_BUS_OFF, 256, CAN_ERR_BUSOFF, timestamp_us);
+            }
+            ScenarioEvent::Recover => {
+                self.set_bus_state(
+                    GS_CAN_STATE_ERROR_ACTIVE,
+                    0,
+                    CAN_ERR_RESTARTED,
+                    timestamp_us,
+                );
+            }
+            ScenarioEvent::Disconnect => {
+                self.connected = false;
+                self.disconnect_reported = false;
+                self.started = false;
+                self.flags = 0;
+                self.rx.clear();
+            }
+            ScenarioEvent::Reconnect => {
+                self.connected = true;
+            }
+        }
+    }
+
+    fn set_bus_state(&mut self, bus_state: u32, txerr: u32, class: u32, timestamp_us: u32) {
+        let state = DeviceState {
+            state: bus_state,
+            rxerr: 0,
+            txerr,
+        };
+        self.responses
+            .insert(GS_USB_BREQ_GET_STATE, Ok(state.pack().to_vec()));
+
+        if self.started {
+            let mut frame = GsUsbFrame::new();
+            frame.echo_id = GS_USB_RX_ECHO_ID;
+            frame.can_id = CAN_ERR_FLAG | CAN_ERR_CNT | class;
+            frame.can_dlc = CAN_MAX_DLC;
+            frame.data[6] = txerr.min(255) as u8;
+            frame.timestamp_us = timestamp_us;
+            self.rx.push_back(RxItem::Frame(frame));
+        }
+    }
+
+    /// Run the scenario and fail if the device is unplugged
+    ///
+    /// Once the disconnect has been reported, further transfers fast-forward
+    /// to the next event so that retry loops eventually see the device come
+    /// back.
+    fn check_connected(&mut self) -> rusb::Result<()> {
+        self.run_scenario();
+        if !self.connected && self.disconnect_reported {
+            self.skip_to_next_event();
+        }
+        if self.connected {
+            return Ok(());
+        }
+        self.disconnect_reported = true;
+        Err(rusb::Error::NoDevice)
+    }
+}
+
+struct Shared {
+    state: Mutex<MockState>,
+    rx_ready: Condvar,
+}
+
+/// Scriptable mock transport
+///
+/// Cloning a `MockGsUsb` yields another handle to the same mock, so a test can
+/// keep one handle while the `GsUsb` created by `open()` owns another.
+///
+/// By default the mock answers the standard read requests (`BT_CONST`,
+/// `BT_CONST_EXT`, `DEVICE_CONFIG`, `GET_STATE`, `TIMESTAMP`) like an error-free
+/// 40 MHz CAN FD adapter, accepts every control write, and echoes every sent
+/// frame back on the RX endpoint.
+#[derive(Clone)]
+pub struct MockGsUsb {
+    shared: Arc<Shared>,
+}
+
+impl Default for MockGsUsb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockGsUsb {
+    /// Create a mock reporting the virtual bus default capability
+    pub fn new() -> Self {
+        Self::with_capability(VirtualBus::default_capability())
+    }
+
+    /// Create a mock reporting the given capability
+    pub fn with_capability(capability: DeviceCapability) -> Self {
+        let mut responses = HashMap::new();
+        responses.insert(GS_USB_BREQ_BT_CONST, Ok(capability.pack().to_vec()));
+        if (capability.feature & GS_CAN_FEATURE_BT_CONST_EXT) != 0 {
+            responses.insert(
+                GS_USB_BREQ_BT_CONST_EXT,
+                Ok(capability.pack_extended().to_vec()),
+            );
+        }
+        if (capability.feature & GS_CAN_FEATURE_GET_STATE) != 0 {
+            let state = DeviceState {
+                state: GS_CAN_STATE_ERROR_ACTIVE,
+                rxerr: 0,
+                txerr: 0,
+            };
+            responses.insert(GS_USB_BREQ_GET_STATE, Ok(state.pack().to_vec()));
+        }
+        let info = DeviceInfo {
+            reserved1: 0,
+            reserved2: 0,
+            reserved3: 0,
+            icount: 0,
+            fw_version: 20,
+            hw_version: 10,
+        };
+        responses.insert(GS_USB_BREQ_DEVICE_CONFIG, Ok(info.pack().to_vec()));
+        responses.insert(GS_USB_BREQ_TIMESTAMP, Ok(0u32.to_le_bytes().to_vec()));
+
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(MockState {
+                    responses,
+                    queued: HashMap::new(),
+                    control_writes: Vec::new(),
+                    rx: VecDeque::new(),
+                    sent: Vec::new(),
+                    bulk_write_sizes: Vec::new(),
+                    bulk_read_timeouts: Vec::new(),
+                    echo: true,
+                    started: false,
+                    flags: 0,
+                    claimed: false,
+                    held_claims: 0,
+                    connected: true,
+                    disconnect_reported: false,
+                    playback: None,
+                    usb_product: None,
+                    tx_max_packet_size: None,
+                    interface: GsUsbInterface::default(),
+                }),
+                rx_ready: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Wrap a handle to this mock in a `GsUsb` (bus 0, address 1)
+    pub fn open(&self) -> GsUsb {
+        GsUsb::from_transport(self.clone(), 0, 1)
+    }
+
+    /// Set the data returned by a control IN request
+    ///
+    /// Control OUT transfers with this request still succeed.
+    pub fn set_response(&self, request: u8, data: impl Into<Vec<u8>>) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.responses.insert(request, Ok(data.into()));
+    }
+
+    /// Make every control transfer (IN or OUT) with this request fail
+    pub fn set_error(&self, request: u8, error: rusb::Error) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.responses.insert(request, Err(error));
+    }
+
+    /// Queue a one-off result for the next control transfer with this request
+    ///
+    /// Queued results are consumed in order before falling back to the
+    /// response set with `set_response()`. For control OUT transfers only
+    /// success or failure matters.
+    pub fn queue_response(&self, request: u8, response: rusb::Result<Vec<u8>>) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queued.entry(request).or_default().push_back(response);
+    }
+
+    /// Replay a recorded session
+    ///
+    /// Recorded control transfer results are queued per request (see
+    /// `queue_response()`) and the recorded bulk IN transfers are played as a
+    /// fast-forward scenario starting with the next device start.
+    pub fn replay(&self, recording: &Recording) {
+        for transfer in &recording.transfers {
+            if matches!(
+                transfer.kind,
+                TransferKind::ControlIn | TransferKind::ControlOut
+            ) {
+                self.queue_response(transfer.request, transfer.result.clone());
+            }
+        }
+        self.play(recording.to_scenario());
+    }
+
+    /// Remove the response for a request
+    ///
+    /// Control IN transfers with this request then stall like unsupported
+    /// requests on real firmware.
+    pub fn clear_response(&self, request: u8) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.responses.remove(&request);
+    }
+
+    /// Set the state returned by `GET_STATE`
+    pub fn set_device_state(&self, device_state: DeviceState) {
+        self.set_response(GS_USB_BREQ_GET_STATE, device_state.pack());
+    }
+
+    /// Enable or disable echoing of sent frames (enabled by default)
+    pub fn set_echo(&self, echo: bool) {
+        self.shared.state.lock().unwrap().echo = echo;
+    }
+
+    /// Queue a frame to be received by the device
+    ///
+    /// Frames carrying the TX echo ID (the default of the `GsUsbFrame`
+    /// constructors) are delivered as regular RX frames. Echo frames for sent
+    /// frames are generated automatically, see `set_echo()`.
+    pub fn push_rx(&self, frame: &GsUsbFrame) {
+        let mut frame = frame.clone();
+        if frame.echo_id == GS_USB_ECHO_ID {
+            frame.echo_id = GS_USB_RX_ECHO_ID;
+        }
+        self.push_item(RxItem::Frame(frame));
+    }
+
+    /// Queue a bulk IN failure (e.g. `rusb::Error::NoDevice` to emulate unplugging)
+    pub fn push_rx_error(&self, error: rusb::Error) {
+        self.push_item(RxItem::Error(error));
+    }
+
+    fn push_item(&self, item: RxItem) {
+        self.shared.state.lock().unwrap().rx.push_back(item);
+        self.shared.rx_ready.notify_all();
+    }
+
+    /// Frames sent by the device, oldest first
+    pub fn sent_frames(&self) -> Vec<GsUsbFrame> {
+        self.shared.state.lock().unwrap().sent.clone()
+    }
+
+    /// Return and forget the frames sent so far
+    pub fn take_sent_frames(&self) -> Vec<GsUsbFrame> {
+        std::mem::take(&mut self.shared.state.lock().unwrap().sent)
+    }
+
+    /// Lengths of the bulk OUT transfers so far, oldest first
+    ///
+    /// Zero-length packets show up as 0.
+    pub fn bulk_write_sizes(&self) -> Vec<usize> {
+        self.shared.state.lock().unwrap().bulk_write_sizes.clone()
+    }
+
+    /// Timeouts of the bulk IN transfers so far, oldest first
+    pub fn bulk_read_timeouts(&self) -> Vec<Duration> {
+        self.shared.state.lock().unwrap().bulk_read_timeouts.clone()
+    }
+
+    /// Control OUT transfers performed so far, oldest first
+    pub fn control_writes(&self) -> Vec<ControlWrite> {
+        self.shared.state.lock().unwrap().control_writes.clone()
+    }
+
+    /// Play a scenario, replacing any scenario already playing
+    ///
+    /// The scenario clock starts with the next `MODE` start request, or
+    /// immediately if the device is already started.
+    pub fn play(&self, scenario: Scenario) {
+        let mut state = self.shared.state.lock().unwrap();
+        let started_at = state.started.then(Instant::now);
+        state.playback = Some(Playback {
+            steps: scenario.steps.into(),
+            realtime: scenario.realtime,
+            started_at,
+            now: Duration::ZERO,
+        });
+        self.shared.rx_ready.notify_all();
+    }
+
+    /// Whether all events of the playing scenario have been applied
+    pub fn scenario_finished(&self) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        state.run_scenario();
+        state
+            .playback
+            .as_ref()
+            .is_none_or(|playback| playback.steps.is_empty())
+    }
+
+    /// Whether the device is plugged in (see `ScenarioEvent::Disconnect`)
+    pub fn is_connected(&self) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        state.run_scenario();
+        state.connected
+    }
+
+    /// Whether the device was started with a `MODE` request
+    pub fn is_started(&self) -> bool {
+        self.shared.state.lock().unwrap().started
+    }
+
+    /// Whether the interface is claimed (between `start()` and `stop()`)
+    pub fn is_claimed(&self) -> bool {
+        self.shared.state.lock().unwrap().claimed
+    }
+
+    /// Fail the next `claims` claims as if another process held the device
+    pub fn hold_elsewhere(&self, claims: u32) {
+        self.shared.state.lock().unwrap().held_claims = claims;
+    }
+
+    /// Mode flags of the last `MODE` start request
+    pub fn mode_flags(&self) -> u32 {
+        self.shared.state.lock().unwrap().flags
+    }
+
+    /// Report a USB vendor and product ID and product string, as a real
+    /// adapter would (none by default)
+    pub fn set_usb_product(&self, vendor_id: u16, product_id: u16, product: &str) {
+        self.shared.state.lock().unwrap().usb_product =
+            Some(((vendor_id, product_id), product.to_string()));
+    }
+
+    /// Report a maximum packet size of the TX endpoint (none by default)
+    pub fn set_tx_max_packet_size(&self, size: usize) {
+        self.shared.state.lock().unwrap().tx_max_packet_size = Some(size);
+    }
+
+    /// Report a gs_usb interface other than the standard one, as a
+    /// composite device would
+    pub fn set_interface(&self, interface: GsUsbInterface) {
+        self.shared.state.lock().unwrap().interface = interface;
+    }
+}
+
+impl Transport for MockGsUsb {
+    fn reset(&mut self) -> rusb::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.check_connected()?;
+        state.started = false;
+        state.flags = 0;
+        Ok(())
+    }
+
+    fn claim_interface(&mut self) -> Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.held_claims > 0 {
+            state.held_claims -= 1;
+            return Err(GsUsbError::ClaimInterface(rusb::Error::Busy));
+        }
+        state.claimed = true;
+        Ok(())
+    }
+
+    fn release_interface(&mut self, _reattach_kernel_driver: bool) -> Result<()> {
+        self.shared.state.lock().unwrap().claimed = false;
+        Ok(())
+    }
+
+    fn write_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.check_connected()?;
+        match state.queued.get_mut(&request).and_then(VecDeque::pop_front) {
+            Some(Err(e)) => return Err(e),
+            Some(Ok(_)) => {}
+            None => {
+                if let Some(Err(e)) = state.responses.get(&request) {
+                    return Err(*e);
+                }
+            }
+        }
+
+        state.control_writes.push(ControlWrite {
+            request,
+            value,
+            data: data.to_vec(),
+        });
+        if request == GS_USB_BREQ_MODE && data.len() >= 8 {
+            let mode = DeviceMode::unpack(data);
+            state.started = mode.mode == GS_CAN_MODE_START;
+            state.flags = if state.started { mode.flags } else { 0 };
+            if state.started {
+                if let Some(playback) = state.playback.as_mut() {
+                    playback.started_at.get_or_insert_with(Instant::now);
+                }
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn read_control(
+        &mut self,
+        request: u8,
+        _value: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.check_connected()?;
+        let response = match state.queued.get_mut(&request).and_then(VecDeque::pop_front) {
+            Some(response) => response,
+            None => state
+                .responses
+                .get(&request)
+                .cloned()
+                .unwrap_or(Err(rusb::Error::Pipe)),
+        };
+        let response = response?;
+        let len = response.len().min(buf.len());
+        buf[..len].copy_from_slice(&response[..len]);
+        Ok(len)
+    }
+
+    fn write_bulk(&mut self, data: &[u8], _timeout: Duration) -> rusb::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.check_connected()?;
+        if state.started && data.is_empty() {
+            state.bulk_write_sizes.push(0);
+            return Ok(0);
+        }
+        if !state.started || data.len() < GS_USB_FRAME_SIZE {
+            return Err(rusb::Error::Io);
+        }
+        state.bulk_write_sizes.push(data.len());
+
+        let hw_timestamp = (state.flags & GS_CAN_MODE_HW_TIMESTAMP) != 0;
+        let fd_mode = (state.flags & GS_CAN_MODE_FD) != 0;
+        let frame = GsUsbFrame::from_bytes(data, hw_timestamp, fd_mode);
+        if state.echo {
+            state.rx.push_back(RxItem::Frame(frame.clone()));
+            self.shared.rx_ready.notify_all();
+        }
+        state.sent.push(frame);
+        Ok(data.len())
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        state.bulk_read_timeouts.push(timeout);
+
+        loop {
+            state.check_connected()?;
+            match state.rx.pop_front() {
+                Some(RxItem::Frame(frame)) => {
+                    let hw_timestamp = (state.flags & GS_CAN_MODE_HW_TIMESTAMP) != 0;
+                    let packed = frame.pack(hw_timestamp, frame.is_fd());
+                    let len = packed.len().min(buf.len());
+                    buf[..len].copy_from_slice(&packed[..len]);
+                    return Ok(len);
+                }
+                Some(RxItem::Raw(data)) => {
+                    let len = data.len().min(buf.len());
+                    buf[..len].copy_from_slice(&data[..len]);
+                    return Ok(len);
+                }
+                Some(RxItem::Error(e)) => return Err(e),
+                None => {}
+            }
+
+            // Nothing to receive: let a fast-forward scenario move on instead
+            // of waiting
+            if state.skip_to_next_event() {
+                continue;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(rusb::Error::Timeout);
+            }
+            let wake = state
+                .next_event_due()
+                .map_or(deadline, |due| due.min(deadline));
+            state = self
+                .shared
+                .rx_ready
+                .wait_timeout(state, wake.saturating_duration_since(now))
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn tx_max_packet_size(&self) -> Option<usize> {
+        self.shared.state.lock().unwrap().tx_max_packet_size
+    }
+
+    fn interface(&self) -> GsUsbInterface {
+        self.shared.state.lock().unwrap().interface
+    }
+
+    fn vendor_product(&self) -> Option<(u16, u16)> {
+        let state = self.shared.state.lock().unwrap();
+        state.usb_product.as_ref().map(|(usb_id, _)| *usb_id)
+    }
+
+    fn serial_number(&mut self) -> rusb::Result<Option<String>> {
+        Ok(Some("MOCK".to_string()))
+    }
+
+    fn product(&mut self) -> rusb::Result<Option<String>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state
+            .usb_product
+            .as_ref()
+            .map(|(_, product)| product.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IdFilter;
+    use crate::scenario::Scenario;
+    use crate::tap::{TapDirection, TapOptions};
+    use crate::virtual_bus::VirtualBus;
+
+    #[test]
+    fn test_records_configuration() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+
+        assert!(mock.is_started());
+        assert!(mock.is_claimed());
+        assert_eq!(mock.mode_flags(), GS_CAN_MODE_HW_TIMESTAMP);
+        let requests: Vec<u8> = mock.control_writes().iter().map(|w| w.request).collect();
+        assert!(requests.contains(&GS_USB_BREQ_BITTIMING));
+        assert_eq!(requests.last(), Some(&GS_USB_BREQ_MODE));
+
+        dev.stop().unwrap();
+        assert!(!mock.is_started());
+        assert!(!mock.is_claimed());
+    }
+
+    #[test]
+    fn test_close_releases_interface() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        assert!(mock.is_claimed());
+
+        dev.close().unwrap();
+        assert!(!mock.is_started());
+        assert!(!mock.is_claimed());
+    }
+
+    #[test]
+    fn test_no_raw_handle() {
+        let mock = MockGsUsb::new();
+        let dev = mock.open();
+        assert!(dev.raw_handle().is_none());
+        assert!(matches!(
+            dev.into_parts(),
+            Err(GsUsbError::FeatureNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_rx_tx_and_echo() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+
+        dev.send(&GsUsbFrame::with_data(0x321, &[9, 8])).unwrap();
+        assert_eq!(mock.take_sent_frames()[0].data(), &[9, 8]);
+        assert!(mock.sent_frames().is_empty());
+        assert!(dev.read(Duration::from_millis(10)).unwrap().is_echo_frame());
+
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[1]));
+        let frame = dev.read(Duration::from_millis(10)).unwrap();
+        assert!(frame.is_rx_frame());
+        assert_eq!(frame.arbitration_id(), 0x100);
+
+        assert!(matches!(
+            dev.read(Duration::from_millis(1)),
+            Err(GsUsbError::ReadTimeout)
+        ));
+    }
+
+    #[test]
+    fn test_read_until() {
+        use crate::device::Unmatched;
+        use std::time::Instant;
+
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        for id in [0x100, 0x200, 0x300, 0x400] {
+            mock.push_rx(&GsUsbFrame::with_data(id, &[]));
+        }
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let is = |id| move |frame: &GsUsbFrame| frame.arbitration_id() == id;
+
+        let frame = dev
+            .read_until(is(0x300), deadline, Unmatched::Keep)
+            .unwrap();
+        assert_eq!(frame.arbitration_id(), 0x300);
+        // The kept frames are searched first and still come in order
+        let frame = dev
+            .read_until(is(0x200), deadline, Unmatched::Keep)
+            .unwrap();
+        assert_eq!(frame.arbitration_id(), 0x200);
+        assert_eq!(
+            dev.read(Duration::from_millis(10))
+                .unwrap()
+                .arbitration_id(),
+            0x100
+        );
+
+        mock.push_rx(&GsUsbFrame::with_data(0x500, &[]));
+        assert!(matches!(
+            dev.read_until(is(0x600), deadline, Unmatched::Discard),
+            Err(GsUsbError::ReadTimeout)
+        ));
+        assert!(matches!(
+            dev.read(Duration::from_millis(1)),
+            Err(GsUsbError::ReadTimeout)
+        ));
+
+        // A deadline less than a millisecond away times out instead of
+        // handing libusb a 0 ms (endless) wait
+        mock.push_rx(&GsUsbFrame::with_data(0x500, &[]));
+        let deadline = Instant::now() + Duration::from_micros(300);
+        assert!(matches!(
+            dev.read_until(is(0x600), deadline, Unmatched::Discard),
+            Err(GsUsbError::ReadTimeout)
+        ));
+        assert!(mock
+            .bulk_read_timeouts()
+            .iter()
+            .all(|t| *t >= Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_sub_millisecond_timeouts() {
+        let mock = MockGsUsb::new();
+        let mut dev = started(&mock);
+        dev.set_filters(vec![IdFilter::new(0x100, 0x7FF)]);
+        for _ in 0..3 {
+            mock.push_rx(&GsUsbFrame::with_data(0x200, &[]));
+        }
+
+        // libusb would wait forever for the 0 ms these truncate to
+        for timeout in [Duration::from_micros(300), Duration::ZERO] {
+            assert!(matches!(dev.read(timeout), Err(GsUsbError::ReadTimeout)));
+        }
+        let timeouts = mock.bulk_read_timeouts();
+        assert!(!timeouts.is_empty());
+        assert!(timeouts.iter().all(|t| *t >= Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_read_n() {
+        use std::time::Instant;
+
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        for id in 0..3 {
+            mock.push_rx(&GsUsbFrame::with_data(id, &[]));
+        }
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let batch = dev.read_n(2, deadline).unwrap();
+        assert_eq!((batch.frames.len(), batch.timed_out), (2, false));
+
+        let batch = dev.read_n(2, deadline).unwrap();
+        assert_eq!(batch.frames[0].arbitration_id(), 2);
+        assert_eq!((batch.frames.len(), batch.timed_out), (1, true));
+        assert!(Instant::now() >= deadline);
+
+        // A deadline less than a millisecond away times out instead of
+        // handing libusb a 0 ms (endless) wait
+        let batch = dev
+            .read_n(2, Instant::now() + Duration::from_micros(300))
+            .unwrap();
+        assert!(batch.frames.is_empty() && batch.timed_out);
+        assert!(mock
+            .bulk_read_timeouts()
+            .iter()
+            .all(|t| *t >= Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_peek() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let timeout = Duration::from_millis(10);
+        assert!(matches!(dev.peek(timeout), Err(GsUsbError::ReadTimeout)));
+
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[]));
+        mock.push_rx(&GsUsbFrame::with_data(0x200, &[]));
+        assert_eq!(dev.peek(timeout).unwrap().arbitration_id(), 0x100);
+        assert_eq!(dev.peek(timeout).unwrap().arbitration_id(), 0x100);
+        assert_eq!(dev.read(timeout).unwrap().arbitration_id(), 0x100);
+        assert_eq!(dev.peek(timeout).unwrap().arbitration_id(), 0x200);
+        assert_eq!(dev.read(timeout).unwrap().arbitration_id(), 0x200);
+    }
+
+    #[test]
+    fn test_read_many() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let timeout = Duration::from_millis(20);
+        assert!(dev.read_many(10, timeout).unwrap().is_empty());
+
+        for id in 0..5 {
+            mock.push_rx(&GsUsbFrame::with_data(id, &[]));
+        }
+        dev.peek(timeout).unwrap();
+        assert_eq!(dev.read_many(3, timeout).unwrap().len(), 3);
+        let frames = dev.read_many(10, timeout).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].arbitration_id(), 4);
+
+        // A reused batch keeps its memory
+        let mut batch = Vec::with_capacity(4);
+        mock.push_rx(&GsUsbFrame::with_data(5, &[]));
+        assert_eq!(dev.read_many_into(&mut batch, 4, timeout).unwrap(), 1);
+        let capacity = batch.capacity();
+        assert_eq!(dev.read_many_into(&mut batch, 4, timeout).unwrap(), 0);
+        assert!(batch.is_empty());
+        assert_eq!(batch.capacity(), capacity);
+
+        // The gap after a frame and a timeout under a millisecond don't
+        // hand libusb a 0 ms (endless) wait
+        mock.push_rx(&GsUsbFrame::with_data(6, &[]));
+        let frames = dev.read_many(4, Duration::from_micros(300)).unwrap();
+        assert!(frames.len() <= 1);
+        assert!(mock
+            .bulk_read_timeouts()
+            .iter()
+            .all(|t| *t >= Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_transfer_stats() {
+        let full = GsUsbFrame::with_data(0x7, &[1; 8]).pack(false, false);
+        let compact = GsUsbFrame::with_data(0x8, &[1; 4]).pack(false, false);
+        let raw = |data: &[u8]| ScenarioEvent::RawRx(data.to_vec());
+        let mock = MockGsUsb::new();
+        mock.play(
+            Scenario::new()
+                .at(Duration::ZERO, raw(&full))
+                // Cut inside the payload
+                .at(Duration::ZERO, raw(&full[..14]))
+                // Without the padding to eight data bytes
+                .at(Duration::ZERO, raw(&compact[..16])),
+        );
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let timeout = Duration::from_millis(20);
+        dev.read(timeout).unwrap();
+        assert!(matches!(
+            dev.read(timeout),
+            Err(GsUsbError::InvalidResponse { actual: 14, .. })
+        ));
+        assert_eq!(dev.read(timeout).unwrap().data(), [1; 4]);
+
+        let stats = dev.transfer_stats();
+        assert_eq!(
+            (stats.transfers(), stats.frames(), stats.bytes()),
+            (3, 2, 50)
+        );
+        assert_eq!(
+            (stats.short_transfers(), stats.truncated_transfers()),
+            (1, 1)
+        );
+        assert_eq!(
+            stats.sizes().collect::<Vec<_>>(),
+            [(14, 1), (16, 1), (20, 1)]
+        );
+        assert_eq!((stats.min_bytes(), stats.max_bytes()), (Some(14), Some(20)));
+        assert_eq!(stats.frames_per_transfer(), Some(2.0 / 3.0));
+
+        dev.reset_transfer_stats();
+        assert_eq!(dev.transfer_stats().transfers(), 0);
+    }
+
+    #[test]
+    fn test_drain() {
+        use crate::device::Unmatched;
+        use std::time::Instant;
+
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        for id in 0..4 {
+            mock.push_rx(&GsUsbFrame::with_data(id, &[]));
+        }
+        let deadline = Instant::now() + Duration::from_millis(10);
+        dev.read_until(
+            |frame| frame.arbitration_id() == 1,
+            deadline,
+            Unmatched::Keep,
+        )
+        .unwrap();
+
+        // Only the kept frame without flushing, then the device's frames
+        let ids = |frames: Vec<GsUsbFrame>| {
+            frames
+                .iter()
+                .map(|f| f.arbitration_id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(dev.drain(None).unwrap()), [0]);
+        assert_eq!(
+            ids(dev.drain(Some(Duration::from_millis(5))).unwrap()),
+            [2, 3]
+        );
+        assert!(dev
+            .drain(Some(Duration::from_millis(1)))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cancel_read() {
+        use std::time::Instant;
+
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let token = dev.cancel_token();
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[]));
+        assert!(dev.read(Duration::from_millis(10)).is_ok());
+
+        let canceller = token.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let start = Instant::now();
+        assert!(matches!(
+            dev.read(Duration::from_secs(60)),
+            Err(GsUsbError::Cancelled)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread.join().unwrap();
+
+        // Cancelled until reset
+        mock.push_rx(&GsUsbFrame::with_data(0x200, &[]));
+        assert!(matches!(
+            dev.read(Duration::from_millis(10)),
+            Err(GsUsbError::Cancelled)
+        ));
+        dev.cancel_token().reset();
+        assert_eq!(
+            dev.read(Duration::from_millis(10))
+                .unwrap()
+                .arbitration_id(),
+            0x200
+        );
+    }
+
+    #[test]
+    fn test_python_can_compat() {
+        use crate::constants::{GS_CAN_FLAG_OVERFLOW, PYTHON_CAN_MODE_FLAGS};
+
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.set_python_can_compat(true);
+        dev.set_bitrate(1_000_000).unwrap();
+        assert_eq!(dev.last_timing().unwrap().sample_point(), 875);
+        dev.set_timestamp_tick_hz(2_000_000);
+        dev.enable_tx_latency(4);
+        dev.start(PYTHON_CAN_MODE_FLAGS).unwrap();
+
+        // Overflowed frames and raw timestamps come through unchanged
+        let mut frame = GsUsbFrame::with_data(0x200, &[7]);
+        frame.flags |= GS_CAN_FLAG_OVERFLOW;
+        frame.timestamp_us = 1000;
+        mock.push_rx(&frame);
+        let received = dev.read(Duration::from_millis(10)).unwrap();
+        assert_eq!(received.arbitration_id(), 0x200);
+        assert!(received.is_overflow());
+        assert_eq!(received.timestamp_us, 1000);
+
+        // Echo IDs are sent as given, and echoes are returned
+        dev.send(&GsUsbFrame::with_data(0x100, &[])).unwrap();
+        assert_eq!(mock.take_sent_frames()[0].echo_id, 0);
+        assert!(dev.read(Duration::from_millis(10)).unwrap().is_echo_frame());
+    }
+
+    #[cfg(not(feature = "fd"))]
+    #[test]
+    fn test_classic_only() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        assert!(!dev.supports_fd().unwrap());
+        dev.set_bitrate(500_000).unwrap();
+        assert!(matches!(
+            dev.set_data_bitrate(2_000_000),
+            Err(GsUsbError::FdNotSupported)
+        ));
+
+        // FD is dropped from the mode, and FD frames can't be sent
+        dev.start(GS_CAN_MODE_FD | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+        assert_eq!(mock.mode_flags(), GS_CAN_MODE_HW_TIMESTAMP);
+        let frame = GsUsbFrame::with_fd_data(0x123, &[0xAA; 12], false);
+        assert_eq!(frame.data(), [0xAA; 8]);
+        assert!(matches!(dev.send(&frame), Err(GsUsbError::FdNotSupported)));
+        assert!(mock.take_sent_frames().is_empty());
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_rx_buffer_follows_mode() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        let timeout = Duration::from_millis(10);
+
+        // The reused transfer buffer grows and shrinks with the frame size
+        for (flags, data) in [
+            (GS_CAN_MODE_NORMAL, &[1u8; 8][..]),
+            (GS_CAN_MODE_FD | GS_CAN_MODE_HW_TIMESTAMP, &[2; 64]),
+            (GS_CAN_MODE_NORMAL, &[3; 4]),
+        ] {
+            dev.set_data_bitrate(2_000_000).unwrap();
+            dev.start(flags).unwrap();
+            let frame = if flags & GS_CAN_MODE_FD != 0 {
+                GsUsbFrame::with_fd_data(0x123, data, true)
+            } else {
+                GsUsbFrame::with_data(0x123, data)
+            };
+            mock.push_rx(&frame);
+            assert_eq!(dev.read(timeout).unwrap().data(), data);
+            dev.stop().unwrap();
+        }
+        assert_eq!(dev.transfer_stats().truncated_transfers(), 0);
+    }
+
+    #[test]
+    fn test_transfer_errors_name_endpoints() {
+        let mock = MockGsUsb::new();
+        mock.set_interface(GsUsbInterface {
+            number: 2,
+            endpoint_in: 0x83,
+            endpoint_out: 0x04,
+        });
+        mock.play(Scenario::new().disconnect(Duration::from_millis(1)));
+        let mut dev = started(&mock);
+
+        let endpoint = |result: Result<_>| match result {
+            Err(GsUsbError::BulkTransfer { endpoint, .. }) => endpoint,
+            other => panic!("expected a bulk transfer error, got {other:?}"),
+        };
+        assert_eq!(
+            endpoint(dev.read(Duration::from_millis(10)).map(drop)),
+            0x83
+        );
+        assert_eq!(endpoint(dev.send(&GsUsbFrame::with_data(0x1, &[]))), 0x04);
+    }
+
+    #[test]
+    fn test_lpc546xx_quirk() {
+        let mut capability = VirtualBus::default_capability();
+        capability.feature |= GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX;
+        let mock = MockGsUsb::with_capability(capability);
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+
+        // Padded to a full packet and closed with a zero-length packet
+        dev.send(&GsUsbFrame::with_data(0x123, &[1, 2, 3])).unwrap();
+        assert_eq!(mock.bulk_write_sizes(), [64, 0]);
+        let sent = mock.take_sent_frames();
+        assert_eq!((sent[0].can_id, sent[0].data()), (0x123, &[1, 2, 3][..]));
+
+        // Other devices get the frames as they are
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        dev.send(&GsUsbFrame::with_data(0x123, &[1, 2, 3])).unwrap();
+        assert_eq!(mock.bulk_write_sizes(), [GS_USB_FRAME_SIZE]);
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_firmware_quirks() {
+        use crate::quirks::Quirks;
+
+        let cantact_pro = |fw_version| {
+            let mock = MockGsUsb::new();
+            mock.set_usb_product(GS_USB_ID_VENDOR, GS_USB_ID_PRODUCT, "CANtact Pro");
+            let info = DeviceInfo {
+                reserved1: 0,
+                reserved2: 0,
+                reserved3: 0,
+                icount: 0,
+                fw_version,
+                hw_version: 1,
+            };
+            mock.set_response(GS_USB_BREQ_DEVICE_CONFIG, info.pack());
+            mock
+        };
+
+        let mock = cantact_pro(2);
+        let mut dev = mock.open();
+        assert!(dev.quirks().unwrap().usb_quirk_lpc546xx);
+        dev.set_bitrate(500_000).unwrap();
+        dev.set_data_bitrate(2_000_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let requests: Vec<u8> = mock.control_writes().iter().map(|w| w.request).collect();
+        assert!(requests.contains(&GS_USB_BREQ_GET_USER_ID));
+        assert!(!requests.contains(&GS_USB_BREQ_DATA_BITTIMING));
+        dev.send(&GsUsbFrame::with_data(0x123, &[1])).unwrap();
+        assert_eq!(mock.bulk_write_sizes(), [64, 0]);
+
+        // Fixed in later firmware
+        let mut dev = cantact_pro(3).open();
+        assert!(dev.quirks().unwrap().is_none());
+
+        // Overridden
+        let mock = cantact_pro(2);
+        let mut dev = mock.open();
+        dev.set_quirks(Quirks {
+            broken_identify: true,
+            ..Quirks::NONE
+        });
+        assert!(matches!(
+            dev.identify(0, true),
+            Err(GsUsbError::FeatureNotSupported("identify"))
+        ));
+        dev.set_data_bitrate(2_000_000).unwrap();
+        assert_eq!(
+            mock.control_writes().last().map(|w| w.request),
+            Some(GS_USB_BREQ_DATA_BITTIMING)
+        );
+    }
+
+    #[test]
+    fn test_programmed_responses() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+
+        mock.set_device_state(DeviceState {
+            state: GS_CAN_STATE_BUS_OFF,
+            rxerr: 3,
+            txerr: 255,
+        });
+        let state = dev.get_state(0).unwrap();
+        assert_eq!(state.state, GS_CAN_STATE_BUS_OFF);
+        assert_eq!(state.txerr, 255);
+
+        mock.set_error(GS_USB_BREQ_BITTIMING, rusb::Error::Pipe);
+        assert!(dev.set_bitrate(500_000).is_err());
+
+        mock.push_rx_error(rusb::Error::NoDevice);
+        assert!(dev.read(Duration::from_millis(10)).is_err());
+
+        assert!(matches!(
+            dev.termination(0),
+            Err(GsUsbError::FeatureNotSupported(_))
+        ));
+        let mut capability = VirtualBus::default_capability();
+        capability.feature |= GS_CAN_FEATURE_TERMINATION;
+        let mock = MockGsUsb::with_capability(capability);
+        mock.set_response(GS_USB_BREQ_GET_TERMINATION, 1u32.to_le_bytes());
+        assert!(mock.open().termination(0).unwrap());
+    }
+
+    fn started(mock: &MockGsUsb) -> GsUsb {
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+        dev
+    }
+
+    #[test]
+    fn test_taps() {
+        let mock = MockGsUsb::new();
+        let mut dev = started(&mock);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let all = Arc::new(Mutex::new(Vec::new()));
+        let rx_tap = {
+            let received = Arc::clone(&received);
+            dev.tap(move |frame| received.lock().unwrap().push(frame.can_id))
+        };
+        {
+            let all = Arc::clone(&all);
+            let options = TapOptions {
+                echoes: true,
+                tx: true,
+            };
+            dev.tap_with(options, move |direction, frame| {
+                all.lock().unwrap().push((direction, frame.can_id))
+            });
+        }
+
+        // Taps see frames the filters drop and frames only peeked at
+        dev.set_filters(vec![IdFilter::new(0x100, 0x7FF)]);
+        mock.push_rx(&GsUsbFrame::with_data(0x200, &[]));
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[]));
+        let timeout = Duration::from_millis(100);
+        assert_eq!(dev.peek(timeout).unwrap().can_id, 0x100);
+        assert_eq!(dev.read(timeout).unwrap().can_id, 0x100);
+        dev.send(&GsUsbFrame::with_data(0x300, &[])).unwrap();
+        assert!(dev.read(timeout).unwrap().is_echo_frame());
+
+        assert_eq!(*received.lock().unwrap(), [0x200, 0x100]);
+        assert_eq!(
+            *all.lock().unwrap(),
+            [
+                (TapDirection::Rx, 0x200),
+                (TapDirection::Rx, 0x100),
+                (TapDirection::Tx, 0x300),
+                (TapDirection::Rx, 0x300),
+            ]
+        );
+
+        assert!(dev.untap(rx_tap));
+        assert!(!dev.untap(rx_tap));
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[]));
+        dev.read(timeout).unwrap();
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert_eq!(all.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_claim_wait() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        mock.hold_elsewhere(1);
+        let err = dev.start(GS_CAN_MODE_NORMAL).unwrap_err();
+        assert!(err.is_claimed_elsewhere());
+        assert!(!mock.is_claimed());
+
+        // Released while waiting
+        mock.hold_elsewhere(2);
+        dev.set_claim_wait(Duration::from_secs(5));
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        assert!(mock.is_claimed());
+    }
+
+    #[cfg(feature = "instrument")]
+    #[test]
+    fn test_hot_path_stats() {
+        use crate::constants::GS_CAN_FLAG_OVERFLOW;
+
+        let mock = MockGsUsb::new();
+        let mut dev = started(&mock);
+        dev.set_filters(vec![IdFilter::new(0x100, 0x7FF)]);
+        mock.push_rx(&GsUsbFrame::with_data(0x200, &[]));
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[]));
+        dev.read(Duration::from_millis(100)).unwrap();
+
+        // Both frames were transferred and parsed, one was returned
+        let stats = dev.hot_path_stats();
+        assert_eq!(stats.transfer_wait().count(), 2);
+        assert_eq!(stats.parse().count(), 2);
+        assert_eq!(stats.dispatch().count(), 1);
+        assert!(stats.parse().mean().unwrap() <= stats.parse().max());
+        assert_eq!(stats.queue_wait().count(), 0);
+
+        dev.reset_hot_path_stats();
+        assert_eq!(dev.hot_path_stats().transfer_wait().count(), 0);
+        assert_eq!(dev.hot_path_stats().dispatch().mean(), None);
+
+        // A peeked frame waits in the queue until read
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[]));
+        dev.peek(Duration::from_millis(100)).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        dev.read(Duration::from_millis(100)).unwrap();
+        let stats = dev.hot_path_stats();
+        assert_eq!(stats.dispatch().count(), 1);
+        assert_eq!(stats.queue_wait().count(), 1);
+        assert!(stats.queue_wait().max() >= Duration::from_millis(2));
+
+        // A frame held back behind its overflow report is dispatched when read
+        dev.reset_hot_path_stats();
+        let mut overflowed = GsUsbFrame::with_data(0x100, &[]);
+        overflowed.flags |= GS_CAN_FLAG_OVERFLOW;
+        mock.push_rx(&overflowed);
+        dev.set_filters(Vec::new());
+        assert!(dev
+            .read(Duration::from_millis(100))
+            .unwrap()
+            .is_error_frame());
+        assert_eq!(dev.read(Duration::from_millis(100)).unwrap().can_id, 0x100);
+        let stats = dev.hot_path_stats();
+        assert_eq!(stats.parse().count(), 1);
+        assert_eq!(stats.dispatch().count(), 2);
+        assert_eq!(stats.queue_wait().count(), 1);
+    }
+
+    #[test]
+    fn test_scenario_bus_off_and_recovery() {
+        let mock = MockGsUsb::new();
+        mock.play(
+            Scenario::new()
+                .periodic(
+                    Duration::from_millis(5),
+                    Duration::from_millis(10),
+                    100,
+                    GsUsbFrame::with_data(0x100, &[1]),
+                )
+                .bus_off(Duration::from_secs(2))
+                .recover(Duration::from_secs(3)),
+        );
+        // Nothing is emitted before start
+        assert!(!mock.scenario_finished());
+        let mut dev = started(&mock);
+
+        for i in 0..100 {
+            let frame = dev.read(Duration::from_millis(10)).unwrap();
+            assert_eq!(frame.arbitration_id(), 0x100);
+            assert_eq!(frame.timestamp_us, 5_000 + i * 10_000);
+        }
+        assert_eq!(dev.get_state(0).unwrap().state, GS_CAN_STATE_ERROR_ACTIVE);
+
+        let frame = dev.read(Duration::from_millis(10)).unwrap();
+        assert!(frame.is_error_frame());
+        assert_ne!(frame.can_id & CAN_ERR_BUSOFF, 0);
+        assert_eq!(dev.get_state(0).unwrap().state, GS_CAN_STATE_BUS_OFF);
+
+        let frame = dev.read(Duration::from_millis(10)).unwrap();
+        assert_ne!(frame.can_id & CAN_ERR_RESTARTED, 0);
+        assert_eq!(frame.timestamp_us, 3_000_000);
+        assert_eq!(dev.get_state(0).unwrap().state, GS_CAN_STATE_ERROR_ACTIVE);
+
+        assert!(mock.scenario_finished());
+        assert!(matches!(
+            dev.read(Duration::from_millis(1)),
+            Err(GsUsbError::ReadTimeout)
+        ));
+    }
+
+    #[test]
+    fn test_scenario_disconnect_and_reconnect() {
+        let mock = MockGsUsb::new();
+        mock.play(
+            Scenario::new()
+                .frame(Duration::from_millis(1), GsUsbFrame::with_data(0x1, &[]))
+                .disconnect(Duration::from_millis(2))
+                .reconnect(Duration::from_millis(500))
+                .frame(Duration::from_millis(600), GsUsbFrame::with_data(0x2, &[])),
+        );
+        let mut dev = started(&mock);
+
+        assert_eq!(
+            dev.read(Duration::from_millis(10))
+                .unwrap()
+                .arbitration_id(),
+            0x1
+        );
+        assert!(dev.read(Duration::from_millis(10)).is_err());
+        assert!(!mock.is_connected());
+
+        // Reconnection logic retries until the device is back
+        let mut attempts = 0;
+        while dev.start(GS_CAN_MODE_NORMAL).is_err() {
+            attempts += 1;
+            assert!(attempts < 10);
+        }
+        assert!(mock.is_connected());
+        assert_eq!(
+            dev.read(Duration::from_millis(10))
+                .unwrap()
+                .arbitration_id(),
+            0x2
+        );
+    }
+
+    #[test]
+    fn test_scenario_realtime() {
+        let mock = MockGsUsb::new();
+        mock.play(
+            Scenario::new()
+                .realtime()
+                .frame(Duration::from_millis(30), GsUsbFrame::with_data(0x1, &[])),
+        );
+        let mut dev = started(&mock);
+
+        assert!(matches!(
+            dev.read(Duration::from_millis(1)),
+            Err(GsUsbError::ReadTimeout)
+        ));
+        let frame = dev.read(Duration::from_millis(500)).unwrap();
+        assert_eq!(frame.arbitration_id(), 0x1);
+    }
+}
@@ -0,0 +1,281 @@
+//! Mode flag negotiation
+//!
+//! Which `GS_CAN_MODE_*` flags a channel can be started with depends on the
+//! device's feature bits. A `ModeRequest` states for each flag whether it is
+//! required or merely preferred, and `negotiate()` turns that into the best
+//! flag set the device supports, or an error naming the required flags it
+//! lacks:
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, ModeRequest};
+//! use gs_usb::{GS_CAN_MODE_FD, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_ONE_SHOT};
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! let flags = ModeRequest::new()
+//!     .prefer(GS_CAN_MODE_HW_TIMESTAMP | GS_CAN_MODE_FD)
+//!     .require(GS_CAN_MODE_ONE_SHOT)
+//!     .negotiate(&dev.device_capability()?)?;
+//! dev.set_bitrate(500_000)?;
+//! dev.start(flags)?;
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use crate::constants::{
+    mode_flag_name, GS_CAN_MODE_FD, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_LISTEN_ONLY,
+    GS_CAN_MODE_LOOP_BACK, GS_CAN_MODE_ONE_SHOT, GS_CAN_MODE_TRIPLE_SAMPLE,
+};
+use crate::error::{GsUsbError, Result};
+use crate::structures::DeviceCapability;
+
+/// Mode flags `GsUsb::start()` passes on to the device
+pub(crate) const DRIVER_MODE_FLAGS: u32 = GS_CAN_MODE_LISTEN_ONLY
+    | GS_CAN_MODE_LOOP_BACK
+    | GS_CAN_MODE_ONE_SHOT
+    | GS_CAN_MODE_HW_TIMESTAMP
+    | GS_CAN_MODE_TRIPLE_SAMPLE
+    | if cfg!(feature = "fd") {
+        GS_CAN_MODE_FD
+    } else {
+        0
+    };
+
+/// Where loopback frames go, see `GS_CAN_MODE_LOOP_BACK`
+///
+/// gs_usb has a single loopback flag; which kind is active depends on
+/// whether listen-only mode is set along with it. Controllers in the common
+/// firmwares (bxCAN, M_CAN) follow this, but the protocol can't report it,
+/// so a self test that matters should check for its frames on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Loopback {
+    /// Frames stay inside the controller and the bus is left alone, so the
+    /// device can be tested unconnected or on a live bus
+    Internal,
+    /// Frames are also sent on the bus, so the transceiver and wiring are
+    /// tested too and other nodes receive them
+    External,
+}
+
+impl Loopback {
+    /// Mode flags selecting this kind of loopback
+    pub fn flags(&self) -> u32 {
+        match self {
+            Self::Internal => GS_CAN_MODE_LOOP_BACK | GS_CAN_MODE_LISTEN_ONLY,
+            Self::External => GS_CAN_MODE_LOOP_BACK,
+        }
+    }
+
+    /// Kind of loopback selected by mode flags, `None` without loopback
+    pub fn from_flags(flags: u32) -> Option<Self> {
+        if flags & GS_CAN_MODE_LOOP_BACK == 0 {
+            None
+        } else if flags & GS_CAN_MODE_LISTEN_ONLY != 0 {
+            Some(Self::Internal)
+        } else {
+            Some(Self::External)
+        }
+    }
+}
+
+impl std::fmt::Display for Loopback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Internal => write!(f, "internal loopback"),
+            Self::External => write!(f, "external loopback"),
+        }
+    }
+}
+
+/// Mode a channel was started with, see `GsUsb::active_mode()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveMode {
+    /// Flags passed to `start()`
+    pub requested: u32,
+    /// Flags sent to the device
+    pub flags: u32,
+}
+
+impl ActiveMode {
+    /// Requested flags left out because the device or this driver doesn't
+    /// support them
+    pub fn stripped(&self) -> u32 {
+        self.requested & !self.flags
+    }
+
+    /// Check if all requested flags are in effect
+    pub fn is_complete(&self) -> bool {
+        self.stripped() == 0
+    }
+
+    /// Kind of loopback in effect, `None` without loopback
+    ///
+    /// Internal loopback requested on a device without listen-only mode
+    /// ends up external.
+    pub fn loopback(&self) -> Option<Loopback> {
+        Loopback::from_flags(self.flags)
+    }
+}
+
+impl std::fmt::Display for ActiveMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.flags == 0 {
+            write!(f, "NORMAL")?;
+        } else {
+            write!(
+                f,
+                "{}",
+                flag_names(self.flags).collect::<Vec<_>>().join(" | ")
+            )?;
+        }
+        if !self.is_complete() {
+            let stripped: Vec<_> = flag_names(self.stripped()).collect();
+            write!(f, " (unsupported: {})", stripped.join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Names of the flags set in `flags`, lowest bit first
+fn flag_names(flags: u32) -> impl Iterator<Item = &'static str> {
+    single_flags(flags).map(mode_flag_name)
+}
+
+/// Individual bits set in `flags`, lowest first
+fn single_flags(flags: u32) -> impl Iterator<Item = u32> {
+    (0..32)
+        .map(|bit| 1 << bit)
+        .filter(move |flag| flags & flag != 0)
+}
+
+/// Required and preferred mode flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModeRequest {
+    required: u32,
+    preferred: u32,
+}
+
+impl ModeRequest {
+    /// Request without any flags (normal mode)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Flags the channel must be started with
+    pub fn require(mut self, flags: u32) -> Self {
+        self.required |= flags;
+        self
+    }
+
+    /// Flags to use if the device supports them
+    pub fn prefer(mut self, flags: u32) -> Self {
+        self.preferred |= flags;
+        self
+    }
+
+    /// Required flags
+    pub fn required(&self) -> u32 {
+        self.required
+    }
+
+    /// Preferred flags
+    pub fn preferred(&self) -> u32 {
+        self.preferred
+    }
+
+    /// Best flag set for a device
+    ///
+    /// All required flags plus the preferred ones the device supports. Fails
+    /// with `GsUsbError::FeatureNotSupported` for a missing required flag, or
+    /// `GsUsbError::InvalidConfigs` listing all of them if several are
+    /// missing.
+    pub fn negotiate(&self, capability: &DeviceCapability) -> Result<u32> {
+        // Mode flags share their bit with the feature flag
+        let supported = capability.feature & DRIVER_MODE_FLAGS;
+        let mut missing: Vec<GsUsbError> = flag_names(self.required & !supported)
+            .map(GsUsbError::FeatureNotSupported)
+            .collect();
+        match missing.len() {
+            0 => Ok(self.required | (self.preferred & supported)),
+            1 => Err(missing.remove(0)),
+            _ => Err(GsUsbError::InvalidConfigs(missing)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{
+        GS_CAN_FEATURE_FD, GS_CAN_FEATURE_LISTEN_ONLY, GS_CAN_MODE_TRIPLE_SAMPLE,
+    };
+    use crate::mock::MockGsUsb;
+    use crate::virtual_bus::VirtualBus;
+
+    #[test]
+    fn test_negotiate() {
+        let mut capability = VirtualBus::default_capability();
+        capability.feature &= !GS_CAN_FEATURE_FD;
+
+        let request = ModeRequest::new()
+            .prefer(GS_CAN_MODE_HW_TIMESTAMP | GS_CAN_MODE_FD)
+            .require(GS_CAN_MODE_ONE_SHOT);
+        assert_eq!(
+            request.negotiate(&capability).unwrap(),
+            GS_CAN_MODE_HW_TIMESTAMP | GS_CAN_MODE_ONE_SHOT
+        );
+
+        assert!(matches!(
+            request.require(GS_CAN_MODE_FD).negotiate(&capability),
+            Err(GsUsbError::FeatureNotSupported("FD"))
+        ));
+        // Flags the driver doesn't pass on can't be required either
+        let err = ModeRequest::new()
+            .require(GS_CAN_MODE_FD | GS_CAN_MODE_TRIPLE_SAMPLE)
+            .negotiate(&capability)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid configuration: Device does not support feature: TRIPLE_SAMPLE; \
+             Device does not support feature: FD"
+        );
+    }
+
+    #[test]
+    fn test_active_mode() {
+        let mut capability = VirtualBus::default_capability();
+        capability.feature &= !GS_CAN_FEATURE_FD;
+        let mock = MockGsUsb::with_capability(capability);
+        let mut dev = mock.open();
+        assert_eq!(dev.active_mode(), None);
+
+        dev.start(GS_CAN_MODE_HW_TIMESTAMP | GS_CAN_MODE_FD | GS_CAN_MODE_TRIPLE_SAMPLE)
+            .unwrap();
+        let mode = dev.active_mode().unwrap();
+        assert_eq!(mode.flags, GS_CAN_MODE_HW_TIMESTAMP);
+        assert_eq!(mode.stripped(), GS_CAN_MODE_FD | GS_CAN_MODE_TRIPLE_SAMPLE);
+        assert_eq!(
+            mode.to_string(),
+            "HW_TIMESTAMP (unsupported: TRIPLE_SAMPLE | FD)"
+        );
+
+        dev.stop().unwrap();
+        assert_eq!(dev.active_mode(), None);
+    }
+
+    #[test]
+    fn test_loopback() {
+        for loopback in [Loopback::Internal, Loopback::External] {
+            assert_eq!(Loopback::from_flags(loopback.flags()), Some(loopback));
+        }
+        assert_eq!(Loopback::from_flags(GS_CAN_MODE_LISTEN_ONLY), None);
+
+        // Without listen-only, internal loopback degrades to external
+        let mut capability = VirtualBus::default_capability();
+        capability.feature &= !GS_CAN_FEATURE_LISTEN_ONLY;
+        let mut dev = MockGsUsb::with_capability(capability).open();
+        dev.start(Loopback::Internal.flags()).unwrap();
+        assert_eq!(
+            dev.active_mode().unwrap().loopback(),
+            Some(Loopback::External)
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Passive bus monitoring
+//!
+//! `GsUsb::monitor()` starts a device in listen-only mode and hands back a
+//! `Monitor`, which can read but not send. In listen-only mode the controller
+//! neither transmits nor acknowledges frames, so sniffing a production bus
+//! can't disturb it.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::GsUsb;
+//! use std::time::Duration;
+//!
+//! let mut monitor = GsUsb::scan()?.remove(0).monitor(500_000)?;
+//! loop {
+//!     match monitor.read(Duration::from_secs(1)) {
+//!         Ok(frame) => println!("{frame}"),
+//!         Err(gs_usb::GsUsbError::ReadTimeout) => continue,
+//!         Err(e) => return Err(e),
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::time::Duration;
+
+use crate::config::IdFilter;
+use crate::device::GsUsb;
+use crate::error::Result;
+use crate::frame::GsUsbFrame;
+use crate::structures::DeviceState;
+
+/// A device started in listen-only mode, receive only
+///
+/// Created by `GsUsb::monitor()`. Dropping it stops the device.
+#[derive(Debug)]
+pub struct Monitor {
+    dev: GsUsb,
+}
+
+impl Monitor {
+    /// Wrap a device started in listen-only mode
+    pub(crate) fn new(dev: GsUsb) -> Self {
+        Self { dev }
+    }
+
+    /// Read the next received frame, see `GsUsb::read()`
+    pub fn read(&mut self, timeout: Duration) -> Result<GsUsbFrame> {
+        self.dev.read(timeout)
+    }
+
+    /// Only receive frames passing one of `filters`, see `GsUsb::set_filters()`
+    pub fn set_filters(&mut self, filters: Vec<IdFilter>) {
+        self.dev.set_filters(filters);
+    }
+
+    /// Bus state and error counters of the channel
+    pub fn get_state(&mut self) -> Result<DeviceState> {
+        self.dev.get_state(0)
+    }
+
+    /// The monitoring device, for its settings
+    pub fn device(&self) -> &GsUsb {
+        &self.dev
+    }
+
+    /// Stop the device and close it, see `GsUsb::close()`
+    pub fn close(self) -> Result<()> {
+        self.dev.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{
+        CAN_EFF_FLAG, CAN_ERR_ACK, GS_CAN_FEATURE_LISTEN_ONLY, GS_CAN_MODE_HW_TIMESTAMP,
+        GS_CAN_MODE_LISTEN_ONLY, GS_CAN_MODE_NORMAL,
+    };
+    use crate::error::GsUsbError;
+    use crate::mock::MockGsUsb;
+    use crate::virtual_bus::VirtualBus;
+
+    fn started(bus: &VirtualBus) -> GsUsb {
+        let mut dev = bus.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        dev
+    }
+
+    #[test]
+    fn test_monitor() {
+        let bus = VirtualBus::new();
+        let mut sender = started(&bus);
+        let mut monitor = bus.open().monitor(500_000).unwrap();
+        assert_eq!(
+            monitor.device().active_mode().unwrap().flags,
+            GS_CAN_MODE_LISTEN_ONLY | GS_CAN_MODE_HW_TIMESTAMP
+        );
+
+        // Alone with the sender, the monitor doesn't acknowledge
+        let timeout = Duration::from_millis(100);
+        sender
+            .send(&GsUsbFrame::with_data(0x123 | CAN_EFF_FLAG, &[1]))
+            .unwrap();
+        let error = sender.read(timeout).unwrap();
+        assert!(error.is_error_frame() && error.can_id & CAN_ERR_ACK != 0);
+        sender.stop().unwrap();
+
+        let mut sender = started(&bus);
+        let _acker = started(&bus);
+        sender.send(&GsUsbFrame::with_data(0x456, &[2])).unwrap();
+        assert!(sender.read(timeout).unwrap().is_echo_frame());
+        let frame = monitor.read(timeout).unwrap();
+        assert!(frame.is_rx_frame());
+        assert_eq!(frame.arbitration_id(), 0x456);
+        monitor.close().unwrap();
+    }
+
+    #[test]
+    fn test_listen_only_required() {
+        let mut capability = VirtualBus::default_capability();
+        capability.feature &= !GS_CAN_FEATURE_LISTEN_ONLY;
+        let mock = MockGsUsb::with_capability(capability);
+        assert!(matches!(
+            mock.open().monitor(500_000),
+            Err(GsUsbError::FeatureNotSupported("listen-only"))
+        ));
+        assert_eq!(mock.mode_flags(), 0);
+    }
+}
@@ -0,0 +1,670 @@
+//! SAE J2534 PassThru style API
+//!
+//! `PassThruChannel` mirrors the CAN subset of the J2534 PassThru API, so
+//! diagnostic applications written against PassThru devices can be ported
+//! call by call:
+//!
+//! | J2534                       | Here                                    |
+//! |-----------------------------|-----------------------------------------|
+//! | `PassThruConnect`           | `PassThruChannel::connect()`            |
+//! | `PassThruDisconnect`        | `PassThruChannel::disconnect()`         |
+//! | `PassThruReadMsgs`          | `PassThruChannel::read_msgs()`          |
+//! | `PassThruWriteMsgs`         | `PassThruChannel::write_msgs()`         |
+//! | `PassThruStartMsgFilter`    | `PassThruChannel::start_msg_filter()`   |
+//! | `PassThruStopMsgFilter`     | `PassThruChannel::stop_msg_filter()`    |
+//! | `PassThruStartPeriodicMsg`  | `PassThruChannel::start_periodic_msg()` |
+//! | `PassThruStopPeriodicMsg`   | `PassThruChannel::stop_periodic_msg()`  |
+//! | `PassThruIoctl` `LOOPBACK`, `CLEAR_MSG_FILTERS`, `CLEAR_PERIODIC_MSGS` | `set_loopback()`, `clear_msg_filters()`, `clear_periodic_msgs()` |
+//!
+//! As with J2534, messages carry the CAN ID as 4 big-endian bytes before the
+//! data, and nothing is received until a pass filter is set. Only the `CAN`
+//! protocol is supported; ISO 15765 and flow control filters are not.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use gs_usb::passthru::{FilterType, PassThruChannel, PassThruMsg, CAN};
+//! use gs_usb::GsUsb;
+//!
+//! let dev = GsUsb::scan()?.remove(0);
+//! let mut channel = PassThruChannel::connect(dev, CAN, 0, 500_000)?;
+//!
+//! // Receive responses 0x7E8-0x7EF
+//! let mask = PassThruMsg::can(0x7F8, &[]);
+//! let pattern = PassThruMsg::can(0x7E8, &[]);
+//! channel.start_msg_filter(FilterType::Pass, &mask, &pattern)?;
+//!
+//! channel.write_msgs(&[PassThruMsg::can(0x7DF, &[0x02, 0x01, 0x0D, 0, 0, 0, 0, 0])])?;
+//! for msg in channel.read_msgs(8, Duration::from_millis(100))? {
+//!     println!("{:X}: {:02X?}", msg.can_id(), msg.payload());
+//! }
+//! channel.disconnect()?;
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::constants::{
+    CAN_EFF_FLAG, CAN_EFF_MASK, CAN_MAX_DLEN, CAN_SFF_MASK, GS_CAN_MODE_NORMAL,
+};
+use crate::device::GsUsb;
+use crate::e2e::CyclicFields;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::sync::lock;
+
+/// `ProtocolID` of raw CAN
+pub const CAN: u32 = 0x05;
+
+/// Connect flag and `TxFlags`/`RxStatus` bit: 29-bit identifier
+pub const CAN_29BIT_ID: u32 = 0x0100;
+
+/// Connect flag: the channel carries both 11-bit and 29-bit identifiers
+pub const CAN_ID_BOTH: u32 = 0x0800;
+
+/// `RxStatus` bit: loopback of a message sent on this channel
+pub const TX_MSG_TYPE: u32 = 0x0001;
+
+/// Maximum number of filters per channel
+pub const MAX_FILTERS: usize = 10;
+
+/// Maximum number of periodic messages per channel
+pub const MAX_PERIODIC_MSGS: usize = 10;
+
+/// Length of the CAN ID before the data of a message
+const ID_LEN: usize = 4;
+
+/// Longest wait on the device, so periodic messages get it in between
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A PassThru message (`PASSTHRU_MSG`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassThruMsg {
+    /// Protocol, `CAN`
+    pub protocol_id: u32,
+    /// Receive status bits: `CAN_29BIT_ID`, `TX_MSG_TYPE`
+    pub rx_status: u32,
+    /// Transmit flags: `CAN_29BIT_ID`
+    pub tx_flags: u32,
+    /// Receive time in microseconds since `connect()`
+    pub timestamp: u32,
+    /// CAN ID as 4 big-endian bytes, then the data
+    pub data: Vec<u8>,
+}
+
+impl PassThruMsg {
+    /// A CAN message; IDs above 0x7FF get `CAN_29BIT_ID`
+    pub fn can(id: u32, payload: &[u8]) -> Self {
+        let mut data = id.to_be_bytes().to_vec();
+        data.extend_from_slice(payload);
+        Self {
+            protocol_id: CAN,
+            rx_status: 0,
+            tx_flags: if id > CAN_SFF_MASK { CAN_29BIT_ID } else { 0 },
+            timestamp: 0,
+            data,
+        }
+    }
+
+    /// The CAN ID in the first 4 bytes, 0 if the message is shorter
+    pub fn can_id(&self) -> u32 {
+        match self.data.get(..ID_LEN) {
+            Some(id) => u32::from_be_bytes([id[0], id[1], id[2], id[3]]),
+            None => 0,
+        }
+    }
+
+    /// The data after the CAN ID
+    pub fn payload(&self) -> &[u8] {
+        self.data.get(ID_LEN..).unwrap_or(&[])
+    }
+
+    /// Whether the message has a 29-bit identifier
+    pub fn is_29bit(&self) -> bool {
+        (self.tx_flags | self.rx_status) & CAN_29BIT_ID != 0
+    }
+
+    /// The frame to send for this message
+    fn to_frame(&self) -> Result<GsUsbFrame> {
+        if self.protocol_id != CAN {
+            return Err(invalid(format!(
+                "message protocol {:#x} on a CAN channel",
+                self.protocol_id
+            )));
+        }
+        if !(ID_LEN..=ID_LEN + CAN_MAX_DLEN).contains(&self.data.len()) {
+            return Err(invalid(format!(
+                "CAN message of {} bytes, expected 4 to 12",
+                self.data.len()
+            )));
+        }
+        let id = self.can_id();
+        let can_id = if self.is_29bit() {
+            if id > CAN_EFF_MASK {
+                return Err(invalid(format!("29-bit CAN ID {id:#x} out of range")));
+            }
+            id | CAN_EFF_FLAG
+        } else {
+            if id > CAN_SFF_MASK {
+                return Err(invalid(format!("11-bit CAN ID {id:#x} out of range")));
+            }
+            id
+        };
+        Ok(GsUsbFrame::with_data(can_id, self.payload()))
+    }
+
+    fn from_frame(frame: &GsUsbFrame, timestamp: u32) -> Self {
+        let mut msg = Self::can(frame.arbitration_id(), frame.data());
+        msg.tx_flags = 0;
+        msg.timestamp = timestamp;
+        if frame.is_extended_id() {
+            msg.rx_status |= CAN_29BIT_ID;
+        }
+        if frame.is_echo_frame() {
+            msg.rx_status |= TX_MSG_TYPE;
+        }
+        msg
+    }
+}
+
+/// Kind of a message filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    /// Receive matching messages (`PASS_FILTER`)
+    Pass,
+    /// Drop matching messages, even if a pass filter matches (`BLOCK_FILTER`)
+    Block,
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    kind: FilterType,
+    mask: Vec<u8>,
+    pattern: Vec<u8>,
+}
+
+impl Filter {
+    fn matches(&self, msg: &PassThruMsg) -> bool {
+        msg.data.len() >= self.mask.len()
+            && self
+                .mask
+                .iter()
+                .zip(&self.pattern)
+                .zip(&msg.data)
+                .all(|((mask, pattern), byte)| byte & mask == pattern & mask)
+    }
+}
+
+struct Periodic {
+    frame: GsUsbFrame,
+    interval: Duration,
+    due: Instant,
+    fields: CyclicFields,
+    counter: u8,
+}
+
+/// Thread sending the periodic messages
+struct PeriodicThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl PeriodicThread {
+    fn spawn(device: Arc<Mutex<GsUsb>>, periodic: Arc<Mutex<BTreeMap<u32, Periodic>>>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = std::thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                let now = Instant::now();
+                let mut next = now + POLL_INTERVAL;
+                for msg in lock(&periodic).values_mut() {
+                    if msg.due <= now {
+                        let len = msg.frame.data_length();
+                        let sent = msg
+                            .fields
+                            .stamp(&mut msg.frame.data[..len], msg.counter)
+                            .and_then(|()| lock(&device).send(&msg.frame));
+                        msg.counter = msg.fields.next_counter(msg.counter);
+                        if let Err(e) = sent {
+                            log::warn!("Periodic message 0x{:X}: {e}", msg.frame.can_id);
+                        }
+                        msg.due += msg.interval;
+                        if msg.due < now {
+                            // Fell behind, don't send a burst to catch up
+                            msg.due = now + msg.interval;
+                        }
+                    }
+                    next = next.min(msg.due);
+                }
+                std::thread::sleep(next.saturating_duration_since(Instant::now()));
+            }
+        });
+        Self { stop, handle }
+    }
+
+    fn join(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.handle.join().is_err() {
+            log::warn!("Periodic message thread panicked");
+        }
+    }
+}
+
+/// A connected CAN channel, as opened by `PassThruConnect`
+pub struct PassThruChannel {
+    device: Arc<Mutex<GsUsb>>,
+    flags: u32,
+    connected: Instant,
+    loopback: bool,
+    filters: BTreeMap<u32, Filter>,
+    periodic: Arc<Mutex<BTreeMap<u32, Periodic>>>,
+    periodic_thread: Option<PeriodicThread>,
+    next_id: u32,
+}
+
+impl PassThruChannel {
+    /// Start `dev` at `baudrate`
+    ///
+    /// `flags` are `CAN_29BIT_ID` for a channel of 29-bit messages, or
+    /// `CAN_ID_BOTH`; otherwise messages have 11-bit IDs.
+    pub fn connect(mut dev: GsUsb, protocol_id: u32, flags: u32, baudrate: u32) -> Result<Self> {
+        if protocol_id != CAN {
+            return Err(GsUsbError::FeatureNotSupported(
+                "J2534 protocols other than CAN",
+            ));
+        }
+        if flags & !(CAN_29BIT_ID | CAN_ID_BOTH) != 0 {
+            return Err(invalid(format!("unsupported connect flags {flags:#x}")));
+        }
+        dev.set_bitrate(baudrate)?;
+        dev.start(GS_CAN_MODE_NORMAL)?;
+        let device = Arc::new(Mutex::new(dev));
+        let periodic = Arc::new(Mutex::new(BTreeMap::new()));
+        Ok(Self {
+            periodic_thread: Some(PeriodicThread::spawn(
+                Arc::clone(&device),
+                Arc::clone(&periodic),
+            )),
+            device,
+            flags,
+            connected: Instant::now(),
+            loopback: false,
+            filters: BTreeMap::new(),
+            periodic,
+            next_id: 0,
+        })
+    }
+
+    /// Stop periodic messages and the channel, and give the device back
+    pub fn disconnect(mut self) -> Result<GsUsb> {
+        if let Some(thread) = self.periodic_thread.take() {
+            thread.join();
+        }
+        let device = Arc::clone(&self.device);
+        drop(self);
+        let mut dev = match Arc::try_unwrap(device) {
+            Ok(device) => device.into_inner().unwrap_or_else(|e| e.into_inner()),
+            Err(_) => unreachable!("the periodic message thread has ended"),
+        };
+        dev.stop()?;
+        Ok(dev)
+    }
+
+    /// Also receive the messages sent on this channel, marked `TX_MSG_TYPE`
+    ///
+    /// As the `LOOPBACK` configuration parameter; off after connecting.
+    pub fn set_loopback(&mut self, enabled: bool) {
+        self.loopback = enabled;
+    }
+
+    /// Receive up to `max` messages, waiting up to `timeout` for them
+    ///
+    /// Returns the messages received so far when the timeout expires, which
+    /// may be none; a zero timeout returns only what is already waiting.
+    pub fn read_msgs(&mut self, max: usize, timeout: Duration) -> Result<Vec<PassThruMsg>> {
+        let deadline = Instant::now() + timeout;
+        let mut msgs = Vec::new();
+        while msgs.len() < max {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // A zero timeout would wait forever
+            let wait = remaining.clamp(Duration::from_millis(1), POLL_INTERVAL);
+            match lock(&self.device).read(wait) {
+                Ok(frame) => msgs.extend(self.accept(&frame)),
+                Err(GsUsbError::ReadTimeout) if remaining.is_zero() => break,
+                Err(GsUsbError::ReadTimeout) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msgs)
+    }
+
+    /// Send messages, returning how many were sent
+    ///
+    /// Stops at the first invalid message or send error; the error is
+    /// returned if no message was sent.
+    pub fn write_msgs(&mut self, msgs: &[PassThruMsg]) -> Result<usize> {
+        for (sent, msg) in msgs.iter().enumerate() {
+            let result = self
+                .frame_for(msg)
+                .and_then(|frame| lock(&self.device).send(&frame));
+            if let Err(e) = result {
+                return if sent == 0 { Err(e) } else { Ok(sent) };
+            }
+        }
+        Ok(msgs.len())
+    }
+
+    /// Add a filter on received messages, returning its ID
+    ///
+    /// A message matches if its first bytes (CAN ID, then data) equal
+    /// `pattern` in the bits set in `mask`. Messages are received if a pass
+    /// filter and no block filter match.
+    pub fn start_msg_filter(
+        &mut self,
+        kind: FilterType,
+        mask: &PassThruMsg,
+        pattern: &PassThruMsg,
+    ) -> Result<u32> {
+        if mask.data.len() != pattern.data.len()
+            || !(1..=ID_LEN + CAN_MAX_DLEN).contains(&mask.data.len())
+        {
+            return Err(invalid(format!(
+                "filter mask and pattern of {} and {} bytes, expected the same 1 to 12",
+                mask.data.len(),
+                pattern.data.len()
+            )));
+        }
+        if self.filters.len() >= MAX_FILTERS {
+            return Err(invalid(format!("more than {MAX_FILTERS} filters")));
+        }
+        let id = self.next_id();
+        self.filters.insert(
+            id,
+            Filter {
+                kind,
+                mask: mask.data.clone(),
+                pattern: pattern.data.clone(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Remove a filter
+    pub fn stop_msg_filter(&mut self, filter_id: u32) -> Result<()> {
+        self.filters
+            .remove(&filter_id)
+            .map(|_| ())
+            .ok_or_else(|| invalid(format!("no filter {filter_id}")))
+    }
+
+    /// Remove all filters
+    pub fn clear_msg_filters(&mut self) {
+        self.filters.clear();
+    }
+
+    /// Send `msg` every `interval`, returning the periodic message's ID
+    ///
+    /// The interval is 5 ms to 65.535 s, as in J2534.
+    pub fn start_periodic_msg(&mut self, msg: &PassThruMsg, interval: Duration) -> Result<u32> {
+        self.start_periodic_msg_with_fields(msg, interval, CyclicFields::default())
+    }
+
+    /// Send `msg` every `interval` like `start_periodic_msg()`, updating an
+    /// alive counter and a checksum in it before every transmission
+    ///
+    /// The counter starts at 0. Not part of J2534, but needed to keep ECUs
+    /// from flagging simulated messages as faulty.
+    pub fn start_periodic_msg_with_fields(
+        &mut self,
+        msg: &PassThruMsg,
+        interval: Duration,
+        fields: CyclicFields,
+    ) -> Result<u32> {
+        if !(Duration::from_millis(5)..=Duration::from_millis(65_535)).contains(&interval) {
+            return Err(invalid(format!(
+                "periodic interval {interval:?} outside 5 to 65535 ms"
+            )));
+        }
+        let frame = self.frame_for(msg)?;
+        if frame.data_length() < fields.min_len() {
+            return Err(invalid(format!(
+                "periodic message of {} bytes, at least {} needed for its fields",
+                frame.data_length(),
+                fields.min_len()
+            )));
+        }
+        if lock(&self.periodic).len() >= MAX_PERIODIC_MSGS {
+            return Err(invalid(format!(
+                "more than {MAX_PERIODIC_MSGS} periodic messages"
+            )));
+        }
+        let id = self.next_id();
+        lock(&self.periodic).insert(
+            id,
+            Periodic {
+                frame,
+                interval,
+                due: Instant::now(),
+                fields,
+                counter: 0,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Stop a periodic message
+    pub fn stop_periodic_msg(&mut self, msg_id: u32) -> Result<()> {
+        lock(&self.periodic)
+            .remove(&msg_id)
+            .map(|_| ())
+            .ok_or_else(|| invalid(format!("no periodic message {msg_id}")))
+    }
+
+    /// Stop all periodic messages
+    pub fn clear_periodic_msgs(&mut self) {
+        lock(&self.periodic).clear();
+    }
+
+    /// The frame for `msg`, checking its ID size against the connect flags
+    fn frame_for(&self, msg: &PassThruMsg) -> Result<GsUsbFrame> {
+        if self.flags & CAN_ID_BOTH == 0 && msg.is_29bit() != (self.flags & CAN_29BIT_ID != 0) {
+            return Err(invalid(format!(
+                "{}-bit message on a channel of {}-bit IDs",
+                if msg.is_29bit() { 29 } else { 11 },
+                if self.flags & CAN_29BIT_ID != 0 {
+                    29
+                } else {
+                    11
+                }
+            )));
+        }
+        msg.to_frame()
+    }
+
+    /// The message for a received frame, if the channel passes it on
+    fn accept(&self, frame: &GsUsbFrame) -> Option<PassThruMsg> {
+        if frame.channel != 0 || frame.is_error_frame() || frame.is_fd() {
+            return None;
+        }
+        let timestamp = frame.host_instant().map_or(frame.timestamp_us, |time| {
+            time.saturating_duration_since(self.connected).as_micros() as u32
+        });
+        let msg = PassThruMsg::from_frame(frame, timestamp);
+        if frame.is_echo_frame() {
+            return self.loopback.then_some(msg);
+        }
+        if self.flags & CAN_ID_BOTH == 0 && msg.is_29bit() != (self.flags & CAN_29BIT_ID != 0) {
+            return None;
+        }
+        let mut pass = false;
+        for filter in self.filters.values().filter(|filter| filter.matches(&msg)) {
+            match filter.kind {
+                FilterType::Block => return None,
+                FilterType::Pass => pass = true,
+            }
+        }
+        pass.then_some(msg)
+    }
+
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+impl Drop for PassThruChannel {
+    fn drop(&mut self) {
+        if let Some(thread) = self.periodic_thread.take() {
+            thread.join();
+        }
+    }
+}
+
+fn invalid(reason: String) -> GsUsbError {
+    GsUsbError::InvalidConfig(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_bus::VirtualBus;
+
+    fn ids(msgs: &[PassThruMsg]) -> Vec<u32> {
+        msgs.iter().map(PassThruMsg::can_id).collect()
+    }
+
+    #[test]
+    fn test_filters_and_loopback() {
+        let bus = VirtualBus::new();
+        let mut a = PassThruChannel::connect(bus.open(), CAN, 0, 500_000).unwrap();
+        let mut b = PassThruChannel::connect(bus.open(), CAN, CAN_ID_BOTH, 500_000).unwrap();
+        let timeout = Duration::from_millis(20);
+
+        // Nothing is received without a pass filter
+        a.write_msgs(&[PassThruMsg::can(0x7E8, &[1])]).unwrap();
+        assert!(b.read_msgs(10, timeout).unwrap().is_empty());
+
+        let pass = b
+            .start_msg_filter(
+                FilterType::Pass,
+                &PassThruMsg::can(0x7F8, &[]),
+                &PassThruMsg::can(0x7E8, &[]),
+            )
+            .unwrap();
+        b.start_msg_filter(
+            FilterType::Block,
+            &PassThruMsg::can(0x7FF, &[0xFF]),
+            &PassThruMsg::can(0x7EF, &[0x10]),
+        )
+        .unwrap();
+        let msgs = [
+            PassThruMsg::can(0x7E8, &[1, 2]),
+            PassThruMsg::can(0x7EF, &[0x10]),
+            PassThruMsg::can(0x7EF, &[0x11]),
+            PassThruMsg::can(0x100, &[]),
+        ];
+        assert_eq!(a.write_msgs(&msgs).unwrap(), 4);
+        let received = b.read_msgs(10, timeout).unwrap();
+        assert_eq!(ids(&received), [0x7E8, 0x7EF]);
+        assert_eq!(received[0].payload(), [1, 2]);
+        assert_eq!(received[1].payload(), [0x11]);
+        assert_eq!(received[0].rx_status, 0);
+
+        // The channel of 11-bit IDs refuses 29-bit messages
+        assert!(a.write_msgs(&[PassThruMsg::can(0x18DA_F110, &[])]).is_err());
+        assert!(a.write_msgs(&[PassThruMsg::can(0x123, &[0; 9])]).is_err());
+
+        // Echoes of the messages sent so far are dropped
+        assert!(a.read_msgs(10, Duration::ZERO).unwrap().is_empty());
+        a.set_loopback(true);
+        a.write_msgs(&[PassThruMsg::can(0x123, &[])]).unwrap();
+        let looped = a.read_msgs(1, timeout).unwrap();
+        assert_eq!(ids(&looped), [0x123]);
+        assert_eq!(looped[0].rx_status, TX_MSG_TYPE);
+
+        b.stop_msg_filter(pass).unwrap();
+        assert!(b.stop_msg_filter(pass).is_err());
+    }
+
+    #[test]
+    fn test_periodic_msgs() {
+        let bus = VirtualBus::new();
+        let mut a = PassThruChannel::connect(bus.open(), CAN, CAN_29BIT_ID, 500_000).unwrap();
+        let mut b = PassThruChannel::connect(bus.open(), CAN, CAN_ID_BOTH, 500_000).unwrap();
+        let all = PassThruMsg::can(0, &[]);
+        b.start_msg_filter(FilterType::Pass, &all, &all).unwrap();
+
+        let msg = PassThruMsg::can(0x18DA_F110, &[0x3E, 0x80]);
+        assert!(a
+            .start_periodic_msg(&msg, Duration::from_millis(4))
+            .is_err());
+        let id = a
+            .start_periodic_msg(&msg, Duration::from_millis(5))
+            .unwrap();
+        let received = b.read_msgs(5, Duration::from_secs(1)).unwrap();
+        assert_eq!(received.len(), 5);
+        assert!(received
+            .iter()
+            .all(|msg| msg.can_id() == 0x18DA_F110 && msg.rx_status == CAN_29BIT_ID));
+
+        a.stop_periodic_msg(id).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        b.read_msgs(100, Duration::ZERO).unwrap();
+        assert!(b
+            .read_msgs(1, Duration::from_millis(20))
+            .unwrap()
+            .is_empty());
+        a.disconnect().unwrap();
+    }
+
+    #[test]
+    fn test_periodic_fields() {
+        use crate::e2e::{Checksum, ChecksumField, CounterField};
+
+        let bus = VirtualBus::new();
+        let mut a = PassThruChannel::connect(bus.open(), CAN, 0, 500_000).unwrap();
+        let mut b = PassThruChannel::connect(bus.open(), CAN, 0, 500_000).unwrap();
+        let all = PassThruMsg::can(0, &[]);
+        b.start_msg_filter(FilterType::Pass, &all, &all).unwrap();
+
+        // Counter 0-2 in the high nibble of byte 0, sum of bytes 0-2 in byte 3
+        let fields = CyclicFields {
+            counter: Some(CounterField {
+                offset: 4,
+                bits: 4,
+                max: 2,
+            }),
+            checksum: Some(ChecksumField {
+                checksum: Checksum::Sum8,
+                offset: 3,
+                range: 0..3,
+            }),
+        };
+        let msg = PassThruMsg::can(0x123, &[0x01, 0x10, 0x20, 0x00]);
+        let short = PassThruMsg::can(0x123, &[0x01, 0x10, 0x20]);
+        let interval = Duration::from_millis(5);
+        assert!(a
+            .start_periodic_msg_with_fields(&short, interval, fields.clone())
+            .is_err());
+        a.start_periodic_msg_with_fields(&msg, interval, fields)
+            .unwrap();
+
+        let received = b.read_msgs(4, Duration::from_secs(1)).unwrap();
+        let payloads: Vec<_> = received.iter().map(PassThruMsg::payload).collect();
+        assert_eq!(
+            payloads,
+            [
+                [0x01, 0x10, 0x20, 0x31],
+                [0x11, 0x10, 0x20, 0x41],
+                [0x21, 0x10, 0x20, 0x51],
+                [0x01, 0x10, 0x20, 0x31],
+            ]
+        );
+        a.disconnect().unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+//! Platform-specific diagnosis of USB access failures
+//!
+//! Opening or claiming an adapter usually fails for reasons that depend on
+//! the host OS rather than the device: a missing udev rule on Linux, a device
+//! not bound to WinUSB on Windows, a sandboxed app on macOS. `diagnose()`
+//! maps the raw `rusb::Error` of such a failure to a `PlatformIssue`, which
+//! `GsUsb::find()` and `GsUsb::start()` return as `GsUsbError::Platform`.
+//!
+//! A missing WinUSB binding comes with the hardware ID Windows matches driver
+//! packages against, so an installer can bind a driver package to it or
+//! point the user at the right device in Zadig.
+
+use crate::error::GsUsbError;
+use crate::transport::GsUsbInterface;
+
+/// Likely host-side cause of a failed open or claim
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlatformIssue {
+    /// Linux: the user may not open the device node (no udev rule)
+    UdevPermission {
+        /// Device node, e.g. `/dev/bus/usb/001/007`
+        path: String,
+    },
+    /// Windows: the device is not bound to the WinUSB driver
+    MissingWinUsbDriver {
+        /// Hardware ID of the gs_usb function, e.g. `USB\VID_1D50&PID_606F`,
+        /// or `USB\VID_1D50&PID_606F&MI_02` for interface 2 of a composite
+        /// device; `None` if the descriptors couldn't be read
+        ///
+        /// Identical adapters share it, so it names a kind of device, not a
+        /// device instance.
+        hardware_id: Option<String>,
+    },
+    /// macOS: access denied, typically by the app sandbox
+    MacOsAccessDenied,
+    /// The interface is claimed by another driver or process
+    InterfaceBusy,
+    /// macOS: another process has the device open for exclusive access
+    ExclusiveAccess,
+}
+
+impl std::fmt::Display for PlatformIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlatformIssue::UdevPermission { path } => write!(
+                f,
+                "no permission to open {path}; install a udev rule for the adapter \
+                 (see README, \"Linux Permissions\")"
+            ),
+            PlatformIssue::MissingWinUsbDriver { hardware_id } => {
+                write!(f, "the adapter ")?;
+                if let Some(id) = hardware_id {
+                    write!(f, "({id}) ")?;
+                }
+                write!(
+                    f,
+                    "is not using the WinUSB driver; install it with Zadig \
+                     or update the adapter firmware"
+                )
+            }
+            PlatformIssue::MacOsAccessDenied => write!(
+                f,
+                "access denied; sandboxed apps need the \
+                 com.apple.security.device.usb entitlement"
+            ),
+            PlatformIssue::ExclusiveAccess => write!(
+                f,
+                "another program has the adapter open for exclusive access; close it \
+                 or wait for it to let go (see GsUsb::set_claim_wait())"
+            ),
+            PlatformIssue::InterfaceBusy => write!(
+                f,
+                "the adapter is in use by another driver or program \
+                 (e.g. the gs_usb kernel module or a SocketCAN interface)"
+            ),
+        }
+    }
+}
+
+/// Diagnose a failed open or claim of the device at `bus`/`address`
+///
+/// Returns `None` if the error has no known platform-specific cause.
+pub fn diagnose(error: rusb::Error, bus: u8, address: u8) -> Option<PlatformIssue> {
+    diagnose_on(std::env::consts::OS, error, bus, address, || {
+        windows_hardware_id(bus, address)
+    })
+}
+
+/// Turn a failed open or claim into `GsUsbError::Platform` if it can be
+/// diagnosed, or into `fallback` otherwise
+pub(crate) fn access_error(
+    error: rusb::Error,
+    bus: u8,
+    address: u8,
+    fallback: fn(rusb::Error) -> GsUsbError,
+) -> GsUsbError {
+    match diagnose(error, bus, address) {
+        Some(issue) => GsUsbError::Platform {
+            issue,
+            source: error,
+        },
+        None => fallback(error),
+    }
+}
+
+/// Hardware ID of a device, with the interface of a composite device
+fn hardware_id(vendor_id: u16, product_id: u16, interface: Option<u8>) -> String {
+    let mut id = format!("USB\\VID_{vendor_id:04X}&PID_{product_id:04X}");
+    if let Some(number) = interface {
+        id.push_str(&format!("&MI_{number:02X}"));
+    }
+    id
+}
+
+/// Hardware ID of the device at `bus`/`address`, with the interface
+/// `GsUsbInterface::find()` picks if it is composite
+fn windows_hardware_id(bus: u8, address: u8) -> Option<String> {
+    let device = rusb::devices()
+        .ok()?
+        .iter()
+        .find(|d| d.bus_number() == bus && d.address() == address)?;
+    let desc = device.device_descriptor().ok()?;
+    // Without a driver the device usually can't be opened to read the
+    // interface names; the interfaces are then chosen by class and endpoints
+    let handle = device.open().ok();
+    let interface = device
+        .active_config_descriptor()
+        .ok()
+        .filter(|config| config.num_interfaces() > 1)
+        .map(|config| {
+            GsUsbInterface::find_in(&config, |index| {
+                handle.as_ref()?.read_string_descriptor_ascii(index).ok()
+            })
+            .unwrap_or_default()
+            .number
+        });
+    Some(hardware_id(desc.vendor_id(), desc.product_id(), interface))
+}
+
+/// Turn a failed claim into `GsUsbError::Platform` if it can be diagnosed,
+/// or into `GsUsbError::ClaimInterface` otherwise
+///
+/// On macOS, claims fail with `Access` when another process has the device
+/// open for exclusive access, not for lack of permission as when opening.
+pub(crate) fn claim_error(error: rusb::Error, bus: u8, address: u8) -> GsUsbError {
+    match diagnose_claim_on(std::env::consts::OS, error) {
+        Some(issue) => GsUsbError::Platform {
+            issue,
+            source: error,
+        },
+        None => access_error(error, bus, address, GsUsbError::ClaimInterface),
+    }
+}
+
+fn diagnose_claim_on(os: &str, error: rusb::Error) -> Option<PlatformIssue> {
+    match (os, error) {
+        ("macos", rusb::Error::Access) => Some(PlatformIssue::ExclusiveAccess),
+        _ => None,
+    }
+}
+
+fn diagnose_on(
+    os: &str,
+    error: rusb::Error,
+    bus: u8,
+    address: u8,
+    hardware_id: impl FnOnce() -> Option<String>,
+) -> Option<PlatformIssue> {
+    match (os, error) {
+        (_, rusb::Error::Busy) => Some(PlatformIssue::InterfaceBusy),
+        ("linux" | "android", rusb::Error::Access) => Some(PlatformIssue::UdevPermission {
+            path: format!("/dev/bus/usb/{bus:03}/{address:03}"),
+        }),
+        ("windows", rusb::Error::NotSupported | rusb::Error::NotFound) => {
+            Some(PlatformIssue::MissingWinUsbDriver {
+                hardware_id: hardware_id(),
+            })
+        }
+        ("windows", rusb::Error::Access) => Some(PlatformIssue::InterfaceBusy),
+        ("macos", rusb::Error::Access) => Some(PlatformIssue::MacOsAccessDenied),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnose_on(os: &str, error: rusb::Error, bus: u8, address: u8) -> Option<PlatformIssue> {
+        super::diagnose_on(os, error, bus, address, || {
+            Some(hardware_id(0x1D50, 0x606F, None))
+        })
+    }
+
+    #[test]
+    fn test_diagnose() {
+        assert_eq!(
+            diagnose_on("linux", rusb::Error::Access, 1, 7),
+            Some(PlatformIssue::UdevPermission {
+                path: "/dev/bus/usb/001/007".into()
+            })
+        );
+        assert_eq!(
+            diagnose_on("windows", rusb::Error::NotSupported, 1, 7),
+            Some(PlatformIssue::MissingWinUsbDriver {
+                hardware_id: Some(r"USB\VID_1D50&PID_606F".into())
+            })
+        );
+        assert_eq!(
+            diagnose_on("macos", rusb::Error::Access, 1, 7),
+            Some(PlatformIssue::MacOsAccessDenied)
+        );
+        assert_eq!(
+            diagnose_on("linux", rusb::Error::Busy, 1, 7),
+            Some(PlatformIssue::InterfaceBusy)
+        );
+        assert_eq!(diagnose_on("linux", rusb::Error::Pipe, 1, 7), None);
+    }
+
+    #[test]
+    fn test_diagnose_claim() {
+        assert_eq!(
+            diagnose_claim_on("macos", rusb::Error::Access),
+            Some(PlatformIssue::ExclusiveAccess)
+        );
+        assert_eq!(diagnose_claim_on("linux", rusb::Error::Access), None);
+        assert_eq!(diagnose_claim_on("macos", rusb::Error::Busy), None);
+    }
+
+    #[test]
+    fn test_missing_winusb_driver() {
+        assert_eq!(
+            hardware_id(0x1D50, 0x606F, Some(2)),
+            r"USB\VID_1D50&PID_606F&MI_02"
+        );
+        let issue = PlatformIssue::MissingWinUsbDriver {
+            hardware_id: Some(hardware_id(0x1209, 0x2323, None)),
+        };
+        assert!(issue
+            .to_string()
+            .starts_with(r"the adapter (USB\VID_1209&PID_2323) is not using"));
+        let issue = PlatformIssue::MissingWinUsbDriver { hardware_id: None };
+        assert!(issue.to_string().starts_with("the adapter is not using"));
+    }
+}
@@ -0,0 +1,44 @@
+//! The types, traits and constants most programs need
+//!
+//! ```no_run
+//! use gs_usb::prelude::*;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)?;
+//! dev.send(&GsUsbFrame::with_data(0x123 | CAN_EFF_FLAG, &[1, 2, 3]))?;
+//! # Ok::<(), GsUsbError>(())
+//! ```
+
+pub use crate::bitrate::{Bitrate, SamplePoint};
+pub use crate::builder::GsUsbBuilder;
+pub use crate::config::{Config, IdFilter, ModeConfig};
+pub use crate::device::GsUsb;
+pub use crate::error::{ErrorKind, GsUsbError};
+pub use crate::error_frame::CanErrorFrame;
+pub use crate::frame::GsUsbFrame;
+pub use crate::id::CanId;
+pub use crate::shared::SharedGsUsb;
+pub use crate::structures::{DeviceCapability, DeviceInfo, DeviceState};
+pub use crate::timestamp::TimestampSource;
+pub use crate::transport::Transport;
+
+#[cfg(feature = "async")]
+pub use crate::asynchronous::{AsyncCan, AsyncGsUsb};
+#[cfg(feature = "embedded-can")]
+pub use embedded_can::{blocking::Can as _, Frame as _};
+
+pub use crate::constants::{
+    CAN_EFF_FLAG, CAN_EFF_MASK, CAN_ERR_FLAG, CAN_ERR_MASK, CAN_RTR_FLAG, CAN_SFF_MASK,
+    GS_CAN_FEATURE_BERR_REPORTING, GS_CAN_FEATURE_BT_CONST_EXT, GS_CAN_FEATURE_FD,
+    GS_CAN_FEATURE_GET_STATE, GS_CAN_FEATURE_HW_TIMESTAMP, GS_CAN_FEATURE_IDENTIFY,
+    GS_CAN_FEATURE_LISTEN_ONLY, GS_CAN_FEATURE_LOOP_BACK, GS_CAN_FEATURE_ONE_SHOT,
+    GS_CAN_FEATURE_PAD_PKTS_TO_MAX_PKT_SIZE, GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX,
+    GS_CAN_FEATURE_TERMINATION, GS_CAN_FEATURE_TRIPLE_SAMPLE, GS_CAN_FEATURE_USER_ID,
+    GS_CAN_FLAG_BRS, GS_CAN_FLAG_ESI, GS_CAN_FLAG_FD, GS_CAN_FLAG_OVERFLOW,
+    GS_CAN_MODE_BERR_REPORTING, GS_CAN_MODE_FD, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_IDENTIFY,
+    GS_CAN_MODE_LISTEN_ONLY, GS_CAN_MODE_LOOP_BACK, GS_CAN_MODE_NORMAL, GS_CAN_MODE_ONE_SHOT,
+    GS_CAN_MODE_PAD_PKTS_TO_MAX_PKT_SIZE, GS_CAN_MODE_TRIPLE_SAMPLE, GS_CAN_MODE_USER_ID,
+    GS_CAN_STATE_BUS_OFF, GS_CAN_STATE_ERROR_ACTIVE, GS_CAN_STATE_ERROR_PASSIVE,
+    GS_CAN_STATE_ERROR_WARNING, GS_CAN_STATE_SLEEPING, GS_CAN_STATE_STOPPED,
+};
@@ -0,0 +1,214 @@
+//! Known firmware bugs and their workarounds
+//!
+//! Some adapters misreport their features or deviate from the protocol in
+//! ways the driver can work around once it knows about them. `KNOWN_QUIRKS`
+//! lists them by USB vendor and product ID, product string and firmware
+//! version (the `sw_version` of DEVICE_CONFIG, `DeviceInfo::fw_version`).
+//! `GsUsb` looks its device up the first time it reads the capability and
+//! adjusts the reported features accordingly, so applications see a device
+//! that behaves as documented. `GsUsb::set_quirks()` replaces the looked up
+//! quirks, e.g. for an adapter not listed yet.
+
+use std::ops::RangeInclusive;
+
+use crate::constants::{
+    GS_CAN_FEATURE_BT_CONST_EXT, GS_CAN_FEATURE_IDENTIFY, GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX,
+    GS_USB_ID_PRODUCT, GS_USB_ID_VENDOR,
+};
+use crate::structures::DeviceCapability;
+
+/// Workarounds for one device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Needs the LPC546xx USB workaround (padded TX transfers) without
+    /// reporting `GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX`
+    pub usb_quirk_lpc546xx: bool,
+    /// Takes DATA_BITTIMING with the request number candleLight uses for
+    /// GET_USER_ID
+    pub cantact_pro_data_bittiming: bool,
+    /// Reports `GS_CAN_FEATURE_IDENTIFY` without implementing it
+    pub broken_identify: bool,
+    /// Answers BT_CONST_EXT with wrong limits; the data phase is then
+    /// timed within the BT_CONST limits
+    pub broken_bt_const_ext: bool,
+    /// Rate of the timestamp counter if it isn't the specified 1 MHz
+    pub timestamp_tick_hz: Option<u32>,
+}
+
+impl Quirks {
+    /// No workarounds
+    pub const NONE: Self = Self {
+        usb_quirk_lpc546xx: false,
+        cantact_pro_data_bittiming: false,
+        broken_identify: false,
+        broken_bt_const_ext: false,
+        timestamp_tick_hz: None,
+    };
+
+    /// Check if no workaround is needed
+    pub fn is_none(&self) -> bool {
+        *self == Self::NONE
+    }
+
+    /// Workarounds of both
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            usb_quirk_lpc546xx: self.usb_quirk_lpc546xx || other.usb_quirk_lpc546xx,
+            cantact_pro_data_bittiming: self.cantact_pro_data_bittiming
+                || other.cantact_pro_data_bittiming,
+            broken_identify: self.broken_identify || other.broken_identify,
+            broken_bt_const_ext: self.broken_bt_const_ext || other.broken_bt_const_ext,
+            timestamp_tick_hz: self.timestamp_tick_hz.or(other.timestamp_tick_hz),
+        }
+    }
+
+    /// Correct the feature flags of a reported capability
+    pub fn apply(&self, capability: &mut DeviceCapability) {
+        if self.usb_quirk_lpc546xx {
+            capability.feature |= GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX;
+        }
+        if self.broken_identify {
+            capability.feature &= !GS_CAN_FEATURE_IDENTIFY;
+        }
+        if self.broken_bt_const_ext {
+            capability.feature &= !GS_CAN_FEATURE_BT_CONST_EXT;
+        }
+    }
+}
+
+impl std::fmt::Display for Quirks {
+    /// Names of the workarounds, e.g. "USB_QUIRK_LPC546XX BROKEN_IDENTIFY"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<String> = [
+            (self.usb_quirk_lpc546xx, "USB_QUIRK_LPC546XX"),
+            (
+                self.cantact_pro_data_bittiming,
+                "CANTACT_PRO_DATA_BITTIMING",
+            ),
+            (self.broken_identify, "BROKEN_IDENTIFY"),
+            (self.broken_bt_const_ext, "BROKEN_BT_CONST_EXT"),
+        ]
+        .into_iter()
+        .filter(|&(set, _)| set)
+        .map(|(_, name)| name.to_string())
+        .collect();
+        if let Some(tick_hz) = self.timestamp_tick_hz {
+            names.push(format!("TIMESTAMP_TICK_HZ={tick_hz}"));
+        }
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(" "))
+        }
+    }
+}
+
+/// A device and firmware versions that need workarounds
+#[derive(Debug, Clone)]
+pub struct QuirkEntry {
+    /// USB vendor and product ID, any device if `None`
+    pub usb_id: Option<(u16, u16)>,
+    /// USB product string, any product if `None`
+    pub product: Option<&'static str>,
+    /// Affected firmware versions
+    pub fw_versions: RangeInclusive<u32>,
+    /// Workarounds
+    pub quirks: Quirks,
+    /// What goes wrong without them
+    pub reason: &'static str,
+}
+
+impl QuirkEntry {
+    /// Check if the entry covers a device
+    pub fn matches(
+        &self,
+        usb_id: Option<(u16, u16)>,
+        product: Option<&str>,
+        fw_version: u32,
+    ) -> bool {
+        self.usb_id.is_none_or(|id| usb_id == Some(id))
+            && self.product.is_none_or(|name| product == Some(name))
+            && self.fw_versions.contains(&fw_version)
+    }
+}
+
+/// Devices known to need workarounds, as the Linux gs_usb driver handles them
+pub const KNOWN_QUIRKS: &[QuirkEntry] = &[
+    QuirkEntry {
+        usb_id: Some((GS_USB_ID_VENDOR, GS_USB_ID_PRODUCT)),
+        product: Some("CANtact Pro"),
+        fw_versions: 0..=2,
+        quirks: Quirks {
+            usb_quirk_lpc546xx: true,
+            cantact_pro_data_bittiming: true,
+            ..Quirks::NONE
+        },
+        reason: "LPC54616 affected by the LPC546xx USB erratum, DATA_BITTIMING \
+                 on the GET_USER_ID request",
+    },
+    QuirkEntry {
+        usb_id: None,
+        product: None,
+        fw_versions: 0..=1,
+        quirks: Quirks {
+            broken_identify: true,
+            ..Quirks::NONE
+        },
+        reason: "IDENTIFY is only implemented from firmware version 2 on",
+    },
+];
+
+/// Workarounds `KNOWN_QUIRKS` lists for a device
+pub fn lookup(usb_id: Option<(u16, u16)>, product: Option<&str>, fw_version: u32) -> Quirks {
+    KNOWN_QUIRKS
+        .iter()
+        .filter(|entry| entry.matches(usb_id, product, fw_version))
+        .fold(Quirks::NONE, |quirks, entry| quirks.merge(entry.quirks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_bus::VirtualBus;
+
+    #[test]
+    fn test_lookup() {
+        let gs_usb = Some((GS_USB_ID_VENDOR, GS_USB_ID_PRODUCT));
+        let cantact = lookup(gs_usb, Some("CANtact Pro"), 2);
+        assert!(cantact.usb_quirk_lpc546xx && cantact.cantact_pro_data_bittiming);
+        assert!(!cantact.broken_identify);
+        assert!(lookup(gs_usb, Some("CANtact Pro"), 3).is_none());
+        assert!(lookup(gs_usb, Some("candleLight USB to CAN adapter"), 2).is_none());
+
+        let old = lookup(None, None, 1);
+        assert_eq!(
+            old,
+            Quirks {
+                broken_identify: true,
+                ..Quirks::NONE
+            }
+        );
+        assert_eq!(old.to_string(), "BROKEN_IDENTIFY");
+        assert_eq!(Quirks::NONE.to_string(), "none");
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut capability = VirtualBus::default_capability();
+        Quirks {
+            usb_quirk_lpc546xx: true,
+            broken_identify: true,
+            broken_bt_const_ext: true,
+            ..Quirks::NONE
+        }
+        .apply(&mut capability);
+        assert_ne!(
+            capability.feature & GS_CAN_FEATURE_REQ_USB_QUIRK_LPC546XX,
+            0
+        );
+        assert_eq!(
+            capability.feature & (GS_CAN_FEATURE_IDENTIFY | GS_CAN_FEATURE_BT_CONST_EXT),
+            0
+        );
+    }
+}
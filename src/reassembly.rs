@@ -0,0 +1,489 @@
+//! Multi-frame reassembly
+//!
+//! Transport protocols carry payloads longer than a frame in a first frame
+//! announcing the length, followed by consecutive frames with a sequence
+//! counter. ISO-TP, J1939 TP and NMEA 2000 fast packets differ only in where
+//! they keep the length and the counter. A `Framing` describes that, and a
+//! `Reassembler` does the rest: it follows concurrent transfers by key,
+//! checks the sequence, drops transfers that stall and reports each
+//! completed payload.
+//!
+//! Only the receiving side is covered; flow control, as in ISO-TP, must be
+//! sent by the caller.
+//!
+//! # Example
+//!
+//! ISO-TP with normal addressing, without flow control:
+//!
+//! ```no_run
+//! use gs_usb::{Framing, GsUsb, GsUsbFrame, Reassembler, Reassembly, Segment, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! struct IsoTp;
+//!
+//! impl Framing for IsoTp {
+//!     type Key = u32;
+//!
+//!     fn classify<'a>(&self, frame: &'a GsUsbFrame) -> Segment<'a, u32> {
+//!         let (key, data) = (frame.can_id, frame.data());
+//!         match data.first().map(|pci| pci >> 4) {
+//!             Some(0) => Segment::Single { key, data: &data[1..=usize::from(data[0]).min(data.len() - 1)] },
+//!             Some(1) if data.len() > 2 => Segment::First {
+//!                 key,
+//!                 total_len: usize::from(data[0] & 0x0F) << 8 | usize::from(data[1]),
+//!                 seq: 0,
+//!                 data: &data[2..],
+//!             },
+//!             Some(2) => Segment::Consecutive { key, seq: u32::from(data[0] & 0x0F), data: &data[1..] },
+//!             _ => Segment::Ignore,
+//!         }
+//!     }
+//!
+//!     fn sequence_modulo(&self) -> u32 {
+//!         16
+//!     }
+//! }
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let mut reassembler = Reassembler::new(IsoTp);
+//! loop {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     for event in reassembler.observe(&frame) {
+//!         if let Reassembly::Complete(message) = event {
+//!             println!("{:03X}: {:02X?}", message.key, message.data);
+//!         }
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::frame::GsUsbFrame;
+
+/// Default time a transfer may stall between two frames
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What a frame contributes to a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment<'a, K> {
+    /// Not part of the protocol
+    Ignore,
+    /// A complete payload in one frame
+    Single {
+        /// Transfer the frame belongs to
+        key: K,
+        /// The payload
+        data: &'a [u8],
+    },
+    /// Start of a transfer, replacing one in progress with the same key
+    First {
+        /// Transfer the frame belongs to
+        key: K,
+        /// Length of the whole payload; data past it is padding
+        total_len: usize,
+        /// Sequence number of this frame, the next one must carry the one
+        /// after it
+        seq: u32,
+        /// The start of the payload, possibly empty
+        data: &'a [u8],
+    },
+    /// Continuation of a transfer
+    Consecutive {
+        /// Transfer the frame belongs to
+        key: K,
+        /// Sequence number of this frame
+        seq: u32,
+        /// The next part of the payload
+        data: &'a [u8],
+    },
+}
+
+/// Description of a transport protocol's framing
+pub trait Framing {
+    /// What separates concurrent transfers, e.g. the identifier or the
+    /// source address
+    type Key: Ord + Clone;
+
+    /// Tell what `frame` contributes
+    fn classify<'a>(&self, frame: &'a GsUsbFrame) -> Segment<'a, Self::Key>;
+
+    /// Number of sequence values before the counter wraps to 0
+    fn sequence_modulo(&self) -> u32 {
+        256
+    }
+}
+
+/// A reassembled payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message<K> {
+    /// Transfer the payload came in
+    pub key: K,
+    /// The payload
+    pub data: Vec<u8>,
+    /// Frames it came in
+    pub frames: u32,
+    /// Hardware timestamp of the first frame
+    pub first_timestamp_us: u32,
+    /// Hardware timestamp of the last frame
+    pub last_timestamp_us: u32,
+}
+
+/// Why a transfer was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    /// A consecutive frame carried the wrong sequence number
+    Sequence {
+        /// Sequence number expected
+        expected: u32,
+        /// Sequence number received
+        actual: u32,
+    },
+    /// No frame arrived within the timeout
+    Timeout,
+    /// A new transfer started with the same key
+    Restarted,
+}
+
+/// Outcome of a transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reassembly<K> {
+    /// The payload is complete
+    Complete(Message<K>),
+    /// The transfer was dropped with the data received so far
+    Aborted {
+        /// Transfer dropped
+        key: K,
+        /// Why it was dropped
+        reason: AbortReason,
+        /// Payload bytes received before
+        received: usize,
+    },
+}
+
+/// Called with every completed payload
+type Callback<K> = Box<dyn FnMut(&Message<K>) + Send>;
+
+/// A transfer in progress
+#[derive(Debug, Clone)]
+struct Transfer {
+    total_len: usize,
+    data: Vec<u8>,
+    next_seq: u32,
+    frames: u32,
+    first_timestamp_us: u32,
+    last_seen: Instant,
+}
+
+/// Reassembles the payloads of a transport protocol, see the module
+/// documentation
+///
+/// Stalls are measured by the arrival on the host, read from the clock when
+/// frames are passed to `observe()`; call `check()` to report stalled
+/// transfers when no frames arrive.
+pub struct Reassembler<F: Framing, C: Clock = SystemClock> {
+    framing: F,
+    clock: C,
+    timeout: Duration,
+    transfers: BTreeMap<F::Key, Transfer>,
+    on_complete: Option<Callback<F::Key>>,
+}
+
+impl<F: Framing> Reassembler<F> {
+    /// Create a reassembler using the system clock
+    pub fn new(framing: F) -> Self {
+        Self::with_clock(framing, SystemClock)
+    }
+}
+
+impl<F: Framing, C: Clock> Reassembler<F, C> {
+    /// Create a reassembler using the given clock
+    pub fn with_clock(framing: F, clock: C) -> Self {
+        Self {
+            framing,
+            clock,
+            timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            transfers: BTreeMap::new(),
+            on_complete: None,
+        }
+    }
+
+    /// Set the time a transfer may stall between two frames
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Call `callback` with every completed payload, in addition to
+    /// reporting it
+    pub fn on_complete(&mut self, callback: impl FnMut(&Message<F::Key>) + Send + 'static) {
+        self.on_complete = Some(Box::new(callback));
+    }
+
+    /// The framing
+    pub fn framing(&self) -> &F {
+        &self.framing
+    }
+
+    /// Transfers in progress
+    pub fn in_progress(&self) -> usize {
+        self.transfers.len()
+    }
+
+    /// Take a frame, reporting the transfers it completes or aborts
+    ///
+    /// Consecutive frames without a transfer in progress are ignored, as is
+    /// data past the announced length.
+    pub fn observe(&mut self, frame: &GsUsbFrame) -> Vec<Reassembly<F::Key>> {
+        let mut events = self.check();
+        let now = self.clock.now();
+        let modulo = self.framing.sequence_modulo().max(1);
+        match self.framing.classify(frame) {
+            Segment::Ignore => {}
+            Segment::Single { key, data } => {
+                events.extend(self.abort(&key, AbortReason::Restarted));
+                events.push(self.complete(Message {
+                    key,
+                    data: data.to_vec(),
+                    frames: 1,
+                    first_timestamp_us: frame.timestamp_us,
+                    last_timestamp_us: frame.timestamp_us,
+                }));
+            }
+            Segment::First {
+                key,
+                total_len,
+                seq,
+                data,
+            } => {
+                events.extend(self.abort(&key, AbortReason::Restarted));
+                let transfer = Transfer {
+                    total_len,
+                    data: data[..data.len().min(total_len)].to_vec(),
+                    next_seq: (seq + 1) % modulo,
+                    frames: 1,
+                    first_timestamp_us: frame.timestamp_us,
+                    last_seen: now,
+                };
+                events.extend(self.advance(key, transfer, frame));
+            }
+            Segment::Consecutive { key, seq, data } => {
+                let Some(mut transfer) = self.transfers.remove(&key) else {
+                    return events;
+                };
+                if seq != transfer.next_seq {
+                    let reason = AbortReason::Sequence {
+                        expected: transfer.next_seq,
+                        actual: seq,
+                    };
+                    events.push(Reassembly::Aborted {
+                        key,
+                        reason,
+                        received: transfer.data.len(),
+                    });
+                    return events;
+                }
+                let missing = transfer.total_len - transfer.data.len();
+                transfer
+                    .data
+                    .extend_from_slice(&data[..data.len().min(missing)]);
+                transfer.next_seq = (seq + 1) % modulo;
+                transfer.frames += 1;
+                transfer.last_seen = now;
+                events.extend(self.advance(key, transfer, frame));
+            }
+        }
+        events
+    }
+
+    /// Report and drop the transfers that stalled longer than the timeout
+    pub fn check(&mut self) -> Vec<Reassembly<F::Key>> {
+        let now = self.clock.now();
+        let stalled: Vec<F::Key> = self
+            .transfers
+            .iter()
+            .filter(|(_, transfer)| {
+                now.saturating_duration_since(transfer.last_seen) > self.timeout
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        stalled
+            .iter()
+            .filter_map(|key| self.abort(key, AbortReason::Timeout))
+            .collect()
+    }
+
+    /// Drop the transfers in progress without reporting them
+    pub fn reset(&mut self) {
+        self.transfers.clear();
+    }
+
+    /// Complete `transfer` if it has all its data, or keep it
+    fn advance(
+        &mut self,
+        key: F::Key,
+        transfer: Transfer,
+        frame: &GsUsbFrame,
+    ) -> Option<Reassembly<F::Key>> {
+        if transfer.data.len() < transfer.total_len {
+            self.transfers.insert(key, transfer);
+            return None;
+        }
+        Some(self.complete(Message {
+            key,
+            data: transfer.data,
+            frames: transfer.frames,
+            first_timestamp_us: transfer.first_timestamp_us,
+            last_timestamp_us: frame.timestamp_us,
+        }))
+    }
+
+    fn complete(&mut self, message: Message<F::Key>) -> Reassembly<F::Key> {
+        if let Some(callback) = &mut self.on_complete {
+            callback(&message);
+        }
+        Reassembly::Complete(message)
+    }
+
+    fn abort(&mut self, key: &F::Key, reason: AbortReason) -> Option<Reassembly<F::Key>> {
+        let transfer = self.transfers.remove(key)?;
+        Some(Reassembly::Aborted {
+            key: key.clone(),
+            reason,
+            received: transfer.data.len(),
+        })
+    }
+}
+
+impl<F: Framing + std::fmt::Debug, C: Clock> std::fmt::Debug for Reassembler<F, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reassembler")
+            .field("framing", &self.framing)
+            .field("timeout", &self.timeout)
+            .field("in_progress", &self.transfers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use std::sync::{Arc, Mutex};
+
+    /// NMEA 2000 fast packets: sequence counter in the upper three bits of
+    /// byte 0, frame counter in the lower five, length in byte 1 of the
+    /// first frame
+    #[derive(Debug)]
+    struct FastPacket;
+
+    impl Framing for FastPacket {
+        type Key = (u32, u8);
+
+        fn classify<'a>(&self, frame: &'a GsUsbFrame) -> Segment<'a, (u32, u8)> {
+            let data = frame.data();
+            let Some(&head) = data.first() else {
+                return Segment::Ignore;
+            };
+            let key = (frame.can_id, head >> 5);
+            match head & 0x1F {
+                0 if data.len() >= 2 => Segment::First {
+                    key,
+                    total_len: usize::from(data[1]),
+                    seq: 0,
+                    data: &data[2..],
+                },
+                0 => Segment::Ignore,
+                seq => Segment::Consecutive {
+                    key,
+                    seq: u32::from(seq),
+                    data: &data[1..],
+                },
+            }
+        }
+
+        fn sequence_modulo(&self) -> u32 {
+            32
+        }
+    }
+
+    fn frame(head: u8, data: &[u8]) -> GsUsbFrame {
+        let mut payload = vec![head];
+        payload.extend_from_slice(data);
+        GsUsbFrame::with_data(0x1F801, &payload)
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut reassembler = Reassembler::new(FastPacket);
+        let completed = Arc::new(Mutex::new(0));
+        let counter = completed.clone();
+        reassembler.on_complete(move |_| *counter.lock().unwrap() += 1);
+
+        assert!(reassembler
+            .observe(&frame(0x20, &[10, 1, 2, 3, 4, 5, 6]))
+            .is_empty());
+        // Another sequence counter is another transfer
+        assert!(reassembler.observe(&frame(0x40, &[9, 1])).is_empty());
+        assert_eq!(reassembler.in_progress(), 2);
+
+        let events = reassembler.observe(&frame(0x21, &[7, 8, 9, 10, 0xFF, 0xFF, 0xFF]));
+        let [Reassembly::Complete(message)] = &events[..] else {
+            panic!("unexpected {events:?}");
+        };
+        assert_eq!(message.key, (0x1F801, 1));
+        assert_eq!(message.data, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(message.frames, 2);
+        assert_eq!(*completed.lock().unwrap(), 1);
+        assert_eq!(reassembler.in_progress(), 1);
+    }
+
+    #[test]
+    fn test_aborts() {
+        let clock = TestClock::new();
+        let mut reassembler = Reassembler::with_clock(FastPacket, clock.clone());
+        reassembler.set_timeout(Duration::from_millis(750));
+
+        reassembler.observe(&frame(0x00, &[20, 1, 2, 3, 4, 5, 6]));
+        let events = reassembler.observe(&frame(0x02, &[7; 7]));
+        assert_eq!(
+            events,
+            [Reassembly::Aborted {
+                key: (0x1F801, 0),
+                reason: AbortReason::Sequence {
+                    expected: 1,
+                    actual: 2
+                },
+                received: 6
+            }]
+        );
+        // Nothing in progress any more
+        assert!(reassembler.observe(&frame(0x03, &[7; 7])).is_empty());
+
+        reassembler.observe(&frame(0x00, &[20, 1, 2, 3, 4, 5, 6]));
+        let events = reassembler.observe(&frame(0x00, &[20, 1, 2, 3, 4, 5, 6]));
+        assert!(matches!(
+            events[..],
+            [Reassembly::Aborted {
+                reason: AbortReason::Restarted,
+                ..
+            }]
+        ));
+
+        clock.advance(Duration::from_secs(1));
+        let events = reassembler.check();
+        assert!(matches!(
+            events[..],
+            [Reassembly::Aborted {
+                reason: AbortReason::Timeout,
+                received: 6,
+                ..
+            }]
+        ));
+        assert_eq!(reassembler.in_progress(), 0);
+    }
+}
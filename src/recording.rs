@@ -0,0 +1,533 @@
+//! USB traffic recording and replay
+//!
+//! `RecordingTransport` wraps a transport and logs every transfer (direction,
+//! request, payload, result and time) to a `Recording`, optionally streaming it
+//! to a file as it happens. A recording of a real-device session, e.g. attached
+//! to a bug report, can later be replayed with `MockGsUsb::replay()` (`test-util`
+//! feature) to turn it into a reproducible regression test.
+//!
+//! Bulk IN timeouts are not recorded, since idle polling would dominate the log.
+//!
+//! # File format
+//!
+//! Recordings are line-based text. Lines starting with `#` are comments. Every
+//! other line is one transfer:
+//!
+//! ```text
+//! <time_us> <kind> <request> <value> <result> [payload]
+//! ```
+//!
+//! - `time_us`: microseconds since recording started
+//! - `kind`: `reset`, `claim`, `ctrl_out`, `ctrl_in`, `bulk_out` or `bulk_in`
+//! - `request`, `value`: control request and wValue in hex (0 otherwise)
+//! - `result`: `ok` or the `rusb::Error` variant name (e.g. `Pipe`)
+//! - `payload`: data sent or received, in hex
+
+use std::fmt;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{GsUsbError, Result};
+#[cfg(any(test, feature = "test-util"))]
+use crate::scenario::{Scenario, ScenarioEvent};
+use crate::transport::{GsUsbInterface, Transport};
+
+/// First line of a recording file
+pub const RECORDING_HEADER: &str = "# gs_usb recording v1";
+
+/// Kind of a recorded transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferKind {
+    /// Device reset
+    Reset,
+    /// Interface claim
+    ClaimInterface,
+    /// Control transfer, host to device
+    ControlOut,
+    /// Control transfer, device to host
+    ControlIn,
+    /// Bulk transfer, host to device
+    BulkOut,
+    /// Bulk transfer, device to host
+    BulkIn,
+}
+
+impl TransferKind {
+    fn name(self) -> &'static str {
+        match self {
+            TransferKind::Reset => "reset",
+            TransferKind::ClaimInterface => "claim",
+            TransferKind::ControlOut => "ctrl_out",
+            TransferKind::ControlIn => "ctrl_in",
+            TransferKind::BulkOut => "bulk_out",
+            TransferKind::BulkIn => "bulk_in",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "reset" => TransferKind::Reset,
+            "claim" => TransferKind::ClaimInterface,
+            "ctrl_out" => TransferKind::ControlOut,
+            "ctrl_in" => TransferKind::ControlIn,
+            "bulk_out" => TransferKind::BulkOut,
+            "bulk_in" => TransferKind::BulkIn,
+            _ => return None,
+        })
+    }
+}
+
+/// One recorded transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedTransfer {
+    /// Time since recording started
+    pub time: Duration,
+    /// Transfer kind
+    pub kind: TransferKind,
+    /// Control request (0 for other kinds)
+    pub request: u8,
+    /// Control wValue (0 for other kinds)
+    pub value: u16,
+    /// Transferred data, or the error the transfer failed with
+    pub result: std::result::Result<Vec<u8>, rusb::Error>,
+}
+
+const RUSB_ERRORS: [rusb::Error; 14] = [
+    rusb::Error::Io,
+    rusb::Error::InvalidParam,
+    rusb::Error::Access,
+    rusb::Error::NoDevice,
+    rusb::Error::NotFound,
+    rusb::Error::Busy,
+    rusb::Error::Timeout,
+    rusb::Error::Overflow,
+    rusb::Error::Pipe,
+    rusb::Error::Interrupted,
+    rusb::Error::NoMem,
+    rusb::Error::NotSupported,
+    rusb::Error::BadDescriptor,
+    rusb::Error::Other,
+];
+
+impl fmt::Display for RecordedTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:02x} {:04x}",
+            self.time.as_micros(),
+            self.kind.name(),
+            self.request,
+            self.value
+        )?;
+        match &self.result {
+            Ok(data) if data.is_empty() => write!(f, " ok"),
+            Ok(data) => {
+                write!(f, " ok ")?;
+                data.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            Err(e) => write!(f, " {:?}", e),
+        }
+    }
+}
+
+impl RecordedTransfer {
+    /// Whether this is a successful `MODE` request starting the device
+    #[cfg(any(test, feature = "test-util"))]
+    fn is_start(&self) -> bool {
+        use crate::constants::{GS_CAN_MODE_START, GS_USB_BREQ_MODE};
+
+        self.kind == TransferKind::ControlOut
+            && self.request == GS_USB_BREQ_MODE
+            && matches!(&self.result, Ok(data) if data.len() >= 4
+                && u32::from_le_bytes([data[0], data[1], data[2], data[3]]) == GS_CAN_MODE_START)
+    }
+
+    fn parse(line: &str, line_number: usize) -> Result<Self> {
+        let invalid = |reason| GsUsbError::InvalidRecording {
+            line: line_number,
+            reason,
+        };
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 5 || fields.len() > 6 {
+            return Err(invalid("expected 5 or 6 fields"));
+        }
+
+        let time_us: u64 = fields[0].parse().map_err(|_| invalid("invalid time"))?;
+        let kind = TransferKind::from_name(fields[1]).ok_or(invalid("unknown transfer kind"))?;
+        let request = u8::from_str_radix(fields[2], 16).map_err(|_| invalid("invalid request"))?;
+        let value = u16::from_str_radix(fields[3], 16).map_err(|_| invalid("invalid value"))?;
+
+        let result = match fields[4] {
+            "ok" => {
+                let hex = fields.get(5).copied().unwrap_or("");
+                if hex.len() % 2 != 0 || !hex.is_ascii() {
+                    return Err(invalid("invalid payload"));
+                }
+                let data = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                    .collect::<std::result::Result<Vec<u8>, _>>()
+                    .map_err(|_| invalid("invalid payload"))?;
+                Ok(data)
+            }
+            name => Err(RUSB_ERRORS
+                .into_iter()
+                .find(|e| format!("{:?}", e) == name)
+                .ok_or(invalid("unknown error"))?),
+        };
+
+        Ok(Self {
+            time: Duration::from_micros(time_us),
+            kind,
+            request,
+            value,
+            result,
+        })
+    }
+}
+
+/// Sequence of recorded transfers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    /// Transfers in the order they happened
+    pub transfers: Vec<RecordedTransfer>,
+}
+
+impl Recording {
+    /// Create an empty recording
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a recording from its text form
+    pub fn parse(text: &str) -> Result<Self> {
+        let transfers = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| RecordedTransfer::parse(line, i + 1))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { transfers })
+    }
+
+    /// Load a recording file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Save the recording to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Convert the bulk IN transfers into a scenario
+    ///
+    /// Event times are relative to the first device start (`MODE` request)
+    /// in the recording; transfers before it are skipped.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn to_scenario(&self) -> Scenario {
+        let mut scenario = Scenario::new();
+        let Some(start) = self.transfers.iter().find(|t| t.is_start()) else {
+            return scenario;
+        };
+
+        for transfer in &self.transfers {
+            if transfer.kind != TransferKind::BulkIn || transfer.time < start.time {
+                continue;
+            }
+            let event = match &transfer.result {
+                Ok(data) => ScenarioEvent::RawRx(data.clone()),
+                Err(e) => ScenarioEvent::RxError(*e),
+            };
+            scenario = scenario.at(transfer.time - start.time, event);
+        }
+        scenario
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", RECORDING_HEADER)?;
+        self.transfers
+            .iter()
+            .try_for_each(|transfer| writeln!(f, "{}", transfer))
+    }
+}
+
+/// Handle for reading the recording of a `RecordingTransport` after it has
+/// been moved into a `GsUsb`
+#[derive(Clone)]
+pub struct RecordingHandle {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl RecordingHandle {
+    /// Get a copy of everything recorded so far
+    pub fn recording(&self) -> Recording {
+        self.recording.lock().unwrap().clone()
+    }
+}
+
+/// Transport wrapper recording all transfers
+pub struct RecordingTransport<T> {
+    inner: T,
+    recording: Arc<Mutex<Recording>>,
+    writer: Option<Box<dyn Write + Send>>,
+    epoch: Instant,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Record transfers in memory
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            recording: Arc::new(Mutex::new(Recording::new())),
+            writer: None,
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Record transfers in memory and stream them to a writer
+    pub fn with_writer(inner: T, mut writer: impl Write + Send + 'static) -> Result<Self> {
+        writeln!(writer, "{}", RECORDING_HEADER)?;
+        let mut transport = Self::new(inner);
+        transport.writer = Some(Box::new(writer));
+        Ok(transport)
+    }
+
+    /// Record transfers in memory and stream them to a new file
+    ///
+    /// Every transfer is written out immediately, so the file survives a
+    /// crash of the application.
+    pub fn create(inner: T, path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_writer(inner, LineWriter::new(File::create(path)?))
+    }
+
+    /// Get a handle to the recording
+    pub fn handle(&self) -> RecordingHandle {
+        RecordingHandle {
+            recording: self.recording.clone(),
+        }
+    }
+
+    /// Unwrap the transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(
+        &mut self,
+        kind: TransferKind,
+        request: u8,
+        value: u16,
+        result: std::result::Result<Vec<u8>, rusb::Error>,
+    ) {
+        // Microsecond resolution, as in the file format
+        let transfer = RecordedTransfer {
+            time: Duration::from_micros(self.epoch.elapsed().as_micros() as u64),
+            kind,
+            request,
+            value,
+            result,
+        };
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(e) = writeln!(writer, "{}", transfer) {
+                log::warn!("recording: writing to file failed, stopping: {}", e);
+                self.writer = None;
+            }
+        }
+        self.recording.lock().unwrap().transfers.push(transfer);
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn reset(&mut self) -> rusb::Result<()> {
+        let result = self.inner.reset();
+        self.record(TransferKind::Reset, 0, 0, result.map(|_| Vec::new()));
+        result
+    }
+
+    fn claim_interface(&mut self) -> Result<()> {
+        let result = self.inner.claim_interface();
+        let recorded = match &result {
+            Ok(()) => Ok(Vec::new()),
+            Err(GsUsbError::ClaimInterface(e))
+            | Err(GsUsbError::DetachKernelDriver(e))
+            | Err(GsUsbError::Platform { source: e, .. }) => Err(*e),
+            Err(_) => Err(rusb::Error::Other),
+        };
+        self.record(TransferKind::ClaimInterface, 0, 0, recorded);
+        result
+    }
+
+    fn release_interface(&mut self, reattach_kernel_driver: bool) -> Result<()> {
+        self.inner.release_interface(reattach_kernel_driver)
+    }
+
+    fn write_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let result = self.inner.write_control(request, value, data, timeout);
+        let recorded = result.map(|len| data[..len.min(data.len())].to_vec());
+        self.record(TransferKind::ControlOut, request, value, recorded);
+        result
+    }
+
+    fn read_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let result = self.inner.read_control(request, value, buf, timeout);
+        let recorded = result.map(|len| buf[..len].to_vec());
+        self.record(TransferKind::ControlIn, request, value, recorded);
+        result
+    }
+
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        let result = self.inner.write_bulk(data, timeout);
+        let recorded = result.map(|len| data[..len.min(data.len())].to_vec());
+        self.record(TransferKind::BulkOut, 0, 0, recorded);
+        result
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        let result = self.inner.read_bulk(buf, timeout);
+        if result != Err(rusb::Error::Timeout) {
+            let recorded = result.map(|len| buf[..len].to_vec());
+            self.record(TransferKind::BulkIn, 0, 0, recorded);
+        }
+        result
+    }
+
+    fn tx_max_packet_size(&self) -> Option<usize> {
+        self.inner.tx_max_packet_size()
+    }
+
+    fn interface(&self) -> GsUsbInterface {
+        self.inner.interface()
+    }
+
+    fn vendor_product(&self) -> Option<(u16, u16)> {
+        self.inner.vendor_product()
+    }
+
+    fn serial_number(&mut self) -> rusb::Result<Option<String>> {
+        self.inner.serial_number()
+    }
+
+    fn product(&mut self) -> rusb::Result<Option<String>> {
+        self.inner.product()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::*;
+    use crate::device::GsUsb;
+    use crate::frame::GsUsbFrame;
+    use crate::mock::MockGsUsb;
+    use crate::structures::DeviceState;
+
+    /// Record a short session against a mock
+    fn record_session() -> Recording {
+        let mock = MockGsUsb::new();
+        let transport = RecordingTransport::new(mock.clone());
+        let recording = transport.handle();
+        let mut dev = GsUsb::from_transport(transport, 0, 1);
+
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+        mock.push_rx(&GsUsbFrame::with_data(0x100, &[1, 2, 3]));
+        mock.push_rx(&GsUsbFrame::with_data(0x200, &[4]));
+        dev.read(Duration::from_millis(10)).unwrap();
+        dev.read(Duration::from_millis(10)).unwrap();
+        mock.set_device_state(DeviceState {
+            state: GS_CAN_STATE_ERROR_PASSIVE,
+            rxerr: 130,
+            txerr: 0,
+        });
+        dev.get_state(0).unwrap();
+        mock.push_rx_error(rusb::Error::NoDevice);
+        assert!(dev.read(Duration::from_millis(10)).is_err());
+
+        recording.recording()
+    }
+
+    #[test]
+    fn test_text_roundtrip() {
+        let recording = record_session();
+        let kinds: Vec<TransferKind> = recording.transfers.iter().map(|t| t.kind).collect();
+        assert!(kinds.contains(&TransferKind::Reset));
+        assert!(kinds.contains(&TransferKind::ControlIn));
+        assert_eq!(kinds.last(), Some(&TransferKind::BulkIn));
+        assert_eq!(
+            recording.transfers.last().unwrap().result,
+            Err(rusb::Error::NoDevice)
+        );
+
+        let text = recording.to_string();
+        assert!(text.starts_with(RECORDING_HEADER));
+        assert_eq!(Recording::parse(&text).unwrap(), recording);
+
+        assert!(matches!(
+            Recording::parse("12 bulk_in 00 0000 ok 0"),
+            Err(GsUsbError::InvalidRecording { line: 1, .. })
+        ));
+        assert!(matches!(
+            Recording::parse("# header\n12 warp 00 0000 ok"),
+            Err(GsUsbError::InvalidRecording { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_replay_through_mock() {
+        let recording = Recording::parse(&record_session().to_string()).unwrap();
+
+        let mock = MockGsUsb::new();
+        mock.replay(&recording);
+        let mut dev = mock.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+
+        let first = dev.read(Duration::from_millis(10)).unwrap();
+        assert_eq!(first.arbitration_id(), 0x100);
+        assert_eq!(first.data(), &[1, 2, 3]);
+        let second = dev.read(Duration::from_millis(10)).unwrap();
+        assert_eq!(second.arbitration_id(), 0x200);
+
+        let state = dev.get_state(0).unwrap();
+        assert_eq!(state.state, GS_CAN_STATE_ERROR_PASSIVE);
+        assert_eq!(state.rxerr, 130);
+
+        assert!(matches!(
+            dev.read(Duration::from_millis(10)),
+            Err(GsUsbError::BulkTransfer {
+                source: rusb::Error::NoDevice,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_forwards_packet_size() {
+        let mock = MockGsUsb::new();
+        mock.set_tx_max_packet_size(512);
+        let transport = RecordingTransport::new(mock);
+        assert_eq!(transport.tx_max_packet_size(), Some(512));
+    }
+}
@@ -0,0 +1,504 @@
+//! gRPC remote bus service and client
+//!
+//! This module exposes GS-USB adapters over the network using the `RemoteBus`
+//! gRPC service defined in `proto/gs_usb.proto`, so that several hosts (e.g. CI
+//! test jobs) can share physical adapters attached to one machine.
+//!
+//! Enabled with the `grpc` cargo feature.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::remote::RemoteBusService;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let addr = "0.0.0.0:50051".parse()?;
+//!     tonic::transport::Server::builder()
+//!         .add_service(RemoteBusService::new().into_server())
+//!         .serve(addr)
+//!         .await?;
+//!     Ok(())
+//! }
+//! ```
+
+// `tonic::Status` is large, but it is the error type the gRPC API is built on
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::constants::{
+    CANFD_MAX_DLC, CANFD_MAX_DLEN, CAN_MAX_DLEN, CAN_RTR_FLAG, GS_CAN_FLAG_FD,
+    GS_USB_FRAME_DATA_LEN,
+};
+use crate::device::GsUsb;
+use crate::error::{ErrorKind, GsUsbError};
+use crate::frame::{dlc_to_len, GsUsbFrame};
+
+/// Generated protobuf and gRPC types
+pub mod proto {
+    tonic::include_proto!("gs_usb");
+}
+
+use proto::remote_bus_client::RemoteBusClient as GeneratedClient;
+use proto::remote_bus_server::{RemoteBus, RemoteBusServer};
+
+/// Read timeout used by the streaming receive loop between checks for
+/// client disconnect and session close
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Receive stream channel depth (frames buffered per connected client)
+const RECEIVE_CHANNEL_DEPTH: usize = 256;
+
+impl From<&GsUsbFrame> for proto::Frame {
+    fn from(frame: &GsUsbFrame) -> Self {
+        Self {
+            echo_id: frame.echo_id,
+            can_id: frame.can_id,
+            can_dlc: frame.can_dlc as u32,
+            channel: frame.channel as u32,
+            flags: frame.flags as u32,
+            // Remote requests carry only their DLC
+            data: if frame.is_remote_frame() {
+                Vec::new()
+            } else {
+                frame.data().to_vec()
+            },
+            timestamp_us: frame.timestamp_us,
+        }
+    }
+}
+
+impl TryFrom<proto::Frame> for GsUsbFrame {
+    type Error = Status;
+
+    fn try_from(frame: proto::Frame) -> Result<Self, Self::Error> {
+        let byte = |name: &str, value: u32| {
+            u8::try_from(value)
+                .map_err(|_| Status::invalid_argument(format!("{name} out of range: {value}")))
+        };
+        let can_dlc = byte("can_dlc", frame.can_dlc)?;
+        let channel = byte("channel", frame.channel)?;
+        let flags = byte("flags", frame.flags)?;
+        let fd = (flags & GS_CAN_FLAG_FD) != 0;
+        if fd && !cfg!(feature = "fd") {
+            return Err(Status::invalid_argument(
+                "CAN FD frames need the fd feature",
+            ));
+        }
+        // Within the frame's data array either way
+        let max_len = if fd { CANFD_MAX_DLEN } else { CAN_MAX_DLEN }.min(GS_USB_FRAME_DATA_LEN);
+        if frame.data.len() > max_len {
+            return Err(Status::invalid_argument(format!(
+                "frame data too long: {} bytes (max {})",
+                frame.data.len(),
+                max_len
+            )));
+        }
+        // Classic frames may use DLCs above 8 for 8 bytes too
+        if can_dlc > CANFD_MAX_DLC {
+            return Err(Status::invalid_argument(format!(
+                "can_dlc out of range: {can_dlc}"
+            )));
+        }
+        // Remote requests carry no data whatever their DLC
+        let expected_len = if frame.can_id & CAN_RTR_FLAG != 0 {
+            0
+        } else {
+            dlc_to_len(can_dlc, fd)
+        };
+        if frame.data.len() != expected_len {
+            return Err(Status::invalid_argument(format!(
+                "can_dlc {can_dlc} doesn't match {} data bytes",
+                frame.data.len()
+            )));
+        }
+
+        let mut out = GsUsbFrame::new();
+        out.echo_id = frame.echo_id;
+        out.can_id = frame.can_id;
+        out.can_dlc = can_dlc;
+        out.channel = channel;
+        out.flags = flags;
+        out.data[..frame.data.len()].copy_from_slice(&frame.data);
+        out.timestamp_us = frame.timestamp_us;
+        Ok(out)
+    }
+}
+
+impl From<GsUsbError> for Status {
+    fn from(err: GsUsbError) -> Self {
+        let message = err.to_string();
+        match err {
+            GsUsbError::DeviceNotFound => Status::not_found(message),
+            GsUsbError::ReadTimeout | GsUsbError::WriteTimeout => {
+                Status::deadline_exceeded(message)
+            }
+            _ => match err.kind() {
+                ErrorKind::Configuration => Status::invalid_argument(message),
+                ErrorKind::Unsupported | ErrorKind::State => Status::failed_precondition(message),
+                ErrorKind::Transient | ErrorKind::Disconnected => Status::unavailable(message),
+                ErrorKind::Fatal => Status::internal(message),
+            },
+        }
+    }
+}
+
+/// An open device owned by the server
+struct Session {
+    device: Arc<Mutex<GsUsb>>,
+    closed: Arc<AtomicBool>,
+}
+
+/// gRPC service exposing locally attached GS-USB devices
+#[derive(Default)]
+pub struct RemoteBusService {
+    sessions: Mutex<HashMap<u64, Session>>,
+    next_session_id: AtomicU64,
+}
+
+impl RemoteBusService {
+    /// Create a new service with no open sessions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wrap the service in the generated tonic server type
+    pub fn into_server(self) -> RemoteBusServer<Self> {
+        RemoteBusServer::new(self)
+    }
+
+    fn session(&self, session_id: u64) -> Result<(Arc<Mutex<GsUsb>>, Arc<AtomicBool>), Status> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(&session_id)
+            .map(|s| (s.device.clone(), s.closed.clone()))
+            .ok_or_else(|| Status::not_found(format!("unknown session {}", session_id)))
+    }
+}
+
+#[tonic::async_trait]
+impl RemoteBus for RemoteBusService {
+    async fn list_devices(
+        &self,
+        _request: Request<proto::ListDevicesRequest>,
+    ) -> Result<Response<proto::ListDevicesResponse>, Status> {
+        let devices = tokio::task::spawn_blocking(|| -> crate::Result<Vec<proto::Device>> {
+            let mut out = Vec::new();
+            for mut dev in GsUsb::scan()? {
+                out.push(proto::Device {
+                    bus: dev.bus() as u32,
+                    address: dev.address() as u32,
+                    serial_number: dev.serial_number().unwrap_or_default(),
+                });
+            }
+            Ok(out)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+
+        Ok(Response::new(proto::ListDevicesResponse { devices }))
+    }
+
+    async fn open(
+        &self,
+        request: Request<proto::OpenRequest>,
+    ) -> Result<Response<proto::OpenResponse>, Status> {
+        let req = request.into_inner();
+        let (bus, address) = (req.bus as u8, req.address as u8);
+
+        let device = tokio::task::spawn_blocking(move || GsUsb::find(bus, address))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??
+            .ok_or_else(|| Status::from(GsUsbError::DeviceNotFound))?;
+
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.sessions.lock().unwrap().insert(
+            session_id,
+            Session {
+                device: Arc::new(Mutex::new(device)),
+                closed: Arc::new(AtomicBool::new(false)),
+            },
+        );
+
+        Ok(Response::new(proto::OpenResponse { session_id }))
+    }
+
+    async fn configure(
+        &self,
+        request: Request<proto::ConfigureRequest>,
+    ) -> Result<Response<proto::ConfigureResponse>, Status> {
+        let req = request.into_inner();
+        let (device, _) = self.session(req.session_id)?;
+
+        tokio::task::spawn_blocking(move || -> crate::Result<()> {
+            let mut dev = device.lock().unwrap();
+            dev.set_bitrate(req.bitrate)?;
+            if req.data_bitrate != 0 {
+                dev.set_data_bitrate(req.data_bitrate)?;
+            }
+            dev.start(req.mode_flags)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+
+        Ok(Response::new(proto::ConfigureResponse {}))
+    }
+
+    async fn send(
+        &self,
+        request: Request<proto::SendRequest>,
+    ) -> Result<Response<proto::SendResponse>, Status> {
+        let req = request.into_inner();
+        let (device, _) = self.session(req.session_id)?;
+        let frame = GsUsbFrame::try_from(
+            req.frame
+                .ok_or_else(|| Status::invalid_argument("missing frame"))?,
+        )?;
+
+        tokio::task::spawn_blocking(move || device.lock().unwrap().send(&frame))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??;
+
+        Ok(Response::new(proto::SendResponse {}))
+    }
+
+    type ReceiveStream = ReceiverStream<Result<proto::Frame, Status>>;
+
+    async fn receive(
+        &self,
+        request: Request<proto::ReceiveRequest>,
+    ) -> Result<Response<Self::ReceiveStream>, Status> {
+        let req = request.into_inner();
+        let (device, closed) = self.session(req.session_id)?;
+        let (tx, rx) = mpsc::channel(RECEIVE_CHANNEL_DEPTH);
+
+        tokio::task::spawn_blocking(move || {
+            while !tx.is_closed() && !closed.load(Ordering::Relaxed) {
+                let result = device.lock().unwrap().read(RECEIVE_POLL_INTERVAL);
+                match result {
+                    Ok(frame) => {
+                        if tx.blocking_send(Ok(proto::Frame::from(&frame))).is_err() {
+                            break;
+                        }
+                    }
+                    Err(GsUsbError::ReadTimeout) => continue,
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(Status::from(e)));
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn close(
+        &self,
+        request: Request<proto::CloseRequest>,
+    ) -> Result<Response<proto::CloseResponse>, Status> {
+        let req = request.into_inner();
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .remove(&req.session_id)
+            .ok_or_else(|| Status::not_found(format!("unknown session {}", req.session_id)))?;
+
+        session.closed.store(true, Ordering::Relaxed);
+        tokio::task::spawn_blocking(move || session.device.lock().unwrap().stop())
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??;
+
+        Ok(Response::new(proto::CloseResponse {}))
+    }
+}
+
+/// Client for a remote GS-USB device served by `RemoteBusService`
+///
+/// Wraps the generated gRPC client and converts between protobuf frames
+/// and `GsUsbFrame`.
+#[derive(Debug, Clone)]
+pub struct RemoteBusClient {
+    inner: GeneratedClient<tonic::transport::Channel>,
+    session_id: Option<u64>,
+}
+
+impl RemoteBusClient {
+    /// Connect to a remote bus server (e.g. `"http://ci-can-host:50051"`)
+    pub async fn connect(endpoint: String) -> Result<Self, tonic::transport::Error> {
+        let inner = GeneratedClient::connect(endpoint).await?;
+        Ok(Self {
+            inner,
+            session_id: None,
+        })
+    }
+
+    /// List devices attached to the server
+    pub async fn list_devices(&mut self) -> Result<Vec<proto::Device>, Status> {
+        let resp = self
+            .inner
+            .list_devices(proto::ListDevicesRequest {})
+            .await?;
+        Ok(resp.into_inner().devices)
+    }
+
+    /// Open the device at the given bus/address on the server
+    pub async fn open(&mut self, bus: u8, address: u8) -> Result<(), Status> {
+        let resp = self
+            .inner
+            .open(proto::OpenRequest {
+                bus: bus as u32,
+                address: address as u32,
+            })
+            .await?;
+        self.session_id = Some(resp.into_inner().session_id);
+        Ok(())
+    }
+
+    /// Configure bitrate(s) and start the remote device
+    ///
+    /// # Arguments
+    /// * `bitrate` - Nominal bitrate in bits per second
+    /// * `data_bitrate` - CAN FD data phase bitrate, if any
+    /// * `mode_flags` - Mode flags (combination of GS_CAN_MODE_* constants)
+    pub async fn configure(
+        &mut self,
+        bitrate: u32,
+        data_bitrate: Option<u32>,
+        mode_flags: u32,
+    ) -> Result<(), Status> {
+        let session_id = self.session_id()?;
+        self.inner
+            .configure(proto::ConfigureRequest {
+                session_id,
+                bitrate,
+                data_bitrate: data_bitrate.unwrap_or(0),
+                mode_flags,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Send a CAN frame on the remote device
+    pub async fn send(&mut self, frame: &GsUsbFrame) -> Result<(), Status> {
+        let session_id = self.session_id()?;
+        self.inner
+            .send(proto::SendRequest {
+                session_id,
+                frame: Some(proto::Frame::from(frame)),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Start streaming received frames from the remote device
+    pub async fn receive(&mut self) -> Result<RemoteFrameStream, Status> {
+        let session_id = self.session_id()?;
+        let stream = self
+            .inner
+            .receive(proto::ReceiveRequest { session_id })
+            .await?
+            .into_inner();
+        Ok(RemoteFrameStream { inner: stream })
+    }
+
+    /// Stop the remote device and close the session
+    pub async fn close(&mut self) -> Result<(), Status> {
+        let session_id = self.session_id()?;
+        self.inner.close(proto::CloseRequest { session_id }).await?;
+        self.session_id = None;
+        Ok(())
+    }
+
+    fn session_id(&self) -> Result<u64, Status> {
+        self.session_id
+            .ok_or_else(|| Status::failed_precondition("no device opened"))
+    }
+}
+
+/// Stream of frames received from a remote device
+pub struct RemoteFrameStream {
+    inner: tonic::Streaming<proto::Frame>,
+}
+
+impl RemoteFrameStream {
+    /// Wait for the next received frame
+    ///
+    /// Returns `Ok(None)` when the server ends the stream.
+    pub async fn next(&mut self) -> Result<Option<GsUsbFrame>, Status> {
+        match self.inner.message().await? {
+            Some(frame) => Ok(Some(GsUsbFrame::try_from(frame)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_from_proto() {
+        let classic = proto::Frame::from(&GsUsbFrame::with_data(0x123, &[1, 2, 3]));
+        let frame = GsUsbFrame::try_from(classic.clone()).unwrap();
+        assert_eq!(frame.data(), [1, 2, 3]);
+
+        let too_long = proto::Frame {
+            data: vec![0; 9],
+            ..classic.clone()
+        };
+        assert!(GsUsbFrame::try_from(too_long).is_err());
+
+        // FD frames only fit the data array with the fd feature
+        let fd = proto::Frame {
+            can_dlc: 15,
+            flags: u32::from(GS_CAN_FLAG_FD),
+            data: vec![0; CANFD_MAX_DLEN],
+            ..classic
+        };
+        assert_eq!(GsUsbFrame::try_from(fd).is_ok(), cfg!(feature = "fd"));
+    }
+
+    #[test]
+    fn test_frame_fields_validated() {
+        let frame = proto::Frame::from(&GsUsbFrame::with_data(0x123, &[1, 2, 3]));
+        let with = |change: fn(&mut proto::Frame)| {
+            let mut frame = frame.clone();
+            change(&mut frame);
+            GsUsbFrame::try_from(frame)
+        };
+        assert!(with(|f| f.can_dlc = 0x103).is_err());
+        assert!(with(|f| f.can_dlc = 16).is_err());
+        assert!(with(|f| f.can_dlc = 8).is_err());
+        assert!(with(|f| f.channel = 0x101).is_err());
+        assert!(with(|f| f.flags = 0x100).is_err());
+
+        // Remote requests have a DLC but no data
+        let remote = with(|f| {
+            f.can_id |= CAN_RTR_FLAG;
+            f.data.clear();
+        });
+        assert_eq!(remote.unwrap().can_dlc, 3);
+    }
+
+    #[test]
+    fn test_remote_frame_round_trip() {
+        let mut request = GsUsbFrame::new();
+        request.can_id = 0x123 | CAN_RTR_FLAG;
+        request.can_dlc = 8;
+        let message = proto::Frame::from(&request);
+        assert!(message.data.is_empty());
+        let frame = GsUsbFrame::try_from(message).unwrap();
+        assert!(frame.is_remote_frame());
+        assert_eq!((frame.can_id, frame.can_dlc), (request.can_id, 8));
+    }
+}
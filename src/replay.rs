@@ -0,0 +1,213 @@
+//! Replay of CAN logs through a device
+//!
+//! `Replay` sends the frames of a log file (see `crate::logfile`) with their
+//! original spacing, sped up or slowed down by a factor, and with logged
+//! channels mapped to device channels. Error frames are skipped, as a
+//! device can't send them.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, Replay, GS_CAN_MODE_NORMAL};
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! // Channel 1 of the log only, twice as fast
+//! let replay = Replay::load("drive.asc")?.speed(2.0).map_channel(1, 0);
+//! let stats = replay.run(&mut dev)?;
+//! println!("{} frames sent, {} skipped", stats.sent, stats.skipped);
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::logfile::{read_log, LogRecord};
+
+/// Counters of a `Replay::run()`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// Frames sent
+    pub sent: u64,
+    /// Frames skipped: error frames and frames on unmapped channels
+    pub skipped: u64,
+    /// Longest delay of a frame behind its schedule
+    pub max_lag: Duration,
+    /// Time the replay took
+    pub elapsed: Duration,
+}
+
+/// Log replay with timing and channel mapping
+#[derive(Debug, Clone)]
+pub struct Replay {
+    records: Vec<LogRecord>,
+    speed: f64,
+    channels: BTreeMap<u8, u8>,
+}
+
+impl Replay {
+    /// Slowest speed factor, one millionth of the logged speed
+    pub const MIN_SPEED: f64 = 1e-6;
+
+    /// Replay `records` at the original speed, all channels to channel 0
+    pub fn new(records: Vec<LogRecord>) -> Self {
+        Self {
+            records,
+            speed: 1.0,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    /// Replay a log file, see `logfile::read_log()`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(read_log(path)?))
+    }
+
+    /// Play `factor` times as fast as logged; 0 sends without delays
+    ///
+    /// Factors below `Replay::MIN_SPEED` are raised to it; NaN counts as 0.
+    pub fn speed(mut self, factor: f64) -> Self {
+        self.speed = match factor {
+            f if f > 0.0 => f.max(Self::MIN_SPEED),
+            _ => 0.0,
+        };
+        self
+    }
+
+    /// Send frames logged on channel `log` to device channel `device`
+    ///
+    /// Once a channel is mapped, frames on unmapped channels are skipped.
+    pub fn map_channel(mut self, log: u8, device: u8) -> Self {
+        self.channels.insert(log, device);
+        self
+    }
+
+    /// The logged frames
+    pub fn records(&self) -> &[LogRecord] {
+        &self.records
+    }
+
+    /// Time from the first to the last frame, at the original speed
+    pub fn duration(&self) -> Duration {
+        match (self.records.first(), self.records.last()) {
+            (Some(first), Some(last)) => last.timestamp.saturating_sub(first.timestamp),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Send the frames through a started device
+    pub fn run(&self, dev: &mut GsUsb) -> Result<ReplayStats> {
+        let mut stats = ReplayStats::default();
+        let Some(first) = self.records.first().map(|record| record.timestamp) else {
+            return Ok(stats);
+        };
+        let start = Instant::now();
+        for record in &self.records {
+            let Some(channel) = self.device_channel(record) else {
+                stats.skipped += 1;
+                continue;
+            };
+            if self.speed > 0.0 {
+                let logged = record.timestamp.saturating_sub(first);
+                let due = Duration::try_from_secs_f64(logged.as_secs_f64() / self.speed)
+                    .ok()
+                    .and_then(|offset| start.checked_add(offset))
+                    .ok_or_else(|| {
+                        GsUsbError::InvalidConfig(format!(
+                            "replay speed {} is too slow for a {logged:?} log",
+                            self.speed
+                        ))
+                    })?;
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                } else {
+                    stats.max_lag = stats.max_lag.max(now - due);
+                }
+            }
+            let mut frame = record.frame.clone();
+            frame.channel = channel;
+            dev.send(&frame)?;
+            stats.sent += 1;
+        }
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+
+    /// Device channel to send a record on, `None` to skip it
+    fn device_channel(&self, record: &LogRecord) -> Option<u8> {
+        if record.frame.is_error_frame() {
+            return None;
+        }
+        if self.channels.is_empty() {
+            return Some(0);
+        }
+        self.channels.get(&record.frame.channel).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::logfile::parse_candump;
+    use crate::mock::MockGsUsb;
+
+    const LOG: &str = "(100.000) can0 100#01\n\
+                       (100.010) can1 200#02\n\
+                       (100.020) can0 20000004#0000000000000000\n\
+                       (100.030) can1 300#03\n";
+
+    #[test]
+    fn test_replay() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+
+        let replay = Replay::new(parse_candump(LOG).unwrap()).speed(0.0);
+        assert_eq!(replay.duration(), Duration::from_millis(30));
+        let stats = replay.run(&mut dev).unwrap();
+        assert_eq!((stats.sent, stats.skipped), (3, 1));
+        let ids: Vec<u32> = mock
+            .take_sent_frames()
+            .iter()
+            .map(|f| f.arbitration_id())
+            .collect();
+        assert_eq!(ids, [0x100, 0x200, 0x300]);
+
+        let start = Instant::now();
+        let stats = replay.speed(1.0).map_channel(1, 0).run(&mut dev).unwrap();
+        assert_eq!((stats.sent, stats.skipped), (2, 2));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent[1].arbitration_id(), 0x300);
+        assert_eq!(sent[1].channel, 0);
+    }
+
+    #[test]
+    fn test_speed() {
+        let replay = Replay::new(parse_candump(LOG).unwrap());
+        assert_eq!(replay.clone().speed(f64::NAN).speed, 0.0);
+        assert_eq!(replay.clone().speed(-1.0).speed, 0.0);
+        assert_eq!(replay.clone().speed(1e-20).speed, Replay::MIN_SPEED);
+
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        let stats = replay.speed(f64::INFINITY).run(&mut dev).unwrap();
+        assert_eq!(stats.sent, 3);
+
+        let mut records = parse_candump(LOG).unwrap();
+        records[1].timestamp = Duration::MAX;
+        let replay = Replay::new(records).speed(Replay::MIN_SPEED);
+        assert!(matches!(
+            replay.run(&mut dev),
+            Err(GsUsbError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+//! Retry policy for control requests
+//!
+//! Some adapters (e.g. several candleLight firmware builds) intermittently
+//! stall `BT_CONST` or `DEVICE_CONFIG` right after a reset. A `RetryPolicy`
+//! set with `GsUsb::set_retry_policy()` repeats control requests that fail
+//! with a recoverable error (see `GsUsbError::is_recoverable()`), waiting
+//! with exponential backoff between attempts.
+
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::error::Result;
+
+/// How often and how patiently to repeat failed control requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub attempts: u32,
+    /// Wait before the second attempt
+    pub backoff: Duration,
+    /// Factor the wait grows by after every further failure
+    pub multiplier: u32,
+    /// Upper bound for the wait between two attempts
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Single attempt, no retries
+    pub const NONE: Self = Self {
+        attempts: 1,
+        backoff: Duration::ZERO,
+        multiplier: 1,
+        max_backoff: Duration::ZERO,
+    };
+
+    /// Retry up to `attempts` times in total, doubling the wait from `backoff`
+    /// up to one second
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        Self {
+            attempts,
+            backoff,
+            multiplier: 2,
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Wait before the given retry (1 = first retry)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(retry.saturating_sub(1));
+        self.backoff
+            .checked_mul(factor)
+            .unwrap_or(Duration::MAX)
+            .min(self.max_backoff)
+    }
+
+    /// Run `op` until it succeeds, fails with an error that isn't
+    /// recoverable, or the attempts are used up
+    pub fn run<T>(&self, clock: &dyn Clock, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut retry = 0;
+        loop {
+            match op() {
+                Err(e) if e.is_recoverable() && retry + 1 < self.attempts => {
+                    retry += 1;
+                    log::debug!("Control request failed ({e}), retry {retry}");
+                    clock.sleep(self.delay(retry));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::constants::{GS_CAN_MODE_NORMAL, GS_USB_BREQ_BT_CONST};
+    use crate::error::GsUsbError;
+    use crate::mock::MockGsUsb;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(300));
+        assert_eq!(policy.delay(1), Duration::from_millis(300));
+        assert_eq!(policy.delay(2), Duration::from_millis(600));
+        assert_eq!(policy.delay(3), Duration::from_secs(1));
+
+        let clock = TestClock::new();
+        let start = clock.now();
+        let mut calls = 0;
+        let result: Result<()> = policy.run(&clock, || {
+            calls += 1;
+            Err(GsUsbError::ControlTransfer {
+                request: GS_USB_BREQ_BT_CONST,
+                value: 0,
+                length: 40,
+                source: rusb::Error::Pipe,
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 5);
+        assert_eq!(
+            clock.now() - start,
+            Duration::from_millis(300 + 600 + 1000 + 1000)
+        );
+
+        // Errors that can't go away by retrying are returned at once
+        calls = 0;
+        let _ = policy.run(&clock, || -> Result<()> {
+            calls += 1;
+            Err(GsUsbError::FdNotSupported)
+        });
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_start_retries_stalled_bt_const() {
+        let mock = MockGsUsb::new();
+        mock.queue_response(GS_USB_BREQ_BT_CONST, Err(rusb::Error::Pipe));
+        assert!(mock.open().start(GS_CAN_MODE_NORMAL).is_err());
+
+        mock.queue_response(GS_USB_BREQ_BT_CONST, Err(rusb::Error::Pipe));
+        mock.queue_response(GS_USB_BREQ_BT_CONST, Err(rusb::Error::Timeout));
+        let mut dev = mock.open();
+        dev.set_retry_policy(RetryPolicy::new(3, Duration::from_millis(1)));
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        assert!(mock.is_started());
+    }
+}
@@ -0,0 +1,158 @@
+//! Scripted device behavior for `MockGsUsb`
+//!
+//! A `Scenario` is a timeline of events (received frames, bus state changes,
+//! disconnects) that a `MockGsUsb` plays back once the device is started. This
+//! makes reconnection, watchdog and error recovery logic testable with fully
+//! deterministic input.
+//!
+//! Event times are relative to the first start of the device and become the
+//! hardware timestamps of the emitted frames. By default scenarios run in
+//! fast-forward: whenever the device would have to wait for the next event,
+//! the scenario clock jumps to it, so tests don't sleep. `realtime()` plays
+//! the events back against the wall clock instead.
+//!
+//! Available with the `test-util` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use gs_usb::{GsUsbFrame, MockGsUsb, Scenario, GS_CAN_MODE_NORMAL, GS_CAN_STATE_BUS_OFF};
+//! use std::time::Duration;
+//!
+//! let scenario = Scenario::new()
+//!     .periodic(Duration::ZERO, Duration::from_millis(10), 100, GsUsbFrame::with_data(0x100, &[0]))
+//!     .bus_off(Duration::from_millis(1000))
+//!     .recover(Duration::from_millis(1500));
+//!
+//! let mock = MockGsUsb::new();
+//! mock.play(scenario);
+//! let mut dev = mock.open();
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! for _ in 0..100 {
+//!     assert_eq!(dev.read(Duration::from_millis(10))?.arbitration_id(), 0x100);
+//! }
+//! assert!(dev.read(Duration::from_millis(10))?.is_error_frame());
+//! assert_eq!(dev.get_state(0)?.state, GS_CAN_STATE_BUS_OFF);
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::time::Duration;
+
+use crate::frame::GsUsbFrame;
+use crate::structures::DeviceState;
+
+/// An event on a scenario timeline
+#[derive(Debug, Clone)]
+pub enum ScenarioEvent {
+    /// Receive a frame (dropped if the device is not started)
+    Frame(GsUsbFrame),
+    /// Deliver a bulk IN transfer verbatim (dropped if the device is not started)
+    RawRx(Vec<u8>),
+    /// Fail the next bulk IN transfer with the given error
+    RxError(rusb::Error),
+    /// Change the state reported by `GET_STATE`
+    State(DeviceState),
+    /// Enter bus-off: report `BUS_OFF` and emit a bus-off error frame
+    BusOff,
+    /// Recover from bus-off: report `ERROR_ACTIVE` with cleared error
+    /// counters and emit a restarted error frame
+    Recover,
+    /// Unplug the device: every transfer fails with `rusb::Error::NoDevice`
+    Disconnect,
+    /// Plug the device back in; it has to be started again
+    Reconnect,
+}
+
+/// Timeline of device events played back by `MockGsUsb::play()`
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub(crate) steps: Vec<(Duration, ScenarioEvent)>,
+    pub(crate) realtime: bool,
+}
+
+impl Scenario {
+    /// Create an empty fast-forward scenario
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Play the scenario against the wall clock instead of fast-forwarding
+    pub fn realtime(mut self) -> Self {
+        self.realtime = true;
+        self
+    }
+
+    /// Add an event at the given time after start
+    ///
+    /// Events with the same time are played in the order they were added.
+    pub fn at(mut self, at: Duration, event: ScenarioEvent) -> Self {
+        let index = self.steps.partition_point(|(t, _)| *t <= at);
+        self.steps.insert(index, (at, event));
+        self
+    }
+
+    /// Receive a frame at the given time
+    pub fn frame(self, at: Duration, frame: GsUsbFrame) -> Self {
+        self.at(at, ScenarioEvent::Frame(frame))
+    }
+
+    /// Receive several frames at the given times
+    pub fn frames(self, frames: impl IntoIterator<Item = (Duration, GsUsbFrame)>) -> Self {
+        frames
+            .into_iter()
+            .fold(self, |scenario, (at, frame)| scenario.frame(at, frame))
+    }
+
+    /// Receive `count` copies of a frame every `period`, starting at `start`
+    pub fn periodic(
+        self,
+        start: Duration,
+        period: Duration,
+        count: u32,
+        frame: GsUsbFrame,
+    ) -> Self {
+        self.frames((0..count).map(|i| (start + period * i, frame.clone())))
+    }
+
+    /// Change the state reported by `GET_STATE` at the given time
+    pub fn state(self, at: Duration, state: DeviceState) -> Self {
+        self.at(at, ScenarioEvent::State(state))
+    }
+
+    /// Enter bus-off at the given time
+    pub fn bus_off(self, at: Duration) -> Self {
+        self.at(at, ScenarioEvent::BusOff)
+    }
+
+    /// Recover from bus-off at the given time
+    pub fn recover(self, at: Duration) -> Self {
+        self.at(at, ScenarioEvent::Recover)
+    }
+
+    /// Unplug the device at the given time
+    pub fn disconnect(self, at: Duration) -> Self {
+        self.at(at, ScenarioEvent::Disconnect)
+    }
+
+    /// Plug the device back in at the given time
+    pub fn reconnect(self, at: Duration) -> Self {
+        self.at(at, ScenarioEvent::Reconnect)
+    }
+
+    /// Time of the last event
+    pub fn duration(&self) -> Duration {
+        self.steps.last().map(|(t, _)| *t).unwrap_or_default()
+    }
+
+    /// Number of events
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Whether the scenario has no events
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
@@ -0,0 +1,201 @@
+//! Segmentation of payloads longer than a classic frame
+//!
+//! For two endpoints both using this crate that need to exchange a few
+//! dozen bytes over classic CAN without the flow control and timing rules
+//! of ISO-TP. `segment()` splits a payload of up to 127 bytes into frames
+//! of one identifier, each starting with a header byte:
+//!
+//! | Frame       | Bit 7 | Bits 0-6                     | Bytes 1-7         |
+//! |-------------|-------|------------------------------|-------------------|
+//! | First       | 1     | payload length               | payload start     |
+//! | Consecutive | 0     | sequence number, 1 upwards   | next 7 bytes      |
+//!
+//! The last frame is only as long as needed. On the receiving side a
+//! `Desegmenter` reassembles the payloads per identifier (see
+//! `Reassembler`), dropping transfers with lost frames.
+//!
+//! A `Gateway` fragmenting FD frames for a classic bus
+//! (`FdToClassic::Fragment`) uses the same scheme, so its fragments are
+//! reassembled with a `Desegmenter` as well.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{segment, Desegmenter, GsUsb, Reassembly, Segmented, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! for frame in segment(0x600, b"a payload longer than eight bytes")? {
+//!     dev.send(&frame)?;
+//! }
+//!
+//! let mut desegmenter = Desegmenter::new(Segmented);
+//! loop {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     for event in desegmenter.observe(&frame) {
+//!         if let Reassembly::Complete(message) = event {
+//!             println!("{:03X}: {:02X?}", message.key, message.data);
+//!         }
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use crate::clock::SystemClock;
+use crate::constants::CAN_MAX_DLEN;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::reassembly::{Framing, Reassembler, Segment};
+
+/// Longest payload `segment()` can split
+pub const MAX_SEGMENTED_LEN: usize = 127;
+
+/// Header bit marking the first frame of a segmented payload
+pub const SEGMENT_FIRST: u8 = 0x80;
+
+/// Payload bytes carried by each frame after the header
+pub const SEGMENT_PAYLOAD_LEN: usize = CAN_MAX_DLEN - 1;
+
+/// Split `payload` into frames with identifier `can_id`, see the module
+/// documentation
+///
+/// Fails with `InvalidConfig` for payloads longer than
+/// `MAX_SEGMENTED_LEN`.
+pub fn segment(can_id: u32, payload: &[u8]) -> Result<Vec<GsUsbFrame>> {
+    if payload.len() > MAX_SEGMENTED_LEN {
+        return Err(GsUsbError::InvalidConfig(format!(
+            "payload of {} bytes, at most {MAX_SEGMENTED_LEN} can be segmented",
+            payload.len()
+        )));
+    }
+    let mut frames = Vec::new();
+    let mut chunks = payload.chunks(SEGMENT_PAYLOAD_LEN);
+    let first = chunks.next().unwrap_or_default();
+    frames.push(frame(can_id, SEGMENT_FIRST | payload.len() as u8, first));
+    for (seq, chunk) in (1..).zip(chunks) {
+        frames.push(frame(can_id, seq, chunk));
+    }
+    Ok(frames)
+}
+
+fn frame(can_id: u32, header: u8, chunk: &[u8]) -> GsUsbFrame {
+    let mut data = Vec::with_capacity(chunk.len() + 1);
+    data.push(header);
+    data.extend_from_slice(chunk);
+    GsUsbFrame::with_data(can_id, &data)
+}
+
+/// `Framing` of segmented payloads, keyed by CAN identifier
+///
+/// Echoes of sent frames, error and remote frames are ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Segmented;
+
+impl Framing for Segmented {
+    type Key = u32;
+
+    fn classify<'a>(&self, frame: &'a GsUsbFrame) -> Segment<'a, u32> {
+        if frame.is_echo_frame() || frame.is_error_frame() || frame.is_remote_frame() {
+            return Segment::Ignore;
+        }
+        let data = frame.data();
+        let Some((&header, rest)) = data.split_first() else {
+            return Segment::Ignore;
+        };
+        let key = frame.can_id;
+        if header & SEGMENT_FIRST != 0 {
+            Segment::First {
+                key,
+                total_len: usize::from(header & !SEGMENT_FIRST),
+                seq: 0,
+                data: rest,
+            }
+        } else {
+            Segment::Consecutive {
+                key,
+                seq: u32::from(header),
+                data: rest,
+            }
+        }
+    }
+
+    fn sequence_modulo(&self) -> u32 {
+        128
+    }
+}
+
+/// Reassembles segmented payloads
+pub type Desegmenter<C = SystemClock> = Reassembler<Segmented, C>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_USB_RX_ECHO_ID;
+    use crate::reassembly::{AbortReason, Reassembly};
+
+    /// The frames of `payload` as received from another node
+    fn received(can_id: u32, payload: &[u8]) -> Vec<GsUsbFrame> {
+        let mut frames = segment(can_id, payload).unwrap();
+        for frame in &mut frames {
+            frame.echo_id = GS_USB_RX_ECHO_ID;
+        }
+        frames
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let payload: Vec<u8> = (0..20).collect();
+        let frames = received(0x600, &payload);
+        let lengths: Vec<_> = frames.iter().map(GsUsbFrame::data_length).collect();
+        assert_eq!(lengths, [8, 8, 7]);
+        assert_eq!(frames[0].data()[0], 0x80 | 20);
+        assert_eq!(frames[2].data()[0], 2);
+
+        let mut desegmenter = Desegmenter::new(Segmented);
+        // Echoes of frames sent are not received
+        assert!(desegmenter
+            .observe(&segment(0x600, &payload).unwrap()[0])
+            .is_empty());
+        assert_eq!(desegmenter.in_progress(), 0);
+        assert!(desegmenter.observe(&frames[0]).is_empty());
+        assert!(desegmenter.observe(&frames[1]).is_empty());
+        let events = desegmenter.observe(&frames[2]);
+        assert!(matches!(
+            &events[..],
+            [Reassembly::Complete(message)] if message.data == payload && message.key == 0x600
+        ));
+
+        // Short and empty payloads take one frame
+        for payload in [&b""[..], b"1234567"] {
+            let frames = received(0x601, payload);
+            assert_eq!(frames.len(), 1);
+            assert!(matches!(
+                &desegmenter.observe(&frames[0])[..],
+                [Reassembly::Complete(message)] if message.data == payload
+            ));
+        }
+        assert!(segment(0x600, &[0; 128]).is_err());
+        assert_eq!(segment(0x600, &[0; 127]).unwrap().len(), 19);
+    }
+
+    #[test]
+    fn test_lost_frame() {
+        let frames = received(0x600, &[0; 30]);
+        let mut desegmenter = Desegmenter::new(Segmented);
+        desegmenter.observe(&frames[0]);
+        let events = desegmenter.observe(&frames[2]);
+        assert!(matches!(
+            events[..],
+            [Reassembly::Aborted {
+                reason: AbortReason::Sequence {
+                    expected: 1,
+                    actual: 2
+                },
+                ..
+            }]
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+//! Cloneable device handle
+//!
+//! `SharedGsUsb` lets several threads use one started device: sends are
+//! serialized on the device, and a reader thread hands every received frame
+//! to every handle. Cloning a handle is cheap and gives the clone its own
+//! receive queue, so the bus can be passed around like a channel sender.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, GsUsbFrame, SharedGsUsb, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//! let bus = SharedGsUsb::new(dev);
+//!
+//! let heartbeat = bus.clone();
+//! std::thread::spawn(move || loop {
+//!     heartbeat.send(&GsUsbFrame::with_data(0x700, &[0x05])).unwrap();
+//!     std::thread::sleep(Duration::from_secs(1));
+//! });
+//!
+//! loop {
+//!     let frame = bus.recv(Duration::from_secs(5))?;
+//!     println!("{frame}");
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::device::GsUsb;
+use crate::error::{ErrorKind, GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+use crate::sync::lock;
+
+/// Read timeout of the reader thread, between checks for shutdown
+const READER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Default number of frames queued per handle
+pub const DEFAULT_QUEUE_CAPACITY: usize = 4096;
+
+/// Receive queue of one handle, as seen by the reader thread
+struct Subscriber {
+    queue: SyncSender<GsUsbFrame>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// State shared by all handles of a device
+struct Shared {
+    device: Arc<Mutex<GsUsb>>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    capacity: usize,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            if reader.join().is_err() {
+                log::warn!("Shared device reader panicked");
+            }
+        }
+    }
+}
+
+/// A started device shared by cloneable handles
+///
+/// Every handle receives every frame read from the device, including the
+/// echoes of frames sent through any handle, from the time it was created
+/// on. A handle whose queue is full misses frames (see `dropped()`) rather
+/// than holding up the others. Error frames are received as frames; don't
+/// enable `set_error_frames_as_errors()` on a shared device.
+///
+/// The device is dropped, and so stopped, with the last handle.
+pub struct SharedGsUsb {
+    shared: Arc<Shared>,
+    frames: Receiver<GsUsbFrame>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl SharedGsUsb {
+    /// Share a started device, queueing `DEFAULT_QUEUE_CAPACITY` frames per handle
+    pub fn new(dev: GsUsb) -> Self {
+        Self::with_queue_capacity(dev, DEFAULT_QUEUE_CAPACITY)
+    }
+
+    /// Share a started device, queueing up to `capacity` frames per handle
+    pub fn with_queue_capacity(dev: GsUsb, capacity: usize) -> Self {
+        let device = Arc::new(Mutex::new(dev));
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = spawn_reader(Arc::clone(&device), Arc::clone(&stop), {
+            let subscribers = Arc::clone(&subscribers);
+            let stop = Arc::clone(&stop);
+            move |result| fan_out(&subscribers, &stop, result)
+        });
+        let shared = Arc::new(Shared {
+            device,
+            subscribers,
+            capacity: capacity.max(1),
+            stop,
+            reader: Some(reader),
+        });
+        Self::subscribe(shared)
+    }
+
+    /// A handle with a new receive queue
+    fn subscribe(shared: Arc<Shared>) -> Self {
+        let (queue, frames) = mpsc::sync_channel(shared.capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut subscribers = lock(&shared.subscribers);
+        // Without a reader, the queue starts out disconnected
+        if !shared.stop.load(Ordering::Relaxed) {
+            subscribers.push(Subscriber {
+                queue,
+                dropped: Arc::clone(&dropped),
+            });
+        }
+        drop(subscribers);
+        Self {
+            shared,
+            frames,
+            dropped,
+        }
+    }
+
+    /// Send a frame
+    pub fn send(&self, frame: &GsUsbFrame) -> Result<()> {
+        lock(&self.shared.device).send(frame)
+    }
+
+    /// Receive the next frame for this handle
+    ///
+    /// Fails with `GsUsbError::ReadTimeout` if none arrives within
+    /// `timeout`, and with `GsUsbError::DeviceNotOpen` once the reader
+    /// stopped after a device error and the queue is empty.
+    pub fn recv(&self, timeout: Duration) -> Result<GsUsbFrame> {
+        match self.frames.recv_timeout(timeout) {
+            Ok(frame) => Ok(frame),
+            Err(RecvTimeoutError::Timeout) => Err(GsUsbError::ReadTimeout),
+            Err(RecvTimeoutError::Disconnected) => Err(GsUsbError::DeviceNotOpen),
+        }
+    }
+
+    /// Receive the frames queued for this handle, up to `max_frames`,
+    /// waiting up to `timeout` for the first
+    ///
+    /// Returns an empty batch if none arrives, and fails like `recv()` once
+    /// the reader stopped.
+    pub fn recv_many(&self, max_frames: usize, timeout: Duration) -> Result<Vec<GsUsbFrame>> {
+        if max_frames == 0 {
+            return Ok(Vec::new());
+        }
+        let first = match self.recv(timeout) {
+            Ok(frame) => frame,
+            Err(GsUsbError::ReadTimeout) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut frames = vec![first];
+        frames.extend(self.frames.try_iter().take(max_frames - 1));
+        Ok(frames)
+    }
+
+    /// The next frame for this handle if one is queued
+    pub fn try_recv(&self) -> Option<GsUsbFrame> {
+        self.frames.try_recv().ok()
+    }
+
+    /// Frames this handle missed because its queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Run requests on the device, e.g. `get_state()`
+    ///
+    /// Receiving pauses while `f` runs.
+    pub fn with_device<T>(&self, f: impl FnOnce(&mut GsUsb) -> T) -> T {
+        f(&mut lock(&self.shared.device))
+    }
+
+    /// Number of handles of the device
+    pub fn handle_count(&self) -> usize {
+        Arc::strong_count(&self.shared)
+    }
+}
+
+impl Clone for SharedGsUsb {
+    /// A handle of the same device with its own, empty receive queue
+    fn clone(&self) -> Self {
+        Self::subscribe(Arc::clone(&self.shared))
+    }
+}
+
+/// Read a device on a new thread until `stop` is set, passing every
+/// frame and error except read timeouts to `deliver`
+///
+/// Reading ends when `deliver` returns false and after any error that isn't
+/// transient. `AsyncGsUsb` and the uniffi `Adapter` listener read through
+/// this as well.
+pub(crate) fn spawn_reader(
+    device: Arc<Mutex<GsUsb>>,
+    stop: Arc<AtomicBool>,
+    mut deliver: impl FnMut(Result<GsUsbFrame>) -> bool + Send + 'static,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            // Read with a short timeout, so sends get the lock in between
+            let result = lock(&device).read(READER_POLL_INTERVAL);
+            if let Err(GsUsbError::ReadTimeout) = result {
+                continue;
+            }
+            let fatal = result
+                .as_ref()
+                .is_err_and(|e| e.kind() != ErrorKind::Transient);
+            if !deliver(result) || fatal {
+                return;
+            }
+        }
+    })
+}
+
+/// Queue a read result for every handle, false once reading must stop
+fn fan_out(
+    subscribers: &Mutex<Vec<Subscriber>>,
+    stop: &AtomicBool,
+    result: Result<GsUsbFrame>,
+) -> bool {
+    let frame = match result {
+        Ok(frame) => frame,
+        Err(e) if e.kind() == ErrorKind::Transient => {
+            log::debug!("Shared device: {e}");
+            return true;
+        }
+        Err(e) => {
+            log::warn!("Shared device reader stopped: {e}");
+            // Disconnects the handles once their queues are empty
+            let mut subscribers = lock(subscribers);
+            stop.store(true, Ordering::Relaxed);
+            subscribers.clear();
+            return false;
+        }
+    };
+    lock(subscribers).retain(
+        |subscriber| match subscriber.queue.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            // The handle is gone
+            Err(TrySendError::Disconnected(_)) => false,
+        },
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::virtual_bus::VirtualBus;
+
+    fn started(bus: &VirtualBus) -> GsUsb {
+        let mut dev = bus.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        dev
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let bus = VirtualBus::new();
+        let mut other = started(&bus);
+        let a = SharedGsUsb::new(started(&bus));
+        let b = a.clone();
+        assert_eq!(a.handle_count(), 2);
+
+        other.send(&GsUsbFrame::with_data(0x100, &[1])).unwrap();
+        let timeout = Duration::from_millis(100);
+        assert!(other.read(timeout).unwrap().is_echo_frame());
+        assert_eq!(a.recv(timeout).unwrap().arbitration_id(), 0x100);
+        assert_eq!(b.recv(timeout).unwrap().arbitration_id(), 0x100);
+
+        // Sends from any thread; every handle sees the echoes
+        std::thread::scope(|scope| {
+            let c = b.clone();
+            scope.spawn(move || c.send(&GsUsbFrame::with_data(0x200, &[2])).unwrap());
+        });
+        assert_eq!(other.read(timeout).unwrap().arbitration_id(), 0x200);
+        assert!(a.recv(timeout).unwrap().is_echo_frame());
+        assert!(b.recv(timeout).unwrap().is_echo_frame());
+        assert_eq!(a.handle_count(), 2);
+
+        let state = a.with_device(|dev| dev.get_state(0)).unwrap();
+        assert_eq!(state.state_name(), "ERROR_ACTIVE");
+    }
+
+    #[test]
+    fn test_full_queue_drops() {
+        let bus = VirtualBus::new();
+        let mut other = started(&bus);
+        let slow = SharedGsUsb::with_queue_capacity(started(&bus), 2);
+        let fast = slow.clone();
+
+        let timeout = Duration::from_millis(100);
+        for i in 0..4 {
+            other.send(&GsUsbFrame::with_data(0x100 + i, &[])).unwrap();
+            assert_eq!(fast.recv(timeout).unwrap().arbitration_id(), 0x100 + i);
+        }
+        assert_eq!(slow.recv(timeout).unwrap().arbitration_id(), 0x100);
+        assert_eq!(slow.recv(timeout).unwrap().arbitration_id(), 0x101);
+        assert!(slow.try_recv().is_none());
+        assert_eq!((slow.dropped(), fast.dropped()), (2, 0));
+    }
+
+    #[test]
+    fn test_recv_many() {
+        let bus = VirtualBus::new();
+        let mut other = started(&bus);
+        let shared = SharedGsUsb::new(started(&bus));
+        let timeout = Duration::from_millis(100);
+        assert!(shared
+            .recv_many(8, Duration::from_millis(10))
+            .unwrap()
+            .is_empty());
+
+        for i in 0..3 {
+            other.send(&GsUsbFrame::with_data(0x100 + i, &[])).unwrap();
+            other.read(timeout).unwrap();
+        }
+        // The reader thread may still be queueing
+        let mut frames = shared.recv_many(2, timeout).unwrap();
+        assert!(!frames.is_empty() && frames.len() <= 2);
+        while frames.len() < 3 {
+            frames.extend(shared.recv_many(8, timeout).unwrap());
+        }
+        let ids: Vec<_> = frames.iter().map(|f| f.arbitration_id()).collect();
+        assert_eq!(ids, [0x100, 0x101, 0x102]);
+    }
+}
@@ -0,0 +1,481 @@
+//! Bit-level signals in payloads
+//!
+//! A `Signal` describes a value packed into a payload the way DBC files do:
+//! start bit, length, byte order, signedness, and the factor and offset
+//! converting the raw value to a physical one. It extracts and inserts
+//! values without a DBC file, for quick scripts that poke at unknown
+//! traffic.
+//!
+//! Bits are numbered as in DBC files, bit 0 being the least significant
+//! bit of byte 0 and bit 8 the least significant bit of byte 1. An Intel
+//! (little endian) signal starts at its least significant bit and runs
+//! upwards; a Motorola (big endian) signal starts at its most significant
+//! bit and runs down through a byte, then on at the top of the next byte.
+//!
+//! A `MessageLayout` names the signals of a message to decode and encode
+//! them together. Multiplexed messages carry a multiplexor signal whose
+//! value selects which group of the other signals the payload holds, as
+//! battery management systems do to report many cell voltages under one
+//! identifier.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, Signal, GS_CAN_MODE_LISTEN_ONLY};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_LISTEN_ONLY)?;
+//!
+//! // Engine speed: 16 bits Motorola from bit 7, 0.25 rpm per bit
+//! let rpm = Signal::motorola(7, 16).scaled(0.25, 0.0);
+//! loop {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     if frame.arbitration_id() == 0x0C0 {
+//!         println!("{:.0} rpm", rpm.decode(frame.data())?);
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use crate::error::{GsUsbError, Result};
+
+/// Order of the bytes of a signal spanning several
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// Intel, least significant byte first
+    #[default]
+    LittleEndian,
+    /// Motorola, most significant byte first
+    BigEndian,
+}
+
+/// A value packed into a payload, see the module documentation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signal {
+    /// First bit: the least significant one for Intel signals, the most
+    /// significant one for Motorola signals
+    pub start_bit: u16,
+    /// Length in bits, 1 to 64
+    pub length: u8,
+    /// Byte order
+    pub byte_order: ByteOrder,
+    /// Whether the raw value is two's complement
+    pub signed: bool,
+    /// Physical value of one raw step
+    pub factor: f64,
+    /// Physical value of raw 0
+    pub offset: f64,
+}
+
+impl Signal {
+    /// Unsigned Intel signal of `length` bits from `start_bit`
+    pub fn intel(start_bit: u16, length: u8) -> Self {
+        Self {
+            start_bit,
+            length,
+            byte_order: ByteOrder::LittleEndian,
+            signed: false,
+            factor: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    /// Unsigned Motorola signal of `length` bits, `start_bit` being its most
+    /// significant bit
+    pub fn motorola(start_bit: u16, length: u8) -> Self {
+        Self {
+            byte_order: ByteOrder::BigEndian,
+            ..Self::intel(start_bit, length)
+        }
+    }
+
+    /// Make the raw value two's complement
+    pub fn signed(mut self) -> Self {
+        self.signed = true;
+        self
+    }
+
+    /// Convert raw values to physical ones as `raw * factor + offset`
+    pub fn scaled(mut self, factor: f64, offset: f64) -> Self {
+        self.factor = factor;
+        self.offset = offset;
+        self
+    }
+
+    /// Raw bits of the signal in `data`
+    ///
+    /// Fails with `InvalidSignal` if the signal doesn't fit in `data`.
+    pub fn extract(&self, data: &[u8]) -> Result<u64> {
+        let positions = self.positions(data.len())?;
+        Ok(positions.into_iter().fold(0, |raw, bit| {
+            (raw << 1) | u64::from(data[bit / 8] >> (bit % 8) & 1)
+        }))
+    }
+
+    /// Raw value of the signal in `data`, sign extended if signed
+    pub fn decode_raw(&self, data: &[u8]) -> Result<i128> {
+        let raw = self.extract(data)?;
+        let length = u32::from(self.length);
+        Ok(if self.signed && raw >> (length - 1) & 1 == 1 {
+            i128::from(raw) - (1i128 << length)
+        } else {
+            i128::from(raw)
+        })
+    }
+
+    /// Physical value of the signal in `data`
+    pub fn decode(&self, data: &[u8]) -> Result<f64> {
+        Ok(self.decode_raw(data)? as f64 * self.factor + self.offset)
+    }
+
+    /// Write the raw bits of the signal into `data`, leaving the other bits
+    /// as they are
+    ///
+    /// Bits of `raw` above the signal's length are ignored.
+    pub fn insert(&self, data: &mut [u8], raw: u64) -> Result<()> {
+        let positions = self.positions(data.len())?;
+        for (i, bit) in positions.iter().rev().enumerate() {
+            let mask = 1 << (bit % 8);
+            if raw >> i & 1 == 1 {
+                data[bit / 8] |= mask;
+            } else {
+                data[bit / 8] &= !mask;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a raw value into `data`
+    ///
+    /// Fails with `InvalidSignal` if it is out of the signal's range.
+    pub fn encode_raw(&self, data: &mut [u8], raw: i128) -> Result<()> {
+        let (min, max) = self.raw_range();
+        if !(min..=max).contains(&raw) {
+            return Err(GsUsbError::InvalidSignal(format!(
+                "raw value {raw} outside {min} to {max}"
+            )));
+        }
+        self.insert(data, raw as u64)
+    }
+
+    /// Write a physical value into `data`, rounded to the nearest raw step
+    ///
+    /// Fails with `InvalidSignal` if it is out of the signal's range.
+    pub fn encode(&self, data: &mut [u8], value: f64) -> Result<()> {
+        let raw = ((value - self.offset) / self.factor).round();
+        if !raw.is_finite() {
+            return Err(GsUsbError::InvalidSignal(format!(
+                "value {value} can't be encoded with factor {}",
+                self.factor
+            )));
+        }
+        let (min, max) = self.raw_range();
+        if raw < min as f64 || raw > max as f64 {
+            return Err(GsUsbError::InvalidSignal(format!(
+                "value {value} outside {} to {}",
+                self.physical(min),
+                self.physical(max)
+            )));
+        }
+        self.encode_raw(data, raw as i128)
+    }
+
+    /// Smallest and largest raw value
+    pub fn raw_range(&self) -> (i128, i128) {
+        let length = u32::from(self.length.clamp(1, 64));
+        if self.signed {
+            (-(1i128 << (length - 1)), (1i128 << (length - 1)) - 1)
+        } else {
+            (0, (1i128 << length) - 1)
+        }
+    }
+
+    fn physical(&self, raw: i128) -> f64 {
+        raw as f64 * self.factor + self.offset
+    }
+
+    /// Bit positions from the most to the least significant bit
+    fn positions(&self, len: usize) -> Result<Vec<usize>> {
+        if !(1..=64).contains(&self.length) {
+            return Err(GsUsbError::InvalidSignal(format!(
+                "length of {} bits, must be 1 to 64",
+                self.length
+            )));
+        }
+        let start = usize::from(self.start_bit);
+        let length = usize::from(self.length);
+        let positions: Vec<usize> = match self.byte_order {
+            ByteOrder::LittleEndian => (start..start + length).rev().collect(),
+            ByteOrder::BigEndian => {
+                let mut bit = start;
+                let mut positions = Vec::with_capacity(length);
+                for _ in 0..length {
+                    positions.push(bit);
+                    bit = if bit % 8 == 0 { bit + 15 } else { bit - 1 };
+                }
+                positions
+            }
+        };
+        if positions.iter().any(|&bit| bit >= 8 * len) {
+            return Err(GsUsbError::InvalidSignal(format!(
+                "{} bit signal at bit {start} beyond a payload of {len} bytes",
+                self.length
+            )));
+        }
+        Ok(positions)
+    }
+}
+
+/// A signal of a `MessageLayout`
+#[derive(Debug, Clone, PartialEq)]
+struct Named {
+    name: String,
+    signal: Signal,
+    /// Multiplexor value selecting the signal, `None` if always present
+    group: Option<u64>,
+}
+
+/// The named signals of a message, optionally multiplexed
+///
+/// # Example
+///
+/// ```
+/// use gs_usb::{MessageLayout, Signal};
+///
+/// // Byte 0 selects which three cells bytes 1-6 report, in millivolts
+/// let mut layout = MessageLayout::new()
+///     .signal("soc", Signal::intel(56, 8).scaled(0.5, 0.0))
+///     .multiplexor("block", Signal::intel(0, 8));
+/// for block in 0..4u64 {
+///     for cell in 0..3u64 {
+///         let name = format!("cell{}", block * 3 + cell);
+///         let start = 8 + 16 * cell as u16;
+///         layout = layout.multiplexed(&name, block, Signal::intel(start, 16));
+///     }
+/// }
+///
+/// let mut data = [0; 8];
+/// layout.encode(&mut data, &[("cell4", 3312.0), ("soc", 80.0)])?;
+/// assert_eq!(data[0], 1);
+/// let values = layout.decode(&data)?;
+/// assert_eq!(values[..3], [("soc", 80.0), ("block", 1.0), ("cell3", 0.0)]);
+/// # Ok::<(), gs_usb::GsUsbError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageLayout {
+    signals: Vec<Named>,
+    multiplexor: Option<(String, Signal)>,
+}
+
+impl MessageLayout {
+    /// Layout without signals
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a signal present in every payload
+    pub fn signal(mut self, name: &str, signal: Signal) -> Self {
+        self.signals.push(Named {
+            name: name.to_string(),
+            signal,
+            group: None,
+        });
+        self
+    }
+
+    /// Set the multiplexor, whose raw value selects the signal group
+    pub fn multiplexor(mut self, name: &str, signal: Signal) -> Self {
+        self.multiplexor = Some((name.to_string(), signal));
+        self
+    }
+
+    /// Add a signal present only when the multiplexor's raw value is `group`
+    pub fn multiplexed(mut self, name: &str, group: u64, signal: Signal) -> Self {
+        self.signals.push(Named {
+            name: name.to_string(),
+            signal,
+            group: Some(group),
+        });
+        self
+    }
+
+    /// Signal named `name`, including the multiplexor
+    pub fn get(&self, name: &str) -> Option<&Signal> {
+        match &self.multiplexor {
+            Some((mux, signal)) if mux == name => Some(signal),
+            _ => self.find(name).map(|named| &named.signal),
+        }
+    }
+
+    /// Physical values of the signals `data` holds, in the order they were
+    /// added: the signals present in every payload, the multiplexor and the
+    /// signals of the group it selects
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<(&str, f64)>> {
+        let group = match &self.multiplexor {
+            Some((_, mux)) => Some(mux.extract(data)?),
+            None => None,
+        };
+        let mut values = Vec::new();
+        for named in self.signals.iter().filter(|named| named.group.is_none()) {
+            values.push((named.name.as_str(), named.signal.decode(data)?));
+        }
+        if let Some((name, mux)) = &self.multiplexor {
+            values.push((name.as_str(), mux.decode(data)?));
+        }
+        for named in self
+            .signals
+            .iter()
+            .filter(|named| named.group.is_some() && named.group == group)
+        {
+            values.push((named.name.as_str(), named.signal.decode(data)?));
+        }
+        Ok(values)
+    }
+
+    /// Write physical values of signals into `data`, leaving the other
+    /// bits as they are
+    ///
+    /// Writing a multiplexed signal sets the multiplexor to its group; all
+    /// multiplexed signals written must be of one group, and of the one the
+    /// multiplexor is set to if it is written too. Fails with
+    /// `InvalidSignal` for unknown names, mixed groups and values out of
+    /// range, before writing anything.
+    pub fn encode(&self, data: &mut [u8], values: &[(&str, f64)]) -> Result<()> {
+        let mut group = None;
+        let mut writes = Vec::with_capacity(values.len() + 1);
+        for &(name, value) in values {
+            if let Some((mux, signal)) = &self.multiplexor {
+                if mux == name {
+                    writes.push((signal, value));
+                    continue;
+                }
+            }
+            let named = self
+                .find(name)
+                .ok_or_else(|| GsUsbError::InvalidSignal(format!("no signal {name}")))?;
+            if let Some(selected) = named.group {
+                if let Some(group) = group.filter(|&group| group != selected) {
+                    return Err(GsUsbError::InvalidSignal(format!(
+                        "{name} is not in multiplexor group {group}"
+                    )));
+                }
+                group = Some(selected);
+            }
+            writes.push((&named.signal, value));
+        }
+        if let (Some((name, mux)), Some(group)) = (&self.multiplexor, group) {
+            match values.iter().find(|(written, _)| written == name) {
+                Some(&(_, value)) if mux.physical(group as i128) != value => {
+                    return Err(GsUsbError::InvalidSignal(format!(
+                        "{name} set to {value}, but the signals written are in group {group}"
+                    )));
+                }
+                Some(_) => {}
+                None => writes.push((mux, mux.physical(group as i128))),
+            }
+        }
+
+        let mut encoded = data.to_vec();
+        for (signal, value) in writes {
+            signal.encode(&mut encoded, value)?;
+        }
+        data.copy_from_slice(&encoded);
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<&Named> {
+        self.signals.iter().find(|named| named.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intel() {
+        let data = [0x34, 0x12, 0xF0, 0x0F];
+        assert_eq!(Signal::intel(0, 16).extract(&data).unwrap(), 0x1234);
+        assert_eq!(Signal::intel(4, 8).extract(&data).unwrap(), 0x23);
+        assert_eq!(Signal::intel(20, 8).extract(&data).unwrap(), 0xFF);
+        assert_eq!(
+            Signal::intel(16, 8).signed().decode_raw(&data).unwrap(),
+            -16
+        );
+
+        let temperature = Signal::intel(8, 8).scaled(0.5, -40.0);
+        assert_eq!(temperature.decode(&data).unwrap(), -31.0);
+        let mut data = [0; 4];
+        temperature.encode(&mut data, 25.2).unwrap();
+        assert_eq!(data, [0, 130, 0, 0]);
+        assert!(temperature.encode(&mut data, 100.0).is_err());
+        assert!(Signal::intel(30, 4).extract(&data).is_err());
+    }
+
+    #[test]
+    fn test_motorola() {
+        let data = [0x12, 0x34, 0x56];
+        assert_eq!(Signal::motorola(7, 16).extract(&data).unwrap(), 0x1234);
+        // Low nibble of byte 0 and high nibble of byte 1
+        assert_eq!(Signal::motorola(3, 8).extract(&data).unwrap(), 0x23);
+        assert_eq!(Signal::motorola(15, 12).extract(&data).unwrap(), 0x345);
+
+        let mut data = [0xFF; 3];
+        let signal = Signal::motorola(3, 8).signed();
+        signal.encode(&mut data, -2.0).unwrap();
+        assert_eq!(data, [0xFF, 0xEF, 0xFF]);
+        assert_eq!(signal.decode(&data).unwrap(), -2.0);
+    }
+
+    #[test]
+    fn test_multiplexed() {
+        let layout = MessageLayout::new()
+            .multiplexor("mux", Signal::intel(0, 4))
+            .signal("counter", Signal::intel(4, 4))
+            .multiplexed("a", 0, Signal::intel(8, 8))
+            .multiplexed("b", 1, Signal::intel(8, 16).signed())
+            .multiplexed("c", 1, Signal::intel(24, 8));
+
+        let mut data = [0xF0, 0, 0, 0];
+        layout
+            .encode(&mut data, &[("b", -2.0), ("c", 7.0)])
+            .unwrap();
+        assert_eq!(data, [0xF1, 0xFE, 0xFF, 7]);
+        assert_eq!(
+            layout.decode(&data).unwrap(),
+            [("counter", 15.0), ("mux", 1.0), ("b", -2.0), ("c", 7.0)]
+        );
+
+        layout
+            .encode(&mut data, &[("mux", 0.0), ("a", 9.0)])
+            .unwrap();
+        assert_eq!(
+            layout.decode(&data).unwrap(),
+            [("counter", 15.0), ("mux", 0.0), ("a", 9.0)]
+        );
+        // Groups, multiplexor and names must agree, and nothing is written
+        // if they don't
+        assert!(layout.encode(&mut data, &[("a", 1.0), ("b", 1.0)]).is_err());
+        assert!(layout
+            .encode(&mut data, &[("mux", 1.0), ("a", 1.0)])
+            .is_err());
+        assert!(layout
+            .encode(&mut data, &[("counter", 1.0), ("d", 1.0)])
+            .is_err());
+        assert_eq!(data, [0xF0, 9, 0xFF, 7]);
+        assert_eq!(layout.get("mux"), Some(&Signal::intel(0, 4)));
+    }
+
+    #[test]
+    fn test_full_width() {
+        let signal = Signal::intel(0, 64).signed();
+        let mut data = [0; 8];
+        signal.encode_raw(&mut data, -1).unwrap();
+        assert_eq!(data, [0xFF; 8]);
+        assert_eq!(signal.decode_raw(&data).unwrap(), -1);
+        assert_eq!(Signal::intel(0, 64).extract(&data).unwrap(), u64::MAX);
+        assert!(Signal::intel(0, 0).extract(&data).is_err());
+    }
+}
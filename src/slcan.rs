@@ -0,0 +1,314 @@
+//! SLCAN (LAWICEL) ASCII protocol encoding and decoding
+//!
+//! This module converts between `GsUsbFrame` and the SLCAN text commands used by
+//! serial-line CAN adapters and tools such as `slcand`:
+//!
+//! - `tiiiL<data>` / `TiiiiiiiiL<data>` - standard / extended data frame
+//! - `riiiL` / `RiiiiiiiiL` - standard / extended remote frame
+//! - `diiiL<data>` / `DiiiiiiiiL<data>` - standard / extended CAN FD frame
+//! - `biiiL<data>` / `BiiiiiiiiL<data>` - standard / extended CAN FD frame with BRS
+//!
+//! `L` is a single hex digit holding the DLC (0-8 for classic CAN, 0-F for CAN FD).
+//! An optional 4-digit hex timestamp in milliseconds may follow the data.
+//! Commands are terminated by a carriage return (`\r`).
+
+use crate::constants::{
+    CANFD_MAX_DLC, CAN_EFF_FLAG, CAN_EFF_MASK, CAN_MAX_DLC, CAN_RTR_FLAG, CAN_SFF_MASK,
+    GS_CAN_FLAG_BRS, GS_CAN_FLAG_FD, GS_USB_FRAME_DATA_LEN,
+};
+use crate::error::{GsUsbError, Result};
+use crate::frame::{dlc_to_len, GsUsbFrame};
+
+/// SLCAN command terminator
+pub const SLCAN_TERMINATOR: char = '\r';
+
+/// SLCAN timestamps wrap around after 60 seconds
+pub const SLCAN_TIMESTAMP_WRAP_MS: u32 = 60_000;
+
+/// Encode a frame as an SLCAN command (including the trailing `\r`)
+///
+/// Error frames have no SLCAN representation and are rejected.
+pub fn encode(frame: &GsUsbFrame) -> Result<String> {
+    encode_inner(frame, None)
+}
+
+/// Encode a frame as an SLCAN command with a millisecond timestamp appended
+///
+/// The timestamp is taken from the frame's hardware timestamp, wrapped to
+/// the 0-59999 ms range used by SLCAN.
+pub fn encode_with_timestamp(frame: &GsUsbFrame) -> Result<String> {
+    let ms = (frame.timestamp_us / 1000) % SLCAN_TIMESTAMP_WRAP_MS;
+    encode_inner(frame, Some(ms as u16))
+}
+
+fn encode_inner(frame: &GsUsbFrame, timestamp_ms: Option<u16>) -> Result<String> {
+    if frame.is_error_frame() {
+        return Err(GsUsbError::InvalidSlcan("error frames cannot be encoded"));
+    }
+
+    let extended = frame.is_extended_id();
+    let command = match (frame.is_fd(), frame.is_brs(), frame.is_remote_frame()) {
+        (true, true, _) => 'b',
+        (true, false, _) => 'd',
+        (false, _, true) => 'r',
+        (false, _, false) => 't',
+    };
+    let command = if extended {
+        command.to_ascii_uppercase()
+    } else {
+        command
+    };
+
+    let mut out = String::with_capacity(1 + 8 + 1 + 2 * frame.data_length() + 4 + 1);
+    out.push(command);
+    if extended {
+        out.push_str(&format!("{:08X}", frame.can_id & CAN_EFF_MASK));
+    } else {
+        out.push_str(&format!("{:03X}", frame.can_id & CAN_SFF_MASK));
+    }
+    out.push_str(&format!("{:X}", frame.can_dlc & 0x0F));
+
+    if !frame.is_remote_frame() || frame.is_fd() {
+        for b in frame.data() {
+            out.push_str(&format!("{:02X}", b));
+        }
+    }
+
+    if let Some(ms) = timestamp_ms {
+        out.push_str(&format!("{:04X}", ms));
+    }
+
+    out.push(SLCAN_TERMINATOR);
+    Ok(out)
+}
+
+/// Decode a single SLCAN frame command into a `GsUsbFrame`
+///
+/// A trailing `\r` is accepted but not required. If a 4-digit timestamp is
+/// present it is stored in `timestamp_us` (converted from milliseconds).
+pub fn decode(command: &str) -> Result<GsUsbFrame> {
+    let command = command.trim_end_matches(SLCAN_TERMINATOR);
+    if !command.is_ascii() {
+        return Err(GsUsbError::InvalidSlcan("non-ASCII characters"));
+    }
+    let bytes = command.as_bytes();
+    let kind = *bytes
+        .first()
+        .ok_or(GsUsbError::InvalidSlcan("empty command"))?;
+
+    let (extended, remote, fd, brs) = match kind {
+        b't' => (false, false, false, false),
+        b'T' => (true, false, false, false),
+        b'r' => (false, true, false, false),
+        b'R' => (true, true, false, false),
+        b'd' => (false, false, true, false),
+        b'D' => (true, false, true, false),
+        b'b' => (false, false, true, true),
+        b'B' => (true, false, true, true),
+        _ => return Err(GsUsbError::InvalidSlcan("not a frame command")),
+    };
+
+    let id_len = if extended { 8 } else { 3 };
+    if bytes.len() < 1 + id_len + 1 {
+        return Err(GsUsbError::InvalidSlcan("command too short"));
+    }
+
+    let id = parse_hex(&command[1..1 + id_len])?;
+    let id_mask = if extended { CAN_EFF_MASK } else { CAN_SFF_MASK };
+    if id > id_mask {
+        return Err(GsUsbError::InvalidSlcan("identifier out of range"));
+    }
+
+    let dlc = parse_hex(&command[1 + id_len..2 + id_len])? as u8;
+    let max_dlc = if fd { CANFD_MAX_DLC } else { CAN_MAX_DLC };
+    if dlc > max_dlc {
+        return Err(GsUsbError::InvalidSlcan("DLC out of range"));
+    }
+
+    let data_len = if remote { 0 } else { dlc_to_len(dlc, fd) };
+    let data_start = 2 + id_len;
+    let data_end = data_start + 2 * data_len;
+    let rest = bytes.len() - data_start;
+    if rest != 2 * data_len && rest != 2 * data_len + 4 {
+        return Err(GsUsbError::InvalidSlcan("data length does not match DLC"));
+    }
+    if data_len > GS_USB_FRAME_DATA_LEN {
+        return Err(GsUsbError::InvalidSlcan("CAN FD data needs the fd feature"));
+    }
+
+    let mut frame = GsUsbFrame::new();
+    frame.can_id = id;
+    if extended {
+        frame.can_id |= CAN_EFF_FLAG;
+    }
+    if remote {
+        frame.can_id |= CAN_RTR_FLAG;
+    }
+    if fd {
+        frame.flags |= GS_CAN_FLAG_FD;
+    }
+    if brs {
+        frame.flags |= GS_CAN_FLAG_BRS;
+    }
+    frame.can_dlc = dlc;
+
+    for i in 0..data_len {
+        let offset = data_start + 2 * i;
+        frame.data[i] = parse_hex(&command[offset..offset + 2])? as u8;
+    }
+
+    if rest == 2 * data_len + 4 {
+        let ms = parse_hex(&command[data_end..data_end + 4])?;
+        frame.timestamp_us = ms * 1000;
+    }
+
+    Ok(frame)
+}
+
+fn parse_hex(s: &str) -> Result<u32> {
+    if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(GsUsbError::InvalidSlcan("invalid hex digit"));
+    }
+    u32::from_str_radix(s, 16).map_err(|_| GsUsbError::InvalidSlcan("invalid hex digit"))
+}
+
+/// Get the SLCAN `Sn` setup command code for a standard bitrate
+///
+/// Returns `None` for bitrates that have no predefined SLCAN code.
+pub fn bitrate_code(bitrate: u32) -> Option<u8> {
+    match bitrate {
+        10_000 => Some(0),
+        20_000 => Some(1),
+        50_000 => Some(2),
+        100_000 => Some(3),
+        125_000 => Some(4),
+        250_000 => Some(5),
+        500_000 => Some(6),
+        800_000 => Some(7),
+        1_000_000 => Some(8),
+        _ => None,
+    }
+}
+
+/// Incremental SLCAN stream decoder
+///
+/// Accepts arbitrary chunks of an SLCAN byte stream (e.g. from a serial port or
+/// pty) and yields one decode result per complete `\r`-terminated command.
+/// Acknowledgement bytes (`\r`, `\x07`) and empty lines are skipped.
+#[derive(Debug, Default)]
+pub struct SlcanDecoder {
+    buf: String,
+}
+
+impl SlcanDecoder {
+    /// Create a new empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed bytes into the decoder and return all commands completed by them
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Result<GsUsbFrame>> {
+        let mut results = Vec::new();
+        for &b in bytes {
+            match b {
+                b'\r' | b'\n' => {
+                    if !self.buf.is_empty() {
+                        results.push(decode(&self.buf));
+                        self.buf.clear();
+                    }
+                }
+                0x07 => self.buf.clear(),
+                _ => self.buf.push(b as char),
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_standard() {
+        let frame = GsUsbFrame::with_data(0x123, &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(encode(&frame).unwrap(), "t1234DEADBEEF\r");
+    }
+
+    #[test]
+    fn test_encode_extended_remote() {
+        let frame = GsUsbFrame::with_data(0x1234567 | CAN_EFF_FLAG | CAN_RTR_FLAG, &[0; 2]);
+        assert_eq!(encode(&frame).unwrap(), "R012345672\r");
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_encode_fd_brs() {
+        let data: Vec<u8> = (0..12).collect();
+        let frame = GsUsbFrame::with_fd_data(0x7FF, &data, true);
+        assert_eq!(encode(&frame).unwrap(), "b7FF9000102030405060708090A0B\r");
+    }
+
+    #[test]
+    fn test_encode_with_timestamp() {
+        let mut frame = GsUsbFrame::with_data(0x001, &[0x11]);
+        frame.timestamp_us = 61_234_000;
+        assert_eq!(encode_with_timestamp(&frame).unwrap(), "t00111104D2\r");
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_decode_roundtrip() {
+        for cmd in [
+            "t1234DEADBEEF\r",
+            "T1ABCDEF080102030405060708\r",
+            "r7FF8\r",
+            "R012345672\r",
+            "d0011AA\r",
+            "B1FFFFFFFA000102030405060708090A0B0C0D0E0F\r",
+        ] {
+            let frame = decode(cmd).unwrap();
+            assert_eq!(encode(&frame).unwrap(), cmd);
+        }
+    }
+
+    #[test]
+    fn test_decode_timestamp() {
+        let frame = decode("t00111104D2").unwrap();
+        assert_eq!(frame.data(), &[0x11]);
+        assert_eq!(frame.timestamp_us, 1_234_000);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(decode("").is_err());
+        assert!(decode("O").is_err());
+        assert!(decode("t12").is_err());
+        assert!(decode("t8001").is_err()); // 11-bit ID out of range
+        assert!(decode("t1239").is_err()); // classic DLC > 8
+        assert!(decode("t1232AA").is_err()); // too little data
+        assert!(decode("t1231GG").is_err()); // bad hex
+        assert!(decode("t1231\u{e9}").is_err()); // non-ASCII
+    }
+
+    #[cfg(not(feature = "fd"))]
+    #[test]
+    fn test_decode_fd_without_fd_feature() {
+        // FD frames fit as long as their data does
+        let frame = decode("d1238AABBCCDDEEFF0011").unwrap();
+        assert!(frame.is_fd());
+        assert_eq!(frame.data().len(), 8);
+        let long = format!("d123F{}", "AA".repeat(64));
+        assert!(matches!(decode(&long), Err(GsUsbError::InvalidSlcan(_))));
+    }
+
+    #[test]
+    fn test_stream_decoder() {
+        let mut decoder = SlcanDecoder::new();
+        assert!(decoder.feed(b"t12").is_empty());
+        let frames = decoder.feed(b"31AA\r\rt4560\r");
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].as_ref().unwrap().arbitration_id(), 0x123);
+        assert_eq!(frames[1].as_ref().unwrap().arbitration_id(), 0x456);
+    }
+}
@@ -0,0 +1,301 @@
+//! Time-slotted transmission
+//!
+//! `SlotScheduler` divides a fixed cycle into equal slots and sends the
+//! frames assigned to a slot at its start, approximating time-triggered
+//! traffic for testing receivers that are sensitive to the phase of
+//! messages. Like FlexRay cycle multiplexing, a frame can be sent only in
+//! every n-th cycle.
+//!
+//! Slots are timed on the host, so frames leave with the USB and bus
+//! arbitration delays on top. A slot whose start has been overtaken by the
+//! next one is skipped rather than sent late, keeping the phases intact.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, GsUsbFrame, SlotScheduler, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! // 10 ms cycle of 5 slots of 2 ms
+//! let mut scheduler = SlotScheduler::new(Duration::from_millis(10), 5)?;
+//! scheduler.assign(0, GsUsbFrame::with_data(0x100, &[1]))?;
+//! scheduler.assign(2, GsUsbFrame::with_data(0x200, &[2]))?;
+//! // Every fourth cycle, starting with the second
+//! scheduler.assign_every(4, GsUsbFrame::with_data(0x300, &[3]), 1, 4)?;
+//! let sent = scheduler.run(&mut dev, 1000)?;
+//! println!("{sent} frames, {} slots skipped", scheduler.skipped());
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::device::GsUsb;
+use crate::error::{GsUsbError, Result};
+use crate::frame::GsUsbFrame;
+
+/// A frame assigned to a slot
+#[derive(Debug, Clone)]
+struct Entry {
+    frame: GsUsbFrame,
+    base_cycle: u64,
+    repetition: u64,
+}
+
+/// Sends frames in the slots of a repeating cycle, see the module
+/// documentation
+///
+/// The first cycle starts on construction or `restart()`.
+#[derive(Debug, Clone)]
+pub struct SlotScheduler<C: Clock = SystemClock> {
+    clock: C,
+    cycle: Duration,
+    slots: Vec<Vec<Entry>>,
+    start: Instant,
+    /// Slots since the start up to the next one to process
+    position: u64,
+    skipped: u64,
+}
+
+impl SlotScheduler {
+    /// `slots` slots per `cycle`, using the system clock
+    ///
+    /// Fails with `InvalidConfig` if either is zero.
+    pub fn new(cycle: Duration, slots: usize) -> Result<Self> {
+        Self::with_clock(cycle, slots, SystemClock)
+    }
+}
+
+impl<C: Clock> SlotScheduler<C> {
+    /// `slots` slots per `cycle`, using the given clock
+    ///
+    /// Fails with `InvalidConfig` if either is zero.
+    pub fn with_clock(cycle: Duration, slots: usize, clock: C) -> Result<Self> {
+        if cycle.is_zero() || slots == 0 {
+            return Err(GsUsbError::InvalidConfig(format!(
+                "{slots} slots per cycle of {cycle:?}"
+            )));
+        }
+        Ok(Self {
+            start: clock.now(),
+            clock,
+            cycle,
+            slots: vec![Vec::new(); slots],
+            position: 0,
+            skipped: 0,
+        })
+    }
+
+    /// Send `frame` in `slot` of every cycle
+    ///
+    /// Frames of one slot are sent in the order they were assigned. Fails
+    /// with `InvalidConfig` if there is no such slot.
+    pub fn assign(&mut self, slot: usize, frame: GsUsbFrame) -> Result<()> {
+        self.assign_every(slot, frame, 0, 1)
+    }
+
+    /// Send `frame` in `slot` of every `repetition`-th cycle, starting with
+    /// cycle `base_cycle`
+    ///
+    /// Fails with `InvalidConfig` if there is no such slot, `repetition` is
+    /// zero or `base_cycle` isn't below it.
+    pub fn assign_every(
+        &mut self,
+        slot: usize,
+        frame: GsUsbFrame,
+        base_cycle: u64,
+        repetition: u64,
+    ) -> Result<()> {
+        let slots = self.slots.len();
+        let entries = self.slots.get_mut(slot).ok_or_else(|| {
+            GsUsbError::InvalidConfig(format!("slot {slot} of a cycle of {slots} slots"))
+        })?;
+        if base_cycle >= repetition {
+            return Err(GsUsbError::InvalidConfig(format!(
+                "base cycle {base_cycle} with a repetition of {repetition}"
+            )));
+        }
+        entries.push(Entry {
+            frame,
+            base_cycle,
+            repetition,
+        });
+        Ok(())
+    }
+
+    /// Remove the frames assigned to `slot`
+    pub fn clear(&mut self, slot: usize) {
+        if let Some(entries) = self.slots.get_mut(slot) {
+            entries.clear();
+        }
+    }
+
+    /// Start the first cycle now
+    pub fn restart(&mut self) {
+        self.start = self.clock.now();
+        self.position = 0;
+        self.skipped = 0;
+    }
+
+    /// Length of a cycle
+    pub fn cycle(&self) -> Duration {
+        self.cycle
+    }
+
+    /// Slots per cycle
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Length of a slot
+    pub fn slot_duration(&self) -> Duration {
+        self.cycle / self.slots.len() as u32
+    }
+
+    /// Start of the next slot to process
+    pub fn next_slot_at(&self) -> Instant {
+        self.slot_start(self.position)
+    }
+
+    /// Slots skipped because their start was overtaken by the next one
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// The frames of the current slot if they haven't been taken yet
+    ///
+    /// Earlier slots not taken in time are skipped.
+    pub fn due(&mut self) -> Vec<GsUsbFrame> {
+        let slots = self.slots.len() as u128;
+        let elapsed = self.clock.now().saturating_duration_since(self.start);
+        let current = (elapsed.as_nanos() * slots / self.cycle.as_nanos()) as u64;
+        if current < self.position {
+            return Vec::new();
+        }
+        self.skipped += current - self.position;
+        self.position = current + 1;
+
+        let cycle = current / slots as u64;
+        self.slots[(current % slots as u64) as usize]
+            .iter()
+            .filter(|entry| cycle % entry.repetition == entry.base_cycle)
+            .map(|entry| entry.frame.clone())
+            .collect()
+    }
+
+    /// Send the frames of `cycles` cycles, waiting for the start of each
+    /// slot, and return the number of frames sent
+    ///
+    /// Continues from the next slot to process, so a run started mid-cycle
+    /// finishes that cycle before the full ones.
+    pub fn run(&mut self, dev: &mut GsUsb, cycles: u64) -> Result<u64> {
+        let slots = self.slots.len() as u64;
+        let end = self
+            .position
+            .div_ceil(slots)
+            .saturating_add(cycles)
+            .saturating_mul(slots);
+        let mut sent = 0;
+        while self.position < end {
+            let at = self.next_slot_at();
+            let now = self.clock.now();
+            if at > now {
+                self.clock.sleep(at - now);
+            }
+            for frame in self.due() {
+                dev.send(&frame)?;
+                sent += 1;
+            }
+        }
+        Ok(sent)
+    }
+
+    fn slot_start(&self, position: u64) -> Instant {
+        let offset = self.cycle.as_nanos() * u128::from(position) / self.slots.len() as u128;
+        self.start + Duration::from_nanos(offset as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::mock::MockGsUsb;
+
+    fn scheduler(clock: &TestClock) -> SlotScheduler<TestClock> {
+        let mut scheduler =
+            SlotScheduler::with_clock(Duration::from_millis(10), 4, clock.clone()).unwrap();
+        scheduler
+            .assign(0, GsUsbFrame::with_data(0x100, &[]))
+            .unwrap();
+        scheduler
+            .assign(2, GsUsbFrame::with_data(0x200, &[]))
+            .unwrap();
+        scheduler
+            .assign_every(3, GsUsbFrame::with_data(0x300, &[]), 1, 2)
+            .unwrap();
+        scheduler
+    }
+
+    fn ids(frames: &[GsUsbFrame]) -> Vec<u32> {
+        frames.iter().map(|f| f.can_id).collect()
+    }
+
+    #[test]
+    fn test_run() {
+        let clock = TestClock::new();
+        let start = clock.now();
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+
+        let mut scheduler = scheduler(&clock);
+        assert_eq!(scheduler.run(&mut dev, 2).unwrap(), 5);
+        assert_eq!(
+            ids(&mock.sent_frames()),
+            [0x100, 0x200, 0x100, 0x200, 0x300]
+        );
+        // Ends at the start of the last slot
+        assert_eq!(clock.now() - start, Duration::from_micros(17_500));
+        assert_eq!(scheduler.next_slot_at() - start, Duration::from_millis(20));
+        assert_eq!(scheduler.skipped(), 0);
+    }
+
+    #[test]
+    fn test_due() {
+        let clock = TestClock::new();
+        let mut scheduler = scheduler(&clock);
+        assert_eq!(ids(&scheduler.due()), [0x100]);
+        assert!(scheduler.due().is_empty());
+        clock.advance(Duration::from_millis(2));
+        assert!(scheduler.due().is_empty());
+        clock.advance(Duration::from_millis(1));
+        assert!(scheduler.due().is_empty());
+
+        // Slots 2 and 3 overtaken, slot 3 is not sent in even cycles anyway
+        clock.advance(Duration::from_millis(8));
+        assert_eq!(ids(&scheduler.due()), [0x100]);
+        assert_eq!(scheduler.skipped(), 2);
+
+        scheduler.restart();
+        assert_eq!(ids(&scheduler.due()), [0x100]);
+        assert_eq!(scheduler.skipped(), 0);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let clock = TestClock::new();
+        assert!(SlotScheduler::with_clock(Duration::ZERO, 4, clock.clone()).is_err());
+        assert!(SlotScheduler::with_clock(Duration::from_millis(10), 0, clock.clone()).is_err());
+        let mut scheduler = scheduler(&clock);
+        let frame = GsUsbFrame::with_data(0x100, &[]);
+        assert!(scheduler.assign(4, frame.clone()).is_err());
+        assert!(scheduler.assign_every(0, frame.clone(), 2, 2).is_err());
+        assert!(scheduler.assign_every(0, frame, 0, 0).is_err());
+    }
+}
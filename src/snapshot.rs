@@ -0,0 +1,388 @@
+//! Statistics snapshots and their export
+//!
+//! `BusStats::snapshot()` takes the per-identifier table, the bus load
+//! history and the error counters at one point in time. A snapshot renders
+//! as CSV tables or, with the `serde` feature, as one JSON document.
+//! `StatsExporter` writes snapshots to a file or other sink at a fixed
+//! interval, so a long test run leaves a summary behind without custom code.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{BusStats, ExportFormat, GsUsb, StatsExporter, GS_CAN_MODE_HW_TIMESTAMP,
+//!              GS_CAN_MODE_LISTEN_ONLY};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_LISTEN_ONLY | GS_CAN_MODE_HW_TIMESTAMP)?;
+//!
+//! let mut stats = BusStats::new();
+//! stats.set_bitrate(500_000.into(), Duration::from_secs(1));
+//! let mut exporter = StatsExporter::create("ids.csv", ExportFormat::Csv, Duration::from_secs(60))?;
+//! for _ in 0..1_000_000 {
+//!     if let Ok(frame) = dev.read(Duration::from_millis(100)) {
+//!         stats.observe(&frame);
+//!     }
+//!     exporter.poll(&stats)?;
+//! }
+//! std::fs::write("load.csv", stats.snapshot().load_csv())?;
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::Result;
+use crate::id::CanId;
+use crate::stats::{Bucket, ErrorCounts, IdStats, LoadSample};
+
+/// Header of the per-identifier CSV table
+const ID_CSV_HEADER: &str = "id,extended,count,payload_bytes,first_timestamp_us,\
+                             last_timestamp_us,period_mean_us,period_min_us,period_max_us,jitter_us";
+
+/// One row of the per-identifier table
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IdRow {
+    /// The identifier, as hex in JSON
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_id"))]
+    pub id: CanId,
+    /// Frames seen
+    pub count: u64,
+    /// Payload bytes of all frames seen
+    pub payload_bytes: u64,
+    /// Hardware timestamp of the first frame
+    pub first_timestamp_us: u32,
+    /// Hardware timestamp of the last frame
+    pub last_timestamp_us: u32,
+    /// Mean inter-arrival time, `None` before the second frame
+    pub period_mean_us: Option<u64>,
+    /// Shortest inter-arrival time
+    pub period_min_us: Option<u64>,
+    /// Longest inter-arrival time
+    pub period_max_us: Option<u64>,
+    /// Inter-arrival time histogram, if kept
+    pub period_histogram: Option<Vec<Bucket>>,
+    /// Payload length histogram, if kept
+    pub length_histogram: Option<Vec<Bucket>>,
+}
+
+#[cfg(feature = "serde")]
+fn serialize_id<S: serde::Serializer>(
+    id: &CanId,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(id)
+}
+
+impl IdRow {
+    pub(crate) fn new(id: CanId, stats: &IdStats) -> Self {
+        let period = stats.period();
+        let micros = |d: Option<Duration>| d.map(|d| d.as_micros() as u64);
+        Self {
+            id,
+            count: stats.count(),
+            payload_bytes: stats.payload_bytes(),
+            first_timestamp_us: stats.first_timestamp_us(),
+            last_timestamp_us: stats.last_timestamp_us(),
+            period_mean_us: micros(period.mean()),
+            period_min_us: micros(period.min()),
+            period_max_us: micros(period.max()),
+            period_histogram: stats.period_histogram().map(|h| h.buckets()),
+            length_histogram: stats.length_histogram().map(|h| h.buckets()),
+        }
+    }
+
+    /// Difference between the longest and the shortest inter-arrival time
+    pub fn jitter_us(&self) -> Option<u64> {
+        Some(self.period_max_us? - self.period_min_us?)
+    }
+
+    /// The row as CSV, in the columns of `ID_CSV_HEADER`
+    fn write_csv(&self, out: &mut String) {
+        let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            self.id,
+            self.id.is_extended(),
+            self.count,
+            self.payload_bytes,
+            self.first_timestamp_us,
+            self.last_timestamp_us,
+            opt(self.period_mean_us),
+            opt(self.period_min_us),
+            opt(self.period_max_us),
+            opt(self.jitter_us()),
+        );
+    }
+}
+
+/// Bus statistics at one point in time
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatsSnapshot {
+    /// Hardware timestamp of the last frame seen
+    pub timestamp_us: u32,
+    /// Frames seen, including error frames
+    pub frames: u64,
+    /// Error frames seen
+    pub error_frames: u64,
+    /// Per-identifier table, in ascending identifier order
+    pub ids: Vec<IdRow>,
+    /// Bus load history, oldest first
+    pub load: Vec<LoadSample>,
+    /// Error frames per class and the last reported error counters
+    pub errors: ErrorCounts,
+}
+
+impl StatsSnapshot {
+    /// The per-identifier table as CSV, with a header line
+    ///
+    /// Period columns are empty for identifiers seen once; histograms are
+    /// only part of the JSON export.
+    pub fn ids_csv(&self) -> String {
+        let mut out = format!("{ID_CSV_HEADER}\n");
+        for row in &self.ids {
+            row.write_csv(&mut out);
+        }
+        out
+    }
+
+    /// The bus load history as CSV, with a header line
+    pub fn load_csv(&self) -> String {
+        let mut out = String::from("start_us,load_percent\n");
+        for sample in &self.load {
+            let _ = writeln!(out, "{},{:.2}", sample.start_us, sample.load_percent);
+        }
+        out
+    }
+
+    /// The error frames per class as CSV, with a header line
+    pub fn errors_csv(&self) -> String {
+        let mut out = String::from("class,count\n");
+        for (name, count) in self.errors.classes() {
+            let _ = writeln!(out, "{name},{count}");
+        }
+        out
+    }
+
+    /// The whole snapshot as pretty-printed JSON
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("statistics serialize to JSON")
+    }
+}
+
+/// Format of periodically exported snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The per-identifier table, each row prefixed with the snapshot's
+    /// `timestamp_us`, under one header line
+    Csv,
+    /// One JSON snapshot per line (JSON Lines)
+    #[cfg(feature = "serde")]
+    JsonLines,
+}
+
+/// Writes statistics snapshots to a sink at a fixed interval
+///
+/// `poll()` is called from the read loop and exports a snapshot whenever the
+/// interval has passed since the last one, timed by the clock.
+pub struct StatsExporter<C: Clock = SystemClock> {
+    clock: C,
+    writer: Box<dyn Write + Send>,
+    format: ExportFormat,
+    interval: Duration,
+    next_at: Instant,
+    header_written: bool,
+}
+
+impl<C: Clock> std::fmt::Debug for StatsExporter<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatsExporter")
+            .field("format", &self.format)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StatsExporter {
+    /// Export to `writer` every `interval` of system time
+    pub fn new(
+        writer: impl Write + Send + 'static,
+        format: ExportFormat,
+        interval: Duration,
+    ) -> Self {
+        Self::with_clock(SystemClock, writer, format, interval)
+    }
+
+    /// Export to a newly created file every `interval` of system time
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: ExportFormat,
+        interval: Duration,
+    ) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Self::new(file, format, interval))
+    }
+}
+
+impl<C: Clock> StatsExporter<C> {
+    /// Export to `writer` every `interval` of the given clock's time
+    pub fn with_clock(
+        clock: C,
+        writer: impl Write + Send + 'static,
+        format: ExportFormat,
+        interval: Duration,
+    ) -> Self {
+        let next_at = clock.now() + interval;
+        Self {
+            clock,
+            writer: Box::new(writer),
+            format,
+            interval,
+            next_at,
+            header_written: false,
+        }
+    }
+
+    /// Export a snapshot of `stats` if the interval has passed, returning
+    /// whether one was written
+    pub fn poll(&mut self, stats: &crate::stats::BusStats) -> Result<bool> {
+        let now = self.clock.now();
+        if now < self.next_at {
+            return Ok(false);
+        }
+        // Keep to the schedule, skipping intervals missed entirely
+        while self.next_at <= now {
+            self.next_at += self.interval.max(Duration::from_millis(1));
+        }
+        self.export(&stats.snapshot())?;
+        Ok(true)
+    }
+
+    /// Export a snapshot now, without changing the schedule
+    pub fn export(&mut self, snapshot: &StatsSnapshot) -> Result<()> {
+        match self.format {
+            ExportFormat::Csv => {
+                if !std::mem::replace(&mut self.header_written, true) {
+                    writeln!(self.writer, "snapshot_us,{ID_CSV_HEADER}")?;
+                }
+                for row in &snapshot.ids {
+                    let mut line = String::new();
+                    row.write_csv(&mut line);
+                    write!(self.writer, "{},{line}", snapshot.timestamp_us)?;
+                }
+            }
+            #[cfg(feature = "serde")]
+            ExportFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, snapshot).map_err(std::io::Error::from)?;
+                writeln!(self.writer)?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::constants::CAN_EFF_FLAG;
+    use crate::frame::GsUsbFrame;
+    use crate::stats::BusStats;
+    use std::sync::{Arc, Mutex};
+
+    /// Sink whose contents stay readable after it moved into the exporter
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedSink {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn stats() -> BusStats {
+        let mut stats = BusStats::new();
+        for (id, at) in [
+            (0x100, 0),
+            (0x100, 10_000),
+            (0x18FF_50E5 | CAN_EFF_FLAG, 15_000),
+        ] {
+            let mut frame = GsUsbFrame::with_data(id, &[1, 2]);
+            frame.timestamp_us = at;
+            stats.observe(&frame);
+        }
+        stats
+    }
+
+    #[test]
+    fn test_csv() {
+        let snapshot = stats().snapshot();
+        assert_eq!(snapshot.timestamp_us, 15_000);
+        assert_eq!(
+            snapshot.ids_csv(),
+            format!(
+                "{ID_CSV_HEADER}\n\
+                 100,false,2,4,0,10000,10000,10000,10000,0\n\
+                 18FF50E5,true,1,2,15000,15000,,,,\n"
+            )
+        );
+        assert_eq!(snapshot.load_csv(), "start_us,load_percent\n");
+        assert_eq!(snapshot.errors_csv(), "class,count\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let json: serde_json::Value = serde_json::from_str(&stats().snapshot().to_json()).unwrap();
+        assert_eq!(json["frames"], 3);
+        assert_eq!(json["ids"][1]["id"], "18FF50E5");
+        assert_eq!(json["ids"][0]["period_mean_us"], 10_000);
+    }
+
+    #[test]
+    fn test_periodic_export() {
+        let clock = TestClock::new();
+        let sink = SharedSink::default();
+        let mut exporter = StatsExporter::with_clock(
+            clock.clone(),
+            sink.clone(),
+            ExportFormat::Csv,
+            Duration::from_secs(10),
+        );
+        let stats = stats();
+        assert!(!exporter.poll(&stats).unwrap());
+        clock.advance(Duration::from_secs(10));
+        assert!(exporter.poll(&stats).unwrap());
+        assert!(!exporter.poll(&stats).unwrap());
+        // Missed intervals are skipped, not caught up on
+        clock.advance(Duration::from_secs(35));
+        assert!(exporter.poll(&stats).unwrap());
+        assert!(!exporter.poll(&stats).unwrap());
+
+        let text = sink.text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("snapshot_us,id,"));
+        assert_eq!(lines[1], "15000,100,false,2,4,0,10000,10000,10000,10000,0");
+    }
+}
@@ -0,0 +1,939 @@
+//! Per-identifier bus statistics
+//!
+//! `BusStats` is fed every frame read from a device and keeps a table of the
+//! identifiers seen, with frame counts, payload bytes and periods, plus
+//! counts of all frames and error frames. It also reports identifiers that
+//! appear for the first time: after a learning phase in which the normal
+//! traffic of a bus is recorded as its baseline, a new identifier points to
+//! a newly attached node, a changed configuration or an intrusion.
+//!
+//! For jitter analysis, each identifier can also keep histograms of its
+//! inter-arrival times and frame lengths, with buckets set once for all
+//! identifiers (`set_period_histogram()`, `set_length_histogram()`).
+//! Given the bitrate, the statistics also keep a history of the bus load per
+//! time window, and error frames are counted per error class.
+//!
+//! `snapshot()` takes all of it at once, for export as JSON or CSV (see
+//! `StatsSnapshot`), and a `StatsExporter` writes snapshots to a file or
+//! other sink periodically.
+//!
+//! Below the frames, `TransferStats` describes the USB bulk transfers a
+//! device received: their sizes, the frames decoded from them and the ones
+//! too short to decode. `GsUsb::transfer_stats()` returns them, to tune
+//! transfer sizing and to catch firmware that frames its transfers wrongly.
+//! With the `instrument` feature, `HotPathStats` times the stages every
+//! received frame passes through, to find where throughput is lost without
+//! a profiler.
+//!
+//! Times come from the frames' hardware timestamps, so logs can be analysed
+//! like live traffic; intervals must be shorter than the 71.6 minute
+//! counter period.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{BusStats, GsUsb, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_LISTEN_ONLY};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_LISTEN_ONLY | GS_CAN_MODE_HW_TIMESTAMP)?;
+//!
+//! let mut stats = BusStats::with_learning(Duration::from_secs(60));
+//! loop {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     if let Some(new) = stats.observe(&frame) {
+//!         println!("new identifier {} with [{:02X?}]", new.id, new.data);
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+#[cfg(feature = "instrument")]
+use std::time::Instant;
+
+use crate::bitrate::Bitrate;
+use crate::error::{GsUsbError, Result};
+use crate::error_frame::{CanErrorFrame, CLASS_NAMES};
+use crate::frame::GsUsbFrame;
+use crate::gaps::GapStats;
+use crate::generator::frame_duration;
+use crate::id::CanId;
+use crate::snapshot::{IdRow, StatsSnapshot};
+
+/// Bus load samples kept, an hour of one second windows
+pub const LOAD_HISTORY_LEN: usize = 3600;
+
+/// Counts of values falling into buckets
+///
+/// Buckets are delimited by ascending bounds: the first counts values below
+/// the first bound, each next one values from its lower up to below its
+/// upper bound, and the last values from the last bound on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bounds: Vec<u64>,
+    counts: Vec<u64>,
+}
+
+/// One bucket of a histogram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Bucket {
+    /// Smallest value counted
+    pub low: u64,
+    /// Bound above the values counted, `None` for the last bucket
+    pub high: Option<u64>,
+    /// Values counted
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Histogram with the given bucket bounds, sorted and deduplicated
+    pub fn new(bounds: &[u64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Self { bounds, counts }
+    }
+
+    /// `count` buckets of `width` from 0, plus one for larger values
+    pub fn linear(width: u64, count: usize) -> Self {
+        let bounds: Vec<u64> = (1..=count as u64).map(|i| i * width).collect();
+        Self::new(&bounds)
+    }
+
+    /// Buckets doubling in width from `first` up to below `limit`, plus one
+    /// below `first` and one for larger values
+    pub fn exponential(first: u64, limit: u64) -> Self {
+        let bounds: Vec<u64> = std::iter::successors(Some(first.max(1)), |b| b.checked_mul(2))
+            .take_while(|&b| b <= limit)
+            .collect();
+        Self::new(&bounds)
+    }
+
+    /// Count a value
+    pub fn record(&mut self, value: u64) {
+        let index = self.bounds.partition_point(|&bound| bound <= value);
+        self.counts[index] += 1;
+    }
+
+    /// Bucket bounds
+    pub fn bounds(&self) -> &[u64] {
+        &self.bounds
+    }
+
+    /// Values counted per bucket
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Values counted in all buckets
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Buckets with their bounds and counts
+    pub fn buckets(&self) -> Vec<Bucket> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| Bucket {
+                low: if i == 0 { 0 } else { self.bounds[i - 1] },
+                high: self.bounds.get(i).copied(),
+                count,
+            })
+            .collect()
+    }
+
+    /// Zero the counts, keeping the buckets
+    pub fn reset(&mut self) {
+        self.counts.fill(0);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Histogram {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        self.buckets().serialize(serializer)
+    }
+}
+
+/// Statistics of one identifier
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdStats {
+    count: u64,
+    payload_bytes: u64,
+    first_timestamp_us: u32,
+    last_timestamp_us: u32,
+    period: GapStats,
+    period_histogram: Option<Histogram>,
+    length_histogram: Option<Histogram>,
+}
+
+impl IdStats {
+    /// Frames seen
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Payload bytes of all frames seen
+    pub fn payload_bytes(&self) -> u64 {
+        self.payload_bytes
+    }
+
+    /// Hardware timestamp of the first frame
+    pub fn first_timestamp_us(&self) -> u32 {
+        self.first_timestamp_us
+    }
+
+    /// Hardware timestamp of the last frame
+    pub fn last_timestamp_us(&self) -> u32 {
+        self.last_timestamp_us
+    }
+
+    /// Intervals between consecutive frames
+    pub fn period(&self) -> &GapStats {
+        &self.period
+    }
+
+    /// Inter-arrival times in microseconds, if histograms are kept
+    pub fn period_histogram(&self) -> Option<&Histogram> {
+        self.period_histogram.as_ref()
+    }
+
+    /// Payload lengths in bytes, if histograms are kept
+    pub fn length_histogram(&self) -> Option<&Histogram> {
+        self.length_histogram.as_ref()
+    }
+}
+
+/// Bus load during one time window
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LoadSample {
+    /// Hardware timestamp at the start of the window
+    pub start_us: u32,
+    /// Share of the window the bus was busy, in percent
+    pub load_percent: f64,
+}
+
+/// Error frames counted per error class
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ErrorCounts {
+    classes: BTreeMap<&'static str, u64>,
+    tx_error_count: Option<u8>,
+    rx_error_count: Option<u8>,
+}
+
+impl ErrorCounts {
+    fn record(&mut self, error: &CanErrorFrame) {
+        let overflow = error.rx_overflow().map(|_| "rx-overflow");
+        let classes = CLASS_NAMES
+            .iter()
+            .filter(|&&(class, _)| error.has_class(class))
+            .map(|&(_, name)| name);
+        for name in classes.chain(overflow) {
+            *self.classes.entry(name).or_default() += 1;
+        }
+        if let Some(count) = error.tx_error_count() {
+            self.tx_error_count = Some(count);
+        }
+        if let Some(count) = error.rx_error_count() {
+            self.rx_error_count = Some(count);
+        }
+    }
+
+    /// Error frames of a class, by its name in `CanErrorFrame`'s display
+    /// (e.g. "bus-off", "no-ack", "rx-overflow")
+    pub fn class(&self, name: &str) -> u64 {
+        self.classes.get(name).copied().unwrap_or(0)
+    }
+
+    /// Classes seen with their counts, in name order
+    pub fn classes(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.classes.iter().map(|(&name, &count)| (name, count))
+    }
+
+    /// TX error counter last reported by an error frame
+    pub fn tx_error_count(&self) -> Option<u8> {
+        self.tx_error_count
+    }
+
+    /// RX error counter last reported by an error frame
+    pub fn rx_error_count(&self) -> Option<u8> {
+        self.rx_error_count
+    }
+}
+
+/// Load measurement of the current window
+#[derive(Debug, Clone, Copy)]
+struct LoadWindow {
+    bitrate: Bitrate,
+    length_us: u32,
+    start_us: Option<u32>,
+    busy: Duration,
+}
+
+/// An identifier seen for the first time after the learning phase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewId {
+    /// The identifier
+    pub id: CanId,
+    /// Hardware timestamp of its first frame
+    pub timestamp_us: u32,
+    /// Payload of its first frame
+    pub data: Vec<u8>,
+}
+
+/// Learning phase of new-identifier discovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Learning {
+    /// Until `duration` past the first frame's timestamp
+    For(Duration, Option<u32>),
+    /// Until `end_learning()`
+    Manual,
+    Done,
+}
+
+/// Per-identifier statistics, see the module documentation
+///
+/// Error frames are counted but not added to the identifier table.
+#[derive(Debug, Clone)]
+pub struct BusStats {
+    ids: BTreeMap<CanId, IdStats>,
+    frames: u64,
+    error_frames: u64,
+    errors: ErrorCounts,
+    last_timestamp_us: u32,
+    load_window: Option<LoadWindow>,
+    load: VecDeque<LoadSample>,
+    learning: Learning,
+    period_histogram: Option<Histogram>,
+    length_histogram: Option<Histogram>,
+}
+
+impl Default for BusStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BusStats {
+    /// Statistics reporting every identifier as new on its first frame
+    pub fn new() -> Self {
+        Self {
+            ids: BTreeMap::new(),
+            frames: 0,
+            error_frames: 0,
+            errors: ErrorCounts::default(),
+            last_timestamp_us: 0,
+            load_window: None,
+            load: VecDeque::new(),
+            learning: Learning::Done,
+            period_histogram: None,
+            length_histogram: None,
+        }
+    }
+
+    /// Statistics learning the identifiers of the first `duration` of
+    /// traffic as known
+    pub fn with_learning(duration: Duration) -> Self {
+        Self {
+            learning: Learning::For(duration, None),
+            ..Self::new()
+        }
+    }
+
+    /// Statistics learning identifiers as known until `end_learning()`
+    pub fn learning() -> Self {
+        Self {
+            learning: Learning::Manual,
+            ..Self::new()
+        }
+    }
+
+    /// End the learning phase; identifiers not seen so far are new
+    pub fn end_learning(&mut self) {
+        self.learning = Learning::Done;
+    }
+
+    /// Check if identifiers are still being learned
+    pub fn is_learning(&self) -> bool {
+        self.learning != Learning::Done
+    }
+
+    /// Keep a histogram of inter-arrival times in microseconds per
+    /// identifier, with the buckets of `histogram`
+    ///
+    /// The histograms of identifiers already seen start over.
+    pub fn set_period_histogram(&mut self, histogram: Histogram) {
+        let empty = self.empty_histogram(&histogram);
+        for stats in self.ids.values_mut() {
+            stats.period_histogram = Some(empty.clone());
+        }
+        self.period_histogram = Some(empty);
+    }
+
+    /// Keep a histogram of payload lengths in bytes per identifier, with the
+    /// buckets of `histogram`
+    ///
+    /// The histograms of identifiers already seen start over.
+    pub fn set_length_histogram(&mut self, histogram: Histogram) {
+        let empty = self.empty_histogram(&histogram);
+        for stats in self.ids.values_mut() {
+            stats.length_histogram = Some(empty.clone());
+        }
+        self.length_histogram = Some(empty);
+    }
+
+    /// Keep a history of the bus load in windows of `window`, computed from
+    /// the frames' lengths at `bitrate`
+    ///
+    /// The load is estimated without stuff bits, like
+    /// `generator::frame_duration()` does. The last `LOAD_HISTORY_LEN`
+    /// windows are kept.
+    pub fn set_bitrate(&mut self, bitrate: Bitrate, window: Duration) {
+        self.load_window = Some(LoadWindow {
+            bitrate,
+            length_us: (window.as_micros() as u32).max(1),
+            start_us: None,
+            busy: Duration::ZERO,
+        });
+        self.load.clear();
+    }
+
+    /// Bus load of the completed windows, oldest first
+    pub fn load_history(&self) -> impl Iterator<Item = &LoadSample> {
+        self.load.iter()
+    }
+
+    fn record_load(&mut self, frame: &GsUsbFrame) {
+        let Some(window) = &mut self.load_window else {
+            return;
+        };
+        let timestamp_us = frame.timestamp_us;
+        let start_us = *window.start_us.get_or_insert(timestamp_us);
+        let elapsed = timestamp_us.wrapping_sub(start_us);
+        // A frame stamped before the window, e.g. an echo read after a
+        // later reception, counts in the current window
+        if elapsed < 1 << 31 && elapsed >= window.length_us {
+            let windows = elapsed / window.length_us;
+            let length = Duration::from_micros(u64::from(window.length_us));
+            let skip = (windows as usize).saturating_sub(LOAD_HISTORY_LEN);
+            for i in skip as u32..windows {
+                let busy = if i == 0 { window.busy } else { Duration::ZERO };
+                self.load.push_back(LoadSample {
+                    start_us: start_us.wrapping_add(i.wrapping_mul(window.length_us)),
+                    load_percent: 100.0 * busy.as_secs_f64() / length.as_secs_f64(),
+                });
+            }
+            while self.load.len() > LOAD_HISTORY_LEN {
+                self.load.pop_front();
+            }
+            window.start_us = Some(start_us.wrapping_add(windows * window.length_us));
+            window.busy = Duration::ZERO;
+        }
+        if !frame.is_error_frame() {
+            window.busy += frame_duration(frame, &window.bitrate);
+        }
+    }
+
+    fn empty_histogram(&self, histogram: &Histogram) -> Histogram {
+        let mut empty = histogram.clone();
+        empty.reset();
+        empty
+    }
+
+    /// Empty statistics for a newly seen identifier
+    fn new_id_stats(&self) -> IdStats {
+        IdStats {
+            period_histogram: self.period_histogram.clone(),
+            length_histogram: self.length_histogram.clone(),
+            ..IdStats::default()
+        }
+    }
+
+    /// Add a frame, reporting its identifier if it is new
+    pub fn observe(&mut self, frame: &GsUsbFrame) -> Option<NewId> {
+        self.frames += 1;
+        self.last_timestamp_us = frame.timestamp_us;
+        self.record_load(frame);
+        if let Some(error) = CanErrorFrame::from_frame(frame) {
+            self.error_frames += 1;
+            self.errors.record(&error);
+            return None;
+        }
+        let timestamp_us = frame.timestamp_us;
+        if let Learning::For(duration, start) = &mut self.learning {
+            let start = *start.get_or_insert(timestamp_us);
+            let elapsed = u64::from(timestamp_us.wrapping_sub(start));
+            if elapsed >= duration.as_micros() as u64 {
+                self.learning = Learning::Done;
+            }
+        }
+
+        let id = CanId::from_can_id(frame.can_id);
+        let data = frame.data();
+        let payload_bytes = data.len() as u64;
+        if let Some(stats) = self.ids.get_mut(&id) {
+            if stats.count > 0 {
+                let period_us = timestamp_us.wrapping_sub(stats.last_timestamp_us);
+                stats.period.record(period_us);
+                if let Some(histogram) = &mut stats.period_histogram {
+                    histogram.record(u64::from(period_us));
+                }
+            } else {
+                stats.first_timestamp_us = timestamp_us;
+            }
+            stats.count += 1;
+            stats.payload_bytes += payload_bytes;
+            stats.last_timestamp_us = timestamp_us;
+            if let Some(histogram) = &mut stats.length_histogram {
+                histogram.record(payload_bytes);
+            }
+            return None;
+        }
+        let mut stats = IdStats {
+            count: 1,
+            payload_bytes,
+            first_timestamp_us: timestamp_us,
+            last_timestamp_us: timestamp_us,
+            ..self.new_id_stats()
+        };
+        if let Some(histogram) = &mut stats.length_histogram {
+            histogram.record(payload_bytes);
+        }
+        self.ids.insert(id, stats);
+        (!self.is_learning()).then(|| NewId {
+            id,
+            timestamp_us,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Statistics of an identifier, if it has been seen
+    pub fn id(&self, id: CanId) -> Option<&IdStats> {
+        self.ids.get(&id)
+    }
+
+    /// Statistics of all identifiers seen, in ascending identifier order
+    pub fn ids(&self) -> impl Iterator<Item = (CanId, &IdStats)> {
+        self.ids.iter().map(|(&id, stats)| (id, stats))
+    }
+
+    /// Frames seen, including error frames
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Error frames seen
+    pub fn error_frames(&self) -> u64 {
+        self.error_frames
+    }
+
+    /// Error frames per class and the last reported error counters
+    pub fn errors(&self) -> &ErrorCounts {
+        &self.errors
+    }
+
+    /// Hardware timestamp of the last frame seen
+    pub fn last_timestamp_us(&self) -> u32 {
+        self.last_timestamp_us
+    }
+
+    /// All statistics at this point, for export
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            timestamp_us: self.last_timestamp_us,
+            frames: self.frames,
+            error_frames: self.error_frames,
+            ids: self
+                .ids
+                .iter()
+                .map(|(&id, stats)| IdRow::new(id, stats))
+                .collect(),
+            load: self.load.iter().copied().collect(),
+            errors: self.errors.clone(),
+        }
+    }
+
+    /// Forget the statistics, keeping the learned identifiers as known
+    pub fn reset(&mut self) {
+        let empty = self.new_id_stats();
+        for stats in self.ids.values_mut() {
+            *stats = empty.clone();
+        }
+        self.frames = 0;
+        self.error_frames = 0;
+        self.errors = ErrorCounts::default();
+        self.load.clear();
+        if let Some(window) = &mut self.load_window {
+            window.start_us = None;
+            window.busy = Duration::ZERO;
+        }
+    }
+}
+
+/// Statistics of the USB bulk transfers a device received
+///
+/// Each transfer carries one frame, so a transfer shorter than a full frame
+/// of the current mode is either a compact frame sent by the firmware
+/// (short) or one cut inside its header or payload that can't be decoded
+/// (truncated). Failed and timed out reads are no transfers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TransferStats {
+    transfers: u64,
+    bytes: u64,
+    frames: u64,
+    short_transfers: u64,
+    truncated_transfers: u64,
+    sizes: BTreeMap<usize, u64>,
+}
+
+impl TransferStats {
+    /// Record a transfer of `len` bytes, in a mode whose full frames take
+    /// `full_size` bytes, and the result of decoding it
+    pub(crate) fn record(&mut self, len: usize, full_size: usize, decoded: &Result<GsUsbFrame>) {
+        self.transfers += 1;
+        self.bytes += len as u64;
+        *self.sizes.entry(len).or_default() += 1;
+        match decoded {
+            Ok(_) => {
+                self.frames += 1;
+                if len < full_size {
+                    self.short_transfers += 1;
+                }
+            }
+            Err(GsUsbError::InvalidResponse { .. }) => self.truncated_transfers += 1,
+            Err(_) => {}
+        }
+    }
+
+    /// Transfers received
+    pub fn transfers(&self) -> u64 {
+        self.transfers
+    }
+
+    /// Bytes received in all transfers
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Frames decoded from the transfers
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Transfers decoded although shorter than a full frame
+    pub fn short_transfers(&self) -> u64 {
+        self.short_transfers
+    }
+
+    /// Transfers cut inside the frame header or payload, which were
+    /// rejected with `InvalidResponse`
+    pub fn truncated_transfers(&self) -> u64 {
+        self.truncated_transfers
+    }
+
+    /// Transfers per size in bytes, in ascending size
+    pub fn sizes(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.sizes.iter().map(|(&len, &count)| (len, count))
+    }
+
+    /// Size of the smallest transfer
+    pub fn min_bytes(&self) -> Option<usize> {
+        self.sizes.keys().next().copied()
+    }
+
+    /// Size of the largest transfer
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.sizes.keys().next_back().copied()
+    }
+
+    /// Average bytes per transfer
+    pub fn bytes_per_transfer(&self) -> Option<f64> {
+        (self.transfers > 0).then(|| self.bytes as f64 / self.transfers as f64)
+    }
+
+    /// Average frames decoded per transfer
+    pub fn frames_per_transfer(&self) -> Option<f64> {
+        (self.transfers > 0).then(|| self.frames as f64 / self.transfers as f64)
+    }
+
+    /// Forget the transfers recorded
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Count, total and longest of the times spent in one stage of reading
+#[cfg(feature = "instrument")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageTime {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+#[cfg(feature = "instrument")]
+impl StageTime {
+    fn record(&mut self, time: Duration) {
+        self.count += 1;
+        self.total += time;
+        self.max = self.max.max(time);
+    }
+
+    /// Times measured
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of the times
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Longest time
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Mean time
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0)
+            .then(|| Duration::from_nanos((self.total.as_nanos() / u128::from(self.count)) as u64))
+    }
+}
+
+/// Where `GsUsb::read()` and the other reads spend their time, measured
+/// with the `instrument` feature
+///
+/// Each received frame goes through three stages: waiting for the bulk
+/// transfer, which includes any time the bus was quiet; parsing it and
+/// attaching timestamps; and dispatching it to the taps and through the
+/// overflow handling and filters until a read returns it. Frames kept on
+/// the host by `peek()` and `read_until()`, or held back behind an overflow
+/// report, add a fourth: waiting in the queue until a read takes them.
+#[cfg(feature = "instrument")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HotPathStats {
+    transfer_wait: StageTime,
+    parse: StageTime,
+    dispatch: StageTime,
+    queue_wait: StageTime,
+    /// End of parsing the frame being dispatched
+    parsed_at: Option<Instant>,
+}
+
+#[cfg(feature = "instrument")]
+impl HotPathStats {
+    pub(crate) fn transferred(&mut self, wait: Duration) {
+        self.transfer_wait.record(wait);
+    }
+
+    /// Parsing that started at `start` ended now
+    pub(crate) fn parsed(&mut self, start: Instant) {
+        let now = Instant::now();
+        self.parse.record(now - start);
+        self.parsed_at = Some(now);
+    }
+
+    /// The frame parsed last is handed out now
+    pub(crate) fn dispatched(&mut self) {
+        if let Some(parsed_at) = self.parsed_at.take() {
+            self.dispatch.record(parsed_at.elapsed());
+        }
+    }
+
+    /// A frame kept since `queued_at` is handed out now
+    pub(crate) fn unqueued(&mut self, queued_at: Instant) {
+        self.queue_wait.record(queued_at.elapsed());
+    }
+
+    /// A frame held back since `queued_at` is dispatched again now
+    pub(crate) fn resumed(&mut self, queued_at: Instant) {
+        let now = Instant::now();
+        self.queue_wait.record(now - queued_at);
+        self.parsed_at = Some(now);
+    }
+
+    /// Waiting for bulk transfers that brought a frame
+    pub fn transfer_wait(&self) -> &StageTime {
+        &self.transfer_wait
+    }
+
+    /// Decoding transfers and attaching timestamps
+    pub fn parse(&self) -> &StageTime {
+        &self.parse
+    }
+
+    /// Taps, overflow handling and filters, for frames a read returned
+    pub fn dispatch(&self) -> &StageTime {
+        &self.dispatch
+    }
+
+    /// Waiting on the host, for frames kept or held back before a read
+    /// returned them
+    pub fn queue_wait(&self) -> &StageTime {
+        &self.queue_wait
+    }
+
+    /// Forget the times measured
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{CAN_EFF_FLAG, CAN_ERR_FLAG};
+
+    fn frame(can_id: u32, timestamp_us: u32, data: &[u8]) -> GsUsbFrame {
+        let mut frame = GsUsbFrame::with_data(can_id, data);
+        frame.timestamp_us = timestamp_us;
+        frame
+    }
+
+    #[test]
+    fn test_table() {
+        let mut stats = BusStats::new();
+        assert!(stats.observe(&frame(0x100, 0, &[1, 2])).is_some());
+        assert!(stats.observe(&frame(0x100, 10_000, &[1, 2, 3])).is_none());
+        stats.observe(&frame(0x100 | CAN_EFF_FLAG, 12_000, &[]));
+        stats.observe(&frame(CAN_ERR_FLAG, 13_000, &[0; 8]));
+
+        let id = stats.id(CanId::Standard(0x100)).unwrap();
+        assert_eq!((id.count(), id.payload_bytes()), (2, 5));
+        assert_eq!(id.period().mean(), Some(Duration::from_millis(10)));
+        assert_eq!(id.last_timestamp_us(), 10_000);
+        assert_eq!(stats.ids().count(), 2);
+        assert_eq!((stats.frames(), stats.error_frames()), (4, 1));
+
+        stats.reset();
+        assert_eq!(stats.frames(), 0);
+        assert!(stats.observe(&frame(0x100, 20_000, &[])).is_none());
+        let id = stats.id(CanId::Standard(0x100)).unwrap();
+        assert_eq!((id.count(), id.first_timestamp_us()), (1, 20_000));
+        assert_eq!(id.period().count(), 0);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new(&[20, 10]);
+        for value in [0, 9, 10, 19, 20, 1000] {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.bounds(), [10, 20]);
+        assert_eq!(histogram.counts(), [2, 2, 2]);
+        assert_eq!(
+            histogram.buckets()[1],
+            Bucket {
+                low: 10,
+                high: Some(20),
+                count: 2
+            }
+        );
+        assert_eq!(histogram.buckets()[2].high, None);
+        assert_eq!(Histogram::linear(8, 3).bounds(), [8, 16, 24]);
+        assert_eq!(
+            Histogram::exponential(1000, 10_000).bounds(),
+            [1000, 2000, 4000, 8000]
+        );
+    }
+
+    #[test]
+    fn test_id_histograms() {
+        let mut stats = BusStats::new();
+        stats.observe(&frame(0x100, 0, &[1]));
+        stats.set_period_histogram(Histogram::new(&[9_000, 11_000]));
+        stats.set_length_histogram(Histogram::linear(4, 2));
+        for (at, len) in [(10_000, 1), (21_500, 8), (30_000, 8), (50_000, 4)] {
+            stats.observe(&frame(0x100, at, &vec![0; len]));
+        }
+        let id = stats.id(CanId::Standard(0x100)).unwrap();
+        assert_eq!(id.period_histogram().unwrap().counts(), [1, 1, 2]);
+        assert_eq!(id.length_histogram().unwrap().counts(), [1, 1, 2]);
+
+        // New identifiers and reset statistics get empty histograms
+        stats.observe(&frame(0x200, 50_000, &[]));
+        let id = stats.id(CanId::Standard(0x200)).unwrap();
+        assert_eq!(id.length_histogram().unwrap().total(), 1);
+        assert_eq!(id.period_histogram().unwrap().total(), 0);
+        stats.reset();
+        let id = stats.id(CanId::Standard(0x100)).unwrap();
+        assert_eq!(id.length_histogram().unwrap().total(), 0);
+    }
+
+    #[test]
+    fn test_load_history() {
+        let mut stats = BusStats::new();
+        // 111 bits at 100 kbit/s, 1.11 ms per frame
+        stats.set_bitrate(Bitrate::new(100_000), Duration::from_millis(10));
+        for at in [1_000, 3_000, 10_999] {
+            stats.observe(&frame(0x100, at, &[0; 8]));
+        }
+        assert_eq!(stats.load_history().count(), 0);
+        // Closes the first window and skips an empty one
+        stats.observe(&frame(0x100, 31_000, &[0; 8]));
+        let load: Vec<_> = stats.load_history().copied().collect();
+        assert_eq!(load.len(), 3);
+        assert_eq!(load[0].start_us, 1_000);
+        assert!((load[0].load_percent - 33.3).abs() < 0.01);
+        assert_eq!((load[2].start_us, load[2].load_percent), (21_000, 0.0));
+    }
+
+    #[test]
+    fn test_error_counts() {
+        use crate::constants::{CAN_ERR_ACK, CAN_ERR_BUSOFF, CAN_ERR_CNT};
+
+        let mut stats = BusStats::new();
+        let mut error = frame(0, 0, &[0, 0, 0, 0, 0, 0, 200, 7]);
+        error.can_id = CAN_ERR_FLAG | CAN_ERR_ACK | CAN_ERR_CNT;
+        stats.observe(&error);
+        error.can_id = CAN_ERR_FLAG | CAN_ERR_ACK | CAN_ERR_BUSOFF;
+        stats.observe(&error);
+        let errors = stats.errors();
+        assert_eq!((errors.class("no-ack"), errors.class("bus-off")), (2, 1));
+        assert_eq!(
+            (errors.tx_error_count(), errors.rx_error_count()),
+            (Some(200), Some(7))
+        );
+        assert_eq!(errors.classes().count(), 2);
+    }
+
+    #[test]
+    fn test_new_ids() {
+        let mut stats = BusStats::with_learning(Duration::from_secs(1));
+        for (id, at) in [(0x100, 100_000), (0x200, 600_000), (0x100, 1_099_999)] {
+            assert_eq!(stats.observe(&frame(id, at, &[])), None);
+        }
+        assert!(stats.is_learning());
+        assert_eq!(stats.observe(&frame(0x200, 1_100_000, &[])), None);
+        assert!(!stats.is_learning());
+
+        let new = stats.observe(&frame(0x300, 1_200_000, &[7])).unwrap();
+        assert_eq!(
+            new,
+            NewId {
+                id: CanId::Standard(0x300),
+                timestamp_us: 1_200_000,
+                data: vec![7],
+            }
+        );
+        assert_eq!(stats.observe(&frame(0x300, 1_300_000, &[7])), None);
+
+        let mut stats = BusStats::learning();
+        assert_eq!(stats.observe(&frame(0x100, 0, &[])), None);
+        stats.end_learning();
+        assert!(stats.observe(&frame(0x101, 0, &[])).is_some());
+    }
+}
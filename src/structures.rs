@@ -2,11 +2,38 @@
 //!
 //! This module contains the data structures used in the GS-USB protocol
 //! for device configuration, bit timing, and state management.
+//!
+//! Unpacking a device response (`DeviceInfo`, `DeviceCapability`,
+//! `DeviceState`) fails with `GsUsbError::InvalidResponse` if the buffer is
+//! too short. The host-to-device structures (`DeviceMode`, `DeviceBitTiming`)
+//! read missing bytes as zero.
 
 use crate::constants::{
-    can_state_name, GS_CAN_STATE_BUS_OFF, GS_CAN_STATE_ERROR_ACTIVE, GS_CAN_STATE_ERROR_PASSIVE,
-    GS_CAN_STATE_ERROR_WARNING,
+    can_state_name, feature_flag_name, GS_CAN_STATE_BUS_OFF, GS_CAN_STATE_ERROR_ACTIVE,
+    GS_CAN_STATE_ERROR_PASSIVE, GS_CAN_STATE_ERROR_WARNING, GS_CAN_STATE_SLEEPING,
 };
+use crate::error::{GsUsbError, Result};
+
+/// Byte at `offset`, zero if `data` is too short
+fn byte(data: &[u8], offset: usize) -> u8 {
+    data.get(offset).copied().unwrap_or(0)
+}
+
+/// Fail with `InvalidResponse` if `data` is shorter than `expected`
+fn check_len(data: &[u8], expected: usize) -> Result<()> {
+    if data.len() < expected {
+        return Err(GsUsbError::InvalidResponse {
+            expected,
+            actual: data.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Little-endian u32 at `offset`, with missing bytes read as zero
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(std::array::from_fn(|i| byte(data, offset + i)))
+}
 
 /// Device mode configuration
 ///
@@ -32,6 +59,14 @@ impl DeviceMode {
         buf[4..8].copy_from_slice(&self.flags.to_le_bytes());
         buf
     }
+
+    /// Unpack from bytes received via USB (8 bytes, 2 x uint32)
+    pub fn unpack(data: &[u8]) -> Self {
+        Self {
+            mode: le_u32(data, 0),
+            flags: le_u32(data, 4),
+        }
+    }
 }
 
 impl std::fmt::Display for DeviceMode {
@@ -44,7 +79,7 @@ impl std::fmt::Display for DeviceMode {
 ///
 /// Used to configure the bit timing parameters for both
 /// nominal (arbitration) phase and data phase (CAN FD).
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DeviceBitTiming {
     /// Propagation segment (typically 1)
     pub prop_seg: u32,
@@ -80,6 +115,38 @@ impl DeviceBitTiming {
         buf[16..20].copy_from_slice(&self.brp.to_le_bytes());
         buf
     }
+
+    /// Unpack from bytes received via USB (20 bytes, 5 x uint32)
+    pub fn unpack(data: &[u8]) -> Self {
+        Self {
+            prop_seg: le_u32(data, 0),
+            phase_seg1: le_u32(data, 4),
+            phase_seg2: le_u32(data, 8),
+            sjw: le_u32(data, 12),
+            brp: le_u32(data, 16),
+        }
+    }
+
+    /// Total number of time quanta per bit (sync segment included)
+    pub fn total_tq(&self) -> u32 {
+        1u32.saturating_add(self.prop_seg)
+            .saturating_add(self.phase_seg1)
+            .saturating_add(self.phase_seg2)
+    }
+
+    /// Bitrate produced by this timing for the given CAN clock
+    pub fn bitrate(&self, clock_hz: u32) -> u32 {
+        self.brp
+            .checked_mul(self.total_tq())
+            .and_then(|divisor| clock_hz.checked_div(divisor))
+            .unwrap_or(0)
+    }
+
+    /// Sample point in permille (875 = 87.5%)
+    pub fn sample_point(&self) -> u32 {
+        let total = u64::from(self.total_tq());
+        (1000 * (total - u64::from(self.phase_seg2.min(self.total_tq()))) / total) as u32
+    }
 }
 
 impl std::fmt::Display for DeviceBitTiming {
@@ -112,16 +179,32 @@ pub struct DeviceInfo {
 }
 
 impl DeviceInfo {
-    /// Unpack from bytes received via USB
-    pub fn unpack(data: &[u8]) -> Self {
-        Self {
-            reserved1: data[0],
-            reserved2: data[1],
-            reserved3: data[2],
-            icount: data[3],
-            fw_version: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
-            hw_version: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
-        }
+    /// Size of a DEVICE_CONFIG response in bytes
+    pub const SIZE: usize = 12;
+
+    /// Unpack from bytes received via USB (12 bytes)
+    pub fn unpack(data: &[u8]) -> Result<Self> {
+        check_len(data, Self::SIZE)?;
+        Ok(Self {
+            reserved1: byte(data, 0),
+            reserved2: byte(data, 1),
+            reserved3: byte(data, 2),
+            icount: byte(data, 3),
+            fw_version: le_u32(data, 4),
+            hw_version: le_u32(data, 8),
+        })
+    }
+
+    /// Pack into bytes as sent by the device (12 bytes)
+    pub fn pack(&self) -> [u8; 12] {
+        let mut buf = [0u8; 12];
+        buf[0] = self.reserved1;
+        buf[1] = self.reserved2;
+        buf[2] = self.reserved3;
+        buf[3] = self.icount;
+        buf[4..8].copy_from_slice(&self.fw_version.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.hw_version.to_le_bytes());
+        buf
     }
 
     /// Get the number of CAN channels
@@ -140,6 +223,15 @@ impl DeviceInfo {
     }
 }
 
+impl TryFrom<&[u8]> for DeviceInfo {
+    type Error = GsUsbError;
+
+    /// Parse a DEVICE_CONFIG response, as `unpack()`
+    fn try_from(data: &[u8]) -> Result<Self> {
+        Self::unpack(data)
+    }
+}
+
 impl std::fmt::Display for DeviceInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -199,19 +291,26 @@ pub struct DeviceCapability {
 }
 
 impl DeviceCapability {
+    /// Size of a BT_CONST response in bytes
+    pub const SIZE: usize = 40;
+
+    /// Size of a BT_CONST_EXT response in bytes
+    pub const EXTENDED_SIZE: usize = 72;
+
     /// Unpack from BT_CONST response (40 bytes, 10 x uint32)
-    pub fn unpack(data: &[u8]) -> Self {
-        Self {
-            feature: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
-            fclk_can: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
-            tseg1_min: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
-            tseg1_max: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
-            tseg2_min: u32::from_le_bytes([data[16], data[17], data[18], data[19]]),
-            tseg2_max: u32::from_le_bytes([data[20], data[21], data[22], data[23]]),
-            sjw_max: u32::from_le_bytes([data[24], data[25], data[26], data[27]]),
-            brp_min: u32::from_le_bytes([data[28], data[29], data[30], data[31]]),
-            brp_max: u32::from_le_bytes([data[32], data[33], data[34], data[35]]),
-            brp_inc: u32::from_le_bytes([data[36], data[37], data[38], data[39]]),
+    pub fn unpack(data: &[u8]) -> Result<Self> {
+        check_len(data, Self::SIZE)?;
+        Ok(Self {
+            feature: le_u32(data, 0),
+            fclk_can: le_u32(data, 4),
+            tseg1_min: le_u32(data, 8),
+            tseg1_max: le_u32(data, 12),
+            tseg2_min: le_u32(data, 16),
+            tseg2_max: le_u32(data, 20),
+            sjw_max: le_u32(data, 24),
+            brp_min: le_u32(data, 28),
+            brp_max: le_u32(data, 32),
+            brp_inc: le_u32(data, 36),
             dtseg1_min: None,
             dtseg1_max: None,
             dtseg2_min: None,
@@ -220,21 +319,66 @@ impl DeviceCapability {
             dbrp_min: None,
             dbrp_max: None,
             dbrp_inc: None,
-        }
+        })
     }
 
     /// Unpack from BT_CONST_EXT response (72 bytes, 18 x uint32)
-    pub fn unpack_extended(data: &[u8]) -> Self {
-        let mut cap = Self::unpack(data);
-        cap.dtseg1_min = Some(u32::from_le_bytes([data[40], data[41], data[42], data[43]]));
-        cap.dtseg1_max = Some(u32::from_le_bytes([data[44], data[45], data[46], data[47]]));
-        cap.dtseg2_min = Some(u32::from_le_bytes([data[48], data[49], data[50], data[51]]));
-        cap.dtseg2_max = Some(u32::from_le_bytes([data[52], data[53], data[54], data[55]]));
-        cap.dsjw_max = Some(u32::from_le_bytes([data[56], data[57], data[58], data[59]]));
-        cap.dbrp_min = Some(u32::from_le_bytes([data[60], data[61], data[62], data[63]]));
-        cap.dbrp_max = Some(u32::from_le_bytes([data[64], data[65], data[66], data[67]]));
-        cap.dbrp_inc = Some(u32::from_le_bytes([data[68], data[69], data[70], data[71]]));
-        cap
+    pub fn unpack_extended(data: &[u8]) -> Result<Self> {
+        check_len(data, Self::EXTENDED_SIZE)?;
+        let mut cap = Self::unpack(data)?;
+        cap.dtseg1_min = Some(le_u32(data, 40));
+        cap.dtseg1_max = Some(le_u32(data, 44));
+        cap.dtseg2_min = Some(le_u32(data, 48));
+        cap.dtseg2_max = Some(le_u32(data, 52));
+        cap.dsjw_max = Some(le_u32(data, 56));
+        cap.dbrp_min = Some(le_u32(data, 60));
+        cap.dbrp_max = Some(le_u32(data, 64));
+        cap.dbrp_inc = Some(le_u32(data, 68));
+        Ok(cap)
+    }
+
+    /// Pack into a BT_CONST response as sent by the device (40 bytes)
+    pub fn pack(&self) -> [u8; 40] {
+        let mut buf = [0u8; 40];
+        let fields = [
+            self.feature,
+            self.fclk_can,
+            self.tseg1_min,
+            self.tseg1_max,
+            self.tseg2_min,
+            self.tseg2_max,
+            self.sjw_max,
+            self.brp_min,
+            self.brp_max,
+            self.brp_inc,
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&field.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Pack into a BT_CONST_EXT response as sent by the device (72 bytes)
+    ///
+    /// Missing data phase fields are sent as zero.
+    pub fn pack_extended(&self) -> [u8; 72] {
+        let mut buf = [0u8; 72];
+        buf[..40].copy_from_slice(&self.pack());
+        let fields = [
+            self.dtseg1_min,
+            self.dtseg1_max,
+            self.dtseg2_min,
+            self.dtseg2_max,
+            self.dsjw_max,
+            self.dbrp_min,
+            self.dbrp_max,
+            self.dbrp_inc,
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            let offset = 40 + i * 4;
+            buf[offset..offset + 4].copy_from_slice(&field.unwrap_or(0).to_le_bytes());
+        }
+        buf
     }
 
     /// Check if CAN FD data phase timing is available
@@ -246,6 +390,30 @@ impl DeviceCapability {
     pub fn clock_mhz(&self) -> f32 {
         self.fclk_can as f32 / 1_000_000.0
     }
+
+    /// Names of the feature flags set, lowest bit first
+    ///
+    /// Bits without a known feature are named `"UNKNOWN"`.
+    pub fn feature_names(&self) -> Vec<&'static str> {
+        (0..32)
+            .map(|bit| 1 << bit)
+            .filter(|flag| self.feature & flag != 0)
+            .map(feature_flag_name)
+            .collect()
+    }
+}
+
+impl TryFrom<&[u8]> for DeviceCapability {
+    type Error = GsUsbError;
+
+    /// Parse a BT_CONST response, or a BT_CONST_EXT one if `data` is long enough
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() >= Self::EXTENDED_SIZE {
+            Self::unpack_extended(data)
+        } else {
+            Self::unpack(data)
+        }
+    }
 }
 
 impl std::fmt::Display for DeviceCapability {
@@ -308,13 +476,26 @@ pub struct DeviceState {
 }
 
 impl DeviceState {
+    /// Size of a GET_STATE response in bytes
+    pub const SIZE: usize = 12;
+
     /// Unpack from GET_STATE response (12 bytes, 3 x uint32)
-    pub fn unpack(data: &[u8]) -> Self {
-        Self {
-            state: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
-            rxerr: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
-            txerr: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
-        }
+    pub fn unpack(data: &[u8]) -> Result<Self> {
+        check_len(data, Self::SIZE)?;
+        Ok(Self {
+            state: le_u32(data, 0),
+            rxerr: le_u32(data, 4),
+            txerr: le_u32(data, 8),
+        })
+    }
+
+    /// Pack into a GET_STATE response as sent by the device (12 bytes)
+    pub fn pack(&self) -> [u8; 12] {
+        let mut buf = [0u8; 12];
+        buf[0..4].copy_from_slice(&self.state.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.rxerr.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.txerr.to_le_bytes());
+        buf
     }
 
     /// Get human-readable state name
@@ -341,6 +522,20 @@ impl DeviceState {
     pub fn is_bus_off(&self) -> bool {
         self.state == GS_CAN_STATE_BUS_OFF
     }
+
+    /// Check if the controller sleeps until there is bus activity
+    pub fn is_sleeping(&self) -> bool {
+        self.state == GS_CAN_STATE_SLEEPING
+    }
+}
+
+impl TryFrom<&[u8]> for DeviceState {
+    type Error = GsUsbError;
+
+    /// Parse a GET_STATE response, as `unpack()`
+    fn try_from(data: &[u8]) -> Result<Self> {
+        Self::unpack(data)
+    }
 }
 
 impl std::fmt::Display for DeviceState {
@@ -358,6 +553,7 @@ impl std::fmt::Display for DeviceState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::{GS_CAN_FEATURE_FD, GS_CAN_FEATURE_LISTEN_ONLY};
 
     #[test]
     fn test_device_mode_pack() {
@@ -381,7 +577,7 @@ mod tests {
     #[test]
     fn test_device_info_unpack() {
         let data = [0, 0, 0, 1, 20, 0, 0, 0, 10, 0, 0, 0];
-        let info = DeviceInfo::unpack(&data);
+        let info = DeviceInfo::unpack(&data).unwrap();
         assert_eq!(info.icount, 1);
         assert_eq!(info.channel_count(), 2);
         assert_eq!(info.fw_version, 20);
@@ -390,13 +586,118 @@ mod tests {
         assert_eq!(info.hardware_version(), 1.0);
     }
 
+    #[test]
+    fn test_try_from() {
+        let info = DeviceInfo::try_from(&[0, 0, 0, 1, 20, 0, 0, 0, 10, 0, 0, 0][..]).unwrap();
+        assert_eq!(info.channel_count(), 2);
+        assert!(DeviceInfo::try_from(&[0; 11][..]).is_err());
+
+        let state = DeviceState::try_from(&[2, 0, 0, 0, 128, 0, 0, 0, 5, 0, 0, 0][..]).unwrap();
+        assert!(state.is_error_passive());
+        assert_eq!((state.rxerr, state.txerr), (128, 5));
+
+        let mut data = [0u8; 72];
+        data[40] = 3;
+        let classic = DeviceCapability::try_from(&data[..40]).unwrap();
+        assert_eq!(classic.dtseg1_min, None);
+        let extended = DeviceCapability::try_from(&data[..]).unwrap();
+        assert_eq!(extended.dtseg1_min, Some(3));
+        assert!(DeviceCapability::try_from(&data[..39]).is_err());
+    }
+
+    #[test]
+    fn test_device_capability_pack_roundtrip() {
+        let mut data = [0u8; 72];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let cap = DeviceCapability::unpack_extended(&data).unwrap();
+        assert_eq!(cap.pack_extended(), data);
+        assert_eq!(cap.pack()[..], data[..40]);
+    }
+
+    #[test]
+    fn test_feature_names() {
+        let mut cap = DeviceCapability::unpack(&[0; 40]).unwrap();
+        assert!(cap.feature_names().is_empty());
+        cap.feature = GS_CAN_FEATURE_LISTEN_ONLY | GS_CAN_FEATURE_FD | 1 << 31;
+        assert_eq!(cap.feature_names(), ["LISTEN_ONLY", "FD", "UNKNOWN"]);
+    }
+
+    #[test]
+    fn test_device_bit_timing_bitrate() {
+        let timing = DeviceBitTiming::unpack(&DeviceBitTiming::new(34, 35, 10, 5, 1).pack());
+        assert_eq!(timing.total_tq(), 80);
+        assert_eq!(timing.bitrate(40_000_000), 500_000);
+    }
+
     #[test]
     fn test_device_state_unpack() {
         let data = [1, 0, 0, 0, 50, 0, 0, 0, 25, 0, 0, 0];
-        let state = DeviceState::unpack(&data);
+        let state = DeviceState::unpack(&data).unwrap();
         assert_eq!(state.state, 1);
         assert_eq!(state.rxerr, 50);
         assert_eq!(state.txerr, 25);
         assert!(state.is_error_warning());
     }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn device_mode_round_trips(mode: u32, flags: u32) {
+                let packed = DeviceMode::new(mode, flags).pack();
+                prop_assert_eq!(DeviceMode::unpack(&packed).pack(), packed);
+            }
+
+            #[test]
+            fn bit_timing_round_trips(fields: [u32; 5]) {
+                let [prop_seg, phase_seg1, phase_seg2, sjw, brp] = fields;
+                let packed = DeviceBitTiming::new(prop_seg, phase_seg1, phase_seg2, sjw, brp).pack();
+                prop_assert_eq!(DeviceBitTiming::unpack(&packed).pack(), packed);
+            }
+
+            #[test]
+            fn device_info_round_trips(bytes: [u8; 12]) {
+                prop_assert_eq!(DeviceInfo::unpack(&bytes).unwrap().pack(), bytes);
+            }
+
+            #[test]
+            fn device_state_round_trips(bytes: [u8; 12]) {
+                prop_assert_eq!(DeviceState::unpack(&bytes).unwrap().pack(), bytes);
+            }
+
+            #[test]
+            fn capability_round_trips(bytes in prop::collection::vec(any::<u8>(), 72)) {
+                let cap = DeviceCapability::unpack_extended(&bytes).unwrap();
+                prop_assert_eq!(&cap.pack_extended()[..], &bytes[..]);
+                prop_assert_eq!(&DeviceCapability::unpack(&bytes).unwrap().pack()[..], &bytes[..40]);
+            }
+
+            #[test]
+            fn unpack_is_total(bytes in prop::collection::vec(any::<u8>(), 0..80)) {
+                let _ = DeviceMode::unpack(&bytes);
+                let _ = DeviceBitTiming::unpack(&bytes).bitrate(80_000_000);
+                let _ = DeviceInfo::unpack(&bytes);
+                let _ = DeviceCapability::unpack(&bytes);
+                let _ = DeviceCapability::unpack_extended(&bytes);
+                let _ = DeviceState::unpack(&bytes).map(|state| state.state_name());
+            }
+
+            #[test]
+            fn short_input_is_rejected(bytes in prop::collection::vec(any::<u8>(), 0..72)) {
+                let len = bytes.len();
+                prop_assert_eq!(DeviceInfo::unpack(&bytes).is_ok(), len >= DeviceInfo::SIZE);
+                prop_assert_eq!(DeviceState::unpack(&bytes).is_ok(), len >= DeviceState::SIZE);
+                prop_assert_eq!(DeviceCapability::unpack(&bytes).is_ok(), len >= DeviceCapability::SIZE);
+                let rejected = matches!(
+                    DeviceCapability::unpack_extended(&bytes),
+                    Err(GsUsbError::InvalidResponse { expected: 72, actual }) if actual == len
+                );
+                prop_assert!(rejected);
+            }
+        }
+    }
 }
@@ -0,0 +1,10 @@
+//! Mutex helpers for the modules sharing a device between threads
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Lock a mutex, also when another thread panicked while holding it
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
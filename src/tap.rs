@@ -0,0 +1,123 @@
+//! Passive observers of a device's traffic
+//!
+//! A tap registered with `GsUsb::tap()` is called with every frame the
+//! device receives, whichever of `read()`, `peek()`, `read_until()` or
+//! `read_many()` takes it from the device, and whatever the acceptance
+//! filters. It can't change or consume frames, so logging and statistics
+//! can ride along with an application's own read loop without touching
+//! it. With `GsUsb::tap_with()`, taps also see echoes and sent frames.
+//!
+//! Taps run on the thread reading or sending, before the frame is handed
+//! on, so they should be quick.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{GsUsb, GS_CAN_MODE_NORMAL};
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let received = Arc::new(AtomicU64::new(0));
+//! let counter = Arc::clone(&received);
+//! dev.tap(move |_frame| {
+//!     counter.fetch_add(1, Ordering::Relaxed);
+//! });
+//!
+//! // The application's read loop is unchanged
+//! for _ in 0..100 {
+//!     let frame = dev.read(Duration::from_secs(1))?;
+//!     println!("{frame}");
+//! }
+//! println!("{} frames received", received.load(Ordering::Relaxed));
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use crate::frame::GsUsbFrame;
+
+/// Which way a tapped frame went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapDirection {
+    /// Received from the device, including echoes and error frames
+    Rx,
+    /// Sent to the device
+    Tx,
+}
+
+/// Frames a tap sees besides received ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TapOptions {
+    /// Echoes of sent frames
+    pub echoes: bool,
+    /// Frames sent, once the device accepted them
+    pub tx: bool,
+}
+
+/// Handle of a registered tap, for `GsUsb::untap()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TapId(u64);
+
+type Callback = Box<dyn FnMut(TapDirection, &GsUsbFrame) + Send>;
+
+struct Tap {
+    id: TapId,
+    options: TapOptions,
+    callback: Callback,
+}
+
+/// The taps of a device
+#[derive(Default)]
+pub(crate) struct Taps {
+    taps: Vec<Tap>,
+    next_id: u64,
+}
+
+impl Taps {
+    pub(crate) fn add(
+        &mut self,
+        options: TapOptions,
+        callback: impl FnMut(TapDirection, &GsUsbFrame) + Send + 'static,
+    ) -> TapId {
+        let id = TapId(self.next_id);
+        self.next_id += 1;
+        self.taps.push(Tap {
+            id,
+            options,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: TapId) -> bool {
+        let before = self.taps.len();
+        self.taps.retain(|tap| tap.id != id);
+        self.taps.len() < before
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.taps.len()
+    }
+
+    /// Show a received frame to the taps that want it
+    pub(crate) fn rx(&mut self, frame: &GsUsbFrame) {
+        let echo = frame.is_echo_frame();
+        for tap in &mut self.taps {
+            if !echo || tap.options.echoes {
+                (tap.callback)(TapDirection::Rx, frame);
+            }
+        }
+    }
+
+    /// Show a sent frame to the taps that want it
+    pub(crate) fn tx(&mut self, frame: &GsUsbFrame) {
+        for tap in &mut self.taps {
+            if tap.options.tx {
+                (tap.callback)(TapDirection::Tx, frame);
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Host-device time synchronization
+//!
+//! Hardware timestamps count microseconds from an arbitrary point in the
+//! device's life, on a crystal that drifts against the host clock. `TimeSync`
+//! reads the device counter (`GS_USB_BREQ_TIMESTAMP`) a few times against the
+//! host's monotonic clock, estimates offset and rate, and converts frame
+//! timestamps to host `Instant`s.
+//!
+//! Each call to `sample()` adds one sync point: the reading with the shortest
+//! request round trip of a short burst. The offset is known after the first
+//! point; the rate is estimated once the points span at least
+//! `MIN_RATE_BASELINE`, and becomes more precise the longer they span.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::device::GsUsb;
+use crate::error::Result;
+use crate::timestamp::TimestampExtender;
+
+/// Default number of requests per sync point
+pub const DEFAULT_SAMPLES: usize = 8;
+
+/// Shortest span of sync points used to estimate the rate
+pub const MIN_RATE_BASELINE: Duration = Duration::from_secs(1);
+
+/// Number of sync points the fit is based on
+const MAX_POINTS: usize = 32;
+
+/// Largest plausible rate deviation of a device crystal (1000 ppm)
+const MAX_DRIFT: f64 = 1e-3;
+
+/// Relation between the device timestamp counter and host time
+///
+/// # Example
+///
+/// ```no_run
+/// use gs_usb::{GsUsb, TimeSync, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_NORMAL};
+/// use std::time::Duration;
+///
+/// let mut dev = GsUsb::scan()?.remove(0);
+/// dev.set_bitrate(500_000)?;
+/// dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)?;
+///
+/// let sync = TimeSync::measure(&mut dev)?;
+/// let frame = dev.read(Duration::from_secs(1))?;
+/// println!("received {:?} ago", sync.to_host(frame.timestamp_us).unwrap().elapsed());
+/// # Ok::<(), gs_usb::GsUsbError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TimeSync<C: Clock + Clone = SystemClock> {
+    clock: C,
+    extender: TimestampExtender<C>,
+    /// Host time and extended device time of each sync point, oldest first
+    points: VecDeque<(Instant, u64)>,
+    /// Shortest round trip of the latest sync point
+    round_trip: Duration,
+    fit: Option<Fit>,
+}
+
+/// Straight line through the sync points
+#[derive(Debug, Clone, Copy)]
+struct Fit {
+    anchor_host: Instant,
+    /// Device time at `anchor_host`, in extended microseconds
+    anchor_device: f64,
+    /// Device microseconds per host microsecond
+    rate: f64,
+    /// Largest deviation of a sync point from the line, in microseconds
+    max_residual: f64,
+}
+
+impl TimeSync {
+    /// Create an empty synchronization using the system clock
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+
+    /// Take a first sync point with `DEFAULT_SAMPLES` requests
+    pub fn measure(dev: &mut GsUsb) -> Result<Self> {
+        let mut sync = Self::new();
+        sync.sample(dev, DEFAULT_SAMPLES)?;
+        Ok(sync)
+    }
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock + Clone> TimeSync<C> {
+    /// Create an empty synchronization using the given clock
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            extender: TimestampExtender::with_clock(clock.clone()),
+            clock,
+            points: VecDeque::new(),
+            round_trip: Duration::ZERO,
+            fit: None,
+        }
+    }
+
+    /// Add a sync point from `samples` timestamp requests and refit
+    ///
+    /// Call this periodically (e.g. once a minute) to track drift and to keep
+    /// `to_host()` unambiguous.
+    pub fn sample(&mut self, dev: &mut GsUsb, samples: usize) -> Result<()> {
+        let mut best: Option<(Instant, Duration, u32)> = None;
+        for _ in 0..samples.max(1) {
+            let before = self.clock.now();
+            let device_us = dev.device_timestamp()?;
+            let round_trip = self.clock.now().saturating_duration_since(before);
+            if best.is_none_or(|(_, rt, _)| round_trip < rt) {
+                best = Some((before + round_trip / 2, round_trip, device_us));
+            }
+        }
+
+        let (host, round_trip, device_us) = best.unwrap();
+        let device = self.extender.extend(device_us);
+        if self.points.len() == MAX_POINTS {
+            self.points.pop_front();
+        }
+        self.points.push_back((host, device));
+        self.round_trip = round_trip;
+        self.fit = Some(self.fit_points());
+        Ok(())
+    }
+
+    fn fit_points(&self) -> Fit {
+        let (base_host, base_device) = self.points[0];
+        let xy: Vec<(f64, f64)> = self
+            .points
+            .iter()
+            .map(|&(host, device)| {
+                let x = host.duration_since(base_host).as_secs_f64() * 1e6;
+                (x, (device - base_device) as f64)
+            })
+            .collect();
+
+        let n = xy.len() as f64;
+        let mean_x = xy.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = xy.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxx: f64 = xy.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let sxy: f64 = xy.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+
+        let span = xy.last().map_or(0.0, |p| p.0);
+        let rate = if span >= MIN_RATE_BASELINE.as_secs_f64() * 1e6 && sxx > 0.0 {
+            (sxy / sxx).clamp(1.0 - MAX_DRIFT, 1.0 + MAX_DRIFT)
+        } else {
+            1.0
+        };
+
+        let max_residual = xy
+            .iter()
+            .map(|p| (p.1 - mean_y - rate * (p.0 - mean_x)).abs())
+            .fold(0.0, f64::max);
+
+        // Anchor the line at the latest point, where it is used
+        Fit {
+            anchor_host: base_host + Duration::from_secs_f64(span / 1e6),
+            anchor_device: base_device as f64 + mean_y + rate * (span - mean_x),
+            rate,
+            max_residual,
+        }
+    }
+
+    /// Convert a hardware timestamp to host time
+    ///
+    /// The timestamp must be within about 35 minutes (half the counter
+    /// period) of the latest sync point. Returns `None` before the first
+    /// `sample()`.
+    pub fn to_host(&self, timestamp_us: u32) -> Option<Instant> {
+        let fit = self.fit?;
+        let anchor_whole = fit.anchor_device.floor();
+        let anchor_raw = anchor_whole as u64 as u32;
+        let device_delta = f64::from(timestamp_us.wrapping_sub(anchor_raw) as i32)
+            - (fit.anchor_device - anchor_whole);
+        let host_delta = device_delta / fit.rate;
+        let offset = Duration::from_secs_f64(host_delta.abs() / 1e6);
+        if host_delta >= 0.0 {
+            Some(fit.anchor_host + offset)
+        } else {
+            fit.anchor_host.checked_sub(offset)
+        }
+    }
+
+    /// Estimated device microseconds per host microsecond (1.0 until the sync
+    /// points span `MIN_RATE_BASELINE`)
+    pub fn rate(&self) -> f64 {
+        self.fit.map_or(1.0, |f| f.rate)
+    }
+
+    /// Estimated drift of the device clock in parts per million
+    pub fn drift_ppm(&self) -> f64 {
+        (self.rate() - 1.0) * 1e6
+    }
+
+    /// Error bound of the latest sync point (half its request round trip)
+    pub fn uncertainty(&self) -> Duration {
+        self.round_trip / 2
+    }
+
+    /// Largest deviation of a sync point from the fitted drift model
+    ///
+    /// Together with `uncertainty()` this bounds the error of `to_host()`
+    /// for timestamps between the sync points.
+    pub fn max_residual(&self) -> Duration {
+        self.fit.map_or(Duration::ZERO, |f| {
+            Duration::from_secs_f64(f.max_residual / 1e6)
+        })
+    }
+
+    /// Host time of the latest sync point
+    pub fn last_sync(&self) -> Option<Instant> {
+        self.points.back().map(|&(host, _)| host)
+    }
+
+    /// Number of sync points the estimate is based on
+    pub fn points(&self) -> usize {
+        self.points.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::constants::{GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_NORMAL, GS_USB_BREQ_TIMESTAMP};
+    use crate::frame::GsUsbFrame;
+    use crate::mock::MockGsUsb;
+    use crate::virtual_bus::VirtualBus;
+
+    fn abs_diff(a: Instant, b: Instant) -> Duration {
+        a.max(b) - a.min(b)
+    }
+
+    #[test]
+    fn test_offset_and_drift() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        let clock = TestClock::new();
+        let mut sync = TimeSync::with_clock(clock.clone());
+        assert!(sync.to_host(0).is_none());
+
+        // Device counter 100 ppm fast, wrapping between the two sync points
+        let start_us = 0xFFFF_0000u32;
+        let t0 = clock.now();
+        mock.queue_response(GS_USB_BREQ_TIMESTAMP, Ok(start_us.to_le_bytes().to_vec()));
+        sync.sample(&mut dev, 1).unwrap();
+        let soon = sync.to_host(start_us + 500).unwrap();
+        assert!(abs_diff(soon, t0 + Duration::from_micros(500)) < Duration::from_micros(1));
+        assert_eq!(sync.rate(), 1.0);
+
+        clock.advance(Duration::from_secs(10));
+        let later_us = start_us.wrapping_add(10_001_000);
+        mock.queue_response(GS_USB_BREQ_TIMESTAMP, Ok(later_us.to_le_bytes().to_vec()));
+        sync.sample(&mut dev, 1).unwrap();
+        assert!((sync.drift_ppm() - 100.0).abs() < 0.01);
+
+        let midway = sync.to_host(start_us.wrapping_add(5_000_500)).unwrap();
+        assert!(abs_diff(midway, t0 + Duration::from_secs(5)) < Duration::from_micros(2));
+    }
+
+    #[test]
+    fn test_virtual_device() {
+        let bus = VirtualBus::new();
+        let mut dev = bus.open();
+        dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+
+        let sync = TimeSync::measure(&mut dev).unwrap();
+        assert_eq!(sync.points(), 1);
+        let now = Instant::now();
+        let device_now = sync.to_host(dev.device_timestamp().unwrap()).unwrap();
+        assert!(abs_diff(device_now, now) < Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_frame_host_time() {
+        let bus = VirtualBus::new();
+        let mut a = bus.open();
+        let mut b = bus.open();
+        a.start(GS_CAN_MODE_NORMAL).unwrap();
+        b.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+        b.sync_time().unwrap();
+        assert!(b.time_sync().is_some());
+
+        let frame = GsUsbFrame::with_data(0x10, &[1]);
+        assert!(frame.host_instant().is_none());
+        a.send(&frame).unwrap();
+        let sent = Instant::now();
+        let rx = b.read(Duration::from_millis(100)).unwrap();
+        assert!(abs_diff(rx.host_instant().unwrap(), sent) < Duration::from_millis(5));
+
+        let wall = rx.system_time().unwrap();
+        let age = std::time::SystemTime::now()
+            .duration_since(wall)
+            .unwrap_or_default();
+        assert!(age < Duration::from_millis(100));
+
+        // Without hardware timestamps the USB reception time is used
+        let echo = a.read(Duration::from_millis(100)).unwrap();
+        assert!(echo.is_echo_frame());
+        assert!(echo.host_instant().unwrap() >= sent);
+    }
+
+    #[test]
+    fn test_continuous_resync() {
+        let bus = VirtualBus::new();
+        let mut dev = bus.open();
+        dev.set_resync_interval(Some(Duration::ZERO));
+        dev.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+
+        for _ in 0..3 {
+            let _ = dev.read(Duration::from_millis(1));
+        }
+        let sync = dev.time_sync().unwrap();
+        assert_eq!(sync.points(), 3);
+        assert!(sync.max_residual() < Duration::from_millis(5));
+
+        dev.set_resync_interval(Some(Duration::from_secs(3600)));
+        let _ = dev.read(Duration::from_millis(1));
+        assert_eq!(dev.time_sync().unwrap().points(), 3);
+    }
+}
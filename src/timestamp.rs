@@ -0,0 +1,299 @@
+//! Hardware timestamp extension
+//!
+//! GS-USB hardware timestamps are 32-bit microsecond counters that wrap
+//! around every 71.6 minutes. `TimestampExtender` turns them into a
+//! continuous 64-bit time base, using the host clock to count wraps that
+//! happened while no frames were received.
+//!
+//! `TimestampSource` selects which time `GsUsb::read()` attaches to frames.
+//!
+//! Some firmware runs the counter at a rate other than 1 MHz. `GsUsb` then
+//! rescales its timestamps to microseconds with a `TickScaler`, see
+//! `GsUsb::set_timestamp_tick_hz()`.
+
+use crate::clock::{Clock, SystemClock};
+use crate::constants::GS_USB_TIMESTAMP_TICK_HZ;
+use std::time::{Duration, Instant};
+
+/// Time base attached to received frames
+///
+/// Set per device with `GsUsb::set_timestamp_source()`; each received frame
+/// reports the source that was actually used in
+/// `GsUsbFrame::timestamp_source()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TimestampSource {
+    /// Only the raw hardware timestamp (`timestamp_us`), no host time
+    Hardware,
+    /// Hardware timestamp converted to host time (see `GsUsb::sync_time()`);
+    /// frames fall back to `HostArrival` while the device isn't synchronized
+    #[default]
+    SyncedHost,
+    /// Host time at which the frame was read from USB
+    HostArrival,
+    /// No timestamp
+    None,
+}
+
+/// Period of the 32-bit microsecond counter
+const WRAP_US: u64 = 1 << 32;
+
+/// Extends 32-bit hardware timestamps to 64 bits
+///
+/// Timestamps must be fed in reception order.
+///
+/// # Example
+///
+/// ```
+/// use gs_usb::timestamp::TimestampExtender;
+///
+/// let mut ext = TimestampExtender::new();
+/// assert_eq!(ext.extend(0xFFFF_FF00), 0xFFFF_FF00);
+/// assert_eq!(ext.extend(0x0000_0100), 0x1_0000_0100);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TimestampExtender<C: Clock = SystemClock> {
+    clock: C,
+    /// Last raw timestamp, its extended value and the host time it was seen
+    last: Option<(u32, u64, Instant)>,
+}
+
+impl TimestampExtender {
+    /// Create an extender using the system clock
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl Default for TimestampExtender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> TimestampExtender<C> {
+    /// Create an extender using the given clock
+    pub fn with_clock(clock: C) -> Self {
+        Self { clock, last: None }
+    }
+
+    /// Extend a hardware timestamp (microseconds)
+    ///
+    /// The first timestamp is returned unchanged. After that, the result
+    /// advances by the counter difference plus as many full wraps as fit the
+    /// host time elapsed since the previous timestamp.
+    pub fn extend(&mut self, timestamp_us: u32) -> u64 {
+        let now = self.clock.now();
+        let extended = match self.last {
+            None => u64::from(timestamp_us),
+            Some((last_raw, last_extended, last_seen)) => {
+                let delta = u64::from(timestamp_us.wrapping_sub(last_raw));
+                let elapsed = now.saturating_duration_since(last_seen).as_micros() as u64;
+                // Round to the nearest number of missed wraps
+                let wraps = elapsed.saturating_sub(delta).saturating_add(WRAP_US / 2) / WRAP_US;
+                last_extended + delta + wraps * WRAP_US
+            }
+        };
+        self.last = Some((timestamp_us, extended, now));
+        extended
+    }
+
+    /// Extend a hardware timestamp to a duration since the first one's zero
+    ///
+    /// Same as `extend()`, without microsecond arithmetic at the call site.
+    pub fn extend_duration(&mut self, timestamp_us: u32) -> Duration {
+        Duration::from_micros(self.extend(timestamp_us))
+    }
+
+    /// Forget the previous timestamp, e.g. after the device was restarted
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Counter rates firmware is known to use, snapped to when detected
+const COMMON_TICK_HZ: [u32; 6] = [
+    1_000_000, 2_000_000, 4_000_000, 8_000_000, 10_000_000, 16_000_000,
+];
+
+/// Round a measured counter rate to a plausible one
+///
+/// Rates within 1% of a common rate are snapped to it, others are rounded to
+/// the nearest kHz.
+pub(crate) fn snap_tick_hz(measured: f64) -> u32 {
+    COMMON_TICK_HZ
+        .into_iter()
+        .find(|&hz| (measured / f64::from(hz) - 1.0).abs() < 0.01)
+        .unwrap_or_else(|| ((measured / 1000.0).round() * 1000.0).max(1000.0) as u32)
+}
+
+/// Converts a counter running at an arbitrary rate to wrapping microseconds
+///
+/// The ticks are extended to 64 bits first, so the result wraps at 2^32 µs
+/// like a 1 MHz counter. Values up to 2^31 ticks older than the newest one
+/// seen (e.g. frames still queued when the counter was read over a control
+/// request) are placed before it instead of a wrap later.
+#[derive(Debug, Clone)]
+pub(crate) struct TickScaler {
+    tick_hz: u32,
+    /// Newest raw value and its extended tick count
+    last: Option<(u32, i64)>,
+}
+
+impl TickScaler {
+    pub(crate) fn new(tick_hz: u32) -> Self {
+        Self {
+            tick_hz: tick_hz.max(1),
+            last: None,
+        }
+    }
+
+    pub(crate) fn tick_hz(&self) -> u32 {
+        self.tick_hz
+    }
+
+    /// Forget the counter history, e.g. after the device was restarted
+    pub(crate) fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Convert a raw counter value to microseconds
+    pub(crate) fn scale(&mut self, raw: u32) -> u32 {
+        if self.tick_hz == GS_USB_TIMESTAMP_TICK_HZ {
+            return raw;
+        }
+        let ticks = match self.last {
+            None => i64::from(raw),
+            Some((last_raw, last_ticks)) => {
+                last_ticks + i64::from(raw.wrapping_sub(last_raw) as i32)
+            }
+        };
+        if self.last.is_none_or(|(_, last_ticks)| ticks > last_ticks) {
+            self.last = Some((raw, ticks));
+        }
+        let us =
+            i128::from(ticks) * i128::from(GS_USB_TIMESTAMP_TICK_HZ) / i128::from(self.tick_hz);
+        us as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::constants::{GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_NORMAL};
+    use crate::frame::GsUsbFrame;
+    use crate::virtual_bus::VirtualBus;
+
+    #[test]
+    fn test_single_wrap() {
+        let clock = TestClock::new();
+        let mut ext = TimestampExtender::with_clock(clock.clone());
+        assert_eq!(ext.extend(u32::MAX - 10), u64::from(u32::MAX - 10));
+        clock.advance(Duration::from_micros(20));
+        assert_eq!(ext.extend(9), WRAP_US + 9);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(
+            ext.extend_duration(1009),
+            Duration::from_micros(WRAP_US + 1009)
+        );
+    }
+
+    #[test]
+    fn test_silent_wraps() {
+        let clock = TestClock::new();
+        let mut ext = TimestampExtender::with_clock(clock.clone());
+        ext.extend(1000);
+
+        // Three hours without traffic: two full wraps plus change
+        clock.advance(Duration::from_secs(3 * 3600));
+        let expected = 1000 + 3 * 3600 * 1_000_000u64;
+        assert_eq!(ext.extend(expected as u32), expected);
+
+        ext.reset();
+        assert_eq!(ext.extend(5), 5);
+    }
+
+    #[test]
+    fn test_timestamp_source() {
+        let bus = VirtualBus::new();
+        let mut a = bus.open();
+        let mut b = bus.open();
+        a.start(GS_CAN_MODE_NORMAL).unwrap();
+        b.start(GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+        assert_eq!(GsUsbFrame::new().timestamp_source(), TimestampSource::None);
+
+        let mut receive = |source| {
+            b.set_timestamp_source(source);
+            a.send(&GsUsbFrame::with_data(0x10, &[])).unwrap();
+            let frame = b.read(Duration::from_millis(100)).unwrap();
+            (frame.timestamp_source(), frame.host_instant().is_some())
+        };
+        assert_eq!(
+            receive(TimestampSource::SyncedHost),
+            (TimestampSource::HostArrival, true)
+        );
+        assert_eq!(
+            receive(TimestampSource::Hardware),
+            (TimestampSource::Hardware, false)
+        );
+        assert_eq!(
+            receive(TimestampSource::None),
+            (TimestampSource::None, false)
+        );
+
+        b.sync_time().unwrap();
+        let mut receive = |source| {
+            b.set_timestamp_source(source);
+            a.send(&GsUsbFrame::with_data(0x10, &[])).unwrap();
+            b.read(Duration::from_millis(100))
+                .unwrap()
+                .timestamp_source()
+        };
+        assert_eq!(
+            receive(TimestampSource::SyncedHost),
+            TimestampSource::SyncedHost
+        );
+        assert_eq!(
+            receive(TimestampSource::HostArrival),
+            TimestampSource::HostArrival
+        );
+    }
+
+    #[test]
+    fn test_tick_scaler() {
+        let mut identity = TickScaler::new(GS_USB_TIMESTAMP_TICK_HZ);
+        assert_eq!(identity.scale(u32::MAX), u32::MAX);
+
+        // 2 MHz counter wrapping after 2147 s: microseconds keep counting
+        let mut scaler = TickScaler::new(2_000_000);
+        assert_eq!(scaler.scale(u32::MAX - 1), (u32::MAX - 1) / 2);
+        assert_eq!(scaler.scale(2), (u32::MAX / 2) + 2);
+        // Slightly older value, e.g. a frame still in the queue
+        assert_eq!(scaler.scale(u32::MAX), u32::MAX / 2);
+
+        scaler.reset();
+        assert_eq!(scaler.scale(2_000), 1_000);
+    }
+
+    #[test]
+    fn test_snap_tick_hz() {
+        assert_eq!(snap_tick_hz(1_004_000.0), 1_000_000);
+        assert_eq!(snap_tick_hz(1_995_000.0), 2_000_000);
+        assert_eq!(snap_tick_hz(750_123.0), 750_000);
+    }
+
+    #[test]
+    fn test_detect_tick_rate() {
+        let bus = VirtualBus::new();
+        let mut dev = bus.open();
+        dev.set_timestamp_tick_hz(2_000_000);
+        let halved = dev.device_timestamp().unwrap();
+        assert_eq!(
+            dev.detect_timestamp_tick_hz(Duration::from_millis(50))
+                .unwrap(),
+            GS_USB_TIMESTAMP_TICK_HZ
+        );
+        assert!(dev.device_timestamp().unwrap() > halved);
+    }
+}
@@ -0,0 +1,422 @@
+//! CAN bit timing calculation
+//!
+//! This module computes bit timing register sets from a bitrate, a sample
+//! point and the timing limits of a device, following the algorithm of the
+//! Linux kernel (`can_calc_bittiming()`): the bitrate error is minimized
+//! first, then the sample point error, preferring the largest number of time
+//! quanta per bit and sample points at or before the requested one.
+//!
+//! Sample points are given as `SamplePoint`s; the register sets report
+//! theirs in permille (875 = 87.5%).
+//!
+//! # Example
+//!
+//! ```
+//! use gs_usb::timing::{calc_bit_timing, TimingLimits};
+//! use gs_usb::SamplePoint;
+//!
+//! // candleLight (STM32 bxCAN, 48 MHz)
+//! let limits = TimingLimits {
+//!     tseg1_min: 1,
+//!     tseg1_max: 16,
+//!     tseg2_min: 1,
+//!     tseg2_max: 8,
+//!     sjw_max: 4,
+//!     brp_min: 1,
+//!     brp_max: 1024,
+//!     brp_inc: 1,
+//! };
+//! let sample_point = SamplePoint::from_percent(87.5).unwrap();
+//! let timing = calc_bit_timing(48_000_000, 500_000, sample_point, &limits).unwrap();
+//! assert_eq!(timing.brp, 6);
+//! assert_eq!(timing.sample_point(), 875);
+//! ```
+
+use crate::bitrate::SamplePoint;
+use crate::structures::{DeviceBitTiming, DeviceCapability};
+
+/// Largest accepted bitrate error in units of 0.01%
+const MAX_BITRATE_ERROR: u64 = 50;
+
+/// Sample point of the data phase used by `data_timing()`
+pub const DEFAULT_DATA_SAMPLE_POINT: SamplePoint = permille(750);
+
+const fn permille(permille: u32) -> SamplePoint {
+    SamplePoint::from_permille(permille).unwrap()
+}
+
+/// Bit timing limits of one phase (nominal or data) of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingLimits {
+    /// Minimum time segment 1 (prop_seg + phase_seg1)
+    pub tseg1_min: u32,
+    /// Maximum time segment 1 (prop_seg + phase_seg1)
+    pub tseg1_max: u32,
+    /// Minimum time segment 2
+    pub tseg2_min: u32,
+    /// Maximum time segment 2
+    pub tseg2_max: u32,
+    /// Maximum synchronization jump width
+    pub sjw_max: u32,
+    /// Minimum baud rate prescaler
+    pub brp_min: u32,
+    /// Maximum baud rate prescaler
+    pub brp_max: u32,
+    /// Baud rate prescaler increment
+    pub brp_inc: u32,
+}
+
+impl TimingLimits {
+    /// Limits used for nominal timing on 40 and 80 MHz devices
+    ///
+    /// At most 200 time quanta per bit; `set_bitrate()` has always used the
+    /// register sets these limits produce.
+    pub const PRESET_NOMINAL: Self = Self {
+        tseg1_min: 1,
+        tseg1_max: 174,
+        tseg2_min: 1,
+        tseg2_max: 25,
+        sjw_max: 128,
+        brp_min: 1,
+        brp_max: 1024,
+        brp_inc: 1,
+    };
+
+    /// Limits used for data phase timing on 40 and 80 MHz devices
+    ///
+    /// These are the data phase limits of the Bosch M_CAN controller.
+    pub const PRESET_DATA: Self = Self {
+        tseg1_min: 1,
+        tseg1_max: 32,
+        tseg2_min: 1,
+        tseg2_max: 16,
+        sjw_max: 16,
+        brp_min: 1,
+        brp_max: 32,
+        brp_inc: 1,
+    };
+
+    /// Nominal (arbitration) phase limits reported by a device
+    pub fn nominal(capability: &DeviceCapability) -> Self {
+        Self {
+            tseg1_min: capability.tseg1_min,
+            tseg1_max: capability.tseg1_max,
+            tseg2_min: capability.tseg2_min,
+            tseg2_max: capability.tseg2_max,
+            sjw_max: capability.sjw_max,
+            brp_min: capability.brp_min,
+            brp_max: capability.brp_max,
+            brp_inc: capability.brp_inc,
+        }
+    }
+
+    /// Data phase limits reported by a device, if it reported any
+    pub fn data(capability: &DeviceCapability) -> Option<Self> {
+        Some(Self {
+            tseg1_min: capability.dtseg1_min?,
+            tseg1_max: capability.dtseg1_max?,
+            tseg2_min: capability.dtseg2_min?,
+            tseg2_max: capability.dtseg2_max?,
+            sjw_max: capability.dsjw_max?,
+            brp_min: capability.dbrp_min?,
+            brp_max: capability.dbrp_max?,
+            brp_inc: capability.dbrp_inc?,
+        })
+    }
+}
+
+/// Default sample point for a bitrate, as recommended by CiA
+///
+/// 87.5% up to 500 kbit/s, 80% up to 800 kbit/s and 75% above.
+pub fn default_sample_point(bitrate: u32) -> SamplePoint {
+    if bitrate > 800_000 {
+        permille(750)
+    } else if bitrate > 500_000 {
+        permille(800)
+    } else {
+        permille(875)
+    }
+}
+
+/// Split `tseg` (time quanta without the sync segment) into tseg1 and tseg2
+///
+/// Returns `(tseg1, tseg2, sample_point, sample_point_error)` for the
+/// closest sample point not later than `sample_point`.
+fn split_tseg(limits: &TimingLimits, sample_point: u32, tseg: u32) -> Option<(u32, u32, u32, u32)> {
+    let total = i64::from(tseg) + 1;
+    let mut best: Option<(u32, u32, u32, u32)> = None;
+
+    for i in 0..=1 {
+        let tseg2 = (total - i64::from(sample_point) * total / 1000 - i)
+            .clamp(i64::from(limits.tseg2_min), i64::from(limits.tseg2_max));
+        let mut tseg1 = i64::from(tseg) - tseg2;
+        let mut tseg2 = tseg2;
+        if tseg1 > i64::from(limits.tseg1_max) {
+            tseg1 = i64::from(limits.tseg1_max);
+            tseg2 = i64::from(tseg) - tseg1;
+        }
+        if tseg1 < 0 || tseg2 < 0 {
+            continue;
+        }
+
+        let point = (1000 * (total - tseg2) / total) as u32;
+        let error = sample_point.abs_diff(point);
+        if point <= sample_point && !matches!(best, Some((_, _, _, e)) if e <= error) {
+            best = Some((tseg1 as u32, tseg2 as u32, point, error));
+        }
+    }
+    best
+}
+
+/// Calculate bit timing for a bitrate and sample point
+///
+/// Returns `None` if no register set within `limits` comes within 0.5% of
+/// the requested bitrate. The synchronization jump width is set to half of
+/// phase segment 2 (at least 1, at most `phase_seg1` and `limits.sjw_max`).
+pub fn calc_bit_timing(
+    clock_hz: u32,
+    bitrate: u32,
+    sample_point: SamplePoint,
+    limits: &TimingLimits,
+) -> Option<DeviceBitTiming> {
+    let sample_point = sample_point.permille();
+    if bitrate == 0 || limits.brp_inc == 0 {
+        return None;
+    }
+    let clock = u64::from(clock_hz);
+    let target = u64::from(bitrate);
+
+    // (bitrate error, sample point error, tseg, brp)
+    let mut best: Option<(u64, u32, u32, u32)> = None;
+
+    // Every number of time quanta is tried twice: even values round the
+    // prescaler down, odd values round it up
+    let max = limits
+        .tseg1_max
+        .saturating_add(limits.tseg2_max)
+        .saturating_mul(2)
+        .saturating_add(1);
+    let min = limits
+        .tseg1_min
+        .saturating_add(limits.tseg2_min)
+        .saturating_mul(2);
+    for doubled in (min..=max).rev() {
+        let tseg = doubled / 2;
+        let total = u64::from(tseg) + 1;
+
+        let brp = clock / (total * target) + u64::from(doubled % 2);
+        let brp = brp / u64::from(limits.brp_inc) * u64::from(limits.brp_inc);
+        if brp == 0 || brp < u64::from(limits.brp_min) || brp > u64::from(limits.brp_max) {
+            continue;
+        }
+
+        let bitrate_error = target.abs_diff(clock / (brp * total));
+        let Some((_, _, _, sample_point_error)) = split_tseg(limits, sample_point, tseg) else {
+            continue;
+        };
+        if let Some((best_bitrate_error, best_sample_point_error, _, _)) = best {
+            if bitrate_error > best_bitrate_error
+                || (bitrate_error == best_bitrate_error
+                    && sample_point_error >= best_sample_point_error)
+            {
+                continue;
+            }
+        }
+
+        best = Some((bitrate_error, sample_point_error, tseg, brp as u32));
+        if bitrate_error == 0 && sample_point_error == 0 {
+            break;
+        }
+    }
+
+    let (bitrate_error, _, tseg, brp) = best?;
+    if bitrate_error * 10_000 / target > MAX_BITRATE_ERROR {
+        return None;
+    }
+
+    let (tseg1, tseg2, _, _) = split_tseg(limits, sample_point, tseg)?;
+    let prop_seg = tseg1 / 2;
+    let phase_seg1 = tseg1 - prop_seg;
+    let sjw = (tseg2 / 2).min(phase_seg1).min(limits.sjw_max).max(1);
+    Some(DeviceBitTiming::new(prop_seg, phase_seg1, tseg2, sjw, brp))
+}
+
+/// Nominal timing `set_bitrate()` uses for a device
+///
+/// Devices with a 40 or 80 MHz clock use `TimingLimits::PRESET_NOMINAL`,
+/// all others the limits they report. The sample point is
+/// `default_sample_point(bitrate)`.
+pub fn nominal_timing(capability: &DeviceCapability, bitrate: u32) -> Option<DeviceBitTiming> {
+    nominal_timing_at(capability, bitrate, default_sample_point(bitrate))
+}
+
+/// Nominal timing for a device with a given sample point
+///
+/// Uses the same limits as `nominal_timing()`.
+pub fn nominal_timing_at(
+    capability: &DeviceCapability,
+    bitrate: u32,
+    sample_point: SamplePoint,
+) -> Option<DeviceBitTiming> {
+    let limits = match capability.fclk_can {
+        40_000_000 | 80_000_000 => TimingLimits::PRESET_NOMINAL,
+        _ => TimingLimits::nominal(capability),
+    };
+    calc_bit_timing(capability.fclk_can, bitrate, sample_point, &limits)
+}
+
+/// Data phase timing `set_data_bitrate()` uses for a device
+///
+/// Devices with a 40 or 80 MHz clock use `TimingLimits::PRESET_DATA`, all
+/// others the data phase limits they report. The sample point is 75%.
+pub fn data_timing(capability: &DeviceCapability, bitrate: u32) -> Option<DeviceBitTiming> {
+    data_timing_at(capability, bitrate, DEFAULT_DATA_SAMPLE_POINT)
+}
+
+/// Data phase timing for a device with a given sample point
+///
+/// Uses the same limits as `data_timing()`.
+pub fn data_timing_at(
+    capability: &DeviceCapability,
+    bitrate: u32,
+    sample_point: SamplePoint,
+) -> Option<DeviceBitTiming> {
+    let limits = match capability.fclk_can {
+        40_000_000 | 80_000_000 => TimingLimits::PRESET_DATA,
+        _ => TimingLimits::data(capability)?,
+    };
+    calc_bit_timing(capability.fclk_can, bitrate, sample_point, &limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// candleLight firmware on STM32 bxCAN
+    const CANDLELIGHT: TimingLimits = TimingLimits {
+        tseg1_min: 1,
+        tseg1_max: 16,
+        tseg2_min: 1,
+        tseg2_max: 8,
+        sjw_max: 4,
+        brp_min: 1,
+        brp_max: 1024,
+        brp_inc: 1,
+    };
+
+    /// Golden register sets: (clock, bitrate, sample point,
+    /// (prop_seg, phase_seg1, phase_seg2, sjw, brp))
+    type Golden = (u32, u32, u32, (u32, u32, u32, u32, u32));
+
+    /// The nominal tables `set_bitrate()` shipped with for 40/80 MHz devices
+    const PRESET_NOMINAL: &[Golden] = &[
+        (80_000_000, 10_000, 875, (87, 87, 25, 12, 40)),
+        (80_000_000, 20_000, 875, (87, 87, 25, 12, 20)),
+        (80_000_000, 50_000, 875, (87, 87, 25, 12, 8)),
+        (80_000_000, 100_000, 875, (87, 87, 25, 12, 4)),
+        (80_000_000, 125_000, 875, (69, 70, 20, 10, 4)),
+        (80_000_000, 250_000, 875, (69, 70, 20, 10, 2)),
+        (80_000_000, 500_000, 875, (69, 70, 20, 10, 1)),
+        (80_000_000, 1_000_000, 750, (29, 30, 20, 10, 1)),
+        (40_000_000, 10_000, 875, (87, 87, 25, 12, 20)),
+        (40_000_000, 20_000, 875, (87, 87, 25, 12, 10)),
+        (40_000_000, 50_000, 875, (87, 87, 25, 12, 4)),
+        (40_000_000, 100_000, 875, (87, 87, 25, 12, 2)),
+        (40_000_000, 125_000, 875, (69, 70, 20, 10, 2)),
+        (40_000_000, 250_000, 875, (69, 70, 20, 10, 1)),
+        (40_000_000, 500_000, 875, (34, 35, 10, 5, 1)),
+        (40_000_000, 1_000_000, 750, (14, 15, 10, 5, 1)),
+    ];
+
+    /// The data phase tables `set_data_bitrate()` shipped with, requested at
+    /// 75%; 8 Mbit/s can't reach it and settles on the closest earlier point
+    const PRESET_DATA: &[Golden] = &[
+        (80_000_000, 1_000_000, 750, (14, 15, 10, 5, 2)),
+        (80_000_000, 2_000_000, 750, (14, 15, 10, 5, 1)),
+        (80_000_000, 5_000_000, 750, (5, 6, 4, 2, 1)),
+        (80_000_000, 8_000_000, 700, (3, 3, 3, 1, 1)),
+        (80_000_000, 10_000_000, 750, (2, 3, 2, 1, 1)),
+        (40_000_000, 1_000_000, 750, (14, 15, 10, 5, 1)),
+        (40_000_000, 2_000_000, 750, (7, 7, 5, 2, 1)),
+        (40_000_000, 5_000_000, 750, (2, 3, 2, 1, 1)),
+        (40_000_000, 8_000_000, 600, (1, 1, 2, 1, 1)),
+        (40_000_000, 10_000_000, 750, (1, 1, 1, 1, 1)),
+    ];
+
+    /// SocketCAN's `can_calc_bittiming()` for a 48 MHz candleLight with the
+    /// default sample points (`ip link set can0 type can bitrate ...`)
+    const CANDLELIGHT_NOMINAL: &[Golden] = &[
+        (48_000_000, 10_000, 875, (6, 7, 2, 1, 300)),
+        (48_000_000, 20_000, 875, (6, 7, 2, 1, 150)),
+        (48_000_000, 50_000, 875, (6, 7, 2, 1, 60)),
+        (48_000_000, 83_333, 875, (6, 7, 2, 1, 36)),
+        (48_000_000, 100_000, 875, (6, 7, 2, 1, 30)),
+        (48_000_000, 125_000, 875, (6, 7, 2, 1, 24)),
+        (48_000_000, 250_000, 875, (6, 7, 2, 1, 12)),
+        (48_000_000, 500_000, 875, (6, 7, 2, 1, 6)),
+        (48_000_000, 800_000, 800, (7, 8, 4, 2, 3)),
+        (48_000_000, 1_000_000, 750, (5, 6, 4, 2, 3)),
+    ];
+
+    fn check(limits: &TimingLimits, golden: &[Golden], sample_point: impl Fn(u32) -> SamplePoint) {
+        for &(clock, bitrate, expected_point, (prop_seg, phase_seg1, phase_seg2, sjw, brp)) in
+            golden
+        {
+            let timing = calc_bit_timing(clock, bitrate, sample_point(bitrate), limits)
+                .unwrap_or_else(|| panic!("no timing for {bitrate} @ {clock}"));
+            assert_eq!(
+                timing,
+                DeviceBitTiming::new(prop_seg, phase_seg1, phase_seg2, sjw, brp),
+                "{bitrate} @ {clock}"
+            );
+            assert_eq!(timing.sample_point(), expected_point, "{bitrate} @ {clock}");
+            assert_eq!(timing.bitrate(clock), bitrate, "{bitrate} @ {clock}");
+        }
+    }
+
+    #[test]
+    fn test_preset_tables() {
+        check(
+            &TimingLimits::PRESET_NOMINAL,
+            PRESET_NOMINAL,
+            default_sample_point,
+        );
+        check(&TimingLimits::PRESET_DATA, PRESET_DATA, |_| {
+            DEFAULT_DATA_SAMPLE_POINT
+        });
+    }
+
+    #[test]
+    fn test_candlelight_matches_socketcan() {
+        check(&CANDLELIGHT, CANDLELIGHT_NOMINAL, default_sample_point);
+    }
+
+    #[test]
+    fn test_unreachable_bitrates() {
+        // 48 MHz with at most 25 quanta and a prescaler of 1024 covers
+        // 1875 bit/s to 16 Mbit/s
+        let sample_point = permille(875);
+        assert!(calc_bit_timing(48_000_000, 33_000_000, sample_point, &CANDLELIGHT).is_none());
+        assert!(calc_bit_timing(48_000_000, 1_000, sample_point, &CANDLELIGHT).is_none());
+        assert!(calc_bit_timing(48_000_000, 0, sample_point, &CANDLELIGHT).is_none());
+    }
+
+    #[test]
+    fn test_device_timing() {
+        let mut capability = crate::virtual_bus::VirtualBus::default_capability();
+        let timing = nominal_timing(&capability, 800_000).unwrap();
+        assert_eq!(timing.bitrate(40_000_000), 800_000);
+        assert_eq!(timing.sample_point(), 800);
+
+        // Other clocks use the limits the device reports
+        capability.fclk_can = 48_000_000;
+        capability.tseg1_max = 16;
+        capability.tseg2_max = 8;
+        assert_eq!(
+            nominal_timing(&capability, 500_000).unwrap(),
+            DeviceBitTiming::new(6, 7, 2, 1, 6)
+        );
+        capability.dtseg1_min = None;
+        assert!(data_timing(&capability, 2_000_000).is_none());
+    }
+}
@@ -0,0 +1,584 @@
+//! Transport abstraction for GS-USB devices
+//!
+//! `GsUsb` talks to the adapter exclusively through the `Transport` trait, which
+//! covers the handful of USB operations the gs_usb protocol needs: device reset,
+//! interface claiming, vendor control transfers and bulk transfers on the CAN
+//! endpoints. `UsbTransport` implements it on top of a real `rusb` device handle;
+//! other implementations (e.g. the in-memory virtual bus) let the same device API
+//! run without hardware.
+//!
+//! Adapters like the CANable can be composite devices, with the gs_usb
+//! function next to a CDC serial port or DFU interface. `UsbTransport` finds
+//! the gs_usb interface by its vendor-specific class and bulk endpoints,
+//! preferring one whose interface string names it, so on Windows it claims
+//! the child interface bound to WinUSB rather than interface 0.
+
+use std::time::Duration;
+
+use rusb::{ConfigDescriptor, DeviceHandle, Direction, GlobalContext, TransferType};
+
+use crate::constants::{GS_USB_ENDPOINT_IN, GS_USB_ENDPOINT_OUT};
+use crate::error::{GsUsbError, Result};
+use crate::platform::claim_error;
+
+/// bmRequestType for vendor control requests, host-to-device
+pub const CONTROL_REQUEST_TYPE_OUT: u8 = 0x41;
+/// bmRequestType for vendor control requests, device-to-host
+pub const CONTROL_REQUEST_TYPE_IN: u8 = 0xC1;
+
+/// bInterfaceClass of vendor-specific interfaces such as gs_usb
+const VENDOR_SPECIFIC_CLASS: u8 = 0xFF;
+
+/// Low-level operations used by `GsUsb` to talk to an adapter
+///
+/// Errors are reported as `rusb::Error` so that every transport maps onto the
+/// same `GsUsbError` variants (e.g. `rusb::Error::Timeout` for read timeouts).
+pub trait Transport: Send {
+    /// Reset the device (USB port reset on real hardware)
+    fn reset(&mut self) -> rusb::Result<()>;
+
+    /// Detach any kernel driver and claim the gs_usb interface
+    fn claim_interface(&mut self) -> Result<()>;
+
+    /// Release the interface claimed by `claim_interface()`
+    ///
+    /// With `reattach_kernel_driver`, a kernel driver detached by
+    /// `claim_interface()` is attached again. Does nothing if the interface
+    /// isn't claimed.
+    fn release_interface(&mut self, reattach_kernel_driver: bool) -> Result<()> {
+        let _ = reattach_kernel_driver;
+        Ok(())
+    }
+
+    /// Vendor control OUT transfer (host to device)
+    fn write_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    /// Vendor control IN transfer (device to host)
+    fn read_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    /// Bulk OUT transfer on the CAN TX endpoint
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> rusb::Result<usize>;
+
+    /// Bulk IN transfer on the CAN RX endpoint
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize>;
+
+    /// Maximum packet size of the CAN TX endpoint, if known
+    fn tx_max_packet_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// The gs_usb interface and bulk endpoints in use, the standard ones of
+    /// interface 0 if the transport doesn't discover them
+    fn interface(&self) -> GsUsbInterface {
+        GsUsbInterface::default()
+    }
+
+    /// USB vendor and product ID, if known
+    fn vendor_product(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// Device serial number string, if the device has one
+    fn serial_number(&mut self) -> rusb::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// USB product string, if the device has one
+    fn product(&mut self) -> rusb::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// The USB device handle, for transports that talk to it directly
+    fn usb_handle(&self) -> Option<&DeviceHandle<GlobalContext>> {
+        None
+    }
+
+    /// Give up the USB device handle, see `usb_handle()`
+    fn into_usb_parts(self: Box<Self>) -> Option<UsbParts> {
+        None
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn reset(&mut self) -> rusb::Result<()> {
+        (**self).reset()
+    }
+
+    fn claim_interface(&mut self) -> Result<()> {
+        (**self).claim_interface()
+    }
+
+    fn release_interface(&mut self, reattach_kernel_driver: bool) -> Result<()> {
+        (**self).release_interface(reattach_kernel_driver)
+    }
+
+    fn write_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        (**self).write_control(request, value, data, timeout)
+    }
+
+    fn read_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        (**self).read_control(request, value, buf, timeout)
+    }
+
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        (**self).write_bulk(data, timeout)
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        (**self).read_bulk(buf, timeout)
+    }
+
+    fn tx_max_packet_size(&self) -> Option<usize> {
+        (**self).tx_max_packet_size()
+    }
+
+    fn interface(&self) -> GsUsbInterface {
+        (**self).interface()
+    }
+
+    fn vendor_product(&self) -> Option<(u16, u16)> {
+        (**self).vendor_product()
+    }
+
+    fn serial_number(&mut self) -> rusb::Result<Option<String>> {
+        (**self).serial_number()
+    }
+
+    fn product(&mut self) -> rusb::Result<Option<String>> {
+        (**self).product()
+    }
+
+    fn usb_handle(&self) -> Option<&DeviceHandle<GlobalContext>> {
+        (**self).usb_handle()
+    }
+
+    fn into_usb_parts(self: Box<Self>) -> Option<UsbParts> {
+        (*self).into_usb_parts()
+    }
+}
+
+/// Placeholder transport for a device whose transport has been taken
+pub(crate) struct Detached;
+
+impl Transport for Detached {
+    fn reset(&mut self) -> rusb::Result<()> {
+        Err(rusb::Error::NoDevice)
+    }
+
+    fn claim_interface(&mut self) -> Result<()> {
+        Err(GsUsbError::DeviceNotOpen)
+    }
+
+    fn write_control(&mut self, _: u8, _: u16, _: &[u8], _: Duration) -> rusb::Result<usize> {
+        Err(rusb::Error::NoDevice)
+    }
+
+    fn read_control(&mut self, _: u8, _: u16, _: &mut [u8], _: Duration) -> rusb::Result<usize> {
+        Err(rusb::Error::NoDevice)
+    }
+
+    fn write_bulk(&mut self, _: &[u8], _: Duration) -> rusb::Result<usize> {
+        Err(rusb::Error::NoDevice)
+    }
+
+    fn read_bulk(&mut self, _: &mut [u8], _: Duration) -> rusb::Result<usize> {
+        Err(rusb::Error::NoDevice)
+    }
+}
+
+/// The interface of a device's gs_usb function and its bulk endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GsUsbInterface {
+    /// bInterfaceNumber, also sent as wIndex of control requests
+    pub number: u8,
+    /// Bulk IN endpoint address
+    pub endpoint_in: u8,
+    /// Bulk OUT endpoint address
+    pub endpoint_out: u8,
+}
+
+impl Default for GsUsbInterface {
+    /// Interface 0 with the standard gs_usb endpoints
+    fn default() -> Self {
+        Self {
+            number: 0,
+            endpoint_in: GS_USB_ENDPOINT_IN,
+            endpoint_out: GS_USB_ENDPOINT_OUT,
+        }
+    }
+}
+
+/// An interface of the active configuration, as far as choosing goes
+#[derive(Debug, Clone, Default)]
+struct Candidate {
+    number: u8,
+    class: u8,
+    name: Option<String>,
+    endpoint_in: Option<u8>,
+    endpoint_out: Option<u8>,
+}
+
+impl GsUsbInterface {
+    /// Find the gs_usb function of the opened device
+    ///
+    /// Falls back to the default if the descriptors can't be read or no
+    /// interface qualifies.
+    pub fn find(handle: &DeviceHandle<GlobalContext>) -> Self {
+        let Ok(config) = handle.device().active_config_descriptor() else {
+            return Self::default();
+        };
+        Self::find_in(&config, |index| {
+            handle.read_string_descriptor_ascii(index).ok()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Find the gs_usb function in a configuration descriptor
+    ///
+    /// `name` reads the string descriptor at an index, for devices that
+    /// can't be opened e.g. to diagnose a missing driver.
+    pub(crate) fn find_in(
+        config: &ConfigDescriptor,
+        name: impl Fn(u8) -> Option<String>,
+    ) -> Option<Self> {
+        let candidates: Vec<Candidate> = config
+            .interfaces()
+            .filter_map(|interface| interface.descriptors().next())
+            .map(|descriptor| {
+                let bulk = |direction| {
+                    descriptor
+                        .endpoint_descriptors()
+                        .find(|e| {
+                            e.transfer_type() == TransferType::Bulk && e.direction() == direction
+                        })
+                        .map(|e| e.address())
+                };
+                Candidate {
+                    number: descriptor.interface_number(),
+                    class: descriptor.class_code(),
+                    name: descriptor.description_string_index().and_then(&name),
+                    endpoint_in: bulk(Direction::In),
+                    endpoint_out: bulk(Direction::Out),
+                }
+            })
+            .collect();
+        Self::choose(&candidates)
+    }
+
+    /// The vendor-specific interface with bulk endpoints in both
+    /// directions, preferring one named gs_usb and then one on the standard
+    /// endpoints
+    fn choose(candidates: &[Candidate]) -> Option<Self> {
+        let usable = candidates.iter().filter_map(|c| {
+            let (endpoint_in, endpoint_out) = (c.endpoint_in?, c.endpoint_out?);
+            (c.class == VENDOR_SPECIFIC_CLASS).then_some((
+                c,
+                Self {
+                    number: c.number,
+                    endpoint_in,
+                    endpoint_out,
+                },
+            ))
+        });
+        usable
+            .max_by_key(|(c, interface)| {
+                let named = c
+                    .name
+                    .as_deref()
+                    .is_some_and(|name| name.to_ascii_lowercase().contains("gs_usb"));
+                let standard = interface.endpoint_in == GS_USB_ENDPOINT_IN
+                    && interface.endpoint_out == GS_USB_ENDPOINT_OUT;
+                // Lowest interface number among equals
+                (named, standard, std::cmp::Reverse(interface.number))
+            })
+            .map(|(_, interface)| interface)
+    }
+}
+
+/// USB device handle and interface state of a `UsbTransport`
+///
+/// Taken apart with `GsUsb::into_parts()` to issue requests the crate
+/// doesn't know, e.g. of custom firmware, and put back together with
+/// `GsUsb::from_parts()`.
+pub struct UsbParts {
+    /// The opened device
+    pub handle: DeviceHandle<GlobalContext>,
+    /// Whether the gs_usb interface is claimed
+    pub interface_claimed: bool,
+    /// Whether a kernel driver was detached from the gs_usb interface when
+    /// claiming it, to be reattached when releasing it
+    pub kernel_driver_detached: bool,
+}
+
+/// Transport backed by a real USB device handle
+pub struct UsbTransport {
+    handle: DeviceHandle<GlobalContext>,
+    interface: GsUsbInterface,
+    /// Whether the interface is currently claimed
+    claimed: bool,
+    /// Whether `claim_interface()` detached a kernel driver
+    detached_kernel_driver: bool,
+}
+
+impl UsbTransport {
+    /// Wrap an opened USB device handle, using its gs_usb interface
+    pub fn new(handle: DeviceHandle<GlobalContext>) -> Self {
+        let interface = GsUsbInterface::find(&handle);
+        Self::with_interface(handle, interface)
+    }
+
+    /// Wrap an opened USB device handle, using the given interface
+    pub fn with_interface(handle: DeviceHandle<GlobalContext>, interface: GsUsbInterface) -> Self {
+        Self {
+            handle,
+            interface,
+            claimed: false,
+            detached_kernel_driver: false,
+        }
+    }
+
+    /// Get the underlying USB device handle
+    pub fn handle(&self) -> &DeviceHandle<GlobalContext> {
+        &self.handle
+    }
+
+    /// Rebuild a transport from a handle and its interface state
+    pub fn from_parts(parts: UsbParts) -> Self {
+        Self {
+            interface: GsUsbInterface::find(&parts.handle),
+            handle: parts.handle,
+            claimed: parts.interface_claimed,
+            detached_kernel_driver: parts.kernel_driver_detached,
+        }
+    }
+
+    /// Take the handle and its interface state
+    pub fn into_parts(self) -> UsbParts {
+        UsbParts {
+            handle: self.handle,
+            interface_claimed: self.claimed,
+            kernel_driver_detached: self.detached_kernel_driver,
+        }
+    }
+}
+
+impl Transport for UsbTransport {
+    fn reset(&mut self) -> rusb::Result<()> {
+        self.handle.reset()
+    }
+
+    fn claim_interface(&mut self) -> Result<()> {
+        let device = self.handle.device();
+        let (bus, address) = (device.bus_number(), device.address());
+        let number = self.interface.number;
+
+        // Only Linux has kernel drivers to detach; on macOS detaching needs
+        // root or an entitlement, and claiming works without it
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if self.handle.kernel_driver_active(number).unwrap_or(false) {
+                self.handle.detach_kernel_driver(number).map_err(|e| {
+                    crate::platform::access_error(e, bus, address, GsUsbError::DetachKernelDriver)
+                })?;
+                self.detached_kernel_driver = true;
+            }
+        }
+
+        self.handle
+            .claim_interface(number)
+            .map_err(|e| claim_error(e, bus, address))?;
+        self.claimed = true;
+        Ok(())
+    }
+
+    fn release_interface(&mut self, reattach_kernel_driver: bool) -> Result<()> {
+        if self.claimed {
+            self.claimed = false;
+            self.handle
+                .release_interface(self.interface.number)
+                .map_err(GsUsbError::ReleaseInterface)?;
+        }
+        if reattach_kernel_driver && self.detached_kernel_driver {
+            self.detached_kernel_driver = false;
+            self.handle
+                .attach_kernel_driver(self.interface.number)
+                .map_err(GsUsbError::AttachKernelDriver)?;
+        }
+        Ok(())
+    }
+
+    fn write_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.handle.write_control(
+            CONTROL_REQUEST_TYPE_OUT,
+            request,
+            value,
+            u16::from(self.interface.number),
+            data,
+            timeout,
+        )
+    }
+
+    fn read_control(
+        &mut self,
+        request: u8,
+        value: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.handle.read_control(
+            CONTROL_REQUEST_TYPE_IN,
+            request,
+            value,
+            u16::from(self.interface.number),
+            buf,
+            timeout,
+        )
+    }
+
+    fn write_bulk(&mut self, data: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        self.handle
+            .write_bulk(self.interface.endpoint_out, data, timeout)
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        self.handle
+            .read_bulk(self.interface.endpoint_in, buf, timeout)
+    }
+
+    fn tx_max_packet_size(&self) -> Option<usize> {
+        let config = self.handle.device().active_config_descriptor().ok()?;
+        for interface in config.interfaces() {
+            for descriptor in interface.descriptors() {
+                for endpoint in descriptor.endpoint_descriptors() {
+                    if endpoint.address() == self.interface.endpoint_out {
+                        return Some(usize::from(endpoint.max_packet_size()));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn interface(&self) -> GsUsbInterface {
+        self.interface
+    }
+
+    fn vendor_product(&self) -> Option<(u16, u16)> {
+        self.handle
+            .device()
+            .device_descriptor()
+            .ok()
+            .map(|desc| (desc.vendor_id(), desc.product_id()))
+    }
+
+    fn serial_number(&mut self) -> rusb::Result<Option<String>> {
+        let desc = self.handle.device().device_descriptor()?;
+        match desc.serial_number_string_index() {
+            Some(index) => Ok(Some(self.handle.read_string_descriptor_ascii(index)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn product(&mut self) -> rusb::Result<Option<String>> {
+        let desc = self.handle.device().device_descriptor()?;
+        match desc.product_string_index() {
+            Some(index) => Ok(Some(self.handle.read_string_descriptor_ascii(index)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn usb_handle(&self) -> Option<&DeviceHandle<GlobalContext>> {
+        Some(&self.handle)
+    }
+
+    fn into_usb_parts(self: Box<Self>) -> Option<UsbParts> {
+        Some((*self).into_parts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(number: u8, class: u8, name: Option<&str>, endpoints: (u8, u8)) -> Candidate {
+        Candidate {
+            number,
+            class,
+            name: name.map(str::to_string),
+            endpoint_in: Some(endpoints.0),
+            endpoint_out: Some(endpoints.1),
+        }
+    }
+
+    #[test]
+    fn test_choose_interface() {
+        assert_eq!(GsUsbInterface::choose(&[]), None);
+
+        // CDC ACM on interfaces 0 and 1 ahead of gs_usb
+        let cdc_data = candidate(1, 0x0A, None, (0x83, 0x03));
+        let composite = [
+            Candidate {
+                number: 0,
+                class: 0x02,
+                ..Candidate::default()
+            },
+            cdc_data.clone(),
+            candidate(2, VENDOR_SPECIFIC_CLASS, None, (0x81, 0x02)),
+        ];
+        assert_eq!(GsUsbInterface::choose(&composite).unwrap().number, 2);
+
+        // The named interface wins over one on the standard endpoints
+        let named = [
+            candidate(0, VENDOR_SPECIFIC_CLASS, Some("DFU"), (0x81, 0x02)),
+            candidate(
+                1,
+                VENDOR_SPECIFIC_CLASS,
+                Some("gs_usb interface"),
+                (0x84, 0x04),
+            ),
+        ];
+        assert_eq!(
+            GsUsbInterface::choose(&named),
+            Some(GsUsbInterface {
+                number: 1,
+                endpoint_in: 0x84,
+                endpoint_out: 0x04,
+            })
+        );
+
+        // Without bulk endpoints in both directions, nothing qualifies
+        let mut one_way = candidate(0, VENDOR_SPECIFIC_CLASS, None, (0x81, 0x02));
+        one_way.endpoint_out = None;
+        assert_eq!(GsUsbInterface::choose(&[one_way, cdc_data]), None);
+    }
+}
@@ -0,0 +1,239 @@
+//! Frame types sized for classic CAN or CAN FD
+//!
+//! `GsUsbFrame` covers both kinds of frames and so carries a 64-byte data
+//! array with the `fd` feature. `ClassicFrame` holds at most 8 bytes and
+//! `FdFrame` up to 64; both are `Frame<N>` with the payload capacity as a
+//! const parameter. Sent with `GsUsb::send_classic()`, a classic frame
+//! can't put an FD payload on a classic channel, and `GsUsb::send_fd()`
+//! refuses FD frames unless the device was started in FD mode.
+//!
+//! Both convert to `GsUsbFrame`, and back from received frames of the
+//! matching kind.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{ClassicFrame, GsUsb, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! dev.send_classic(&ClassicFrame::new(0x123, &[1, 2, 3])?)?;
+//! let received = dev.read(Duration::from_secs(1))?;
+//! if let Ok(frame) = ClassicFrame::try_from(&received) {
+//!     println!("{:03X} {:02X?}", frame.can_id(), frame.data());
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+#[cfg(feature = "fd")]
+use crate::constants::CANFD_MAX_DLEN;
+use crate::constants::{CAN_EFF_FLAG, CAN_EFF_MASK, CAN_MAX_DLEN, CAN_SFF_MASK};
+use crate::error::{GsUsbError, Result};
+use crate::frame::{dlc_to_len, len_to_dlc, GsUsbFrame};
+
+/// A CAN frame with room for `N` data bytes, see the module documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<const N: usize> {
+    can_id: u32,
+    channel: u8,
+    brs: bool,
+    len: u8,
+    data: [u8; N],
+    timestamp_us: u32,
+}
+
+/// A classic CAN frame, with up to 8 data bytes
+pub type ClassicFrame = Frame<CAN_MAX_DLEN>;
+
+/// A CAN FD frame, with up to 64 data bytes
+#[cfg(feature = "fd")]
+pub type FdFrame = Frame<CANFD_MAX_DLEN>;
+
+impl<const N: usize> Frame<N> {
+    /// Whether frames of this size are CAN FD frames
+    const FD: bool = N > CAN_MAX_DLEN;
+
+    fn with_data(can_id: u32, data: &[u8], brs: bool) -> Result<Self> {
+        if data.len() > N {
+            return Err(GsUsbError::InvalidConfig(format!(
+                "{} data bytes, at most {N} fit in the frame",
+                data.len()
+            )));
+        }
+        let mut bytes = [0; N];
+        bytes[..data.len()].copy_from_slice(data);
+        Ok(Self {
+            can_id,
+            channel: 0,
+            brs,
+            // FD lengths between DLC steps are padded with zeros
+            len: dlc_to_len(len_to_dlc(data.len(), Self::FD), Self::FD) as u8,
+            data: bytes,
+            timestamp_us: 0,
+        })
+    }
+
+    /// Send on or received from `channel`
+    pub fn on_channel(mut self, channel: u8) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// CAN identifier with flags, as `GsUsbFrame::can_id`
+    pub fn can_id(&self) -> u32 {
+        self.can_id
+    }
+
+    /// CAN identifier without flags
+    pub fn arbitration_id(&self) -> u32 {
+        if self.is_extended_id() {
+            self.can_id & CAN_EFF_MASK
+        } else {
+            self.can_id & CAN_SFF_MASK
+        }
+    }
+
+    /// Whether the identifier is extended (29 bit)
+    pub fn is_extended_id(&self) -> bool {
+        self.can_id & CAN_EFF_FLAG != 0
+    }
+
+    /// CAN channel
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    /// Frame data
+    pub fn data(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
+
+    /// Hardware timestamp in microseconds of a received frame
+    pub fn timestamp_us(&self) -> u32 {
+        self.timestamp_us
+    }
+}
+
+impl Frame<CAN_MAX_DLEN> {
+    /// Classic frame with up to 8 bytes of `data`
+    ///
+    /// Fails with `InvalidConfig` for longer data.
+    pub fn new(can_id: u32, data: &[u8]) -> Result<Self> {
+        Self::with_data(can_id, data, false)
+    }
+}
+
+#[cfg(feature = "fd")]
+impl Frame<CANFD_MAX_DLEN> {
+    /// FD frame with up to 64 bytes of `data`, sent with bit rate switch
+    /// if `brs`
+    ///
+    /// Data between the valid FD lengths is padded with zeros. Fails with
+    /// `InvalidConfig` for longer data.
+    pub fn new(can_id: u32, data: &[u8], brs: bool) -> Result<Self> {
+        Self::with_data(can_id, data, brs)
+    }
+
+    /// Whether the data phase uses the data bitrate
+    pub fn is_brs(&self) -> bool {
+        self.brs
+    }
+}
+
+impl<const N: usize> From<&Frame<N>> for GsUsbFrame {
+    fn from(frame: &Frame<N>) -> Self {
+        let mut converted = if Frame::<N>::FD {
+            GsUsbFrame::with_fd_data(frame.can_id, frame.data(), frame.brs)
+        } else {
+            GsUsbFrame::with_data(frame.can_id, frame.data())
+        };
+        converted.channel = frame.channel;
+        converted.timestamp_us = frame.timestamp_us;
+        converted
+    }
+}
+
+impl<const N: usize> From<Frame<N>> for GsUsbFrame {
+    fn from(frame: Frame<N>) -> Self {
+        Self::from(&frame)
+    }
+}
+
+impl<const N: usize> TryFrom<&GsUsbFrame> for Frame<N> {
+    type Error = GsUsbError;
+
+    /// Take a frame of the same kind, failing with `InvalidConfig` for a
+    /// classic frame as FD frame or the other way round
+    fn try_from(frame: &GsUsbFrame) -> Result<Self> {
+        if frame.is_fd() != Self::FD {
+            let kind = if frame.is_fd() { "CAN FD" } else { "classic" };
+            return Err(GsUsbError::InvalidConfig(format!(
+                "{kind} frame doesn't convert to a frame of {N} bytes"
+            )));
+        }
+        let mut converted = Self::with_data(frame.can_id, frame.data(), frame.is_brs())?;
+        converted.channel = frame.channel;
+        converted.timestamp_us = frame.timestamp_us;
+        Ok(converted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GS_CAN_MODE_NORMAL;
+    use crate::mock::MockGsUsb;
+
+    #[test]
+    fn test_classic() {
+        assert!(std::mem::size_of::<ClassicFrame>() < 24);
+        let frame = ClassicFrame::new(0x123 | CAN_EFF_FLAG, &[1, 2, 3])
+            .unwrap()
+            .on_channel(1);
+        assert_eq!(frame.arbitration_id(), 0x123);
+        assert_eq!(frame.data(), [1, 2, 3]);
+        assert!(ClassicFrame::new(0x123, &[0; 9]).is_err());
+
+        let converted = GsUsbFrame::from(frame);
+        assert!(!converted.is_fd());
+        assert_eq!((converted.channel, converted.data()), (1, &[1, 2, 3][..]));
+        assert_eq!(ClassicFrame::try_from(&converted).unwrap(), frame);
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_fd() {
+        let frame = FdFrame::new(0x456, &[0xAA; 10], true).unwrap();
+        assert_eq!(frame.data(), [&[0xAA; 10][..], &[0; 2]].concat());
+        assert!(FdFrame::new(0x456, &[0; 65], false).is_err());
+
+        let converted = GsUsbFrame::from(&frame);
+        assert!(converted.is_fd() && converted.is_brs());
+        assert_eq!(FdFrame::try_from(&converted).unwrap(), frame);
+        assert!(ClassicFrame::try_from(&converted).is_err());
+        assert!(FdFrame::try_from(&GsUsbFrame::with_data(0x1, &[])).is_err());
+    }
+
+    #[test]
+    fn test_send() {
+        let mock = MockGsUsb::new();
+        let mut dev = mock.open();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        dev.send_classic(&ClassicFrame::new(0x123, &[7]).unwrap())
+            .unwrap();
+        assert_eq!(mock.take_sent_frames()[0].data(), [7]);
+
+        #[cfg(feature = "fd")]
+        {
+            let frame = FdFrame::new(0x123, &[0; 12], false).unwrap();
+            assert!(matches!(
+                dev.send_fd(&frame),
+                Err(GsUsbError::FdNotSupported)
+            ));
+            assert!(mock.sent_frames().is_empty());
+        }
+    }
+}
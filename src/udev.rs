@@ -0,0 +1,218 @@
+//! udev rules for non-root access on Linux
+//!
+//! Without a udev rule, opening an adapter as a normal user fails with
+//! `PlatformIssue::UdevPermission`. `UdevRules` generates the rule file the
+//! README describes, for the known adapters plus any IDs of custom
+//! firmware, so setup tools can install it instead of asking users to copy
+//! it by hand. udev only applies new rules after a reload and replug, e.g.
+//! `udevadm control --reload-rules && udevadm trigger`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{UdevRules, UDEV_RULES_PATH};
+//!
+//! let rules = UdevRules::new()
+//!     .device(0x1234, 0x5678, "in-house adapter")
+//!     .group("plugdev");
+//! print!("{rules}");
+//! rules.install(UDEV_RULES_PATH)?;
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::path::Path;
+
+use crate::constants::GS_USB_KNOWN_DEVICES;
+use crate::error::{GsUsbError, Result};
+
+/// Where the README installs the rule file
+pub const UDEV_RULES_PATH: &str = "/etc/udev/rules.d/99-gs_usb.rules";
+
+/// An adapter the rules grant access to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdevDevice {
+    /// USB vendor ID
+    pub vendor_id: u16,
+    /// USB product ID
+    pub product_id: u16,
+    /// Name written as a comment above the rule
+    pub name: String,
+}
+
+/// Text of a udev rule file, see the module documentation
+///
+/// By default every user may open the adapters (mode 0666), as with the
+/// README's rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdevRules {
+    devices: Vec<UdevDevice>,
+    group: Option<String>,
+}
+
+impl UdevRules {
+    /// Rules for the adapters `GsUsb::scan()` finds
+    pub fn new() -> Self {
+        let devices = GS_USB_KNOWN_DEVICES
+            .iter()
+            .map(|&(vendor_id, product_id, name)| UdevDevice {
+                vendor_id,
+                product_id,
+                name: name.to_string(),
+            })
+            .collect();
+        Self {
+            devices,
+            group: None,
+        }
+    }
+
+    /// Also grant access to the adapter with the given IDs
+    ///
+    /// IDs already covered are not added again.
+    pub fn device(mut self, vendor_id: u16, product_id: u16, name: &str) -> Self {
+        let known = self
+            .devices
+            .iter()
+            .any(|d| (d.vendor_id, d.product_id) == (vendor_id, product_id));
+        if !known {
+            self.devices.push(UdevDevice {
+                vendor_id,
+                product_id,
+                name: name.to_string(),
+            });
+        }
+        self
+    }
+
+    /// Grant access to members of `group` only (mode 0660)
+    pub fn group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    /// The adapters the rules cover
+    pub fn devices(&self) -> &[UdevDevice] {
+        &self.devices
+    }
+
+    /// Write the rule file to `path`, replacing it if it exists
+    ///
+    /// Fails with `GsUsbError::InvalidConfig`, writing nothing, if a device
+    /// name contains a control character or the group a control character,
+    /// quote or backslash, which would end the comment or string they are
+    /// written into and let them add rules. Fails with `GsUsbError::Io`
+    /// otherwise, typically for lack of permission to write to
+    /// `/etc/udev/rules.d` without root.
+    pub fn install(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.validate()?;
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        for device in &self.devices {
+            if device.name.chars().any(char::is_control) {
+                return Err(GsUsbError::InvalidConfig(format!(
+                    "control character in udev device name {:?}",
+                    device.name
+                )));
+            }
+        }
+        if let Some(group) = &self.group {
+            if group
+                .chars()
+                .any(|c| c.is_control() || c == '"' || c == '\\')
+            {
+                return Err(GsUsbError::InvalidConfig(format!(
+                    "invalid udev group {group:?}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for UdevRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for UdevRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let access = match &self.group {
+            Some(group) => format!("MODE=\"0660\", GROUP=\"{group}\""),
+            None => "MODE=\"0666\"".to_string(),
+        };
+        for device in &self.devices {
+            writeln!(f, "# {}", device.name)?;
+            writeln!(
+                f,
+                "SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", \
+                 ATTR{{idProduct}}==\"{:04x}\", {access}",
+                device.vendor_id, device.product_id
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let rules = UdevRules::new().device(0x1234, 0xABCD, "custom");
+        let text = rules.to_string();
+        assert!(text.starts_with(
+            "# GS-USB\n\
+             SUBSYSTEM==\"usb\", ATTR{idVendor}==\"1d50\", ATTR{idProduct}==\"606f\", MODE=\"0666\"\n"
+        ));
+        assert!(text.ends_with(
+            "# custom\n\
+             SUBSYSTEM==\"usb\", ATTR{idVendor}==\"1234\", ATTR{idProduct}==\"abcd\", MODE=\"0666\"\n"
+        ));
+
+        // Known IDs aren't repeated
+        let rules = rules.device(0x1209, 0x2323, "candleLight clone");
+        assert_eq!(rules.devices().len(), GS_USB_KNOWN_DEVICES.len() + 1);
+
+        let text = UdevRules::new().group("plugdev").to_string();
+        assert!(text.contains("MODE=\"0660\", GROUP=\"plugdev\"\n"));
+        assert!(!text.contains("0666"));
+    }
+
+    #[test]
+    fn test_install() {
+        let path = std::env::temp_dir().join(format!("gs_usb-{}.rules", std::process::id()));
+        let rules = UdevRules::new();
+        rules.install(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), rules.to_string());
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = std::env::temp_dir().join("gs_usb-missing-dir/99-gs_usb.rules");
+        assert!(rules.install(missing).is_err());
+
+        // Names and groups can't break out of their comment or string
+        let injected = [
+            UdevRules::new().device(0x1234, 0x5678, "adapter\nSUBSYSTEM==\"usb\", MODE=\"0666\""),
+            UdevRules::new().group("plugdev\", MODE=\"0666"),
+            UdevRules::new().group("plug\\dev"),
+            UdevRules::new().group("plug\rdev"),
+        ];
+        for rules in injected {
+            assert!(matches!(
+                rules.install(&path),
+                Err(GsUsbError::InvalidConfig(_))
+            ));
+            assert!(!path.exists());
+        }
+        // Quotes are fine in comments
+        UdevRules::new()
+            .device(0x1234, 0x5678, "\"in-house\" adapter")
+            .install(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
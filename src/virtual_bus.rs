@@ -0,0 +1,770 @@
+//! In-process virtual CAN bus
+//!
+//! This module provides `VirtualBus`, an in-memory CAN bus, and `VirtualGsUsb`,
+//! a transport that emulates gs_usb firmware attached to that bus. Devices opened
+//! on the same bus see each other's frames, so applications can be developed and
+//! tested in CI without any hardware, using the regular `GsUsb` API.
+//!
+//! The simulation covers:
+//! - Arbitration: frames queued by several devices are transmitted in CAN
+//!   priority order (lowest identifier first, standard before extended)
+//! - Echo frames for transmitted frames and hardware timestamps
+//! - Listen-only and one-shot modes, internal loopback (with listen-only)
+//!   and external loopback (frames also reach the other nodes)
+//! - Missing ACK when no other active node is on the bus
+//! - Optional random error injection with TX/RX error counters and bus-off
+//!
+//! # Example
+//!
+//! ```
+//! use gs_usb::{GsUsbFrame, VirtualBus, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let bus = VirtualBus::new();
+//! let mut a = bus.open();
+//! let mut b = bus.open();
+//! a.set_bitrate(500_000)?;
+//! b.set_bitrate(500_000)?;
+//! a.start(GS_CAN_MODE_NORMAL)?;
+//! b.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! a.send(&GsUsbFrame::with_data(0x123, &[1, 2, 3]))?;
+//! let frame = b.read(Duration::from_millis(100))?;
+//! assert_eq!(frame.arbitration_id(), 0x123);
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::constants::*;
+use crate::device::GsUsb;
+use crate::error::Result;
+use crate::frame::GsUsbFrame;
+use crate::rng::XorShift64;
+use crate::structures::{DeviceBitTiming, DeviceCapability, DeviceInfo, DeviceMode, DeviceState};
+use crate::transport::Transport;
+
+/// TX error counter increment for a failed transmission
+const TX_ERROR_INCREMENT: u32 = 8;
+/// RX error counter increment for a corrupted reception
+const RX_ERROR_INCREMENT: u32 = 1;
+
+/// In-memory CAN bus shared by any number of virtual devices
+///
+/// Cloning a `VirtualBus` yields another handle to the same bus.
+#[derive(Clone)]
+pub struct VirtualBus {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<BusState>,
+    activity: Condvar,
+    epoch: Instant,
+}
+
+struct BusState {
+    capability: DeviceCapability,
+    nodes: Vec<Node>,
+    pending: Vec<PendingTx>,
+    sequence: u64,
+    error_rate: f64,
+    rng: XorShift64,
+}
+
+struct PendingTx {
+    node: usize,
+    sequence: u64,
+    frame: GsUsbFrame,
+}
+
+#[derive(Default)]
+struct Node {
+    started: bool,
+    flags: u32,
+    timing: Option<DeviceBitTiming>,
+    rx: VecDeque<GsUsbFrame>,
+    txerr: u32,
+    rxerr: u32,
+    /// Set with SET_USER_ID, kept across resets like the firmware's flash copy
+    user_id: u32,
+    /// Set with SET_TERMINATION, kept across resets as well
+    termination: bool,
+}
+
+impl Node {
+    fn has_flag(&self, flag: u32) -> bool {
+        (self.flags & flag) != 0
+    }
+
+    fn is_bus_off(&self) -> bool {
+        self.txerr > 255
+    }
+
+    fn state(&self) -> u32 {
+        if !self.started {
+            GS_CAN_STATE_STOPPED
+        } else if self.is_bus_off() {
+            GS_CAN_STATE_BUS_OFF
+        } else if self.txerr.max(self.rxerr) >= 128 {
+            GS_CAN_STATE_ERROR_PASSIVE
+        } else if self.txerr.max(self.rxerr) >= 96 {
+            GS_CAN_STATE_ERROR_WARNING
+        } else {
+            GS_CAN_STATE_ERROR_ACTIVE
+        }
+    }
+
+    fn push_error_frame(&mut self, class: u32, timestamp_us: u32) {
+        let mut frame = GsUsbFrame::new();
+        frame.echo_id = GS_USB_RX_ECHO_ID;
+        frame.can_id = CAN_ERR_FLAG | CAN_ERR_CNT | class;
+        frame.can_dlc = CAN_MAX_DLC;
+        frame.data[6] = self.txerr.min(255) as u8;
+        frame.data[7] = self.rxerr.min(255) as u8;
+        frame.timestamp_us = timestamp_us;
+        self.rx.push_back(frame);
+    }
+}
+
+/// Arbitration priority key (lower wins)
+///
+/// The base identifier is compared first; a standard frame wins against an
+/// extended frame with the same base ID, and a data frame wins against a
+/// remote frame.
+fn arbitration_key(frame: &GsUsbFrame) -> (u32, bool, u32, bool) {
+    if frame.is_extended_id() {
+        let id = frame.arbitration_id();
+        (id >> 18, true, id & 0x3FFFF, frame.is_remote_frame())
+    } else {
+        (
+            frame.arbitration_id() & CAN_SFF_MASK,
+            false,
+            0,
+            frame.is_remote_frame(),
+        )
+    }
+}
+
+impl Default for VirtualBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualBus {
+    /// Create a new virtual bus whose devices report a candleLight-like
+    /// 40 MHz CAN FD capability
+    pub fn new() -> Self {
+        Self::with_capability(Self::default_capability())
+    }
+
+    /// Create a new virtual bus whose devices report the given capability
+    pub fn with_capability(capability: DeviceCapability) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(BusState {
+                    capability,
+                    nodes: Vec::new(),
+                    pending: Vec::new(),
+                    sequence: 0,
+                    error_rate: 0.0,
+                    rng: XorShift64::new(0),
+                }),
+                activity: Condvar::new(),
+                epoch: Instant::now(),
+            }),
+        }
+    }
+
+    /// Capability reported by devices on a bus created with `new()`
+    pub fn default_capability() -> DeviceCapability {
+        DeviceCapability {
+            feature: GS_CAN_FEATURE_LISTEN_ONLY
+                | GS_CAN_FEATURE_LOOP_BACK
+                | GS_CAN_FEATURE_ONE_SHOT
+                | GS_CAN_FEATURE_HW_TIMESTAMP
+                | GS_CAN_FEATURE_IDENTIFY
+                | GS_CAN_FEATURE_FD
+                | GS_CAN_FEATURE_BT_CONST_EXT
+                | GS_CAN_FEATURE_GET_STATE
+                | GS_CAN_FEATURE_USER_ID,
+            fclk_can: 40_000_000,
+            tseg1_min: 1,
+            tseg1_max: 256,
+            tseg2_min: 1,
+            tseg2_max: 128,
+            sjw_max: 128,
+            brp_min: 1,
+            brp_max: 512,
+            brp_inc: 1,
+            dtseg1_min: Some(1),
+            dtseg1_max: Some(32),
+            dtseg2_min: Some(1),
+            dtseg2_max: Some(16),
+            dsjw_max: Some(16),
+            dbrp_min: Some(1),
+            dbrp_max: Some(32),
+            dbrp_inc: Some(1),
+        }
+    }
+
+    /// Attach a new virtual device to the bus and return its transport
+    pub fn attach(&self) -> VirtualGsUsb {
+        let mut state = self.shared.state.lock().unwrap();
+        state.nodes.push(Node::default());
+        VirtualGsUsb {
+            shared: self.shared.clone(),
+            node: state.nodes.len() - 1,
+        }
+    }
+
+    /// Attach a new virtual device and wrap it in a `GsUsb`
+    ///
+    /// The device is reported on bus 0 with an address equal to its node
+    /// number plus one.
+    pub fn open(&self) -> GsUsb {
+        let transport = self.attach();
+        let address = (transport.node + 1) as u8;
+        GsUsb::from_transport(transport, 0, address)
+    }
+
+    /// Number of devices that have been attached to the bus
+    pub fn node_count(&self) -> usize {
+        self.shared.state.lock().unwrap().nodes.len()
+    }
+
+    /// Enable random error injection
+    ///
+    /// Each transmission attempt is corrupted with the given probability
+    /// (0.0 - 1.0). Corrupted transmissions raise error frames on all started
+    /// devices, increase error counters and are retried unless the sender is
+    /// in one-shot mode. The seed makes runs reproducible.
+    pub fn set_error_rate(&self, probability: f64, seed: u64) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.error_rate = probability.clamp(0.0, 1.0);
+        state.rng = XorShift64::new(seed);
+    }
+
+    /// Put a frame on the bus as if it was sent by an external node
+    ///
+    /// The frame is received by every started device and is not arbitrated.
+    pub fn inject(&self, frame: &GsUsbFrame) {
+        let mut state = self.shared.state.lock().unwrap();
+        let timestamp_us = self.shared.timestamp_us();
+        for node in state.nodes.iter_mut().filter(|n| n.started) {
+            if frame.is_fd() && !node.has_flag(GS_CAN_MODE_FD) {
+                continue;
+            }
+            let mut rx = frame.clone();
+            rx.echo_id = GS_USB_RX_ECHO_ID;
+            rx.timestamp_us = timestamp_us;
+            node.rx.push_back(rx);
+        }
+        self.shared.activity.notify_all();
+    }
+}
+
+impl Shared {
+    fn timestamp_us(&self) -> u32 {
+        self.epoch.elapsed().as_micros() as u32
+    }
+
+    /// Transmit all pending frames in arbitration order
+    fn arbitrate(&self, state: &mut BusState) {
+        let mut retry = Vec::new();
+
+        while !state.pending.is_empty() {
+            let winner = state
+                .pending
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, tx)| (arbitration_key(&tx.frame), tx.sequence))
+                .map(|(i, _)| i)
+                .unwrap();
+            let tx = state.pending.swap_remove(winner);
+            if let Some(tx) = self.transmit(state, tx) {
+                retry.push(tx);
+            }
+        }
+
+        state.pending = retry;
+    }
+
+    /// Transmit a single frame, returning it if it has to be retried
+    fn transmit(&self, state: &mut BusState, tx: PendingTx) -> Option<PendingTx> {
+        let timestamp_us = self.timestamp_us();
+        let sender = &state.nodes[tx.node];
+        if !sender.started || sender.is_bus_off() {
+            return None;
+        }
+        let one_shot = sender.has_flag(GS_CAN_MODE_ONE_SHOT);
+        let bitrate = sender.timing.map(|t| t.bitrate(state.capability.fclk_can));
+
+        // Internal loopback: the frame never reaches the wire
+        let loopback = sender.has_flag(GS_CAN_MODE_LOOP_BACK);
+        if loopback && sender.has_flag(GS_CAN_MODE_LISTEN_ONLY) {
+            let sender = &mut state.nodes[tx.node];
+            let mut rx = tx.frame.clone();
+            rx.echo_id = GS_USB_RX_ECHO_ID;
+            rx.timestamp_us = timestamp_us;
+            sender.rx.push_back(rx);
+            let mut echo = tx.frame;
+            echo.timestamp_us = timestamp_us;
+            sender.rx.push_back(echo);
+            return None;
+        }
+
+        let receivers: Vec<usize> = state
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, n)| {
+                *i != tx.node
+                    && n.started
+                    && !n.is_bus_off()
+                    && (!tx.frame.is_fd() || n.has_flag(GS_CAN_MODE_FD))
+                    && match (bitrate, n.timing) {
+                        (Some(b), Some(t)) => t.bitrate(state.capability.fclk_can) == b,
+                        _ => true,
+                    }
+            })
+            .map(|(i, _)| i)
+            .collect();
+        // External loopback: the sender's own reception stands in for an ACK
+        let acked = loopback
+            || receivers
+                .iter()
+                .any(|&i| !state.nodes[i].has_flag(GS_CAN_MODE_LISTEN_ONLY));
+
+        if state.rng.chance(state.error_rate) {
+            // Corrupted frame: every active node sees an error frame
+            let sender = &mut state.nodes[tx.node];
+            sender.txerr += TX_ERROR_INCREMENT;
+            let class = if sender.is_bus_off() {
+                CAN_ERR_PROT | CAN_ERR_BUSOFF
+            } else {
+                CAN_ERR_PROT
+            };
+            sender.push_error_frame(class, timestamp_us);
+            for &i in &receivers {
+                let node = &mut state.nodes[i];
+                node.rxerr += RX_ERROR_INCREMENT;
+                node.push_error_frame(CAN_ERR_PROT, timestamp_us);
+            }
+            return if one_shot { None } else { Some(tx) };
+        }
+
+        if !acked {
+            let sender = &mut state.nodes[tx.node];
+            sender.txerr += TX_ERROR_INCREMENT;
+            let class = if sender.is_bus_off() {
+                CAN_ERR_ACK | CAN_ERR_BUSOFF
+            } else {
+                CAN_ERR_ACK
+            };
+            sender.push_error_frame(class, timestamp_us);
+            return if one_shot { None } else { Some(tx) };
+        }
+
+        for &i in &receivers {
+            let node = &mut state.nodes[i];
+            node.rxerr = node.rxerr.saturating_sub(1);
+            let mut rx = tx.frame.clone();
+            rx.echo_id = GS_USB_RX_ECHO_ID;
+            rx.timestamp_us = timestamp_us;
+            node.rx.push_back(rx);
+        }
+
+        let sender = &mut state.nodes[tx.node];
+        sender.txerr = sender.txerr.saturating_sub(1);
+        if loopback {
+            let mut rx = tx.frame.clone();
+            rx.echo_id = GS_USB_RX_ECHO_ID;
+            rx.timestamp_us = timestamp_us;
+            sender.rx.push_back(rx);
+        }
+        let mut echo = tx.frame;
+        echo.timestamp_us = timestamp_us;
+        sender.rx.push_back(echo);
+        None
+    }
+}
+
+/// Transport emulating gs_usb firmware attached to a `VirtualBus`
+///
+/// Use `VirtualBus::open()` to get a ready-to-use `GsUsb`, or wrap this
+/// transport yourself with `GsUsb::from_transport()`.
+pub struct VirtualGsUsb {
+    shared: Arc<Shared>,
+    node: usize,
+}
+
+impl VirtualGsUsb {
+    /// Node number of this device on its bus
+    pub fn node(&self) -> usize {
+        self.node
+    }
+}
+
+impl Transport for VirtualGsUsb {
+    fn reset(&mut self) -> rusb::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        let node_id = self.node;
+        state.pending.retain(|tx| tx.node != node_id);
+        // Like the real firmware, a USB reset stops the channel but keeps
+        // the configured bit timing
+        let Node {
+            timing,
+            user_id,
+            termination,
+            ..
+        } = state.nodes[node_id];
+        state.nodes[node_id] = Node {
+            timing,
+            user_id,
+            termination,
+            ..Node::default()
+        };
+        Ok(())
+    }
+
+    fn claim_interface(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_control(
+        &mut self,
+        request: u8,
+        _value: u16,
+        data: &[u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        let fd_supported = (state.capability.feature & GS_CAN_FEATURE_FD) != 0;
+        let node_id = self.node;
+
+        match request {
+            GS_USB_BREQ_HOST_FORMAT => {}
+            GS_USB_BREQ_IDENTIFY
+                if data.len() >= 4 && (state.capability.feature & GS_CAN_FEATURE_IDENTIFY) != 0 => {
+            }
+            GS_USB_BREQ_SET_TERMINATION
+                if data.len() >= 4
+                    && (state.capability.feature & GS_CAN_FEATURE_TERMINATION) != 0 =>
+            {
+                state.nodes[node_id].termination = data[..4] != [0; 4];
+            }
+            GS_USB_BREQ_BITTIMING if data.len() >= 20 => {
+                state.nodes[node_id].timing = Some(DeviceBitTiming::unpack(data));
+            }
+            GS_USB_BREQ_DATA_BITTIMING if data.len() >= 20 && fd_supported => {}
+            GS_USB_BREQ_SET_USER_ID
+                if data.len() >= 4 && (state.capability.feature & GS_CAN_FEATURE_USER_ID) != 0 =>
+            {
+                state.nodes[node_id].user_id =
+                    u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            }
+            GS_USB_BREQ_MODE if data.len() >= 8 => {
+                let mode = DeviceMode::unpack(data);
+                let node = &mut state.nodes[node_id];
+                node.txerr = 0;
+                node.rxerr = 0;
+                node.rx.clear();
+                if mode.mode == GS_CAN_MODE_START {
+                    node.started = true;
+                    node.flags = mode.flags;
+                } else {
+                    node.started = false;
+                    node.flags = 0;
+                    state.pending.retain(|tx| tx.node != node_id);
+                }
+            }
+            _ => return Err(rusb::Error::Pipe),
+        }
+
+        Ok(data.len())
+    }
+
+    fn read_control(
+        &mut self,
+        request: u8,
+        _value: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let state = self.shared.state.lock().unwrap();
+        let node = &state.nodes[self.node];
+
+        let response: Vec<u8> = match request {
+            GS_USB_BREQ_BT_CONST => state.capability.pack().to_vec(),
+            GS_USB_BREQ_BT_CONST_EXT
+                if (state.capability.feature & GS_CAN_FEATURE_BT_CONST_EXT) != 0 =>
+            {
+                state.capability.pack_extended().to_vec()
+            }
+            GS_USB_BREQ_DEVICE_CONFIG => DeviceInfo {
+                reserved1: 0,
+                reserved2: 0,
+                reserved3: 0,
+                icount: 0,
+                fw_version: 20,
+                hw_version: 10,
+            }
+            .pack()
+            .to_vec(),
+            GS_USB_BREQ_GET_STATE if (state.capability.feature & GS_CAN_FEATURE_GET_STATE) != 0 => {
+                DeviceState {
+                    state: node.state(),
+                    rxerr: node.rxerr,
+                    txerr: node.txerr,
+                }
+                .pack()
+                .to_vec()
+            }
+            GS_USB_BREQ_TIMESTAMP => self.shared.timestamp_us().to_le_bytes().to_vec(),
+            GS_USB_BREQ_GET_USER_ID if (state.capability.feature & GS_CAN_FEATURE_USER_ID) != 0 => {
+                node.user_id.to_le_bytes().to_vec()
+            }
+            GS_USB_BREQ_GET_TERMINATION
+                if (state.capability.feature & GS_CAN_FEATURE_TERMINATION) != 0 =>
+            {
+                u32::from(node.termination).to_le_bytes().to_vec()
+            }
+            _ => return Err(rusb::Error::Pipe),
+        };
+
+        let len = response.len().min(buf.len());
+        buf[..len].copy_from_slice(&response[..len]);
+        Ok(len)
+    }
+
+    fn write_bulk(&mut self, data: &[u8], _timeout: Duration) -> rusb::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        let node = &state.nodes[self.node];
+        if !node.started || data.len() < GS_USB_FRAME_SIZE {
+            return Err(rusb::Error::Io);
+        }
+
+        // Listen-only devices never transmit, except to themselves in
+        // internal loopback
+        if !node.has_flag(GS_CAN_MODE_LISTEN_ONLY) || node.has_flag(GS_CAN_MODE_LOOP_BACK) {
+            let hw_timestamp = node.has_flag(GS_CAN_MODE_HW_TIMESTAMP);
+            let fd_mode = node.has_flag(GS_CAN_MODE_FD);
+            let frame = GsUsbFrame::from_bytes(data, hw_timestamp, fd_mode);
+            state.sequence += 1;
+            let sequence = state.sequence;
+            state.pending.push(PendingTx {
+                node: self.node,
+                sequence,
+                frame,
+            });
+            self.shared.activity.notify_all();
+        }
+
+        Ok(data.len())
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+
+        loop {
+            self.shared.arbitrate(&mut state);
+
+            let node = &mut state.nodes[self.node];
+            if let Some(frame) = node.rx.pop_front() {
+                let hw_timestamp = node.has_flag(GS_CAN_MODE_HW_TIMESTAMP);
+                let packed = frame.pack(hw_timestamp, frame.is_fd());
+                let len = packed.len().min(buf.len());
+                buf[..len].copy_from_slice(&packed[..len]);
+                return Ok(len);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(rusb::Error::Timeout);
+            }
+            state = self
+                .shared
+                .activity
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn serial_number(&mut self) -> rusb::Result<Option<String>> {
+        Ok(Some(format!("VIRTUAL{:04}", self.node)))
+    }
+}
+
+impl Drop for VirtualGsUsb {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            let node_id = self.node;
+            state.pending.retain(|tx| tx.node != node_id);
+            state.nodes[node_id] = Node::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GsUsbError;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn started(bus: &VirtualBus, flags: u32) -> GsUsb {
+        let mut dev = bus.open();
+        dev.set_bitrate(500_000).unwrap();
+        dev.start(flags).unwrap();
+        dev
+    }
+
+    #[test]
+    fn test_frames_reach_other_devices() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_NORMAL);
+        let mut b = started(&bus, GS_CAN_MODE_NORMAL | GS_CAN_MODE_HW_TIMESTAMP);
+
+        a.send(&GsUsbFrame::with_data(0x123, &[1, 2, 3])).unwrap();
+
+        let rx = b.read(TIMEOUT).unwrap();
+        assert!(rx.is_rx_frame());
+        assert_eq!(rx.arbitration_id(), 0x123);
+        assert_eq!(rx.data(), &[1, 2, 3]);
+
+        let echo = a.read(TIMEOUT).unwrap();
+        assert!(echo.is_echo_frame());
+        assert_eq!(echo.arbitration_id(), 0x123);
+    }
+
+    #[test]
+    fn test_arbitration_order() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_NORMAL);
+        let mut b = started(&bus, GS_CAN_MODE_NORMAL);
+        let mut c = started(&bus, GS_CAN_MODE_NORMAL);
+
+        a.send(&GsUsbFrame::with_data(0x200, &[])).unwrap();
+        b.send(&GsUsbFrame::with_data(0x100 | CAN_EFF_FLAG, &[]))
+            .unwrap();
+        b.send(&GsUsbFrame::with_data(0x100, &[])).unwrap();
+
+        let ids: Vec<u32> = (0..3).map(|_| c.read(TIMEOUT).unwrap().can_id).collect();
+        assert_eq!(ids, vec![0x100 | CAN_EFF_FLAG, 0x100, 0x200]);
+    }
+
+    #[test]
+    fn test_arbitration_key_standard_before_extended() {
+        let std_frame = GsUsbFrame::with_data(0x100, &[]);
+        let ext_frame = GsUsbFrame::with_data((0x100 << 18) | CAN_EFF_FLAG, &[]);
+        assert!(arbitration_key(&std_frame) < arbitration_key(&ext_frame));
+    }
+
+    #[test]
+    fn test_missing_ack() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_ONE_SHOT);
+
+        a.send(&GsUsbFrame::with_data(0x123, &[])).unwrap();
+        let err = a.read(TIMEOUT).unwrap();
+        assert!(err.is_error_frame());
+        assert_ne!(err.can_id & CAN_ERR_ACK, 0);
+        assert_eq!(a.get_state(0).unwrap().txerr, TX_ERROR_INCREMENT);
+    }
+
+    #[test]
+    fn test_loopback() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_LOOP_BACK | GS_CAN_MODE_LISTEN_ONLY);
+        let mut b = started(&bus, GS_CAN_MODE_NORMAL);
+
+        // Internal: nothing on the wire
+        a.send(&GsUsbFrame::with_data(0x321, &[9])).unwrap();
+        assert!(a.read(TIMEOUT).unwrap().is_rx_frame());
+        assert!(a.read(TIMEOUT).unwrap().is_echo_frame());
+        assert!(b.read(TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn test_external_loopback() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_LOOP_BACK);
+
+        // Needs no ACK from another node
+        a.send(&GsUsbFrame::with_data(0x321, &[9])).unwrap();
+        assert!(a.read(TIMEOUT).unwrap().is_rx_frame());
+        assert!(a.read(TIMEOUT).unwrap().is_echo_frame());
+
+        let mut b = started(&bus, GS_CAN_MODE_NORMAL);
+        a.send(&GsUsbFrame::with_data(0x322, &[])).unwrap();
+        assert!(a.read(TIMEOUT).unwrap().is_rx_frame());
+        assert!(a.read(TIMEOUT).unwrap().is_echo_frame());
+        assert_eq!(b.read(TIMEOUT).unwrap().arbitration_id(), 0x322);
+    }
+
+    #[test]
+    fn test_error_injection() {
+        let bus = VirtualBus::new();
+        bus.set_error_rate(1.0, 42);
+        let mut a = started(&bus, GS_CAN_MODE_ONE_SHOT);
+        let mut b = started(&bus, GS_CAN_MODE_NORMAL);
+
+        a.send(&GsUsbFrame::with_data(0x123, &[])).unwrap();
+        assert!(b.read(TIMEOUT).unwrap().is_error_frame());
+        assert!(a.read(TIMEOUT).unwrap().is_error_frame());
+        assert_eq!(b.get_state(0).unwrap().rxerr, RX_ERROR_INCREMENT);
+    }
+
+    #[test]
+    fn test_bitrate_mismatch() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_ONE_SHOT);
+        let mut b = bus.open();
+        b.set_bitrate(250_000).unwrap();
+        b.start(GS_CAN_MODE_NORMAL).unwrap();
+
+        a.send(&GsUsbFrame::with_data(0x123, &[])).unwrap();
+        assert!(a.read(TIMEOUT).unwrap().is_error_frame());
+        assert!(b.read(TIMEOUT).is_err());
+    }
+
+    #[cfg(feature = "fd")]
+    #[test]
+    fn test_fd_frames() {
+        let bus = VirtualBus::new();
+        let mut a = started(&bus, GS_CAN_MODE_FD);
+        let mut b = started(&bus, GS_CAN_MODE_FD);
+
+        let data: Vec<u8> = (0..48).collect();
+        a.send(&GsUsbFrame::with_fd_data(0x7FF, &data, true))
+            .unwrap();
+        let rx = b.read(TIMEOUT).unwrap();
+        assert!(rx.is_fd());
+        assert!(rx.is_brs());
+        assert_eq!(rx.data(), &data[..]);
+    }
+
+    #[test]
+    fn test_termination_and_identify() {
+        let mut dev = VirtualBus::new().open();
+        dev.identify(0, true).unwrap();
+        assert!(matches!(
+            dev.set_termination(0, true),
+            Err(GsUsbError::FeatureNotSupported(_))
+        ));
+
+        let mut capability = VirtualBus::default_capability();
+        capability.feature |= GS_CAN_FEATURE_TERMINATION;
+        let mut dev = VirtualBus::with_capability(capability).open();
+        assert!(!dev.termination(0).unwrap());
+        dev.set_termination(0, true).unwrap();
+        dev.start(GS_CAN_MODE_NORMAL).unwrap();
+        assert!(dev.termination(0).unwrap());
+    }
+}
@@ -0,0 +1,237 @@
+//! Supervision of periodic messages
+//!
+//! `MessageWatchdog` is told which identifiers must arrive and how long they
+//! may stay away. Fed the received frames, it reports a message as lost once
+//! its timeout passes without a frame, and as recovered when a frame arrives
+//! again, so a test can supervise safety-relevant periodic traffic without
+//! keeping its own timers.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use gs_usb::{CanId, GsUsb, GsUsbError, MessageWatchdog, WatchEvent, GS_CAN_MODE_NORMAL};
+//! use std::time::Duration;
+//!
+//! let mut dev = GsUsb::scan()?.remove(0);
+//! dev.set_bitrate(500_000)?;
+//! dev.start(GS_CAN_MODE_NORMAL)?;
+//!
+//! let mut watchdog = MessageWatchdog::new();
+//! watchdog.expect(CanId::Standard(0x100), Duration::from_millis(50));
+//! watchdog.expect(CanId::Extended(0x18FF50E5), Duration::from_millis(500));
+//! loop {
+//!     match dev.read(Duration::from_millis(10)) {
+//!         Ok(frame) => {
+//!             if let Some(event) = watchdog.observe(&frame) {
+//!                 println!("{event}");
+//!             }
+//!         }
+//!         Err(GsUsbError::ReadTimeout) => {}
+//!         Err(e) => return Err(e),
+//!     }
+//!     for event in watchdog.check() {
+//!         if let WatchEvent::Lost { id, .. } = event {
+//!             eprintln!("{id} is missing");
+//!         }
+//!     }
+//! }
+//! # Ok::<(), gs_usb::GsUsbError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::frame::GsUsbFrame;
+use crate::id::CanId;
+
+/// A supervised message went missing or came back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// No frame arrived within the timeout
+    Lost {
+        /// Identifier of the message
+        id: CanId,
+        /// Time since the last frame, or since supervision started
+        silence: Duration,
+    },
+    /// A frame arrived after the message was lost
+    Recovered {
+        /// Identifier of the message
+        id: CanId,
+        /// Time without a frame
+        outage: Duration,
+    },
+}
+
+impl std::fmt::Display for WatchEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lost { id, silence } => write!(f, "{id} lost, silent for {silence:?}"),
+            Self::Recovered { id, outage } => write!(f, "{id} recovered after {outage:?}"),
+        }
+    }
+}
+
+/// Supervision state of one identifier
+#[derive(Debug, Clone, Copy)]
+struct Watched {
+    timeout: Duration,
+    last_seen: Instant,
+    lost: bool,
+}
+
+/// Reports supervised messages that stop arriving, see the module
+/// documentation
+///
+/// Frames count by their arrival on the host, read from the clock when
+/// passed to `observe()`. Echoes of sent frames count like received ones;
+/// error frames don't.
+#[derive(Debug, Clone)]
+pub struct MessageWatchdog<C: Clock = SystemClock> {
+    clock: C,
+    watched: BTreeMap<CanId, Watched>,
+}
+
+impl MessageWatchdog {
+    /// Create a watchdog using the system clock
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl Default for MessageWatchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> MessageWatchdog<C> {
+    /// Create a watchdog using the given clock
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            clock,
+            watched: BTreeMap::new(),
+        }
+    }
+
+    /// Supervise `id`, which must arrive at least every `timeout`
+    ///
+    /// The first frame is due `timeout` from now. Supervising an identifier
+    /// again changes its timeout and restarts it.
+    pub fn expect(&mut self, id: CanId, timeout: Duration) {
+        let watched = Watched {
+            timeout,
+            last_seen: self.clock.now(),
+            lost: false,
+        };
+        self.watched.insert(id, watched);
+    }
+
+    /// Stop supervising `id`
+    pub fn forget(&mut self, id: CanId) {
+        self.watched.remove(&id);
+    }
+
+    /// Take a frame, reporting a lost message that came back
+    pub fn observe(&mut self, frame: &GsUsbFrame) -> Option<WatchEvent> {
+        if frame.is_error_frame() {
+            return None;
+        }
+        let id = CanId::from_can_id(frame.can_id);
+        let watched = self.watched.get_mut(&id)?;
+        let now = self.clock.now();
+        let outage = now.saturating_duration_since(watched.last_seen);
+        watched.last_seen = now;
+        std::mem::take(&mut watched.lost).then_some(WatchEvent::Recovered { id, outage })
+    }
+
+    /// Report the messages whose timeout has passed, in identifier order
+    ///
+    /// A lost message is reported once, and again only after it recovered.
+    pub fn check(&mut self) -> Vec<WatchEvent> {
+        let now = self.clock.now();
+        self.watched
+            .iter_mut()
+            .filter_map(|(&id, watched)| {
+                let silence = now.saturating_duration_since(watched.last_seen);
+                if watched.lost || silence <= watched.timeout {
+                    return None;
+                }
+                watched.lost = true;
+                Some(WatchEvent::Lost { id, silence })
+            })
+            .collect()
+    }
+
+    /// Check if `id` was reported lost and hasn't recovered
+    pub fn is_lost(&self, id: CanId) -> bool {
+        self.watched.get(&id).is_some_and(|watched| watched.lost)
+    }
+
+    /// Identifiers reported lost that haven't recovered
+    pub fn lost(&self) -> impl Iterator<Item = CanId> + '_ {
+        self.watched
+            .iter()
+            .filter(|(_, watched)| watched.lost)
+            .map(|(&id, _)| id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::constants::CAN_EFF_FLAG;
+    use crate::error_frame::RxOverflow;
+
+    #[test]
+    fn test_lost_and_recovered() {
+        let clock = TestClock::new();
+        let mut watchdog = MessageWatchdog::with_clock(clock.clone());
+        let fast = CanId::Standard(0x100);
+        let slow = CanId::Extended(0x100);
+        watchdog.expect(fast, Duration::from_millis(20));
+        watchdog.expect(slow, Duration::from_millis(100));
+
+        for _ in 0..5 {
+            clock.advance(Duration::from_millis(10));
+            assert_eq!(watchdog.observe(&GsUsbFrame::with_data(0x100, &[])), None);
+            assert!(watchdog.check().is_empty());
+        }
+
+        // Only the standard identifier stops
+        clock.advance(Duration::from_millis(30));
+        let extended = GsUsbFrame::with_data(0x100 | CAN_EFF_FLAG, &[]);
+        watchdog.observe(&extended);
+        let silence = Duration::from_millis(30);
+        assert_eq!(watchdog.check(), [WatchEvent::Lost { id: fast, silence }]);
+        assert!(watchdog.check().is_empty());
+        assert!(watchdog.is_lost(fast) && !watchdog.is_lost(slow));
+
+        // Error frames don't count
+        clock.advance(Duration::from_millis(10));
+        let error = RxOverflow {
+            channel: 0,
+            timestamp_us: 0,
+            lost_estimate: 1,
+        }
+        .to_error_frame();
+        assert_eq!(watchdog.observe(&error), None);
+
+        let event = watchdog.observe(&GsUsbFrame::with_data(0x100, &[]));
+        assert_eq!(
+            event,
+            Some(WatchEvent::Recovered {
+                id: fast,
+                outage: Duration::from_millis(40)
+            })
+        );
+        assert_eq!(event.unwrap().to_string(), "100 recovered after 40ms");
+        assert_eq!(watchdog.lost().count(), 0);
+
+        watchdog.forget(slow);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(watchdog.check().len(), 1);
+    }
+}
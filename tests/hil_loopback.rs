@@ -0,0 +1,79 @@
+//! Loopback tests against a real adapter
+//!
+//! These need hardware and are ignored by default. Run them with
+//! `cargo test --test hil_loopback -- --ignored --test-threads=1`, optionally
+//! selecting the adapter with `GS_USB_HIL_DEVICE=<bus>:<address>`.
+
+use std::time::Duration;
+
+use gs_usb::hil::{self, FrameMatcher};
+use gs_usb::{GsUsbFrame, GS_CAN_MODE_FD, GS_CAN_MODE_HW_TIMESTAMP, GS_CAN_MODE_LOOP_BACK};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[test]
+#[ignore = "needs a GS-USB adapter"]
+fn classic_bitrates() {
+    let Some(mut dev) = hil::device() else {
+        return;
+    };
+
+    for bitrate in [
+        10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 1_000_000,
+    ] {
+        dev.set_bitrate(bitrate).unwrap();
+        dev.start(GS_CAN_MODE_LOOP_BACK | GS_CAN_MODE_HW_TIMESTAMP)
+            .unwrap();
+
+        let frame = GsUsbFrame::with_data(0x123, &[0xDE, 0xAD, 0xBE, 0xEF, 0xCA, 0xFE, 0xBA, 0xBE]);
+        hil::loopback_round_trip(&mut dev, &frame, TIMEOUT);
+        hil::expect_silence(&mut dev, Duration::from_millis(50));
+
+        dev.stop().unwrap();
+    }
+}
+
+#[test]
+#[ignore = "needs a CAN FD capable GS-USB adapter"]
+fn fd_bitrates() {
+    let Some(mut dev) = hil::device() else {
+        return;
+    };
+    if !dev.supports_fd().unwrap() {
+        return;
+    }
+
+    let payload: Vec<u8> = (0..64).collect();
+    for data_bitrate in [2_000_000, 5_000_000, 8_000_000] {
+        dev.set_bitrate(500_000).unwrap();
+        dev.set_data_bitrate(data_bitrate).unwrap();
+        dev.start(GS_CAN_MODE_LOOP_BACK | GS_CAN_MODE_FD).unwrap();
+
+        let trip = hil::loopback_round_trip(
+            &mut dev,
+            &GsUsbFrame::with_fd_data(0x123, &payload, true),
+            TIMEOUT,
+        );
+        assert!(trip.rx.is_brs());
+
+        dev.stop().unwrap();
+    }
+}
+
+#[test]
+#[ignore = "needs a GS-USB adapter"]
+fn extended_id_echo() {
+    let Some(mut dev) = hil::device() else {
+        return;
+    };
+    dev.set_bitrate(500_000).unwrap();
+    dev.start(GS_CAN_MODE_LOOP_BACK).unwrap();
+
+    let id = 0x1ABC_DEF0 | gs_usb::CAN_EFF_FLAG;
+    dev.send(&GsUsbFrame::with_data(id, &[1])).unwrap();
+    let echo = hil::expect_frame(&mut dev, &FrameMatcher::new().echo(), TIMEOUT);
+    assert!(echo.is_extended_id());
+    assert_eq!(echo.arbitration_id(), 0x1ABC_DEF0);
+
+    dev.stop().unwrap();
+}